@@ -0,0 +1,88 @@
+//! Interpreter and JIT evaluation of an expression, for arrays of increasing
+//! length. Compiling includes the time to generate the native code, which is
+//! what makes the interpreter faster for short arrays.
+//!
+//! cargo bench --features jit
+
+use std::time::{Duration, Instant};
+
+use distance::array::ArrowArray;
+use distance::buffer::Buffer;
+use distance::context::UdfContext;
+use distance::export::{export_array, export_schema, ArrayData};
+use distance::expr::{Expression, ARROW_UDF_FLAG_JIT};
+use distance::options::ArrowUdfExecOptions;
+use distance::schema::{ArrowType, Schema};
+
+const EXPRESSION: &str = "abs(x - 5) * y + x % 7 - sqrt(y)";
+const LENGTHS: [usize; 9] = [
+    100, 1_000, 10_000, 20_000, 50_000, 100_000, 200_000, 1_000_000, 10_000_000,
+];
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let repeats = 5;
+    let started = Instant::now();
+    for _ in 0..repeats {
+        f();
+    }
+    started.elapsed() / repeats
+}
+
+fn main() {
+    let columns = [("x", ArrowType::Int64), ("y", ArrowType::Float64)];
+    let context = UdfContext::new();
+    let interpreted = ArrowUdfExecOptions::default();
+    let compiled = ArrowUdfExecOptions {
+        flags: ARROW_UDF_FLAG_JIT,
+        ..interpreted
+    };
+    let cached = ArrowUdfExecOptions {
+        context: &context,
+        ..compiled
+    };
+    println!(
+        "{:>10} {:>14} {:>14} {:>14}",
+        "rows", "interpreter", "jit", "jit (cached)"
+    );
+    for len in LENGTHS {
+        let x: Buffer = (0..len as i64).collect();
+        let y: Buffer = (0..len).map(|i| i as f64 * 0.5).collect();
+        let schemas = [
+            Schema::new(ArrowType::Int64, "x"),
+            Schema::new(ArrowType::Float64, "y"),
+        ];
+        let ffi_schemas: Vec<_> = schemas.iter().map(export_schema).collect();
+        let ffi_arrays = [
+            export_array(ArrayData::primitive(x, len)),
+            export_array(ArrayData::primitive(y, len)),
+        ];
+        let parsed: Vec<_> = ffi_schemas
+            .iter()
+            .map(|schema| unsafe { Schema::from_ffi(schema) }.unwrap())
+            .collect();
+        let arrays: Vec<_> = parsed
+            .iter()
+            .zip(&ffi_arrays)
+            .map(|(schema, array)| unsafe { ArrowArray::new(schema, array) })
+            .collect();
+        let run = |options: &ArrowUdfExecOptions| {
+            time(|| {
+                let expression =
+                    Expression::compile_with_options(EXPRESSION, &columns, options).unwrap();
+                expression.evaluate(&arrays, options).unwrap();
+            })
+        };
+        println!(
+            "{len:>10} {:>14?} {:>14?} {:>14?}",
+            run(&interpreted),
+            run(&compiled),
+            run(&cached)
+        );
+        for mut schema in ffi_schemas {
+            unsafe { schema.release.unwrap()(&mut schema) };
+        }
+        for mut array in ffi_arrays {
+            unsafe { array.release.unwrap()(&mut array) };
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Fused multiply-add of three Float64 arrays, with the FMA instructions of
+//! the processor when available, compared to the portable `f64::mul_add`,
+//! which is a software fallback unless the crate is compiled for a target
+//! with FMA, and to an unfused multiplication and addition.
+//!
+//! cargo bench --bench fma
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use distance::fma::mul_add_slices;
+
+const LENGTHS: [usize; 4] = [1_000, 100_000, 1_000_000, 10_000_000];
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let repeats = 5;
+    let started = Instant::now();
+    for _ in 0..repeats {
+        f();
+    }
+    started.elapsed() / repeats
+}
+
+fn main() {
+    println!(
+        "{:>10} {:>12} {:>12} {:>12}",
+        "length", "fma", "mul_add", "unfused"
+    );
+    for len in LENGTHS {
+        let a: Vec<f64> = (0..len).map(|i| i as f64 * 0.5).collect();
+        let b: Vec<f64> = (0..len).map(|i| (len - i) as f64 * 0.25).collect();
+        let c: Vec<f64> = (0..len).map(|i| i as f64).collect();
+        // Touched before timing, so page faults aren't counted in the first run.
+        let mut out = vec![1.0; len];
+        let fma = time(|| mul_add_slices(black_box(&a), &b, &c, &mut out));
+        let mul_add = time(|| {
+            for (((out, a), b), c) in out.iter_mut().zip(black_box(&a)).zip(&b).zip(&c) {
+                *out = a.mul_add(*b, *c);
+            }
+        });
+        let unfused = time(|| {
+            for (((out, a), b), c) in out.iter_mut().zip(black_box(&a)).zip(&b).zip(&c) {
+                *out = a * b + c;
+            }
+        });
+        println!("{len:>10} {fma:>12.2?} {mul_add:>12.2?} {unfused:>12.2?}");
+    }
+}
@@ -0,0 +1,114 @@
+//! Sum of the distances of an Int64 array to a point, like
+//! `arrow_udf_sum_distances`, with every loop strategy of `strategy.rs`, and
+//! with the `Simd` loop split among all the cores, for several lengths and
+//! fractions of nulls, up to arrays larger than the last level cache. The
+//! fastest strategy of every row is where the thresholds of `strategy.rs`
+//! come from, and the last column is the plan chosen with them.
+//!
+//! cargo bench --bench strategies
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use distance::bitmap::{Bitmap, BitmapBuilder};
+use distance::exec;
+use distance::options::ArrowUdfExecOptions;
+use distance::strategy::{self, sum_rows, LoopStrategy};
+
+/// The last length is larger than the last level cache of most processors.
+const LENGTHS: [usize; 7] = [
+    100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000, 40_000_000,
+];
+const NULL_FRACTIONS: [f64; 4] = [0.0, 0.1, 0.5, 0.99];
+
+/// Calls of every measurement, at least.
+const MIN_REPEATS: u32 = 5;
+/// Time every measurement takes, at least.
+const MIN_DURATION: Duration = Duration::from_millis(200);
+
+fn time<F: FnMut() -> i64>(mut f: F) -> Duration {
+    // Short inputs are repeated more, until they take `MIN_DURATION`, so
+    // their times are above the resolution of the clock.
+    let mut repeats = 0;
+    let started = Instant::now();
+    while repeats < MIN_REPEATS || started.elapsed() < MIN_DURATION {
+        black_box(f());
+        repeats += 1;
+    }
+    started.elapsed() / repeats
+}
+
+/// Validity with `fraction` of nulls, spread with a linear congruential
+/// generator so the branches on them aren't predictable.
+fn validity(len: usize, fraction: f64) -> Option<Vec<u8>> {
+    if fraction == 0.0 {
+        return None;
+    }
+    let mut state = 42u64;
+    let mut builder = BitmapBuilder::with_capacity(len);
+    for _ in 0..len {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+        builder.push((state >> 33) as f64 / (1u64 << 31) as f64 >= fraction);
+    }
+    Some(builder.finish().as_slice().to_vec())
+}
+
+fn main() {
+    let threads = ArrowUdfExecOptions {
+        num_threads: 0,
+        ..Default::default()
+    };
+    println!(
+        "{:>10} {:>6} {:>12} {:>12} {:>12} {:>12} {:>12}  {:<9} plan",
+        "length", "nulls", "iterator", "slice", "simd", "unrolled", "threads", "fastest"
+    );
+    let distance = |value: i64| (value - 1_000).abs();
+    for len in LENGTHS {
+        let values: Vec<i64> = (0..len as i64).map(|i| i * 7 % 10_007).collect();
+        for fraction in NULL_FRACTIONS {
+            let bytes = validity(len, fraction);
+            let bitmap = bytes.as_deref().map(|bytes| Bitmap::new(bytes, 0, len));
+            let serial = |strategy| {
+                time(|| sum_rows(strategy, black_box(&values), bitmap, 0..len, &distance).unwrap())
+            };
+            let times = [
+                ("iterator", serial(LoopStrategy::Iterator)),
+                ("slice", serial(LoopStrategy::Slice)),
+                ("simd", serial(LoopStrategy::Simd)),
+                ("unrolled", serial(LoopStrategy::Unrolled)),
+                (
+                    "threads",
+                    time(|| {
+                        exec::reduce(
+                            len,
+                            &threads,
+                            Some(0),
+                            |sum: Option<i64>, rows| {
+                                let rows =
+                                    sum_rows(LoopStrategy::Simd, &values, bitmap, rows, &distance);
+                                sum?.checked_add(rows?)
+                            },
+                            |a, b| a?.checked_add(b?),
+                        )
+                        .unwrap()
+                        .unwrap()
+                    }),
+                ),
+            ];
+            let fastest = times.iter().min_by_key(|(_, time)| *time).unwrap().0;
+            let nulls = bitmap.map_or(0, |bitmap| len - bitmap.count_set());
+            let plan = strategy::plan(len, nulls, 8, &threads).unwrap();
+            println!(
+                "{len:>10} {fraction:>6} {:>12.2?} {:>12.2?} {:>12.2?} {:>12.2?} {:>12.2?}  \
+                 {fastest:<9} {:?} in {} threads",
+                times[0].1,
+                times[1].1,
+                times[2].1,
+                times[3].1,
+                times[4].1,
+                plan.loop_strategy,
+                plan.threads
+            );
+        }
+    }
+}
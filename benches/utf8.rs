@@ -0,0 +1,70 @@
+//! Uppercase of Utf8 arrays with `utf8::upper`, writing the converted
+//! strings in the scratch arena, compared to allocating a `String` for every
+//! one with `str::to_uppercase`, for ASCII strings and strings with other
+//! characters, of several lengths.
+//!
+//! cargo bench --bench utf8
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use distance::array::ArrowArray;
+use distance::binary::Utf8Builder;
+use distance::export::{export_array, export_schema};
+use distance::options::ArrowUdfExecOptions;
+use distance::schema::{ArrowType, Schema};
+use distance::utf8;
+
+const LENGTHS: [usize; 3] = [1_000, 100_000, 1_000_000];
+const WORDS: [(&str, [&str; 4]); 2] = [
+    ("ascii", ["apache", "arrow", "columnar", "format"]),
+    ("unicode", ["straße", "ωmega", "café", "ærø"]),
+];
+
+/// Calls of every measurement, at least.
+const MIN_REPEATS: u32 = 5;
+/// Time every measurement takes, at least.
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    // Called once before timing, so the first allocations of the arena and
+    // of the outputs aren't counted.
+    f();
+    let mut repeats = 0;
+    let started = Instant::now();
+    while repeats < MIN_REPEATS || started.elapsed() < MIN_DURATION {
+        f();
+        repeats += 1;
+    }
+    started.elapsed() / repeats
+}
+
+fn main() {
+    let options = ArrowUdfExecOptions::default();
+    println!(
+        "{:>10} {:>8} {:>12} {:>12}",
+        "length", "strings", "arena", "string"
+    );
+    for len in LENGTHS {
+        for (name, words) in WORDS {
+            let mut builder = Utf8Builder::with_capacity(len);
+            (0..len).for_each(|i| builder.push(Some(words[i % words.len()])));
+            let schema = export_schema(&Schema::new(ArrowType::Utf8, "s"));
+            let ffi_array = export_array(builder.finish());
+            let schema = unsafe { Schema::from_ffi(&schema) }.unwrap();
+            let array = unsafe { ArrowArray::new(&schema, &ffi_array) };
+            let arena = time(|| {
+                black_box(utf8::upper(black_box(&array), &options).unwrap());
+            });
+            let string = time(|| {
+                let mut builder = Utf8Builder::with_capacity(len);
+                for i in 0..len {
+                    let value = array.utf8_value(i).unwrap();
+                    builder.push(Some(&value.to_uppercase()));
+                }
+                black_box(builder.finish());
+            });
+            println!("{len:>10} {name:>8} {arena:>12.2?} {string:>12.2?}");
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! `#[derive(ArrowRow)]`, mapping the fields of a Rust struct to the children
+//! of a struct array. See `distance::row` for how the rows are read and built.
+//!
+//! `#[arrow_udf(setup = ..., teardown = ...)]`, making a Rust function an
+//! entry point applying it with `distance::udf::map_with_setup`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, FnArg, ItemFn, Lifetime, Path, ReturnType,
+};
+
+/// Implement `distance::row::ArrowRow` for a struct with named fields, each
+/// of a type implementing `distance::row::ArrowField`. Fields are matched by
+/// name with the children of struct arrays. The struct can borrow from the
+/// arrays through a single lifetime parameter, like `struct Row<'a>`.
+#[proc_macro_derive(ArrowRow)]
+pub fn derive_arrow_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            input,
+            "ArrowRow can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            input,
+            "ArrowRow can only be derived for structs with named fields",
+        ));
+    };
+    let generics = &input.generics;
+    let lifetimes: Vec<&Lifetime> = generics.lifetimes().map(|def| &def.lifetime).collect();
+    if generics.params.len() != lifetimes.len() || lifetimes.len() > 1 {
+        return Err(Error::new_spanned(
+            generics,
+            "ArrowRow can only be derived for structs with at most a lifetime parameter",
+        ));
+    }
+    let (lifetime, type_generics) = match lifetimes.first() {
+        Some(lifetime) => ((*lifetime).clone(), quote!(<#lifetime>)),
+        None => (Lifetime::new("'a", Span::call_site()), quote!()),
+    };
+
+    let idents: Vec<_> = fields.named.iter().map(|field| &field.ident).collect();
+    let names: Vec<String> = fields
+        .named
+        .iter()
+        .map(|field| field.ident.as_ref().unwrap().unraw().to_string())
+        .collect();
+    let types: Vec<_> = fields.named.iter().map(|field| &field.ty).collect();
+    let columns = 0..idents.len();
+
+    Ok(quote! {
+        impl<#lifetime> ::distance::row::ArrowRow<#lifetime> for #name #type_generics {
+            fn fields() -> ::std::vec::Vec<::distance::schema::Schema> {
+                ::std::vec![#(
+                    ::distance::schema::Schema::new(
+                        <#types as ::distance::row::ArrowField<#lifetime>>::DATA_TYPE,
+                        #names,
+                    )
+                ),*]
+            }
+
+            fn read(
+                columns: &[::distance::array::ArrowArray<#lifetime>],
+                i: usize,
+            ) -> ::distance::error::Result<Self> {
+                ::std::result::Result::Ok(#name {#(
+                    #idents: <#types as ::distance::row::ArrowField<#lifetime>>::read(
+                        &columns[#columns],
+                        i,
+                    )?
+                ),*})
+            }
+
+            fn build(rows: &[Self]) -> ::std::vec::Vec<::distance::export::ArrayData> {
+                ::std::vec![#(
+                    <#types as ::distance::row::ArrowField<#lifetime>>::build(
+                        rows.iter().map(|row| ::std::option::Option::Some(&row.#idents)),
+                    )
+                ),*]
+            }
+        }
+    })
+}
+
+/// Make a function `fn name(state: &S, x: T) -> O` the entry point `name`,
+/// applying it to every element of an array of `T` with
+/// `distance::udf::map_with_setup`. The function `setup`, of the options
+/// returning `distance::error::Result<S>`, makes the state at the start of
+/// every batch, and `teardown`, if given, receives it at the end:
+///
+/// ```text
+/// #[arrow_udf(setup = load_table, teardown = release_table)]
+/// fn lookup(table: &Table, key: i64) -> f64 { ... }
+/// ```
+///
+/// The entry point takes the schema and the array of the input, the
+/// options, and the schema and the array of the output, like the built-in
+/// kernels.
+#[proc_macro_attribute]
+pub fn arrow_udf(args: TokenStream, item: TokenStream) -> TokenStream {
+    let (mut setup, mut teardown) = (None, None);
+    let parser = syn::meta::parser(|meta| {
+        let function = match meta.path.get_ident() {
+            Some(ident) if ident == "setup" => &mut setup,
+            Some(ident) if ident == "teardown" => &mut teardown,
+            _ => return Err(meta.error("expected `setup` or `teardown`")),
+        };
+        *function = Some(meta.value()?.parse::<Path>()?);
+        Ok(())
+    });
+    parse_macro_input!(args with parser);
+    let function = parse_macro_input!(item as ItemFn);
+    expand_udf(setup, teardown, function)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_udf(
+    setup: Option<Path>,
+    teardown: Option<Path>,
+    mut function: ItemFn,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let Some(setup) = setup else {
+        return Err(Error::new(
+            Span::call_site(),
+            "arrow_udf needs a `setup` function",
+        ));
+    };
+    let teardown = match teardown {
+        Some(teardown) => quote!(#teardown),
+        None => quote!(::std::mem::drop),
+    };
+    let signature = &function.sig;
+    if signature.inputs.len() != 2
+        || signature
+            .inputs
+            .iter()
+            .any(|input| matches!(input, FnArg::Receiver(_)))
+    {
+        return Err(Error::new_spanned(
+            &signature.inputs,
+            "an arrow_udf takes the state and a value",
+        ));
+    }
+    if let ReturnType::Default = signature.output {
+        return Err(Error::new_spanned(
+            signature,
+            "an arrow_udf returns the value of its result",
+        ));
+    }
+    if !signature.generics.params.is_empty() || signature.asyncness.is_some() {
+        return Err(Error::new_spanned(
+            signature,
+            "an arrow_udf can't be generic or async",
+        ));
+    }
+    let name = function.sig.ident.clone();
+    let vis = std::mem::replace(&mut function.vis, syn::Visibility::Inherited);
+    let attrs = std::mem::take(&mut function.attrs);
+
+    Ok(quote! {
+        #(#attrs)*
+        ///
+        /// # Safety
+        ///
+        /// `schema` and `array` must point to a valid Arrow C Data Interface
+        /// array, `options` must be null or valid, and `out_schema` and
+        /// `out_array` must be valid for writes.
+        #[no_mangle]
+        #vis unsafe extern "C" fn #name(
+            schema: *const ::distance::ffi::ArrowCDataInterfaceSchema,
+            array: *const ::distance::ffi::ArrowCDataInterfaceArray,
+            options: *const ::distance::options::ArrowUdfExecOptions,
+            out_schema: *mut ::distance::ffi::ArrowCDataInterfaceSchema,
+            out_array: *mut ::distance::ffi::ArrowCDataInterfaceArray,
+        ) -> ::distance::error::ArrowUdfStatus {
+            #function
+            ::distance::udf::map_with_setup_ffi(
+                schema, array, options, out_schema, out_array, #setup, #name, #teardown,
+            )
+        }
+    })
+}
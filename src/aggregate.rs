@@ -0,0 +1,1021 @@
+//! Aggregate functions computed per group, used by the group by engine.
+//!
+//! An aggregate keeps a state for every group, updated with the values of
+//! the rows of each group, one batch at a time. When all the batches are
+//! processed, the states are converted into an array with a value per
+//! group. Groups without any non-null value are null in the result, except
+//! for `count`, which is 0. With the propagate null policy, groups with any
+//! null value are null in the states and in the result, and null states
+//! make the merged groups null.
+//!
+//! Aggregates are named by a spec: the name of the function, followed by
+//! its scalar arguments between parenthesis if it has any, for example
+//! `approx_quantile(0.9)`.
+//!
+//! States can also be serialized, so partial aggregates computed over
+//! different partitions of the data, possibly in different processes, can
+//! be merged before computing the final result:
+//!
+//! - `arrow_udf_aggregate_state` returns the state of a partition, as a
+//!   Binary array with a single element.
+//! - `arrow_udf_aggregate_merge` merges all the states of a Binary array
+//!   into a single one.
+//! - `arrow_udf_aggregate_finish` merges all the states of a Binary array,
+//!   and returns the result of the aggregate.
+//!
+//! The schema of the states records the spec of the aggregate and the type
+//! of its input in its metadata, so merging only requires the states.
+
+use std::ffi::{c_char, CStr};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::binary::BinaryBuilder;
+use crate::bitmap::{Bitmap, BitmapBuilder};
+use crate::bloom;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::hll;
+use crate::nan;
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::sample;
+use crate::schema::{ArrowType, Schema};
+use crate::tdigest;
+use crate::types::NativeType;
+use crate::udf;
+use crate::welford;
+
+/// Metadata key of the states, with the spec of the aggregate.
+pub const STATE_AGGREGATE_METADATA_KEY: &str = "arrow_udf.aggregate";
+
+/// Metadata key of the states, with the format of the input of the aggregate.
+pub const STATE_INPUT_TYPE_METADATA_KEY: &str = "arrow_udf.input_type";
+
+/// State of an aggregate for every group.
+pub trait Accumulator: Send {
+    /// Type of the values of the result.
+    fn output_type(&self) -> ArrowType;
+
+    /// Make room for the states of `n_groups` groups, called before any
+    /// update with new groups.
+    fn resize(&mut self, n_groups: usize);
+
+    /// Update the states with the `rows` of `values`, where `groups` has the
+    /// group of every row.
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]);
+
+    /// Merge the serialized `rows` of `states` into the states, where
+    /// `groups` has the group of every row. Null states are skipped.
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()>;
+
+    /// The serialized state of every group, as a Binary array.
+    fn state(self: Box<Self>) -> ArrayData;
+
+    /// The value of the aggregate for every group.
+    fn finish(self: Box<Self>) -> ArrayData;
+}
+
+/// Create the accumulator of an aggregate for values of the given type,
+/// with the scalar arguments of its spec.
+pub type AccumulatorFactory = fn(ArrowType, &[f64]) -> Result<Box<dyn Accumulator>>;
+
+/// Aggregate function that hosts can refer to by name.
+pub struct AggregateFunction {
+    pub name: &'static str,
+    /// Fails if the type or the arguments are not supported.
+    pub accumulator: AccumulatorFactory,
+}
+
+pub static AGGREGATES: &[AggregateFunction] = &[
+    AggregateFunction {
+        name: "count",
+        accumulator: |_, args| {
+            no_args("count", args)?;
+            Ok(Box::new(Count::default()))
+        },
+    },
+    AggregateFunction {
+        name: "sum",
+        accumulator: |data_type, args| {
+            no_args("sum", args)?;
+            match data_type {
+                ArrowType::Int64 => Ok(Box::new(Sum::<i64>::default())),
+                ArrowType::Float64 => Ok(Box::new(Sum::<f64>::default())),
+                other => Err(unsupported("sum", other)),
+            }
+        },
+    },
+    AggregateFunction {
+        name: "min",
+        accumulator: |data_type, args| {
+            no_args("min", args)?;
+            match data_type {
+                ArrowType::Int64 => Ok(Box::new(MinMax::<i64>::new(false))),
+                ArrowType::Float64 => Ok(Box::new(MinMax::<f64>::new(false))),
+                other => Err(unsupported("min", other)),
+            }
+        },
+    },
+    AggregateFunction {
+        name: "max",
+        accumulator: |data_type, args| {
+            no_args("max", args)?;
+            match data_type {
+                ArrowType::Int64 => Ok(Box::new(MinMax::<i64>::new(true))),
+                ArrowType::Float64 => Ok(Box::new(MinMax::<f64>::new(true))),
+                other => Err(unsupported("max", other)),
+            }
+        },
+    },
+    AggregateFunction {
+        name: "mean",
+        accumulator: |data_type, args| {
+            no_args("mean", args)?;
+            welford::accumulator("mean", data_type, welford::Statistic::Mean)
+        },
+    },
+    AggregateFunction {
+        name: "var",
+        accumulator: |data_type, args| {
+            let ddof = welford::ddof("var", args)?;
+            welford::accumulator("var", data_type, welford::Statistic::Variance(ddof))
+        },
+    },
+    AggregateFunction {
+        name: "std",
+        accumulator: |data_type, args| {
+            let ddof = welford::ddof("std", args)?;
+            welford::accumulator("std", data_type, welford::Statistic::Std(ddof))
+        },
+    },
+    AggregateFunction {
+        name: "approx_quantile",
+        accumulator: tdigest::accumulator,
+    },
+    AggregateFunction {
+        name: "approx_count_distinct",
+        accumulator: hll::accumulator,
+    },
+    AggregateFunction {
+        name: "bloom_filter",
+        accumulator: bloom::accumulator,
+    },
+    AggregateFunction {
+        name: "reservoir_sample",
+        accumulator: sample::accumulator,
+    },
+];
+
+/// The aggregate function named `name`.
+pub fn lookup(name: &str) -> Option<&'static AggregateFunction> {
+    AGGREGATES.iter().find(|function| function.name == name)
+}
+
+pub(crate) fn unsupported(name: &str, data_type: ArrowType) -> Error {
+    Error::UnsupportedType(format!("{name} of {data_type:?} values"))
+}
+
+fn no_args(name: &str, args: &[f64]) -> Result<()> {
+    if !args.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "{name} doesn't have arguments"
+        )));
+    }
+    Ok(())
+}
+
+/// An aggregate function together with its scalar arguments.
+#[derive(Clone)]
+pub struct AggregateSpec {
+    pub function: &'static AggregateFunction,
+    pub args: Vec<f64>,
+}
+
+impl AggregateSpec {
+    /// Parse a spec like `sum` or `approx_quantile(0.9)`.
+    pub fn parse(spec: &str) -> Result<AggregateSpec> {
+        let invalid = || Error::InvalidArgument(format!("invalid aggregate {spec:?}"));
+        let (name, args) = match spec.split_once('(') {
+            Some((name, args)) => {
+                let args = args.trim_end().strip_suffix(')').ok_or_else(invalid)?;
+                let args = args
+                    .split(',')
+                    .map(|arg| arg.trim().parse::<f64>().map_err(|_| invalid()))
+                    .collect::<Result<_>>()?;
+                (name.trim(), args)
+            }
+            None => (spec.trim(), Vec::new()),
+        };
+        let function = lookup(name)
+            .ok_or_else(|| Error::InvalidArgument(format!("unknown aggregate {name:?}")))?;
+        Ok(AggregateSpec { function, args })
+    }
+
+    pub fn accumulator(&self, data_type: ArrowType) -> Result<Box<dyn Accumulator>> {
+        (self.function.accumulator)(data_type, &self.args)
+    }
+
+    /// Name of the result of aggregating the column `column`.
+    pub fn column_name(&self, column: &str) -> String {
+        let args: String = self.args.iter().map(|arg| format!(", {arg}")).collect();
+        format!("{}({column}{args})", self.function.name)
+    }
+}
+
+impl std::fmt::Display for AggregateSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.function.name)?;
+        if !self.args.is_empty() {
+            let args: Vec<_> = self.args.iter().map(|arg| arg.to_string()).collect();
+            write!(f, "({})", args.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Numeric values that can be aggregated.
+pub trait Number: NativeType + PartialOrd {
+    /// Addition, wrapping on overflow for integers.
+    fn add(self, other: Self) -> Self;
+    fn to_f64(self) -> f64;
+    fn to_bits(self) -> u64;
+    fn from_bits(bits: u64) -> Self;
+}
+
+impl Number for i64 {
+    fn add(self, other: i64) -> i64 {
+        self.wrapping_add(other)
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+
+    fn from_bits(bits: u64) -> i64 {
+        bits as i64
+    }
+}
+
+impl Number for f64 {
+    fn add(self, other: f64) -> f64 {
+        self + other
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+
+    fn from_bits(bits: u64) -> f64 {
+        f64::from_bits(bits)
+    }
+}
+
+/// Reader of the fields of a serialized state.
+pub struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+    pub fn new(state: &'a [u8]) -> StateReader<'a> {
+        StateReader(state)
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let (bytes, rest) = self
+            .0
+            .split_first_chunk::<8>()
+            .ok_or_else(|| Error::InvalidArgument("truncated aggregate state".to_string()))?;
+        self.0 = rest;
+        Ok(u64::from_le_bytes(*bytes))
+    }
+
+    pub fn f64(&mut self) -> Result<f64> {
+        self.u64().map(f64::from_bits)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::InvalidArgument(
+                "truncated aggregate state".to_string(),
+            ));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+}
+
+/// Call `f` with the group and the value of the non-null `rows` of `values`.
+pub(crate) fn for_each_valid<T: NativeType>(
+    values: &ArrowArray,
+    rows: Range<usize>,
+    groups: &[u32],
+    mut f: impl FnMut(usize, T),
+) {
+    let data = &values.values::<T>()[rows.clone()];
+    match values.validity().filter(|_| values.null_count() > 0) {
+        Some(validity) => {
+            for (i, (group, value)) in groups.iter().zip(data).enumerate() {
+                if validity.is_set(rows.start + i) {
+                    f(*group as usize, *value);
+                }
+            }
+        }
+        None => {
+            for (group, value) in groups.iter().zip(data) {
+                f(*group as usize, *value);
+            }
+        }
+    }
+}
+
+/// Call `f` with the group and the reader of the non-null `rows` of
+/// `states`.
+pub(crate) fn for_each_state(
+    states: &ArrowArray,
+    rows: Range<usize>,
+    groups: &[u32],
+    mut f: impl FnMut(usize, StateReader) -> Result<()>,
+) -> Result<()> {
+    if states.data_type() != ArrowType::Binary {
+        return Err(Error::UnsupportedType(format!(
+            "aggregate states of type {:?}",
+            states.data_type()
+        )));
+    }
+    for (row, group) in rows.zip(groups) {
+        if states.is_valid(row) {
+            f(*group as usize, StateReader::new(states.binary_value(row)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Binary array with the serialized state of `n_groups` groups, written by
+/// `write` into the bytes of each group.
+pub(crate) fn states_array(
+    n_groups: usize,
+    mut write: impl FnMut(usize, &mut Vec<u8>),
+) -> ArrayData {
+    let mut builder = BinaryBuilder::with_capacity(n_groups);
+    let mut state = Vec::new();
+    for group in 0..n_groups {
+        state.clear();
+        write(group, &mut state);
+        builder.push(Some(&state));
+    }
+    builder.finish()
+}
+
+/// Array with the `values` of every group, null for the groups with a count
+/// of 0.
+pub(crate) fn nullable_result<T: NativeType>(values: &[T], counts: &[i64]) -> ArrayData {
+    let data = ArrayData::primitive(Buffer::from_slice(values), values.len());
+    let null_count = counts.iter().filter(|count| **count == 0).count();
+    if null_count == 0 {
+        return data;
+    }
+    let mut validity = BitmapBuilder::with_capacity(counts.len());
+    counts.iter().for_each(|count| validity.push(*count > 0));
+    data.with_validity(Some(validity.finish()), null_count)
+}
+
+#[derive(Default)]
+struct Count {
+    counts: Vec<i64>,
+}
+
+impl Accumulator for Count {
+    fn output_type(&self) -> ArrowType {
+        ArrowType::Int64
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.counts.resize(n_groups, 0);
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        match values.validity().filter(|_| values.null_count() > 0) {
+            Some(validity) => {
+                for (i, group) in groups.iter().enumerate() {
+                    self.counts[*group as usize] += validity.is_set(rows.start + i) as i64;
+                }
+            }
+            None => groups
+                .iter()
+                .for_each(|group| self.counts[*group as usize] += 1),
+        }
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        for_each_state(states, rows, groups, |group, mut state| {
+            self.counts[group] += state.u64()? as i64;
+            Ok(())
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        states_array(self.counts.len(), |group, state| {
+            state.extend_from_slice(&self.counts[group].to_le_bytes())
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        ArrayData::primitive(Buffer::from_slice(&self.counts), self.counts.len())
+    }
+}
+
+#[derive(Default)]
+struct Sum<T> {
+    sums: Vec<T>,
+    counts: Vec<i64>,
+}
+
+impl<T: Number> Accumulator for Sum<T> {
+    fn output_type(&self) -> ArrowType {
+        T::ARROW_TYPE
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.sums.resize(n_groups, T::default());
+        self.counts.resize(n_groups, 0);
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        for_each_valid(values, rows, groups, |group, value: T| {
+            self.sums[group] = self.sums[group].add(value);
+            self.counts[group] += 1;
+        });
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        for_each_state(states, rows, groups, |group, mut state| {
+            self.sums[group] = self.sums[group].add(T::from_bits(state.u64()?));
+            self.counts[group] += state.u64()? as i64;
+            Ok(())
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        states_array(self.sums.len(), |group, state| {
+            state.extend_from_slice(&self.sums[group].to_bits().to_le_bytes());
+            state.extend_from_slice(&self.counts[group].to_le_bytes());
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        nullable_result(&self.sums, &self.counts)
+    }
+}
+
+struct MinMax<T> {
+    max: bool,
+    values: Vec<T>,
+    counts: Vec<i64>,
+}
+
+impl<T: Number> MinMax<T> {
+    fn new(max: bool) -> MinMax<T> {
+        MinMax {
+            max,
+            values: Vec::new(),
+            counts: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, group: usize, value: T, count: i64) {
+        let current = self.values[group];
+        if self.counts[group] == 0
+            || (self.max && value > current)
+            || (!self.max && value < current)
+        {
+            self.values[group] = value;
+        }
+        self.counts[group] += count;
+    }
+}
+
+impl<T: Number> Accumulator for MinMax<T> {
+    fn output_type(&self) -> ArrowType {
+        T::ARROW_TYPE
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.values.resize(n_groups, T::default());
+        self.counts.resize(n_groups, 0);
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        for_each_valid(values, rows, groups, |group, value: T| {
+            self.add(group, value, 1)
+        });
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        for_each_state(states, rows, groups, |group, mut state| {
+            let value = T::from_bits(state.u64()?);
+            let count = state.u64()? as i64;
+            if count > 0 {
+                self.add(group, value, count);
+            }
+            Ok(())
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        states_array(self.values.len(), |group, state| {
+            state.extend_from_slice(&self.values[group].to_bits().to_le_bytes());
+            state.extend_from_slice(&self.counts[group].to_le_bytes());
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        nullable_result(&self.values, &self.counts)
+    }
+}
+
+/// Accumulator whose groups that received nulls are null in the states and
+/// in the result, for the propagate null policy. The groups are flagged by
+/// the caller, from the input before masking NaN, so skipped NaN values
+/// don't make the result null.
+pub(crate) struct PropagateNulls {
+    inner: Box<dyn Accumulator>,
+    null_groups: Vec<bool>,
+}
+
+impl PropagateNulls {
+    pub(crate) fn new(inner: Box<dyn Accumulator>, null_groups: Vec<bool>) -> PropagateNulls {
+        PropagateNulls { inner, null_groups }
+    }
+}
+
+/// Flag in `null_groups` the groups of the null `rows` of `array`, where
+/// `groups` has the group of every row.
+pub(crate) fn flag_null_groups(
+    array: &ArrowArray,
+    rows: Range<usize>,
+    groups: &[u32],
+    null_groups: &mut [bool],
+) {
+    if let Some(validity) = array.validity().filter(|_| array.null_count() > 0) {
+        for (i, group) in rows.zip(groups) {
+            if !validity.is_set(i) {
+                null_groups[*group as usize] = true;
+            }
+        }
+    }
+}
+
+/// `data`, with one element per group, and also null for `null_groups`.
+fn mask_null_groups(data: ArrayData, null_groups: &[bool]) -> ArrayData {
+    let validity = data.buffers[0]
+        .as_ref()
+        .filter(|_| data.null_count > 0)
+        .map(|validity| Bitmap::new(validity.as_slice(), 0, data.length));
+    let mut masked = BitmapBuilder::with_capacity(data.length);
+    let mut null_count = 0;
+    for (group, null) in null_groups.iter().enumerate() {
+        let valid = !null && validity.is_none_or(|validity| validity.is_set(group));
+        null_count += !valid as usize;
+        masked.push(valid);
+    }
+    data.with_validity(Some(masked.finish()), null_count)
+}
+
+impl Accumulator for PropagateNulls {
+    fn output_type(&self) -> ArrowType {
+        self.inner.output_type()
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.inner.resize(n_groups);
+        self.null_groups.resize(n_groups, false);
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        self.inner.update(values, rows, groups);
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        // Null states are the ones of groups that received nulls.
+        flag_null_groups(states, rows.clone(), groups, &mut self.null_groups);
+        self.inner.merge(states, rows, groups)
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        let PropagateNulls { inner, null_groups } = *self;
+        mask_null_groups(inner.state(), &null_groups)
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        let PropagateNulls { inner, null_groups } = *self;
+        mask_null_groups(inner.finish(), &null_groups)
+    }
+}
+
+/// Accumulator of a single group, updated with all the rows of `array`.
+pub(crate) fn aggregate_all(
+    spec: &AggregateSpec,
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<Box<dyn Accumulator>> {
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let mut accumulator = spec.accumulator(array.data_type())?;
+    accumulator.resize(1);
+    if udf::null_result(array, options)? {
+        // The result is null whatever the values are.
+        return Ok(Box::new(PropagateNulls::new(accumulator, vec![true])));
+    }
+    let masked = nan::mask_non_finite(array, options)?;
+    let array = &masked
+        .as_ref()
+        .map_or(*array, |masked| masked.array(array.schema()));
+    let groups = vec![0; options.batch_len(array.len())];
+    exec::for_each_batch(array.len(), options, |rows| {
+        let n_rows = rows.len();
+        accumulator.update(array, rows, &groups[..n_rows]);
+        Ok(())
+    })?;
+    Ok(accumulator)
+}
+
+/// Accumulator of a single group, merging all the states of `states`.
+fn merge_all(
+    states: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(AggregateSpec, Box<dyn Accumulator>)> {
+    let metadata = &states.schema().metadata;
+    let missing =
+        || Error::InvalidArgument("the array doesn't contain aggregate states".to_string());
+    let spec = AggregateSpec::parse(
+        metadata
+            .get(STATE_AGGREGATE_METADATA_KEY)
+            .ok_or_else(missing)?,
+    )?;
+    let format = metadata
+        .get(STATE_INPUT_TYPE_METADATA_KEY)
+        .ok_or_else(missing)?;
+    let input_type = ArrowType::from_format(format)
+        .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+    let mut accumulator = spec.accumulator(input_type)?;
+    if options.null_policy()? == NullPolicy::Propagate {
+        accumulator = Box::new(PropagateNulls::new(accumulator, Vec::new()));
+    }
+    accumulator.resize(1);
+    let groups = vec![0; options.batch_len(states.len())];
+    exec::for_each_batch(states.len(), options, |rows| {
+        let n_rows = rows.len();
+        accumulator.merge(states, rows, &groups[..n_rows])
+    })?;
+    Ok((spec, accumulator))
+}
+
+/// Schema of the serialized states of `spec` over `input`.
+pub(crate) fn state_schema(spec: &AggregateSpec, input_type: ArrowType, name: &str) -> Schema {
+    Schema::new(ArrowType::Binary, name)
+        .with_metadata(STATE_AGGREGATE_METADATA_KEY, &spec.to_string())
+        .with_metadata(STATE_INPUT_TYPE_METADATA_KEY, input_type.format())
+}
+
+/// Serialized state of the aggregate `spec` over all the values of an
+/// array, as a Binary array with a single element, to be merged with other
+/// states by `arrow_udf_aggregate_merge` or `arrow_udf_aggregate_finish`.
+///
+/// # Safety
+///
+/// `spec` must be a valid null-terminated string, `schema` and `array` must
+/// point to a valid Arrow C Data Interface array, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_aggregate_state(
+    spec: *const c_char,
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let spec = AggregateSpec::parse(&CStr::from_ptr(spec).to_string_lossy())?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let accumulator = aggregate_all(&spec, &array, &options)?;
+        let out = state_schema(&spec, array.data_type(), &schema.name);
+        export::export_to(&out, &Arc::new(accumulator.state()), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Merge all the aggregate states of a Binary array created by
+/// `arrow_udf_aggregate_state` or by this function into a single state.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_aggregate_merge(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (_, accumulator) = merge_all(&array, &options)?;
+        export::export_to(
+            &schema,
+            &Arc::new(accumulator.state()),
+            out_schema,
+            out_array,
+        )?;
+        Ok(())
+    })
+}
+
+/// Merge all the aggregate states of a Binary array created by
+/// `arrow_udf_aggregate_state` or `arrow_udf_aggregate_merge`, and return
+/// the result of the aggregate as an array with a single element.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_aggregate_finish(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (spec, accumulator) = merge_all(&array, &options)?;
+        let out = Schema::new(accumulator.output_type(), &spec.column_name(&schema.name));
+        export::export_to(&out, &Arc::new(accumulator.finish()), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    /// Aggregate `values` in two batches, split at `split`, with the group
+    /// of every row in `groups`.
+    fn aggregate<T: NativeType>(
+        name: &str,
+        values: &[Option<T>],
+        groups: &[u32],
+        split: usize,
+    ) -> Exported {
+        let input = Exported::nullable(values);
+        let spec = AggregateSpec::parse(name).unwrap();
+        let mut accumulator = spec.accumulator(T::ARROW_TYPE).unwrap();
+        let n_groups = |rows: &[u32]| rows.iter().max().map_or(0, |max| *max as usize + 1);
+        input.with_array(|array| {
+            accumulator.resize(n_groups(&groups[..split]));
+            accumulator.update(array, 0..split, &groups[..split]);
+            accumulator.resize(n_groups(groups));
+            accumulator.update(array, split..values.len(), &groups[split..]);
+        });
+        let output_type = accumulator.output_type();
+        Exported::new(&Schema::new(output_type, "x"), accumulator.finish())
+    }
+
+    #[test]
+    fn aggregates_per_group() {
+        let values = [Some(4_i64), None, Some(-2), Some(7), None, Some(1)];
+        let groups = [0, 1, 0, 2, 3, 2];
+        assert_eq!(
+            aggregate("count", &values, &groups, 2).values::<i64>(),
+            [2, 0, 2, 0]
+        );
+        assert_eq!(
+            aggregate("sum", &values, &groups, 2).nullable_values::<i64>(),
+            [Some(2), None, Some(8), None]
+        );
+        assert_eq!(
+            aggregate("min", &values, &groups, 3).nullable_values::<i64>(),
+            [Some(-2), None, Some(1), None]
+        );
+        assert_eq!(
+            aggregate("max", &values, &groups, 3).nullable_values::<i64>(),
+            [Some(4), None, Some(7), None]
+        );
+        assert_eq!(
+            aggregate("mean", &values, &groups, 4).nullable_values::<f64>(),
+            [Some(1.0), None, Some(4.0), None]
+        );
+    }
+
+    #[test]
+    fn float_aggregates() {
+        let values = [Some(0.5_f64), Some(-1.5), Some(3.0)];
+        let groups = [0, 0, 1];
+        assert_eq!(
+            aggregate("sum", &values, &groups, 1).values::<f64>(),
+            [-1.0, 3.0]
+        );
+        assert_eq!(
+            aggregate("min", &values, &groups, 1).values::<f64>(),
+            [-1.5, 3.0]
+        );
+    }
+
+    #[test]
+    fn integer_sums_wrap() {
+        let values = [Some(i64::MAX), Some(1)];
+        assert_eq!(
+            aggregate("sum", &values, &[0, 0], 1).values::<i64>(),
+            [i64::MIN]
+        );
+    }
+
+    #[test]
+    fn unsupported_aggregates() {
+        assert!(lookup("median").is_none());
+        for name in ["sum", "min", "max", "mean"] {
+            assert!(matches!(
+                (lookup(name).unwrap().accumulator)(ArrowType::Int32, &[]),
+                Err(Error::UnsupportedType(_))
+            ));
+        }
+        assert!((lookup("count").unwrap().accumulator)(ArrowType::Int32, &[]).is_ok());
+    }
+
+    #[test]
+    fn specs_with_arguments() {
+        let spec = AggregateSpec::parse(" approx_quantile( 0.5 , 50 ) ").unwrap();
+        assert_eq!(spec.function.name, "approx_quantile");
+        assert_eq!(spec.args, [0.5, 50.0]);
+        assert_eq!(spec.to_string(), "approx_quantile(0.5, 50)");
+        assert_eq!(spec.column_name("x"), "approx_quantile(x, 0.5, 50)");
+        assert_eq!(AggregateSpec::parse("count").unwrap().to_string(), "count");
+        for spec in [
+            "median",
+            "sum(1)",
+            "approx_quantile(0.5",
+            "approx_quantile(a)",
+        ] {
+            let result = AggregateSpec::parse(spec).and_then(|s| s.accumulator(ArrowType::Int64));
+            assert!(
+                matches!(result, Err(Error::InvalidArgument(_))),
+                "{spec:?} should fail"
+            );
+        }
+    }
+
+    type StatesEntryPoint = unsafe extern "C" fn(
+        *const ArrowCDataInterfaceSchema,
+        *const ArrowCDataInterfaceArray,
+        *const ArrowUdfExecOptions,
+        *mut ArrowCDataInterfaceSchema,
+        *mut ArrowCDataInterfaceArray,
+    ) -> ArrowUdfStatus;
+
+    fn state(spec: &CStr, input: &Exported) -> Exported {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_aggregate_state(
+                spec.as_ptr(),
+                &input.schema,
+                &input.array,
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        out
+    }
+
+    fn call(
+        f: StatesEntryPoint,
+        states: &Exported,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            f(
+                &states.schema,
+                &states.array,
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    /// Binary array with the single state of every partition, and a null.
+    fn concat_states(partitions: &[Exported]) -> Exported {
+        let mut builder = BinaryBuilder::with_capacity(partitions.len() + 1);
+        for partition in partitions {
+            partition.with_array(|array| builder.push(Some(array.binary_value(0))));
+        }
+        builder.push(None);
+        let schema = partitions[0].with_array(|array| array.schema().clone());
+        Exported::new(&schema, builder.finish())
+    }
+
+    #[test]
+    fn partial_states_through_ffi() {
+        let first = Exported::nullable(&[Some(3_i64), None, Some(-8)]);
+        let second = Exported::nullable(&[Some(5_i64), Some(1)]);
+        let empty = Exported::primitive::<i64>(&[]);
+        for (spec, expected) in [
+            (c"count", 4.0),
+            (c"sum", 1.0),
+            (c"min", -8.0),
+            (c"max", 5.0),
+            (c"mean", 0.25),
+            (c"approx_quantile(0)", -8.0),
+        ] {
+            let partitions = [
+                state(spec, &first),
+                state(spec, &empty),
+                state(spec, &second),
+            ];
+            let merged = call(arrow_udf_aggregate_merge, &concat_states(&partitions)).unwrap();
+            assert_eq!(merged.with_array(|array| array.len()), 1);
+            let result = call(arrow_udf_aggregate_finish, &merged).unwrap();
+            let value = result.with_array(|array| match array.data_type() {
+                ArrowType::Int64 => array.values::<i64>()[0] as f64,
+                _ => array.values::<f64>()[0],
+            });
+            assert_eq!(value, expected, "{spec:?}");
+        }
+        let sum = call(arrow_udf_aggregate_finish, &state(c"sum", &empty)).unwrap();
+        sum.with_array(|array| assert_eq!(array.schema().name, "sum(x)"));
+        assert_eq!(sum.nullable_values::<i64>(), [None]);
+    }
+
+    #[test]
+    fn states_need_their_metadata() {
+        let plain = Exported::primitive(&[1_i64]);
+        assert_eq!(
+            call(arrow_udf_aggregate_finish, &plain).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let mut builder = BinaryBuilder::with_capacity(1);
+        builder.push(Some(&[1, 2, 3]));
+        let spec = AggregateSpec::parse("sum").unwrap();
+        let schema = state_schema(&spec, ArrowType::Int64, "x");
+        let truncated = Exported::new(&schema, builder.finish());
+        assert_eq!(
+            call(arrow_udf_aggregate_merge, &truncated).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn nulls_are_propagated() {
+        let propagate = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let spec = AggregateSpec::parse("sum").unwrap();
+        let with_nulls = Exported::nullable(&[Some(1_i64), None]);
+        let accumulator = with_nulls.with_array(|array| aggregate_all(&spec, array, &propagate));
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let result = Exported::new(&schema, accumulator.unwrap().finish());
+        assert_eq!(result.nullable_values::<i64>(), [None]);
+        // A null state, like the one `concat_states` appends, makes the
+        // merged result null.
+        let valid = Exported::primitive(&[2_i64, 3]);
+        let states = concat_states(&[state(c"sum", &valid)]);
+        let merged = states.with_array(|array| merge_all(array, &propagate));
+        let (_, accumulator) = merged.unwrap();
+        let result = Exported::new(&schema, accumulator.finish());
+        assert_eq!(result.nullable_values::<i64>(), [None]);
+        let states = state(c"sum", &valid);
+        let merged = states.with_array(|array| merge_all(array, &propagate));
+        let result = Exported::new(&schema, merged.unwrap().1.finish());
+        assert_eq!(result.nullable_values::<i64>(), [Some(5)]);
+    }
+}
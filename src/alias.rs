@@ -0,0 +1,318 @@
+//! Detection of arrays used by several calls at once, and of results
+//! aliasing the inputs of their call.
+//!
+//! Every array imported by an entry point is claimed until the call
+//! returns, by the address of its C Data Interface struct. Arrays moved
+//! into a call, like the input of `arrow_udf_take`, are claimed for it
+//! alone: importing them again in another call while it runs, or in the
+//! same call, fails with `Error::Aliasing`, since the struct of the host is
+//! marked as released by the move, and releasing the array twice would
+//! corrupt the memory of the host. Arrays only read can be imported by any
+//! number of calls at once.
+//!
+//! When the host registers an allocator, or passes an output buffer in the
+//! options, the buffers of the results are in memory of the host, and a
+//! host reusing the memory of an input for a result makes the call
+//! overwrite its own input while reading it. The buffers of the inputs are
+//! recorded, and results overlapping them, or written into the struct of an
+//! input, fail the call with `Error::Aliasing` instead of being exported.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{LazyLock, Mutex};
+
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::ffi::ArrowCDataInterfaceArray;
+use crate::schema::{ArrowType, UnionMode};
+use crate::types::with_native_type;
+
+/// Arrays being imported by running calls.
+#[derive(Default)]
+struct Claim {
+    readers: usize,
+    moved: bool,
+}
+
+static CLAIMS: LazyLock<Mutex<HashMap<usize, Claim>>> = LazyLock::new(Default::default);
+
+/// Arrays claimed by the running call of a thread, with whether they were
+/// moved, and the bytes of their buffers.
+#[derive(Default)]
+struct CallClaims {
+    arrays: Vec<(usize, bool)>,
+    inputs: Vec<Range<usize>>,
+}
+
+thread_local! {
+    static CALL: RefCell<Option<CallClaims>> = const { RefCell::new(None) };
+}
+
+/// Run `f` as a call claiming the arrays it imports, released when it
+/// returns.
+pub(crate) fn with_claims<R>(f: impl FnOnce() -> R) -> R {
+    struct Release(Option<CallClaims>);
+    impl Drop for Release {
+        fn drop(&mut self) {
+            let claims = CALL.with(|call| call.replace(self.0.take()));
+            let Some(claims) = claims.filter(|claims| !claims.arrays.is_empty()) else {
+                return;
+            };
+            let mut all = CLAIMS.lock().unwrap();
+            for (address, moved) in claims.arrays {
+                let claim = all.get_mut(&address).unwrap();
+                claim.readers -= usize::from(!moved);
+                claim.moved &= !moved;
+                if claim.readers == 0 && !claim.moved {
+                    all.remove(&address);
+                }
+            }
+        }
+    }
+    let _release = Release(CALL.with(|call| call.replace(Some(CallClaims::default()))));
+    f()
+}
+
+/// Claim `array` for the running call, moved into it or only read. Fails
+/// with `Error::Aliasing` if it's moved by a running call, or if it's moved
+/// while any call reads it. Outside of calls arrays aren't claimed.
+pub(crate) fn claim(array: *const ArrowCDataInterfaceArray, moved: bool) -> Result<()> {
+    let address = array as usize;
+    CALL.with(|call| {
+        let mut call = call.borrow_mut();
+        let Some(call) = call.as_mut() else {
+            return Ok(());
+        };
+        let mut all = CLAIMS.lock().unwrap();
+        let claim = all.entry(address).or_default();
+        if claim.moved || (moved && claim.readers > 0) {
+            return Err(Error::Aliasing(format!(
+                "the array at {array:p} is {} by another import",
+                if claim.moved { "moved" } else { "read" }
+            )));
+        }
+        match moved {
+            true => claim.moved = true,
+            false => claim.readers += 1,
+        }
+        call.arrays.push((address, moved));
+        Ok(())
+    })
+}
+
+/// Record the buffers of `array`, an input of the running call, to check
+/// that its results don't overlap them.
+pub(crate) fn record_input(array: &ArrowArray) {
+    CALL.with(|call| {
+        if let Some(call) = call.borrow_mut().as_mut() {
+            input_ranges(array, &mut call.inputs);
+        }
+    })
+}
+
+/// The memory of `values`.
+fn bytes<T>(values: &[T]) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    }
+}
+
+/// The bytes of the buffers of `array` and its children read by the
+/// accessors of `ArrowArray`.
+fn input_ranges(array: &ArrowArray, ranges: &mut Vec<Range<usize>>) {
+    let mut push = |bytes: &[u8]| {
+        let start = bytes.as_ptr() as usize;
+        if !bytes.is_empty() {
+            ranges.push(start..start + bytes.len());
+        }
+    };
+    let ffi = array.ffi();
+    let end = array.offset() + array.len();
+    let raw = |i: usize, len: usize| match unsafe { ffi.buffer(i) } {
+        data if data.is_null() => &[][..],
+        data => unsafe { std::slice::from_raw_parts(data, len) },
+    };
+    let has_validity = !matches!(
+        array.data_type(),
+        ArrowType::RunEndEncoded | ArrowType::Union(_)
+    );
+    if has_validity && ffi.n_buffers > 0 {
+        push(raw(0, end.div_ceil(8)));
+    }
+    match array.data_type() {
+        ArrowType::Boolean => push(raw(1, end.div_ceil(8))),
+        ArrowType::Binary | ArrowType::Utf8 => {
+            push(bytes(array.binary_offsets()));
+            push(array.binary_data());
+        }
+        ArrowType::LargeBinary => {
+            push(bytes(array.large_binary_offsets()));
+            push(array.binary_data());
+        }
+        ArrowType::FixedSizeBinary => push(array.fixed_size_binary_data()),
+        ArrowType::Decimal128 => push(raw(1, 16 * end)),
+        ArrowType::Map => push(bytes(array.map_offsets())),
+        ArrowType::Union(mode) => {
+            push(raw(0, end));
+            if mode == UnionMode::Dense {
+                push(raw(1, 4 * end));
+            }
+        }
+        ArrowType::Struct | ArrowType::RunEndEncoded => {}
+        data_type => with_native_type!(data_type.physical_type(), T => {
+            push(bytes(array.values::<T>()))
+        }, _ => {}),
+    }
+    for i in 0..ffi.n_children as usize {
+        input_ranges(&array.child(i), ranges);
+    }
+}
+
+/// Fail with `Error::Aliasing` if `out_array` is the struct of an input of
+/// the running call, or if any buffer of `data` overlaps an input.
+pub(crate) fn check_output(
+    out_array: *mut ArrowCDataInterfaceArray,
+    data: &ArrayData,
+) -> Result<()> {
+    CALL.with(|call| {
+        let call = call.borrow();
+        let Some(call) = call.as_ref() else {
+            return Ok(());
+        };
+        if call
+            .arrays
+            .iter()
+            .any(|(address, _)| *address == out_array as usize)
+        {
+            return Err(Error::Aliasing(format!(
+                "the output array at {out_array:p} is an input of the call"
+            )));
+        }
+        let mut pending = vec![data];
+        while let Some(data) = pending.pop() {
+            for buffer in data
+                .buffers
+                .iter()
+                .flatten()
+                .filter(|buffer| !buffer.is_empty())
+            {
+                let start = buffer.as_ptr() as usize;
+                let output = start..start + buffer.len();
+                if call
+                    .inputs
+                    .iter()
+                    .any(|input| input.start < output.end && output.start < input.end)
+                {
+                    return Err(Error::Aliasing(format!(
+                        "the output buffer at {:p} overlaps the buffers of an input",
+                        buffer.as_ptr()
+                    )));
+                }
+            }
+            pending.extend(&data.children);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::error::ArrowUdfStatus;
+    use crate::export;
+    use crate::schema::Schema;
+    use crate::slice::arrow_udf_take;
+    use crate::testing::Exported;
+
+    fn is_aliasing(result: Result<()>, message: &str) -> bool {
+        matches!(result, Err(Error::Aliasing(m)) if m.contains(message))
+    }
+
+    #[test]
+    fn arrays_read_can_be_shared_and_arrays_moved_cannot() {
+        let input = Exported::primitive(&[1_i64, 2]);
+        let array = &input.array as *const ArrowCDataInterfaceArray;
+        with_claims(|| {
+            assert_eq!(claim(array, false), Ok(()));
+            assert_eq!(claim(array, false), Ok(()));
+            assert!(is_aliasing(claim(array, true), "is read by another import"));
+        });
+        with_claims(|| {
+            assert_eq!(claim(array, true), Ok(()));
+            assert!(is_aliasing(
+                claim(array, false),
+                "is moved by another import"
+            ));
+            assert!(is_aliasing(
+                claim(array, true),
+                "is moved by another import"
+            ));
+        });
+        // The claims end with their call, and arrays aren't claimed outside
+        // of calls.
+        with_claims(|| assert_eq!(claim(array, true), Ok(())));
+        assert_eq!(claim(array, true), Ok(()));
+        assert_eq!(claim(array, true), Ok(()));
+        assert!(CLAIMS.lock().unwrap().get(&(array as usize)).is_none());
+    }
+
+    #[test]
+    fn results_written_into_an_input_fail() {
+        let input = Exported::primitive(&[1_i64, 2]);
+        let array = &input.array as *const ArrowCDataInterfaceArray;
+        let data = ArrayData::primitive(Buffer::from_slice(&[3_i64]), 1);
+        with_claims(|| {
+            claim(array, false).unwrap();
+            let result = check_output(array as *mut _, &data);
+            assert!(is_aliasing(result, "is an input of the call"));
+            let mut out = Exported::empty();
+            assert_eq!(check_output(&mut out.array, &data), Ok(()));
+        });
+        assert_eq!(check_output(array as *mut _, &data), Ok(()));
+    }
+
+    #[test]
+    fn results_overlapping_the_buffers_of_an_input_fail() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        let data = Arc::new(ArrayData::primitive(Buffer::from_slice(&[1_i32, 2, 3]), 3));
+        let mut ffi = export::export_shared(&data);
+        let other = ArrayData::primitive(Buffer::from_slice(&[1_i32, 2, 3]), 3);
+        with_claims(|| {
+            let array = unsafe { ArrowArray::new(&schema, &ffi) };
+            CALL.with(|call| input_ranges(&array, &mut call.borrow_mut().as_mut().unwrap().inputs));
+            let mut out = Exported::empty();
+            let result = check_output(&mut out.array, &data);
+            assert!(is_aliasing(result, "overlaps the buffers of an input"));
+            assert_eq!(check_output(&mut out.array, &other), Ok(()));
+        });
+        unsafe { ffi.release.unwrap()(&mut ffi) };
+    }
+
+    #[test]
+    fn arrays_moved_into_a_call_cannot_be_read_by_it() {
+        let mut input = Exported::primitive(&[1_i64, 2, 3]);
+        let mut out = Exported::empty();
+        let array = &mut input.array as *mut ArrowCDataInterfaceArray;
+        let status = unsafe {
+            arrow_udf_take(
+                &input.schema,
+                array,
+                &input.schema,
+                array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::AliasingError);
+        assert!(out.array.release.is_none());
+        // The moved array was released by the call.
+        assert!(input.array.release.is_none());
+    }
+}
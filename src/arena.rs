@@ -0,0 +1,228 @@
+//! Per-thread scratch memory for UDF bodies.
+//!
+//! Allocating temporary values for every row (strings, small vectors) is
+//! expensive. Instead, UDF bodies can allocate them in the scratch arena of
+//! the running thread with `with_scratch`. Allocating in the arena is just
+//! bumping a pointer, and memory is never freed individually: the arena is
+//! reset after every batch, and its memory reused by the next one.
+
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::mem;
+
+use crate::memory::Reservation;
+
+/// Size of the first chunk of memory allocated by an arena.
+const INITIAL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Memory kept by an arena when it's reset, bigger arenas are freed.
+const MAX_RETAINED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Memory region owned by an arena, accessed only through raw pointers, so
+/// references to values allocated in it are never invalidated.
+struct Chunk {
+    data: *mut u8,
+    len: usize,
+    _reservation: Reservation,
+}
+
+impl Chunk {
+    fn new(len: usize) -> Chunk {
+        let data = Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8;
+        Chunk {
+            data,
+            len,
+            _reservation: Reservation::new(len),
+        }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                self.data, self.len,
+            )))
+        }
+    }
+}
+
+/// Bump allocator. Values allocated in it are never dropped, so only `Copy`
+/// types are allowed.
+pub struct Arena {
+    // Chunks are never freed while references to them exist, since the arena
+    // can only be reset with a mutable reference.
+    chunks: UnsafeCell<Vec<Chunk>>,
+    current: Cell<usize>,
+    pos: Cell<usize>,
+    allocated: Cell<usize>,
+}
+
+// Every allocation returns a different region of memory, so handing out
+// mutable references from a shared one is fine.
+#[allow(clippy::mut_from_ref)]
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            chunks: UnsafeCell::new(Vec::new()),
+            current: Cell::new(0),
+            pos: Cell::new(0),
+            allocated: Cell::new(0),
+        }
+    }
+
+    /// Bytes allocated since the last reset.
+    pub fn allocated(&self) -> usize {
+        self.allocated.get()
+    }
+
+    /// Total memory owned by the arena.
+    pub fn capacity(&self) -> usize {
+        unsafe { &*self.chunks.get() }
+            .iter()
+            .map(|chunk| chunk.len)
+            .sum()
+    }
+
+    pub fn alloc<T: Copy>(&self, value: T) -> &mut T {
+        &mut self.alloc_slice_fill(1, value)[0]
+    }
+
+    pub fn alloc_slice_fill<T: Copy>(&self, len: usize, value: T) -> &mut [T] {
+        let data = self.alloc_raw(len * mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+        unsafe {
+            for i in 0..len {
+                data.add(i).write(value);
+            }
+            std::slice::from_raw_parts_mut(data, len)
+        }
+    }
+
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        let data = self.alloc_raw(mem::size_of_val(values), mem::align_of::<T>()) as *mut T;
+        unsafe {
+            std::ptr::copy_nonoverlapping(values.as_ptr(), data, values.len());
+            std::slice::from_raw_parts_mut(data, values.len())
+        }
+    }
+
+    pub fn alloc_str(&self, value: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(value.as_bytes());
+        unsafe { std::str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Make all the memory of the arena available again.
+    pub fn reset(&mut self) {
+        let capacity = self.capacity();
+        let chunks = self.chunks.get_mut();
+        if capacity > MAX_RETAINED_SIZE {
+            chunks.clear();
+        } else if chunks.len() > 1 {
+            // Replace the chunks by a single one able to hold everything, so
+            // the next batch doesn't need to allocate.
+            *chunks = vec![Chunk::new(capacity)];
+        }
+        self.current.set(0);
+        self.pos.set(0);
+        self.allocated.set(0);
+    }
+
+    fn alloc_raw(&self, size: usize, align: usize) -> *mut u8 {
+        let chunks = unsafe { &mut *self.chunks.get() };
+        loop {
+            if let Some(chunk) = chunks.get(self.current.get()) {
+                let base = chunk.data as usize;
+                let start = (base + self.pos.get()).next_multiple_of(align) - base;
+                if start + size <= chunk.len {
+                    self.pos.set(start + size);
+                    self.allocated.set(self.allocated.get() + size);
+                    return unsafe { chunk.data.add(start) };
+                }
+                if self.current.get() + 1 < chunks.len() {
+                    self.current.set(self.current.get() + 1);
+                    self.pos.set(0);
+                    continue;
+                }
+            }
+            let last_size = chunks
+                .last()
+                .map_or(INITIAL_CHUNK_SIZE / 2, |chunk| chunk.len);
+            chunks.push(Chunk::new((last_size * 2).max(size + align)));
+            self.current.set(chunks.len() - 1);
+            self.pos.set(0);
+        }
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Arena> = RefCell::new(Arena::new());
+}
+
+/// Run `f` with the scratch arena of the current thread. Values allocated in
+/// it can't outlive `f`.
+pub fn with_scratch<R, F: FnOnce(&Arena) -> R>(f: F) -> R {
+    SCRATCH.with(|arena| f(&arena.borrow()))
+}
+
+/// Reset the scratch arena of the current thread, called between batches.
+pub(crate) fn reset_scratch() {
+    SCRATCH.with(|arena| {
+        if let Ok(mut arena) = arena.try_borrow_mut() {
+            if arena.allocated() > 0 {
+                arena.reset();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned_and_distinct() {
+        let arena = Arena::new();
+        let byte = arena.alloc(1_u8);
+        let value = arena.alloc(2_u64);
+        assert_eq!(value as *mut u64 as usize % mem::align_of::<u64>(), 0);
+        *byte += 1;
+        *value += 1;
+        assert_eq!((*byte, *value), (2, 3));
+        assert_eq!(arena.alloc_slice_fill(3, 7_i32), [7, 7, 7]);
+        assert_eq!(arena.alloc_str("abc"), "abc");
+        assert_eq!(arena.allocated(), 1 + 8 + 12 + 3);
+    }
+
+    #[test]
+    fn reset_keeps_a_single_chunk_for_everything() {
+        let mut arena = Arena::new();
+        for _ in 0..4 {
+            arena.alloc_slice_fill(INITIAL_CHUNK_SIZE / 2, 0_u8);
+        }
+        let capacity = arena.capacity();
+        assert!(capacity > INITIAL_CHUNK_SIZE);
+        arena.reset();
+        assert_eq!(arena.allocated(), 0);
+        assert_eq!(arena.capacity(), capacity);
+        arena.alloc_slice_fill(capacity, 0_u8);
+        assert_eq!(arena.capacity(), capacity);
+        arena.alloc_slice_fill(MAX_RETAINED_SIZE, 0_u8);
+        arena.reset();
+        assert_eq!(arena.capacity(), 0);
+    }
+
+    #[test]
+    fn scratch_is_reset_between_batches() {
+        with_scratch(|arena| {
+            arena.alloc_slice_fill(10, 0_u8);
+        });
+        assert!(with_scratch(|arena| arena.allocated()) >= 10);
+        reset_scratch();
+        assert_eq!(with_scratch(|arena| arena.allocated()), 0);
+    }
+}
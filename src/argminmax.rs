@@ -0,0 +1,274 @@
+//! Reductions returning the position of the minimum or the maximum value of
+//! an array, and optionally the value itself.
+//!
+//! Nulls and NaN are skipped, unless the null policy propagates nulls, and
+//! then the position is -1 like for arrays without values. When several
+//! rows have the extreme value, the first of them is returned, or the last
+//! one when the host sets the `ARROW_UDF_FLAG_LAST_TIE` flag.
+
+use std::ffi::c_void;
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::nan;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::{with_native_type, TotalOrd};
+use crate::udf;
+
+/// Host flag to return the last position among the rows with the extreme
+/// value, instead of the first one.
+pub const ARROW_UDF_FLAG_LAST_TIE: u32 = 8;
+
+/// Position and value of the minimum, or the maximum if `max`, of the
+/// non-null values of `array`. `None` if there are no such values, or if
+/// there are nulls and the null policy propagates them.
+pub fn arg_extreme<T: TotalOrd>(
+    array: &ArrowArray,
+    max: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<Option<(usize, T)>> {
+    udf::check_input::<T>(array, options)?;
+    if udf::null_result(array, options)? {
+        return Ok(None);
+    }
+    let masked = nan::mask_non_finite(array, options)?;
+    let array = &masked
+        .as_ref()
+        .map_or(*array, |masked| masked.array(array.schema()));
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let last_tie = options.flags & ARROW_UDF_FLAG_LAST_TIE != 0;
+    // Whether `candidate`, at a later position than `current`, replaces it.
+    let better = |candidate: T, current: T| match candidate.total_cmp(&current) {
+        std::cmp::Ordering::Less => !max,
+        std::cmp::Ordering::Greater => max,
+        std::cmp::Ordering::Equal => last_tie,
+    };
+    exec::reduce_values(
+        values,
+        options,
+        None,
+        |mut best: Option<(usize, T)>, rows| {
+            for i in rows {
+                let value = values[i];
+                // NaN is the only value that isn't equal to itself.
+                #[allow(clippy::eq_op)]
+                let is_nan = value != value;
+                if is_nan || !validity.is_none_or(|validity| validity.is_set(i)) {
+                    continue;
+                }
+                if best.is_none_or(|(_, current)| better(value, current)) {
+                    best = Some((i, value));
+                }
+            }
+            best
+        },
+        |a, b| match (a, b) {
+            (Some((_, current)), Some((_, candidate))) if !better(candidate, current) => a,
+            (None, b) => b,
+            (a, None) => a,
+            (_, b) => b,
+        },
+    )
+}
+
+unsafe fn arg_extreme_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    max: bool,
+    options: *const ArrowUdfExecOptions,
+    out_index: *mut i64,
+    out_value: *mut c_void,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        with_native_type!(array.data_type(), T => {
+            let extreme = arg_extreme::<T>(&array, max, &options)?;
+            if let (Some((_, value)), false) = (extreme, out_value.is_null()) {
+                (out_value as *mut T).write_unaligned(value);
+            }
+            let index = extreme.map(|(index, _)| index as i64);
+            match options.exports_scalar() {
+                true => export::export_scalar(&options, &schema.name, index)?,
+                false => out_index.write(index.unwrap_or(-1)),
+            }
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "{} of {:?} arrays",
+            if max { "argmax" } else { "argmin" },
+            array.data_type()
+        ))));
+        Ok(())
+    })
+}
+
+/// Position of the minimum value of a numeric array, written into
+/// `out_index`, or -1 if the array has no values other than nulls and NaN.
+/// When `out_value` isn't null, the minimum is also written into it, as a
+/// value of the type of the array. When the options export the result as
+/// an array, the position is exported as Int64, null instead of -1.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, `out_index` must be valid for writes, or
+/// null if the options export the result, and `out_value` must be null or
+/// valid for writes of a value of the type of the array.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_argmin(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_index: *mut i64,
+    out_value: *mut c_void,
+) -> ArrowUdfStatus {
+    arg_extreme_ffi(schema, array, false, options, out_index, out_value)
+}
+
+/// Position of the maximum value of a numeric array, like
+/// `arrow_udf_argmin`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_argmin`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_argmax(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_index: *mut i64,
+    out_value: *mut c_void,
+) -> ArrowUdfStatus {
+    arg_extreme_ffi(schema, array, true, options, out_index, out_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    type EntryPoint = unsafe extern "C" fn(
+        *const ArrowCDataInterfaceSchema,
+        *const ArrowCDataInterfaceArray,
+        *const ArrowUdfExecOptions,
+        *mut i64,
+        *mut c_void,
+    ) -> ArrowUdfStatus;
+
+    fn run<T: Default>(f: EntryPoint, input: &Exported, options: &ArrowUdfExecOptions) -> (i64, T) {
+        let (mut index, mut value) = (0, T::default());
+        let status = unsafe {
+            f(
+                &input.schema,
+                &input.array,
+                options,
+                &mut index,
+                &mut value as *mut T as *mut c_void,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        (index, value)
+    }
+
+    #[test]
+    fn nulls_and_nan_are_skipped() {
+        let input = Exported::nullable(&[None, Some(f64::NAN), Some(2.0), Some(-1.0), Some(2.0)]);
+        let default = ArrowUdfExecOptions::default();
+        assert_eq!(run(arrow_udf_argmin, &input, &default), (3, -1.0));
+        assert_eq!(run(arrow_udf_argmax, &input, &default), (2, 2.0));
+        let last_tie = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_LAST_TIE,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(run(arrow_udf_argmax, &input, &last_tie), (4, 2.0));
+    }
+
+    #[test]
+    fn ties_across_batches() {
+        let values: Vec<i16> = (0..1000).map(|i| (i % 10) as i16).collect();
+        let input = Exported::primitive(&values);
+        for (flags, expected) in [(0, 9), (ARROW_UDF_FLAG_LAST_TIE, 999)] {
+            let options = ArrowUdfExecOptions {
+                flags,
+                batch_size: 7,
+                num_threads: 4,
+                ..ArrowUdfExecOptions::default()
+            };
+            assert_eq!(run(arrow_udf_argmax, &input, &options), (expected, 9_i16));
+        }
+    }
+
+    #[test]
+    fn arrays_without_values() {
+        let input = Exported::nullable::<u32>(&[None, None]);
+        let mut index = 0;
+        let status = unsafe {
+            arrow_udf_argmin(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut index,
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!((status, index), (ArrowUdfStatus::Ok, -1));
+        let booleans = Exported::boolean(&[true]);
+        let status = unsafe {
+            arrow_udf_argmax(
+                &booleans.schema,
+                &booleans.array,
+                ptr::null(),
+                &mut index,
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+    }
+
+    #[test]
+    fn nulls_are_propagated() {
+        let propagate = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let input = Exported::nullable(&[Some(3_i32), None, Some(1)]);
+        assert_eq!(run::<i32>(arrow_udf_argmin, &input, &propagate).0, -1);
+        assert_eq!(run::<i32>(arrow_udf_argmax, &input, &propagate).0, -1);
+        let valid = Exported::nullable(&[Some(3_i32), Some(1)]);
+        assert_eq!(run(arrow_udf_argmin, &valid, &propagate), (1, 1));
+    }
+
+    #[test]
+    fn positions_are_exported_as_scalars_null_without_values() {
+        for (values, expected) in [
+            (vec![Some(3_i32), Some(-2), None], Some(1)),
+            (vec![None, None], None),
+        ] {
+            let input = Exported::nullable(&values);
+            let mut scalar = Exported::empty();
+            let options = ArrowUdfExecOptions {
+                scalar_schema: &mut scalar.schema,
+                scalar_array: &mut scalar.array,
+                ..ArrowUdfExecOptions::default()
+            };
+            let status = unsafe {
+                arrow_udf_argmin(
+                    &input.schema,
+                    &input.array,
+                    &options,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                )
+            };
+            assert_eq!(status, ArrowUdfStatus::Ok);
+            assert_eq!(scalar.nullable_values::<i64>(), [expected]);
+        }
+    }
+}
@@ -0,0 +1,796 @@
+//! Element-wise arithmetic of two numeric operands: `add`, `sub`, `mul`
+//! and `div`.
+//!
+//! Every operand is an array or a scalar. Arrays of the same length are
+//! computed element by element, an array of a single element is broadcast
+//! to the length of the other one, like a scalar, and the scalars, received
+//! as `f64`, are converted to the type of the arrays, which must be an
+//! integer in its range for integer arrays, and under 2^53 in magnitude,
+//! where `f64` has every integer. Both arrays must have the same type, so
+//! hosts align mixed types first with `arrow_udf_cast`. Nulls of any of the
+//! operands are null in the result.
+//!
+//! Integer overflows fail the row, with the error collected when the host
+//! sets `ARROW_UDF_FLAG_COLLECT_ERRORS`, and integer divisions truncate
+//! towards zero. Divisions by zero follow their `ARROW_UDF_DIV_ZERO_*`
+//! policy: fail the row, make it null, or, for floats, return infinity or
+//! NaN like IEEE 754.
+
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::{Bitmap, BitmapBuilder};
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, Numeric};
+use crate::udf::{self, RowErrors};
+
+pub const ARROW_UDF_DIV_ZERO_ERROR: i32 = 0;
+pub const ARROW_UDF_DIV_ZERO_NULL: i32 = 1;
+pub const ARROW_UDF_DIV_ZERO_INFINITY: i32 = 2;
+
+/// The magnitude from which not every integer is an `f64`, so integer
+/// scalars from it on may be the rounding of another one.
+const MAX_EXACT_INTEGER: f64 = (1_u64 << 53) as f64;
+
+/// What divisions by zero return.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DivideByZero {
+    /// Fail the row.
+    Error,
+    /// Null.
+    Null,
+    /// Infinity, or NaN for zero divided by zero, for floats. Integers fail
+    /// the row.
+    Infinity,
+}
+
+impl DivideByZero {
+    pub fn from_ffi(policy: i32) -> Result<DivideByZero> {
+        match policy {
+            ARROW_UDF_DIV_ZERO_ERROR => Ok(DivideByZero::Error),
+            ARROW_UDF_DIV_ZERO_NULL => Ok(DivideByZero::Null),
+            ARROW_UDF_DIV_ZERO_INFINITY => Ok(DivideByZero::Infinity),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown division by zero policy {other}"
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl ArithmeticOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            ArithmeticOp::Add => "+",
+            ArithmeticOp::Sub => "-",
+            ArithmeticOp::Mul => "*",
+            ArithmeticOp::Div => "/",
+        }
+    }
+}
+
+/// An operand of an element-wise kernel of two operands.
+#[derive(Clone, Copy)]
+pub enum Operand<'a> {
+    /// Array of the length of the result, or of a single element, which is
+    /// broadcast.
+    Array(&'a ArrowArray<'a>),
+    Scalar(f64),
+}
+
+impl<'a> Operand<'a> {
+    fn array(self) -> Option<&'a ArrowArray<'a>> {
+        match self {
+            Operand::Array(array) => Some(array),
+            Operand::Scalar(_) => None,
+        }
+    }
+}
+
+/// The length of the result of `left` and `right`, the type of their
+/// arrays, and the name of the first one.
+pub(crate) fn broadcast(left: Operand, right: Operand) -> Result<(usize, ArrowType, String)> {
+    let (first, len) = match (left.array(), right.array()) {
+        (Some(left), Some(right)) => {
+            if left.data_type() != right.data_type() {
+                return Err(Error::UnsupportedType(format!(
+                    "operands of different types: {:?} and {:?}",
+                    left.data_type(),
+                    right.data_type()
+                )));
+            }
+            let len = match (left.len(), right.len()) {
+                (a, b) if a == b => a,
+                (1, len) | (len, 1) => len,
+                (a, b) => {
+                    return Err(Error::InvalidArgument(format!(
+                        "arrays of different lengths: {a} and {b}"
+                    )))
+                }
+            };
+            (left, len)
+        }
+        (Some(array), None) | (None, Some(array)) => (array, array.len()),
+        (None, None) => {
+            return Err(Error::InvalidArgument(
+                "at least one of the operands must be an array".to_string(),
+            ))
+        }
+    };
+    Ok((len, first.data_type(), first.schema().name.clone()))
+}
+
+/// An operand with the values of type `T`, whose element `i` is the one
+/// broadcast to the row `i` of the result.
+pub(crate) enum Values<'a, T> {
+    Array {
+        values: &'a [T],
+        validity: Option<Bitmap<'a>>,
+        broadcast: bool,
+    },
+    Scalar(T),
+}
+
+impl<'a, T: Numeric> Values<'a, T> {
+    pub(crate) fn new(
+        operand: Operand<'a>,
+        len: usize,
+        options: &ArrowUdfExecOptions,
+    ) -> Result<Values<'a, T>> {
+        match operand {
+            Operand::Array(array) => {
+                udf::check_input::<T>(array, options)?;
+                Ok(Values::Array {
+                    values: array.values::<T>(),
+                    validity: array.validity().filter(|_| array.null_count() > 0),
+                    broadcast: array.len() != len,
+                })
+            }
+            Operand::Scalar(value) => {
+                let float = matches!(T::ARROW_TYPE, ArrowType::Float32 | ArrowType::Float64);
+                match T::checked_from_f64(value) {
+                    Some(_) if !float && value.abs() >= MAX_EXACT_INTEGER => {
+                        Err(Error::InvalidArgument(format!(
+                            "scalar {value} may be rounded, integer scalars must be under 2^53"
+                        )))
+                    }
+                    Some(scalar) => Ok(Values::Scalar(scalar)),
+                    None => Err(Error::InvalidArgument(format!(
+                        "scalar {value} can't be represented as {:?}",
+                        T::ARROW_TYPE
+                    ))),
+                }
+            }
+        }
+    }
+
+    /// The element broadcast to the row `i`, or `None` if it's null.
+    #[inline]
+    pub(crate) fn get(&self, i: usize) -> Option<T> {
+        match self {
+            Values::Array {
+                values,
+                validity,
+                broadcast,
+            } => {
+                let i = if *broadcast { 0 } else { i };
+                validity
+                    .is_none_or(|validity| validity.is_set(i))
+                    .then(|| values[i])
+            }
+            Values::Scalar(value) => Some(*value),
+        }
+    }
+}
+
+/// Numbers the arithmetic kernels compute with.
+trait Arithmetic: Numeric + Display {
+    /// `a op b`, or `None` if it overflows or divides by zero.
+    fn apply(op: ArithmeticOp, a: Self, b: Self) -> Option<Self>;
+    fn is_zero(self) -> bool;
+    /// `a / b` for `b` zero, with the `Infinity` policy.
+    fn infinity(a: Self, b: Self) -> Option<Self>;
+}
+
+macro_rules! arithmetic {
+    (int: $($int:ty),*; float: $($float:ty),*) => {
+        $(
+            impl Arithmetic for $int {
+                #[inline]
+                fn apply(op: ArithmeticOp, a: $int, b: $int) -> Option<$int> {
+                    match op {
+                        ArithmeticOp::Add => a.checked_add(b),
+                        ArithmeticOp::Sub => a.checked_sub(b),
+                        ArithmeticOp::Mul => a.checked_mul(b),
+                        ArithmeticOp::Div => a.checked_div(b),
+                    }
+                }
+
+                fn is_zero(self) -> bool {
+                    self == 0
+                }
+
+                fn infinity(_: $int, _: $int) -> Option<$int> {
+                    None
+                }
+            }
+        )*
+        $(
+            impl Arithmetic for $float {
+                #[inline]
+                fn apply(op: ArithmeticOp, a: $float, b: $float) -> Option<$float> {
+                    Some(match op {
+                        ArithmeticOp::Add => a + b,
+                        ArithmeticOp::Sub => a - b,
+                        ArithmeticOp::Mul => a * b,
+                        ArithmeticOp::Div => a / b,
+                    })
+                }
+
+                fn is_zero(self) -> bool {
+                    self == 0.0
+                }
+
+                fn infinity(a: $float, b: $float) -> Option<$float> {
+                    Some(a / b)
+                }
+            }
+        )*
+    };
+}
+
+arithmetic! {
+    int: i8, i16, i32, i64, u8, u16, u32, u64;
+    float: f32, f64
+}
+
+fn compute<T: Arithmetic>(
+    schema: Schema,
+    op: ArithmeticOp,
+    left: Operand,
+    right: Operand,
+    len: usize,
+    divide_by_zero: DivideByZero,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let (a, b) = (
+        Values::<T>::new(left, len, options)?,
+        Values::<T>::new(right, len, options)?,
+    );
+    let errors = RowErrors::new(options);
+    let mut out: Vec<Option<T>> = vec![None; len];
+    exec::map(&mut out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            let (Some(a), Some(b)) = (a.get(i), b.get(i)) else {
+                continue;
+            };
+            let result = match op == ArithmeticOp::Div && b.is_zero() {
+                true => match divide_by_zero {
+                    DivideByZero::Null => continue,
+                    DivideByZero::Infinity => T::infinity(a, b),
+                    DivideByZero::Error => None,
+                },
+                false => T::apply(op, a, b),
+            };
+            *out = errors.check(
+                i,
+                result.ok_or_else(|| match b.is_zero() && op == ArithmeticOp::Div {
+                    true => Error::InvalidArgument(format!("division by zero: {a} / {b}")),
+                    false => Error::InvalidArgument(format!(
+                        "{:?} overflow: {a} {} {b}",
+                        T::ARROW_TYPE,
+                        op.symbol()
+                    )),
+                }),
+            )?;
+        }
+        Ok(())
+    })?;
+    let mut values = options.output_values(len * std::mem::size_of::<T>())?;
+    let mut valid = BitmapBuilder::with_capacity(len);
+    for (value, out) in values.typed_data_mut::<T>().iter_mut().zip(&out) {
+        *value = out.unwrap_or_default();
+        valid.push(out.is_some());
+    }
+    let null_count = out.iter().filter(|out| out.is_none()).count();
+    let data = ArrayData::primitive(values, len);
+    Ok(errors.finish(
+        schema,
+        match null_count {
+            0 => data,
+            _ => data.with_validity(Some(valid.finish()), null_count),
+        },
+    ))
+}
+
+/// `left op right` for every row, with the arrays of the operands of the
+/// same numeric type, and the name of the first one.
+pub fn arithmetic(
+    op: ArithmeticOp,
+    left: Operand,
+    right: Operand,
+    divide_by_zero: DivideByZero,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let (len, data_type, name) = broadcast(left, right)?;
+    let schema = Schema::new(data_type, &name);
+    with_native_type!(data_type, T => {
+        compute::<T>(schema, op, left, right, len, divide_by_zero, options)
+    }, _ => Err(Error::UnsupportedType(format!(
+        "arithmetic of {data_type:?} arrays"
+    ))))
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn arrays_ffi(
+    op: ArithmeticOp,
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    divide_by_zero: i32,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let divide_by_zero = DivideByZero::from_ffi(divide_by_zero)?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        let (out, data) = arithmetic(
+            op,
+            Operand::Array(&a),
+            Operand::Array(&b),
+            divide_by_zero,
+            &options,
+        )?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn scalar_ffi(
+    op: ArithmeticOp,
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    divide_by_zero: i32,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let divide_by_zero = DivideByZero::from_ffi(divide_by_zero)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = arithmetic(
+            op,
+            Operand::Array(&array),
+            Operand::Scalar(scalar),
+            divide_by_zero,
+            &options,
+        )?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Sum of the elements of two numeric arrays of the same type, with the
+/// same length, or with one of them of a single element, which is
+/// broadcast. Integer overflows fail, and nulls of any of the arrays are
+/// null in the result.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, and `b_schema` and `b_array`, must point to
+/// valid Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_add(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let (op, error) = (ArithmeticOp::Add, ARROW_UDF_DIV_ZERO_ERROR);
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, error, options, out_schema, out_array,
+    )
+}
+
+/// Difference of the elements of two numeric arrays, `a - b`, like
+/// `arrow_udf_add`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_add`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_sub(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let (op, error) = (ArithmeticOp::Sub, ARROW_UDF_DIV_ZERO_ERROR);
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, error, options, out_schema, out_array,
+    )
+}
+
+/// Product of the elements of two numeric arrays, like `arrow_udf_add`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_add`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_mul(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let (op, error) = (ArithmeticOp::Mul, ARROW_UDF_DIV_ZERO_ERROR);
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, error, options, out_schema, out_array,
+    )
+}
+
+/// Quotient of the elements of two numeric arrays, `a / b`, like
+/// `arrow_udf_add`, truncated towards zero for integers. `divide_by_zero` is
+/// one of the `ARROW_UDF_DIV_ZERO_*` policies.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_add`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_div(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    divide_by_zero: i32,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ArithmeticOp::Div;
+    arrays_ffi(
+        op,
+        a_schema,
+        a_array,
+        b_schema,
+        b_array,
+        divide_by_zero,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+/// Sum of every element of a numeric array and `scalar`, which must be an
+/// integer of the type under 2^53 in magnitude for integer arrays, and
+/// mustn't overflow the type of float arrays. Integer overflows fail, and
+/// nulls are null in the result. Scalars on the left of `arrow_udf_sub` or
+/// `arrow_udf_div` are passed to them as arrays of a single element.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_add_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let (op, error) = (ArithmeticOp::Add, ARROW_UDF_DIV_ZERO_ERROR);
+    scalar_ffi(
+        op, schema, array, scalar, error, options, out_schema, out_array,
+    )
+}
+
+/// Every element of a numeric array minus `scalar`, like
+/// `arrow_udf_add_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_add_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_sub_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let (op, error) = (ArithmeticOp::Sub, ARROW_UDF_DIV_ZERO_ERROR);
+    scalar_ffi(
+        op, schema, array, scalar, error, options, out_schema, out_array,
+    )
+}
+
+/// Every element of a numeric array times `scalar`, like
+/// `arrow_udf_add_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_add_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_mul_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let (op, error) = (ArithmeticOp::Mul, ARROW_UDF_DIV_ZERO_ERROR);
+    scalar_ffi(
+        op, schema, array, scalar, error, options, out_schema, out_array,
+    )
+}
+
+/// Every element of a numeric array divided by `scalar`, like
+/// `arrow_udf_add_scalar`, with the `ARROW_UDF_DIV_ZERO_*` policy
+/// `divide_by_zero` if `scalar` is zero.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_add_scalar`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_div_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    divide_by_zero: i32,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ArithmeticOp::Div;
+    scalar_ffi(
+        op,
+        schema,
+        array,
+        scalar,
+        divide_by_zero,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    type Status = std::result::Result<Exported, ArrowUdfStatus>;
+
+    fn status(status: ArrowUdfStatus, out: Exported) -> Status {
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn div(a: &Exported, b: &Exported, divide_by_zero: i32) -> Status {
+        let mut out = Exported::empty();
+        let result = unsafe {
+            arrow_udf_div(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                divide_by_zero,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        status(result, out)
+    }
+
+    fn add_scalar(input: &Exported, scalar: f64) -> Status {
+        let mut out = Exported::empty();
+        let result = unsafe {
+            arrow_udf_add_scalar(
+                &input.schema,
+                &input.array,
+                scalar,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        status(result, out)
+    }
+
+    #[test]
+    fn arrays_are_computed_element_by_element_with_their_nulls() {
+        let a = Exported::named("a", &[Some(1_i32), None, Some(7), Some(-7)]);
+        let b = Exported::named("b", &[Some(2_i32), Some(3), None, Some(2)]);
+        let mut out = Exported::empty();
+        let result = unsafe {
+            arrow_udf_sub(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        let out = status(result, out).unwrap();
+        assert_eq!(
+            out.nullable_values::<i32>(),
+            [Some(-1), None, None, Some(-9)]
+        );
+        out.with_array(|array| assert_eq!(array.schema().name, "a"));
+        let quotient = div(&a, &b, ARROW_UDF_DIV_ZERO_ERROR).unwrap();
+        assert_eq!(
+            quotient.nullable_values::<i32>(),
+            [Some(0), None, None, Some(-3)]
+        );
+        let one = Exported::primitive(&[10_i32]);
+        let broadcast = div(&one, &b, ARROW_UDF_DIV_ZERO_ERROR).unwrap();
+        assert_eq!(
+            broadcast.nullable_values::<i32>(),
+            [Some(5), Some(3), None, Some(5)]
+        );
+    }
+
+    #[test]
+    fn divisions_by_zero_follow_their_policy() {
+        let a = Exported::primitive(&[1_i64, 4]);
+        let b = Exported::primitive(&[0_i64, 2]);
+        assert_eq!(
+            div(&a, &b, ARROW_UDF_DIV_ZERO_ERROR).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let nulls = div(&a, &b, ARROW_UDF_DIV_ZERO_NULL).unwrap();
+        assert_eq!(nulls.nullable_values::<i64>(), [None, Some(2)]);
+        assert_eq!(
+            div(&a, &b, ARROW_UDF_DIV_ZERO_INFINITY).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let floats = Exported::primitive(&[1.0_f64, 0.0]);
+        let zeros = Exported::primitive(&[0.0_f64]);
+        let ieee = div(&floats, &zeros, ARROW_UDF_DIV_ZERO_INFINITY).unwrap();
+        let values = ieee.values::<f64>();
+        assert!(values[0] == f64::INFINITY && values[1].is_nan());
+        assert_eq!(div(&a, &b, 3).err(), Some(ArrowUdfStatus::InvalidArgument));
+    }
+
+    #[test]
+    fn overflows_fail_or_are_collected() {
+        let input = Exported::nullable(&[Some(1_i8), Some(i8::MAX), None]);
+        assert_eq!(
+            add_scalar(&input, 1.0).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let collect = ArrowUdfExecOptions {
+            flags: udf::ARROW_UDF_FLAG_COLLECT_ERRORS,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| {
+                arithmetic(
+                    ArithmeticOp::Mul,
+                    Operand::Array(array),
+                    Operand::Scalar(2.0),
+                    DivideByZero::Error,
+                    &collect,
+                )
+            })
+            .unwrap();
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.child_values::<i8>(0), [Some(2), None, None]);
+        out.with_array(|array| {
+            let errors = array.child(1);
+            assert!(errors.utf8_value(1).unwrap().contains("Int8 overflow"));
+            assert!(!errors.is_valid(0) && !errors.is_valid(2));
+        });
+    }
+
+    #[test]
+    fn scalars_out_of_the_type_are_rejected() {
+        let int64 = Exported::primitive(&[1_i64]);
+        assert_eq!(add_scalar(&int64, -2.0).unwrap().values::<i64>(), [-1]);
+        let exact = 2f64.powi(53) - 1.0;
+        assert_eq!(
+            add_scalar(&int64, exact).unwrap().values::<i64>(),
+            [1 << 53]
+        );
+        for scalar in [0.5, f64::NAN, 2f64.powi(53), -(2f64.powi(60)), 1e300] {
+            assert_eq!(
+                add_scalar(&int64, scalar).err(),
+                Some(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        let uint8 = Exported::primitive(&[1_u8]);
+        for scalar in [-1.0, 256.0] {
+            assert_eq!(
+                add_scalar(&uint8, scalar).err(),
+                Some(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        let float32 = Exported::primitive(&[1.0_f32]);
+        assert_eq!(add_scalar(&float32, 0.5).unwrap().values::<f32>(), [1.5]);
+        assert_eq!(
+            add_scalar(&float32, 1e300).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let float64 = Exported::primitive(&[1.0_f64]);
+        assert_eq!(
+            add_scalar(&float64, 2f64.powi(60)).unwrap().values::<f64>(),
+            [2f64.powi(60)]
+        );
+    }
+
+    #[test]
+    fn operands_of_different_types_or_lengths_fail() {
+        let int32 = Exported::primitive(&[1_i32, 2]);
+        let int64 = Exported::primitive(&[1_i64, 2]);
+        assert_eq!(
+            div(&int32, &int64, ARROW_UDF_DIV_ZERO_ERROR).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let three = Exported::primitive(&[1_i32, 2, 3]);
+        assert_eq!(
+            div(&int32, &three, ARROW_UDF_DIV_ZERO_ERROR).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let strings = Exported::utf8(&[Some("a")]);
+        assert_eq!(
+            add_scalar(&strings, 1.0).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let options = ArrowUdfExecOptions::default();
+        let scalars = arithmetic(
+            ArithmeticOp::Add,
+            Operand::Scalar(1.0),
+            Operand::Scalar(2.0),
+            DivideByZero::Error,
+            &options,
+        );
+        assert!(matches!(scalars, Err(Error::InvalidArgument(_))));
+    }
+}
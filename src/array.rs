@@ -0,0 +1,890 @@
+//! Access to the arrays received through the C Data Interface.
+
+use std::ops::Range;
+
+use crate::alias;
+use crate::bitmap::Bitmap;
+use crate::error::{Error, Result};
+use crate::ffi::ArrowCDataInterfaceArray;
+use crate::row::{self, ArrowRow};
+use crate::schema::{ArrowType, Schema, SchemaFlags, UnionMode};
+use crate::types::NativeType;
+
+/// The value of a row of a union array: the row `index` of the field of
+/// `type_id`.
+#[derive(Clone, Copy)]
+pub struct UnionValue<'a> {
+    pub type_id: i8,
+    /// Position of the field in the children of the union.
+    pub field: usize,
+    pub array: ArrowArray<'a>,
+    /// Row of the value in `array`, with the offset of the union applied.
+    pub index: usize,
+}
+
+impl UnionValue<'_> {
+    /// Unions have no validity bitmap, their nulls are the nulls of their
+    /// fields.
+    pub fn is_valid(&self) -> bool {
+        self.array.is_valid(self.index)
+    }
+}
+
+/// Array imported from the C Data Interface, together with its schema.
+///
+/// The memory of the array is owned by the producer, this is only a view
+/// of it, and the array must outlive it.
+#[derive(Clone, Copy)]
+pub struct ArrowArray<'a> {
+    schema: &'a Schema,
+    array: &'a ArrowCDataInterfaceArray,
+    /// Whether the strings of the Utf8 arrays of the array, its own and the
+    /// ones of its children, are known to be valid UTF-8.
+    utf8_checked: bool,
+}
+
+impl<'a> ArrowArray<'a> {
+    /// # Safety
+    ///
+    /// `array` must be a valid, non released, C Data Interface array, with
+    /// the layout described by `schema`.
+    pub unsafe fn new(schema: &'a Schema, array: &'a ArrowCDataInterfaceArray) -> ArrowArray<'a> {
+        ArrowArray {
+            schema,
+            array,
+            utf8_checked: false,
+        }
+    }
+
+    /// Array received by an entry point, claimed by the call until it
+    /// returns, see `alias`. With the `abort_safe` feature, or when the
+    /// library is built with `panic = "abort"`, its structure is checked
+    /// first with `validate::validate`, failing with
+    /// `Error::InvalidArgument` if it's malformed.
+    ///
+    /// # Safety
+    ///
+    /// Same as `new`.
+    pub unsafe fn import(
+        schema: &'a Schema,
+        array: &'a ArrowCDataInterfaceArray,
+    ) -> Result<ArrowArray<'a>> {
+        let array = ArrowArray::new(schema, array).validated()?;
+        alias::claim(array.array, false)?;
+        alias::record_input(&array);
+        Ok(array)
+    }
+
+    /// This array, after `validate::validate` when the entry points validate
+    /// their inputs.
+    pub(crate) fn validated(self) -> Result<ArrowArray<'a>> {
+        #[cfg(any(feature = "abort_safe", panic = "abort"))]
+        crate::validate::validate(&self)?;
+        Ok(self)
+    }
+
+    pub fn schema(&self) -> &'a Schema {
+        self.schema
+    }
+
+    /// The C Data Interface struct this array was imported from.
+    pub fn ffi(&self) -> &'a ArrowCDataInterfaceArray {
+        self.array
+    }
+
+    pub fn data_type(&self) -> ArrowType {
+        self.schema.data_type
+    }
+
+    /// The type of the values: the type of the array, or the type of the
+    /// values of a run-end encoded array.
+    pub fn value_type(&self) -> ArrowType {
+        match self.data_type() {
+            ArrowType::RunEndEncoded => self.schema.children[1].data_type,
+            data_type => data_type,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.length as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.length == 0
+    }
+
+    pub fn offset(&self) -> usize {
+        self.array.offset as usize
+    }
+
+    /// The flags of the field of the array, like whether it can have nulls.
+    pub fn flags(&self) -> SchemaFlags {
+        self.schema.flags
+    }
+
+    /// The validity bitmap, or `None` if all the values are valid, as they
+    /// are in fields that can't have nulls.
+    pub fn validity(&self) -> Option<Bitmap<'a>> {
+        if matches!(
+            self.data_type(),
+            ArrowType::RunEndEncoded | ArrowType::Union(_)
+        ) || self.array.n_buffers == 0
+            || !self.schema.flags.nullable
+        {
+            return None;
+        }
+        let data = unsafe { self.array.buffer(0) };
+        if data.is_null() {
+            return None;
+        }
+        let bytes = (self.offset() + self.len()).div_ceil(8);
+        let data = unsafe { std::slice::from_raw_parts(data, bytes) };
+        Some(Bitmap::new(data, self.offset(), self.len()))
+    }
+
+    /// Number of nulls, computed from the validity bitmap when the producer
+    /// didn't provide it.
+    pub fn null_count(&self) -> usize {
+        if !self.schema.flags.nullable || matches!(self.data_type(), ArrowType::Union(_)) {
+            return 0;
+        }
+        match self.array.null_count {
+            -1 => self
+                .validity()
+                .map_or(0, |validity| validity.len() - validity.count_set()),
+            null_count => null_count as usize,
+        }
+    }
+
+    pub fn is_valid(&self, i: usize) -> bool {
+        self.validity().is_none_or(|validity| validity.is_set(i))
+    }
+
+    pub fn child(&self, i: usize) -> ArrowArray<'a> {
+        assert!(
+            i < self.array.n_children as usize,
+            "child {i} out of bounds"
+        );
+        ArrowArray {
+            schema: &self.schema.children[i],
+            array: unsafe { self.array.child(i) },
+            utf8_checked: self.utf8_checked,
+        }
+    }
+
+    /// The values of a primitive array, with the offset already applied.
+    /// Temporal arrays are read as their physical type.
+    pub fn values<T: NativeType>(&self) -> &'a [T] {
+        assert_eq!(
+            self.data_type().physical_type(),
+            T::ARROW_TYPE,
+            "values requested as {:?}, but the array type is {:?}",
+            T::ARROW_TYPE,
+            self.data_type()
+        );
+        if self.is_empty() {
+            return &[];
+        }
+        unsafe {
+            let data = self.array.buffer(1) as *const T;
+            std::slice::from_raw_parts(data.add(self.offset()), self.len())
+        }
+    }
+
+    /// The bit-packed values of a Boolean array, with the offset already
+    /// applied.
+    pub fn boolean_values(&self) -> Bitmap<'a> {
+        assert_eq!(
+            self.data_type(),
+            ArrowType::Boolean,
+            "boolean values requested, but the array type is {:?}",
+            self.data_type()
+        );
+        if self.is_empty() {
+            return Bitmap::new(&[], 0, 0);
+        }
+        let bytes = (self.offset() + self.len()).div_ceil(8);
+        let data = unsafe { std::slice::from_raw_parts(self.array.buffer(1), bytes) };
+        Bitmap::new(data, self.offset(), self.len())
+    }
+
+    /// The values of a primitive array in slices of `n` values, the last one
+    /// being shorter if the length isn't a multiple of `n`. Loops over slices
+    /// of a small constant size get vectorized by the compiler, without
+    /// bound checks in the inner loop.
+    pub fn chunks<T: NativeType>(&self, n: usize) -> impl Iterator<Item = &'a [T]> + use<'a, T> {
+        assert!(n > 0, "chunks of 0 values");
+        self.values::<T>().chunks(n)
+    }
+
+    /// The rows of a struct array, as tuples or structs deriving `ArrowRow`,
+    /// see `row::rows`.
+    pub fn rows<R: ArrowRow<'a>>(&self) -> Result<impl Iterator<Item = Result<R>> + use<'a, R>> {
+        row::rows(self)
+    }
+
+    /// The `len() + 1` offsets of a Binary or Utf8 array into its data, with
+    /// the offset of the array already applied.
+    pub fn binary_offsets(&self) -> &'a [i32] {
+        assert!(
+            matches!(self.data_type(), ArrowType::Binary | ArrowType::Utf8),
+            "binary values of a {:?} array",
+            self.data_type()
+        );
+        let offsets = unsafe { self.array.buffer(1) } as *const i32;
+        if offsets.is_null() {
+            // Producers can omit the buffers of empty arrays.
+            return &[0];
+        }
+        unsafe { std::slice::from_raw_parts(offsets.add(self.offset()), self.len() + 1) }
+    }
+
+    /// The `len() + 1` offsets of a Map array into its entries, with the
+    /// offset of the array already applied.
+    pub fn map_offsets(&self) -> &'a [i32] {
+        assert_eq!(
+            self.data_type(),
+            ArrowType::Map,
+            "map entries of a {:?} array",
+            self.data_type()
+        );
+        let offsets = unsafe { self.array.buffer(1) } as *const i32;
+        if offsets.is_null() {
+            return &[0];
+        }
+        unsafe { std::slice::from_raw_parts(offsets.add(self.offset()), self.len() + 1) }
+    }
+
+    /// The struct array of the entries of a Map array, with the keys and the
+    /// values as its fields.
+    pub fn map_entries(&self) -> ArrowArray<'a> {
+        self.map_offsets();
+        self.child(0)
+    }
+
+    /// The keys of the entries of a Map array.
+    pub fn map_keys(&self) -> ArrowArray<'a> {
+        self.map_entries().child(0)
+    }
+
+    /// The values of the entries of a Map array.
+    pub fn map_values(&self) -> ArrowArray<'a> {
+        self.map_entries().child(1)
+    }
+
+    /// The positions in `map_keys` and `map_values` of the entries of the
+    /// element `i` of a Map array. Like the rows of the fields of a struct,
+    /// they include the offset of the entries.
+    pub fn map_entry_range(&self, i: usize) -> Range<usize> {
+        assert!(i < self.len(), "element {i} out of bounds");
+        let offsets = self.map_offsets();
+        let start = self.map_entries().offset();
+        start + offsets[i] as usize..start + offsets[i + 1] as usize
+    }
+
+    /// The values of a FixedSizeBinary array, `width` bytes each, with the
+    /// offset of the array already applied.
+    pub fn fixed_size_binary_data(&self) -> &'a [u8] {
+        let width = self
+            .schema
+            .fixed_size_binary_width()
+            .unwrap_or_else(|| panic!("fixed size values of a {:?} array", self.data_type()));
+        let data = unsafe { self.array.buffer(1) };
+        if data.is_null() || width == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(data.add(self.offset() * width), self.len() * width) }
+    }
+
+    /// The bytes of the element `i` of a FixedSizeBinary array.
+    pub fn fixed_size_binary_value(&self, i: usize) -> &'a [u8] {
+        assert!(i < self.len(), "element {i} out of bounds");
+        let width = self.schema.fixed_size_binary_width().unwrap_or_default();
+        &self.fixed_size_binary_data()[i * width..(i + 1) * width]
+    }
+
+    /// The values of a FixedSizeBinary array of `N` bytes, as arrays that
+    /// can be compared and hashed directly.
+    pub fn fixed_size_values<const N: usize>(&self) -> &'a [[u8; N]] {
+        assert_eq!(
+            self.schema.fixed_size_binary_width(),
+            Some(N),
+            "values of {N} bytes of a {:?} array",
+            self.schema.format
+        );
+        let data = self.fixed_size_binary_data();
+        unsafe { std::slice::from_raw_parts(data.as_ptr() as *const [u8; N], self.len()) }
+    }
+
+    /// The UUIDs of an `arrow.uuid` array, as their 16 bytes.
+    pub fn uuid_values(&self) -> &'a [[u8; 16]] {
+        assert!(
+            self.schema.is_uuid(),
+            "UUIDs of a {:?} array",
+            self.schema.format
+        );
+        self.fixed_size_values::<16>()
+    }
+
+    /// The type id of every element of a union array, with the offset of the
+    /// array already applied.
+    pub fn union_type_ids(&self) -> &'a [i8] {
+        assert!(
+            matches!(self.data_type(), ArrowType::Union(_)),
+            "type ids of a {:?} array",
+            self.data_type()
+        );
+        let type_ids = unsafe { self.array.buffer(0) } as *const i8;
+        if type_ids.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(type_ids.add(self.offset()), self.len()) }
+    }
+
+    /// The offset of every element of a dense union array into the field of
+    /// its type, with the offset of the array already applied.
+    pub fn union_offsets(&self) -> &'a [i32] {
+        assert_eq!(
+            self.data_type(),
+            ArrowType::Union(UnionMode::Dense),
+            "offsets of a {:?} array",
+            self.data_type()
+        );
+        let offsets = unsafe { self.array.buffer(1) } as *const i32;
+        if offsets.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(offsets.add(self.offset()), self.len()) }
+    }
+
+    /// The value of the element `i` of a union array. Use `union_values` to
+    /// read all of them, which parses the type ids of the fields only once.
+    pub fn union_value(&self, i: usize) -> Result<UnionValue<'a>> {
+        assert!(i < self.len(), "element {i} out of bounds");
+        let ids = self.schema.union_type_ids().unwrap_or_default();
+        self.union_value_with(&ids, i)
+    }
+
+    /// The value of every element of a union array.
+    pub fn union_values(&self) -> impl Iterator<Item = Result<UnionValue<'a>>> + use<'a> {
+        let array = *self;
+        let ids = self.schema.union_type_ids().unwrap_or_default();
+        (0..self.len()).map(move |i| array.union_value_with(&ids, i))
+    }
+
+    fn union_value_with(&self, ids: &[i8], i: usize) -> Result<UnionValue<'a>> {
+        let type_id = self.union_type_ids()[i];
+        let field = ids.iter().position(|id| *id == type_id).ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "element {i} of union field {:?} has the unknown type id {type_id}",
+                self.schema.name
+            ))
+        })?;
+        let array = self.child(field);
+        let index = match self.data_type() {
+            ArrowType::Union(UnionMode::Dense) => self.union_offsets()[i] as usize,
+            _ => self.offset() + i,
+        };
+        if index >= array.len() {
+            return Err(Error::InvalidArgument(format!(
+                "element {i} of union field {:?} is out of its field of {} rows",
+                self.schema.name,
+                array.len()
+            )));
+        }
+        Ok(UnionValue {
+            type_id,
+            field,
+            array,
+            index,
+        })
+    }
+
+    /// The `len() + 1` offsets of a LargeBinary array into its data, with
+    /// the offset of the array already applied.
+    pub fn large_binary_offsets(&self) -> &'a [i64] {
+        assert_eq!(
+            self.data_type(),
+            ArrowType::LargeBinary,
+            "large binary values of a {:?} array",
+            self.data_type()
+        );
+        let offsets = unsafe { self.array.buffer(1) } as *const i64;
+        if offsets.is_null() {
+            return &[0];
+        }
+        unsafe { std::slice::from_raw_parts(offsets.add(self.offset()), self.len() + 1) }
+    }
+
+    /// The range of the element `i` of a Binary, LargeBinary or Utf8 array
+    /// in its data.
+    fn binary_range(&self, i: usize) -> Range<usize> {
+        match self.data_type() {
+            ArrowType::LargeBinary => {
+                let offsets = self.large_binary_offsets();
+                offsets[i] as usize..offsets[i + 1] as usize
+            }
+            _ => {
+                let offsets = self.binary_offsets();
+                offsets[i] as usize..offsets[i + 1] as usize
+            }
+        }
+    }
+
+    /// The data buffer of a Binary, LargeBinary or Utf8 array, indexed by
+    /// its offsets.
+    pub fn binary_data(&self) -> &'a [u8] {
+        let end = match self.data_type() {
+            ArrowType::LargeBinary => *self.large_binary_offsets().last().unwrap() as usize,
+            _ => *self.binary_offsets().last().unwrap() as usize,
+        };
+        let data = unsafe { self.array.buffer(2) };
+        if end == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(data, end) }
+    }
+
+    /// The bytes of the element `i` of a Binary, LargeBinary or Utf8 array.
+    pub fn binary_value(&self, i: usize) -> &'a [u8] {
+        assert!(i < self.len(), "element {i} out of bounds");
+        &self.binary_data()[self.binary_range(i)]
+    }
+
+    /// The string of the element `i` of a Utf8 array. Producers must only
+    /// export valid UTF-8, but it's checked anyway, since the data comes from
+    /// outside of Rust, unless the array was checked at once with
+    /// `validate_utf8` or trusted with `assume_utf8`.
+    pub fn utf8_value(&self, i: usize) -> Result<&'a str> {
+        let bytes = self.binary_value(i);
+        if self.utf8_checked && self.is_valid(i) {
+            return Ok(unsafe { std::str::from_utf8_unchecked(bytes) });
+        }
+        std::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8(i))
+    }
+
+    /// This array, with the strings of its Utf8 arrays, its own and the ones
+    /// of its children, checked to be valid UTF-8 at once, so `utf8_value`
+    /// doesn't check them again. Fails with `Error::InvalidUtf8` with the
+    /// position of the first invalid string.
+    pub fn validate_utf8(mut self) -> Result<ArrowArray<'a>> {
+        if self.data_type() == ArrowType::Utf8 {
+            self.check_utf8()?;
+        }
+        for i in 0..self.array.n_children as usize {
+            self.child(i).validate_utf8()?;
+        }
+        self.utf8_checked = true;
+        Ok(self)
+    }
+
+    /// This array, with its strings trusted to be valid UTF-8, so
+    /// `utf8_value` doesn't check them.
+    ///
+    /// # Safety
+    ///
+    /// The strings of the Utf8 arrays of the array, its own and the ones of
+    /// its children, must be valid UTF-8.
+    pub unsafe fn assume_utf8(mut self) -> ArrowArray<'a> {
+        self.utf8_checked = true;
+        self
+    }
+
+    /// Check the data of a Utf8 array at once, and that its offsets are at
+    /// the boundaries of characters. Only when that fails the strings are
+    /// checked one by one, to find the first invalid one, ignoring the data
+    /// of the nulls.
+    fn check_utf8(&self) -> Result<()> {
+        let offsets = self.binary_offsets();
+        let start = offsets[0] as usize;
+        let valid = std::str::from_utf8(&self.binary_data()[start..]).is_ok_and(|data| {
+            offsets
+                .iter()
+                .all(|offset| data.is_char_boundary(*offset as usize - start))
+        });
+        if valid {
+            return Ok(());
+        }
+        match (0..self.len())
+            .find(|i| self.is_valid(*i) && std::str::from_utf8(self.binary_value(*i)).is_err())
+        {
+            Some(i) => Err(Error::InvalidUtf8(i)),
+            None => Ok(()),
+        }
+    }
+
+    /// The value repeated in all the positions of the array, when it's known
+    /// without scanning it: the producer flagged the array as constant in
+    /// the schema metadata, or the array is run-end encoded and its visible
+    /// part consists of a single run. Arrays with nulls are never considered
+    /// constant.
+    pub fn constant_value<T: NativeType>(&self) -> Option<T> {
+        if self.is_empty() || self.null_count() > 0 {
+            return None;
+        }
+        if self.data_type() == ArrowType::RunEndEncoded {
+            return self.single_run_value();
+        }
+        if self.schema.is_constant() && self.data_type() == T::ARROW_TYPE {
+            return Some(self.values::<T>()[0]);
+        }
+        None
+    }
+
+    fn single_run_value<T: NativeType>(&self) -> Option<T> {
+        let run_ends = self.child(0);
+        let values = self.child(1);
+        if values.data_type() != T::ARROW_TYPE {
+            return None;
+        }
+        let first = self.offset() as i64;
+        let last = first + self.len() as i64 - 1;
+        let run_end = |i: usize| -> i64 {
+            match run_ends.data_type() {
+                ArrowType::Int16 => run_ends.values::<i16>()[i] as i64,
+                ArrowType::Int32 => run_ends.values::<i32>()[i] as i64,
+                ArrowType::Int64 => run_ends.values::<i64>()[i],
+                other => panic!("invalid run ends type: {other:?}"),
+            }
+        };
+        // Index of the run containing the first visible value.
+        let (mut low, mut high) = (0, run_ends.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if run_end(mid) <= first {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low < run_ends.len() && run_end(low) > last && values.is_valid(low) {
+            Some(values.values::<T>()[low])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::Buffer;
+    use crate::error::Error;
+    use crate::export::{self, ArrayData};
+    #[cfg(feature = "nested")]
+    use crate::schema::UnionMode;
+    use crate::schema::{ArrowType, Metadata, Schema, CONSTANT_METADATA_KEY};
+    #[cfg(feature = "nested")]
+    use crate::testing::nullable_data;
+    use crate::testing::Exported;
+
+    #[test]
+    fn values_start_at_the_offset() {
+        let mut exported = Exported::primitive(&[1_i32, 2, 3, 4]);
+        exported.array.offset = 1;
+        exported.array.length = 2;
+        assert_eq!(exported.values::<i32>(), [2, 3]);
+    }
+
+    #[test]
+    fn boolean_values_start_at_the_offset() {
+        let mut exported = Exported::boolean(&[true, false, false, true, true]);
+        exported.array.offset = 2;
+        exported.array.length = 3;
+        let bits = exported.with_array(|array| array.boolean_values().iter().collect::<Vec<_>>());
+        assert_eq!(bits, [false, true, true]);
+    }
+
+    #[test]
+    #[should_panic(expected = "values requested as Int64")]
+    fn values_of_another_type_panic() {
+        Exported::primitive(&[1_i32]).values::<i64>();
+    }
+
+    #[test]
+    fn constant_from_the_schema_metadata() {
+        let mut schema = Schema::new(ArrowType::Int32, "x");
+        schema.metadata = Metadata(vec![(
+            CONSTANT_METADATA_KEY.to_string(),
+            "true".to_string(),
+        )]);
+        let data = ArrayData::primitive(Buffer::from_slice(&[7_i32, 7]), 2);
+        let exported = Exported::new(&schema, data);
+        assert_eq!(
+            exported.with_array(|array| array.constant_value::<i32>()),
+            Some(7)
+        );
+        assert_eq!(
+            exported.with_array(|array| array.constant_value::<i64>()),
+            None
+        );
+        let plain = Exported::primitive(&[7_i32, 7]);
+        assert_eq!(
+            plain.with_array(|array| array.constant_value::<i32>()),
+            None
+        );
+    }
+
+    #[test]
+    fn constant_when_the_visible_part_is_a_single_run() {
+        let schema = export::constant_schema(ArrowType::Int64, "x");
+        let data = ArrayData {
+            length: 5,
+            null_count: 0,
+            buffers: Vec::new(),
+            children: vec![
+                ArrayData::primitive(Buffer::from_slice(&[2_i64, 5]), 2),
+                ArrayData::primitive(Buffer::from_slice(&[10_i64, 20]), 2),
+            ],
+        };
+        let mut exported = Exported::new(&schema, data);
+        assert_eq!(
+            exported.with_array(|array| (array.data_type(), array.value_type())),
+            (ArrowType::RunEndEncoded, ArrowType::Int64)
+        );
+        assert_eq!(
+            exported.with_array(|array| array.constant_value::<i64>()),
+            None
+        );
+        exported.array.offset = 2;
+        exported.array.length = 3;
+        assert_eq!(
+            exported.with_array(|array| array.constant_value::<i64>()),
+            Some(20)
+        );
+        exported.array.offset = 1;
+        exported.array.length = 1;
+        assert_eq!(
+            exported.with_array(|array| array.constant_value::<i64>()),
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn validity_and_null_count() {
+        let mut exported = Exported::nullable(&[Some(1_i64), None, Some(3), None]);
+        assert_eq!(exported.with_array(|array| array.null_count()), 2);
+        exported.array.null_count = -1;
+        exported.array.offset = 2;
+        exported.array.length = 2;
+        assert_eq!(exported.with_array(|array| array.null_count()), 1);
+        assert_eq!(exported.nullable_values::<i64>(), [Some(3), None]);
+        assert!(Exported::primitive(&[1_u8]).with_array(|array| array.validity().is_none()));
+    }
+
+    #[test]
+    fn arrays_with_nulls_are_not_constant() {
+        let exported = Exported::nullable(&[Some(1_i64), None]);
+        let constant = exported.with_array(|array| array.constant_value::<i64>());
+        assert_eq!(constant, None);
+    }
+
+    #[test]
+    fn chunks_of_the_values_after_the_offset() {
+        let mut exported = Exported::primitive(&[0_u16, 1, 2, 3, 4, 5, 6, 7]);
+        exported.array.offset = 1;
+        exported.array.length = 6;
+        let chunks: Vec<Vec<u16>> =
+            exported.with_array(|array| array.chunks::<u16>(4).map(<[u16]>::to_vec).collect());
+        assert_eq!(chunks, [vec![1, 2, 3, 4], vec![5, 6]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "chunks of 0 values")]
+    fn chunks_must_have_values() {
+        Exported::primitive(&[1_i32]).with_array(|array| array.chunks::<i32>(0).count());
+    }
+
+    #[test]
+    fn fields_that_cant_have_nulls_ignore_their_validity() {
+        let mut exported = Exported::nullable(&[Some(1_i64), None, Some(3)]);
+        exported.schema.flags = 0;
+        exported.with_array(|array| {
+            assert!(!array.flags().nullable);
+            assert!(array.validity().is_none());
+            assert_eq!(array.null_count(), 0);
+        });
+        assert_eq!(exported.values::<i64>().len(), 3);
+    }
+
+    /// Sparse and dense unions of `[1, "a", null, 3]`, an Int64 field with
+    /// type id 0 and a Utf8 field with type id 1.
+    #[cfg(feature = "nested")]
+    fn unions() -> [Exported; 2] {
+        let strings = |values: &[Option<&str>]| {
+            let mut builder = crate::binary::Utf8Builder::with_capacity(values.len());
+            values.iter().for_each(|value| builder.push(*value));
+            builder.finish()
+        };
+        let fields = || {
+            vec![
+                Schema::new(ArrowType::Int64, "i"),
+                Schema::new(ArrowType::Utf8, "s"),
+            ]
+        };
+        let type_ids = || Buffer::from_slice(&[0_i8, 1, 1, 0]);
+        let sparse = ArrayData::union_array(
+            type_ids(),
+            None,
+            vec![
+                nullable_data(&[Some(1_i64), None, None, Some(3)]),
+                strings(&[None, Some("a"), None, None]),
+            ],
+            4,
+        );
+        let dense = ArrayData::union_array(
+            type_ids(),
+            Some(Buffer::from_slice(&[0_i32, 0, 1, 1])),
+            vec![
+                nullable_data(&[Some(1_i64), Some(3)]),
+                strings(&[Some("a"), None]),
+            ],
+            4,
+        );
+        [
+            Exported::new(&Schema::union("u", UnionMode::Sparse, fields()), sparse),
+            Exported::new(&Schema::union("u", UnionMode::Dense, fields()), dense),
+        ]
+    }
+
+    #[cfg(feature = "nested")]
+    #[test]
+    fn union_values_of_every_row() {
+        for mut exported in unions() {
+            exported.with_array(|array| {
+                assert_eq!(array.union_type_ids(), [0, 1, 1, 0]);
+                assert!(array.validity().is_none());
+                assert_eq!(array.null_count(), 0);
+                let values: Vec<_> = array.union_values().map(Result::unwrap).collect();
+                assert_eq!(
+                    values.iter().map(|v| v.field).collect::<Vec<_>>(),
+                    [0, 1, 1, 0]
+                );
+                assert_eq!(values[0].array.values::<i64>()[values[0].index], 1);
+                assert_eq!(values[1].array.utf8_value(values[1].index).unwrap(), "a");
+                assert!(!values[2].is_valid());
+                assert_eq!(values[3].array.values::<i64>()[values[3].index], 3);
+            });
+            (exported.array.offset, exported.array.length) = (3, 1);
+            exported.with_array(|array| {
+                let value = array.union_value(0).unwrap();
+                assert_eq!(
+                    (value.type_id, value.array.values::<i64>()[value.index]),
+                    (0, 3)
+                );
+            });
+        }
+    }
+
+    #[cfg(feature = "nested")]
+    #[test]
+    fn union_values_must_be_in_their_fields() {
+        let [_, dense] = unions();
+        let buffers = unsafe { std::slice::from_raw_parts_mut(dense.array.buffers, 2) };
+        let original = buffers.to_vec();
+        let (type_ids, offsets) = ([0_i8, 5, 1, 0], [0_i32, 0, 1, 2]);
+        buffers[0] = type_ids.as_ptr() as *const _;
+        dense.with_array(|array| {
+            assert!(array.union_value(0).is_ok());
+            assert!(matches!(
+                array.union_value(1),
+                Err(Error::InvalidArgument(_))
+            ));
+        });
+        buffers[0] = original[0];
+        buffers[1] = offsets.as_ptr() as *const _;
+        dense.with_array(|array| {
+            assert!(matches!(
+                array.union_value(3),
+                Err(Error::InvalidArgument(_))
+            ));
+        });
+        buffers.copy_from_slice(&original);
+    }
+
+    #[test]
+    fn fixed_size_values_after_the_offset() {
+        let data = ArrayData::primitive(Buffer::from_slice(b"abcdefghijkl"), 3);
+        let mut exported = Exported::new(&Schema::fixed_size_binary("x", 4), data);
+        exported.with_array(|array| {
+            assert_eq!(array.fixed_size_binary_value(1), b"efgh");
+            assert_eq!(
+                array.fixed_size_values::<4>(),
+                [*b"abcd", *b"efgh", *b"ijkl"]
+            );
+        });
+        (exported.array.offset, exported.array.length) = (2, 1);
+        exported.with_array(|array| {
+            assert_eq!(array.fixed_size_binary_data(), b"ijkl");
+            assert_eq!(array.fixed_size_values::<4>(), [*b"ijkl"]);
+        });
+    }
+
+    #[test]
+    fn uuids_are_values_of_16_bytes() {
+        let uuids = [[0xab_u8; 16], [0xcd; 16]];
+        let data = ArrayData::primitive(Buffer::from_slice(uuids.as_flattened()), 2);
+        let exported = Exported::new(&Schema::uuid("id"), data);
+        exported.with_array(|array| assert_eq!(array.uuid_values(), uuids));
+    }
+
+    #[test]
+    #[should_panic(expected = "values of 8 bytes")]
+    fn fixed_size_values_of_another_width_panic() {
+        let data = ArrayData::primitive(Buffer::from_slice(b"abcd"), 1);
+        let exported = Exported::new(&Schema::fixed_size_binary("x", 4), data);
+        exported.with_array(|array| array.fixed_size_values::<8>().len());
+    }
+
+    /// Utf8 array named `x` with the bytes of `values`, where `None` is null.
+    fn utf8_bytes(values: &[Option<&[u8]>]) -> Exported {
+        let mut builder = crate::binary::BinaryBuilder::with_capacity(values.len());
+        values.iter().for_each(|value| builder.push(*value));
+        Exported::new(&Schema::new(ArrowType::Utf8, "x"), builder.finish())
+    }
+
+    #[test]
+    fn utf8_is_validated_at_once_with_the_first_invalid_position() {
+        let valid = Exported::utf8(&[Some("a"), None, Some("ñ€")]);
+        let array = valid.with_array(|array| {
+            let array = (*array).validate_utf8().unwrap();
+            (
+                array.utf8_value(0).unwrap().to_string(),
+                array.utf8_value(2).unwrap().to_string(),
+            )
+        });
+        assert_eq!(array, ("a".to_string(), "ñ€".to_string()));
+        let invalid = utf8_bytes(&[Some(b"a"), Some(b"\xff"), Some(b"\xfe")]);
+        invalid.with_array(|array| {
+            assert_eq!((*array).validate_utf8().err(), Some(Error::InvalidUtf8(1)));
+            assert_eq!(array.utf8_value(2), Err(Error::InvalidUtf8(2)));
+        });
+        let split = utf8_bytes(&[Some(b"\xc3"), Some(b"\xb1")]);
+        split.with_array(|array| {
+            assert_eq!((*array).validate_utf8().err(), Some(Error::InvalidUtf8(0)));
+        });
+    }
+
+    #[test]
+    fn utf8_of_nulls_and_children_is_validated() {
+        let invalid = utf8_bytes(&[Some(b"a"), Some(b"\xff")]);
+        invalid.with_array(|array| assert!((*array).validate_utf8().is_err()));
+        let validity = crate::testing::validity(&[Some(()), None]);
+        let mut builder = crate::binary::BinaryBuilder::with_capacity(2);
+        builder.push(Some(b"a"));
+        builder.push(Some(b"\xff"));
+        let data = builder.finish().with_validity(Some(validity), 1);
+        let null = Exported::new(&Schema::new(ArrowType::Utf8, "x"), data);
+        null.with_array(|array| {
+            let array = (*array).validate_utf8().unwrap();
+            assert_eq!(array.utf8_value(0), Ok("a"));
+        });
+        let mut builder = crate::binary::BinaryBuilder::with_capacity(1);
+        builder.push(Some(b"\xff"));
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Utf8, "x")]);
+        let nested = Exported::new(&schema, ArrayData::struct_array(vec![builder.finish()], 1));
+        nested.with_array(|array| {
+            assert_eq!((*array).validate_utf8().err(), Some(Error::InvalidUtf8(0)));
+        });
+    }
+}
@@ -0,0 +1,177 @@
+//! Async UDFs, for functions mostly waiting on IO, like calls to external
+//! services.
+//!
+//! The async function is called for many rows at the same time, up to the
+//! `max_concurrency` of the execution options, in a tokio runtime created
+//! for the call. Entry points using it are still blocking: they return when
+//! all rows are processed. This means they can't be called from a thread
+//! that is already running a tokio runtime.
+
+use std::future::Future;
+
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinSet;
+
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::NativeType;
+use crate::udf;
+
+fn runtime(options: &ArrowUdfExecOptions) -> Result<Runtime> {
+    let mut builder = match options.threads() {
+        1 => Builder::new_current_thread(),
+        threads => {
+            let mut builder = Builder::new_multi_thread();
+            builder.worker_threads(threads);
+            builder
+        }
+    };
+    builder
+        .enable_all()
+        .build()
+        .map_err(|err| Error::InvalidArgument(format!("can't start the async runtime: {err}")))
+}
+
+fn join_error(err: tokio::task::JoinError) -> Error {
+    Error::Panic(err.to_string())
+}
+
+/// Apply the async function `f` to every element of `array`. Null elements
+/// are null in the result, and `f` is not called for them.
+///
+/// Batches are processed one after the other, and the rows of a batch
+/// concurrently.
+pub fn map_async<T, O, F, Fut>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    F: Fn(T) -> Fut + Sync,
+    Fut: Future<Output = O> + Send + 'static,
+{
+    let runtime = runtime(options)?;
+    let name = &array.schema().name;
+    if let Some(value) = udf::constant_input::<T>(array, options) {
+        return Ok((
+            export::constant_schema(O::ARROW_TYPE, name),
+            ArrayData::constant(runtime.block_on(f(value)), array.len()),
+        ));
+    }
+    udf::check_input::<T>(array, options)?;
+    let input = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let mut values = options.output_values(input.len() * std::mem::size_of::<O>())?;
+    // Concurrency comes from the runtime, batches are run one at a time.
+    let serial = ArrowUdfExecOptions {
+        num_threads: 1,
+        ..*options
+    };
+    exec::map(values.typed_data_mut::<O>(), &serial, |rows, out| {
+        let limit = match options.max_concurrency {
+            0 => rows.len(),
+            limit => limit as usize,
+        };
+        let start = rows.start;
+        runtime.block_on(async {
+            let mut tasks = JoinSet::new();
+            for i in rows {
+                if validity.is_some_and(|validity| !validity.is_set(i)) {
+                    continue;
+                }
+                if tasks.len() >= limit {
+                    let (j, value) = tasks.join_next().await.unwrap().map_err(join_error)?;
+                    out[j - start] = value;
+                }
+                let future = f(input[i]);
+                tasks.spawn(async move { (i, future.await) });
+            }
+            while let Some(task) = tasks.join_next().await {
+                let (j, value) = task.map_err(join_error)?;
+                out[j - start] = value;
+            }
+            Ok(())
+        })
+    })?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok((
+        Schema::new(O::ARROW_TYPE, name),
+        ArrayData::primitive(values, array.len()).with_validity(validity, null_count),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    #[test]
+    fn map_async_keeps_the_nulls() {
+        let input = Exported::nullable(&[Some(1i64), None, Some(3)]);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (schema, data) = input
+            .with_array(|array| {
+                map_async::<i64, i64, _, _>(array, &ArrowUdfExecOptions::default(), |x| {
+                    let calls = calls.clone();
+                    async move {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                        x * 10
+                    }
+                })
+            })
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        let output = Exported::new(&schema, data);
+        assert_eq!(output.nullable_values::<i64>(), [Some(10), None, Some(30)]);
+    }
+
+    #[test]
+    fn concurrency_is_limited() {
+        let input = Exported::primitive(&(0..100i64).collect::<Vec<_>>());
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let options = ArrowUdfExecOptions {
+            max_concurrency: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| {
+                map_async::<i64, i64, _, _>(array, &options, |x| {
+                    let (running, peak) = (running.clone(), peak.clone());
+                    async move {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        tokio::task::yield_now().await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        x + 1
+                    }
+                })
+            })
+            .unwrap();
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        let output = Exported::new(&schema, data);
+        assert_eq!(output.values::<i64>(), (1..=100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn map_async_rejects_other_types() {
+        let input = Exported::primitive(&[1.0f64]);
+        let result = input.with_array(|array| {
+            map_async::<i64, i64, _, _>(
+                array,
+                &ArrowUdfExecOptions::default(),
+                |x| async move { x },
+            )
+        });
+        assert!(matches!(result, Err(Error::UnsupportedType(_))));
+    }
+}
@@ -0,0 +1,57 @@
+//! Replay of a trace file written with the `trace` feature: calls the
+//! recorded kernel again with the recorded input, arguments and options,
+//! and tells if it returns the recorded result.
+//!
+//! cargo run --features trace --bin arrow-udf-replay -- TRACE
+//!
+//! Exits with 0 if the result is the recorded one, 1 if it differs, and 2
+//! if the trace can't be read or replayed.
+
+use std::env;
+use std::process::ExitCode;
+
+use distance::trace::Trace;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let [_, path] = args.as_slice() else {
+        eprintln!("usage: arrow-udf-replay TRACE");
+        return ExitCode::from(2);
+    };
+    let replay = Trace::read(path).and_then(|trace| {
+        println!("function: {}", trace.function);
+        println!("arguments: {:?}", trace.args);
+        match &trace.outcome {
+            Some(outcome) => println!("recorded: {}", status(outcome.status, &outcome.message)),
+            None => println!("recorded: the call didn't return"),
+        }
+        trace.replay()
+    });
+    match replay {
+        Ok(replay) => {
+            println!("replayed: {}", status(replay.status, &replay.message));
+            match replay.matches {
+                true => {
+                    println!("the result is the recorded one");
+                    ExitCode::SUCCESS
+                }
+                false => {
+                    println!("the result differs from the recorded one");
+                    ExitCode::from(1)
+                }
+            }
+        }
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// The status of a call, with the error message of failures.
+fn status(status: i32, message: &str) -> String {
+    match message {
+        "" => format!("status {status}"),
+        message => format!("status {status}, {message}"),
+    }
+}
@@ -0,0 +1,155 @@
+//! Variable-length binary and string arrays.
+
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::export::ArrayData;
+
+/// Builder of a Binary or LargeBinary array, one element at a time.
+pub struct BinaryBuilder {
+    offsets: Buffer,
+    /// Whether the offsets are 64-bit, for a LargeBinary array.
+    large: bool,
+    data: Buffer,
+    validity: BitmapBuilder,
+    len: usize,
+    null_count: usize,
+}
+
+impl BinaryBuilder {
+    pub fn with_capacity(len: usize) -> BinaryBuilder {
+        let mut offsets = Buffer::with_capacity((len + 1) * 4);
+        offsets.push(0i32);
+        BinaryBuilder {
+            offsets,
+            large: false,
+            data: Buffer::new(),
+            validity: BitmapBuilder::with_capacity(len),
+            len: 0,
+            null_count: 0,
+        }
+    }
+
+    /// Builder of a LargeBinary array, without the 2GiB limit of the values
+    /// of Binary arrays.
+    pub fn large_with_capacity(len: usize) -> BinaryBuilder {
+        let mut offsets = Buffer::with_capacity((len + 1) * 8);
+        offsets.push(0i64);
+        BinaryBuilder {
+            offsets,
+            large: true,
+            ..BinaryBuilder::with_capacity(0)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, value: Option<&[u8]>) {
+        if let Some(value) = value {
+            self.data.extend_from_slice(value);
+        } else {
+            self.null_count += 1;
+        }
+        if self.large {
+            self.offsets.push(self.data.len() as i64);
+        } else {
+            let end = i32::try_from(self.data.len()).expect("binary arrays are limited to 2GiB");
+            self.offsets.push(end);
+        }
+        self.validity.push(value.is_some());
+        self.len += 1;
+    }
+
+    pub fn finish(self) -> ArrayData {
+        let validity = (self.null_count > 0).then(|| self.validity.finish());
+        ArrayData {
+            length: self.len,
+            null_count: self.null_count,
+            buffers: vec![validity, Some(self.offsets), Some(self.data)],
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Builder of a Utf8 array, one string at a time. Strings have the same
+/// layout as binary values, but are guaranteed to be valid UTF-8.
+pub struct Utf8Builder(BinaryBuilder);
+
+impl Utf8Builder {
+    pub fn with_capacity(len: usize) -> Utf8Builder {
+        Utf8Builder(BinaryBuilder::with_capacity(len))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, value: Option<&str>) {
+        self.0.push(value.map(str::as_bytes));
+    }
+
+    pub fn finish(self) -> ArrayData {
+        self.0.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{ArrowType, Schema};
+    use crate::testing::Exported;
+
+    #[test]
+    fn builds_binary_arrays_with_nulls() {
+        let mut builder = BinaryBuilder::with_capacity(2);
+        assert!(builder.is_empty());
+        builder.push(Some(b"ab"));
+        builder.push(None);
+        builder.push(Some(b""));
+        builder.push(Some(b"cde"));
+        assert_eq!(builder.len(), 4);
+        let mut exported = Exported::new(&Schema::new(ArrowType::Binary, "x"), builder.finish());
+        exported.with_array(|array| {
+            assert_eq!(array.null_count(), 1);
+            assert!(!array.is_valid(1));
+            assert_eq!(array.binary_value(0), b"ab");
+            assert_eq!(array.binary_value(2), b"");
+        });
+        exported.array.offset = 3;
+        exported.array.length = 1;
+        assert_eq!(
+            exported.with_array(|array| array.binary_value(0).to_vec()),
+            b"cde"
+        );
+    }
+
+    #[test]
+    fn builds_large_binary_arrays_with_64_bit_offsets() {
+        let mut builder = BinaryBuilder::large_with_capacity(3);
+        builder.push(Some(b"ab"));
+        builder.push(None);
+        builder.push(Some(b"cde"));
+        let schema = Schema::new(ArrowType::LargeBinary, "x");
+        let mut exported = Exported::new(&schema, builder.finish());
+        exported.with_array(|array| {
+            assert_eq!(array.large_binary_offsets(), [0, 2, 2, 5]);
+            assert_eq!(array.null_count(), 1);
+            assert_eq!(array.binary_value(0), b"ab");
+            assert_eq!(array.binary_data(), b"abcde");
+        });
+        (exported.array.offset, exported.array.length) = (2, 1);
+        exported.with_array(|array| {
+            assert_eq!(array.large_binary_offsets(), [2, 5]);
+            assert_eq!(array.binary_value(0), b"cde");
+        });
+    }
+}
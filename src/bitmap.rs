@@ -0,0 +1,267 @@
+//! Bit-packed buffers, as used by validity bitmaps.
+
+use crate::buffer::Buffer;
+
+/// Read-only view of `len` bits, starting at bit `offset` of `data`.
+#[derive(Clone, Copy)]
+pub struct Bitmap<'a> {
+    data: &'a [u8],
+    offset: usize,
+    len: usize,
+}
+
+impl<'a> Bitmap<'a> {
+    pub fn new(data: &'a [u8], offset: usize, len: usize) -> Bitmap<'a> {
+        assert!(
+            (offset + len).div_ceil(8) <= data.len(),
+            "bitmap of {len} bits at offset {offset} doesn't fit in {} bytes",
+            data.len()
+        );
+        Bitmap { data, offset, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_set(&self, i: usize) -> bool {
+        debug_assert!(i < self.len);
+        let bit = self.offset + i;
+        self.data[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    /// The `len` bits from `offset`.
+    pub fn slice(&self, offset: usize, len: usize) -> Bitmap<'a> {
+        assert!(offset + len <= self.len, "slice out of the bitmap");
+        Bitmap {
+            data: self.data,
+            offset: self.offset + offset,
+            len,
+        }
+    }
+
+    pub fn count_set(&self) -> usize {
+        self.chunks().map(|chunk| chunk.count_ones() as usize).sum()
+    }
+
+    /// The bits from `64 * i`, as a word whose lowest bit is the first one.
+    /// The last word is padded with unset bits.
+    fn chunk(&self, i: usize) -> u64 {
+        let start = self.offset + 64 * i;
+        let bits = (self.len - 64 * i).min(64);
+        // 64 bits not starting at a byte boundary span 9 bytes.
+        let (first, last) = (start / 8, (start + bits).div_ceil(8));
+        let mut bytes = [0; 16];
+        bytes[..last - first].copy_from_slice(&self.data[first..last]);
+        let chunk = (u128::from_le_bytes(bytes) >> (start % 8)) as u64;
+        match bits {
+            64 => chunk,
+            _ => chunk & ((1 << bits) - 1),
+        }
+    }
+
+    /// The bits in words of 64, whatever the offset of the bitmap is.
+    pub fn chunks(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.len.div_ceil(64)).map(|i| self.chunk(i))
+    }
+
+    /// The bits set in both `self` and `other`, in a new buffer starting at
+    /// bit 0. The bitmaps must have the same length, but not the same
+    /// offset.
+    pub fn and(&self, other: &Bitmap) -> Buffer {
+        assert_eq!(self.len, other.len, "bitmaps of different lengths");
+        from_chunks(
+            self.len,
+            self.chunks().zip(other.chunks()).map(|(a, b)| a & b),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(|i| self.is_set(i))
+    }
+
+    /// Copy the bits into a new buffer, starting at bit 0.
+    pub fn to_buffer(&self) -> Buffer {
+        let mut builder = BitmapBuilder::with_capacity(self.len);
+        for bit in self.iter() {
+            builder.push(bit);
+        }
+        builder.finish()
+    }
+}
+
+/// Buffer of the first `len` bits of `chunks`, words of 64 bits whose lowest
+/// bit is the first one, like the ones of `Bitmap::chunks`. The bits of the
+/// last word after `len` are unset.
+pub fn from_chunks(len: usize, chunks: impl Iterator<Item = u64>) -> Buffer {
+    let bytes = len.div_ceil(8);
+    let mut buffer = Buffer::with_capacity(bytes);
+    for (i, chunk) in chunks.take(len.div_ceil(64)).enumerate() {
+        let chunk = match len - 64 * i {
+            bits if bits < 64 => chunk & ((1 << bits) - 1),
+            _ => chunk,
+        };
+        buffer.extend_from_slice(&chunk.to_le_bytes()[..(bytes - 8 * i).min(8)]);
+    }
+    buffer
+}
+
+/// Builder of a bit-packed buffer, one bit at a time.
+pub struct BitmapBuilder {
+    buffer: Buffer,
+    len: usize,
+}
+
+impl BitmapBuilder {
+    pub fn with_capacity(bits: usize) -> BitmapBuilder {
+        BitmapBuilder {
+            buffer: Buffer::with_capacity(bits.div_ceil(8)),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.buffer.push(0u8);
+        }
+        if bit {
+            let last = self.buffer.len() - 1;
+            self.buffer.as_mut_slice()[last] |= 1 << (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    pub fn finish(self) -> Buffer {
+        self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_and_reads_bits_at_an_offset() {
+        let mut builder = BitmapBuilder::with_capacity(11);
+        for i in 0..11 {
+            builder.push(i % 3 == 0);
+        }
+        assert_eq!(builder.len(), 11);
+        let buffer = builder.finish();
+        assert_eq!(buffer.as_slice(), [0b0100_1001, 0b0000_0010]);
+        let bitmap = Bitmap::new(buffer.as_slice(), 3, 7);
+        assert_eq!(
+            bitmap.iter().collect::<Vec<_>>(),
+            [true, false, false, true, false, false, true]
+        );
+        assert_eq!(bitmap.count_set(), 3);
+        let copy = bitmap.to_buffer();
+        assert_eq!(copy.as_slice(), [0b0100_1001]);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit")]
+    fn bitmaps_must_fit_in_their_data() {
+        Bitmap::new(&[0], 4, 5);
+    }
+
+    /// Bits set in a pattern without period 8 or 64, so shifts by wrong
+    /// offsets are noticed.
+    fn pattern(len: usize, seed: usize) -> Buffer {
+        let mut builder = BitmapBuilder::with_capacity(len);
+        for i in 0..len {
+            builder.push((i * 7 + seed) % 5 < 3);
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn chunks_at_any_offset() {
+        let data = pattern(300, 1);
+        for offset in [0, 3, 8, 13, 61, 64, 70] {
+            let bitmap = Bitmap::new(data.as_slice(), offset, 200);
+            let bits: Vec<_> = bitmap.iter().collect();
+            let chunks: Vec<_> = bitmap.chunks().collect();
+            assert_eq!(chunks.len(), 4);
+            for (i, bit) in bits.iter().enumerate() {
+                assert_eq!(chunks[i / 64] >> (i % 64) & 1 == 1, *bit, "{offset} {i}");
+            }
+            // The padding of the last word is unset.
+            assert_eq!(chunks[3] >> 8, 0);
+            assert_eq!(bitmap.count_set(), bits.iter().filter(|bit| **bit).count());
+        }
+    }
+
+    #[test]
+    fn and_of_bitmaps_with_different_offsets() {
+        let a = pattern(300, 1);
+        let b = pattern(300, 4);
+        for (offset_a, offset_b, len) in [
+            (0, 0, 130),
+            (3, 13, 130),
+            (5, 70, 200),
+            (61, 2, 64),
+            (70, 9, 7),
+            (1, 0, 0),
+        ] {
+            let a = Bitmap::new(a.as_slice(), offset_a, len);
+            let b = Bitmap::new(b.as_slice(), offset_b, len);
+            let and = a.and(&b);
+            assert_eq!(and.len(), len.div_ceil(8));
+            let expected: Vec<_> = a.iter().zip(b.iter()).map(|(a, b)| a && b).collect();
+            let actual: Vec<_> = Bitmap::new(and.as_slice(), 0, len).iter().collect();
+            assert_eq!(actual, expected, "{offset_a} {offset_b} {len}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "bitmaps of different lengths")]
+    fn and_needs_bitmaps_of_the_same_length() {
+        let data = pattern(16, 0);
+        Bitmap::new(data.as_slice(), 0, 8).and(&Bitmap::new(data.as_slice(), 0, 9));
+    }
+
+    #[test]
+    fn slices_keep_the_bits_from_their_offset() {
+        let buffer = pattern(200, 1);
+        let bitmap = Bitmap::new(buffer.as_slice(), 5, 190);
+        let slice = bitmap.slice(61, 70);
+        assert_eq!(slice.len(), 70);
+        let bits: Vec<_> = bitmap.iter().skip(61).take(70).collect();
+        assert_eq!(slice.iter().collect::<Vec<_>>(), bits);
+        assert_eq!(slice.count_set(), bits.iter().filter(|bit| **bit).count());
+        assert_eq!(bitmap.slice(190, 0).count_set(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "slice out of the bitmap")]
+    fn slices_must_fit_in_the_bitmap() {
+        let buffer = pattern(16, 0);
+        Bitmap::new(buffer.as_slice(), 0, 16).slice(10, 7);
+    }
+
+    #[test]
+    fn buffers_from_chunks_unset_the_bits_after_the_length() {
+        let buffer = from_chunks(70, [u64::MAX, u64::MAX, u64::MAX].into_iter());
+        assert_eq!(buffer.len(), 9);
+        assert_eq!(&buffer.as_slice()[..8], [0xff; 8]);
+        assert_eq!(buffer.as_slice()[8], 0b0011_1111);
+        assert_eq!(
+            from_chunks(64, [u64::MAX].into_iter()).as_slice(),
+            [0xff; 8]
+        );
+        assert!(from_chunks(0, [u64::MAX].into_iter()).is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+//! Bloom filters, to test whether values may be in a set without storing it.
+//!
+//! Hosts build a filter from the keys of one side of a join, and probe it
+//! with the keys of the other side to discard the rows that can't match,
+//! before moving them. A probe never misses a value of the set, and
+//! reports values that are not in the set with a false positive probability
+//! chosen when building the filter.
+//!
+//! Filters are also the `bloom_filter(n[, fpp])` aggregate, sized for `n`
+//! distinct values, so they can be built per group, or per partition and
+//! merged with the partial aggregation entry points. Filters are merged by
+//! or-ing their bits, which requires them to have the same size.
+//!
+//! Every value is hashed once with XXH3, and the `k` bit positions are
+//! derived from the two halves of the hash, as described in "Less Hashing,
+//! Same Performance: Building a Better Bloom Filter" by Kirsch and
+//! Mitzenmacher.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aggregate::{self, Accumulator, AggregateSpec, StateReader};
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::hash;
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+
+/// False positive probability used when the spec doesn't specify it.
+pub const DEFAULT_FPP: f64 = 0.01;
+
+/// Maximum number of hash functions.
+const MAX_HASHES: u64 = 30;
+
+/// Bits of a filter, and the number of bits set for every value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BloomFilter {
+    n_hashes: u64,
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Filter with the optimal size for `n` values with a false positive
+    /// probability of `fpp`.
+    pub fn new(n: u64, fpp: f64) -> BloomFilter {
+        let n = n.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let n_bits = (-n * fpp.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let n_hashes = ((n_bits / n) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u64;
+        BloomFilter {
+            n_hashes,
+            words: vec![0; (n_bits as usize).div_ceil(64)],
+        }
+    }
+
+    fn n_bits(&self) -> u64 {
+        self.words.len() as u64 * 64
+    }
+
+    /// Positions of the bits of a value, from its hash.
+    fn bits(&self, hash: u64) -> impl Iterator<Item = u64> + use<> {
+        let (n_bits, n_hashes) = (self.n_bits(), self.n_hashes);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..n_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % n_bits)
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        for bit in self.bits(hash) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.bits(hash)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Add the values of `other`, which must have the same size.
+    pub fn merge(&mut self, other: &BloomFilter) -> Result<()> {
+        if self.n_hashes != other.n_hashes || self.words.len() != other.words.len() {
+            return Err(Error::InvalidArgument(format!(
+                "can't merge bloom filters of {} and {} bits",
+                self.n_bits(),
+                other.n_bits()
+            )));
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+        Ok(())
+    }
+
+    pub fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.n_hashes.to_le_bytes());
+        out.extend_from_slice(&(self.words.len() as u64).to_le_bytes());
+        for word in &self.words {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    pub fn deserialize(state: &mut StateReader) -> Result<BloomFilter> {
+        let n_hashes = state.u64()?;
+        let n_words = state.u64()?;
+        if !(1..=MAX_HASHES).contains(&n_hashes) || n_words == 0 {
+            return Err(Error::InvalidArgument("invalid bloom filter".to_string()));
+        }
+        let words = (0..n_words).map(|_| state.u64()).collect::<Result<_>>()?;
+        Ok(BloomFilter { n_hashes, words })
+    }
+}
+
+/// Accumulator of `bloom_filter(n[, fpp])`.
+pub(crate) fn accumulator(data_type: ArrowType, args: &[f64]) -> Result<Box<dyn Accumulator>> {
+    let (n, fpp) = match *args {
+        [n] => (n, DEFAULT_FPP),
+        [n, fpp] => (n, fpp),
+        _ => {
+            return Err(Error::InvalidArgument(
+                "bloom_filter expects the number of values and optionally the false positive \
+                 probability"
+                    .to_string(),
+            ))
+        }
+    };
+    if !(n >= 1.0 && n.fract() == 0.0) {
+        return Err(Error::InvalidArgument(format!(
+            "the number of values must be a positive integer, got {n}"
+        )));
+    }
+    if !(fpp > 0.0 && fpp < 1.0) {
+        return Err(Error::InvalidArgument(format!(
+            "the false positive probability must be between 0 and 1, got {fpp}"
+        )));
+    }
+    if !hash::is_hashable(data_type) {
+        return Err(aggregate::unsupported("bloom_filter", data_type));
+    }
+    Ok(Box::new(Filters {
+        empty: BloomFilter::new(n as u64, fpp),
+        filters: Vec::new(),
+    }))
+}
+
+struct Filters {
+    /// Filter of the size of all the others, without values.
+    empty: BloomFilter,
+    filters: Vec<BloomFilter>,
+}
+
+impl Accumulator for Filters {
+    fn output_type(&self) -> ArrowType {
+        ArrowType::Binary
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.filters.resize(n_groups, self.empty.clone());
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        let start = rows.start;
+        hash::for_each_hash(values, rows, 0, |i, hash| {
+            self.filters[groups[i - start] as usize].insert(hash)
+        })
+        .expect("validated input type");
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        aggregate::for_each_state(states, rows, groups, |group, mut state| {
+            self.filters[group].merge(&BloomFilter::deserialize(&mut state)?)
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        aggregate::states_array(self.filters.len(), |group, state| {
+            self.filters[group].serialize(state)
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        self.state()
+    }
+}
+
+/// Build a bloom filter with the non-null values of a primitive, binary or
+/// Utf8 array, sized for the number of values, with a false positive
+/// probability of `fpp`. The filter is returned as a Binary array with a
+/// single element, to be used with `arrow_udf_bloom_probe`.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_bloom_build(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    fpp: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let n = (array.len() - array.null_count()).max(1);
+        let spec = AggregateSpec {
+            function: aggregate::lookup("bloom_filter").unwrap(),
+            args: vec![n as f64, fpp],
+        };
+        let accumulator = aggregate::aggregate_all(&spec, &array, &options)?;
+        let out = aggregate::state_schema(&spec, array.data_type(), &schema.name);
+        export::export_to(&out, &Arc::new(accumulator.state()), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Boolean array telling whether every element of an array may be in the
+/// bloom filter stored in the first element of a Binary array, as created by
+/// `arrow_udf_bloom_build` or the `bloom_filter` aggregate. Null elements
+/// are null in the result.
+///
+/// # Safety
+///
+/// `schema` and `array`, and `filter_schema` and `filter_array`, must point
+/// to valid Arrow C Data Interface arrays, `options` must be null or valid,
+/// and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_bloom_probe(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    filter_schema: *const ArrowCDataInterfaceSchema,
+    filter_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let filter_schema = Schema::from_ffi(&*filter_schema)?;
+        let filter_array = ArrowArray::import(&filter_schema, &*filter_array)?;
+        if filter_array.data_type() != ArrowType::Binary
+            || filter_array.is_empty()
+            || !filter_array.is_valid(0)
+        {
+            return Err(Error::InvalidArgument(
+                "the filter must be a Binary array with a bloom filter".to_string(),
+            ));
+        }
+        let built_for = filter_schema
+            .metadata
+            .get(aggregate::STATE_INPUT_TYPE_METADATA_KEY);
+        if built_for.is_some_and(|format| format != array.data_type().format()) {
+            return Err(Error::InvalidArgument(format!(
+                "the filter was built for {:?} values, got {:?}",
+                built_for
+                    .and_then(ArrowType::from_format)
+                    .unwrap_or(ArrowType::Binary),
+                array.data_type()
+            )));
+        }
+        if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+            return Err(Error::NullValue);
+        }
+        let filter = BloomFilter::deserialize(&mut StateReader::new(filter_array.binary_value(0)))?;
+        let mut found = vec![false; array.len()];
+        hash::for_each_hash(&array, 0..array.len(), 0, |i, hash| {
+            found[i] = filter.contains(hash)
+        })?;
+        let mut values = BitmapBuilder::with_capacity(array.len());
+        found.iter().for_each(|found| values.push(*found));
+        let (validity, null_count) = crate::udf::output_validity(&array);
+        let data =
+            ArrayData::primitive(values.finish(), array.len()).with_validity(validity, null_count);
+        let out = Schema::new(ArrowType::Boolean, &schema.name);
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    fn build(input: &Exported, fpp: f64) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            let options = std::ptr::null();
+            arrow_udf_bloom_build(schema, array, fpp, options, &mut out.schema, &mut out.array)
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn probe(input: &Exported, filter: &Exported) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_bloom_probe(
+                &input.schema,
+                &input.array,
+                &filter.schema,
+                &filter.array,
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn members_are_always_found() {
+        let keys: Vec<i64> = (0..1000).map(|i| i * 7).collect();
+        let filter = build(&Exported::primitive(&keys), 0.01).unwrap();
+        let found = probe(&Exported::primitive(&keys), &filter).unwrap();
+        assert!(found.booleans().iter().all(|found| *found == Some(true)));
+        let others: Vec<i64> = (0..10_000).map(|i| i * 7 + 3).collect();
+        let found = probe(&Exported::primitive(&others), &filter).unwrap();
+        let false_positives = found
+            .booleans()
+            .iter()
+            .filter(|found| **found == Some(true))
+            .count();
+        assert!(false_positives < 200, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn nulls_are_left_out() {
+        let filter = build(&Exported::nullable(&[Some(1.5_f64), None]), 0.01).unwrap();
+        let input = Exported::nullable(&[None, Some(1.5), Some(-0.0)]);
+        let found = probe(&input, &filter).unwrap();
+        assert_eq!(found.booleans()[..2], [None, Some(true)]);
+    }
+
+    #[test]
+    fn filters_must_match_the_probed_values() {
+        let filter = build(&Exported::primitive(&[1_i32, 2]), 0.01).unwrap();
+        let status = probe(&Exported::primitive(&[1_i64]), &filter).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let not_a_filter = Exported::primitive(&[1_i32]);
+        let status = probe(&Exported::primitive(&[1_i32]), &not_a_filter).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        for fpp in [0.0, 1.0, f64::NAN] {
+            let status = build(&Exported::primitive(&[1_i32]), fpp).err();
+            assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        }
+        let status = build(&Exported::boolean(&[true]), 0.01).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+    }
+
+    #[test]
+    fn merged_filters_contain_both_sets() {
+        let (mut a, mut b) = (BloomFilter::new(100, 0.01), BloomFilter::new(100, 0.01));
+        a.insert(1);
+        b.insert(u64::MAX);
+        a.merge(&b).unwrap();
+        assert!(a.contains(1) && a.contains(u64::MAX));
+        let mut state = Vec::new();
+        a.serialize(&mut state);
+        assert_eq!(
+            BloomFilter::deserialize(&mut StateReader::new(&state)).unwrap(),
+            a
+        );
+        assert!(a.merge(&BloomFilter::new(10_000, 0.01)).is_err());
+        assert!(accumulator(ArrowType::Int32, &[0.0]).is_err());
+        assert!(accumulator(ArrowType::Int32, &[10.0, 0.5, 1.0]).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Memory backing the arrays exported by this library.
+//!
+//! Buffers are allocated with the Rust global allocator, unless the host
+//! registers its own allocator with `arrow_udf_set_allocator`, so exported
+//! arrays count against the memory accounting of the host. Every buffer is
+//! freed by the allocator it was allocated with, also if another allocator is
+//! registered in the meantime.
+
+use std::alloc::{self, Layout};
+use std::ffi::c_void;
+use std::panic;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::memory::Reservation;
+use crate::types::NativeType;
+
+/// Alignment recommended by the Arrow specification for buffers.
+pub const ALIGNMENT: usize = 64;
+
+/// Allocator provided by the host, like the memory pool of a query engine.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ArrowUdfAllocator {
+    /// Allocate `size` bytes aligned to `alignment`, returning null when the
+    /// memory can't be allocated.
+    pub allocate: Option<
+        unsafe extern "C" fn(size: usize, alignment: usize, private_data: *mut c_void) -> *mut u8,
+    >,
+    /// Free memory returned by `allocate`, with the same size and alignment.
+    pub free: Option<
+        unsafe extern "C" fn(
+            ptr: *mut u8,
+            size: usize,
+            alignment: usize,
+            private_data: *mut c_void,
+        ),
+    >,
+    /// Passed to the callbacks as it is.
+    pub private_data: *mut c_void,
+}
+
+// Hosts registering an allocator make it callable from any thread.
+unsafe impl Send for ArrowUdfAllocator {}
+unsafe impl Sync for ArrowUdfAllocator {}
+
+/// The host allocator used for new buffers, or null for the Rust global
+/// allocator. Registered allocators are never freed, since buffers they
+/// allocated can outlive their registration.
+static ALLOCATOR: AtomicPtr<ArrowUdfAllocator> = AtomicPtr::new(ptr::null_mut());
+
+/// Register the allocator used for the buffers allocated from now on, or go
+/// back to the Rust global allocator if `allocator` is null. Allocations
+/// failing in the host allocator make the calls fail with
+/// `ArrowUdfStatus::OutOfBudget`.
+///
+/// # Safety
+///
+/// `allocator` must be null or point to an allocator with both callbacks
+/// set, callable from any thread until all the buffers it allocated are
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_set_allocator(
+    allocator: *const ArrowUdfAllocator,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let registered = match allocator.as_ref() {
+            None => ptr::null_mut(),
+            Some(allocator) if allocator.allocate.is_none() || allocator.free.is_none() => {
+                return Err(Error::InvalidArgument(
+                    "allocator without allocate or free callback".to_string(),
+                ));
+            }
+            Some(allocator) => Box::into_raw(Box::new(*allocator)),
+        };
+        ALLOCATOR.store(registered, Ordering::Release);
+        Ok(())
+    })
+}
+
+/// Growable, 64 bytes aligned, region of memory.
+pub struct Buffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+    /// The host allocator the memory was allocated with, if any.
+    allocator: Option<&'static ArrowUdfAllocator>,
+    /// Whether the memory belongs to the host, and isn't freed with the
+    /// buffer, see `ArrowUdfExecOptions::output_buffer`.
+    host_owned: bool,
+    /// The capacity, in the tracker of the call that allocated the buffer.
+    reservation: Reservation,
+}
+
+// The buffer owns its memory exclusively, as a `Vec<u8>` would.
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer {
+            ptr: dangling(),
+            len: 0,
+            capacity: 0,
+            allocator: None,
+            host_owned: false,
+            reservation: Reservation::default(),
+        }
+    }
+
+    /// Allocate a buffer able to hold `capacity` bytes without reallocating.
+    pub fn with_capacity(capacity: usize) -> Buffer {
+        let mut buffer = Buffer::new();
+        buffer.reserve(capacity);
+        buffer
+    }
+
+    /// Allocate a buffer able to hold `capacity` bytes, failing with
+    /// `Error::OutOfBudget` if the host allocator can't allocate them.
+    pub fn try_with_capacity(capacity: usize) -> Result<Buffer> {
+        let mut buffer = Buffer::new();
+        buffer.try_reserve(capacity)?;
+        Ok(buffer)
+    }
+
+    /// Allocate a buffer of `len` bytes set to zero, failing with
+    /// `Error::OutOfBudget` if the host allocator can't allocate them.
+    pub fn zeroed(len: usize) -> Result<Buffer> {
+        let mut buffer = Buffer::try_with_capacity(len)?;
+        unsafe {
+            buffer.ptr.as_ptr().write_bytes(0, len);
+        }
+        buffer.len = len;
+        Ok(buffer)
+    }
+
+    /// Buffer of `len` bytes set to zero in the `capacity` bytes of memory
+    /// at `ptr`, owned by the host. The memory isn't freed with the buffer,
+    /// and isn't accounted in the tracker of the call. Growing the buffer
+    /// past `capacity` moves it to memory allocated like any other buffer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned to 8 bytes and valid for writes of `capacity`
+    /// bytes, which must be at least `len`, until the buffer and its exports
+    /// are dropped.
+    pub unsafe fn zeroed_in_host(ptr: NonNull<u8>, len: usize, capacity: usize) -> Buffer {
+        ptr.as_ptr().write_bytes(0, len);
+        Buffer {
+            ptr,
+            len,
+            capacity,
+            allocator: None,
+            host_owned: true,
+            reservation: Reservation::default(),
+        }
+    }
+
+    pub fn from_slice<T: NativeType>(values: &[T]) -> Buffer {
+        let mut buffer = Buffer::with_capacity(std::mem::size_of_val(values));
+        buffer.extend_from_slice(values);
+        buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// View the buffer as a slice of `T`. Any trailing bytes not filling a
+    /// whole `T` are ignored.
+    pub fn typed_data<T: NativeType>(&self) -> &[T] {
+        let len = self.len / std::mem::size_of::<T>();
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr() as *const T, len) }
+    }
+
+    pub fn typed_data_mut<T: NativeType>(&mut self) -> &mut [T] {
+        let len = self.len / std::mem::size_of::<T>();
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut T, len) }
+    }
+
+    /// Make sure `additional` more bytes can be written without reallocating.
+    ///
+    /// Failures of the host allocator unwind to the entry point with
+    /// `Error::OutOfBudget` as the payload, without calling the panic hook,
+    /// and `ffi_guard` returns it as the status of the call. Use
+    /// `try_reserve` to handle them instead.
+    pub fn reserve(&mut self, additional: usize) {
+        if let Err(error) = self.try_reserve(additional) {
+            panic::resume_unwind(Box::new(error));
+        }
+    }
+
+    /// Like `reserve`, but failing with `Error::OutOfBudget` if the host
+    /// allocator can't allocate the memory. The buffer is unchanged then.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<()> {
+        let required = self.len + additional;
+        if required <= self.capacity {
+            return Ok(());
+        }
+        if self.host_owned {
+            let mut owned = Buffer::try_with_capacity(required.max(self.capacity * 2))?;
+            owned.extend_from_slice(self.as_slice());
+            *self = owned;
+            return Ok(());
+        }
+        let new_capacity = required.max(self.capacity * 2).next_multiple_of(ALIGNMENT);
+        let new_layout = Layout::from_size_align(new_capacity, ALIGNMENT).unwrap();
+        let allocator = match self.capacity {
+            0 => unsafe { ALLOCATOR.load(Ordering::Acquire).as_ref() },
+            _ => self.allocator,
+        };
+        let new_ptr = match allocator {
+            Some(allocator) => unsafe {
+                let new_ptr = host_allocate(allocator, new_capacity)?;
+                if self.capacity > 0 {
+                    ptr::copy_nonoverlapping(self.ptr.as_ptr(), new_ptr, self.len);
+                    self.free();
+                }
+                new_ptr
+            },
+            None => unsafe {
+                if self.capacity == 0 {
+                    alloc::alloc(new_layout)
+                } else {
+                    alloc::realloc(self.ptr.as_ptr(), self.layout(), new_capacity)
+                }
+            },
+        };
+        self.ptr = NonNull::new(new_ptr).unwrap_or_else(|| alloc::handle_alloc_error(new_layout));
+        if self.capacity == 0 {
+            self.reservation = Reservation::new(new_capacity);
+        } else {
+            self.reservation.resize(new_capacity);
+        }
+        self.capacity = new_capacity;
+        self.allocator = allocator;
+        Ok(())
+    }
+
+    /// Free the memory of the buffer, with the allocator it comes from.
+    unsafe fn free(&mut self) {
+        match self.allocator {
+            Some(allocator) => (allocator.free.unwrap())(
+                self.ptr.as_ptr(),
+                self.capacity,
+                ALIGNMENT,
+                allocator.private_data,
+            ),
+            None => alloc::dealloc(self.ptr.as_ptr(), self.layout()),
+        }
+    }
+
+    pub fn push<T: NativeType>(&mut self, value: T) {
+        self.extend_from_slice(&[value]);
+    }
+
+    pub fn extend_from_slice<T: NativeType>(&mut self, values: &[T]) {
+        let bytes = std::mem::size_of_val(values);
+        self.reserve(bytes);
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                values.as_ptr() as *const u8,
+                self.ptr.as_ptr().add(self.len),
+                bytes,
+            );
+        }
+        self.len += bytes;
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::from_size_align(self.capacity, ALIGNMENT).unwrap()
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Buffer {
+        Buffer::new()
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if self.capacity > 0 && !self.host_owned {
+            unsafe { self.free() }
+        }
+    }
+}
+
+impl<T: NativeType> FromIterator<T> for Buffer {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Buffer {
+        let iter = iter.into_iter();
+        let mut buffer = Buffer::with_capacity(iter.size_hint().0 * std::mem::size_of::<T>());
+        for value in iter {
+            buffer.push(value);
+        }
+        buffer
+    }
+}
+
+/// Allocate `size` bytes with a host allocator. Unlike the global
+/// allocator, hosts are expected to refuse allocations over their limits,
+/// so null is an `Error::OutOfBudget` instead of aborting.
+unsafe fn host_allocate(allocator: &ArrowUdfAllocator, size: usize) -> Result<*mut u8> {
+    let allocate = allocator.allocate.unwrap();
+    let ptr = allocate(size, ALIGNMENT, allocator.private_data);
+    if ptr.is_null() {
+        return Err(Error::OutOfBudget);
+    }
+    Ok(ptr)
+}
+
+fn dangling() -> NonNull<u8> {
+    // Empty buffers must still be aligned, since consumers may check it.
+    NonNull::new(ALIGNMENT as *mut u8).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn grows_aligned() {
+        let mut buffer = Buffer::new();
+        assert!(buffer.is_empty());
+        for value in 0..100_i64 {
+            buffer.push(value);
+        }
+        assert_eq!(buffer.len(), 800);
+        assert_eq!(buffer.as_ptr() as usize % ALIGNMENT, 0);
+        assert_eq!(buffer.typed_data::<i64>()[99], 99);
+        assert_eq!(Buffer::zeroed(3).unwrap().as_slice(), [0, 0, 0]);
+    }
+
+    /// Size of the allocations counted by the test allocator. Other tests
+    /// running at the same time also allocate with it, so only this size is
+    /// counted, and only sizes over a GiB are refused.
+    const COUNTED: usize = 64 * 1001;
+    const REFUSED: usize = 1 << 30;
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+    static REGISTRATION: Mutex<()> = Mutex::new(());
+
+    unsafe extern "C" fn allocate(size: usize, alignment: usize, _: *mut c_void) -> *mut u8 {
+        if size >= REFUSED {
+            return ptr::null_mut();
+        }
+        if size == COUNTED {
+            ALLOCATED.fetch_add(1, Ordering::Relaxed);
+        }
+        alloc::alloc(Layout::from_size_align(size, alignment).unwrap())
+    }
+
+    unsafe extern "C" fn free(ptr: *mut u8, size: usize, alignment: usize, _: *mut c_void) {
+        if size == COUNTED {
+            FREED.fetch_add(1, Ordering::Relaxed);
+        }
+        alloc::dealloc(ptr, Layout::from_size_align(size, alignment).unwrap());
+    }
+
+    fn with_host_allocator(f: impl FnOnce()) {
+        let _registration = REGISTRATION.lock().unwrap();
+        let allocator = ArrowUdfAllocator {
+            allocate: Some(allocate),
+            free: Some(free),
+            private_data: ptr::null_mut(),
+        };
+        assert_eq!(
+            unsafe { arrow_udf_set_allocator(&allocator) },
+            ArrowUdfStatus::Ok
+        );
+        f();
+        assert_eq!(
+            unsafe { arrow_udf_set_allocator(ptr::null()) },
+            ArrowUdfStatus::Ok
+        );
+    }
+
+    #[test]
+    fn buffers_are_freed_by_their_allocator() {
+        let (allocated, freed) = (
+            ALLOCATED.load(Ordering::Relaxed),
+            FREED.load(Ordering::Relaxed),
+        );
+        let mut host = None;
+        with_host_allocator(|| {
+            let mut buffer = Buffer::with_capacity(COUNTED);
+            buffer.extend_from_slice(&[1_u8, 2, 3]);
+            host = Some(buffer);
+        });
+        assert_eq!(ALLOCATED.load(Ordering::Relaxed), allocated + 1);
+        // Growing copies the data into a new allocation of the host.
+        let mut buffer = host.unwrap();
+        buffer.reserve(COUNTED);
+        assert_eq!(buffer.as_slice(), [1, 2, 3]);
+        assert_eq!(FREED.load(Ordering::Relaxed), freed + 1);
+        drop(buffer);
+        let registration = REGISTRATION.lock().unwrap();
+        drop(Buffer::with_capacity(COUNTED));
+        drop(registration);
+        assert_eq!(ALLOCATED.load(Ordering::Relaxed), allocated + 1);
+    }
+
+    #[test]
+    fn host_allocation_failures_are_errors() {
+        with_host_allocator(|| {
+            assert_eq!(
+                Buffer::try_with_capacity(REFUSED).err(),
+                Some(Error::OutOfBudget)
+            );
+            assert_eq!(Buffer::zeroed(REFUSED).err(), Some(Error::OutOfBudget));
+            let mut buffer = Buffer::from_slice(&[1_i32, 2]);
+            assert_eq!(buffer.try_reserve(REFUSED), Err(Error::OutOfBudget));
+            assert_eq!(buffer.typed_data::<i32>(), [1, 2]);
+            // The infallible methods fail the call with the same status.
+            let status = ffi_guard(|| {
+                Buffer::new().reserve(REFUSED);
+                Ok(())
+            });
+            assert_eq!(status, ArrowUdfStatus::OutOfBudget);
+        });
+    }
+
+    #[test]
+    fn allocators_need_both_callbacks() {
+        let allocator = ArrowUdfAllocator {
+            allocate: Some(allocate),
+            free: None,
+            private_data: ptr::null_mut(),
+        };
+        assert_eq!(
+            unsafe { arrow_udf_set_allocator(&allocator) },
+            ArrowUdfStatus::InvalidArgument
+        );
+    }
+}
@@ -0,0 +1,693 @@
+//! Casts of arrays to another type, for hosts aligning the types of their
+//! columns with the ones the UDFs take.
+//!
+//! Numbers are cast to each other, to and from Boolean, and to and from
+//! Utf8, as their decimal text. Dates and timestamps are cast to each other
+//! and durations to durations, rescaling their values: timestamps cast to
+//! dates keep the day, rounding towards the past, and durations cast to a
+//! coarser unit are truncated towards zero. All the temporal types are cast
+//! to and from their integer values, and, with the `temporal` feature,
+//! dates and timestamps to and from ISO 8601 text, in the timezone of the
+//! timestamps.
+//!
+//! Values out of the range of the target type, like 300 as Int8 or NaN as
+//! an integer, overflow, and text that isn't a value of the target type is
+//! a parse error. Each has its policy: failing the call, which the host can
+//! turn into collecting the errors of the rows, see `udf::RowErrors`,
+//! making the value null, or, only for overflows, saturating it to the
+//! closest value of the target type. Floats are truncated towards zero when
+//! cast to integers, without any error.
+
+use std::ffi::{c_char, CStr};
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::row::ArrowField;
+use crate::schema::{ArrowType, Schema, TimeUnit};
+use crate::types::{with_native_type, NativeType, Numeric};
+use crate::udf::RowErrors;
+
+pub const ARROW_UDF_CAST_ERROR: i32 = 0;
+pub const ARROW_UDF_CAST_NULL: i32 = 1;
+pub const ARROW_UDF_CAST_SATURATE: i32 = 2;
+
+const NANOS_PER_DAY: i128 = 86_400_000_000_000;
+const NANOS_PER_MILLI: i128 = 1_000_000;
+
+/// What to do with the values that can't be cast.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CastPolicy {
+    /// Fail the call, or collect the error of the row.
+    Error,
+    /// Make the value null.
+    Null,
+    /// Make the value the closest one of the target type. Only for
+    /// overflows.
+    Saturate,
+}
+
+impl CastPolicy {
+    pub fn from_ffi(policy: i32) -> Result<CastPolicy> {
+        match policy {
+            ARROW_UDF_CAST_ERROR => Ok(CastPolicy::Error),
+            ARROW_UDF_CAST_NULL => Ok(CastPolicy::Null),
+            ARROW_UDF_CAST_SATURATE => Ok(CastPolicy::Saturate),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown cast policy {other}"
+            ))),
+        }
+    }
+}
+
+/// Policies of the values out of range and of the text that can't be
+/// parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CastOptions {
+    pub overflow: CastPolicy,
+    pub parse_error: CastPolicy,
+}
+
+impl CastOptions {
+    pub fn from_ffi(overflow: i32, parse_error: i32) -> Result<CastOptions> {
+        let options = CastOptions {
+            overflow: CastPolicy::from_ffi(overflow)?,
+            parse_error: CastPolicy::from_ffi(parse_error)?,
+        };
+        if options.parse_error == CastPolicy::Saturate {
+            return Err(Error::InvalidArgument(
+                "parse errors can't saturate".to_string(),
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// A value cast to the target type.
+enum Converted<O> {
+    Value(O),
+    /// Out of range, with the saturated value, if there is one, and the
+    /// message of the error.
+    Overflow(Option<O>, String),
+    /// Text that isn't a value of the target type.
+    Invalid(String),
+}
+
+fn overflow<O: NativeType>(value: impl Display, saturated: O) -> Converted<O> {
+    Converted::Overflow(
+        Some(saturated),
+        format!("{value} out of range of {:?}", O::ARROW_TYPE),
+    )
+}
+
+fn invalid<O>(text: &str, data_type: ArrowType) -> Converted<O> {
+    Converted::Invalid(format!("{text:?} is not a valid {data_type:?}"))
+}
+
+/// Numbers cast to each other and parsed from text.
+trait CastNumber: Numeric + Display + for<'a> ArrowField<'a> {
+    /// The value, if it's an integer type.
+    fn to_i128(self) -> Option<i128>;
+    fn from_i128(value: i128) -> Converted<Self>;
+    fn from_float(value: f64) -> Converted<Self>;
+    fn parse(text: &str) -> Converted<Self>;
+}
+
+macro_rules! cast_number {
+    (int: $($int:ty),*; float: $($float:ty),*) => {
+        $(
+            impl CastNumber for $int {
+                fn to_i128(self) -> Option<i128> {
+                    Some(self as i128)
+                }
+
+                fn from_i128(value: i128) -> Converted<$int> {
+                    match <$int>::try_from(value) {
+                        Ok(value) => Converted::Value(value),
+                        Err(_) if value < 0 => overflow(value, <$int>::MIN),
+                        Err(_) => overflow(value, <$int>::MAX),
+                    }
+                }
+
+                fn from_float(value: f64) -> Converted<$int> {
+                    match <$int>::try_from(value.trunc() as i128) {
+                        Ok(int) if !value.is_nan() => Converted::Value(int),
+                        _ => overflow(value, <$int>::from_f64(value)),
+                    }
+                }
+
+                fn parse(text: &str) -> Converted<$int> {
+                    match text.trim().parse::<i128>() {
+                        Ok(value) => <$int>::from_i128(value),
+                        Err(_) => invalid(text, <$int>::ARROW_TYPE),
+                    }
+                }
+            }
+        )*
+        $(
+            impl CastNumber for $float {
+                fn to_i128(self) -> Option<i128> {
+                    None
+                }
+
+                fn from_i128(value: i128) -> Converted<$float> {
+                    Converted::Value(value as $float)
+                }
+
+                fn from_float(value: f64) -> Converted<$float> {
+                    let cast = value as $float;
+                    match cast.is_infinite() && value.is_finite() {
+                        true if value < 0.0 => overflow(value, <$float>::MIN),
+                        true => overflow(value, <$float>::MAX),
+                        false => Converted::Value(cast),
+                    }
+                }
+
+                fn parse(text: &str) -> Converted<$float> {
+                    match text.trim().parse::<f64>() {
+                        Ok(value) => <$float>::from_float(value),
+                        Err(_) => invalid(text, <$float>::ARROW_TYPE),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+cast_number! {
+    int: i8, i16, i32, i64, u8, u16, u32, u64;
+    float: f32, f64
+}
+
+fn cast_number<I: CastNumber, O: CastNumber>(value: I) -> Converted<O> {
+    match value.to_i128() {
+        Some(value) => O::from_i128(value),
+        None => O::from_float(value.to_f64()),
+    }
+}
+
+/// Whether the values of `data_type` are stored as numbers, which includes
+/// the temporal types other than intervals.
+fn is_number(data_type: ArrowType) -> bool {
+    let physical = data_type.physical_type();
+    !matches!(data_type, ArrowType::Interval(_))
+        && with_native_type!(physical, T => T::ARROW_TYPE == physical, _ => false)
+}
+
+/// Whether the temporal type is a duration, and the nanoseconds of its unit.
+fn temporal_unit(data_type: ArrowType) -> Option<(bool, i128)> {
+    let nanos = |unit: TimeUnit| 1_000_000_000 / unit.per_second() as i128;
+    match data_type {
+        ArrowType::Date32 => Some((false, NANOS_PER_DAY)),
+        ArrowType::Date64 => Some((false, NANOS_PER_MILLI)),
+        ArrowType::Timestamp(unit) => Some((false, nanos(unit))),
+        ArrowType::Duration(unit) => Some((true, nanos(unit))),
+        _ => None,
+    }
+}
+
+/// `value` in units of `from` nanoseconds, in units of `to` nanoseconds,
+/// rounded towards the past with `floor`, or else towards zero.
+fn rescale(value: i64, from: i128, to: i128, floor: bool) -> i128 {
+    let value = value as i128;
+    match from >= to {
+        true => value * (from / to),
+        false if floor => value.div_euclid(to / from),
+        false => value / (to / from),
+    }
+}
+
+/// The value of the policy for the row `i`, converted to `converted`.
+fn resolve<O>(
+    converted: Converted<O>,
+    i: usize,
+    cast_options: &CastOptions,
+    errors: &RowErrors,
+) -> Result<Option<O>> {
+    let (policy, saturated, message) = match converted {
+        Converted::Value(value) => return Ok(Some(value)),
+        Converted::Overflow(saturated, message) => (cast_options.overflow, saturated, message),
+        Converted::Invalid(message) => (cast_options.parse_error, None, message),
+    };
+    match policy {
+        CastPolicy::Error => errors.check(i, Err(Error::InvalidArgument(message))),
+        CastPolicy::Null => Ok(None),
+        CastPolicy::Saturate => Ok(saturated),
+    }
+}
+
+/// Array of `to` with the valid elements of `array` converted by `f`.
+fn convert<O: for<'a> ArrowField<'a>>(
+    array: &ArrowArray,
+    to: Schema,
+    cast_options: &CastOptions,
+    options: &ArrowUdfExecOptions,
+    f: impl Fn(usize) -> Result<Converted<O>>,
+) -> Result<(Schema, ArrayData)> {
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let errors = RowErrors::new(options);
+    let mut values: Vec<Option<O>> = Vec::with_capacity(array.len());
+    exec::for_each_batch(array.len(), options, |rows| {
+        for i in rows {
+            values.push(match array.is_valid(i) {
+                true => resolve(f(i)?, i, cast_options, &errors)?,
+                false => None,
+            });
+        }
+        Ok(())
+    })?;
+    options.check_memory()?;
+    let data = O::build(values.iter().map(Option::as_ref));
+    Ok(errors.finish(to, data))
+}
+
+/// Dates and timestamps, or durations, rescaled to the unit of `to`.
+fn cast_temporal(
+    array: &ArrowArray,
+    to: Schema,
+    cast_options: &CastOptions,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let (from_type, to_type) = (array.data_type(), to.data_type);
+    let ((from_duration, from), (to_duration, to_unit)) = (
+        temporal_unit(from_type).unwrap(),
+        temporal_unit(to_type).unwrap(),
+    );
+    if from_duration != to_duration {
+        return Err(Error::UnsupportedType(format!(
+            "cast from {from_type:?} to {to_type:?}"
+        )));
+    }
+    let value = |i: usize| match from_type {
+        ArrowType::Date32 => array.values::<i32>()[i] as i64,
+        _ => array.values::<i64>()[i],
+    };
+    let ticks = |i: usize| match to_type {
+        // Date64 values are whole days.
+        ArrowType::Date64 => {
+            rescale(value(i), from, NANOS_PER_DAY, true) * (NANOS_PER_DAY / NANOS_PER_MILLI)
+        }
+        _ => rescale(value(i), from, to_unit, !to_duration),
+    };
+    match to_type {
+        ArrowType::Date32 => convert(array, to, cast_options, options, |i| {
+            Ok(i32::from_i128(ticks(i)))
+        }),
+        _ => convert(array, to, cast_options, options, |i| {
+            Ok(i64::from_i128(ticks(i)))
+        }),
+    }
+}
+
+fn cast_numbers<I: CastNumber, O: CastNumber>(
+    array: &ArrowArray,
+    to: Schema,
+    cast_options: &CastOptions,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let values = array.values::<I>();
+    convert(array, to, cast_options, options, |i| {
+        Ok(cast_number::<I, O>(values[i]))
+    })
+}
+
+/// Copy of `array` with its values cast to the type of `to`, and its name.
+pub fn cast(
+    array: &ArrowArray,
+    to: &Schema,
+    cast_options: &CastOptions,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let (from_type, to_type) = (array.data_type(), to.data_type);
+    let unsupported = || Error::UnsupportedType(format!("cast from {from_type:?} to {to_type:?}"));
+    let to = Schema {
+        name: array.schema().name.clone(),
+        ..to.clone()
+    };
+    let is_date_or_timestamp = |data_type| {
+        matches!(
+            data_type,
+            ArrowType::Date32 | ArrowType::Date64 | ArrowType::Timestamp(_)
+        )
+    };
+    match (from_type, to_type) {
+        _ if temporal_unit(from_type).is_some() && temporal_unit(to_type).is_some() => {
+            cast_temporal(array, to, cast_options, options)
+        }
+        (ArrowType::Utf8, ArrowType::Utf8) => convert(array, to, cast_options, options, |i| {
+            Ok(Converted::Value(array.utf8_value(i)?.to_string()))
+        }),
+        (ArrowType::Boolean, ArrowType::Boolean) => {
+            convert(array, to, cast_options, options, |i| {
+                Ok(Converted::Value(bool::read(array, i)?))
+            })
+        }
+        (ArrowType::Boolean, ArrowType::Utf8) => convert(array, to, cast_options, options, |i| {
+            Ok(Converted::Value(bool::read(array, i)?.to_string()))
+        }),
+        (ArrowType::Utf8, ArrowType::Boolean) => convert(array, to, cast_options, options, |i| {
+            let text = array.utf8_value(i)?;
+            Ok(match text.trim() {
+                text if text.eq_ignore_ascii_case("true") => Converted::Value(true),
+                text if text.eq_ignore_ascii_case("false") => Converted::Value(false),
+                _ => invalid(text, ArrowType::Boolean),
+            })
+        }),
+        (ArrowType::Boolean, _) => with_native_type!(to_type, O => {
+            convert(array, to, cast_options, options, |i| {
+                Ok(O::from_i128(bool::read(array, i)? as i128))
+            })
+        }, _ => Err(unsupported())),
+        (_, ArrowType::Boolean) => with_native_type!(from_type, I => {
+            let values = array.values::<I>();
+            convert(array, to, cast_options, options, |i| {
+                Ok(Converted::Value(values[i].to_f64() != 0.0))
+            })
+        }, _ => Err(unsupported())),
+        (_, ArrowType::Utf8) if is_date_or_timestamp(from_type) => {
+            #[cfg(feature = "temporal")]
+            {
+                let format = crate::temporal::text_formatter(array.schema())?;
+                let value = |i: usize| match from_type {
+                    ArrowType::Date32 => array.values::<i32>()[i] as i64,
+                    _ => array.values::<i64>()[i],
+                };
+                convert(array, to, cast_options, options, |i| {
+                    Ok(match format(value(i)) {
+                        Some(text) => Converted::Value(text),
+                        None => {
+                            Converted::Overflow(None, format!("{} out of range of dates", value(i)))
+                        }
+                    })
+                })
+            }
+            #[cfg(not(feature = "temporal"))]
+            Err(Error::UnsupportedType(format!(
+                "cast from {from_type:?} to text, which requires the temporal feature"
+            )))
+        }
+        (ArrowType::Utf8, _) if is_date_or_timestamp(to_type) => {
+            #[cfg(feature = "temporal")]
+            {
+                let parse = crate::temporal::text_parser(&to)?;
+                let parsed = |i: usize| -> Result<Converted<i64>> {
+                    let text = array.utf8_value(i)?;
+                    Ok(parse(text).map_or_else(|| invalid(text, to_type), Converted::Value))
+                };
+                match to_type {
+                    ArrowType::Date32 => convert(array, to, cast_options, options, |i| {
+                        Ok(match parsed(i)? {
+                            Converted::Value(days) => i32::from_i128(days as i128),
+                            Converted::Invalid(message) => Converted::Invalid(message),
+                            Converted::Overflow(_, message) => Converted::Overflow(None, message),
+                        })
+                    }),
+                    _ => convert(array, to, cast_options, options, parsed),
+                }
+            }
+            #[cfg(not(feature = "temporal"))]
+            Err(Error::UnsupportedType(format!(
+                "cast from text to {to_type:?}, which requires the temporal feature"
+            )))
+        }
+        (ArrowType::Utf8, _) => with_native_type!(to_type, O => {
+            convert(array, to, cast_options, options, |i| {
+                Ok(O::parse(array.utf8_value(i)?))
+            })
+        }, _ => Err(unsupported())),
+        (_, ArrowType::Utf8) => with_native_type!(from_type, I => {
+            let values = array.values::<I>();
+            convert(array, to, cast_options, options, |i| {
+                Ok(Converted::Value(values[i].to_string()))
+            })
+        }, _ => Err(unsupported())),
+        _ if is_number(from_type) && is_number(to_type) => {
+            with_native_type!(from_type.physical_type(), I => {
+                with_native_type!(to_type.physical_type(), O => {
+                    cast_numbers::<I, O>(array, to, cast_options, options)
+                }, _ => Err(unsupported()))
+            }, _ => Err(unsupported()))
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+/// Copy of an array with its values cast to the type of the C Data
+/// Interface format `format`, like `i` for Int32 or `tsu:UTC` for
+/// timestamps, and its name. `overflow` and `parse_error` are the
+/// `ARROW_UDF_CAST_*` policies of the values out of the range of the type,
+/// and of the text that isn't a value of the type, which can't saturate.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `format` must be a valid null-terminated string, `options` must be null
+/// or valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_cast(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    format: *const c_char,
+    overflow: i32,
+    parse_error: i32,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let cast_options = CastOptions::from_ffi(overflow, parse_error)?;
+        let format = CStr::from_ptr(format)
+            .to_str()
+            .map_err(|_| Error::InvalidArgument("the format is not valid UTF-8".to_string()))?;
+        let data_type = ArrowType::from_format(format)
+            .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+        let to = Schema {
+            format: format.to_string(),
+            ..Schema::new(data_type, "")
+        };
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = cast(&array, &to, &cast_options, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::{self, Exported};
+    use crate::types::NativeType;
+    use crate::udf;
+
+    fn typed<T: NativeType>(format: &str, values: &[Option<T>]) -> Exported {
+        let mut schema = Schema::new(ArrowType::from_format(format).unwrap(), "x");
+        schema.format = format.to_string();
+        Exported::new(&schema, testing::nullable_data(values))
+    }
+
+    fn run(
+        input: &Exported,
+        format: &str,
+        overflow: i32,
+        parse_error: i32,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let format = CString::new(format).unwrap();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_cast(
+                &input.schema,
+                &input.array,
+                format.as_ptr(),
+                overflow,
+                parse_error,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn cast_to(input: &Exported, format: &str) -> std::result::Result<Exported, ArrowUdfStatus> {
+        run(input, format, ARROW_UDF_CAST_ERROR, ARROW_UDF_CAST_ERROR)
+    }
+
+    #[test]
+    fn numbers_are_cast_keeping_nulls_and_the_name() {
+        let input = Exported::named("x", &[Some(1_i32), None, Some(-3)]);
+        let out = cast_to(&input, "g").unwrap();
+        assert_eq!(out.nullable_values::<f64>(), [Some(1.0), None, Some(-3.0)]);
+        out.with_array(|array| {
+            assert_eq!(array.data_type(), ArrowType::Float64);
+            assert_eq!(array.schema().name, "x");
+        });
+        // Floats are truncated towards zero.
+        let floats = Exported::primitive(&[2.9_f64, -2.9, 0.0]);
+        assert_eq!(cast_to(&floats, "s").unwrap().values::<i16>(), [2, -2, 0]);
+        assert_eq!(
+            cast_to(&floats, "b").unwrap().booleans(),
+            [Some(true), Some(true), Some(false)]
+        );
+        let booleans = Exported::boolean(&[true, false]);
+        assert_eq!(cast_to(&booleans, "C").unwrap().values::<u8>(), [1, 0]);
+        let dates = typed("tdD", &[Some(19_000_i32)]);
+        assert_eq!(cast_to(&dates, "l").unwrap().values::<i64>(), [19_000]);
+    }
+
+    #[test]
+    fn overflows_follow_their_policy() {
+        let input = Exported::primitive(&[1_i32, 300, -300]);
+        assert_eq!(
+            cast_to(&input, "c").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let nulls = run(&input, "c", ARROW_UDF_CAST_NULL, ARROW_UDF_CAST_ERROR).unwrap();
+        assert_eq!(nulls.nullable_values::<i8>(), [Some(1), None, None]);
+        let saturated = run(&input, "c", ARROW_UDF_CAST_SATURATE, ARROW_UDF_CAST_ERROR).unwrap();
+        assert_eq!(saturated.values::<i8>(), [1, i8::MAX, i8::MIN]);
+        let floats = Exported::primitive(&[f64::NAN, 1e300, -1.0]);
+        let saturated = run(&floats, "I", ARROW_UDF_CAST_SATURATE, ARROW_UDF_CAST_ERROR).unwrap();
+        assert_eq!(saturated.values::<u32>(), [0, u32::MAX, 0]);
+        let narrowed = run(&floats, "f", ARROW_UDF_CAST_NULL, ARROW_UDF_CAST_ERROR).unwrap();
+        assert_eq!(narrowed.nullable_values::<f32>()[1], None);
+    }
+
+    #[test]
+    fn text_is_parsed_and_formatted() {
+        let input = Exported::utf8(&[Some(" 42 "), None, Some("x"), Some("1000")]);
+        let out = run(&input, "c", ARROW_UDF_CAST_SATURATE, ARROW_UDF_CAST_NULL).unwrap();
+        assert_eq!(
+            out.nullable_values::<i8>(),
+            [Some(42), None, None, Some(i8::MAX)]
+        );
+        assert_eq!(
+            cast_to(&input, "i").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let booleans = Exported::utf8(&[Some("TRUE"), Some("false"), Some("yes")]);
+        let out = run(&booleans, "b", ARROW_UDF_CAST_ERROR, ARROW_UDF_CAST_NULL).unwrap();
+        assert_eq!(out.booleans(), [Some(true), Some(false), None]);
+        let numbers = Exported::nullable(&[Some(1.5_f64), None]);
+        assert_eq!(
+            cast_to(&numbers, "u").unwrap().strings(),
+            [Some("1.5".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn parse_errors_are_collected_by_row() {
+        let input = Exported::utf8(&[Some("1"), Some("x"), None]);
+        let collect = ArrowUdfExecOptions {
+            flags: udf::ARROW_UDF_FLAG_COLLECT_ERRORS,
+            ..ArrowUdfExecOptions::default()
+        };
+        let to = Schema::new(ArrowType::Int64, "");
+        let (schema, data) = input
+            .with_array(|array| cast(array, &to, &CastOptions::from_ffi(0, 0).unwrap(), &collect))
+            .unwrap();
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.child_values::<i64>(0), [Some(1), None, None]);
+        out.with_array(|array| {
+            let errors = array.child(1);
+            assert!(errors.utf8_value(1).unwrap().contains("not a valid"));
+            assert!(!errors.is_valid(0) && !errors.is_valid(2));
+        });
+    }
+
+    #[test]
+    fn temporal_values_are_rescaled() {
+        let seconds = typed("tss:", &[Some(-1_i64), Some(86_400), None]);
+        let dates = cast_to(&seconds, "tdD").unwrap();
+        assert_eq!(dates.nullable_values::<i32>(), [Some(-1), Some(1), None]);
+        let days = cast_to(&seconds, "tdm").unwrap();
+        assert_eq!(
+            days.nullable_values::<i64>(),
+            [Some(-86_400_000), Some(86_400_000), None]
+        );
+        let millis = cast_to(&dates, "tsm:").unwrap();
+        assert_eq!(
+            millis.nullable_values::<i64>(),
+            [Some(-86_400_000), Some(86_400_000), None]
+        );
+        let durations = typed("tDn", &[Some(-1_999_999_999_i64)]);
+        assert_eq!(cast_to(&durations, "tDs").unwrap().values::<i64>(), [-1]);
+        assert_eq!(
+            cast_to(&durations, "tss:").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let large = typed("tss:", &[Some(i64::MAX / 10)]);
+        assert_eq!(
+            cast_to(&large, "tsn:").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+
+    #[cfg(feature = "temporal")]
+    #[test]
+    fn dates_and_timestamps_round_trip_through_text() {
+        let timestamps = typed("tsm:+01:00", &[Some(1_706_686_200_250_i64), None]);
+        let text = cast_to(&timestamps, "u").unwrap();
+        assert_eq!(
+            text.strings(),
+            [Some("2024-01-31T08:30:00.250+01:00".to_string()), None]
+        );
+        let back = cast_to(&text, "tsm:+01:00").unwrap();
+        assert_eq!(
+            back.nullable_values::<i64>(),
+            [Some(1_706_686_200_250), None]
+        );
+        let input = Exported::utf8(&[Some("2024-01-31"), Some("31/01/2024")]);
+        let dates = run(&input, "tdD", ARROW_UDF_CAST_ERROR, ARROW_UDF_CAST_NULL).unwrap();
+        assert_eq!(dates.nullable_values::<i32>(), [Some(19_753), None]);
+        assert_eq!(
+            cast_to(&dates, "u").unwrap().strings(),
+            [Some("2024-01-31".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn invalid_policies_and_types_fail() {
+        let input = Exported::primitive(&[1_i32]);
+        assert_eq!(
+            run(&input, "l", 7, ARROW_UDF_CAST_ERROR).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            run(&input, "l", ARROW_UDF_CAST_ERROR, ARROW_UDF_CAST_SATURATE).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            cast_to(&input, "not a format").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        assert_eq!(
+            cast_to(&input, "tiM").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let nulls = Exported::nullable(&[Some(1_i32), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let to = Schema::new(ArrowType::Int64, "");
+        let failed = nulls.with_array(|array| {
+            cast(array, &to, &CastOptions::from_ffi(0, 0).unwrap(), &options).err()
+        });
+        assert_eq!(failed, Some(Error::NullValue));
+    }
+}
@@ -0,0 +1,573 @@
+//! Validation of the input schemas of functions, before executing them.
+//!
+//! Hosts pushing a function down to this library can check whether the
+//! schema of its input is supported while planning the query, instead of
+//! failing when the query is executed. Functions are named by their entry
+//! point, with or without the `arrow_udf_` prefix, or by an aggregate spec
+//! like `approx_quantile(0.9)`. Functions receiving more than one array are
+//! checked with a struct schema with a field per array, in the order of the
+//! arguments.
+
+use std::ffi::{c_char, CStr};
+
+use crate::aggregate::AggregateSpec;
+use crate::duplicate;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export;
+use crate::ffi::ArrowCDataInterfaceSchema;
+use crate::hash;
+use crate::histogram;
+use crate::if_else;
+use crate::kernels::KERNEL_SIGNATURES;
+use crate::registry::{self, OutputType};
+use crate::run_end;
+use crate::schema::{ArrowType, Schema};
+use crate::stats;
+use crate::types::{with_native_type, NativeType};
+#[cfg(feature = "strings")]
+use crate::udtf;
+
+type Check = fn(&Schema) -> bool;
+
+fn any(_: &Schema) -> bool {
+    true
+}
+
+fn numeric(schema: &Schema) -> bool {
+    with_native_type!(schema.data_type, T => T::ARROW_TYPE == schema.data_type, _ => false)
+}
+
+fn boolean(schema: &Schema) -> bool {
+    schema.data_type == ArrowType::Boolean
+}
+
+fn float(schema: &Schema) -> bool {
+    matches!(schema.data_type, ArrowType::Float32 | ArrowType::Float64)
+}
+
+fn float64(schema: &Schema) -> bool {
+    schema.data_type == ArrowType::Float64
+}
+
+#[cfg(feature = "temporal")]
+fn temporal(schema: &Schema) -> bool {
+    matches!(
+        schema.data_type,
+        ArrowType::Date32 | ArrowType::Date64 | ArrowType::Timestamp(_)
+    )
+}
+
+#[cfg(feature = "temporal")]
+fn interval(schema: &Schema) -> bool {
+    matches!(schema.data_type, ArrowType::Interval(_))
+}
+
+#[cfg(feature = "strings")]
+fn utf8(schema: &Schema) -> bool {
+    schema.data_type == ArrowType::Utf8
+}
+
+/// Primitive arrays, including the temporal ones.
+fn primitive(schema: &Schema) -> bool {
+    let data_type = schema.data_type.physical_type();
+    with_native_type!(data_type, T => T::ARROW_TYPE == data_type, _ => false)
+}
+
+fn run_end_encoded(schema: &Schema) -> bool {
+    schema.data_type == ArrowType::RunEndEncoded
+        && matches!(&schema.children[..], [run_ends, values]
+            if matches!(run_ends.data_type, ArrowType::Int16 | ArrowType::Int32 | ArrowType::Int64)
+                && primitive(values))
+}
+
+/// Arrays `arrow_udf_cast` casts from: numbers, Boolean, Utf8, and the
+/// temporal types other than intervals.
+fn castable(schema: &Schema) -> bool {
+    !matches!(schema.data_type, ArrowType::Interval(_))
+        && (primitive(schema) || matches!(schema.data_type, ArrowType::Boolean | ArrowType::Utf8))
+}
+
+fn hashable(schema: &Schema) -> bool {
+    hash::is_hashable(schema.data_type)
+}
+
+/// `n` arrays passing `check`, as the fields of a struct schema.
+fn arrays(schema: &Schema, n: usize, check: Check) -> bool {
+    schema.data_type == ArrowType::Struct
+        && schema.children.len() == n
+        && schema.children.iter().all(check)
+}
+
+/// Two numeric arrays of the same type, as the fields of a struct schema.
+fn numeric_pair(schema: &Schema) -> bool {
+    arrays(schema, 2, numeric) && schema.children[0].data_type == schema.children[1].data_type
+}
+
+/// A temporal array and an Interval array, as the fields of a struct schema.
+#[cfg(feature = "temporal")]
+fn timestamp_and_interval(schema: &Schema) -> bool {
+    schema.data_type == ArrowType::Struct
+        && matches!(
+            &schema.children[..],
+            [array, intervals] if temporal(array) && interval(intervals)
+        )
+}
+
+/// Functions with an input check and an output type of their own, other
+/// than the kernels and the aggregates.
+static CHECKS: &[(&str, Check, OutputType)] = &[
+    (
+        "distances",
+        |schema| schema.data_type == ArrowType::Int64,
+        OutputType::Fixed(ArrowType::Int64),
+    ),
+    (
+        "sum_distances",
+        |schema| schema.data_type == ArrowType::Int64,
+        OutputType::Fixed(ArrowType::Int64),
+    ),
+    ("quantile", numeric, OutputType::Fixed(ArrowType::Float64)),
+    ("argmin", numeric, OutputType::Fixed(ArrowType::Int64)),
+    ("argmax", numeric, OutputType::Fixed(ArrowType::Int64)),
+    (
+        "histogram",
+        numeric,
+        OutputType::Infer(histogram::output_schema),
+    ),
+    (
+        "column_stats",
+        |schema| {
+            primitive(schema)
+                || matches!(
+                    schema.data_type,
+                    ArrowType::Utf8 | ArrowType::Binary | ArrowType::LargeBinary
+                )
+        },
+        OutputType::Infer(stats::output_schema),
+    ),
+    (
+        "rolling_mean",
+        numeric,
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    ("rolling_min", numeric, OutputType::SameAsInput),
+    ("rolling_max", numeric, OutputType::SameAsInput),
+    ("shift", numeric, OutputType::SameAsInput),
+    (
+        "run_end_encode",
+        primitive,
+        OutputType::Infer(run_end::encoded_schema),
+    ),
+    (
+        "run_end_decode",
+        run_end_encoded,
+        OutputType::Infer(run_end::decoded_schema),
+    ),
+    ("cast", castable, OutputType::Arguments),
+    ("fill_null", numeric, OutputType::SameAsInput),
+    ("forward_fill", numeric, OutputType::SameAsInput),
+    (
+        "linear_interpolate",
+        numeric,
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    ("jitter", numeric, OutputType::Fixed(ArrowType::Float64)),
+    (
+        "sample_bernoulli",
+        any,
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    ("sort_indices", numeric, OutputType::Fixed(ArrowType::Int64)),
+    ("rank", numeric, OutputType::Fixed(ArrowType::Int64)),
+    ("topk", numeric, OutputType::Arguments),
+    ("hash64", hashable, OutputType::Fixed(ArrowType::UInt64)),
+    (
+        "array_fingerprint",
+        |schema| primitive(schema) || boolean(schema) || hashable(schema),
+        OutputType::Infer(hash::fingerprint_schema),
+    ),
+    (
+        "is_duplicate",
+        |schema| duplicate::is_supported(schema.data_type),
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    (
+        "bloom_build",
+        hashable,
+        OutputType::Fixed(ArrowType::Binary),
+    ),
+    (
+        "bloom_probe",
+        hashable,
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    #[cfg(feature = "strings")]
+    ("utf8_length", utf8, OutputType::Fixed(ArrowType::Int32)),
+    #[cfg(feature = "strings")]
+    ("upper", utf8, OutputType::Fixed(ArrowType::Utf8)),
+    #[cfg(feature = "strings")]
+    ("lower", utf8, OutputType::Fixed(ArrowType::Utf8)),
+    #[cfg(feature = "strings")]
+    ("substring", utf8, OutputType::Fixed(ArrowType::Utf8)),
+    #[cfg(feature = "strings")]
+    ("contains", utf8, OutputType::Fixed(ArrowType::Boolean)),
+    #[cfg(feature = "strings")]
+    ("regex_match", utf8, OutputType::Fixed(ArrowType::Boolean)),
+    #[cfg(feature = "strings")]
+    ("regex_extract", utf8, OutputType::Fixed(ArrowType::Utf8)),
+    #[cfg(feature = "strings")]
+    ("split", utf8, OutputType::Infer(udtf::split_schema)),
+    #[cfg(all(feature = "nested", feature = "strings"))]
+    (
+        "map_get",
+        |schema| schema.data_type == ArrowType::Map && schema.children[0].children.iter().all(utf8),
+        OutputType::Fixed(ArrowType::Utf8),
+    ),
+    #[cfg(feature = "temporal")]
+    ("date_trunc", temporal, OutputType::SameAsInput),
+    #[cfg(feature = "temporal")]
+    ("extract", temporal, OutputType::Fixed(ArrowType::Int64)),
+    #[cfg(feature = "temporal")]
+    ("timestamp_add", temporal, OutputType::SameAsInput),
+    #[cfg(feature = "temporal")]
+    (
+        "timestamp_add_interval",
+        timestamp_and_interval,
+        OutputType::Infer(registry::first_field),
+    ),
+    #[cfg(feature = "temporal")]
+    (
+        "timestamp_sub_interval",
+        timestamp_and_interval,
+        OutputType::Infer(registry::first_field),
+    ),
+    #[cfg(feature = "temporal")]
+    (
+        "duration_cast",
+        |schema| matches!(schema.data_type, ArrowType::Duration(_)),
+        OutputType::Arguments,
+    ),
+    (
+        "haversine",
+        |schema| {
+            let points = Schema::new(ArrowType::Struct, "").with_children(vec![
+                Schema::new(ArrowType::Float64, "lat"),
+                Schema::new(ArrowType::Float64, "lon"),
+            ]);
+            schema.is_compatible_with(&points)
+        },
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    (
+        "haversine_arrays",
+        |schema| arrays(schema, 2, float64),
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    (
+        "pairwise_euclidean",
+        |schema| arrays(schema, 2, float64),
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    (
+        "dot",
+        |schema| arrays(schema, 2, numeric),
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    (
+        "weighted_sum",
+        |schema| arrays(schema, 2, numeric),
+        OutputType::Fixed(ArrowType::Float64),
+    ),
+    (
+        "is_close",
+        |schema| arrays(schema, 2, numeric),
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    (
+        "fma",
+        |schema| {
+            arrays(schema, 3, float)
+                && schema
+                    .children
+                    .iter()
+                    .all(|child| child.data_type == schema.children[0].data_type)
+        },
+        OutputType::Infer(registry::first_field),
+    ),
+    (
+        "add",
+        numeric_pair,
+        OutputType::Infer(registry::first_field),
+    ),
+    (
+        "sub",
+        numeric_pair,
+        OutputType::Infer(registry::first_field),
+    ),
+    (
+        "mul",
+        numeric_pair,
+        OutputType::Infer(registry::first_field),
+    ),
+    (
+        "div",
+        numeric_pair,
+        OutputType::Infer(registry::first_field),
+    ),
+    ("add_scalar", numeric, OutputType::SameAsInput),
+    ("sub_scalar", numeric, OutputType::SameAsInput),
+    ("mul_scalar", numeric, OutputType::SameAsInput),
+    ("div_scalar", numeric, OutputType::SameAsInput),
+    ("eq", numeric_pair, OutputType::Fixed(ArrowType::Boolean)),
+    ("neq", numeric_pair, OutputType::Fixed(ArrowType::Boolean)),
+    ("lt", numeric_pair, OutputType::Fixed(ArrowType::Boolean)),
+    ("lte", numeric_pair, OutputType::Fixed(ArrowType::Boolean)),
+    ("gt", numeric_pair, OutputType::Fixed(ArrowType::Boolean)),
+    ("gte", numeric_pair, OutputType::Fixed(ArrowType::Boolean)),
+    ("eq_scalar", numeric, OutputType::Fixed(ArrowType::Boolean)),
+    ("neq_scalar", numeric, OutputType::Fixed(ArrowType::Boolean)),
+    ("lt_scalar", numeric, OutputType::Fixed(ArrowType::Boolean)),
+    ("lte_scalar", numeric, OutputType::Fixed(ArrowType::Boolean)),
+    ("gt_scalar", numeric, OutputType::Fixed(ArrowType::Boolean)),
+    ("gte_scalar", numeric, OutputType::Fixed(ArrowType::Boolean)),
+    (
+        "and",
+        |schema| arrays(schema, 2, boolean),
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    (
+        "or",
+        |schema| arrays(schema, 2, boolean),
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    (
+        "xor",
+        |schema| arrays(schema, 2, boolean),
+        OutputType::Fixed(ArrowType::Boolean),
+    ),
+    ("not", boolean, OutputType::Fixed(ArrowType::Boolean)),
+    (
+        "if_else",
+        |schema| {
+            schema.data_type == ArrowType::Struct
+                && matches!(&schema.children[..], [cond, then, otherwise]
+                    if boolean(cond) && numeric(then) && then.data_type == otherwise.data_type)
+        },
+        OutputType::Infer(if_else::output_schema),
+    ),
+];
+
+/// The type of a schema, with the types of its children, like
+/// `Struct<lat: Float64, lon: Float64>`.
+pub(crate) fn describe(schema: &Schema) -> String {
+    if schema.children.is_empty() {
+        return format!("{:?}", schema.data_type);
+    }
+    let children: Vec<String> = schema
+        .children
+        .iter()
+        .map(|child| format!("{}: {}", child.name, describe(child)))
+        .collect();
+    format!("{:?}<{}>", schema.data_type, children.join(", "))
+}
+
+/// Fail with `Error::UnsupportedType` if `function` doesn't support inputs
+/// described by `schema`, and with `Error::InvalidArgument` if there is no
+/// such function.
+pub fn check_schema(function: &str, schema: &Schema) -> Result<()> {
+    let name = function.strip_prefix("arrow_udf_").unwrap_or(function);
+    let unsupported = || Error::UnsupportedType(format!("{name} of {} arrays", describe(schema)));
+    if let Some((_, check, _)) = CHECKS.iter().find(|(check_name, ..)| *check_name == name) {
+        return check(schema).then_some(()).ok_or_else(unsupported);
+    }
+    let kernel = KERNEL_SIGNATURES
+        .iter()
+        .find(|(kernel, _)| kernel.strip_prefix("arrow_udf_") == Some(name));
+    if let Some((_, signatures)) = kernel {
+        // Kernels also receive the values of run-end encoded arrays.
+        let value_type = match schema.data_type {
+            ArrowType::RunEndEncoded if schema.children.len() == 2 => schema.children[1].data_type,
+            data_type => data_type,
+        };
+        return signatures
+            .iter()
+            .any(|(input, _)| *input == value_type)
+            .then_some(())
+            .ok_or_else(unsupported);
+    }
+    let spec = AggregateSpec::parse(function)
+        .map_err(|_| Error::InvalidArgument(format!("unknown function {function:?}")))?;
+    spec.accumulator(schema.data_type).map(|_| ())
+}
+
+/// The schema of the result of the function `name`, without the
+/// `arrow_udf_` prefix, for inputs described by `schema`, already checked by
+/// `check_schema`.
+pub(crate) fn output_schema(name: &str, schema: &Schema) -> Result<Schema> {
+    if let Some((.., output)) = CHECKS.iter().find(|(check_name, ..)| *check_name == name) {
+        return output.schema(name, schema);
+    }
+    let kernel = KERNEL_SIGNATURES
+        .iter()
+        .find(|(kernel, _)| kernel.strip_prefix("arrow_udf_") == Some(name));
+    if let Some((_, signatures)) = kernel {
+        // Run-end encoded inputs, having a single run, give a run-end
+        // encoded result.
+        let (value_type, encoded) = match schema.data_type {
+            ArrowType::RunEndEncoded => (schema.children[1].data_type, true),
+            data_type => (data_type, false),
+        };
+        let output = signatures
+            .iter()
+            .find(|(input, _)| *input == value_type)
+            .map(|(_, output)| output(&schema.name))
+            .expect("checked by check_schema");
+        // Rows of constant inputs are repeated in a struct array instead.
+        return Ok(match encoded && output.data_type != ArrowType::Struct {
+            true => export::constant_schema(output.data_type, &schema.name),
+            false => output,
+        });
+    }
+    let spec = AggregateSpec::parse(name)?;
+    let accumulator = spec.accumulator(schema.data_type)?;
+    Ok(Schema::new(
+        accumulator.output_type(),
+        &spec.column_name(&schema.name),
+    ))
+}
+
+/// Check whether `function` supports inputs described by `schema`, without
+/// executing it, so hosts can decide at plan time whether to push it down.
+/// Functions are named by their entry point, with or without the
+/// `arrow_udf_` prefix, or by an aggregate spec. The inputs of functions
+/// receiving several arrays are described by a struct schema with a field
+/// per array.
+///
+/// Returns `ArrowUdfStatus::Ok` if the schema is supported,
+/// `ArrowUdfStatus::UnsupportedType` if it isn't, and
+/// `ArrowUdfStatus::InvalidArgument` if the function is unknown.
+///
+/// # Safety
+///
+/// `function` must be a valid null-terminated string, and `schema` must
+/// point to a valid Arrow C Data Interface schema.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_check_schema(
+    function: *const c_char,
+    schema: *const ArrowCDataInterfaceSchema,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let function = CStr::from_ptr(function).to_string_lossy();
+        let schema = Schema::from_ffi(&*schema)?;
+        check_schema(&function, &schema)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export;
+
+    fn call(function: &CStr, schema: &Schema) -> ArrowUdfStatus {
+        let mut exported = export::export_schema(schema);
+        let status = unsafe { arrow_udf_check_schema(function.as_ptr(), &exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        status
+    }
+
+    fn fields(types: &[ArrowType]) -> Schema {
+        let children = types.iter().enumerate();
+        Schema::new(ArrowType::Struct, "").with_children(
+            children
+                .map(|(i, t)| Schema::new(*t, &i.to_string()))
+                .collect(),
+        )
+    }
+
+    #[cfg(feature = "strings")]
+    #[test]
+    fn functions_by_entry_point_or_name() {
+        let int64 = Schema::new(ArrowType::Int64, "x");
+        let utf8 = Schema::new(ArrowType::Utf8, "x");
+        for function in [
+            c"quantile",
+            c"arrow_udf_quantile",
+            c"arrow_udf_negate",
+            c"sum",
+        ] {
+            assert_eq!(call(function, &int64), ArrowUdfStatus::Ok, "{function:?}");
+            assert_eq!(
+                call(function, &utf8),
+                ArrowUdfStatus::UnsupportedType,
+                "{function:?}"
+            );
+        }
+        assert_eq!(call(c"upper", &utf8), ArrowUdfStatus::Ok);
+        assert_eq!(call(c"approx_quantile(0.9)", &int64), ArrowUdfStatus::Ok);
+        assert_eq!(call(c"nothing", &int64), ArrowUdfStatus::InvalidArgument);
+        assert_eq!(
+            call(c"approx_quantile(2)", &int64),
+            ArrowUdfStatus::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn kernels_of_run_end_encoded_arrays() {
+        let ree = Schema::new(ArrowType::RunEndEncoded, "x").with_children(vec![
+            Schema::new(ArrowType::Int32, "run_ends"),
+            Schema::new(ArrowType::Float64, "values"),
+        ]);
+        assert_eq!(call(c"arrow_udf_abs", &ree), ArrowUdfStatus::Ok);
+        assert_eq!(call(c"to_float64", &ree), ArrowUdfStatus::Ok);
+    }
+
+    #[test]
+    fn several_arrays_as_struct_fields() {
+        let float64 = ArrowType::Float64;
+        assert_eq!(
+            call(c"dot", &fields(&[ArrowType::Int8, float64])),
+            ArrowUdfStatus::Ok
+        );
+        assert_eq!(
+            call(c"dot", &fields(&[float64])),
+            ArrowUdfStatus::UnsupportedType
+        );
+        assert_eq!(
+            call(c"fma", &fields(&[float64, float64, float64])),
+            ArrowUdfStatus::Ok
+        );
+        assert_eq!(
+            call(c"fma", &fields(&[float64, ArrowType::Float32, float64])),
+            ArrowUdfStatus::UnsupportedType
+        );
+        let points = fields(&[float64, float64]);
+        assert_eq!(call(c"haversine", &points), ArrowUdfStatus::UnsupportedType);
+        match check_schema("haversine", &points) {
+            Err(Error::UnsupportedType(message)) => assert_eq!(
+                message,
+                "haversine of Struct<0: Float64, 1: Float64> arrays"
+            ),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "temporal")]
+    #[test]
+    fn intervals_and_durations() {
+        let timestamp = ArrowType::Timestamp(crate::schema::TimeUnit::Second);
+        let interval = ArrowType::Interval(crate::schema::IntervalUnit::MonthDayNano);
+        let pair = fields(&[timestamp, interval]);
+        assert_eq!(call(c"timestamp_add_interval", &pair), ArrowUdfStatus::Ok);
+        assert_eq!(call(c"timestamp_sub_interval", &pair), ArrowUdfStatus::Ok);
+        assert_eq!(
+            call(c"timestamp_add_interval", &fields(&[timestamp, timestamp])),
+            ArrowUdfStatus::UnsupportedType
+        );
+        let duration = Schema::new(ArrowType::Duration(crate::schema::TimeUnit::Second), "d");
+        assert_eq!(call(c"duration_cast", &duration), ArrowUdfStatus::Ok);
+        assert_eq!(
+            call(c"duration_cast", &Schema::new(ArrowType::Int64, "d")),
+            ArrowUdfStatus::UnsupportedType
+        );
+    }
+}
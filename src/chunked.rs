@@ -0,0 +1,226 @@
+//! Several arrays of the same type seen as a single one, without copying.
+//!
+//! Inputs often come in chunks, for example the batches of a stream, and
+//! most kernels don't care where a chunk ends. `ChunkedArray` keeps the
+//! views of the chunks and where each of them starts, so rows can be
+//! addressed in the logical sequence, while iteration and reductions
+//! process one chunk at a time, over its contiguous values.
+
+use std::ops::Range;
+
+use crate::array::ArrowArray;
+use crate::bitmap::Bitmap;
+use crate::error::{Error, Result};
+use crate::exec;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::NativeType;
+
+/// The concatenation of `chunks`, which all have the type of `schema`.
+pub struct ChunkedArray<'a> {
+    schema: &'a Schema,
+    chunks: Vec<ArrowArray<'a>>,
+    /// Row of the logical sequence where every chunk starts, followed by the
+    /// total length.
+    starts: Vec<usize>,
+}
+
+impl<'a> ChunkedArray<'a> {
+    pub fn new(schema: &'a Schema, chunks: Vec<ArrowArray<'a>>) -> Result<ChunkedArray<'a>> {
+        if let Some(chunk) = chunks
+            .iter()
+            .find(|chunk| chunk.data_type() != schema.data_type)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "chunk of type {:?} in a chunked array of {:?}",
+                chunk.data_type(),
+                schema.data_type
+            )));
+        }
+        let mut starts = Vec::with_capacity(chunks.len() + 1);
+        starts.push(0);
+        for chunk in &chunks {
+            starts.push(starts.last().unwrap() + chunk.len());
+        }
+        Ok(ChunkedArray {
+            schema,
+            chunks,
+            starts,
+        })
+    }
+
+    pub fn schema(&self) -> &'a Schema {
+        self.schema
+    }
+
+    pub fn chunks(&self) -> &[ArrowArray<'a>] {
+        &self.chunks
+    }
+
+    pub fn len(&self) -> usize {
+        *self.starts.last().unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.null_count()).sum()
+    }
+
+    /// The chunk containing the row `i`, and the position of the row in it.
+    pub fn locate(&self, i: usize) -> (usize, usize) {
+        assert!(i < self.len(), "element {i} out of bounds");
+        // The last chunk starting at or before `i`, skipping empty chunks.
+        let chunk = self.starts.partition_point(|start| *start <= i) - 1;
+        (chunk, i - self.starts[chunk])
+    }
+
+    pub fn is_valid(&self, i: usize) -> bool {
+        let (chunk, i) = self.locate(i);
+        self.chunks[chunk].is_valid(i)
+    }
+
+    /// The value of the row `i` of a primitive chunked array, whether it's
+    /// valid or not.
+    pub fn value<T: NativeType>(&self, i: usize) -> T {
+        let (chunk, i) = self.locate(i);
+        self.chunks[chunk].values::<T>()[i]
+    }
+
+    /// The values of every chunk of a primitive chunked array, with their
+    /// offsets applied.
+    pub fn values<T: NativeType>(&self) -> impl Iterator<Item = &'a [T]> + '_ {
+        self.chunks.iter().map(|chunk| chunk.values::<T>())
+    }
+
+    /// The values of a primitive chunked array, `None` for nulls.
+    pub fn iter<T: NativeType>(&self) -> impl Iterator<Item = Option<T>> + '_ {
+        self.chunks.iter().flat_map(|chunk| {
+            let values = chunk.values::<T>();
+            let validity = chunk.validity().filter(|_| chunk.null_count() > 0);
+            (0..values.len()).map(move |i| {
+                validity
+                    .is_none_or(|validity| validity.is_set(i))
+                    .then_some(values[i])
+            })
+        })
+    }
+
+    /// Reduce the values of a primitive chunked array with `exec::reduce`,
+    /// one chunk after the other. `fold` receives the values of a chunk, its
+    /// validity if it has nulls, and the rows of the batch to fold, and
+    /// `combine` also combines the results of the chunks.
+    pub fn reduce<T, A, F, C>(
+        &self,
+        options: &ArrowUdfExecOptions,
+        init: A,
+        fold: F,
+        combine: C,
+    ) -> Result<A>
+    where
+        T: NativeType,
+        A: Clone + Send + Sync,
+        F: Fn(A, &[T], Option<Bitmap>, Range<usize>) -> A + Sync,
+        C: Fn(A, A) -> A,
+    {
+        let mut result = init.clone();
+        for chunk in &self.chunks {
+            let values = chunk.values::<T>();
+            let validity = chunk.validity().filter(|_| chunk.null_count() > 0);
+            let part = exec::reduce(
+                values.len(),
+                options,
+                init.clone(),
+                |acc, rows| fold(acc, values, validity, rows),
+                &combine,
+            )?;
+            result = combine(result, part);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ArrowType;
+    use crate::testing::Exported;
+
+    fn chunks() -> Vec<Exported> {
+        vec![
+            Exported::nullable(&[Some(1_i32), None]),
+            Exported::primitive::<i32>(&[]),
+            Exported::primitive(&[3_i32, 4, 5]),
+        ]
+    }
+
+    fn views<'a>(schema: &'a Schema, chunks: &'a [Exported]) -> Vec<ArrowArray<'a>> {
+        let views = chunks
+            .iter()
+            .map(|chunk| unsafe { ArrowArray::new(schema, &chunk.array) });
+        views.collect()
+    }
+
+    #[test]
+    fn rows_are_addressed_across_chunks() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        let chunks = chunks();
+        let array = ChunkedArray::new(&schema, views(&schema, &chunks)).unwrap();
+        assert_eq!((array.len(), array.null_count()), (5, 1));
+        assert_eq!(array.chunks().len(), 3);
+        // The empty chunk is skipped.
+        assert_eq!(array.locate(2), (2, 0));
+        assert_eq!(array.locate(4), (2, 2));
+        assert!(!array.is_valid(1));
+        assert_eq!(array.value::<i32>(3), 4);
+        let values: Vec<_> = array.values::<i32>().map(<[i32]>::len).collect();
+        assert_eq!(values, [2, 0, 3]);
+        let items: Vec<_> = array.iter::<i32>().collect();
+        assert_eq!(items, [Some(1), None, Some(3), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn reductions_over_every_chunk() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        let chunks = chunks();
+        let array = ChunkedArray::new(&schema, views(&schema, &chunks)).unwrap();
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            num_threads: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let sum = array.reduce(
+            &options,
+            0,
+            |sum, values: &[i32], validity, rows| {
+                let valid = |i: &usize| validity.is_none_or(|validity| validity.is_set(*i));
+                sum + rows.filter(valid).map(|i| values[i]).sum::<i32>()
+            },
+            |a, b| a + b,
+        );
+        assert_eq!(sum, Ok(13));
+    }
+
+    #[test]
+    fn chunks_must_have_the_same_type() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        let other = Schema::new(ArrowType::Int64, "x");
+        let int64 = Exported::primitive(&[1_i64]);
+        let chunk = unsafe { ArrowArray::new(&other, &int64.array) };
+        assert!(matches!(
+            ChunkedArray::new(&schema, vec![chunk]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "element 5 out of bounds")]
+    fn rows_must_be_in_bounds() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        let chunks = chunks();
+        let array = ChunkedArray::new(&schema, views(&schema, &chunks)).unwrap();
+        array.locate(5);
+    }
+}
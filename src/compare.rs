@@ -0,0 +1,555 @@
+//! Element-wise comparisons of two numeric operands, `eq`, `neq`, `lt`,
+//! `lte`, `gt` and `gte`, as Boolean arrays hosts can filter with.
+//!
+//! The operands are broadcast like the ones of `arith`: arrays of the same
+//! type and length, or of a single element, and `f64` scalars converted to
+//! the type of the arrays. Nulls of any of the operands are null in the
+//! result.
+//!
+//! Floats are compared like IEEE 754: NaN isn't equal to, less or greater
+//! than anything, so only `neq` is true for it, and -0.0 is equal to 0.0.
+//! With the `ARROW_UDF_FLAG_EQUAL_NAN` flag, NaN is equal to NaN and
+//! greater than any other value, like in the order of `sort`.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::arith::{self, Operand, Values};
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::isclose::ARROW_UDF_FLAG_EQUAL_NAN;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, Numeric};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl ComparisonOp {
+    /// Whether two values in the order `ordering`, or unordered if it's
+    /// `None`, satisfy the comparison.
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        let Some(ordering) = ordering else {
+            return self == ComparisonOp::NotEq;
+        };
+        match self {
+            ComparisonOp::Eq => ordering.is_eq(),
+            ComparisonOp::NotEq => ordering.is_ne(),
+            ComparisonOp::Lt => ordering.is_lt(),
+            ComparisonOp::LtEq => ordering.is_le(),
+            ComparisonOp::Gt => ordering.is_gt(),
+            ComparisonOp::GtEq => ordering.is_ge(),
+        }
+    }
+}
+
+fn compute<T: Numeric + PartialOrd>(
+    op: ComparisonOp,
+    left: Operand,
+    right: Operand,
+    len: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let (a, b) = (
+        Values::<T>::new(left, len, options)?,
+        Values::<T>::new(right, len, options)?,
+    );
+    let equal_nan = options.flags & ARROW_UDF_FLAG_EQUAL_NAN != 0;
+    let mut out: Vec<Option<bool>> = vec![None; len];
+    exec::map(&mut out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            let (Some(a), Some(b)) = (a.get(i), b.get(i)) else {
+                continue;
+            };
+            let ordering = match a.partial_cmp(&b) {
+                None if equal_nan => Some(a.is_nan().cmp(&b.is_nan())),
+                ordering => ordering,
+            };
+            *out = Some(op.holds(ordering));
+        }
+        Ok(())
+    })?;
+    let (mut values, mut valid) = (
+        BitmapBuilder::with_capacity(len),
+        BitmapBuilder::with_capacity(len),
+    );
+    for out in &out {
+        values.push(out.unwrap_or_default());
+        valid.push(out.is_some());
+    }
+    let null_count = out.iter().filter(|out| out.is_none()).count();
+    let data = ArrayData::primitive(values.finish(), len);
+    Ok(match null_count {
+        0 => data,
+        _ => data.with_validity(Some(valid.finish()), null_count),
+    })
+}
+
+/// Whether `left op right` for every row, as a Boolean array with the name
+/// of the first array.
+pub fn compare(
+    op: ComparisonOp,
+    left: Operand,
+    right: Operand,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let (len, data_type, name) = arith::broadcast(left, right)?;
+    let data = with_native_type!(data_type, T => {
+        compute::<T>(op, left, right, len, options)?
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "comparison of {data_type:?} arrays"
+    ))));
+    Ok((Schema::new(ArrowType::Boolean, &name), data))
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn arrays_ffi(
+    op: ComparisonOp,
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        let (out, data) = compare(op, Operand::Array(&a), Operand::Array(&b), &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+unsafe fn scalar_ffi(
+    op: ComparisonOp,
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = compare(
+            op,
+            Operand::Array(&array),
+            Operand::Scalar(scalar),
+            &options,
+        )?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Whether every element of a numeric array is equal to the element of
+/// another one of the same type, as a Boolean array. The arrays have the
+/// same length, or one of them has a single element, which is broadcast.
+/// Nulls of any of the arrays are null in the result.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, and `b_schema` and `b_array`, must point to
+/// valid Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_eq(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::Eq;
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, options, out_schema, out_array,
+    )
+}
+
+/// Whether `a != b` for every element, like `arrow_udf_eq`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_neq(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::NotEq;
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, options, out_schema, out_array,
+    )
+}
+
+/// Whether `a < b` for every element, like `arrow_udf_eq`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_lt(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::Lt;
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, options, out_schema, out_array,
+    )
+}
+
+/// Whether `a <= b` for every element, like `arrow_udf_eq`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_lte(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::LtEq;
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, options, out_schema, out_array,
+    )
+}
+
+/// Whether `a > b` for every element, like `arrow_udf_eq`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_gt(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::Gt;
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, options, out_schema, out_array,
+    )
+}
+
+/// Whether `a >= b` for every element, like `arrow_udf_eq`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_gte(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::GtEq;
+    arrays_ffi(
+        op, a_schema, a_array, b_schema, b_array, options, out_schema, out_array,
+    )
+}
+
+/// Whether every element of a numeric array is equal to `scalar`, converted
+/// to the type of the array like the scalars of `arrow_udf_add_scalar`, as
+/// a Boolean array. Nulls are null in the result.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_eq_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::Eq;
+    scalar_ffi(op, schema, array, scalar, options, out_schema, out_array)
+}
+
+/// Whether every element is `!= scalar`, like `arrow_udf_eq_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_neq_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::NotEq;
+    scalar_ffi(op, schema, array, scalar, options, out_schema, out_array)
+}
+
+/// Whether every element is `< scalar`, like `arrow_udf_eq_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_lt_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::Lt;
+    scalar_ffi(op, schema, array, scalar, options, out_schema, out_array)
+}
+
+/// Whether every element is `<= scalar`, like `arrow_udf_eq_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_lte_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::LtEq;
+    scalar_ffi(op, schema, array, scalar, options, out_schema, out_array)
+}
+
+/// Whether every element is `> scalar`, like `arrow_udf_eq_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_gt_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::Gt;
+    scalar_ffi(op, schema, array, scalar, options, out_schema, out_array)
+}
+
+/// Whether every element is `>= scalar`, like `arrow_udf_eq_scalar`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_eq_scalar`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_gte_scalar(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    scalar: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let op = ComparisonOp::GtEq;
+    scalar_ffi(op, schema, array, scalar, options, out_schema, out_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn lt(a: &Exported, b: &Exported) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_lt(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn eq_scalar(input: &Exported, scalar: f64) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_eq_scalar(
+                &input.schema,
+                &input.array,
+                scalar,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn with_flags(
+        op: ComparisonOp,
+        input: &Exported,
+        scalar: f64,
+        flags: u32,
+    ) -> Vec<Option<bool>> {
+        let options = ArrowUdfExecOptions {
+            flags,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| {
+                compare(op, Operand::Array(array), Operand::Scalar(scalar), &options)
+            })
+            .unwrap();
+        Exported::new(&schema, data).booleans()
+    }
+
+    #[test]
+    fn arrays_are_compared_into_booleans_with_their_nulls() {
+        let a = Exported::named("a", &[Some(1_i64), None, Some(3), Some(4)]);
+        let b = Exported::named("b", &[Some(2_i64), Some(2), None, Some(4)]);
+        let out = lt(&a, &b).unwrap();
+        assert_eq!(out.booleans(), [Some(true), None, None, Some(false)]);
+        out.with_array(|array| {
+            assert_eq!(array.data_type(), ArrowType::Boolean);
+            assert_eq!(array.schema().name, "a");
+        });
+        let one = Exported::primitive(&[3_i64]);
+        assert_eq!(
+            lt(&one, &b).unwrap().booleans(),
+            [Some(false), Some(false), None, Some(true)]
+        );
+        assert_eq!(
+            eq_scalar(&a, 3.0).unwrap().booleans(),
+            [Some(false), None, Some(true), Some(false)]
+        );
+        let input = Exported::primitive(&[1_u8, 2, 3]);
+        let ops = [
+            (ComparisonOp::NotEq, [true, false, true]),
+            (ComparisonOp::LtEq, [true, true, false]),
+            (ComparisonOp::Gt, [false, false, true]),
+            (ComparisonOp::GtEq, [false, true, true]),
+        ];
+        for (op, expected) in ops {
+            let expected: Vec<_> = expected.into_iter().map(Some).collect();
+            assert_eq!(with_flags(op, &input, 2.0, 0), expected);
+        }
+    }
+
+    #[test]
+    fn nan_is_only_equal_to_nan_with_the_flag() {
+        let input = Exported::primitive(&[f64::NAN, 1.0, -0.0]);
+        assert_eq!(
+            with_flags(ComparisonOp::Eq, &input, f64::NAN, 0),
+            [Some(false), Some(false), Some(false)]
+        );
+        assert_eq!(
+            with_flags(ComparisonOp::NotEq, &input, f64::NAN, 0),
+            [Some(true), Some(true), Some(true)]
+        );
+        assert_eq!(
+            with_flags(ComparisonOp::Eq, &input, 0.0, 0),
+            [Some(false), Some(false), Some(true)]
+        );
+        let equal_nan = ARROW_UDF_FLAG_EQUAL_NAN;
+        assert_eq!(
+            with_flags(ComparisonOp::Eq, &input, f64::NAN, equal_nan),
+            [Some(true), Some(false), Some(false)]
+        );
+        assert_eq!(
+            with_flags(ComparisonOp::Gt, &input, 1e300, equal_nan),
+            [Some(true), Some(false), Some(false)]
+        );
+    }
+
+    #[test]
+    fn scalars_and_operands_that_do_not_fit_fail() {
+        let int32 = Exported::primitive(&[1_i32]);
+        for scalar in [0.5, f64::NAN, 2f64.powi(31)] {
+            assert_eq!(
+                eq_scalar(&int32, scalar).err(),
+                Some(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        let int64 = Exported::primitive(&[1_i64]);
+        assert_eq!(
+            eq_scalar(&int64, 2f64.powi(53)).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let float = Exported::primitive(&[1.0_f32]);
+        assert_eq!(
+            eq_scalar(&float, 1e300).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            lt(&int32, &int64).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let strings = Exported::utf8(&[Some("a")]);
+        assert_eq!(
+            eq_scalar(&strings, 1.0).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Kernels composed in Rust, for Rust hosts chaining several of them over
+//! the arrays they imported.
+//!
+//! Calling the entry points one after another exports every intermediate
+//! array through the C Data Interface, and imports it again in the next
+//! call. A `Kernel` is a list of steps, each a function from an array to
+//! another, like `udf::map` with a closure or `hash::hash64`, run in order
+//! on the result of the previous one, which stays owned by the kernel:
+//!
+//! ```text
+//! let kernel = Kernel::map(|value: i64| value - 5)
+//!     .then_map(|value: i64| value.abs())
+//!     .reduce(|array, options| udf::map_sum(array, options, |value: i64| value));
+//! let sum = kernel.call(&array, &options)?;
+//! ```
+//!
+//! Unlike the plans of `pipeline`, steps see whole arrays of any type, and
+//! every step finishes before the next one starts.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::error::Result;
+use crate::export::ArrayData;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::stream::ImportedArray;
+use crate::types::NativeType;
+use crate::udf;
+
+/// Step of a kernel, from the result of the previous step, or the input of
+/// the kernel, to an array.
+pub type Step =
+    Arc<dyn Fn(&ArrowArray, &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)> + Send + Sync>;
+
+/// Reduction ending a kernel, from its result to a value.
+pub type ReduceFn<R> = Arc<dyn Fn(&ArrowArray, &ArrowUdfExecOptions) -> Result<R> + Send + Sync>;
+
+/// Steps applied in order to an array.
+#[derive(Clone)]
+pub struct Kernel {
+    steps: Vec<Step>,
+}
+
+impl Kernel {
+    /// Kernel of a single step.
+    pub fn new<F>(step: F) -> Kernel
+    where
+        F: Fn(&ArrowArray, &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Kernel {
+            steps: vec![Arc::new(step)],
+        }
+    }
+
+    /// Kernel applying `f` to every element of its input, like `udf::map`.
+    pub fn map<T, O, F>(f: F) -> Kernel
+    where
+        T: NativeType,
+        O: NativeType,
+        F: Fn(T) -> O + Send + Sync + 'static,
+    {
+        Kernel::new(move |array, options| udf::map(array, options, &f))
+    }
+
+    /// This kernel followed by `step`, receiving its result.
+    pub fn then<F>(mut self, step: F) -> Kernel
+    where
+        F: Fn(&ArrowArray, &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.steps.push(Arc::new(step));
+        self
+    }
+
+    /// This kernel followed by `f` applied to every element of its result,
+    /// like `Kernel::map`.
+    pub fn then_map<T, O, F>(self, f: F) -> Kernel
+    where
+        T: NativeType,
+        O: NativeType,
+        F: Fn(T) -> O + Send + Sync + 'static,
+    {
+        self.then(move |array, options| udf::map(array, options, &f))
+    }
+
+    /// This kernel followed by `other`.
+    pub fn then_kernel(mut self, other: Kernel) -> Kernel {
+        self.steps.extend(other.steps);
+        self
+    }
+
+    /// This kernel ending with `reduce`, receiving its result, like
+    /// `udf::map_sum` or `dot::dot` with another array.
+    pub fn reduce<R, F>(self, reduce: F) -> Reduction<R>
+    where
+        F: Fn(&ArrowArray, &ArrowUdfExecOptions) -> Result<R> + Send + Sync + 'static,
+    {
+        Reduction {
+            kernel: self,
+            reduce: Arc::new(reduce),
+        }
+    }
+
+    /// The result of the steps of the kernel applied to `array`.
+    pub fn call(
+        &self,
+        array: &ArrowArray,
+        options: &ArrowUdfExecOptions,
+    ) -> Result<(Schema, ArrayData)> {
+        let (first, steps) = self.steps.split_first().unwrap();
+        let mut result = first(array, options)?;
+        for step in steps {
+            options.check_cancelled()?;
+            let (schema, data) = result;
+            let data = ImportedArray::from(data);
+            result = step(&data.view(&schema), options)?;
+        }
+        Ok(result)
+    }
+}
+
+/// Kernel ending with a reduction of its result.
+pub struct Reduction<R> {
+    kernel: Kernel,
+    reduce: ReduceFn<R>,
+}
+
+impl<R> Reduction<R> {
+    /// The reduction of the result of the kernel applied to `array`.
+    pub fn call(&self, array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<R> {
+        let (schema, data) = self.kernel.call(array, options)?;
+        let data = ImportedArray::from(data);
+        (self.reduce)(&data.view(&schema), options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::error::Error;
+    use crate::hash;
+    use crate::testing::Exported;
+
+    fn values<T: NativeType>((schema, data): (Schema, ArrayData)) -> Vec<Option<T>> {
+        let data = ImportedArray::from(data);
+        let array = data.view(&schema);
+        (0..array.len())
+            .map(|i| array.is_valid(i).then(|| array.values::<T>()[i]))
+            .collect()
+    }
+
+    #[test]
+    fn steps_run_in_order_keeping_the_nulls() {
+        let input = Exported::nullable(&[Some(1_i64), None, Some(9), Some(-2)]);
+        let kernel = Kernel::map(|value: i64| value - 5)
+            .then_map(|value: i64| value.abs())
+            .then_map(|value: i64| value as f64 / 2.0);
+        let options = ArrowUdfExecOptions::default();
+        let result = input.with_array(|array| kernel.call(array, &options).unwrap());
+        assert_eq!(result.0.data_type, crate::schema::ArrowType::Float64);
+        assert_eq!(
+            values::<f64>(result),
+            [Some(2.0), None, Some(2.0), Some(3.5)]
+        );
+    }
+
+    #[test]
+    fn kernels_are_chained_and_reduced() {
+        let input = Exported::primitive(&[1_i32, 2, 3]);
+        let widen = Kernel::map(|value: i32| value as i64 * 10);
+        let reduction = Kernel::map(|value: i32| value + 1)
+            .then_kernel(widen)
+            .then(|array, options| hash::hash64(array, 0, options))
+            .then_map(|hash: u64| (hash % 2) as i64)
+            .reduce(|array, options| udf::map_sum(array, options, |value: i64| value));
+        let options = ArrowUdfExecOptions::default();
+        let parity = input.with_array(|array| reduction.call(array, &options).unwrap());
+        assert!((0..=3).contains(&parity));
+        let sum = Kernel::map(|value: i32| value as i64 * 10)
+            .reduce(|array, options| udf::map_sum(array, options, |value: i64| value));
+        assert_eq!(input.with_array(|array| sum.call(array, &options)), Ok(60));
+    }
+
+    #[test]
+    fn errors_and_cancellation_stop_the_kernel() {
+        let input = Exported::primitive(&[1_i64, 2]);
+        let options = ArrowUdfExecOptions::default();
+        // The second step expects Int32 values, and gets the Int64 result.
+        let kernel = Kernel::map(|value: i64| value + 1).then_map(|value: i32| value);
+        let result = input.with_array(|array| kernel.call(array, &options));
+        assert!(matches!(result, Err(Error::UnsupportedType(_))));
+        let cancel = AtomicBool::new(false);
+        let options = ArrowUdfExecOptions {
+            cancel_flag: &cancel,
+            ..ArrowUdfExecOptions::default()
+        };
+        let kernel = Kernel::map(move |value: i64| value).then_map(|value: i64| value);
+        cancel.store(true, Ordering::Relaxed);
+        let result = input.with_array(|array| kernel.call(array, &options));
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+}
@@ -0,0 +1,466 @@
+//! Concatenation of several arrays of the same type into a single array,
+//! for hosts that need a contiguous result of chunked data, and interleaving
+//! of their elements in any order, for kernels merging rows of several
+//! arrays.
+//!
+//! Every buffer of the result is allocated once, with the size of all the
+//! chunks, and filled with `exec::map`, so the copy of large arrays is split
+//! among the threads of the execution options. Offsets of Binary and Utf8
+//! arrays are rebased while they are copied, and bits of validity bitmaps
+//! and Boolean arrays are copied one by one, since the chunks can start at
+//! any bit.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::binary::BinaryBuilder;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::chunked::ChunkedArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{
+    ArrowCDataInterfaceArray, ArrowCDataInterfaceArrayStream, ArrowCDataInterfaceSchema,
+};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::stream::ArrayStream;
+use crate::types::{with_native_type, NativeType};
+
+/// Copy the concatenation of `chunks` into `out`, which has their total
+/// length.
+fn copy_concatenated(
+    chunks: &[&[u8]],
+    out: &mut [u8],
+    options: &ArrowUdfExecOptions,
+) -> Result<()> {
+    let mut starts = Vec::with_capacity(chunks.len());
+    let mut start = 0;
+    for chunk in chunks {
+        starts.push(start);
+        start += chunk.len();
+    }
+    exec::map(out, options, |rows, out| {
+        // The chunk containing the first byte of the batch.
+        let first = starts.partition_point(|start| *start <= rows.start) - 1;
+        let mut written = 0;
+        for (chunk, start) in chunks[first..].iter().zip(&starts[first..]) {
+            if written == out.len() {
+                break;
+            }
+            let from = rows.start + written - start;
+            let n = (chunk.len() - from).min(out.len() - written);
+            out[written..written + n].copy_from_slice(&chunk[from..from + n]);
+            written += n;
+        }
+        Ok(())
+    })
+}
+
+fn value_bytes<'a, T: NativeType>(chunk: &ArrowArray<'a>) -> &'a [u8] {
+    let values = chunk.values::<T>();
+    unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, size_of_val(values)) }
+}
+
+/// Validity of the concatenation, if any of the chunks has nulls.
+fn concat_validity(chunked: &ChunkedArray) -> (Option<Buffer>, usize) {
+    let null_count = chunked.null_count();
+    if null_count == 0 {
+        return (None, 0);
+    }
+    let mut validity = BitmapBuilder::with_capacity(chunked.len());
+    for chunk in chunked.chunks() {
+        match chunk.validity() {
+            Some(bits) => bits.iter().for_each(|valid| validity.push(valid)),
+            None => (0..chunk.len()).for_each(|_| validity.push(true)),
+        }
+    }
+    (Some(validity.finish()), null_count)
+}
+
+fn concat_binary(chunked: &ChunkedArray, options: &ArrowUdfExecOptions) -> Result<ArrayData> {
+    let mut offsets = Vec::with_capacity(chunked.len() + 1);
+    let mut data = Vec::with_capacity(chunked.chunks().len());
+    offsets.push(0);
+    for chunk in chunked.chunks() {
+        let chunk_offsets = chunk.binary_offsets();
+        let (first, last) = (chunk_offsets[0], *chunk_offsets.last().unwrap());
+        let base = *offsets.last().unwrap();
+        if i32::MAX - base < last - first {
+            return Err(Error::InvalidArgument(
+                "the concatenated data doesn't fit in a Binary array".to_string(),
+            ));
+        }
+        offsets.extend(
+            chunk_offsets[1..]
+                .iter()
+                .map(|offset| base + offset - first),
+        );
+        data.push(&chunk.binary_data()[first as usize..]);
+    }
+    let mut values = Buffer::zeroed(*offsets.last().unwrap() as usize)?;
+    copy_concatenated(&data, values.as_mut_slice(), options)?;
+    Ok(ArrayData {
+        length: chunked.len(),
+        null_count: 0,
+        buffers: vec![None, Some(Buffer::from_slice(&offsets)), Some(values)],
+        children: Vec::new(),
+    })
+}
+
+/// The values of all the chunks of `chunked`, in a single array.
+pub fn concat(
+    chunked: &ChunkedArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let data_type = chunked.schema().data_type;
+    let data = match data_type {
+        ArrowType::Boolean => {
+            let mut values = BitmapBuilder::with_capacity(chunked.len());
+            for chunk in chunked.chunks() {
+                let data = unsafe { chunk.ffi().buffer(1) };
+                for i in 0..chunk.len() {
+                    let bit = chunk.offset() + i;
+                    values.push(unsafe { *data.add(bit / 8) } & (1 << (bit % 8)) != 0);
+                }
+            }
+            ArrayData::primitive(values.finish(), chunked.len())
+        }
+        ArrowType::Binary | ArrowType::Utf8 => concat_binary(chunked, options)?,
+        _ => with_native_type!(data_type.physical_type(), T => {
+            let chunks: Vec<&[u8]> = chunked.chunks().iter().map(value_bytes::<T>).collect();
+            let mut values = Buffer::zeroed(chunked.len() * size_of::<T>())?;
+            copy_concatenated(&chunks, values.as_mut_slice(), options)?;
+            ArrayData::primitive(values, chunked.len())
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "concatenation of {data_type:?} arrays"
+        )))),
+    };
+    let (validity, null_count) = concat_validity(chunked);
+    // Keeps the timezone of timestamps.
+    let mut schema = Schema::new(data_type, &chunked.schema().name);
+    schema.format = chunked.schema().format.clone();
+    Ok((schema, data.with_validity(validity, null_count)))
+}
+
+/// Array with the elements of `arrays` given by `indices`, as the array and
+/// the position in it of every element. The arrays have the same type, which
+/// can also be a struct of the supported types.
+pub fn interleave(arrays: &[ArrowArray], indices: &[(usize, usize)]) -> Result<ArrayData> {
+    let Some(first) = arrays.first() else {
+        return Err(Error::InvalidArgument(
+            "interleave of no arrays".to_string(),
+        ));
+    };
+    let data_type = first.data_type();
+    let data = match data_type {
+        ArrowType::Boolean => {
+            let mut values = BitmapBuilder::with_capacity(indices.len());
+            for (a, i) in indices {
+                let (array, bit) = (&arrays[*a], arrays[*a].offset() + i);
+                values.push(unsafe { *array.ffi().buffer(1).add(bit / 8) } & (1 << (bit % 8)) != 0);
+            }
+            ArrayData::primitive(values.finish(), indices.len())
+        }
+        ArrowType::Binary | ArrowType::Utf8 => {
+            let mut builder = BinaryBuilder::with_capacity(indices.len());
+            for (a, i) in indices {
+                builder.push(Some(arrays[*a].binary_value(*i)));
+            }
+            builder.finish()
+        }
+        ArrowType::Struct => {
+            // The fields are indexed by the offset of the struct plus the row.
+            let children = (0..first.ffi().n_children as usize)
+                .map(|c| {
+                    let fields: Vec<ArrowArray> =
+                        arrays.iter().map(|array| array.child(c)).collect();
+                    let rows: Vec<(usize, usize)> = indices
+                        .iter()
+                        .map(|(a, i)| (*a, arrays[*a].offset() + i))
+                        .collect();
+                    interleave(&fields, &rows)
+                })
+                .collect::<Result<_>>()?;
+            ArrayData::struct_array(children, indices.len())
+        }
+        _ => with_native_type!(data_type.physical_type(), T => {
+            let values: Buffer = indices
+                .iter()
+                .map(|(a, i)| arrays[*a].values::<T>()[*i])
+                .collect();
+            ArrayData::primitive(values, indices.len())
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "interleave of {data_type:?} arrays"
+        )))),
+    };
+    let null_count = indices
+        .iter()
+        .filter(|(a, i)| !arrays[*a].is_valid(*i))
+        .count();
+    if null_count == 0 {
+        return Ok(data);
+    }
+    let mut validity = BitmapBuilder::with_capacity(indices.len());
+    indices
+        .iter()
+        .for_each(|(a, i)| validity.push(arrays[*a].is_valid(*i)));
+    Ok(data.with_validity(Some(validity.finish()), null_count))
+}
+
+/// Concatenation of the `n_arrays` arrays of the same type in `schemas` and
+/// `arrays`, as a single array named like the first one. Primitive, Boolean,
+/// Binary, Utf8 and temporal arrays are supported.
+///
+/// # Safety
+///
+/// `schemas` and `arrays` must point to `n_arrays` valid Arrow C Data
+/// Interface arrays, `options` must be null or valid, and `out_schema` and
+/// `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_concat(
+    n_arrays: i64,
+    schemas: *const *const ArrowCDataInterfaceSchema,
+    arrays: *const *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schemas = Schema::from_ffi_list(n_arrays, schemas)?;
+        let Some(first) = schemas.first() else {
+            return Err(Error::InvalidArgument(
+                "concatenation without arrays".to_string(),
+            ));
+        };
+        let chunks = (0..schemas.len())
+            .map(|i| ArrowArray::import(&schemas[i], &**arrays.add(i)))
+            .collect::<Result<_>>()?;
+        let (out, data) = concat(&ChunkedArray::new(first, chunks)?, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Concatenation of all the arrays of `stream`, as a single array.
+///
+/// The stream is consumed and released, also when the call fails.
+///
+/// # Safety
+///
+/// `stream` must point to a valid C Stream Interface stream, `options` must
+/// be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_concat_stream(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let mut stream = ArrayStream::from_ffi(stream)?;
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let arrays = stream.read_all()?;
+        let schema = stream.schema();
+        let chunks = arrays.iter().map(|array| array.view(schema)).collect();
+        let (out, data) = concat(&ChunkedArray::new(schema, chunks)?, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Exported};
+
+    fn run(
+        inputs: &[&Exported],
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let schemas: Vec<_> = inputs
+            .iter()
+            .map(|input| &input.schema as *const _)
+            .collect();
+        let arrays: Vec<_> = inputs
+            .iter()
+            .map(|input| &input.array as *const _)
+            .collect();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_concat(
+                inputs.len() as i64,
+                schemas.as_ptr(),
+                arrays.as_ptr(),
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn primitive_arrays_with_offsets_and_nulls() {
+        let mut first = Exported::nullable(&[Some(1_i32), Some(2), None, Some(4)]);
+        (first.array.offset, first.array.length) = (1, 3);
+        let values: Vec<i32> = (5..100).collect();
+        let second = Exported::primitive(&values);
+        let options = ArrowUdfExecOptions {
+            batch_size: 16,
+            num_threads: 3,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = run(&[&first, &second], &options).unwrap();
+        let mut expected = vec![Some(2), None, Some(4)];
+        expected.extend(values.iter().map(|value| Some(*value)));
+        assert_eq!(out.nullable_values::<i32>(), expected);
+        out.with_array(|array| assert_eq!(array.null_count(), 1));
+        let valid = run(&[&second, &second], &options).unwrap();
+        valid.with_array(|array| assert_eq!(array.null_count(), 0));
+    }
+
+    #[test]
+    fn strings_and_booleans_at_any_offset() {
+        let mut first = Exported::utf8(&[Some("skipped"), Some("a"), None]);
+        (first.array.offset, first.array.length) = (1, 2);
+        let second = Exported::utf8(&[Some("bc"), Some("")]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 1,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = run(&[&first, &second], &options).unwrap();
+        let strings = |values: &[Option<&str>]| -> Vec<Option<String>> {
+            values
+                .iter()
+                .map(|value| value.map(str::to_string))
+                .collect()
+        };
+        assert_eq!(
+            out.strings(),
+            strings(&[Some("a"), None, Some("bc"), Some("")])
+        );
+        let mut booleans = Exported::boolean(&[true, false, true, true, false]);
+        (booleans.array.offset, booleans.array.length) = (3, 2);
+        let out = run(&[&booleans, &booleans], &options).unwrap();
+        assert_eq!(
+            out.booleans(),
+            [Some(true), Some(false), Some(true), Some(false)]
+        );
+    }
+
+    #[test]
+    fn streams_are_concatenated() {
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let batches = vec![
+            Exported::primitive(&[1_i64]),
+            Exported::primitive::<i64>(&[]),
+            Exported::nullable(&[None, Some(3_i64)]),
+        ];
+        let mut stream = testing::stream(&schema, batches, None);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_concat_stream(
+                &mut stream,
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert!(stream.release.is_none());
+        assert_eq!(out.nullable_values::<i64>(), [Some(1), None, Some(3)]);
+    }
+
+    #[test]
+    fn invalid_concatenations_fail() {
+        let options = ArrowUdfExecOptions::default();
+        assert_eq!(
+            run(&[], &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let ints = Exported::primitive(&[1_i32]);
+        let longs = Exported::primitive(&[1_i64]);
+        assert_eq!(
+            run(&[&ints, &longs], &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Int32, "a")]);
+        let child = ArrayData::primitive(Buffer::from_slice(&[1_i32]), 1);
+        let structs = Exported::new(&schema, ArrayData::struct_array(vec![child], 1));
+        assert_eq!(
+            run(&[&structs], &options).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+
+    #[test]
+    fn interleaved_elements_keep_their_nulls() {
+        let mut first = Exported::nullable(&[Some(1_i64), Some(2), None, Some(4)]);
+        (first.array.offset, first.array.length) = (1, 3);
+        let second = Exported::primitive(&[5_i64, 6]);
+        let out = first.with_array(|first| {
+            second.with_array(|second| {
+                let indices = [(1, 1), (0, 1), (0, 0), (1, 0), (0, 2)];
+                interleave(&[*first, *second], &indices).unwrap()
+            })
+        });
+        let out = Exported::new(&Schema::new(ArrowType::Int64, "x"), out);
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [Some(6), None, Some(2), Some(5), Some(4)]
+        );
+    }
+
+    #[test]
+    fn interleaved_strings_booleans_and_structs() {
+        let mut strings = Exported::utf8(&[Some("skipped"), Some("a"), None, Some("bc")]);
+        (strings.array.offset, strings.array.length) = (1, 3);
+        let out =
+            strings.with_array(|array| interleave(&[*array], &[(0, 2), (0, 1), (0, 0)]).unwrap());
+        let out = Exported::new(&Schema::new(ArrowType::Utf8, "x"), out);
+        assert_eq!(
+            out.strings(),
+            [Some("bc".to_string()), None, Some("a".to_string())]
+        );
+
+        let mut booleans = Exported::boolean(&[false, false, false, true, false, true]);
+        (booleans.array.offset, booleans.array.length) = (3, 3);
+        let out =
+            booleans.with_array(|array| interleave(&[*array], &[(0, 2), (0, 1), (0, 0)]).unwrap());
+        let out = Exported::new(&Schema::new(ArrowType::Boolean, "x"), out);
+        assert_eq!(out.booleans(), [Some(true), Some(false), Some(true)]);
+
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Int32, "a")]);
+        let child = ArrayData::primitive(Buffer::from_slice(&[1_i32, 2, 3]), 3);
+        let mut structs = Exported::new(&schema, ArrayData::struct_array(vec![child], 3));
+        (structs.array.offset, structs.array.length) = (1, 2);
+        let out =
+            structs.with_array(|array| interleave(&[*array], &[(0, 1), (0, 0), (0, 1)]).unwrap());
+        let out = Exported::new(&schema, out);
+        assert_eq!(out.child_values::<i32>(0), [Some(3), Some(2), Some(3)]);
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn invalid_interleaves_fail() {
+        assert!(matches!(
+            interleave(&[], &[]),
+            Err(Error::InvalidArgument(_))
+        ));
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Decimal128, "a")]);
+        let child = ArrayData::primitive(Buffer::from_slice(&[0_u8; 16]), 1);
+        let structs = Exported::new(&schema, ArrayData::struct_array(vec![child], 1));
+        let result = structs.with_array(|array| interleave(&[*array], &[(0, 0)]));
+        assert!(matches!(result, Err(Error::UnsupportedType(_))));
+    }
+}
@@ -0,0 +1,129 @@
+//! State shared by the calls made with the same context.
+//!
+//! Hosts create a context with `arrow_udf_context_create`, pass it to the
+//! calls in the `context` field of the execution options, and destroy it
+//! with `arrow_udf_context_destroy` when they're done. UDFs use it to keep
+//! the result of expensive setup (compiled patterns, lookup tables...) from
+//! one call to the next.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::arena::{self, Arena};
+
+/// Values stored in a context, one per type.
+#[derive(Default)]
+pub struct UdfContext {
+    values: RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl UdfContext {
+    pub fn new() -> UdfContext {
+        UdfContext::default()
+    }
+
+    /// The value of type `T`, if one was stored.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let values = self.values.read().unwrap();
+        values
+            .get(&TypeId::of::<T>())
+            .map(|value| Arc::clone(value).downcast::<T>().unwrap())
+    }
+
+    /// Store `value`, replacing any previous value of the same type.
+    pub fn insert<T: Any + Send + Sync>(&self, value: T) {
+        let mut values = self.values.write().unwrap();
+        values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// The value of type `T`, creating it with `init` if it's not stored yet.
+    ///
+    /// `init` runs without holding the lock, so it can use the context, and
+    /// the other threads aren't blocked while it runs. When several threads
+    /// create the value at the same time, the first one stored is kept, and
+    /// returned to all of them.
+    pub fn get_or_insert_with<T, F>(&self, init: F) -> Arc<T>
+    where
+        T: Any + Send + Sync,
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.get::<T>() {
+            return value;
+        }
+        let created: Arc<dyn Any + Send + Sync> = Arc::new(init());
+        let mut values = self.values.write().unwrap();
+        let value = values.entry(TypeId::of::<T>()).or_insert(created);
+        Arc::clone(value).downcast::<T>().unwrap()
+    }
+
+    /// Run `f` with the scratch arena of the current thread, reset after every
+    /// batch. See `arena::with_scratch`.
+    pub fn with_scratch<R, F: FnOnce(&Arena) -> R>(&self, f: F) -> R {
+        arena::with_scratch(f)
+    }
+
+    /// Remove the value of type `T` from the context.
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let mut values = self.values.write().unwrap();
+        values
+            .remove(&TypeId::of::<T>())
+            .map(|value| value.downcast::<T>().unwrap())
+    }
+}
+
+/// Create a new, empty, context.
+#[no_mangle]
+pub extern "C" fn arrow_udf_context_create() -> *mut UdfContext {
+    Box::into_raw(Box::new(UdfContext::new()))
+}
+
+/// Destroy a context created with `arrow_udf_context_create`.
+///
+/// # Safety
+///
+/// `context` must be null or a pointer returned by `arrow_udf_context_create`
+/// that wasn't destroyed yet, and no call using it can be running.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_context_destroy(context: *mut UdfContext) {
+    if !context.is_null() {
+        drop(Box::from_raw(context));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_stored_by_type() {
+        let context = UdfContext::new();
+        assert_eq!(context.get::<i64>(), None);
+        context.insert(1_i64);
+        context.insert("one".to_string());
+        assert_eq!(*context.get::<i64>().unwrap(), 1);
+        context.insert(2_i64);
+        assert_eq!(*context.get::<i64>().unwrap(), 2);
+        assert_eq!(*context.remove::<String>().unwrap(), "one");
+        assert_eq!(context.get::<String>(), None);
+    }
+
+    #[test]
+    fn init_can_use_the_context() {
+        let context = UdfContext::default();
+        context.insert(2_i64);
+        let value = context.get_or_insert_with(|| *context.get::<i64>().unwrap() as i32 * 3);
+        assert_eq!(*value, 6);
+        assert_eq!(*context.get_or_insert_with(|| 0_i32), 6);
+    }
+
+    #[test]
+    fn contexts_through_ffi() {
+        let context = arrow_udf_context_create();
+        unsafe {
+            (*context).insert(1_u8);
+            arrow_udf_context_destroy(context);
+            arrow_udf_context_destroy(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Instruction sets of the processor, detected when the library is loaded.
+//!
+//! The library is built for the baseline of its target, like x86-64 without
+//! AVX, so the same binary runs on every machine of a fleet. The hot kernels
+//! are compiled again for the wider instruction sets with `target_feature`,
+//! and every call picks the widest version the processor supports. On
+//! AArch64, NEON is part of the baseline, so the generic versions already use
+//! it.
+//!
+//! The `ARROW_UDF_CPU` environment variable caps the level used, with
+//! `generic`, `avx2` or `avx512`, to compare the versions on a machine or to
+//! work around a broken one.
+
+use std::sync::OnceLock;
+
+/// Versions of the kernels, from the narrowest to the widest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Compiled for the baseline of the target.
+    Generic,
+    /// x86-64 with AVX2 and FMA.
+    Avx2,
+    /// x86-64 with AVX-512F, besides AVX2 and FMA.
+    Avx512,
+}
+
+pub const ARROW_UDF_CPU_AVX2: u32 = 1;
+pub const ARROW_UDF_CPU_FMA: u32 = 2;
+pub const ARROW_UDF_CPU_AVX512F: u32 = 4;
+pub const ARROW_UDF_CPU_NEON: u32 = 8;
+
+/// Bitwise or of the `ARROW_UDF_CPU_*` instruction sets of the processor.
+fn detect() -> u32 {
+    #[allow(unused_mut)]
+    let mut features = 0;
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            features |= ARROW_UDF_CPU_AVX2;
+        }
+        if is_x86_feature_detected!("fma") {
+            features |= ARROW_UDF_CPU_FMA;
+        }
+        if is_x86_feature_detected!("avx512f") {
+            features |= ARROW_UDF_CPU_AVX512F;
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        features |= ARROW_UDF_CPU_NEON;
+    }
+    features
+}
+
+/// The instruction sets of the processor, as `ARROW_UDF_CPU_*` bits.
+pub fn features() -> u32 {
+    static FEATURES: OnceLock<u32> = OnceLock::new();
+    *FEATURES.get_or_init(detect)
+}
+
+/// The widest version of the kernels the processor supports, capped by the
+/// `ARROW_UDF_CPU` environment variable.
+pub fn level() -> Level {
+    static LEVEL: OnceLock<Level> = OnceLock::new();
+    *LEVEL.get_or_init(|| {
+        let features = features();
+        let avx2 = ARROW_UDF_CPU_AVX2 | ARROW_UDF_CPU_FMA;
+        let supported = if features & avx2 != avx2 {
+            Level::Generic
+        } else if features & ARROW_UDF_CPU_AVX512F != 0 {
+            Level::Avx512
+        } else {
+            Level::Avx2
+        };
+        let cap = match std::env::var("ARROW_UDF_CPU").as_deref() {
+            Ok("generic") => Level::Generic,
+            Ok("avx2") => Level::Avx2,
+            _ => Level::Avx512,
+        };
+        supported.min(cap)
+    })
+}
+
+/// The instruction sets of the processor, as a bitwise or of the
+/// `ARROW_UDF_CPU_*` constants, so hosts can report them.
+#[no_mangle]
+pub extern "C" fn arrow_udf_cpu_features() -> u32 {
+    features()
+}
+
+/// The `Level` of the kernels used, 0 for the generic versions, 1 for AVX2
+/// and 2 for AVX-512.
+#[no_mangle]
+pub extern "C" fn arrow_udf_cpu_level() -> i32 {
+    level() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_level_needs_its_instruction_sets() {
+        let features = features();
+        let level = level();
+        assert_eq!(arrow_udf_cpu_features(), features);
+        assert_eq!(arrow_udf_cpu_level(), level as i32);
+        if level >= Level::Avx2 {
+            assert_eq!(features & ARROW_UDF_CPU_AVX2, ARROW_UDF_CPU_AVX2);
+            assert_eq!(features & ARROW_UDF_CPU_FMA, ARROW_UDF_CPU_FMA);
+        }
+        if level == Level::Avx512 {
+            assert_ne!(features & ARROW_UDF_CPU_AVX512F, 0);
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        assert_eq!(level, Level::Generic);
+        #[cfg(target_arch = "aarch64")]
+        assert_ne!(features & ARROW_UDF_CPU_NEON, 0);
+    }
+}
@@ -0,0 +1,356 @@
+//! Import of a column of a CSV file, to reproduce issues from flat files.
+//!
+//! Numerical issues are often reported with the data that triggers them, in
+//! a CSV file exported from wherever it lives. `read_column` reads one of
+//! its columns into an array of a declared type, which tests, benchmarks and
+//! tools can pass to any entry point, without a library producing Arrow.
+//!
+//! The first record of the file is the header with the names of the
+//! columns. Fields are separated by commas, and quoted fields can contain
+//! commas, line breaks and quotes written twice. Empty fields not quoted
+//! are null, and empty lines are skipped.
+
+use std::borrow::Cow;
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::row::ArrowField;
+use crate::schema::{ArrowType, Schema};
+use crate::types::with_native_type;
+
+/// A field of a record, without its quotes.
+struct Field<'a> {
+    text: Cow<'a, str>,
+    quoted: bool,
+}
+
+impl Field<'_> {
+    fn is_null(&self) -> bool {
+        self.text.is_empty() && !self.quoted
+    }
+}
+
+/// The records of `input`, with the line where every one starts.
+fn records(input: &str) -> Result<Vec<(usize, Vec<Field<'_>>)>> {
+    let bytes = input.as_bytes();
+    let (mut records, mut position, mut line) = (Vec::new(), 0, 1);
+    while position < bytes.len() {
+        let (start_line, mut fields) = (line, Vec::new());
+        loop {
+            if bytes.get(position) == Some(&b'"') {
+                position += 1;
+                let (mut text, mut segment) = (String::new(), position);
+                loop {
+                    match bytes.get(position) {
+                        None => {
+                            return Err(Error::InvalidArgument(format!(
+                                "unterminated quote in the record of line {start_line}"
+                            )))
+                        }
+                        Some(b'"') if bytes.get(position + 1) == Some(&b'"') => {
+                            text.push_str(&input[segment..=position]);
+                            position += 2;
+                            segment = position;
+                        }
+                        Some(b'"') => {
+                            text.push_str(&input[segment..position]);
+                            position += 1;
+                            break;
+                        }
+                        Some(byte) => {
+                            line += usize::from(*byte == b'\n');
+                            position += 1;
+                        }
+                    }
+                }
+                fields.push(Field {
+                    text: Cow::Owned(text),
+                    quoted: true,
+                });
+            } else {
+                let start = position;
+                while position < bytes.len() && !matches!(bytes[position], b',' | b'\n') {
+                    position += 1;
+                }
+                let text = &input[start..position];
+                fields.push(Field {
+                    text: Cow::Borrowed(text.strip_suffix('\r').unwrap_or(text)),
+                    quoted: false,
+                });
+            }
+            match (bytes.get(position), bytes.get(position + 1)) {
+                (Some(b','), _) => position += 1,
+                (Some(b'\n'), _) | (Some(b'\r'), Some(b'\n')) => {
+                    position += 1 + usize::from(bytes[position] == b'\r');
+                    line += 1;
+                    break;
+                }
+                (None, _) => break,
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "text after a closing quote in line {line}"
+                    )))
+                }
+            }
+        }
+        if !(fields.len() == 1 && fields[0].is_null()) {
+            records.push((start_line, fields));
+        }
+    }
+    Ok(records)
+}
+
+/// The values of a column, failing if any can't be converted to `T`.
+fn convert<'v, T>(
+    values: &[(usize, &'v Field<'v>)],
+    field: &Schema,
+    f: impl Fn(&'v str) -> Option<T>,
+) -> Result<Vec<Option<T>>> {
+    values
+        .iter()
+        .map(|(line, value)| match value.is_null() {
+            true if !field.flags.nullable => Err(Error::InvalidArgument(format!(
+                "null in line {line} for the non-nullable column {:?}",
+                field.name
+            ))),
+            true => Ok(None),
+            false => f(&value.text).map(Some).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "{:?} in line {line} for the {:?} column {:?}",
+                    value.text, field.data_type, field.name
+                ))
+            }),
+        })
+        .collect()
+}
+
+/// Array of the values of the column `field.name` of `input`, a CSV file
+/// with a header, as the type of `field`. Boolean columns are `true` or
+/// `false`, temporal columns are their integer values, and float columns
+/// accept `NaN` and `inf`.
+pub fn read_column(input: &str, field: &Schema) -> Result<ArrayData> {
+    let records = records(input)?;
+    let Some((_, header)) = records.first() else {
+        return Err(Error::InvalidArgument("the file has no header".to_string()));
+    };
+    let column = header
+        .iter()
+        .position(|name| name.text == field.name)
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!("no column {:?} in the header", field.name))
+        })?;
+    let values = records[1..]
+        .iter()
+        .map(|(line, fields)| {
+            fields
+                .get(column)
+                .map(|value| (*line, value))
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "{} fields in line {line}, without the column {:?}",
+                        fields.len(),
+                        field.name
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(match field.data_type {
+        ArrowType::Utf8 => {
+            let values = convert(&values, field, Some)?;
+            <&str>::build(values.iter().map(Option::as_ref))
+        }
+        ArrowType::Boolean => {
+            let values = convert(&values, field, |text| match text.trim() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            })?;
+            bool::build(values.iter().map(Option::as_ref))
+        }
+        data_type => with_native_type!(data_type.physical_type(), T => {
+            let values = convert(&values, field, |text| text.trim().parse::<T>().ok())?;
+            T::build(values.iter().map(Option::as_ref))
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "CSV import of {data_type:?} columns"
+        )))),
+    })
+}
+
+/// Import the column `column` of the `len` bytes of CSV at `input`, a file
+/// with a header, as an array of the type of the C Data Interface format
+/// `format`, like `g` for Float64 or `tsu:UTC` for timestamps.
+///
+/// # Safety
+///
+/// `input` must be valid for reads of `len` bytes, `column` and `format`
+/// must be valid null-terminated strings, `options` must be null or valid,
+/// and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_csv_read_column(
+    input: *const c_char,
+    len: usize,
+    column: *const c_char,
+    format: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        ArrowUdfExecOptions::from_ffi(options)?;
+        let text = |value: *const c_char, what: &str| {
+            CStr::from_ptr(value)
+                .to_str()
+                .map_err(|_| Error::InvalidArgument(format!("the {what} is not valid UTF-8")))
+        };
+        let (column, format) = (text(column, "column")?, text(format, "format")?);
+        let data_type = ArrowType::from_format(format)
+            .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+        let field = Schema {
+            format: format.to_string(),
+            ..Schema::new(data_type, column)
+        };
+        let input = match len {
+            0 => "",
+            _ => std::str::from_utf8(std::slice::from_raw_parts(input as *const u8, len))
+                .map_err(|_| Error::InvalidArgument("the input is not valid UTF-8".to_string()))?,
+        };
+        let data = read_column(input, &field)?;
+        export::export_to(&field, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    const FILE: &str = concat!(
+        "id,name,ok,price\r\n",
+        "1,\"a, \"\"b\"\"\",true,1.5\r\n",
+        "\n",
+        "2,,false,NaN\n",
+        "3,\"\",,\n",
+        "4,\"two\nlines\", true ,-inf",
+    );
+
+    fn read_ffi(
+        input: &str,
+        column: &str,
+        format: &str,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let (column, format) = (CString::new(column).unwrap(), CString::new(format).unwrap());
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_csv_read_column(
+                input.as_ptr() as *const c_char,
+                input.len(),
+                column.as_ptr(),
+                format.as_ptr(),
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn message(input: &str, field: &Schema) -> String {
+        match read_column(input, field) {
+            Err(Error::InvalidArgument(message)) => message,
+            Err(error) => panic!("unexpected error {error:?}"),
+            Ok(_) => panic!("expected {:?} to fail", field.name),
+        }
+    }
+
+    #[test]
+    fn columns_are_read_with_nulls_for_empty_fields() {
+        let ids = read_ffi(FILE, "id", "l").unwrap();
+        assert_eq!(
+            ids.nullable_values::<i64>(),
+            [Some(1), Some(2), Some(3), Some(4)]
+        );
+        let names = read_ffi(FILE, "name", "u").unwrap();
+        let expected = [Some("a, \"b\""), None, Some(""), Some("two\nlines")];
+        assert_eq!(
+            names.strings(),
+            expected.map(|name| name.map(str::to_string))
+        );
+        let ok = read_ffi(FILE, "ok", "b").unwrap();
+        assert_eq!(ok.booleans(), [Some(true), Some(false), None, Some(true)]);
+        let prices = read_ffi(FILE, "price", "f")
+            .unwrap()
+            .nullable_values::<f32>();
+        assert_eq!(prices[0], Some(1.5));
+        assert!(prices[1].unwrap().is_nan());
+        assert_eq!(prices[2..], [None, Some(f32::NEG_INFINITY)]);
+    }
+
+    #[test]
+    fn temporal_columns_keep_their_format() {
+        let out = read_ffi("t\n10\n-20\n", "t", "tsu:UTC").unwrap();
+        assert_eq!(out.values::<i64>(), [10, -20]);
+        out.with_array(|array| {
+            assert_eq!(array.schema().format, "tsu:UTC");
+            assert_eq!(array.schema().name, "t");
+        });
+    }
+
+    #[test]
+    fn invalid_files_fail_with_their_line() {
+        let id = Schema::new(ArrowType::UInt8, "id");
+        assert_eq!(message("", &id), "the file has no header");
+        assert_eq!(message("x\n1", &id), "no column \"id\" in the header");
+        assert_eq!(
+            message("id,x\n1,2\n3", &Schema::new(ArrowType::Int64, "x")),
+            "1 fields in line 3, without the column \"x\""
+        );
+        assert_eq!(
+            message("id\n1\n256", &id),
+            "\"256\" in line 3 for the UInt8 column \"id\""
+        );
+        assert_eq!(
+            message("id\n\"1\n", &id),
+            "unterminated quote in the record of line 2"
+        );
+        assert_eq!(
+            message("id\n\"1\"2", &id),
+            "text after a closing quote in line 2"
+        );
+        let mut strict = id.clone();
+        strict.flags.nullable = false;
+        assert_eq!(
+            message("id\n1\n\n,", &strict),
+            "null in line 4 for the non-nullable column \"id\""
+        );
+        assert_eq!(
+            message("id\n\"\"", &id),
+            "\"\" in line 2 for the UInt8 column \"id\""
+        );
+    }
+
+    #[test]
+    fn invalid_arguments_fail_through_ffi() {
+        assert!(matches!(
+            read_ffi(FILE, "id", "+s"),
+            Err(ArrowUdfStatus::UnsupportedType)
+        ));
+        assert!(matches!(
+            read_ffi(FILE, "id", "?"),
+            Err(ArrowUdfStatus::UnsupportedType)
+        ));
+        assert!(matches!(
+            read_ffi(FILE, "missing", "l"),
+            Err(ArrowUdfStatus::InvalidArgument)
+        ));
+    }
+}
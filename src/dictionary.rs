@@ -0,0 +1,183 @@
+//! Dictionary encoding of string and binary arrays.
+//!
+//! UDFs producing strings, like the ones of `utf8`, often repeat a few
+//! distinct values over many rows. Encoding the result stores every distinct
+//! value once, in the dictionary, and an Int32 index per row into it, which
+//! hosts can keep as a categorical column instead of decoding it. The values
+//! get their index in the order they first appear, and nulls are null in
+//! the indices instead of being part of the dictionary.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::binary::BinaryBuilder;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+
+/// The Int32 indices of the elements of the Utf8, Binary or LargeBinary
+/// `array`, and the dictionary with its distinct values, of the same type.
+pub fn encode(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(ArrayData, ArrayData)> {
+    let large = match array.data_type() {
+        ArrowType::Utf8 | ArrowType::Binary => false,
+        ArrowType::LargeBinary => true,
+        other => {
+            return Err(Error::UnsupportedType(format!(
+                "dictionary encoding of {other:?} arrays"
+            )))
+        }
+    };
+    let mut dictionary = match large {
+        true => BinaryBuilder::large_with_capacity(0),
+        false => BinaryBuilder::with_capacity(0),
+    };
+    let mut positions: HashMap<&[u8], i32> = HashMap::new();
+    let mut indices: Vec<i32> = Vec::with_capacity(array.len());
+    let mut valid = BitmapBuilder::with_capacity(array.len());
+    exec::for_each_batch(array.len(), options, |rows| {
+        for i in rows {
+            if !array.is_valid(i) {
+                indices.push(0);
+                valid.push(false);
+                continue;
+            }
+            let value = array.binary_value(i);
+            let index = match positions.get(value) {
+                Some(index) => *index,
+                None => {
+                    let index = i32::try_from(positions.len()).map_err(|_| {
+                        Error::InvalidArgument(
+                            "more distinct values than Int32 indices".to_string(),
+                        )
+                    })?;
+                    positions.insert(value, index);
+                    dictionary.push(Some(value));
+                    index
+                }
+            };
+            indices.push(index);
+            valid.push(true);
+        }
+        Ok(())
+    })?;
+    let mut data = ArrayData::primitive(Buffer::from_slice(&indices), indices.len());
+    if array.null_count() > 0 {
+        data = data.with_validity(Some(valid.finish()), array.null_count());
+    }
+    Ok((data, dictionary.finish()))
+}
+
+/// Dictionary encoded copy of a Utf8, Binary or LargeBinary array, with an
+/// Int32 index per element into a dictionary of its distinct values, in the
+/// order they first appear. Nulls are null indices.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_dictionary_encode(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (indices, dictionary) = encode(&array, &options)?;
+        export::export_dictionary_to(
+            &Schema::new(ArrowType::Int32, &schema.name),
+            &Arc::new(indices),
+            &Schema::new(schema.data_type, ""),
+            &Arc::new(dictionary),
+            out_schema,
+            out_array,
+        )?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn run(input: &Exported) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_dictionary_encode(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    /// The format and the values of the dictionary of `out`.
+    fn dictionary(out: &Exported) -> (String, Vec<Vec<u8>>) {
+        let (schema, array) = unsafe { (&*out.schema.dictionary, &*out.array.dictionary) };
+        let format = unsafe { CStr::from_ptr(schema.format) };
+        let schema = unsafe { Schema::from_ffi(schema) }.unwrap();
+        let array = unsafe { ArrowArray::new(&schema, array) };
+        let values = (0..array.len())
+            .map(|i| array.binary_value(i).to_vec())
+            .collect();
+        (format.to_str().unwrap().to_string(), values)
+    }
+
+    #[test]
+    fn distinct_values_are_indexed_in_order_of_appearance() {
+        let input = Exported::utf8(&[Some("b"), Some("a"), None, Some("b"), Some(""), Some("a")]);
+        let out = run(&input).unwrap();
+        assert_eq!(
+            out.nullable_values::<i32>(),
+            [Some(0), Some(1), None, Some(0), Some(2), Some(1)]
+        );
+        let (format, values) = dictionary(&out);
+        assert_eq!(format, "u");
+        assert_eq!(values, [&b"b"[..], b"a", b""]);
+        let name = unsafe { CStr::from_ptr(out.schema.name) };
+        assert_eq!(name.to_str(), Ok("x"));
+    }
+
+    #[test]
+    fn large_binary_arrays_keep_their_type() {
+        let mut builder = BinaryBuilder::large_with_capacity(3);
+        for value in [&b"\xff"[..], b"\xff", b"\x00"] {
+            builder.push(Some(value));
+        }
+        let schema = Schema::new(ArrowType::LargeBinary, "x");
+        let input = Exported::new(&schema, builder.finish());
+        let out = run(&input).unwrap();
+        assert_eq!(out.values::<i32>(), [0, 0, 1]);
+        assert_eq!(out.with_array(|array| array.null_count()), 0);
+        let (format, values) = dictionary(&out);
+        assert_eq!(format, "Z");
+        assert_eq!(values, [&b"\xff"[..], b"\x00"]);
+    }
+
+    #[test]
+    fn other_types_are_unsupported() {
+        let input = Exported::primitive(&[1_i64]);
+        assert!(matches!(run(&input), Err(ArrowUdfStatus::UnsupportedType)));
+    }
+}
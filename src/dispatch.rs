@@ -0,0 +1,162 @@
+//! Dispatch of kernels to their implementation for the type of the input.
+//!
+//! Kernels are written once, generic over the native type of the values of
+//! the array they receive, and monomorphized for every supported type.
+//! Instead of matching on the type of the array in every entry point, a
+//! kernel implements `Kernel`, and `KernelRegistry` maps the kernel and the
+//! type of the array to the implementation for that type. The
+//! implementations of a kernel are registered the first time it's called.
+//!
+//! Supporting a new type means implementing `Numeric` for it, and adding it
+//! to `KernelRegistry::register_numeric`.
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, RwLock};
+
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::ArrowType;
+use crate::types::Numeric;
+
+/// Kernel generic over the type of the values of its input.
+pub(crate) trait Kernel: 'static {
+    /// Scalar arguments of the kernel, other than the array.
+    type Args: 'static;
+    type Output: 'static;
+
+    /// Name of the kernel in the errors, like `quantile`.
+    fn name(args: &Self::Args) -> String;
+
+    fn call<T: Numeric>(
+        array: &ArrowArray,
+        args: &Self::Args,
+        options: &ArrowUdfExecOptions,
+    ) -> Result<Self::Output>;
+}
+
+type KernelFn<K> =
+    fn(&ArrowArray, &<K as Kernel>::Args, &ArrowUdfExecOptions) -> Result<<K as Kernel>::Output>;
+
+/// Implementation of every kernel for every input type, by the `TypeId` of
+/// the kernel and the type. The values are `KernelFn<K>`.
+#[derive(Default)]
+pub(crate) struct KernelRegistry {
+    kernels: HashMap<(TypeId, ArrowType), Box<dyn Any + Send + Sync>>,
+    registered: HashSet<TypeId>,
+}
+
+static REGISTRY: LazyLock<RwLock<KernelRegistry>> = LazyLock::new(Default::default);
+
+impl KernelRegistry {
+    fn register<K: Kernel, T: Numeric>(&mut self) {
+        let kernel: KernelFn<K> = K::call::<T>;
+        self.kernels
+            .insert((TypeId::of::<K>(), T::ARROW_TYPE), Box::new(kernel));
+    }
+
+    /// Register the implementations of `K` for all the numeric types.
+    fn register_numeric<K: Kernel>(&mut self) {
+        self.register::<K, i8>();
+        self.register::<K, i16>();
+        self.register::<K, i32>();
+        self.register::<K, i64>();
+        self.register::<K, u8>();
+        self.register::<K, u16>();
+        self.register::<K, u32>();
+        self.register::<K, u64>();
+        self.register::<K, f32>();
+        self.register::<K, f64>();
+        self.registered.insert(TypeId::of::<K>());
+    }
+
+    /// The implementation of `K` for `data_type`, the outer `None` meaning
+    /// that `K` isn't registered yet.
+    fn get<K: Kernel>(&self, data_type: ArrowType) -> Option<Option<KernelFn<K>>> {
+        if !self.registered.contains(&TypeId::of::<K>()) {
+            return None;
+        }
+        let kernel = self.kernels.get(&(TypeId::of::<K>(), data_type));
+        Some(kernel.map(|kernel| *kernel.downcast_ref::<KernelFn<K>>().unwrap()))
+    }
+}
+
+/// Call the implementation of `K` for the type of `array`.
+pub(crate) fn call<K: Kernel>(
+    array: &ArrowArray,
+    args: &K::Args,
+    options: &ArrowUdfExecOptions,
+) -> Result<K::Output> {
+    let data_type = array.data_type();
+    let registered = REGISTRY.read().unwrap().get::<K>(data_type);
+    let kernel = match registered {
+        Some(kernel) => kernel,
+        None => {
+            let mut registry = REGISTRY.write().unwrap();
+            if !registry.registered.contains(&TypeId::of::<K>()) {
+                registry.register_numeric::<K>();
+            }
+            registry.get::<K>(data_type).flatten()
+        }
+    };
+    match kernel {
+        Some(kernel) => kernel(array, args, options),
+        None => Err(Error::UnsupportedType(format!(
+            "{} of {data_type:?} arrays",
+            K::name(args)
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    /// Sum of the values plus a constant, as a float.
+    struct SumPlus;
+
+    impl Kernel for SumPlus {
+        type Args = f64;
+        type Output = f64;
+
+        fn name(_args: &f64) -> String {
+            "sum_plus".to_string()
+        }
+
+        fn call<T: Numeric>(
+            array: &ArrowArray,
+            args: &f64,
+            _options: &ArrowUdfExecOptions,
+        ) -> Result<f64> {
+            Ok(array.values::<T>().iter().map(|x| x.to_f64()).sum::<f64>() + args)
+        }
+    }
+
+    #[test]
+    fn calls_the_implementation_for_the_input_type() {
+        let options = ArrowUdfExecOptions::default();
+        let ints = Exported::primitive(&[1_i8, -2, 4]);
+        let sum = ints.with_array(|array| call::<SumPlus>(array, &0.5, &options));
+        assert_eq!(sum.unwrap(), 3.5);
+        let floats = Exported::primitive(&[0.25_f32, 0.5]);
+        let sum = floats.with_array(|array| call::<SumPlus>(array, &1.0, &options));
+        assert_eq!(sum.unwrap(), 1.75);
+        let unsigned = Exported::primitive(&[u64::MAX]);
+        let sum = unsigned.with_array(|array| call::<SumPlus>(array, &0.0, &options));
+        assert_eq!(sum.unwrap(), u64::MAX as f64);
+    }
+
+    #[test]
+    fn unregistered_types_are_unsupported() {
+        let options = ArrowUdfExecOptions::default();
+        let booleans = Exported::boolean(&[true]);
+        match booleans.with_array(|array| call::<SumPlus>(array, &0.0, &options)) {
+            Err(Error::UnsupportedType(message)) => {
+                assert_eq!(message, "sum_plus of Boolean arrays")
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
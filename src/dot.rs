@@ -0,0 +1,287 @@
+//! Reductions over pairs of numeric arrays, like the scores of linear
+//! models.
+//!
+//! The two arrays must have the same length, but can have different numeric
+//! types, for example Int64 values and Float64 weights. Products are
+//! accumulated in `f64`, and pairs where any of the two elements is null are
+//! skipped.
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::nan;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::{with_native_type, Numeric};
+use crate::udf;
+
+fn sum_products<A: Numeric, B: Numeric>(
+    a: &ArrowArray,
+    b: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<f64> {
+    udf::check_input::<A>(a, options)?;
+    udf::check_input::<B>(b, options)?;
+    if udf::null_result(a, options)? || udf::null_result(b, options)? {
+        return Ok(f64::NAN);
+    }
+    let (a_masked, b_masked) = (
+        nan::mask_non_finite(a, options)?,
+        nan::mask_non_finite(b, options)?,
+    );
+    let a = &a_masked
+        .as_ref()
+        .map_or(*a, |masked| masked.array(a.schema()));
+    let b = &b_masked
+        .as_ref()
+        .map_or(*b, |masked| masked.array(b.schema()));
+    let (a_values, b_values) = (a.values::<A>(), b.values::<B>());
+    let a_validity = a.validity().filter(|_| a.null_count() > 0);
+    let b_validity = b.validity().filter(|_| b.null_count() > 0);
+    exec::reduce_values(
+        a_values,
+        options,
+        0.0,
+        |sum, rows| {
+            if a_validity.is_none() && b_validity.is_none() {
+                let products = a_values[rows.clone()].iter().zip(&b_values[rows]);
+                return sum + products.map(|(a, b)| a.to_f64() * b.to_f64()).sum::<f64>();
+            }
+            sum + rows
+                .filter(|i| {
+                    a_validity.is_none_or(|validity| validity.is_set(*i))
+                        && b_validity.is_none_or(|validity| validity.is_set(*i))
+                })
+                .map(|i| a_values[i].to_f64() * b_values[i].to_f64())
+                .sum::<f64>()
+        },
+        |a, b| a + b,
+    )
+}
+
+/// Sum of the products of the elements of `a` and `b` with the same index.
+/// NaN if any of them has nulls and the null policy propagates them.
+pub fn dot(a: &ArrowArray, b: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<f64> {
+    if a.len() != b.len() {
+        return Err(Error::InvalidArgument(format!(
+            "arrays of different lengths: {} and {}",
+            a.len(),
+            b.len()
+        )));
+    }
+    let unsupported = |data_type| Error::UnsupportedType(format!("dot of {data_type:?} arrays"));
+    with_native_type!(a.data_type(), A => {
+        with_native_type!(b.data_type(), B => {
+            sum_products::<A, B>(a, b, options)
+        }, _ => Err(unsupported(b.data_type())))
+    }, _ => Err(unsupported(a.data_type())))
+}
+
+/// Import the two arrays of an entry point, and write the sum of their
+/// products into `out`, or export it into the scalar structs of the options,
+/// null when the arrays have nulls and the null policy propagates them.
+unsafe fn dot_ffi(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out: *mut f64,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        if options.exports_scalar()
+            && (udf::null_result(&a, &options)? || udf::null_result(&b, &options)?)
+        {
+            return export::export_scalar::<f64>(&options, &a_schema.name, None);
+        }
+        export::write_reduction(&options, &a_schema.name, dot(&a, &b, &options)?, out)
+    })
+}
+
+/// Dot product of two numeric arrays of the same length, written into
+/// `out`. Positions where any of the arrays is null are skipped, or make the
+/// result NaN with the propagate null policy, or null when the options
+/// export the result as an array.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, and `b_schema` and `b_array`, must point to
+/// valid Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out` must be valid for writes, or null if the options export the result.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_dot(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out: *mut f64,
+) -> ArrowUdfStatus {
+    dot_ffi(a_schema, a_array, b_schema, b_array, options, out)
+}
+
+/// Sum of the elements of a numeric array multiplied by the weights of
+/// another numeric array of the same length, written into `out`. Values or
+/// weights that are null are skipped, or make the result NaN with the
+/// propagate null policy, or null when the options export the result as an
+/// array.
+///
+/// # Safety
+///
+/// `values_schema` and `values_array`, and `weights_schema` and
+/// `weights_array`, must point to valid Arrow C Data Interface arrays,
+/// `options` must be null or valid, and `out` must be valid for writes, or
+/// null if the options export the result.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_weighted_sum(
+    values_schema: *const ArrowCDataInterfaceSchema,
+    values_array: *const ArrowCDataInterfaceArray,
+    weights_schema: *const ArrowCDataInterfaceSchema,
+    weights_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out: *mut f64,
+) -> ArrowUdfStatus {
+    dot_ffi(
+        values_schema,
+        values_array,
+        weights_schema,
+        weights_array,
+        options,
+        out,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn run(
+        a: &Exported,
+        b: &Exported,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<f64, ArrowUdfStatus> {
+        let mut out = f64::NAN;
+        let status =
+            unsafe { arrow_udf_dot(&a.schema, &a.array, &b.schema, &b.array, options, &mut out) };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn products_of_mixed_types() {
+        let values = Exported::primitive(&[1_i64, -2, 3]);
+        let weights = Exported::primitive(&[0.5, 0.25, 2.0]);
+        assert_eq!(
+            run(&values, &weights, &ArrowUdfExecOptions::default()),
+            Ok(6.0)
+        );
+        let mut out = 0.0;
+        let status = unsafe {
+            arrow_udf_weighted_sum(
+                &values.schema,
+                &values.array,
+                &weights.schema,
+                &weights.array,
+                ptr::null(),
+                &mut out,
+            )
+        };
+        assert_eq!((status, out), (ArrowUdfStatus::Ok, 6.0));
+        let many: Vec<u8> = (0..1000).map(|i| (i % 3) as u8).collect();
+        let options = ArrowUdfExecOptions {
+            batch_size: 7,
+            num_threads: 3,
+            ..ArrowUdfExecOptions::default()
+        };
+        let many = Exported::primitive(&many);
+        assert_eq!(run(&many, &many, &options), Ok(333.0 + 4.0 * 333.0));
+    }
+
+    #[test]
+    fn pairs_with_nulls_are_skipped() {
+        let a = Exported::nullable(&[Some(1.0_f32), None, Some(3.0), Some(4.0)]);
+        let b = Exported::nullable(&[Some(2_u16), Some(5), None, Some(1)]);
+        assert_eq!(run(&a, &b, &ArrowUdfExecOptions::default()), Ok(6.0));
+        let error = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(run(&a, &b, &error), Err(ArrowUdfStatus::NullValue));
+    }
+
+    #[test]
+    fn arrays_must_match() {
+        let options = ArrowUdfExecOptions::default();
+        let a = Exported::primitive(&[1_i32, 2]);
+        let short = Exported::primitive(&[1_i32]);
+        assert_eq!(
+            run(&a, &short, &options),
+            Err(ArrowUdfStatus::InvalidArgument)
+        );
+        let booleans = Exported::boolean(&[true, false]);
+        assert_eq!(
+            run(&a, &booleans, &options),
+            Err(ArrowUdfStatus::UnsupportedType)
+        );
+        assert_eq!(
+            run(&booleans, &a, &options),
+            Err(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+
+    #[test]
+    fn nulls_are_propagated() {
+        let propagate = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let a = Exported::nullable(&[Some(1.0_f64), None]);
+        let b = Exported::primitive(&[2.0_f64, 3.0]);
+        assert!(run(&a, &b, &propagate).unwrap().is_nan());
+        assert!(run(&b, &a, &propagate).unwrap().is_nan());
+        assert_eq!(run(&b, &b, &propagate), Ok(13.0));
+    }
+
+    #[test]
+    fn products_are_exported_as_scalars_null_when_nulls_propagate() {
+        let a = Exported::nullable(&[Some(1.0_f64), None, Some(3.0)]);
+        let b = Exported::primitive(&[2.0_f64, 5.0, 1.0]);
+        for (null_policy, expected) in [
+            (crate::options::ARROW_UDF_NULL_POLICY_SKIP, Some(5.0)),
+            (crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE, None),
+        ] {
+            let mut scalar = Exported::empty();
+            let options = ArrowUdfExecOptions {
+                null_policy,
+                scalar_schema: &mut scalar.schema,
+                scalar_array: &mut scalar.array,
+                ..ArrowUdfExecOptions::default()
+            };
+            let status = unsafe {
+                arrow_udf_dot(
+                    &a.schema,
+                    &a.array,
+                    &b.schema,
+                    &b.array,
+                    &options,
+                    ptr::null_mut(),
+                )
+            };
+            assert_eq!(status, ArrowUdfStatus::Ok);
+            assert_eq!(scalar.nullable_values::<f64>(), [expected]);
+        }
+    }
+}
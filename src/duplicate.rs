@@ -0,0 +1,389 @@
+//! Marking of the rows whose value appeared earlier, to drop the duplicates
+//! of a column without sorting it.
+//!
+//! `is_duplicate` returns a Boolean array, true for every row whose value is
+//! in an earlier row, so hosts remove the duplicates by filtering with its
+//! negation. The values seen are kept in a hash set, by the canonical bytes
+//! of `hash`, so floats that compare equal, like `0.0` and `-0.0`, are
+//! duplicates, and so are all the NaN values. Nulls are a value of their
+//! own, like in `SELECT DISTINCT`: the first null isn't a duplicate, and the
+//! following ones are.
+//!
+//! `arrow_udf_is_duplicate_stream` marks the arrays of a stream, with a set
+//! shared by all of them, so the rows of a batch are duplicates of the rows
+//! of the previous batches too. The set grows with the distinct values of
+//! the stream, and its size is accounted to every call marking an array,
+//! so it's limited by the `memory_limit` of the options.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{
+    ArrowCDataInterfaceArray, ArrowCDataInterfaceArrayStream, ArrowCDataInterfaceSchema,
+};
+use crate::hash::{self, CanonicalBytes};
+use crate::memory::Reservation;
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::stream::{self, ArraySource, ArrayStream};
+use crate::types::with_native_type;
+
+/// The canonical bytes of a primitive value, in a word.
+fn word<T: CanonicalBytes>(value: T) -> u64 {
+    let mut bytes = [0; 8];
+    let value = value.canonical_bytes();
+    bytes[..value.as_ref().len()].copy_from_slice(value.as_ref());
+    u64::from_le_bytes(bytes)
+}
+
+/// Whether the rows of arrays of `data_type` can be marked.
+pub(crate) fn is_supported(data_type: ArrowType) -> bool {
+    let data_type = data_type.physical_type();
+    data_type == ArrowType::Boolean || hash::is_hashable(data_type)
+}
+
+/// The values seen in the arrays marked so far.
+#[derive(Default)]
+pub struct DuplicateSet {
+    /// Primitive and Boolean values, by their canonical bytes.
+    words: HashSet<u64>,
+    /// Binary and string values.
+    bytes: HashSet<Box<[u8]>>,
+    /// The length of all the values of `bytes`.
+    bytes_len: usize,
+    null: bool,
+}
+
+impl DuplicateSet {
+    /// Whether a null was seen, marking it as seen.
+    fn insert_null(&mut self) -> bool {
+        std::mem::replace(&mut self.null, true)
+    }
+
+    fn memory(&self) -> usize {
+        self.words.capacity() * size_of::<u64>()
+            + self.bytes.capacity() * size_of::<Box<[u8]>>()
+            + self.bytes_len
+    }
+
+    /// Boolean array without nulls, true for every row of `array` whose
+    /// value is in an earlier row, or in the arrays marked before with this
+    /// set, which must have the same type.
+    pub fn mark(&mut self, array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<ArrayData> {
+        if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+            return Err(Error::NullValue);
+        }
+        let validity = array.validity().filter(|_| array.null_count() > 0);
+        let valid = |i: usize| validity.is_none_or(|validity| validity.is_set(i));
+        let mut duplicates = BitmapBuilder::with_capacity(array.len());
+        let mut reservation = Reservation::new(self.memory());
+        let data_type = array.data_type().physical_type();
+        with_native_type!(data_type, T => {
+            let values = array.values::<T>();
+            exec::for_each_batch(array.len(), options, |rows| {
+                for i in rows {
+                    duplicates.push(match valid(i) {
+                        true => !self.words.insert(word(values[i])),
+                        false => self.insert_null(),
+                    });
+                }
+                reservation.resize(self.memory());
+                Ok(())
+            })?
+        }, _ => match data_type {
+            ArrowType::Boolean => {
+                let values = array.boolean_values();
+                exec::for_each_batch(array.len(), options, |rows| {
+                    for i in rows {
+                        duplicates.push(match valid(i) {
+                            true => !self.words.insert(values.is_set(i) as u64),
+                            false => self.insert_null(),
+                        });
+                    }
+                    Ok(())
+                })?
+            }
+            ArrowType::Binary
+            | ArrowType::LargeBinary
+            | ArrowType::Utf8
+            | ArrowType::FixedSizeBinary => {
+                let fixed = data_type == ArrowType::FixedSizeBinary;
+                exec::for_each_batch(array.len(), options, |rows| {
+                    for i in rows {
+                        if !valid(i) {
+                            duplicates.push(self.insert_null());
+                            continue;
+                        }
+                        let value = match fixed {
+                            true => array.fixed_size_binary_value(i),
+                            false => array.binary_value(i),
+                        };
+                        // Only new values are copied.
+                        let duplicate = self.bytes.contains(value);
+                        if !duplicate {
+                            self.bytes.insert(value.into());
+                            self.bytes_len += value.len();
+                        }
+                        duplicates.push(duplicate);
+                    }
+                    reservation.resize(self.memory());
+                    Ok(())
+                })?
+            }
+            other => {
+                return Err(Error::UnsupportedType(format!(
+                    "is_duplicate of {other:?} arrays"
+                )))
+            }
+        });
+        Ok(ArrayData::primitive(duplicates.finish(), array.len()))
+    }
+}
+
+/// Boolean array marking every row of `array` whose value is in an earlier
+/// row, with the name of `array`.
+pub fn is_duplicate(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let data = DuplicateSet::default().mark(array, options)?;
+    Ok((Schema::new(ArrowType::Boolean, &array.schema().name), data))
+}
+
+/// The arrays of a stream, marked with a set shared by all of them.
+pub struct DuplicateStream {
+    stream: ArrayStream,
+    schema: Schema,
+    seen: DuplicateSet,
+    options: ArrowUdfExecOptions,
+}
+
+impl DuplicateStream {
+    pub fn new(stream: ArrayStream, options: &ArrowUdfExecOptions) -> Result<DuplicateStream> {
+        let data_type = stream.schema().data_type;
+        if !is_supported(data_type) {
+            return Err(Error::UnsupportedType(format!(
+                "is_duplicate of {data_type:?} arrays"
+            )));
+        }
+        options.null_policy()?;
+        let schema = Schema::new(ArrowType::Boolean, &stream.schema().name);
+        Ok(DuplicateStream {
+            stream,
+            schema,
+            seen: DuplicateSet::default(),
+            options: *options,
+        })
+    }
+}
+
+impl ArraySource for DuplicateStream {
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn next(&mut self) -> Result<Option<ArrayData>> {
+        let Some(array) = self.stream.next_array()? else {
+            return Ok(None);
+        };
+        let data = self
+            .seen
+            .mark(&array.view(self.stream.schema()), &self.options)?;
+        Ok(Some(data))
+    }
+}
+
+/// Boolean array without nulls, true for every row of an array whose value
+/// is in an earlier row. Nulls are equal to each other.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_is_duplicate(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = is_duplicate(&array, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Stream of a Boolean array for every array of `stream`, like
+/// `arrow_udf_is_duplicate`, where the rows are also duplicates of the rows
+/// of the previous arrays, written into `out`.
+///
+/// The input stream is moved into the output stream, which reads it while
+/// it's consumed, and releases it when it's released, or when the call
+/// fails.
+///
+/// # Safety
+///
+/// `stream` must point to a valid C Stream Interface stream, `options` must
+/// be null or valid, and stay valid while the output stream is used, and
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_is_duplicate_stream(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    options: *const ArrowUdfExecOptions,
+    out: *mut ArrowCDataInterfaceArrayStream,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let input = ArrayStream::move_from_ffi(stream)?;
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let duplicates = DuplicateStream::new(input, &options)?;
+        stream::export_stream(Box::new(duplicates), out);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::binary::BinaryBuilder;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::row::ArrowField;
+    use crate::testing::{self, Exported};
+
+    fn run(
+        input: &Exported,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Vec<bool>, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_is_duplicate(
+                &input.schema,
+                &input.array,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out.booleans().into_iter().map(Option::unwrap).collect()),
+            status => Err(status),
+        }
+    }
+
+    fn marks(input: &Exported) -> Vec<bool> {
+        run(input, &ArrowUdfExecOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn later_rows_of_a_value_are_duplicates() {
+        let input = Exported::nullable(&[Some(3_i64), None, Some(1), Some(3), None, Some(1)]);
+        assert_eq!(marks(&input), [false, false, false, true, true, true]);
+        let floats = Exported::primitive(&[0.0_f64, -0.0, f64::NAN, -f64::NAN, 1.0]);
+        assert_eq!(marks(&floats), [false, true, false, true, false]);
+        let data = bool::build([Some(&true), Some(&false), None, Some(&true)].into_iter());
+        let booleans = Exported::new(&Schema::new(ArrowType::Boolean, "x"), data);
+        assert_eq!(marks(&booleans), [false, false, false, true]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let batched = Exported::primitive(&[1_u8, 2, 1, 3, 2]);
+        assert_eq!(
+            run(&batched, &options),
+            Ok(vec![false, false, true, false, true])
+        );
+    }
+
+    #[test]
+    fn strings_and_binaries_by_their_bytes() {
+        let input = Exported::utf8(&[Some("a"), Some("b"), None, Some("a"), Some(""), None]);
+        assert_eq!(marks(&input), [false, false, false, true, false, true]);
+        let mut builder = BinaryBuilder::large_with_capacity(3);
+        for value in [&b"\x00"[..], b"", b"\x00"] {
+            builder.push(Some(value));
+        }
+        let large = Exported::new(&Schema::new(ArrowType::LargeBinary, "x"), builder.finish());
+        assert_eq!(marks(&large), [false, false, true]);
+    }
+
+    #[test]
+    fn streams_share_the_values_seen() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        let batches = vec![
+            Exported::nullable(&[Some(1_i32), None, Some(2)]),
+            Exported::primitive(&[2_i32, 3]),
+            Exported::nullable(&[None, Some(1), Some(4)]),
+        ];
+        let mut input = testing::stream(&schema, batches, None);
+        let mut out = std::mem::MaybeUninit::uninit();
+        let status =
+            unsafe { arrow_udf_is_duplicate_stream(&mut input, ptr::null(), out.as_mut_ptr()) };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert!(input.release.is_none());
+        let mut out = unsafe { out.assume_init() };
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut out) }.unwrap();
+        assert_eq!(stream.schema(), &Schema::new(ArrowType::Boolean, "x"));
+        let mut marks = Vec::new();
+        stream
+            .for_each(|array| {
+                marks.push(array.boolean_values().iter().collect::<Vec<_>>());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            marks,
+            [
+                vec![false, false, false],
+                vec![true, false],
+                vec![true, true, false]
+            ]
+        );
+        drop(stream);
+        assert!(out.release.is_none());
+    }
+
+    #[test]
+    fn invalid_inputs_fail() {
+        let nulls = Exported::nullable(&[Some(1_i32), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(run(&nulls, &options), Err(ArrowUdfStatus::NullValue));
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Int32, "a")]);
+        let child = testing::nullable_data(&[Some(1_i32)]);
+        let structs = Exported::new(&schema, ArrayData::struct_array(vec![child], 1));
+        assert_eq!(
+            run(&structs, &ArrowUdfExecOptions::default()),
+            Err(ArrowUdfStatus::UnsupportedType)
+        );
+        let mut input = testing::stream(&schema, Vec::new(), None);
+        let mut out = std::mem::MaybeUninit::uninit();
+        let status =
+            unsafe { arrow_udf_is_duplicate_stream(&mut input, ptr::null(), out.as_mut_ptr()) };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+        assert!(input.release.is_none());
+        let limited = ArrowUdfExecOptions {
+            memory_limit: 1024,
+            ..ArrowUdfExecOptions::default()
+        };
+        let values: Vec<i64> = (0..10_000).collect();
+        assert_eq!(
+            run(&Exported::primitive(&values), &limited),
+            Err(ArrowUdfStatus::OutOfBudget)
+        );
+    }
+}
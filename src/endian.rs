@@ -0,0 +1,430 @@
+//! Byte order of the arrays received from the host.
+//!
+//! The C Data Interface doesn't describe the byte order of the buffers: they
+//! are in the order of the machine, since producer and consumer share the
+//! process. Producers handing over buffers read as they are from big-endian
+//! IPC files, or from another machine, break that rule, and their numbers
+//! would be read wrong without any error. Such producers can say it with the
+//! `ARROW_UDF:endianness` metadata of the fields, `little` or `big`, and
+//! `Schema::from_ffi` fails with `ArrowUdfStatus::UnsupportedEndianness` for
+//! fields in the order not of the machine.
+//!
+//! With the `byteswap` feature, `arrow_udf_to_native_endianness` copies those
+//! arrays into arrays in the order of the machine, byte-swapping their
+//! values and offsets, which every entry point can use.
+
+use crate::error::{Error, Result};
+use crate::schema::{Metadata, Schema};
+
+/// Metadata key with the byte order of the buffers of a field.
+pub const ENDIANNESS_KEY: &str = "ARROW_UDF:endianness";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// The byte order of the machine.
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Endianness = Endianness::Little;
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Endianness = Endianness::Big;
+
+    /// The byte order declared in `metadata`, if any.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Option<Endianness>> {
+        match metadata.get(ENDIANNESS_KEY) {
+            None => Ok(None),
+            Some("little") => Ok(Some(Endianness::Little)),
+            Some("big") => Ok(Some(Endianness::Big)),
+            Some(other) => Err(Error::InvalidArgument(format!(
+                "{ENDIANNESS_KEY} must be \"little\" or \"big\", got {other:?}"
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Endianness::Little => "little-endian",
+            Endianness::Big => "big-endian",
+        }
+    }
+}
+
+/// Whether the buffers of `schema`, or of any of its children, aren't in the
+/// byte order of the machine.
+pub fn is_foreign(schema: &Schema) -> Result<bool> {
+    if Endianness::from_metadata(&schema.metadata)?.is_some_and(|e| e != Endianness::NATIVE) {
+        return Ok(true);
+    }
+    for child in &schema.children {
+        if is_foreign(child)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Fail with `Error::UnsupportedEndianness` when the buffers of `schema`
+/// aren't in the byte order of the machine.
+pub fn check(schema: &Schema) -> Result<()> {
+    match is_foreign(schema)? {
+        false => Ok(()),
+        true => Err(Error::UnsupportedEndianness(format!(
+            "field {:?} has {} buffers, on a {} machine{}",
+            schema.name,
+            match Endianness::NATIVE {
+                Endianness::Little => Endianness::Big.name(),
+                Endianness::Big => Endianness::Little.name(),
+            },
+            Endianness::NATIVE.name(),
+            match cfg!(feature = "byteswap") {
+                true => ", convert it with arrow_udf_to_native_endianness",
+                false => "",
+            }
+        ))),
+    }
+}
+
+#[cfg(feature = "byteswap")]
+pub use byteswap::*;
+
+#[cfg(feature = "byteswap")]
+mod byteswap {
+    use std::mem;
+
+    use super::{Endianness, ENDIANNESS_KEY};
+    use crate::array::ArrowArray;
+    use crate::bitmap::{Bitmap, BitmapBuilder};
+    use crate::buffer::Buffer;
+    use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+    use crate::export::{self, ArrayData};
+    use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+    use crate::options::ArrowUdfExecOptions;
+    use crate::schema::{ArrowType, Metadata, Schema};
+    use crate::types::with_native_type;
+
+    /// `schema` without the byte order of its fields, for the converted
+    /// arrays.
+    pub fn native_schema(schema: &Schema) -> Schema {
+        Schema {
+            metadata: Metadata(
+                schema
+                    .metadata
+                    .0
+                    .iter()
+                    .filter(|(key, _)| key != ENDIANNESS_KEY)
+                    .cloned()
+                    .collect(),
+            ),
+            children: schema.children.iter().map(native_schema).collect(),
+            ..schema.clone()
+        }
+    }
+
+    /// Bytes of every value of a fixed width type.
+    fn width(data_type: ArrowType) -> Option<usize> {
+        match data_type.physical_type() {
+            ArrowType::Decimal128 => Some(16),
+            data_type => with_native_type!(data_type, T => Some(mem::size_of::<T>()), _ => None),
+        }
+    }
+
+    /// Copy of the `len` bits of `buffer` from `start`.
+    unsafe fn copy_bits(buffer: *const u8, start: usize, len: usize) -> Option<Buffer> {
+        if buffer.is_null() {
+            return None;
+        }
+        let bytes = std::slice::from_raw_parts(buffer, (start + len).div_ceil(8));
+        let bits = Bitmap::new(bytes, start, len);
+        let mut builder = BitmapBuilder::with_capacity(len);
+        for bit in bits.iter() {
+            builder.push(bit);
+        }
+        Some(builder.finish())
+    }
+
+    /// The `len` rows from `array.offset() + skip` of `array`, with buffers
+    /// in the order of the machine, whose order is `swap` if it isn't given
+    /// by the metadata of the field.
+    unsafe fn convert(
+        array: &ArrowArray,
+        skip: usize,
+        len: usize,
+        swap: bool,
+    ) -> Result<ArrayData> {
+        let schema = array.schema();
+        let swap = match Endianness::from_metadata(&schema.metadata)? {
+            Some(endianness) => endianness != Endianness::NATIVE,
+            None => swap,
+        };
+        let ffi = array.ffi();
+        let start = array.offset() + skip;
+        let validity = match ffi.n_buffers {
+            0 => None,
+            _ => copy_bits(ffi.buffer(0), start, len),
+        };
+        let null_count = validity.as_ref().map_or(0, |validity| {
+            len - Bitmap::new(validity.as_slice(), 0, len).count_set()
+        });
+        let data = match array.data_type() {
+            ArrowType::Boolean => {
+                let values = copy_bits(ffi.buffer(1), start, len).unwrap_or_default();
+                ArrayData::primitive(values, len)
+            }
+            ArrowType::Binary | ArrowType::Utf8 => {
+                let read = |i: usize| {
+                    let offset = (ffi.buffer(1) as *const i32)
+                        .add(start + i)
+                        .read_unaligned();
+                    match swap {
+                        true => offset.swap_bytes(),
+                        false => offset,
+                    }
+                };
+                let offsets: Vec<i32> = match len {
+                    0 => vec![0],
+                    _ => (0..=len).map(read).collect(),
+                };
+                let (first, last) = (offsets[0], offsets[len]);
+                if first < 0 || last < first {
+                    return Err(Error::InvalidArgument(format!(
+                        "offsets from {first} to {last} of field {:?}",
+                        schema.name
+                    )));
+                }
+                let offsets: Vec<i32> = offsets.iter().map(|offset| offset - first).collect();
+                let data = match last - first {
+                    0 => Buffer::new(),
+                    n => Buffer::from_slice(std::slice::from_raw_parts(
+                        ffi.buffer(2).add(first as usize),
+                        n as usize,
+                    )),
+                };
+                ArrayData {
+                    length: len,
+                    null_count: 0,
+                    buffers: vec![None, Some(Buffer::from_slice(&offsets)), Some(data)],
+                    children: Vec::new(),
+                }
+            }
+            ArrowType::Struct => {
+                let children = (0..ffi.n_children as usize)
+                    .map(|i| convert(&array.child(i), start, len, swap))
+                    .collect::<Result<Vec<_>>>()?;
+                ArrayData::struct_array(children, len)
+            }
+            data_type => {
+                let Some(width) = width(data_type) else {
+                    return Err(Error::UnsupportedType(format!(
+                        "conversion of {data_type:?} arrays to the byte order of the machine"
+                    )));
+                };
+                let mut values = match len {
+                    0 => Vec::new(),
+                    _ => std::slice::from_raw_parts(ffi.buffer(1).add(start * width), len * width)
+                        .to_vec(),
+                };
+                if swap {
+                    for value in values.chunks_exact_mut(width) {
+                        value.reverse();
+                    }
+                }
+                ArrayData::primitive(Buffer::from_slice(&values), len)
+            }
+        };
+        Ok(data.with_validity(validity, null_count))
+    }
+
+    /// Copy of `array` with the buffers in the byte order of the machine,
+    /// swapping the bytes of the values and the offsets of the fields in the
+    /// other order.
+    pub fn to_native(array: &ArrowArray) -> Result<ArrayData> {
+        unsafe { convert(array, 0, array.len(), false) }
+    }
+
+    /// Copy `array` into `out_array`, with the buffers of its fields whose
+    /// `ARROW_UDF:endianness` metadata isn't the byte order of the machine
+    /// byte-swapped, so it can be passed to any entry point. `out_schema` is
+    /// the schema without the byte order. Primitive, Boolean, Binary, Utf8,
+    /// temporal, decimal and struct arrays are supported.
+    ///
+    /// # Safety
+    ///
+    /// `schema` and `array` must be a valid Arrow C Data Interface array,
+    /// `options` must be null or valid, and `out_schema` and `out_array` must
+    /// be valid for writes.
+    #[no_mangle]
+    pub unsafe extern "C" fn arrow_udf_to_native_endianness(
+        schema: *const ArrowCDataInterfaceSchema,
+        array: *const ArrowCDataInterfaceArray,
+        options: *const ArrowUdfExecOptions,
+        out_schema: *mut ArrowCDataInterfaceSchema,
+        out_array: *mut ArrowCDataInterfaceArray,
+    ) -> ArrowUdfStatus {
+        ffi_guard(|| {
+            ArrowUdfExecOptions::from_ffi(options)?;
+            let schema = Schema::from_ffi_any_endianness(&*schema)?;
+            let native = native_schema(&schema);
+            let data = to_native(&ArrowArray::import(&schema, &*array)?)?;
+            out_schema.write(export::export_schema(&native));
+            out_array.write(export::export_array(data));
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ArrowUdfStatus;
+    use crate::schema::ArrowType;
+    use crate::testing::{nullable_data, Exported};
+
+    /// The `ARROW_UDF:endianness` values of the order of the machine and of
+    /// the other order.
+    const NATIVE: &str = match Endianness::NATIVE {
+        Endianness::Little => "little",
+        Endianness::Big => "big",
+    };
+    const FOREIGN: &str = match Endianness::NATIVE {
+        Endianness::Little => "big",
+        Endianness::Big => "little",
+    };
+
+    fn foreign(schema: Schema) -> Schema {
+        schema.with_metadata(ENDIANNESS_KEY, FOREIGN)
+    }
+
+    #[test]
+    fn the_byte_order_is_read_from_the_metadata() {
+        let schema = Schema::new(ArrowType::Int32, "x");
+        assert_eq!(Endianness::from_metadata(&schema.metadata), Ok(None));
+        let little = schema.clone().with_metadata(ENDIANNESS_KEY, "little");
+        assert_eq!(
+            Endianness::from_metadata(&little.metadata),
+            Ok(Some(Endianness::Little))
+        );
+        let big = schema.clone().with_metadata(ENDIANNESS_KEY, "big");
+        assert_eq!(
+            Endianness::from_metadata(&big.metadata),
+            Ok(Some(Endianness::Big))
+        );
+        let invalid = schema.with_metadata(ENDIANNESS_KEY, "middle");
+        assert!(matches!(
+            Endianness::from_metadata(&invalid.metadata),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn fields_in_the_other_byte_order_are_rejected() {
+        let native = Schema::new(ArrowType::Int32, "x");
+        assert_eq!(check(&native), Ok(()));
+        let declared = native.clone().with_metadata(ENDIANNESS_KEY, NATIVE);
+        assert_eq!(check(&declared), Ok(()));
+        assert!(matches!(
+            check(&foreign(native.clone())),
+            Err(Error::UnsupportedEndianness(_))
+        ));
+        // Children in the other order are found too.
+        let parent = Schema::new(ArrowType::Struct, "s").with_children(vec![
+            native.clone(),
+            foreign(Schema::new(ArrowType::Int64, "y")),
+        ]);
+        assert_eq!(is_foreign(&parent), Ok(true));
+    }
+
+    #[test]
+    fn imported_schemas_are_checked() {
+        let schema = foreign(Schema::new(ArrowType::Int32, "x"));
+        let input = Exported::new(&schema, nullable_data(&[Some(1_i32)]));
+        let imported = unsafe { Schema::from_ffi(&input.schema) };
+        assert_eq!(
+            imported.map_err(|e| e.status()).err(),
+            Some(ArrowUdfStatus::UnsupportedEndianness)
+        );
+        let imported = unsafe { Schema::from_ffi_any_endianness(&input.schema) };
+        assert_eq!(imported, Ok(schema));
+    }
+
+    #[cfg(feature = "byteswap")]
+    mod byteswap {
+        use std::ptr;
+
+        use super::*;
+        use crate::buffer::Buffer;
+        use crate::export::ArrayData;
+
+        fn to_native_endianness(input: &Exported) -> std::result::Result<Exported, ArrowUdfStatus> {
+            let mut out = Exported::empty();
+            let status = unsafe {
+                arrow_udf_to_native_endianness(
+                    &input.schema,
+                    &input.array,
+                    ptr::null(),
+                    &mut out.schema,
+                    &mut out.array,
+                )
+            };
+            match status {
+                ArrowUdfStatus::Ok => Ok(out),
+                status => Err(status),
+            }
+        }
+
+        #[test]
+        fn values_are_byte_swapped() {
+            let values = [Some(1_i32), None, Some(-300)];
+            let swapped = values.map(|value| value.map(i32::swap_bytes));
+            let schema = foreign(Schema::new(ArrowType::Int32, "x"));
+            let mut input = Exported::new(&schema, nullable_data(&swapped));
+            (input.array.offset, input.array.length) = (1, 2);
+            let out = to_native_endianness(&input).unwrap();
+            assert_eq!(out.nullable_values::<i32>(), [None, Some(-300)]);
+            out.with_array(|array| assert!(array.schema().metadata.is_empty()));
+
+            // Arrays already in the order of the machine are only copied.
+            let input = Exported::nullable(&values);
+            let out = to_native_endianness(&input).unwrap();
+            assert_eq!(out.nullable_values::<i32>(), values);
+        }
+
+        #[test]
+        fn offsets_are_byte_swapped() {
+            let offsets = [0_i32, 1, 1, 4].map(i32::swap_bytes);
+            let data = ArrayData {
+                length: 3,
+                null_count: 0,
+                buffers: vec![
+                    None,
+                    Some(Buffer::from_slice(&offsets)),
+                    Some(Buffer::from_slice(b"abcd")),
+                ],
+                children: Vec::new(),
+            };
+            let input = Exported::new(&foreign(Schema::new(ArrowType::Utf8, "x")), data);
+            let out = to_native_endianness(&input).unwrap();
+            let expected = [Some("a"), Some(""), Some("bcd")].map(|s| s.map(String::from));
+            assert_eq!(out.strings(), expected);
+        }
+
+        #[test]
+        fn only_the_fields_in_the_other_order_are_swapped() {
+            let schema = Schema::new(ArrowType::Struct, "s").with_children(vec![
+                Schema::new(ArrowType::Int16, "native"),
+                foreign(Schema::new(ArrowType::Int64, "foreign")),
+            ]);
+            let children = vec![
+                nullable_data(&[Some(1_i16), Some(2)]),
+                nullable_data(&[Some(3_i64.swap_bytes()), None]),
+            ];
+            let input = Exported::new(&schema, ArrayData::struct_array(children, 2));
+            let out = to_native_endianness(&input).unwrap();
+            assert_eq!(out.child_values::<i16>(0), [Some(1), Some(2)]);
+            assert_eq!(out.child_values::<i64>(1), [Some(3), None]);
+            out.with_array(|array| assert_eq!(array.schema(), &native_schema(&schema)));
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Errors, and how they are reported to the host.
+//!
+//! Every entry point returns an `ArrowUdfStatus`. When it's not `Ok`, a
+//! description of the error can be obtained with `arrow_udf_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::alias;
+use crate::memory::{self, MemoryTracker};
+use crate::metrics::{self, CallMetrics};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArrowUdfStatus {
+    Ok = 0,
+    InvalidArgument = 1,
+    UnsupportedType = 2,
+    NullValue = 3,
+    Panic = 4,
+    Cancelled = 5,
+    NonFiniteValue = 6,
+    OutOfBudget = 7,
+    Io = 8,
+    UnsupportedEndianness = 9,
+    InvalidUtf8 = 10,
+    AliasingError = 11,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidArgument(String),
+    UnsupportedType(String),
+    NullValue,
+    Panic(String),
+    Cancelled,
+    NonFiniteValue,
+    OutOfBudget,
+    Io(String),
+    UnsupportedEndianness(String),
+    /// The element at this position of a Utf8 array isn't valid UTF-8.
+    InvalidUtf8(usize),
+    /// An array is used by several calls at once, or a result overlaps an
+    /// input, see `alias`.
+    Aliasing(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn status(&self) -> ArrowUdfStatus {
+        match self {
+            Error::InvalidArgument(_) => ArrowUdfStatus::InvalidArgument,
+            Error::UnsupportedType(_) => ArrowUdfStatus::UnsupportedType,
+            Error::NullValue => ArrowUdfStatus::NullValue,
+            Error::Panic(_) => ArrowUdfStatus::Panic,
+            Error::Cancelled => ArrowUdfStatus::Cancelled,
+            Error::NonFiniteValue => ArrowUdfStatus::NonFiniteValue,
+            Error::OutOfBudget => ArrowUdfStatus::OutOfBudget,
+            Error::Io(_) => ArrowUdfStatus::Io,
+            Error::UnsupportedEndianness(_) => ArrowUdfStatus::UnsupportedEndianness,
+            Error::InvalidUtf8(_) => ArrowUdfStatus::InvalidUtf8,
+            Error::Aliasing(_) => ArrowUdfStatus::AliasingError,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {msg}"),
+            Error::UnsupportedType(msg) => write!(f, "unsupported type: {msg}"),
+            Error::NullValue => write!(f, "null value found with the error null policy"),
+            Error::Panic(msg) => write!(f, "panic: {msg}"),
+            Error::Cancelled => write!(f, "cancelled by the host"),
+            Error::NonFiniteValue => {
+                write!(f, "NaN or infinite value found with the error NaN policy")
+            }
+            Error::OutOfBudget => write!(f, "the memory budget of the call was exceeded"),
+            Error::Io(msg) => write!(f, "I/O error: {msg}"),
+            Error::UnsupportedEndianness(msg) => write!(f, "unsupported endianness: {msg}"),
+            Error::InvalidUtf8(i) => write!(f, "element {i} is not valid UTF-8"),
+            Error::Aliasing(msg) => write!(f, "aliasing: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &Error) {
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run the body of an entry point, converting its errors and panics into
+/// a status code. The memory it allocates is accounted in a new tracker,
+/// its metrics are written where the options ask for them, and the arrays
+/// it imports are claimed until it returns. Errors unwinding as the payload,
+/// like the allocation failures of buffers, keep their status.
+///
+/// With `panic = "abort"`, panics can't be caught, and the arrays are
+/// validated when they are imported instead, see `validate`.
+pub(crate) fn ffi_guard<F: FnOnce() -> Result<()>>(f: F) -> ArrowUdfStatus {
+    let started = Instant::now();
+    let tracker = Arc::new(MemoryTracker::default());
+    let call_metrics = Arc::new(CallMetrics::default());
+    let f = || {
+        metrics::with_metrics(Some(call_metrics.clone()), || {
+            memory::with_tracker(Some(tracker.clone()), || alias::with_claims(f))
+        })
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        if let Some(error) = payload.downcast_ref::<Error>() {
+            return Err(error.clone());
+        }
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Error::Panic(message))
+    });
+    call_metrics.write(call_metrics.snapshot(started.elapsed(), tracker.allocated()));
+    match result {
+        Ok(()) => ArrowUdfStatus::Ok,
+        Err(error) => {
+            set_last_error(&error);
+            error.status()
+        }
+    }
+}
+
+/// Description of the last error that happened in the calling thread.
+///
+/// The returned string is owned by the library, and is valid until the
+/// next error in the same thread. Null if no error happened.
+#[no_mangle]
+pub extern "C" fn arrow_udf_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    fn last_error() -> String {
+        let message = arrow_udf_last_error();
+        unsafe { CStr::from_ptr(message) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn errors_and_panics_become_statuses() {
+        assert_eq!(ffi_guard(|| Ok(())), ArrowUdfStatus::Ok);
+        let status = ffi_guard(|| Err(Error::InvalidArgument("bad".to_string())));
+        assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+        assert_eq!(last_error(), "invalid argument: bad");
+        assert_eq!(
+            ffi_guard(|| Err(Error::NullValue)),
+            ArrowUdfStatus::NullValue
+        );
+        let status = ffi_guard(|| Err(Error::InvalidUtf8(4)));
+        assert_eq!(status, ArrowUdfStatus::InvalidUtf8);
+        assert_eq!(last_error(), "element 4 is not valid UTF-8");
+        let status = ffi_guard(|| Err(Error::Aliasing("shared".to_string())));
+        assert_eq!(status, ArrowUdfStatus::AliasingError);
+        assert_eq!(last_error(), "aliasing: shared");
+        let status = ffi_guard(|| panic!("kernel {} failed", 3));
+        assert_eq!(status, ArrowUdfStatus::Panic);
+        assert_eq!(last_error(), "panic: kernel 3 failed");
+    }
+}
@@ -0,0 +1,397 @@
+//! Execution of kernels over the rows of an array, honoring the batch size
+//! and the number of threads of the execution options.
+//!
+//! The rows are split in as many contiguous parts as threads, and each
+//! thread processes its part one batch at a time. Between batches, the
+//! scratch arena of the thread is reset, the progress is reported, and the
+//! cancellation flag of the host and the memory limit are checked.
+//!
+//! With the `numa` feature, reductions over the values of an array run the
+//! thread of every part on the NUMA node holding its memory.
+
+use std::ops::Range;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::arena;
+use crate::error::Result;
+use crate::memory;
+use crate::metrics;
+use crate::options::ArrowUdfExecOptions;
+
+/// Contiguous parts of `0..len`, one per thread, made of whole batches.
+fn parts(len: usize, options: &ArrowUdfExecOptions) -> Vec<Range<usize>> {
+    let batch_len = options.batch_len(len);
+    let n_batches = len.div_ceil(batch_len);
+    let n_parts = options.threads().min(n_batches).max(1);
+    let part_len = n_batches.div_ceil(n_parts) * batch_len;
+    (0..n_parts)
+        .map(|i| (i * part_len).min(len)..((i + 1) * part_len).min(len))
+        .collect()
+}
+
+/// Rows processed by all the threads of a kernel, reported to the progress
+/// callback of the host.
+struct Progress<'a> {
+    options: &'a ArrowUdfExecOptions,
+    total: usize,
+    processed: AtomicUsize,
+    last_reported: Mutex<usize>,
+}
+
+impl<'a> Progress<'a> {
+    fn new(options: &'a ArrowUdfExecOptions, total: usize) -> Progress<'a> {
+        Progress {
+            options,
+            total,
+            processed: AtomicUsize::new(0),
+            last_reported: Mutex::new(0),
+        }
+    }
+
+    fn advance(&self, rows: usize) {
+        let Some(callback) = self.options.progress_callback else {
+            return;
+        };
+        let processed = self.processed.fetch_add(rows, Ordering::Relaxed) + rows;
+        let mut last_reported = self.last_reported.lock().unwrap();
+        let interval = self.options.progress_interval as usize;
+        if processed > *last_reported
+            && (processed - *last_reported >= interval || processed == self.total)
+        {
+            *last_reported = processed;
+            unsafe {
+                callback(
+                    processed as i64,
+                    self.total as i64,
+                    self.options.progress_user_data,
+                )
+            };
+        }
+    }
+}
+
+fn batches(part: Range<usize>, batch_len: usize) -> impl Iterator<Item = Range<usize>> {
+    part.clone()
+        .step_by(batch_len)
+        .map(move |start| start..(start + batch_len).min(part.end))
+}
+
+/// Reduce `0..len`, folding every batch into an accumulator with `fold`,
+/// and combining the accumulators of the different threads with `combine`.
+pub fn reduce<A, F, C>(
+    len: usize,
+    options: &ArrowUdfExecOptions,
+    init: A,
+    fold: F,
+    combine: C,
+) -> Result<A>
+where
+    A: Clone + Send + Sync,
+    F: Fn(A, Range<usize>) -> A + Sync,
+    C: Fn(A, A) -> A,
+{
+    reduce_parts(len, options, init, fold, combine, &|_| {})
+}
+
+/// Reduce the rows of `values` like `reduce`. With the `numa` feature, the
+/// thread of every part runs on the NUMA node holding its first value.
+pub fn reduce_values<T, A, F, C>(
+    values: &[T],
+    options: &ArrowUdfExecOptions,
+    init: A,
+    fold: F,
+    combine: C,
+) -> Result<A>
+where
+    T: Sync,
+    A: Clone + Send + Sync,
+    F: Fn(A, Range<usize>) -> A + Sync,
+    C: Fn(A, A) -> A,
+{
+    #[cfg(all(feature = "numa", target_os = "linux"))]
+    let on_thread = |part: &Range<usize>| {
+        if let Some(value) = values.get(part.start) {
+            crate::numa::bind_to_memory((value as *const T).cast());
+        }
+    };
+    #[cfg(not(all(feature = "numa", target_os = "linux")))]
+    let on_thread = |_: &Range<usize>| {};
+    reduce_parts(values.len(), options, init, fold, combine, &on_thread)
+}
+
+/// Reduce `0..len`, calling `on_thread` with its part first in every thread
+/// started.
+fn reduce_parts<A, F, C>(
+    len: usize,
+    options: &ArrowUdfExecOptions,
+    init: A,
+    fold: F,
+    combine: C,
+    on_thread: &(dyn Fn(&Range<usize>) + Sync),
+) -> Result<A>
+where
+    A: Clone + Send + Sync,
+    F: Fn(A, Range<usize>) -> A + Sync,
+    C: Fn(A, A) -> A,
+{
+    let batch_len = options.batch_len(len);
+    let progress = Progress::new(options, len);
+    let run_part = |part: Range<usize>| {
+        batches(part, batch_len).try_fold(init.clone(), |acc, rows| {
+            let batch_len = rows.len();
+            let acc = fold(acc, rows);
+            arena::reset_scratch();
+            progress.advance(batch_len);
+            options.check_cancelled()?;
+            options.check_memory()?;
+            Ok(acc)
+        })
+    };
+    let run_part = &run_part;
+    let parts = parts(len, options);
+    let result = if parts.len() == 1 {
+        run_part(parts[0].clone())?
+    } else {
+        thread::scope(|scope| {
+            let handles: Vec<_> = parts
+                .into_iter()
+                .map(|part| {
+                    scope.spawn(in_call(move || {
+                        on_thread(&part);
+                        run_part(part)
+                    }))
+                })
+                .collect();
+            handles
+                .into_iter()
+                // Keeps the payload of panics, like the errors of buffers.
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+                })
+                .try_fold(init.clone(), |acc, part| Ok(combine(acc, part?)))
+        })?
+    };
+    metrics::record_rows(len);
+    Ok(result)
+}
+
+/// Fill `out` batch by batch, calling `fill` with the range of rows of each
+/// batch and the slice of `out` corresponding to it. The first error
+/// returned by `fill` stops the execution.
+pub fn map<O, F>(out: &mut [O], options: &ArrowUdfExecOptions, fill: F) -> Result<()>
+where
+    O: Send,
+    F: Fn(Range<usize>, &mut [O]) -> Result<()> + Sync,
+{
+    let len = out.len();
+    let batch_len = options.batch_len(len);
+    let progress = Progress::new(options, len);
+    let run_part = |part: Range<usize>, out: &mut [O]| -> Result<()> {
+        for (batch, chunk) in batches(part, batch_len).zip(out.chunks_mut(batch_len)) {
+            fill(batch, chunk)?;
+            arena::reset_scratch();
+            progress.advance(chunk.len());
+            options.check_cancelled()?;
+            options.check_memory()?;
+        }
+        Ok(())
+    };
+    let run_part = &run_part;
+    let parts = parts(len, options);
+    if parts.len() == 1 {
+        run_part(0..len, out)?;
+    } else {
+        thread::scope(|scope| {
+            let mut rest = out;
+            let mut handles = Vec::with_capacity(parts.len());
+            for part in parts {
+                let (chunk, tail) = rest.split_at_mut(part.len());
+                rest = tail;
+                handles.push(scope.spawn(in_call(move || run_part(part, chunk))));
+            }
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+        })?;
+    }
+    metrics::record_rows(len);
+    Ok(())
+}
+
+/// Call `f` with the range of rows of every batch of `0..len`, one after the
+/// other in the current thread, for kernels whose state can't be split among
+/// threads. The first error returned by `f` stops the execution.
+pub fn for_each_batch<F>(len: usize, options: &ArrowUdfExecOptions, mut f: F) -> Result<()>
+where
+    F: FnMut(Range<usize>) -> Result<()>,
+{
+    let progress = Progress::new(options, len);
+    for rows in batches(0..len, options.batch_len(len)) {
+        let batch_len = rows.len();
+        f(rows)?;
+        arena::reset_scratch();
+        progress.advance(batch_len);
+        options.check_cancelled()?;
+        options.check_memory()?;
+    }
+    metrics::record_rows(len);
+    Ok(())
+}
+
+/// Wrap `f` to run in another thread with the memory tracker and the
+/// metrics of the current call.
+fn in_call<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let (tracker, call_metrics) = (memory::current(), metrics::current());
+    move || metrics::with_metrics(call_metrics, || memory::with_tracker(tracker, f))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::error::Error;
+
+    fn options(batch_size: i64, num_threads: i32) -> ArrowUdfExecOptions {
+        ArrowUdfExecOptions {
+            batch_size,
+            num_threads,
+            ..ArrowUdfExecOptions::default()
+        }
+    }
+
+    #[test]
+    fn parts_are_made_of_whole_batches() {
+        assert_eq!(parts(10, &options(3, 2)), [0..6, 6..10]);
+        assert_eq!(parts(10, &options(0, 4)).len(), 1);
+        assert!(parts(0, &options(3, 2)).iter().all(|part| part.is_empty()));
+        assert_eq!(batches(6..10, 3).collect::<Vec<_>>(), [6..9, 9..10]);
+    }
+
+    #[test]
+    fn reduce_and_map_cover_every_row_once() {
+        let options = options(7, 3);
+        let sum = reduce(
+            100,
+            &options,
+            0,
+            |sum, rows| sum + rows.sum::<usize>(),
+            |a, b| a + b,
+        );
+        assert_eq!(sum, Ok((0..100).sum::<usize>()));
+        let mut out = vec![0; 100];
+        map(&mut out, &options, |rows, out| {
+            for (out, i) in out.iter_mut().zip(rows) {
+                *out += i;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(out, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cancellation_stops_after_the_current_batch() {
+        let cancel = AtomicBool::new(false);
+        let options = ArrowUdfExecOptions {
+            cancel_flag: &cancel,
+            ..options(10, 1)
+        };
+        let batches = AtomicUsize::new(0);
+        let result = reduce(
+            100,
+            &options,
+            (),
+            |_, _| {
+                batches.fetch_add(1, Ordering::Relaxed);
+                cancel.store(true, Ordering::Relaxed);
+            },
+            |_, _| (),
+        );
+        assert_eq!(result, Err(Error::Cancelled));
+        assert_eq!(batches.load(Ordering::Relaxed), 1);
+        let mut out = vec![0; 100];
+        let options = ArrowUdfExecOptions {
+            num_threads: 4,
+            ..options
+        };
+        assert_eq!(
+            map(&mut out, &options, |_, _| Ok(())),
+            Err(Error::Cancelled)
+        );
+    }
+
+    #[test]
+    fn batches_run_in_order_until_an_error() {
+        let mut seen = Vec::new();
+        for_each_batch(10, &options(4, 4), |rows| {
+            seen.push(rows);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(seen, [0..4, 4..8, 8..10]);
+        let mut calls = 0;
+        let result = for_each_batch(10, &options(4, 4), |_| {
+            calls += 1;
+            Err(Error::NullValue)
+        });
+        assert_eq!((result, calls), (Err(Error::NullValue), 1));
+    }
+
+    unsafe extern "C" fn record(processed: i64, total: i64, user_data: *mut c_void) {
+        let calls = &*(user_data as *const Mutex<Vec<(i64, i64)>>);
+        calls.lock().unwrap().push((processed, total));
+    }
+
+    #[test]
+    fn progress_is_reported_every_interval() {
+        let calls = Mutex::new(Vec::<(i64, i64)>::new());
+        let options = ArrowUdfExecOptions {
+            progress_callback: Some(record),
+            progress_interval: 25,
+            progress_user_data: &calls as *const _ as *mut c_void,
+            ..options(10, 1)
+        };
+        reduce(100, &options, (), |_, _| (), |_, _| ()).unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [(30, 100), (60, 100), (90, 100), (100, 100)]
+        );
+        calls.lock().unwrap().clear();
+        let mut out = vec![0; 100];
+        let options = ArrowUdfExecOptions {
+            num_threads: 3,
+            ..options
+        };
+        map(&mut out, &options, |_, _| Ok(())).unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.last(), Some(&(100, 100)));
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn reduce_values_covers_every_value_once() {
+        let values: Vec<u64> = (0..1000).collect();
+        for options in [options(7, 3), options(0, 1), options(64, 0)] {
+            let sum = reduce_values(
+                &values,
+                &options,
+                0,
+                |sum, rows| sum + values[rows].iter().sum::<u64>(),
+                |a, b| a + b,
+            );
+            assert_eq!(sum, Ok(499_500));
+        }
+        let empty =
+            reduce_values::<u64, _, _, _>(&[], &options(7, 3), 1, |a, _| a + 1, |a, b| a * b);
+        assert_eq!(empty, Ok(1));
+    }
+}
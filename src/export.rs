@@ -0,0 +1,405 @@
+//! Export of the arrays produced by this library through the C Data Interface.
+//!
+//! Exported arrays and schemas own their memory, which is freed when the
+//! consumer calls their `release` callback.
+
+use std::any::Any;
+use std::ffi::{c_void, CString};
+use std::ptr;
+use std::sync::Arc;
+
+use crate::alias;
+use crate::buffer::Buffer;
+use crate::error::Result;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+
+/// Owned array data, ready to be exported.
+pub struct ArrayData {
+    pub length: usize,
+    pub null_count: usize,
+    /// Buffers in the order defined by the layout of the type. `None` is
+    /// exported as a null pointer, for example for an absent validity bitmap.
+    pub buffers: Vec<Option<Buffer>>,
+    pub children: Vec<ArrayData>,
+}
+
+impl ArrayData {
+    /// Array of a primitive type without nulls.
+    pub fn primitive(values: Buffer, length: usize) -> ArrayData {
+        ArrayData {
+            length,
+            null_count: 0,
+            buffers: vec![None, Some(values)],
+            children: Vec::new(),
+        }
+    }
+
+    /// Attach a validity bitmap with `null_count` nulls to the array.
+    pub fn with_validity(mut self, validity: Option<Buffer>, null_count: usize) -> ArrayData {
+        self.buffers[0] = validity;
+        self.null_count = null_count;
+        self
+    }
+
+    /// Struct array without nulls, with a child array per field.
+    pub fn struct_array(children: Vec<ArrayData>, length: usize) -> ArrayData {
+        ArrayData {
+            length,
+            null_count: 0,
+            buffers: vec![None],
+            children,
+        }
+    }
+
+    /// Map array without nulls, with the entries of every element between
+    /// two consecutive `offsets`, and the `keys` and `values` of all of them.
+    pub fn map_array(
+        offsets: Buffer,
+        keys: ArrayData,
+        values: ArrayData,
+        length: usize,
+    ) -> ArrayData {
+        let entries = keys.length;
+        ArrayData {
+            length,
+            null_count: 0,
+            buffers: vec![None, Some(offsets)],
+            children: vec![ArrayData::struct_array(vec![keys, values], entries)],
+        }
+    }
+
+    /// Union array with the `type_ids` of every element and the arrays of
+    /// its `fields`, and for dense unions the `offsets` of every element in
+    /// the field of its type. Unions have no validity bitmap.
+    pub fn union_array(
+        type_ids: Buffer,
+        offsets: Option<Buffer>,
+        fields: Vec<ArrayData>,
+        length: usize,
+    ) -> ArrayData {
+        let mut buffers = vec![Some(type_ids)];
+        if offsets.is_some() {
+            buffers.push(offsets);
+        }
+        ArrayData {
+            length,
+            null_count: 0,
+            buffers,
+            children: fields,
+        }
+    }
+
+    /// Run-end encoded array of `length` elements, all of them equal to `value`.
+    pub fn constant<T: NativeType>(value: T, length: usize) -> ArrayData {
+        ArrayData {
+            length,
+            null_count: 0,
+            buffers: Vec::new(),
+            children: vec![
+                ArrayData::primitive(Buffer::from_slice(&[length as i64]), 1),
+                ArrayData::primitive(Buffer::from_slice(&[value]), 1),
+            ],
+        }
+    }
+}
+
+/// Schema of the arrays created with `ArrayData::constant`.
+pub fn constant_schema(value_type: ArrowType, name: &str) -> Schema {
+    Schema::new(ArrowType::RunEndEncoded, name).with_children(vec![
+        Schema::new(ArrowType::Int64, "run_ends"),
+        Schema::new(value_type, "values"),
+    ])
+}
+
+struct PrivateArrayData {
+    // Keeps the buffers alive. It can be shared by the children, and by other
+    // exported arrays.
+    _owner: Arc<dyn Any + Send + Sync>,
+    buffer_ptrs: Vec<*const c_void>,
+    children: Vec<*mut ArrowCDataInterfaceArray>,
+    /// The dictionary of dictionary encoded arrays, or null.
+    dictionary: *mut ArrowCDataInterfaceArray,
+}
+
+struct PrivateSchemaData {
+    _format: CString,
+    _name: CString,
+    _metadata: Option<Vec<u8>>,
+    children: Vec<*mut ArrowCDataInterfaceSchema>,
+    dictionary: *mut ArrowCDataInterfaceSchema,
+}
+
+/// Move `data` into a C Data Interface array.
+pub fn export_array(data: ArrayData) -> ArrowCDataInterfaceArray {
+    export_shared(&Arc::new(data))
+}
+
+/// Export `data` without copying it, so it can be exported again. The memory
+/// is freed when the last exported array is released and `data` is dropped.
+pub fn export_shared(data: &Arc<ArrayData>) -> ArrowCDataInterfaceArray {
+    let owner: Arc<dyn Any + Send + Sync> = data.clone();
+    export_view(data, &owner)
+}
+
+fn export_view(data: &ArrayData, owner: &Arc<dyn Any + Send + Sync>) -> ArrowCDataInterfaceArray {
+    let buffer_ptrs: Vec<*const c_void> = data
+        .buffers
+        .iter()
+        .map(|buffer| match buffer {
+            Some(buffer) => buffer.as_ptr() as *const c_void,
+            None => ptr::null(),
+        })
+        .collect();
+    let children: Vec<*mut ArrowCDataInterfaceArray> = data
+        .children
+        .iter()
+        .map(|child| Box::into_raw(Box::new(export_view(child, owner))))
+        .collect();
+    let mut private_data = Box::new(PrivateArrayData {
+        _owner: owner.clone(),
+        buffer_ptrs,
+        children,
+        dictionary: ptr::null_mut(),
+    });
+    ArrowCDataInterfaceArray {
+        length: data.length as i64,
+        null_count: data.null_count as i64,
+        offset: 0,
+        n_buffers: private_data.buffer_ptrs.len() as i64,
+        n_children: private_data.children.len() as i64,
+        buffers: private_data.buffer_ptrs.as_mut_ptr(),
+        children: private_data.children.as_mut_ptr(),
+        dictionary: ptr::null_mut(),
+        release: Some(release_array),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    }
+}
+
+/// Export `array`, owned by `owner`, sharing its buffers and its children
+/// instead of copying them. They are released with `owner`, when it and the
+/// last array exported with it are dropped.
+///
+/// # Safety
+///
+/// `array` must be a valid, non released, C Data Interface array, kept
+/// alive by `owner`.
+pub unsafe fn export_foreign(
+    array: &ArrowCDataInterfaceArray,
+    owner: &Arc<dyn Any + Send + Sync>,
+) -> ArrowCDataInterfaceArray {
+    let buffer_ptrs: Vec<*const c_void> = (0..array.n_buffers as usize)
+        .map(|i| array.buffer(i) as *const c_void)
+        .collect();
+    let children: Vec<*mut ArrowCDataInterfaceArray> = (0..array.n_children as usize)
+        .map(|i| Box::into_raw(Box::new(export_foreign(array.child(i), owner))))
+        .collect();
+    let mut private_data = Box::new(PrivateArrayData {
+        _owner: owner.clone(),
+        buffer_ptrs,
+        children,
+        dictionary: ptr::null_mut(),
+    });
+    ArrowCDataInterfaceArray {
+        length: array.length,
+        null_count: array.null_count,
+        offset: array.offset,
+        n_buffers: private_data.buffer_ptrs.len() as i64,
+        n_children: private_data.children.len() as i64,
+        buffers: private_data.buffer_ptrs.as_mut_ptr(),
+        children: private_data.children.as_mut_ptr(),
+        dictionary: ptr::null_mut(),
+        release: Some(release_array),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    }
+}
+
+/// Create a C Data Interface schema describing `schema`.
+pub fn export_schema(schema: &Schema) -> ArrowCDataInterfaceSchema {
+    debug_assert_eq!(
+        ArrowType::from_format(&schema.format),
+        Some(schema.data_type),
+        "format {:?} of a {:?} schema",
+        schema.format,
+        schema.data_type
+    );
+    let format = CString::new(schema.format.as_str()).unwrap();
+    let name = CString::new(schema.name.as_str()).unwrap();
+    let metadata = (!schema.metadata.is_empty()).then(|| schema.metadata.to_bytes());
+    let children: Vec<*mut ArrowCDataInterfaceSchema> = schema
+        .children
+        .iter()
+        .map(|child| Box::into_raw(Box::new(export_schema(child))))
+        .collect();
+    let mut private_data = Box::new(PrivateSchemaData {
+        _format: format,
+        _name: name,
+        _metadata: metadata,
+        children,
+        dictionary: ptr::null_mut(),
+    });
+    ArrowCDataInterfaceSchema {
+        format: private_data._format.as_ptr(),
+        name: private_data._name.as_ptr(),
+        metadata: private_data
+            ._metadata
+            .as_ref()
+            .map_or(ptr::null(), |metadata| metadata.as_ptr() as *const _),
+        flags: schema.flags.bits(),
+        n_children: private_data.children.len() as i64,
+        children: private_data.children.as_mut_ptr(),
+        dictionary: ptr::null_mut(),
+        release: Some(release_schema),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    }
+}
+
+/// Export `value`, the result of a reduction over an array named `name`,
+/// into the scalar structs of `options`, as an array of one element, null
+/// if `value` is `None`.
+///
+/// # Safety
+///
+/// The scalar structs of `options` must be valid for writes, as
+/// `ArrowUdfExecOptions::from_ffi` requires.
+pub unsafe fn export_scalar<T: NativeType>(
+    options: &ArrowUdfExecOptions,
+    name: &str,
+    value: Option<T>,
+) -> Result<()> {
+    let validity = value.is_none().then(|| Buffer::from_slice(&[0u8]));
+    let data = ArrayData::primitive(Buffer::from_slice(&[value.unwrap_or_default()]), 1)
+        .with_validity(validity, usize::from(value.is_none()));
+    export_to(
+        &Schema::new(T::ARROW_TYPE, name),
+        &Arc::new(data),
+        options.scalar_schema,
+        options.scalar_array,
+    )
+}
+
+/// Write `value`, the result of a reduction over an array named `name`, into
+/// `out`, or export it like `export_scalar` if the options ask for it.
+///
+/// # Safety
+///
+/// `out` must be valid for writes, unless the options export the result.
+pub unsafe fn write_reduction<T: NativeType>(
+    options: &ArrowUdfExecOptions,
+    name: &str,
+    value: T,
+    out: *mut T,
+) -> Result<()> {
+    if options.exports_scalar() {
+        return export_scalar(options, name, Some(value));
+    }
+    out.write(value);
+    Ok(())
+}
+
+/// Write an exported schema and array into the structs provided by the host.
+/// Fails with `Error::Aliasing`, writing nothing, if `out_array` is an input
+/// of the running call or the buffers of `data` overlap an input, see
+/// `alias`.
+///
+/// # Safety
+///
+/// `out_schema` and `out_array` must be valid for writes. Whatever they
+/// contain is overwritten without being released.
+pub unsafe fn export_to(
+    schema: &Schema,
+    data: &Arc<ArrayData>,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> Result<()> {
+    alias::check_output(out_array, data)?;
+    out_schema.write(export_schema(schema));
+    out_array.write(export_shared(data));
+    Ok(())
+}
+
+/// Write a dictionary encoded array into the structs provided by the host,
+/// with the indices of every element in `indices`, described by `schema`,
+/// and the values they refer to in `dictionary`, described by
+/// `dictionary_schema`. Fails like `export_to`.
+///
+/// # Safety
+///
+/// Like `export_to`.
+pub unsafe fn export_dictionary_to(
+    schema: &Schema,
+    indices: &Arc<ArrayData>,
+    dictionary_schema: &Schema,
+    dictionary: &Arc<ArrayData>,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> Result<()> {
+    alias::check_output(out_array, indices)?;
+    alias::check_output(out_array, dictionary)?;
+    let mut exported_schema = export_schema(schema);
+    let mut exported_array = export_shared(indices);
+    let schema_data = &mut *(exported_schema.private_data as *mut PrivateSchemaData);
+    schema_data.dictionary = Box::into_raw(Box::new(export_schema(dictionary_schema)));
+    exported_schema.dictionary = schema_data.dictionary;
+    let array_data = &mut *(exported_array.private_data as *mut PrivateArrayData);
+    array_data.dictionary = Box::into_raw(Box::new(export_shared(dictionary)));
+    exported_array.dictionary = array_data.dictionary;
+    out_schema.write(exported_schema);
+    out_array.write(exported_array);
+    Ok(())
+}
+
+unsafe extern "C" fn release_array(array: *mut ArrowCDataInterfaceArray) {
+    if array.is_null() || (*array).release.is_none() {
+        return;
+    }
+    let private_data = Box::from_raw((*array).private_data as *mut PrivateArrayData);
+    let dictionary = (!private_data.dictionary.is_null()).then_some(private_data.dictionary);
+    for child in private_data.children.iter().copied().chain(dictionary) {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    drop(private_data);
+    (*array).release = None;
+}
+
+unsafe extern "C" fn release_schema(schema: *mut ArrowCDataInterfaceSchema) {
+    if schema.is_null() || (*schema).release.is_none() {
+        return;
+    }
+    let private_data = Box::from_raw((*schema).private_data as *mut PrivateSchemaData);
+    let dictionary = (!private_data.dictionary.is_null()).then_some(private_data.dictionary);
+    for child in private_data.children.iter().copied().chain(dictionary) {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    drop(private_data);
+    (*schema).release = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::array::ArrowArray;
+    use crate::schema::ArrowType;
+
+    #[test]
+    fn shared_data_outlives_its_exports() {
+        let schema = constant_schema(ArrowType::Int32, "x");
+        let data = Arc::new(ArrayData::constant(5_i32, 3));
+        let mut first = export_shared(&data);
+        let mut second = export_shared(&data);
+        drop(data);
+        unsafe { first.release.unwrap()(&mut first) };
+        assert!(first.release.is_none());
+        let array = unsafe { ArrowArray::new(&schema, &second) };
+        assert_eq!(array.constant_value::<i32>(), Some(5));
+        unsafe { second.release.unwrap()(&mut second) };
+    }
+}
@@ -0,0 +1,224 @@
+//! Vectorized evaluation of expressions, one batch of rows at a time.
+//!
+//! Every node of the expression is evaluated into a vector with a value per
+//! row of the batch. Columns are borrowed from the input arrays, and
+//! literals are broadcast to the length of the batch.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use super::{BinaryOp, Expr, Function, Scalar, UnaryOp};
+use crate::array::ArrowArray;
+use crate::error::Result;
+use crate::schema::ArrowType;
+use crate::types::NativeType;
+
+/// Values of an input column, for all the rows of the array.
+#[derive(Clone, Copy, Debug)]
+pub enum Column<'a> {
+    Int64(&'a [i64]),
+    Float64(&'a [f64]),
+}
+
+impl<'a> Column<'a> {
+    /// Values of `array`, which must be Int64 or Float64.
+    pub fn new(array: &ArrowArray<'a>) -> Column<'a> {
+        match array.data_type() {
+            ArrowType::Int64 => Column::Int64(array.values()),
+            ArrowType::Float64 => Column::Float64(array.values()),
+            data_type => unreachable!("column of type {data_type:?}"),
+        }
+    }
+}
+
+/// Values of an expression for the rows of a batch.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Values<'a> {
+    Int64(Cow<'a, [i64]>),
+    Float64(Cow<'a, [f64]>),
+    Boolean(Cow<'a, [bool]>),
+}
+
+impl Values<'_> {
+    /// Copy the values into `out`, which must have their type and length.
+    pub fn copy_to<T: NativeType>(&self, out: &mut [T]) {
+        let bytes = match self {
+            Values::Int64(values) => bytemuck(values),
+            Values::Float64(values) => bytemuck(values),
+            Values::Boolean(_) => panic!("Boolean values can't be copied to {:?}", T::ARROW_TYPE),
+        };
+        assert_eq!(bytes.len(), std::mem::size_of_val(out));
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), out.as_mut_ptr() as *mut u8, bytes.len())
+        };
+    }
+}
+
+fn bytemuck<T: NativeType>(values: &[T]) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    }
+}
+
+fn unary<T: Copy, O>(values: &[T], f: impl Fn(T) -> O) -> Vec<O> {
+    values.iter().map(|value| f(*value)).collect()
+}
+
+fn binary<T: Copy, O>(left: &[T], right: &[T], f: impl Fn(T, T) -> O) -> Vec<O> {
+    left.iter().zip(right).map(|(l, r)| f(*l, *r)).collect()
+}
+
+fn compare<T: PartialOrd + Copy>(op: BinaryOp, left: &[T], right: &[T]) -> Vec<bool> {
+    match op {
+        BinaryOp::Eq => binary(left, right, |l, r| l == r),
+        BinaryOp::NotEq => binary(left, right, |l, r| l != r),
+        BinaryOp::Lt => binary(left, right, |l, r| l < r),
+        BinaryOp::LtEq => binary(left, right, |l, r| l <= r),
+        BinaryOp::Gt => binary(left, right, |l, r| l > r),
+        BinaryOp::GtEq => binary(left, right, |l, r| l >= r),
+        _ => unreachable!("{op:?} is not a comparison"),
+    }
+}
+
+fn is_comparison(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq
+    )
+}
+
+fn int64(op: BinaryOp, left: &[i64], right: &[i64]) -> Values<'static> {
+    if is_comparison(op) {
+        return Values::Boolean(compare(op, left, right).into());
+    }
+    Values::Int64(
+        match op {
+            BinaryOp::Add => binary(left, right, i64::wrapping_add),
+            BinaryOp::Sub => binary(left, right, i64::wrapping_sub),
+            BinaryOp::Mul => binary(left, right, i64::wrapping_mul),
+            BinaryOp::Rem => binary(left, right, |l, r| l.checked_rem(r).unwrap_or(0)),
+            _ => unreachable!("{op:?} of Int64 operands"),
+        }
+        .into(),
+    )
+}
+
+fn float64(op: BinaryOp, left: &[f64], right: &[f64]) -> Values<'static> {
+    if is_comparison(op) {
+        return Values::Boolean(compare(op, left, right).into());
+    }
+    Values::Float64(
+        match op {
+            BinaryOp::Add => binary(left, right, |l, r| l + r),
+            BinaryOp::Sub => binary(left, right, |l, r| l - r),
+            BinaryOp::Mul => binary(left, right, |l, r| l * r),
+            BinaryOp::Div => binary(left, right, |l, r| l / r),
+            BinaryOp::Rem => binary(left, right, |l, r| l % r),
+            _ => unreachable!("{op:?} of Float64 operands"),
+        }
+        .into(),
+    )
+}
+
+fn boolean(op: BinaryOp, left: &[bool], right: &[bool]) -> Values<'static> {
+    Values::Boolean(
+        match op {
+            BinaryOp::And => binary(left, right, |l, r| l && r),
+            BinaryOp::Or => binary(left, right, |l, r| l || r),
+            BinaryOp::Eq => binary(left, right, |l, r| l == r),
+            BinaryOp::NotEq => binary(left, right, |l, r| l != r),
+            _ => unreachable!("{op:?} of Boolean operands"),
+        }
+        .into(),
+    )
+}
+
+fn call(function: Function, args: &[Values]) -> Values<'static> {
+    match (function, args) {
+        (Function::Abs, [Values::Int64(values)]) => {
+            Values::Int64(unary(values, i64::wrapping_abs).into())
+        }
+        (Function::Abs, [Values::Float64(values)]) => {
+            Values::Float64(unary(values, f64::abs).into())
+        }
+        (Function::Sqrt, [Values::Float64(values)]) => {
+            Values::Float64(unary(values, f64::sqrt).into())
+        }
+        (Function::Floor, [Values::Float64(values)]) => {
+            Values::Float64(unary(values, f64::floor).into())
+        }
+        (Function::Ceil, [Values::Float64(values)]) => {
+            Values::Float64(unary(values, f64::ceil).into())
+        }
+        // Integers are already rounded.
+        (Function::Floor | Function::Ceil, [Values::Int64(values)]) => {
+            Values::Int64(values.to_vec().into())
+        }
+        (Function::Min, [Values::Int64(left), Values::Int64(right)]) => {
+            Values::Int64(binary(left, right, i64::min).into())
+        }
+        (Function::Min, [Values::Float64(left), Values::Float64(right)]) => {
+            Values::Float64(binary(left, right, f64::min).into())
+        }
+        (Function::Max, [Values::Int64(left), Values::Int64(right)]) => {
+            Values::Int64(binary(left, right, i64::max).into())
+        }
+        (Function::Max, [Values::Float64(left), Values::Float64(right)]) => {
+            Values::Float64(binary(left, right, f64::max).into())
+        }
+        _ => unreachable!("{function:?} with arguments of the wrong type"),
+    }
+}
+
+/// Evaluate `expr` for the `rows` of `columns`.
+pub fn eval<'a>(expr: &Expr, columns: &[Column<'a>], rows: Range<usize>) -> Result<Values<'a>> {
+    let len = rows.len();
+    Ok(match expr {
+        Expr::Column(index, _) => match columns[*index] {
+            Column::Int64(values) => Values::Int64(Cow::Borrowed(&values[rows])),
+            Column::Float64(values) => Values::Float64(Cow::Borrowed(&values[rows])),
+        },
+        Expr::Literal(Scalar::Int64(value)) => Values::Int64(vec![*value; len].into()),
+        Expr::Literal(Scalar::Float64(value)) => Values::Float64(vec![*value; len].into()),
+        Expr::Literal(Scalar::Boolean(value)) => Values::Boolean(vec![*value; len].into()),
+        Expr::Cast(expr) => match eval(expr, columns, rows)? {
+            Values::Int64(values) => Values::Float64(unary(&values, |value| value as f64).into()),
+            values => values,
+        },
+        Expr::Unary(op, expr) => match (op, eval(expr, columns, rows)?) {
+            (UnaryOp::Neg, Values::Int64(values)) => {
+                Values::Int64(unary(&values, i64::wrapping_neg).into())
+            }
+            (UnaryOp::Neg, Values::Float64(values)) => {
+                Values::Float64(unary(&values, |value| -value).into())
+            }
+            (UnaryOp::Not, Values::Boolean(values)) => {
+                Values::Boolean(unary(&values, |value| !value).into())
+            }
+            (op, _) => unreachable!("{op:?} of an operand of the wrong type"),
+        },
+        Expr::Binary(op, left, right, _) => {
+            match (
+                eval(left, columns, rows.clone())?,
+                eval(right, columns, rows)?,
+            ) {
+                (Values::Int64(left), Values::Int64(right)) => int64(*op, &left, &right),
+                (Values::Float64(left), Values::Float64(right)) => float64(*op, &left, &right),
+                (Values::Boolean(left), Values::Boolean(right)) => boolean(*op, &left, &right),
+                _ => unreachable!("{op:?} of operands of different types"),
+            }
+        }
+        Expr::Call(function, args, _) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, columns, rows.clone()))
+                .collect::<Result<Vec<_>>>()?;
+            call(*function, &args)
+        }
+    })
+}
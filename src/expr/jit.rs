@@ -0,0 +1,459 @@
+//! Compilation of expressions to native code with cranelift.
+//!
+//! The compiled function evaluates the whole expression row by row over the
+//! raw buffers of the columns, without the intermediate vectors of the
+//! interpreter. Compiling takes some time, so it only pays off when
+//! expressions are evaluated over many rows, or when compiled functions are
+//! reused from the cache in the context.
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, MemFlagsData, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use super::{BinaryOp, Column, Expr, Function, Scalar, UnaryOp};
+use crate::context::UdfContext;
+use crate::error::{Error, Result};
+use crate::schema::ArrowType;
+
+/// Number of compiled expressions kept by the cache of a context.
+pub const JIT_CACHE_CAPACITY: usize = 64;
+
+/// Signature of the compiled functions: the pointers to the values of every
+/// column, the output buffer, and the range of rows to evaluate.
+type Kernel = unsafe extern "C" fn(*const *const u8, *mut u8, i64, i64);
+
+/// Native code of an expression.
+pub struct CompiledExpr {
+    module: Option<JITModule>,
+    kernel: Kernel,
+    data_type: ArrowType,
+    n_columns: usize,
+}
+
+impl std::fmt::Debug for CompiledExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CompiledExpr")
+            .field("data_type", &self.data_type)
+            .finish_non_exhaustive()
+    }
+}
+
+// The code is never modified after compilation, and the module is only used
+// to free it when the expression is dropped.
+unsafe impl Send for CompiledExpr {}
+unsafe impl Sync for CompiledExpr {}
+
+impl Drop for CompiledExpr {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+impl CompiledExpr {
+    /// Evaluate the expression for the `rows` of `columns` into `out`, which
+    /// must have a value of the type of the expression per row.
+    pub fn eval<T: Copy>(&self, columns: &[Column], rows: Range<usize>, out: &mut [T]) {
+        assert_eq!(out.len(), rows.len());
+        assert_eq!(std::mem::size_of::<T>(), value_size(self.data_type));
+        assert!(columns.len() >= self.n_columns);
+        let values: Vec<*const u8> = columns
+            .iter()
+            .map(|column| match column {
+                Column::Int64(values) => {
+                    assert!(values.len() >= rows.end);
+                    values.as_ptr() as *const u8
+                }
+                Column::Float64(values) => {
+                    assert!(values.len() >= rows.end);
+                    values.as_ptr() as *const u8
+                }
+            })
+            .collect();
+        unsafe {
+            (self.kernel)(
+                values.as_ptr(),
+                out.as_mut_ptr() as *mut u8,
+                rows.start as i64,
+                rows.end as i64,
+            )
+        };
+    }
+}
+
+fn jit_error(err: impl std::fmt::Display) -> Error {
+    Error::UnsupportedType(format!("can't compile the expression: {err}"))
+}
+
+fn value_size(data_type: ArrowType) -> usize {
+    match data_type {
+        ArrowType::Boolean => 1,
+        _ => 8,
+    }
+}
+
+fn ir_type(data_type: ArrowType) -> Type {
+    match data_type {
+        ArrowType::Int64 => types::I64,
+        ArrowType::Float64 => types::F64,
+        _ => types::I8,
+    }
+}
+
+extern "C" fn fmod(left: f64, right: f64) -> f64 {
+    left % right
+}
+
+/// Compile `expr` into native code for the current machine.
+pub fn compile(expr: &Expr) -> Result<CompiledExpr> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(jit_error)?;
+    let isa = cranelift_native::builder()
+        .map_err(jit_error)?
+        .finish(settings::Flags::new(flags))
+        .map_err(jit_error)?;
+    let pointer = isa.pointer_type();
+    let frontend_config = isa.frontend_config();
+    if pointer != types::I64 {
+        return Err(jit_error("only 64 bits targets are supported"));
+    }
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("arrow_udf_fmod", fmod as *const u8);
+    let mut module = JITModule::new(builder);
+
+    let mut fmod_signature = module.make_signature();
+    fmod_signature.params = vec![AbiParam::new(types::F64), AbiParam::new(types::F64)];
+    fmod_signature.returns = vec![AbiParam::new(types::F64)];
+    let fmod_id = module
+        .declare_function("arrow_udf_fmod", Linkage::Import, &fmod_signature)
+        .map_err(jit_error)?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature.params = [pointer, pointer, types::I64, types::I64]
+        .into_iter()
+        .map(AbiParam::new)
+        .collect();
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+    let fmod = module.declare_func_in_func(fmod_id, b.func);
+
+    let entry = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    b.switch_to_block(entry);
+    b.seal_block(entry);
+    let &[columns, out, start, end] = b.block_params(entry) else {
+        unreachable!("the kernel has 4 parameters")
+    };
+    let mut used = Vec::new();
+    expr.columns(&mut used);
+    let n_columns = used.iter().map(|index| index + 1).max().unwrap_or(0);
+    let mut bases = vec![None; n_columns];
+    for index in used {
+        let offset = (index * 8) as i32;
+        bases[index] = Some(
+            b.ins()
+                .load(pointer, MemFlagsData::trusted(), columns, offset),
+        );
+    }
+
+    // for (i = start; i < end; i++) out[i - start] = expr(i)
+    let header = b.create_block();
+    let body = b.create_block();
+    let exit = b.create_block();
+    b.append_block_param(header, types::I64);
+    b.ins().jump(header, &[start.into()]);
+    b.switch_to_block(header);
+    let i = b.block_params(header)[0];
+    let more = b.ins().icmp(IntCC::SignedLessThan, i, end);
+    b.ins().brif(more, body, &[], exit, &[]);
+
+    b.switch_to_block(body);
+    b.seal_block(body);
+    let mut codegen = Codegen {
+        b: &mut b,
+        bases: &bases,
+        row: i,
+        fmod,
+    };
+    let value = codegen.expr(expr);
+    let size = value_size(expr.data_type()) as i64;
+    let position = b.ins().isub(i, start);
+    let offset = b.ins().imul_imm_s(position, size);
+    let address = b.ins().iadd(out, offset);
+    b.ins().store(MemFlagsData::trusted(), value, address, 0);
+    let next = b.ins().iadd_imm_s(i, 1);
+    b.ins().jump(header, &[next.into()]);
+    b.seal_block(header);
+
+    b.switch_to_block(exit);
+    b.seal_block(exit);
+    b.ins().return_(&[]);
+    b.finalize(frontend_config);
+
+    let id = module
+        .declare_function("arrow_udf_expression", Linkage::Export, &ctx.func.signature)
+        .map_err(jit_error)?;
+    module.define_function(id, &mut ctx).map_err(jit_error)?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().map_err(jit_error)?;
+    let code = module.get_finalized_function(id);
+    Ok(CompiledExpr {
+        kernel: unsafe { std::mem::transmute::<*const u8, Kernel>(code) },
+        module: Some(module),
+        data_type: expr.data_type(),
+        n_columns,
+    })
+}
+
+/// Translation of an expression to the instructions evaluating a row.
+struct Codegen<'a, 'b> {
+    b: &'a mut FunctionBuilder<'b>,
+    bases: &'a [Option<Value>],
+    row: Value,
+    fmod: FuncRef,
+}
+
+impl Codegen<'_, '_> {
+    fn expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Column(index, data_type) => {
+                let offset = self.b.ins().ishl_imm_s(self.row, 3);
+                let address = self.b.ins().iadd(self.bases[*index].unwrap(), offset);
+                self.b
+                    .ins()
+                    .load(ir_type(*data_type), MemFlagsData::trusted(), address, 0)
+            }
+            Expr::Literal(Scalar::Int64(value)) => self.b.ins().iconst(types::I64, *value),
+            Expr::Literal(Scalar::Float64(value)) => self.b.ins().f64const(*value),
+            Expr::Literal(Scalar::Boolean(value)) => self.b.ins().iconst(types::I8, *value as i64),
+            Expr::Cast(expr) => {
+                let value = self.expr(expr);
+                self.b.ins().fcvt_from_sint(types::F64, value)
+            }
+            Expr::Unary(op, expr) => {
+                let float = expr.data_type() == ArrowType::Float64;
+                let value = self.expr(expr);
+                match op {
+                    UnaryOp::Neg if float => self.b.ins().fneg(value),
+                    UnaryOp::Neg => self.b.ins().ineg(value),
+                    UnaryOp::Not => self.b.ins().bxor_imm_s(value, 1),
+                }
+            }
+            Expr::Binary(op, left, right, _) => {
+                let data_type = left.data_type();
+                let left = self.expr(left);
+                let right = self.expr(right);
+                match data_type {
+                    ArrowType::Float64 => self.float64(*op, left, right),
+                    _ => self.integer(*op, left, right),
+                }
+            }
+            Expr::Call(function, args, data_type) => {
+                let args: Vec<_> = args.iter().map(|arg| self.expr(arg)).collect();
+                let float = *data_type == ArrowType::Float64;
+                let ins = self.b.ins();
+                match (function, args.as_slice()) {
+                    (Function::Abs, [value]) if float => ins.fabs(*value),
+                    (Function::Abs, [value]) => ins.iabs(*value),
+                    (Function::Sqrt, [value]) => ins.sqrt(*value),
+                    (Function::Floor, [value]) if float => ins.floor(*value),
+                    (Function::Ceil, [value]) if float => ins.ceil(*value),
+                    (Function::Floor | Function::Ceil, [value]) => *value,
+                    (Function::Min, [left, right]) if float => {
+                        self.float_min_max(false, *left, *right)
+                    }
+                    (Function::Max, [left, right]) if float => {
+                        self.float_min_max(true, *left, *right)
+                    }
+                    (Function::Min, [left, right]) => ins.smin(*left, *right),
+                    (Function::Max, [left, right]) => ins.smax(*left, *right),
+                    _ => unreachable!("{function:?} with a wrong number of arguments"),
+                }
+            }
+        }
+    }
+
+    /// Operations on Int64 and Boolean operands.
+    fn integer(&mut self, op: BinaryOp, left: Value, right: Value) -> Value {
+        let ins = self.b.ins();
+        match op {
+            BinaryOp::Add => ins.iadd(left, right),
+            BinaryOp::Sub => ins.isub(left, right),
+            BinaryOp::Mul => ins.imul(left, right),
+            BinaryOp::Rem => {
+                // `x % 0` and `x % -1` are 0, the same as `x % 1`, which
+                // doesn't trap.
+                let plus_one = ins.iadd_imm_s(right, 1);
+                let trapping = self
+                    .b
+                    .ins()
+                    .icmp_imm_s(IntCC::UnsignedLessThanOrEqual, plus_one, 1);
+                let one = self.b.ins().iconst(types::I64, 1);
+                let divisor = self.b.ins().select(trapping, one, right);
+                self.b.ins().srem(left, divisor)
+            }
+            BinaryOp::And => ins.band(left, right),
+            BinaryOp::Or => ins.bor(left, right),
+            BinaryOp::Eq => ins.icmp(IntCC::Equal, left, right),
+            BinaryOp::NotEq => ins.icmp(IntCC::NotEqual, left, right),
+            BinaryOp::Lt => ins.icmp(IntCC::SignedLessThan, left, right),
+            BinaryOp::LtEq => ins.icmp(IntCC::SignedLessThanOrEqual, left, right),
+            BinaryOp::Gt => ins.icmp(IntCC::SignedGreaterThan, left, right),
+            BinaryOp::GtEq => ins.icmp(IntCC::SignedGreaterThanOrEqual, left, right),
+            BinaryOp::Div => unreachable!("division of Int64 operands"),
+        }
+    }
+
+    fn float64(&mut self, op: BinaryOp, left: Value, right: Value) -> Value {
+        let ins = self.b.ins();
+        match op {
+            BinaryOp::Add => ins.fadd(left, right),
+            BinaryOp::Sub => ins.fsub(left, right),
+            BinaryOp::Mul => ins.fmul(left, right),
+            BinaryOp::Div => ins.fdiv(left, right),
+            BinaryOp::Rem => {
+                let call = ins.call(self.fmod, &[left, right]);
+                self.b.inst_results(call)[0]
+            }
+            BinaryOp::Eq => ins.fcmp(FloatCC::Equal, left, right),
+            BinaryOp::NotEq => ins.fcmp(FloatCC::NotEqual, left, right),
+            BinaryOp::Lt => ins.fcmp(FloatCC::LessThan, left, right),
+            BinaryOp::LtEq => ins.fcmp(FloatCC::LessThanOrEqual, left, right),
+            BinaryOp::Gt => ins.fcmp(FloatCC::GreaterThan, left, right),
+            BinaryOp::GtEq => ins.fcmp(FloatCC::GreaterThanOrEqual, left, right),
+            BinaryOp::And | BinaryOp::Or => unreachable!("{op:?} of Float64 operands"),
+        }
+    }
+
+    /// Minimum or maximum ignoring NaN, like `f64::min` and `f64::max`.
+    fn float_min_max(&mut self, max: bool, left: Value, right: Value) -> Value {
+        let cond = if max {
+            FloatCC::GreaterThan
+        } else {
+            FloatCC::LessThan
+        };
+        let wins = self.b.ins().fcmp(cond, left, right);
+        let right_nan = self.b.ins().fcmp(FloatCC::Unordered, right, right);
+        let pick_left = self.b.ins().bor(wins, right_nan);
+        self.b.ins().select(pick_left, left, right)
+    }
+}
+
+/// Key of a compiled expression: its text, and the names and types of the
+/// columns it was compiled for.
+type CacheKey = (String, Vec<(String, ArrowType)>);
+
+/// Expressions compiled with a context, from the oldest to the newest.
+#[derive(Default)]
+struct JitCache {
+    entries: Mutex<Vec<(CacheKey, Arc<CompiledExpr>)>>,
+}
+
+/// Return the compiled `expr` from the cache of `context`, or compile it and
+/// cache it.
+pub fn cached(
+    context: Option<&UdfContext>,
+    text: &str,
+    columns: &[(&str, ArrowType)],
+    expr: &Expr,
+) -> Result<Arc<CompiledExpr>> {
+    let Some(context) = context else {
+        return compile(expr).map(Arc::new);
+    };
+    let key = (
+        text.to_string(),
+        columns
+            .iter()
+            .map(|(name, data_type)| (name.to_string(), *data_type))
+            .collect(),
+    );
+    let cache = context.get_or_insert_with(JitCache::default);
+    if let Some((_, compiled)) = cache
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(cached_key, _)| *cached_key == key)
+    {
+        return Ok(Arc::clone(compiled));
+    }
+    let compiled = Arc::new(compile(expr)?);
+    let mut entries = cache.entries.lock().unwrap();
+    if entries.len() == JIT_CACHE_CAPACITY {
+        entries.remove(0);
+    }
+    entries.push((key, Arc::clone(&compiled)));
+    Ok(compiled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{eval, Expression, Values};
+
+    const COLUMNS: &[(&str, ArrowType)] = &[("x", ArrowType::Int64), ("y", ArrowType::Float64)];
+
+    /// Debug output of the compiled and of the interpreted expression, which
+    /// compares NaNs as equal.
+    fn compiled_and_interpreted(text: &str) -> (String, String) {
+        let x = [3_i64, -7, 0, i64::MAX, i64::MIN];
+        let y = [0.5_f64, f64::NAN, -2.0, 0.0, 1e300];
+        let columns = [Column::Int64(&x), Column::Float64(&y)];
+        let expression = Expression::compile(text, COLUMNS).unwrap();
+        let compiled = compile(&expression.expr).unwrap();
+        let interpreted = eval::eval(&expression.expr, &columns, 1..5).unwrap();
+        let native = match expression.data_type() {
+            ArrowType::Int64 => {
+                let mut out = vec![0_i64; 4];
+                compiled.eval(&columns, 1..5, &mut out);
+                Values::Int64(out.into())
+            }
+            ArrowType::Float64 => {
+                let mut out = vec![0_f64; 4];
+                compiled.eval(&columns, 1..5, &mut out);
+                Values::Float64(out.into())
+            }
+            _ => {
+                let mut out = vec![false; 4];
+                compiled.eval(&columns, 1..5, &mut out);
+                Values::Boolean(out.into())
+            }
+        };
+        (format!("{native:?}"), format!("{interpreted:?}"))
+    }
+
+    #[test]
+    fn compiled_expressions_match_the_interpreter() {
+        for text in [
+            "abs(x - 5) + x * 2",
+            "x % 0 + x % 3 - -x",
+            "floor(x) + ceil(x)",
+            "min(x, 1) * max(x, -1)",
+            "x / y + sqrt(abs(y))",
+            "floor(y) - ceil(y) + y % 2",
+            "x > 0 or y < 0",
+            "not (x == 0) and y != y",
+        ] {
+            let (native, interpreted) = compiled_and_interpreted(text);
+            assert_eq!(native, interpreted, "{text}");
+        }
+    }
+
+    #[test]
+    fn compiled_expressions_are_cached_in_the_context() {
+        let context = UdfContext::new();
+        let expression = Expression::compile("x + 1", COLUMNS).unwrap();
+        let first = cached(Some(&context), "x + 1", COLUMNS, &expression.expr).unwrap();
+        let second = cached(Some(&context), "x + 1", COLUMNS, &expression.expr).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        let other = cached(Some(&context), "x + 1", &COLUMNS[..1], &expression.expr).unwrap();
+        assert!(!Arc::ptr_eq(&first, &other));
+    }
+}
@@ -0,0 +1,527 @@
+//! Expression language evaluated over Arrow arrays.
+//!
+//! Hosts can compute ad-hoc column math, like `abs(x - 5) + y * 2`, without
+//! compiling a new UDF. Names in the expression refer to the input arrays by
+//! the name in their schema. Supported input types are Int64 and Float64,
+//! and the result is Int64, Float64 or Boolean, depending on the expression:
+//!
+//! - `+`, `-`, `*` and `%` return Int64 when both operands are Int64, and
+//!   Float64 otherwise. Int64 arithmetic wraps on overflow, and `x % 0` is 0.
+//! - `/` always returns Float64.
+//! - Comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`) return Boolean, and can
+//!   be combined with `and`, `or` and `not`.
+//! - Functions: `abs`, `min`, `max`, `sqrt`, `floor` and `ceil`.
+//!
+//! A row of the result is null when any of the arrays used in the
+//! expression is null in that row.
+//!
+//! Expressions are evaluated by an interpreter. With the `jit` feature, and
+//! when the host sets the `ARROW_UDF_FLAG_JIT` flag, they are compiled to
+//! native code instead, falling back to the interpreter if compilation fails.
+//! Compiled expressions are cached in the context of the call, if any.
+
+mod eval;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod parser;
+
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+use crate::udf;
+
+pub use eval::{Column, Values};
+pub use parser::{parse, Ast, BinaryOp, UnaryOp};
+
+/// Host flag requesting expressions to be compiled to native code. Ignored
+/// when the library is built without the `jit` feature.
+pub const ARROW_UDF_FLAG_JIT: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scalar {
+    Int64(i64),
+    Float64(f64),
+    Boolean(bool),
+}
+
+impl Scalar {
+    pub fn data_type(&self) -> ArrowType {
+        match self {
+            Scalar::Int64(_) => ArrowType::Int64,
+            Scalar::Float64(_) => ArrowType::Float64,
+            Scalar::Boolean(_) => ArrowType::Boolean,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Min,
+    Max,
+    Sqrt,
+    Floor,
+    Ceil,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<(Function, usize)> {
+        Some(match name {
+            "abs" => (Function::Abs, 1),
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            "sqrt" => (Function::Sqrt, 1),
+            "floor" => (Function::Floor, 1),
+            "ceil" => (Function::Ceil, 1),
+            _ => return None,
+        })
+    }
+}
+
+/// Typed expression tree. Operands of binary operations and function calls
+/// always have the same type, casts are explicit.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Column(usize, ArrowType),
+    Literal(Scalar),
+    /// Conversion from Int64 to Float64.
+    Cast(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>, ArrowType),
+    Call(Function, Vec<Expr>, ArrowType),
+}
+
+impl Expr {
+    pub fn data_type(&self) -> ArrowType {
+        match self {
+            Expr::Column(_, data_type) => *data_type,
+            Expr::Literal(scalar) => scalar.data_type(),
+            Expr::Cast(_) => ArrowType::Float64,
+            Expr::Unary(_, expr) => expr.data_type(),
+            Expr::Binary(_, _, _, data_type) => *data_type,
+            Expr::Call(_, _, data_type) => *data_type,
+        }
+    }
+
+    /// Indices of the columns used in the expression.
+    pub fn columns(&self, columns: &mut Vec<usize>) {
+        match self {
+            Expr::Column(index, _) => {
+                if !columns.contains(index) {
+                    columns.push(*index);
+                }
+            }
+            Expr::Literal(_) => {}
+            Expr::Cast(expr) | Expr::Unary(_, expr) => expr.columns(columns),
+            Expr::Binary(_, left, right, _) => {
+                left.columns(columns);
+                right.columns(columns);
+            }
+            Expr::Call(_, args, _) => args.iter().for_each(|arg| arg.columns(columns)),
+        }
+    }
+}
+
+fn type_error(msg: String) -> Error {
+    Error::InvalidArgument(format!("invalid expression: {msg}"))
+}
+
+fn is_numeric(data_type: ArrowType) -> bool {
+    matches!(data_type, ArrowType::Int64 | ArrowType::Float64)
+}
+
+fn to_float(expr: Expr) -> Expr {
+    match expr.data_type() {
+        ArrowType::Int64 => Expr::Cast(Box::new(expr)),
+        _ => expr,
+    }
+}
+
+/// Cast numeric operands to a common type, Float64 if any of them is.
+fn unify(args: Vec<Expr>) -> Result<(Vec<Expr>, ArrowType)> {
+    if let Some(arg) = args.iter().find(|arg| !is_numeric(arg.data_type())) {
+        return Err(type_error(format!(
+            "expected a numeric operand, got {:?}",
+            arg.data_type()
+        )));
+    }
+    if args.iter().all(|arg| arg.data_type() == ArrowType::Int64) {
+        Ok((args, ArrowType::Int64))
+    } else {
+        Ok((args.into_iter().map(to_float).collect(), ArrowType::Float64))
+    }
+}
+
+fn typecheck(ast: &Ast, columns: &[(&str, ArrowType)]) -> Result<Expr> {
+    Ok(match ast {
+        Ast::Int(value) => Expr::Literal(Scalar::Int64(*value)),
+        Ast::Float(value) => Expr::Literal(Scalar::Float64(*value)),
+        Ast::Bool(value) => Expr::Literal(Scalar::Boolean(*value)),
+        Ast::Name(name) => {
+            let index = columns
+                .iter()
+                .position(|(column, _)| column == name)
+                .ok_or_else(|| type_error(format!("unknown column {name:?}")))?;
+            Expr::Column(index, columns[index].1)
+        }
+        Ast::Unary(UnaryOp::Neg, expr) => {
+            let (mut args, _) = unify(vec![typecheck(expr, columns)?])?;
+            Expr::Unary(UnaryOp::Neg, Box::new(args.remove(0)))
+        }
+        Ast::Unary(UnaryOp::Not, expr) => {
+            let expr = typecheck(expr, columns)?;
+            if expr.data_type() != ArrowType::Boolean {
+                return Err(type_error("`not` expects a Boolean operand".to_string()));
+            }
+            Expr::Unary(UnaryOp::Not, Box::new(expr))
+        }
+        Ast::Binary(op, left, right) => {
+            let left = typecheck(left, columns)?;
+            let right = typecheck(right, columns)?;
+            match op {
+                BinaryOp::And | BinaryOp::Or => {
+                    if left.data_type() != ArrowType::Boolean
+                        || right.data_type() != ArrowType::Boolean
+                    {
+                        return Err(type_error(format!("{op:?} expects Boolean operands")));
+                    }
+                    Expr::Binary(*op, Box::new(left), Box::new(right), ArrowType::Boolean)
+                }
+                BinaryOp::Eq | BinaryOp::NotEq
+                    if left.data_type() == ArrowType::Boolean
+                        && right.data_type() == ArrowType::Boolean =>
+                {
+                    Expr::Binary(*op, Box::new(left), Box::new(right), ArrowType::Boolean)
+                }
+                BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq => {
+                    let (mut args, _) = unify(vec![left, right])?;
+                    let right = args.pop().unwrap();
+                    let left = args.pop().unwrap();
+                    Expr::Binary(*op, Box::new(left), Box::new(right), ArrowType::Boolean)
+                }
+                BinaryOp::Div => {
+                    unify(vec![left.clone(), right.clone()])?;
+                    Expr::Binary(
+                        *op,
+                        Box::new(to_float(left)),
+                        Box::new(to_float(right)),
+                        ArrowType::Float64,
+                    )
+                }
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Rem => {
+                    let (mut args, data_type) = unify(vec![left, right])?;
+                    let right = args.pop().unwrap();
+                    let left = args.pop().unwrap();
+                    Expr::Binary(*op, Box::new(left), Box::new(right), data_type)
+                }
+            }
+        }
+        Ast::Call(name, args) => {
+            let (function, n_args) = Function::from_name(name)
+                .ok_or_else(|| type_error(format!("unknown function {name:?}")))?;
+            if args.len() != n_args {
+                return Err(type_error(format!(
+                    "{name} expects {n_args} arguments, got {}",
+                    args.len()
+                )));
+            }
+            let args = args
+                .iter()
+                .map(|arg| typecheck(arg, columns))
+                .collect::<Result<_>>()?;
+            let (args, data_type) = unify(args)?;
+            match function {
+                Function::Sqrt => Expr::Call(
+                    function,
+                    args.into_iter().map(to_float).collect(),
+                    ArrowType::Float64,
+                ),
+                _ => Expr::Call(function, args, data_type),
+            }
+        }
+    })
+}
+
+/// Parsed and type checked expression, ready to be evaluated.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    pub expr: Expr,
+    #[cfg(feature = "jit")]
+    pub compiled: Option<Arc<jit::CompiledExpr>>,
+}
+
+impl Expression {
+    /// Compile `text` for input columns with the given names and types.
+    pub fn compile(text: &str, columns: &[(&str, ArrowType)]) -> Result<Expression> {
+        if let Some((name, data_type)) = columns.iter().find(|(_, t)| !is_numeric(*t)) {
+            return Err(Error::UnsupportedType(format!(
+                "column {name:?} of type {data_type:?} in an expression"
+            )));
+        }
+        let ast = parser::parse(text)?;
+        Ok(Expression {
+            expr: typecheck(&ast, columns)?,
+            #[cfg(feature = "jit")]
+            compiled: None,
+        })
+    }
+
+    /// Compile `text`, to native code if the options request it and it's
+    /// supported. See `compile`.
+    pub fn compile_with_options(
+        text: &str,
+        columns: &[(&str, ArrowType)],
+        options: &ArrowUdfExecOptions,
+    ) -> Result<Expression> {
+        let expression = Expression::compile(text, columns)?;
+        #[cfg(feature = "jit")]
+        if options.flags & ARROW_UDF_FLAG_JIT != 0 {
+            // Expressions that can't be compiled use the interpreter.
+            let compiled = jit::cached(options.context(), text, columns, &expression.expr).ok();
+            return Ok(Expression {
+                compiled,
+                ..expression
+            });
+        }
+        #[cfg(not(feature = "jit"))]
+        let _ = options;
+        Ok(expression)
+    }
+
+    pub fn data_type(&self) -> ArrowType {
+        self.expr.data_type()
+    }
+
+    /// Evaluate the expression over `arrays`, the columns it was compiled for.
+    pub fn evaluate(
+        &self,
+        arrays: &[ArrowArray],
+        options: &ArrowUdfExecOptions,
+    ) -> Result<(Schema, ArrayData)> {
+        let len = arrays.first().map_or(0, |array| array.len());
+        if arrays.iter().any(|array| array.len() != len) {
+            return Err(Error::InvalidArgument(
+                "all the arrays of an expression must have the same length".to_string(),
+            ));
+        }
+        let mut used = Vec::new();
+        self.expr.columns(&mut used);
+        let used_arrays: Vec<_> = used.iter().map(|i| &arrays[*i]).collect();
+        let (validity, null_count) = udf::combined_validity(&used_arrays);
+        if null_count > 0 && options.null_policy()? == NullPolicy::Error {
+            return Err(Error::NullValue);
+        }
+        let columns: Vec<_> = arrays.iter().map(Column::new).collect();
+        let data = match self.data_type() {
+            ArrowType::Int64 => self.evaluate_primitive::<i64>(&columns, len, options)?,
+            ArrowType::Float64 => self.evaluate_primitive::<f64>(&columns, len, options)?,
+            _ => {
+                let mut values = vec![false; len];
+                exec::map(&mut values, options, |rows, out| {
+                    #[cfg(feature = "jit")]
+                    if let Some(compiled) = &self.compiled {
+                        compiled.eval(&columns, rows, out);
+                        return Ok(());
+                    }
+                    match eval::eval(&self.expr, &columns, rows)? {
+                        Values::Boolean(values) => out.copy_from_slice(&values),
+                        _ => unreachable!("the expression is Boolean"),
+                    }
+                    Ok(())
+                })?;
+                let mut bits = BitmapBuilder::with_capacity(len);
+                values.into_iter().for_each(|value| bits.push(value));
+                ArrayData::primitive(bits.finish(), len)
+            }
+        };
+        Ok((
+            Schema::new(self.data_type(), "result"),
+            data.with_validity(validity, null_count),
+        ))
+    }
+
+    fn evaluate_primitive<T: NativeType>(
+        &self,
+        columns: &[Column],
+        len: usize,
+        options: &ArrowUdfExecOptions,
+    ) -> Result<ArrayData> {
+        let mut values = Buffer::zeroed(len * std::mem::size_of::<T>())?;
+        exec::map(values.typed_data_mut::<T>(), options, |rows, out| {
+            #[cfg(feature = "jit")]
+            if let Some(compiled) = &self.compiled {
+                compiled.eval(columns, rows, out);
+                return Ok(());
+            }
+            eval::eval(&self.expr, columns, rows)?.copy_to(out);
+            Ok(())
+        })?;
+        Ok(ArrayData::primitive(values, len))
+    }
+}
+
+/// Evaluate `expression` over the `n_arrays` arrays in `schemas` and
+/// `arrays`, which are referred to in the expression by their names.
+///
+/// # Safety
+///
+/// `expression` must be a valid null-terminated string, `schemas` and
+/// `arrays` must point to `n_arrays` valid Arrow C Data Interface arrays,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_eval_expression(
+    expression: *const c_char,
+    n_arrays: i64,
+    schemas: *const *const ArrowCDataInterfaceSchema,
+    arrays: *const *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let text = CStr::from_ptr(expression)
+            .to_str()
+            .map_err(|_| Error::InvalidArgument("the expression is not UTF-8".to_string()))?;
+        let parsed = Schema::from_ffi_list(n_arrays, schemas)?;
+        let arrays = (0..parsed.len())
+            .map(|i| ArrowArray::import(&parsed[i], &**arrays.add(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let columns: Vec<_> = parsed
+            .iter()
+            .map(|schema| (schema.name.as_str(), schema.data_type))
+            .collect();
+        let expression = Expression::compile_with_options(text, &columns, &options)?;
+        let (out, data) = expression.evaluate(&arrays, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    const COLUMNS: &[(&str, ArrowType)] = &[("x", ArrowType::Int64), ("y", ArrowType::Float64)];
+
+    #[test]
+    fn types_are_inferred_from_the_operands() {
+        for (text, data_type) in [
+            ("x + 1", ArrowType::Int64),
+            ("x + y", ArrowType::Float64),
+            ("x / 2", ArrowType::Float64),
+            ("sqrt(x)", ArrowType::Float64),
+            ("floor(x)", ArrowType::Int64),
+            ("x < y and not x == 1", ArrowType::Boolean),
+        ] {
+            let expression = Expression::compile(text, COLUMNS).unwrap();
+            assert_eq!(expression.data_type(), data_type, "{text}");
+        }
+    }
+
+    #[test]
+    fn type_errors() {
+        for text in [
+            "z",
+            "x and y",
+            "not x",
+            "abs(x, y)",
+            "pow(x)",
+            "(x < 1) + 1",
+        ] {
+            assert!(
+                matches!(
+                    Expression::compile(text, COLUMNS),
+                    Err(Error::InvalidArgument(_))
+                ),
+                "{text:?} should not compile"
+            );
+        }
+        assert!(matches!(
+            Expression::compile("x", &[("x", ArrowType::Int32)]),
+            Err(Error::UnsupportedType(_))
+        ));
+    }
+
+    fn evaluate(text: &str, x: &Exported, y: &Exported) -> Result<Exported> {
+        let mut out = Exported::empty();
+        let text = std::ffi::CString::new(text).unwrap();
+        let status = unsafe {
+            arrow_udf_eval_expression(
+                text.as_ptr(),
+                2,
+                [&x.schema as *const _, &y.schema].as_ptr(),
+                [&x.array as *const _, &y.array].as_ptr(),
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(Error::InvalidArgument(format!("{status:?}"))),
+        }
+    }
+
+    #[test]
+    fn expressions_through_ffi() {
+        let x = Exported::named("x", &[Some(1_i64), Some(7), None, Some(i64::MAX)]);
+        let y = Exported::named("y", &[Some(0.5_f64), None, Some(2.0), Some(1.0)]);
+        let out = evaluate("abs(x - 5) + 2 * x", &x, &y).unwrap();
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [
+                Some(6),
+                Some(16),
+                None,
+                Some(i64::MAX - 5 + i64::MAX.wrapping_mul(2))
+            ]
+        );
+        let out = evaluate("x * y", &x, &y).unwrap();
+        assert_eq!(
+            out.nullable_values::<f64>(),
+            [Some(0.5), None, None, Some(i64::MAX as f64)]
+        );
+        let out = evaluate("x % 0 == 0 and y < 1", &x, &y).unwrap();
+        assert_eq!(out.booleans(), [Some(true), None, None, Some(false)]);
+    }
+
+    #[test]
+    fn arrays_must_have_the_same_length() {
+        let x = Exported::primitive(&[1_i64, 2]);
+        let y = Exported::named("y", &[Some(1.0_f64)]);
+        assert!(evaluate("x + y", &x, &y).is_err());
+    }
+
+    #[test]
+    fn nulls_fail_with_the_error_policy() {
+        let x = Exported::named("x", &[Some(1_i64), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let expression = Expression::compile("x + 1", COLUMNS).unwrap();
+        let result = x.with_array(|x| expression.evaluate(std::slice::from_ref(x), &options));
+        assert!(matches!(result, Err(Error::NullValue)));
+    }
+}
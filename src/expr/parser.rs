@@ -0,0 +1,334 @@
+//! Parser of the expression language.
+//!
+//! The grammar, from the lowest to the highest precedence:
+//!
+//! ```text
+//! expr       := and ("or" and)*
+//! and        := not ("and" not)*
+//! not        := "not" not | comparison
+//! comparison := additive (("==" | "!=" | "<" | "<=" | ">" | ">=") additive)?
+//! additive   := term (("+" | "-") term)*
+//! term       := unary (("*" | "/" | "%") unary)*
+//! unary      := "-" unary | primary
+//! primary    := number | "true" | "false" | name | name "(" args ")" | "(" expr ")"
+//! ```
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+/// Untyped syntax tree, as written by the user.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ast {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Name(String),
+    Call(String, Vec<Ast>),
+    Unary(UnaryOp, Box<Ast>),
+    Binary(BinaryOp, Box<Ast>, Box<Ast>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let number = &rest[..len];
+            let token = if number.contains(['.', 'e', 'E']) {
+                number.parse().map(Token::Float).ok()
+            } else {
+                number.parse().map(Token::Int).ok()
+            };
+            tokens.push(token.ok_or_else(|| syntax_error(format!("invalid number {number:?}")))?);
+            rest = &rest[len..];
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(syntax_error(format!("unexpected character {c:?}")));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn syntax_error(msg: String) -> Error {
+    Error::InvalidArgument(format!("invalid expression: {msg}"))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(candidate)) if *candidate == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Name(name)) if name == keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(syntax_error(format!("expected {symbol:?}")))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Ast> {
+        let mut left = self.and()?;
+        while self.eat_keyword("or") {
+            left = Ast::Binary(BinaryOp::Or, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Ast> {
+        let mut left = self.not()?;
+        while self.eat_keyword("and") {
+            left = Ast::Binary(BinaryOp::And, Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Ast> {
+        if self.eat_keyword("not") {
+            return Ok(Ast::Unary(UnaryOp::Not, Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Ast> {
+        let left = self.additive()?;
+        let ops = [
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::NotEq),
+            ("<=", BinaryOp::LtEq),
+            (">=", BinaryOp::GtEq),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ];
+        for (symbol, op) in ops {
+            if self.eat_symbol(symbol) {
+                return Ok(Ast::Binary(op, Box::new(left), Box::new(self.additive()?)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<Ast> {
+        let mut left = self.term()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                BinaryOp::Add
+            } else if self.eat_symbol("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(left);
+            };
+            left = Ast::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Ast> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                BinaryOp::Mul
+            } else if self.eat_symbol("/") {
+                BinaryOp::Div
+            } else if self.eat_symbol("%") {
+                BinaryOp::Rem
+            } else {
+                return Ok(left);
+            };
+            left = Ast::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Ast> {
+        if self.eat_symbol("-") {
+            return Ok(Ast::Unary(UnaryOp::Neg, Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Ast> {
+        match self.next() {
+            Some(Token::Int(value)) => Ok(Ast::Int(value)),
+            Some(Token::Float(value)) => Ok(Ast::Float(value)),
+            Some(Token::Name(name)) if name == "true" => Ok(Ast::Bool(true)),
+            Some(Token::Name(name)) if name == "false" => Ok(Ast::Bool(false)),
+            Some(Token::Name(name)) => {
+                if !self.eat_symbol("(") {
+                    return Ok(Ast::Name(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat_symbol(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat_symbol(")") {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                Ok(Ast::Call(name, args))
+            }
+            Some(Token::Symbol("(")) => {
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(syntax_error(format!("unexpected {token:?}"))),
+            None => Err(syntax_error("unexpected end of the expression".to_string())),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Ast> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let ast = parser.expr()?;
+    match parser.peek() {
+        None => Ok(ast),
+        Some(token) => Err(syntax_error(format!("unexpected {token:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Box<Ast> {
+        Box::new(Ast::Name(name.to_string()))
+    }
+
+    #[test]
+    fn operators_follow_their_precedence() {
+        assert_eq!(
+            parse("x + y * 2").unwrap(),
+            Ast::Binary(
+                BinaryOp::Add,
+                name("x"),
+                Box::new(Ast::Binary(BinaryOp::Mul, name("y"), Box::new(Ast::Int(2))))
+            )
+        );
+        assert_eq!(
+            parse("not x < 1 or y").unwrap(),
+            Ast::Binary(
+                BinaryOp::Or,
+                Box::new(Ast::Unary(
+                    UnaryOp::Not,
+                    Box::new(Ast::Binary(BinaryOp::Lt, name("x"), Box::new(Ast::Int(1))))
+                )),
+                name("y")
+            )
+        );
+        assert_eq!(
+            parse("(x - 1.5) % -y").unwrap(),
+            Ast::Binary(
+                BinaryOp::Rem,
+                Box::new(Ast::Binary(
+                    BinaryOp::Sub,
+                    name("x"),
+                    Box::new(Ast::Float(1.5))
+                )),
+                Box::new(Ast::Unary(UnaryOp::Neg, name("y")))
+            )
+        );
+    }
+
+    #[test]
+    fn calls_and_literals() {
+        assert_eq!(
+            parse("max(abs(x), true)").unwrap(),
+            Ast::Call(
+                "max".to_string(),
+                vec![
+                    Ast::Call("abs".to_string(), vec![Ast::Name("x".to_string())]),
+                    Ast::Bool(true)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for text in ["", "x +", "(x", "x y", "f(x,", "x $ y", "1 < 2 < 3"] {
+            assert!(
+                matches!(parse(text), Err(Error::InvalidArgument(_))),
+                "{text:?} should not parse"
+            );
+        }
+    }
+}
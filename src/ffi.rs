@@ -0,0 +1,118 @@
+//! Structs of the Arrow C Data Interface and the C Stream Interface.
+//!
+//! The layout of these structs is defined by the specification, and must not
+//! be changed: <https://arrow.apache.org/docs/format/CDataInterface.html>
+//! and <https://arrow.apache.org/docs/format/CStreamInterface.html>
+
+use std::ffi::{c_char, c_void};
+use std::ptr;
+
+pub const ARROW_FLAG_DICTIONARY_ORDERED: i64 = 1;
+pub const ARROW_FLAG_NULLABLE: i64 = 2;
+pub const ARROW_FLAG_MAP_KEYS_SORTED: i64 = 4;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowCDataInterfaceSchema {
+    pub format: *const c_char,
+    pub name: *const c_char,
+    pub metadata: *const c_char,
+    pub flags: i64,
+    pub n_children: i64,
+    pub children: *mut *mut ArrowCDataInterfaceSchema,
+    pub dictionary: *mut ArrowCDataInterfaceSchema,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowCDataInterfaceSchema)>,
+    pub private_data: *mut c_void,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowCDataInterfaceArray {
+    pub length: i64,
+    pub null_count: i64,
+    pub offset: i64,
+    pub n_buffers: i64,
+    pub n_children: i64,
+    pub buffers: *mut *const c_void,
+    pub children: *mut *mut ArrowCDataInterfaceArray,
+    pub dictionary: *mut ArrowCDataInterfaceArray,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowCDataInterfaceArray)>,
+    pub private_data: *mut c_void,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowCDataInterfaceArrayStream {
+    pub get_schema: Option<
+        unsafe extern "C" fn(
+            *mut ArrowCDataInterfaceArrayStream,
+            *mut ArrowCDataInterfaceSchema,
+        ) -> i32,
+    >,
+    pub get_next: Option<
+        unsafe extern "C" fn(
+            *mut ArrowCDataInterfaceArrayStream,
+            *mut ArrowCDataInterfaceArray,
+        ) -> i32,
+    >,
+    pub get_last_error:
+        Option<unsafe extern "C" fn(*mut ArrowCDataInterfaceArrayStream) -> *const c_char>,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowCDataInterfaceArrayStream)>,
+    pub private_data: *mut c_void,
+}
+
+impl ArrowCDataInterfaceSchema {
+    /// A released schema, used as a placeholder before exporting into it.
+    pub fn empty() -> Self {
+        Self {
+            format: ptr::null(),
+            name: ptr::null(),
+            metadata: ptr::null(),
+            flags: 0,
+            n_children: 0,
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `i` must be lower than `n_children`, and the schema must be valid.
+    pub unsafe fn child(&self, i: usize) -> &ArrowCDataInterfaceSchema {
+        &**self.children.add(i)
+    }
+}
+
+impl ArrowCDataInterfaceArray {
+    /// A released array, used as a placeholder before exporting into it.
+    pub fn empty() -> Self {
+        Self {
+            length: 0,
+            null_count: 0,
+            offset: 0,
+            n_buffers: 0,
+            n_children: 0,
+            buffers: ptr::null_mut(),
+            children: ptr::null_mut(),
+            dictionary: ptr::null_mut(),
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `i` must be lower than `n_buffers`, and the array must be valid.
+    pub unsafe fn buffer(&self, i: usize) -> *const u8 {
+        *self.buffers.add(i) as *const u8
+    }
+
+    /// # Safety
+    ///
+    /// `i` must be lower than `n_children`, and the array must be valid.
+    pub unsafe fn child(&self, i: usize) -> &ArrowCDataInterfaceArray {
+        &**self.children.add(i)
+    }
+}
@@ -0,0 +1,356 @@
+//! Kernels replacing the nulls of an array with actual values.
+//!
+//! The kernels exist to handle nulls, so they accept them regardless of the
+//! null policy in the options.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::dispatch::{self, Kernel};
+use crate::error::{ffi_guard, ArrowUdfStatus, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::Numeric;
+
+/// Copy of `array` with its nulls replaced by `value`, converted to the type
+/// of the array.
+fn fill_null<T: Numeric>(
+    array: &ArrowArray,
+    value: f64,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let input = array.values::<T>();
+    let mut values = options.output_values(std::mem::size_of_val(input))?;
+    let value = T::from_f64(value);
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    exec::map(values.typed_data_mut::<T>(), options, |rows, out| {
+        match validity {
+            Some(validity) => {
+                for ((out, input), i) in out.iter_mut().zip(&input[rows.clone()]).zip(rows) {
+                    *out = if validity.is_set(i) { *input } else { value };
+                }
+            }
+            None => out.copy_from_slice(&input[rows]),
+        }
+        Ok(())
+    })?;
+    Ok(ArrayData::primitive(values, input.len()))
+}
+
+/// Copy of `array` with every null replaced by the last valid value before
+/// it. Nulls before the first valid value stay null.
+fn forward_fill<T: Numeric>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let input = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let mut values = Vec::with_capacity(input.len());
+    let leading_nulls = match validity {
+        Some(validity) => (0..input.len())
+            .find(|i| validity.is_set(*i))
+            .unwrap_or(input.len()),
+        None => 0,
+    };
+    let mut last = T::default();
+    exec::for_each_batch(input.len(), options, |rows| {
+        for i in rows {
+            if validity.is_none_or(|validity| validity.is_set(i)) {
+                last = input[i];
+            }
+            values.push(last);
+        }
+        Ok(())
+    })?;
+    let data = ArrayData::primitive(Buffer::from_slice(&values), values.len());
+    if leading_nulls == 0 {
+        return Ok(data);
+    }
+    let mut valid = BitmapBuilder::with_capacity(values.len());
+    (0..values.len()).for_each(|i| valid.push(i >= leading_nulls));
+    Ok(data.with_validity(Some(valid.finish()), leading_nulls))
+}
+
+/// Float64 copy of `array` with every run of nulls replaced by the values
+/// on the straight line between the valid values around it. Runs at the
+/// start or at the end take the value of the nearest valid value. The
+/// result is fully valid, unless all the values are null.
+fn linear_interpolate<T: Numeric>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let input = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let mut values: Vec<f64> = Vec::with_capacity(input.len());
+    let Some(validity) = validity else {
+        values.extend(input.iter().map(|value| value.to_f64()));
+        return Ok(ArrayData::primitive(
+            Buffer::from_slice(&values),
+            values.len(),
+        ));
+    };
+    if array.null_count() == input.len() {
+        let mut valid = BitmapBuilder::with_capacity(input.len());
+        (0..input.len()).for_each(|_| valid.push(false));
+        let data = ArrayData::primitive(Buffer::zeroed(std::mem::size_of_val(input))?, input.len());
+        return Ok(data.with_validity(Some(valid.finish()), input.len()));
+    }
+    // Position and value of the last valid row, and the start of the run of
+    // nulls after it.
+    let mut previous: Option<(usize, f64)> = None;
+    let mut run_start = 0;
+    exec::for_each_batch(input.len(), options, |rows| {
+        for i in rows {
+            if !validity.is_set(i) {
+                continue;
+            }
+            let value = input[i].to_f64();
+            match previous {
+                Some((start, start_value)) => {
+                    let slope = (value - start_value) / (i - start) as f64;
+                    values.extend((run_start..i).map(|j| start_value + slope * (j - start) as f64));
+                }
+                None => values.extend((run_start..i).map(|_| value)),
+            }
+            values.push(value);
+            previous = Some((i, value));
+            run_start = i + 1;
+        }
+        Ok(())
+    })?;
+    let (_, last_value) = previous.expect("at least a valid value");
+    values.resize(input.len(), last_value);
+    Ok(ArrayData::primitive(
+        Buffer::from_slice(&values),
+        values.len(),
+    ))
+}
+
+/// How the nulls are replaced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fill {
+    /// By a constant value, converted to the type of the array.
+    Value(f64),
+    /// By the last valid value before them.
+    Forward,
+    /// By the linear interpolation of the valid values around them, as
+    /// Float64.
+    Linear,
+}
+
+/// Copy of `array` with its nulls replaced as specified by `fill`.
+pub fn fill(
+    array: &ArrowArray,
+    fill: Fill,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let (output_type, data) = dispatch::call::<FillNulls>(array, &fill, options)?;
+    Ok((Schema::new(output_type, &array.schema().name), data))
+}
+
+struct FillNulls;
+
+impl Kernel for FillNulls {
+    type Args = Fill;
+    type Output = (ArrowType, ArrayData);
+
+    fn name(fill: &Fill) -> String {
+        format!("{fill:?} fill")
+    }
+
+    fn call<T: Numeric>(
+        array: &ArrowArray,
+        fill: &Fill,
+        options: &ArrowUdfExecOptions,
+    ) -> Result<(ArrowType, ArrayData)> {
+        Ok(match *fill {
+            Fill::Value(value) => (array.data_type(), fill_null::<T>(array, value, options)?),
+            Fill::Forward => (array.data_type(), forward_fill::<T>(array, options)?),
+            Fill::Linear => (ArrowType::Float64, linear_interpolate::<T>(array, options)?),
+        })
+    }
+}
+
+unsafe fn fill_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    fill: Fill,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = self::fill(&array, fill, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Copy of a numeric array with its nulls replaced by `value`, converted to
+/// the type of the array. The result has no nulls.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_fill_null(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    value: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let fill = Fill::Value(value);
+    fill_ffi(schema, array, fill, options, out_schema, out_array)
+}
+
+/// Copy of a numeric array with every null replaced by the last valid value
+/// before it. Nulls before the first valid value stay null.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_fill_null`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_forward_fill(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    fill_ffi(schema, array, Fill::Forward, options, out_schema, out_array)
+}
+
+/// Float64 copy of a numeric array with its nulls linearly interpolated
+/// between the valid values around them. Nulls at the start and at the end
+/// take the nearest valid value.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_fill_null`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_linear_interpolate(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    fill_ffi(schema, array, Fill::Linear, options, out_schema, out_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn run(
+        input: &Exported,
+        fill: Fill,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let (schema, array) = (&input.schema, &input.array);
+        let status = unsafe {
+            let out = (&mut out.schema, &mut out.array);
+            match fill {
+                Fill::Value(value) => {
+                    arrow_udf_fill_null(schema, array, value, options, out.0, out.1)
+                }
+                Fill::Forward => arrow_udf_forward_fill(schema, array, options, out.0, out.1),
+                Fill::Linear => arrow_udf_linear_interpolate(schema, array, options, out.0, out.1),
+            }
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn nulls_are_replaced() {
+        let input = Exported::nullable(&[None, Some(2_i32), None, None, Some(8), None]);
+        // The null policy doesn't apply to the kernels handling the nulls.
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            batch_size: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let filled = run(&input, Fill::Value(-1.0), &options).unwrap();
+        assert_eq!(
+            filled.nullable_values::<i32>(),
+            [Some(-1), Some(2), Some(-1), Some(-1), Some(8), Some(-1)]
+        );
+        let forward = run(&input, Fill::Forward, &options).unwrap();
+        assert_eq!(
+            forward.nullable_values::<i32>(),
+            [None, Some(2), Some(2), Some(2), Some(8), Some(8)]
+        );
+        let linear = run(&input, Fill::Linear, &options).unwrap();
+        assert_eq!(
+            linear.nullable_values::<f64>(),
+            [
+                Some(2.0),
+                Some(2.0),
+                Some(4.0),
+                Some(6.0),
+                Some(8.0),
+                Some(8.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_without_nulls_or_without_values() {
+        let options = ArrowUdfExecOptions::default();
+        let full = Exported::primitive(&[1.5_f32, -2.0]);
+        for fill in [Fill::Value(0.0), Fill::Forward] {
+            assert_eq!(
+                run(&full, fill, &options).unwrap().nullable_values::<f32>(),
+                [Some(1.5), Some(-2.0)]
+            );
+        }
+        assert_eq!(
+            run(&full, Fill::Linear, &options).unwrap().values::<f64>(),
+            [1.5, -2.0]
+        );
+        let empty = Exported::nullable::<u8>(&[None, None]);
+        assert_eq!(
+            run(&empty, Fill::Forward, &options)
+                .unwrap()
+                .nullable_values::<u8>(),
+            [None, None]
+        );
+        assert_eq!(
+            run(&empty, Fill::Linear, &options)
+                .unwrap()
+                .nullable_values::<f64>(),
+            [None, None]
+        );
+        assert_eq!(
+            run(&empty, Fill::Value(7.0), &options)
+                .unwrap()
+                .values::<u8>(),
+            [7, 7]
+        );
+    }
+
+    #[test]
+    fn other_types_are_unsupported() {
+        let booleans = Exported::boolean(&[true]);
+        let status = run(&booleans, Fill::Forward, &ArrowUdfExecOptions::default()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+    }
+}
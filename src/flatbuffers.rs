@@ -0,0 +1,390 @@
+//! The subset of FlatBuffers used by the metadata of Arrow IPC messages.
+//!
+//! The metadata of every message is a FlatBuffers table: a reference to the
+//! vtable of the table, followed by its fields, and the vtable listing the
+//! position of every field in the table, or 0 for the fields left out. Fields
+//! are scalars stored in the table, or references to strings, vectors and
+//! other tables stored after it. All the integers are little-endian.
+//!
+//! `Table` builds a table with its fields by their ids, and `finish`
+//! serializes it front to back: the vtable of every table is written before
+//! it, and the strings, vectors and tables it refers to after it, so the
+//! references always point forward, as FlatBuffers requires. `TableRef`
+//! reads the tables of a buffer, checking that everything it reads is in
+//! the buffer, since the messages come from files.
+
+use crate::error::{Error, Result};
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidArgument(format!("invalid Arrow IPC metadata: {message}"))
+}
+
+/// The value of a field of a table.
+pub(crate) enum Value {
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Str(String),
+    Table(Table),
+    Tables(Vec<Table>),
+    /// A vector of structs of `size` bytes, aligned to 8 bytes, with their
+    /// bytes.
+    Structs(Vec<u8>, usize),
+}
+
+impl Value {
+    /// Bytes of the value in the table, which are also its alignment.
+    fn inline_size(&self) -> usize {
+        match self {
+            Value::Bool(_) | Value::U8(_) => 1,
+            Value::I16(_) => 2,
+            Value::I64(_) => 8,
+            // Offsets to the values stored after the table.
+            _ => 4,
+        }
+    }
+}
+
+/// A table being built, with the values of its fields by their ids.
+#[derive(Default)]
+pub(crate) struct Table {
+    fields: Vec<Option<Value>>,
+}
+
+impl Table {
+    pub(crate) fn new() -> Table {
+        Table::default()
+    }
+
+    /// This table with the field `id` set to `value`.
+    pub(crate) fn with(mut self, id: usize, value: Value) -> Table {
+        if self.fields.len() <= id {
+            self.fields.resize_with(id + 1, || None);
+        }
+        self.fields[id] = Some(value);
+        self
+    }
+}
+
+/// The buffer with `root` as its root table.
+pub(crate) fn finish(root: &Table) -> Vec<u8> {
+    let mut out = vec![0; 4];
+    let root = write_table(&mut out, root);
+    out[..4].copy_from_slice(&(root as u32).to_le_bytes());
+    out
+}
+
+fn pad_to(out: &mut Vec<u8>, alignment: usize) {
+    out.resize(out.len().next_multiple_of(alignment), 0);
+}
+
+fn patch_offset(out: &mut [u8], at: usize, target: usize) {
+    out[at..at + 4].copy_from_slice(&((target - at) as u32).to_le_bytes());
+}
+
+/// Write `table` preceded by its vtable, and followed by the values it
+/// refers to, returning its position.
+fn write_table(out: &mut Vec<u8>, table: &Table) -> usize {
+    pad_to(out, 4);
+    let vtable = out.len();
+    let vtable_len = 4 + 2 * table.fields.len();
+    // The fields are aligned to their size in the buffer, after the
+    // reference to the vtable.
+    let start = (vtable + vtable_len).next_multiple_of(4);
+    let mut end = start + 4;
+    let positions: Vec<Option<usize>> = table
+        .fields
+        .iter()
+        .map(|field| {
+            field.as_ref().map(|value| {
+                let position = end.next_multiple_of(value.inline_size());
+                end = position + value.inline_size();
+                position
+            })
+        })
+        .collect();
+    out.extend_from_slice(&(vtable_len as u16).to_le_bytes());
+    out.extend_from_slice(&((end - start) as u16).to_le_bytes());
+    for position in &positions {
+        let offset = position.map_or(0, |position| position - start);
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+    out.resize(end, 0);
+    out[start..start + 4].copy_from_slice(&((start - vtable) as i32).to_le_bytes());
+    let mut references = Vec::new();
+    for (value, position) in table.fields.iter().zip(positions) {
+        let (Some(value), Some(position)) = (value, position) else {
+            continue;
+        };
+        let bytes = &mut out[position..position + value.inline_size()];
+        match value {
+            Value::Bool(value) => bytes[0] = *value as u8,
+            Value::U8(value) => bytes[0] = *value,
+            Value::I16(value) => bytes.copy_from_slice(&value.to_le_bytes()),
+            Value::I32(value) => bytes.copy_from_slice(&value.to_le_bytes()),
+            Value::I64(value) => bytes.copy_from_slice(&value.to_le_bytes()),
+            value => references.push((position, value)),
+        }
+    }
+    for (position, value) in references {
+        let target = write_referenced(out, value);
+        patch_offset(out, position, target);
+    }
+    start
+}
+
+/// Write a value stored out of its table, returning its position.
+fn write_referenced(out: &mut Vec<u8>, value: &Value) -> usize {
+    match value {
+        Value::Str(value) => {
+            pad_to(out, 4);
+            let position = out.len();
+            out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            out.extend_from_slice(value.as_bytes());
+            out.push(0);
+            position
+        }
+        Value::Table(table) => write_table(out, table),
+        Value::Tables(tables) => {
+            pad_to(out, 4);
+            let position = out.len();
+            out.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+            out.resize(out.len() + 4 * tables.len(), 0);
+            for (i, table) in tables.iter().enumerate() {
+                let target = write_table(out, table);
+                patch_offset(out, position + 4 + 4 * i, target);
+            }
+            position
+        }
+        Value::Structs(bytes, size) => {
+            // The structs start after the length, aligned to 8 bytes.
+            pad_to(out, 4);
+            if out.len().is_multiple_of(8) {
+                out.extend_from_slice(&[0; 4]);
+            }
+            let position = out.len();
+            out.extend_from_slice(&((bytes.len() / size) as u32).to_le_bytes());
+            out.extend_from_slice(bytes);
+            position
+        }
+        _ => unreachable!("scalars are stored in their table"),
+    }
+}
+
+/// A table of a buffer being read.
+#[derive(Clone, Copy)]
+pub(crate) struct TableRef<'a> {
+    buffer: &'a [u8],
+    position: usize,
+    vtable: usize,
+    vtable_len: usize,
+    len: usize,
+}
+
+fn read_bytes<const N: usize>(buffer: &[u8], position: usize) -> Result<[u8; N]> {
+    position
+        .checked_add(N)
+        .and_then(|end| buffer.get(position..end))
+        .map(|bytes| bytes.try_into().unwrap())
+        .ok_or_else(|| invalid("reference out of the buffer"))
+}
+
+fn read_u32(buffer: &[u8], position: usize) -> Result<usize> {
+    Ok(u32::from_le_bytes(read_bytes(buffer, position)?) as usize)
+}
+
+/// The position referred to by the offset at `position`.
+fn follow(buffer: &[u8], position: usize) -> Result<usize> {
+    position
+        .checked_add(read_u32(buffer, position)?)
+        .filter(|target| *target < buffer.len())
+        .ok_or_else(|| invalid("reference out of the buffer"))
+}
+
+/// The root table of `buffer`.
+pub(crate) fn root(buffer: &[u8]) -> Result<TableRef<'_>> {
+    TableRef::at(buffer, read_u32(buffer, 0)?)
+}
+
+impl<'a> TableRef<'a> {
+    fn at(buffer: &'a [u8], position: usize) -> Result<TableRef<'a>> {
+        let vtable_offset = i32::from_le_bytes(read_bytes(buffer, position)?) as i64;
+        let vtable = usize::try_from(position as i64 - vtable_offset)
+            .map_err(|_| invalid("vtable out of the buffer"))?;
+        let vtable_len = u16::from_le_bytes(read_bytes(buffer, vtable)?) as usize;
+        let len = u16::from_le_bytes(read_bytes(buffer, vtable + 2)?) as usize;
+        if vtable_len < 4
+            || vtable + vtable_len > buffer.len()
+            || len < 4
+            || position + len > buffer.len()
+        {
+            return Err(invalid("table out of the buffer"));
+        }
+        Ok(TableRef {
+            buffer,
+            position,
+            vtable,
+            vtable_len,
+            len,
+        })
+    }
+
+    /// The `N` bytes of the field `id`, or `None` if it's left out.
+    fn field<const N: usize>(&self, id: usize) -> Result<Option<(usize, [u8; N])>> {
+        let entry = 4 + 2 * id;
+        if entry + 2 > self.vtable_len {
+            return Ok(None);
+        }
+        let offset = u16::from_le_bytes(read_bytes(self.buffer, self.vtable + entry)?) as usize;
+        if offset == 0 {
+            return Ok(None);
+        }
+        if offset + N > self.len {
+            return Err(invalid("field out of its table"));
+        }
+        let position = self.position + offset;
+        Ok(Some((position, read_bytes(self.buffer, position)?)))
+    }
+
+    pub(crate) fn bool(&self, id: usize) -> Result<bool> {
+        Ok(self.field::<1>(id)?.is_some_and(|(_, bytes)| bytes[0] != 0))
+    }
+
+    pub(crate) fn u8(&self, id: usize) -> Result<u8> {
+        Ok(self.field::<1>(id)?.map_or(0, |(_, bytes)| bytes[0]))
+    }
+
+    /// The field `id`, or `default` if it's left out, like writers do with
+    /// the fields equal to the default of the schema.
+    pub(crate) fn i16(&self, id: usize, default: i16) -> Result<i16> {
+        Ok(self
+            .field(id)?
+            .map_or(default, |(_, bytes)| i16::from_le_bytes(bytes)))
+    }
+
+    pub(crate) fn i32(&self, id: usize, default: i32) -> Result<i32> {
+        Ok(self
+            .field(id)?
+            .map_or(default, |(_, bytes)| i32::from_le_bytes(bytes)))
+    }
+
+    pub(crate) fn i64(&self, id: usize) -> Result<i64> {
+        Ok(self
+            .field(id)?
+            .map_or(0, |(_, bytes)| i64::from_le_bytes(bytes)))
+    }
+
+    /// The position of the value the field `id` refers to.
+    fn target(&self, id: usize) -> Result<Option<usize>> {
+        match self.field::<4>(id)? {
+            Some((position, _)) => follow(self.buffer, position).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The length of the vector at `position`, and the position of its
+    /// elements of `size` bytes, all in the buffer.
+    fn vector(&self, position: usize, size: usize) -> Result<(usize, usize)> {
+        let len = read_u32(self.buffer, position)?;
+        let in_bounds = len
+            .checked_mul(size)
+            .and_then(|bytes| bytes.checked_add(position + 4))
+            .is_some_and(|end| end <= self.buffer.len());
+        match in_bounds {
+            true => Ok((len, position + 4)),
+            false => Err(invalid("vector out of the buffer")),
+        }
+    }
+
+    pub(crate) fn str(&self, id: usize) -> Result<Option<&'a str>> {
+        let Some(position) = self.target(id)? else {
+            return Ok(None);
+        };
+        let (len, start) = self.vector(position, 1)?;
+        std::str::from_utf8(&self.buffer[start..start + len])
+            .map(Some)
+            .map_err(|_| invalid("string that isn't UTF-8"))
+    }
+
+    pub(crate) fn table(&self, id: usize) -> Result<Option<TableRef<'a>>> {
+        match self.target(id)? {
+            Some(position) => TableRef::at(self.buffer, position).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The tables of the vector of the field `id`, empty if it's left out.
+    pub(crate) fn tables(&self, id: usize) -> Result<Vec<TableRef<'a>>> {
+        let Some(position) = self.target(id)? else {
+            return Ok(Vec::new());
+        };
+        let (len, start) = self.vector(position, 4)?;
+        (0..len)
+            .map(|i| TableRef::at(self.buffer, follow(self.buffer, start + 4 * i)?))
+            .collect()
+    }
+
+    /// The bytes of the structs of `size` bytes of the vector of the field
+    /// `id`, empty if it's left out.
+    pub(crate) fn structs(&self, id: usize, size: usize) -> Result<&'a [u8]> {
+        let Some(position) = self.target(id)? else {
+            return Ok(&[]);
+        };
+        let (len, start) = self.vector(position, size)?;
+        Ok(&self.buffer[start..start + len * size])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_read_back() {
+        let child = |name: &str| Table::new().with(0, Value::Str(name.to_string()));
+        let structs: Vec<u8> = [1_i64, -2].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let buffer = finish(
+            &Table::new()
+                .with(0, Value::I16(4))
+                .with(1, Value::U8(3))
+                .with(2, Value::Table(child("child")))
+                .with(3, Value::I64(i64::MIN))
+                .with(5, Value::Tables(vec![child("a"), Table::new()]))
+                .with(6, Value::Structs(structs.clone(), 8))
+                .with(7, Value::Bool(true))
+                .with(8, Value::I32(-7)),
+        );
+        let root = root(&buffer).unwrap();
+        assert_eq!(root.i16(0, 0).unwrap(), 4);
+        assert_eq!(root.u8(1).unwrap(), 3);
+        let child = root.table(2).unwrap().unwrap();
+        assert_eq!(child.str(0).unwrap(), Some("child"));
+        assert_eq!(root.i64(3).unwrap(), i64::MIN);
+        // Fields left out have their default value.
+        assert_eq!(root.i64(4).unwrap(), 0);
+        assert!(root.table(4).unwrap().is_none());
+        assert!(root.tables(9).unwrap().is_empty());
+        let tables = root.tables(5).unwrap();
+        assert_eq!(tables[0].str(0).unwrap(), Some("a"));
+        assert_eq!(tables[1].str(0).unwrap(), None);
+        assert_eq!(root.structs(6, 8).unwrap(), structs);
+        assert!(root.bool(7).unwrap());
+        assert_eq!(root.i32(8, 0).unwrap(), -7);
+        assert_eq!(root.i32(10, 128).unwrap(), 128);
+    }
+
+    #[test]
+    fn references_out_of_the_buffer_fail() {
+        let buffer = finish(&Table::new().with(0, Value::Str("name".to_string())));
+        for len in 0..buffer.len() - 1 {
+            let read = root(&buffer[..len]).and_then(|root| root.str(0).map(|_| ()));
+            assert!(matches!(read, Err(Error::InvalidArgument(_))), "{len}");
+        }
+        let mut long = buffer.clone();
+        let string = long.len() - 9;
+        long[string..string + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(root(&long).unwrap().str(0).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Fused multiply-add of three float arrays.
+//!
+//! `a * b + c` is computed with a single rounding, like `f64::mul_add`, so
+//! the result is more accurate than a multiplication followed by an
+//! addition. On x86-64 processors with FMA and AVX2 the loop is compiled for
+//! them, and the compiler vectorizes it with the FMA instructions, which is
+//! much faster than the software fallback used otherwise.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+use crate::udf;
+
+/// Float types with a fused multiply-add.
+pub trait MulAdd: NativeType {
+    fn mul_add(self, b: Self, c: Self) -> Self;
+}
+
+impl MulAdd for f32 {
+    #[inline(always)]
+    fn mul_add(self, b: f32, c: f32) -> f32 {
+        f32::mul_add(self, b, c)
+    }
+}
+
+impl MulAdd for f64 {
+    #[inline(always)]
+    fn mul_add(self, b: f64, c: f64) -> f64 {
+        f64::mul_add(self, b, c)
+    }
+}
+
+#[inline(always)]
+fn mul_add_loop<T: MulAdd>(a: &[T], b: &[T], c: &[T], out: &mut [T]) {
+    for (((out, a), b), c) in out.iter_mut().zip(a).zip(b).zip(c) {
+        *out = a.mul_add(*b, *c);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn mul_add_loop_fma<T: MulAdd>(a: &[T], b: &[T], c: &[T], out: &mut [T]) {
+    mul_add_loop(a, b, c, out)
+}
+
+/// `a * b + c` for every element of the slices, with the FMA instructions of
+/// the processor if it has them.
+pub fn mul_add_slices<T: MulAdd>(a: &[T], b: &[T], c: &[T], out: &mut [T]) {
+    #[cfg(target_arch = "x86_64")]
+    if crate::cpu::level() >= crate::cpu::Level::Avx2 {
+        crate::metrics::record_simd();
+        // Safety: the processor supports the features the function is
+        // compiled for.
+        return unsafe { mul_add_loop_fma(a, b, c, out) };
+    }
+    mul_add_loop(a, b, c, out)
+}
+
+fn fma_values<T: MulAdd>(
+    a: &ArrowArray,
+    b: &ArrowArray,
+    c: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<Buffer> {
+    for array in [a, b, c] {
+        udf::check_input::<T>(array, options)?;
+    }
+    let (a, b, c) = (a.values::<T>(), b.values::<T>(), c.values::<T>());
+    let mut values = options.output_values(std::mem::size_of_val(a))?;
+    exec::map(values.typed_data_mut::<T>(), options, |rows, out| {
+        mul_add_slices(&a[rows.clone()], &b[rows.clone()], &c[rows], out);
+        Ok(())
+    })?;
+    Ok(values)
+}
+
+/// `a * b + c` for the elements of three Float32 or Float64 arrays of the
+/// same type and length. Positions where any of the arrays is null are null
+/// in the result.
+pub fn fma(
+    a: &ArrowArray,
+    b: &ArrowArray,
+    c: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if a.len() != b.len() || a.len() != c.len() {
+        return Err(Error::InvalidArgument(format!(
+            "arrays of different lengths: {}, {} and {}",
+            a.len(),
+            b.len(),
+            c.len()
+        )));
+    }
+    let values = match a.data_type() {
+        ArrowType::Float32 => fma_values::<f32>(a, b, c, options)?,
+        ArrowType::Float64 => fma_values::<f64>(a, b, c, options)?,
+        other => return Err(Error::UnsupportedType(format!("fma of {other:?} arrays"))),
+    };
+    let (validity, null_count) = udf::combined_validity(&[a, b, c]);
+    Ok((
+        Schema::new(a.data_type(), &a.schema().name),
+        ArrayData::primitive(values, a.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// `a * b + c`, with a single rounding, for the elements of three Float32 or
+/// Float64 arrays of the same type and length.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, `b_schema` and `b_array`, and `c_schema` and
+/// `c_array`, must point to valid Arrow C Data Interface arrays, `options`
+/// must be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_fma(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    c_schema: *const ArrowCDataInterfaceSchema,
+    c_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        let c_schema = Schema::from_ffi(&*c_schema)?;
+        let c = ArrowArray::import(&c_schema, &*c_array)?;
+        let (out, data) = fma(&a, &b, &c, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn run(
+        a: &Exported,
+        b: &Exported,
+        c: &Exported,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_fma(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                &c.schema,
+                &c.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn products_are_rounded_once() {
+        let a = Exported::primitive(&[1.0 + f64::EPSILON, 2.0]);
+        let b = Exported::primitive(&[1.0 - f64::EPSILON, 3.0]);
+        let c = Exported::primitive(&[-1.0, 0.5]);
+        let out = run(&a, &b, &c).unwrap();
+        assert_eq!(out.values::<f64>(), [-f64::EPSILON * f64::EPSILON, 6.5]);
+        let eps = f32::EPSILON;
+        let a = Exported::primitive(&[1.0 + eps]);
+        let b = Exported::primitive(&[1.0 - eps]);
+        let c = Exported::primitive(&[-1.0_f32]);
+        assert_eq!(run(&a, &b, &c).unwrap().values::<f32>(), [-eps * eps]);
+    }
+
+    #[test]
+    fn hardware_and_software_loops_match() {
+        let a: Vec<f32> = (0..1000).map(|i| i as f32 / 3.0).collect();
+        let b: Vec<f32> = (0..1000).map(|i| 1.0 / (i as f32 + 1.0)).collect();
+        let c: Vec<f32> = (0..1000).map(|i| -(i as f32) / 7.0).collect();
+        let (mut detected, mut fallback) = (vec![0.0; 1000], vec![0.0; 1000]);
+        mul_add_slices(&a, &b, &c, &mut detected);
+        mul_add_loop(&a, &b, &c, &mut fallback);
+        assert_eq!(detected, fallback);
+    }
+
+    #[test]
+    fn nulls_of_any_array_are_null() {
+        let a = Exported::nullable(&[None, Some(1.0), Some(1.0), Some(2.0)]);
+        let b = Exported::nullable(&[Some(1.0), None, Some(1.0), Some(2.0)]);
+        let c = Exported::nullable(&[Some(1.0), Some(1.0), None, Some(2.0)]);
+        let out = run(&a, &b, &c).unwrap();
+        assert_eq!(out.nullable_values::<f64>(), [None, None, None, Some(6.0)]);
+    }
+
+    #[test]
+    fn arrays_must_be_floats_of_the_same_type_and_length() {
+        let a = Exported::primitive(&[1.0, 2.0]);
+        let short = Exported::primitive(&[1.0]);
+        assert_eq!(
+            run(&a, &a, &short).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let floats = Exported::primitive(&[1.0_f32, 2.0]);
+        assert_eq!(
+            run(&a, &floats, &a).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let integers = Exported::primitive(&[1_i64, 2]);
+        let status = run(&integers, &integers, &integers).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+    }
+}
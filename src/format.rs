@@ -0,0 +1,191 @@
+//! Writing of the format strings of the schemas this library exports.
+//!
+//! Most types have a single format, given by `ArrowType::format`. Timestamps
+//! with a timezone and decimals also carry parameters in their format, which
+//! are written by `FormatBuilder`. The parameters are validated when the
+//! format is built, so every format written here is parsed back by
+//! `ArrowType::from_format` into the same type and parameters.
+
+use crate::error::{Error, Result};
+use crate::schema::{decimal_params, ArrowType};
+
+/// Types that can be described by a C Data Interface format string.
+pub trait ToArrowFormat {
+    fn to_format(&self) -> Result<String>;
+}
+
+impl ToArrowFormat for ArrowType {
+    /// The format without parameters: timestamps without a timezone, and
+    /// decimals with the maximum precision and a scale of 0.
+    fn to_format(&self) -> Result<String> {
+        Ok(self.format().to_string())
+    }
+}
+
+/// Format of a type and its parameters, like
+/// `FormatBuilder::new(ArrowType::Timestamp(TimeUnit::Second)).timezone("UTC")`
+/// or `FormatBuilder::decimal(10, 2)`.
+#[derive(Clone, Debug)]
+pub struct FormatBuilder {
+    data_type: ArrowType,
+    timezone: Option<String>,
+    decimal: Option<(u8, i8)>,
+}
+
+impl FormatBuilder {
+    pub fn new(data_type: ArrowType) -> FormatBuilder {
+        FormatBuilder {
+            data_type,
+            timezone: None,
+            decimal: None,
+        }
+    }
+
+    /// A 128-bit decimal with `precision` digits, `scale` of them after the
+    /// decimal point.
+    pub fn decimal(precision: u8, scale: i8) -> FormatBuilder {
+        FormatBuilder {
+            decimal: Some((precision, scale)),
+            ..FormatBuilder::new(ArrowType::Decimal128)
+        }
+    }
+
+    /// The timezone of a timestamp, as an IANA name like `Europe/Paris` or
+    /// a fixed offset like `+01:00`.
+    pub fn timezone(mut self, timezone: &str) -> FormatBuilder {
+        self.timezone = Some(timezone.to_string());
+        self
+    }
+}
+
+impl ToArrowFormat for FormatBuilder {
+    fn to_format(&self) -> Result<String> {
+        let mut format = self.data_type.format().to_string();
+        if let Some(timezone) = &self.timezone {
+            if !matches!(self.data_type, ArrowType::Timestamp(_)) {
+                return Err(Error::InvalidArgument(format!(
+                    "timezone of a {:?} format",
+                    self.data_type
+                )));
+            }
+            if timezone.contains('\0') {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid timezone {timezone:?}"
+                )));
+            }
+            format.push_str(timezone);
+        }
+        if self.data_type == ArrowType::Decimal128 {
+            let (precision, scale) = self.decimal.unwrap_or((38, 0));
+            format = format!("d:{precision},{scale}");
+            if decimal_params(&format[2..]).is_none() {
+                return Err(Error::InvalidArgument(format!(
+                    "decimal of precision {precision}, which must be from 1 to 38"
+                )));
+            }
+        }
+        Ok(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "decimal")]
+    use crate::export;
+    #[cfg(feature = "decimal")]
+    use crate::schema::Schema;
+    use crate::schema::TimeUnit;
+
+    /// Format `builder`, parse the format into a schema, and format the
+    /// parameters read back from the schema.
+    #[cfg(feature = "decimal")]
+    fn round_trip(builder: &FormatBuilder) -> (String, String) {
+        let format = builder.to_format().unwrap();
+        let schema = Schema::new(ArrowType::Int8, "x")
+            .with_format(builder)
+            .unwrap();
+        let mut again = FormatBuilder::new(schema.data_type);
+        if let Some((precision, scale)) = schema.decimal() {
+            again = FormatBuilder::decimal(precision, scale);
+        }
+        if let Some(timezone) = schema.timezone() {
+            again = again.timezone(timezone);
+        }
+        (format, again.to_format().unwrap())
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn parameterized_formats_round_trip() {
+        let timestamp = ArrowType::Timestamp(TimeUnit::Microsecond);
+        for (builder, expected) in [
+            (FormatBuilder::decimal(10, 2), "d:10,2"),
+            (FormatBuilder::decimal(38, -3), "d:38,-3"),
+            (FormatBuilder::new(ArrowType::Decimal128), "d:38,0"),
+            (FormatBuilder::new(timestamp).timezone("UTC"), "tsu:UTC"),
+            (
+                FormatBuilder::new(timestamp).timezone("Europe/Paris"),
+                "tsu:Europe/Paris",
+            ),
+            (
+                FormatBuilder::new(timestamp).timezone("+01:00"),
+                "tsu:+01:00",
+            ),
+            (FormatBuilder::new(timestamp), "tsu:"),
+            (FormatBuilder::new(ArrowType::Struct), "+s"),
+            (FormatBuilder::new(ArrowType::Map), "+m"),
+        ] {
+            assert_eq!(
+                round_trip(&builder),
+                (expected.to_string(), expected.to_string())
+            );
+        }
+        assert_eq!(ArrowType::Float32.to_format().unwrap(), "f");
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn struct_fields_keep_their_formats_through_ffi() {
+        let timestamp = ArrowType::Timestamp(TimeUnit::Second);
+        let schema = Schema::new(ArrowType::Struct, "s").with_children(vec![
+            Schema::new(ArrowType::Int8, "price")
+                .with_format(&FormatBuilder::decimal(12, 4))
+                .unwrap(),
+            Schema::new(timestamp, "at")
+                .with_format(&FormatBuilder::new(timestamp).timezone("Asia/Tokyo"))
+                .unwrap(),
+            Schema::map(
+                "prices",
+                Schema::new(ArrowType::Utf8, "currency"),
+                Schema::new(ArrowType::Int8, "price")
+                    .with_format(&FormatBuilder::decimal(9, 2))
+                    .unwrap(),
+            ),
+        ]);
+        let mut exported = export::export_schema(&schema);
+        let imported = unsafe { Schema::from_ffi(&exported) }.unwrap();
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert_eq!(imported, schema);
+        assert_eq!(imported.children[0].decimal(), Some((12, 4)));
+        assert_eq!(imported.children[1].timezone(), Some("Asia/Tokyo"));
+        let entries = &imported.children[2].children[0];
+        assert_eq!(imported.children[2].format, "+m");
+        assert_eq!(entries.children[1].decimal(), Some((9, 2)));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        for builder in [
+            FormatBuilder::new(ArrowType::Int64).timezone("UTC"),
+            FormatBuilder::new(ArrowType::Timestamp(TimeUnit::Second)).timezone("U\0TC"),
+            FormatBuilder::decimal(0, 0),
+            FormatBuilder::decimal(39, 2),
+        ] {
+            assert!(
+                matches!(builder.to_format(), Err(Error::InvalidArgument(_))),
+                "{builder:?}"
+            );
+        }
+    }
+}
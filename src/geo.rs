@@ -0,0 +1,381 @@
+//! Geospatial kernels.
+//!
+//! Points are received as a struct array with Float64 `lat` and `lon`
+//! fields, like the ones hosts export for a table with those columns, or as
+//! two Float64 arrays. Coordinates are in degrees.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+
+/// Mean radius of the Earth, in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Great-circle distance in meters between two points, with the haversine
+/// formula, which is accurate to around 0.5% since the Earth isn't a sphere.
+pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+}
+
+/// Latitudes and longitudes of an array of points, and whether every point
+/// is valid.
+pub struct Points<'a> {
+    lat: &'a [f64],
+    lon: &'a [f64],
+    valid: Vec<bool>,
+}
+
+impl<'a> Points<'a> {
+    /// Points of a struct array with `lat` and `lon` Float64 fields. Points
+    /// are null when the struct or any of the fields is null.
+    pub fn from_struct(points: &ArrowArray<'a>) -> Result<Points<'a>> {
+        if points.data_type() != ArrowType::Struct {
+            return Err(Error::UnsupportedType(format!(
+                "expected a struct array of points, got {:?}",
+                points.data_type()
+            )));
+        }
+        let field = |name: &str| {
+            let i = points
+                .schema()
+                .children
+                .iter()
+                .position(|field| field.name == name)
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!("the points don't have a {name:?} field"))
+                })?;
+            coordinates(points.child(i), points.offset(), points.len())
+        };
+        let (lat, lat_valid) = field("lat")?;
+        let (lon, lon_valid) = field("lon")?;
+        let valid = (0..points.len())
+            .map(|i| points.is_valid(i) && lat_valid(i) && lon_valid(i))
+            .collect();
+        Ok(Points { lat, lon, valid })
+    }
+
+    /// Points with the coordinates of two Float64 arrays of the same length.
+    /// Points are null when any of the coordinates is null.
+    pub fn from_arrays(lat: &ArrowArray<'a>, lon: &ArrowArray<'a>) -> Result<Points<'a>> {
+        if lat.len() != lon.len() {
+            return Err(Error::InvalidArgument(format!(
+                "{} latitudes and {} longitudes",
+                lat.len(),
+                lon.len()
+            )));
+        }
+        let (lat_values, lat_valid) = coordinates(*lat, 0, lat.len())?;
+        let (lon_values, lon_valid) = coordinates(*lon, 0, lon.len())?;
+        let valid = (0..lat.len())
+            .map(|i| lat_valid(i) && lon_valid(i))
+            .collect();
+        Ok(Points {
+            lat: lat_values,
+            lon: lon_values,
+            valid,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.valid.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.valid.is_empty()
+    }
+
+    /// Latitude and longitude of the point `i`, or `None` if it's null.
+    pub(crate) fn get(&self, i: usize) -> Option<(f64, f64)> {
+        self.valid[i].then(|| (self.lat[i], self.lon[i]))
+    }
+
+    fn null_count(&self) -> usize {
+        self.valid.iter().filter(|valid| !**valid).count()
+    }
+}
+
+/// The `len` coordinates of a Float64 array starting at `offset`, and
+/// whether every one of them is valid.
+fn coordinates<'a>(
+    array: ArrowArray<'a>,
+    offset: usize,
+    len: usize,
+) -> Result<(&'a [f64], impl Fn(usize) -> bool + 'a)> {
+    if array.data_type() != ArrowType::Float64 {
+        return Err(Error::UnsupportedType(format!(
+            "expected Float64 coordinates, got {:?} for {:?}",
+            array.data_type(),
+            array.schema().name
+        )));
+    }
+    if array.len() < offset + len {
+        return Err(Error::InvalidArgument(format!(
+            "the field {:?} is shorter than the points",
+            array.schema().name
+        )));
+    }
+    let values = &array.values::<f64>()[offset..offset + len];
+    Ok((values, move |i| array.is_valid(offset + i)))
+}
+
+/// Distance in meters from every point to the point at `lat` and `lon`, as
+/// Float64. Null points are null in the result.
+pub fn haversine(
+    points: &Points,
+    lat: f64,
+    lon: f64,
+    name: &str,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let null_count = points.null_count();
+    if options.null_policy()? == NullPolicy::Error && null_count > 0 {
+        return Err(Error::NullValue);
+    }
+    let mut distances = options.output_values(points.len() * std::mem::size_of::<f64>())?;
+    exec::map(distances.typed_data_mut::<f64>(), options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            *out = haversine_distance(points.lat[i], points.lon[i], lat, lon);
+        }
+        Ok(())
+    })?;
+    let validity = (null_count > 0).then(|| {
+        let mut validity = BitmapBuilder::with_capacity(points.len());
+        points.valid.iter().for_each(|valid| validity.push(*valid));
+        validity.finish()
+    });
+    Ok((
+        Schema::new(ArrowType::Float64, name),
+        ArrayData::primitive(distances, points.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Distance in meters from every point of a struct array with Float64 `lat`
+/// and `lon` fields to the point at `lat` and `lon`, in degrees, as a
+/// Float64 array.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_haversine(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    lat: f64,
+    lon: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let points = Points::from_struct(&array)?;
+        let (out, data) = haversine(&points, lat, lon, &schema.name, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Distance in meters from every point with the coordinates of two Float64
+/// arrays of latitudes and longitudes to the point at `lat` and `lon`, in
+/// degrees, as a Float64 array.
+///
+/// # Safety
+///
+/// `lat_schema` and `lat_array`, and `lon_schema` and `lon_array`, must point
+/// to valid Arrow C Data Interface arrays, `options` must be null or valid,
+/// and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_haversine_arrays(
+    lat_schema: *const ArrowCDataInterfaceSchema,
+    lat_array: *const ArrowCDataInterfaceArray,
+    lon_schema: *const ArrowCDataInterfaceSchema,
+    lon_array: *const ArrowCDataInterfaceArray,
+    lat: f64,
+    lon: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let lat_schema = Schema::from_ffi(&*lat_schema)?;
+        let lat_array = ArrowArray::import(&lat_schema, &*lat_array)?;
+        let lon_schema = Schema::from_ffi(&*lon_schema)?;
+        let lon_array = ArrowArray::import(&lon_schema, &*lon_array)?;
+        let points = Points::from_arrays(&lat_array, &lon_array)?;
+        let (out, data) = haversine(&points, lat, lon, "distance", &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::{self, Exported};
+
+    const ONE_DEGREE: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.0;
+
+    /// Struct array of points with `lat` and `lon` fields, and the validity
+    /// of the struct in `valid`.
+    fn points(lat: &[Option<f64>], lon: &[Option<f64>], valid: &[Option<()>]) -> Exported {
+        let schema = Schema::new(ArrowType::Struct, "points").with_children(vec![
+            Schema::new(ArrowType::Float64, "lon"),
+            Schema::new(ArrowType::Float64, "lat"),
+        ]);
+        let children = vec![testing::nullable_data(lon), testing::nullable_data(lat)];
+        let null_count = valid.iter().filter(|valid| valid.is_none()).count();
+        let data = ArrayData::struct_array(children, valid.len())
+            .with_validity(Some(testing::validity(valid)), null_count);
+        Exported::new(&schema, data)
+    }
+
+    fn run(input: &Exported, lat: f64, lon: f64) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            arrow_udf_haversine(
+                schema,
+                array,
+                lat,
+                lon,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64, tolerance: f64) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn distances_over_the_sphere() {
+        assert_close(
+            Some(haversine_distance(0.0, 0.0, 0.0, 1.0)),
+            ONE_DEGREE,
+            1e-6,
+        );
+        assert_close(
+            Some(haversine_distance(0.0, 179.5, 0.0, -179.5)),
+            ONE_DEGREE,
+            1e-6,
+        );
+        let antipodes = haversine_distance(45.0, 0.0, -45.0, 180.0);
+        assert_close(Some(antipodes), 180.0 * ONE_DEGREE, 1e-6);
+        // Paris to London.
+        let distance = haversine_distance(48.8566, 2.3522, 51.5074, -0.1278);
+        assert_close(Some(distance), 343_500.0, 1_000.0);
+    }
+
+    #[test]
+    fn null_points_or_coordinates_are_null() {
+        let input = points(
+            &[Some(0.0), None, Some(1.0), Some(2.0)],
+            &[Some(1.0), Some(0.0), Some(0.0), Some(0.0)],
+            &[Some(()), Some(()), Some(()), None],
+        );
+        let distances = run(&input, 0.0, 0.0).unwrap().nullable_values::<f64>();
+        assert_eq!(distances[1..], [None, Some(distances[2].unwrap()), None]);
+        assert_close(distances[0], ONE_DEGREE, 1e-6);
+        assert_close(distances[2], ONE_DEGREE, 1e-6);
+        let mut sliced = input;
+        sliced.array.offset = 2;
+        sliced.array.length = 1;
+        sliced.array.null_count = -1;
+        let distances = run(&sliced, 1.0, 0.0).unwrap().nullable_values::<f64>();
+        assert_eq!(distances, [Some(0.0)]);
+    }
+
+    #[test]
+    fn coordinate_arrays() {
+        let lat = Exported::nullable(&[Some(0.0), Some(0.0), None]);
+        let lon = Exported::primitive(&[0.0, 2.0, 0.0]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_haversine_arrays(
+                &lat.schema,
+                &lat.array,
+                &lon.schema,
+                &lon.array,
+                0.0,
+                1.0,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        let distances = out.nullable_values::<f64>();
+        assert_close(distances[0], ONE_DEGREE, 1e-6);
+        assert_close(distances[1], ONE_DEGREE, 1e-6);
+        assert_eq!(distances[2], None);
+        let short = Exported::primitive(&[0.0]);
+        let status = unsafe {
+            arrow_udf_haversine_arrays(
+                &lat.schema,
+                &lat.array,
+                &short.schema,
+                &short.array,
+                0.0,
+                1.0,
+                ptr::null(),
+                &mut Exported::empty().schema,
+                &mut Exported::empty().array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+    }
+
+    #[test]
+    fn points_need_float_coordinates() {
+        let status = run(&Exported::primitive(&[1.0]), 0.0, 0.0).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+        let schema = Schema::new(ArrowType::Struct, "points").with_children(vec![
+            Schema::new(ArrowType::Float32, "lat"),
+            Schema::new(ArrowType::Float32, "lon"),
+        ]);
+        let float = || testing::nullable_data(&[Some(1.0_f32)]);
+        let children = vec![float(), float()];
+        let floats = Exported::new(&schema, ArrayData::struct_array(children, 1));
+        assert_eq!(
+            run(&floats, 0.0, 0.0).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let schema = Schema::new(ArrowType::Struct, "points")
+            .with_children(vec![Schema::new(ArrowType::Float64, "lat")]);
+        let children = vec![testing::nullable_data(&[Some(1.0)])];
+        let no_lon = Exported::new(&schema, ArrayData::struct_array(children, 1));
+        assert_eq!(
+            run(&no_lon, 0.0, 0.0).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,998 @@
+//! Hash aggregation: group the rows by the values of one or more key
+//! columns, and compute aggregates of value columns for every group.
+//!
+//! Rows are processed one batch at a time. For every batch, the hashes of
+//! the keys are computed column by column, and then used to find the group
+//! of every row in an open addressing hash table, creating the groups seen
+//! for the first time. The aggregates are then updated with the groups of
+//! the batch.
+//!
+//! The result is a struct array with a row per group, in the order they
+//! were first seen, with the key columns followed by a column per
+//! aggregate, named like `sum(value)` or `approx_quantile(value, 0.5)`.
+//! Null keys form their own group.
+//!
+//! With a memory limit, the groups are spilled to Arrow IPC files per
+//! partition of their hashes when the table would grow past half of the
+//! limit, and the table starts again without groups. Once all the rows are processed, the
+//! groups of every partition are merged in a new table, and the groups of
+//! all the partitions are put back in the order of their first row.
+
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+use std::mem;
+
+use crate::aggregate::{self, Accumulator, AggregateSpec, PropagateNulls};
+use crate::array::ArrowArray;
+use crate::binary::BinaryBuilder;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::concat;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::memory::{self, Reservation};
+use crate::nan;
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::spill::SpillFile;
+use crate::stream::ImportedArray;
+use crate::types::NativeType;
+
+/// Integer types that can be used as keys, hashed and compared by their
+/// bits.
+pub trait KeyType: NativeType {
+    fn to_key(self) -> u64;
+    fn from_key(key: u64) -> Self;
+}
+
+macro_rules! key_type {
+    ($($type:ty),*) => {
+        $(
+            impl KeyType for $type {
+                fn to_key(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_key(key: u64) -> $type {
+                    key as $type
+                }
+            }
+        )*
+    };
+}
+
+key_type!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+/// Evaluate `$body` with `$type` being the key type of `$data_type`, or
+/// `$fallback` if it's not a key type.
+macro_rules! with_key_type {
+    ($data_type:expr, $type:ident => $body:expr, _ => $fallback:expr) => {
+        match $data_type {
+            ArrowType::Int8 => {
+                type $type = i8;
+                $body
+            }
+            ArrowType::Int16 => {
+                type $type = i16;
+                $body
+            }
+            ArrowType::Int32 => {
+                type $type = i32;
+                $body
+            }
+            ArrowType::Int64 => {
+                type $type = i64;
+                $body
+            }
+            ArrowType::UInt8 => {
+                type $type = u8;
+                $body
+            }
+            ArrowType::UInt16 => {
+                type $type = u16;
+                $body
+            }
+            ArrowType::UInt32 => {
+                type $type = u32;
+                $body
+            }
+            ArrowType::UInt64 => {
+                type $type = u64;
+                $body
+            }
+            _ => $fallback,
+        }
+    };
+}
+
+pub(crate) use with_key_type;
+
+fn is_key_type(data_type: ArrowType) -> bool {
+    matches!(
+        data_type,
+        ArrowType::Int8
+            | ArrowType::Int16
+            | ArrowType::Int32
+            | ArrowType::Int64
+            | ArrowType::UInt8
+            | ArrowType::UInt16
+            | ArrowType::UInt32
+            | ArrowType::UInt64
+    )
+}
+
+/// Key of the rows where the key column is null, the bits of an actual
+/// value with the same key are told apart by the validity.
+const NULL_KEY: u64 = 0x5bd1_e995_c6a4_a793;
+
+fn combine_hash(hash: u64, key: u64) -> u64 {
+    (hash ^ key)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        .rotate_left(29)
+}
+
+/// Keys of the `rows` of `array`, with null keys set to 0.
+fn batch_keys<T: KeyType>(array: &ArrowArray, rows: std::ops::Range<usize>, keys: &mut Vec<u64>) {
+    keys.clear();
+    keys.extend(array.values::<T>()[rows].iter().map(|value| value.to_key()));
+}
+
+/// Keys of one column for the rows of a batch, and their validity.
+#[derive(Default)]
+struct BatchColumn {
+    keys: Vec<u64>,
+    valid: Vec<bool>,
+}
+
+/// Groups found so far, and the hash table to find them by key.
+struct Groups {
+    /// Group of every slot of the table plus one, with 0 for empty slots.
+    slots: Vec<u32>,
+    /// Hash of the keys of every group.
+    hashes: Vec<u64>,
+    /// For every key column, the key of every group.
+    keys: Vec<Vec<u64>>,
+    /// For every key column, whether the key of every group is valid.
+    valid: Vec<Vec<bool>>,
+    /// The size of the table, updated when it grows.
+    reservation: Reservation,
+}
+
+impl Groups {
+    fn new(n_keys: usize) -> Groups {
+        Groups {
+            slots: vec![0; 1024],
+            hashes: Vec::new(),
+            keys: vec![Vec::new(); n_keys],
+            valid: vec![Vec::new(); n_keys],
+            reservation: Reservation::new(1024 * size_of::<u32>()),
+        }
+    }
+
+    fn memory(&self) -> usize {
+        size_of_val(self.slots.as_slice())
+            + self.hashes.capacity() * size_of::<u64>()
+            + self
+                .keys
+                .iter()
+                .map(|keys| keys.capacity() * size_of::<u64>())
+                .sum::<usize>()
+            + self.valid.iter().map(Vec::capacity).sum::<usize>()
+    }
+
+    fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    fn slot(&self, hash: u64) -> usize {
+        (hash ^ (hash >> 32)) as usize & (self.slots.len() - 1)
+    }
+
+    fn matches(&self, group: usize, hash: u64, columns: &[BatchColumn], row: usize) -> bool {
+        self.hashes[group] == hash
+            && columns.iter().enumerate().all(|(c, column)| {
+                self.valid[c][group] == column.valid[row] && self.keys[c][group] == column.keys[row]
+            })
+    }
+
+    /// Find the group of the `rows` of the batch, creating the missing ones,
+    /// until the table would have to grow past `limit` bytes in use by the
+    /// call. Returns the end of the rows probed.
+    fn probe(
+        &mut self,
+        columns: &[BatchColumn],
+        hashes: &[u64],
+        rows: std::ops::Range<usize>,
+        limit: Option<usize>,
+        groups: &mut Vec<u32>,
+    ) -> Result<usize> {
+        groups.clear();
+        for row in rows.clone() {
+            let hash = hashes[row];
+            let mut slot = self.slot(hash);
+            let group = loop {
+                match self.slots[slot] {
+                    0 if !self.can_insert(limit) => return Ok(row),
+                    0 => break self.insert(slot, hash, columns, row)?,
+                    group if self.matches(group as usize - 1, hash, columns, row) => {
+                        break group - 1;
+                    }
+                    _ => slot = (slot + 1) & (self.slots.len() - 1),
+                }
+            };
+            groups.push(group);
+        }
+        Ok(rows.end)
+    }
+
+    /// Whether a new group can be inserted without growing the table past
+    /// half of `limit` bytes in use by the call, leaving the rest to spill
+    /// the table. Growing doubles the memory of the table at most, and
+    /// tables without groups can always grow.
+    fn can_insert(&self, limit: Option<usize>) -> bool {
+        let grows = (self.len() + 1) * 2 > self.slots.len();
+        match limit {
+            Some(limit) if grows && self.len() > 0 => {
+                let in_use = memory::current().map_or(0, |tracker| tracker.in_use());
+                in_use + self.memory() <= limit / 2
+            }
+            _ => true,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        slot: usize,
+        hash: u64,
+        columns: &[BatchColumn],
+        row: usize,
+    ) -> Result<u32> {
+        let group = u32::try_from(self.len())
+            .ok()
+            .filter(|group| *group < u32::MAX)
+            .ok_or_else(|| Error::InvalidArgument("too many groups".to_string()))?;
+        self.slots[slot] = group + 1;
+        self.hashes.push(hash);
+        for (c, column) in columns.iter().enumerate() {
+            self.keys[c].push(column.keys[row]);
+            self.valid[c].push(column.valid[row]);
+        }
+        // Keep the table at most half full, so probe sequences are short.
+        if self.len() * 2 > self.slots.len() {
+            self.grow();
+        }
+        Ok(group)
+    }
+
+    fn grow(&mut self) {
+        self.slots = vec![0; self.slots.len() * 2];
+        for (group, hash) in self.hashes.iter().enumerate() {
+            let mut slot = self.slot(*hash);
+            while self.slots[slot] != 0 {
+                slot = (slot + 1) & (self.slots.len() - 1);
+            }
+            self.slots[slot] = group as u32 + 1;
+        }
+        self.reservation.resize(self.memory());
+    }
+
+    /// Array with the key of every group for the key column `c`.
+    fn key_array<T: KeyType>(&self, c: usize) -> ArrayData {
+        let values: Buffer = self.keys[c].iter().map(|key| T::from_key(*key)).collect();
+        let data = ArrayData::primitive(values, self.len());
+        let null_count = self.valid[c].iter().filter(|valid| !**valid).count();
+        if null_count == 0 {
+            return data;
+        }
+        let mut validity = BitmapBuilder::with_capacity(self.len());
+        self.valid[c].iter().for_each(|valid| validity.push(*valid));
+        data.with_validity(Some(validity.finish()), null_count)
+    }
+}
+
+/// Number of spill files of a group by, with the groups of a partition of
+/// the hashes each, merged one at a time.
+const SPILL_PARTITIONS: usize = 16;
+
+/// Spill file of the groups with `hash`, by its highest bits, since the
+/// lowest pick the slots of the table.
+fn partition(hash: u64) -> usize {
+    (hash >> (64 - SPILL_PARTITIONS.trailing_zeros())) as usize
+}
+
+/// The groups of a group by and the states of their aggregates.
+struct Aggregation {
+    groups: Groups,
+    accumulators: Vec<Box<dyn Accumulator>>,
+    /// For every value column, whether every group had nulls.
+    null_groups: Vec<Vec<bool>>,
+    /// The first row of every group, to restore the order of the groups
+    /// after spilling them. Only kept with a memory limit.
+    first_rows: Vec<u64>,
+}
+
+impl Aggregation {
+    fn new(n_keys: usize, accumulators: Vec<Box<dyn Accumulator>>) -> Aggregation {
+        Aggregation {
+            groups: Groups::new(n_keys),
+            null_groups: vec![Vec::new(); accumulators.len()],
+            accumulators,
+            first_rows: Vec::new(),
+        }
+    }
+
+    /// Record the first row of the groups created for `groups`, where `rows`
+    /// has the row of every element. Groups are created in the order of the
+    /// rows.
+    fn first_seen(&mut self, groups: &[u32], rows: impl Iterator<Item = u64>) {
+        for (group, row) in groups.iter().zip(rows) {
+            if *group as usize == self.first_rows.len() {
+                self.first_rows.push(row);
+            }
+        }
+    }
+
+    /// The key columns of the groups followed by their aggregates.
+    fn finish(self, keys: &[ArrowArray], propagate: bool) -> (usize, Vec<ArrayData>) {
+        let n_groups = self.groups.len();
+        let mut children = key_arrays(&self.groups, keys);
+        for (mut accumulator, mut null_groups) in
+            self.accumulators.into_iter().zip(self.null_groups)
+        {
+            if propagate {
+                null_groups.resize(n_groups, false);
+                accumulator = Box::new(PropagateNulls::new(accumulator, null_groups));
+            }
+            accumulator.resize(n_groups);
+            children.push(accumulator.finish());
+        }
+        (n_groups, children)
+    }
+
+    /// Append the groups to the spill files of their partitions, creating
+    /// them on the first spill: a batch with their hash, first row and keys,
+    /// and a batch with the serialized states of every aggregate.
+    fn spill(self, spilled: &mut Vec<SpilledPartition>, propagate: bool) -> Result<()> {
+        if spilled.is_empty() {
+            *spilled = (0..SPILL_PARTITIONS)
+                .map(|_| SpilledPartition::create(self.groups.keys.len(), self.accumulators.len()))
+                .collect::<Result<_>>()?;
+        }
+        let n_groups = self.groups.len();
+        let mut partitions = vec![Vec::new(); SPILL_PARTITIONS];
+        for (group, hash) in self.groups.hashes.iter().enumerate() {
+            partitions[partition(*hash)].push(group);
+        }
+        for (spilled, partition) in spilled.iter_mut().zip(&partitions) {
+            if partition.is_empty() {
+                continue;
+            }
+            let groups = &self.groups;
+            let hashes: Buffer = partition
+                .iter()
+                .map(|group| groups.hashes[*group])
+                .collect();
+            let rows: Buffer = partition
+                .iter()
+                .map(|group| self.first_rows[*group])
+                .collect();
+            let mut children = vec![
+                ArrayData::primitive(hashes, partition.len()),
+                ArrayData::primitive(rows, partition.len()),
+            ];
+            for (keys, valid) in groups.keys.iter().zip(&groups.valid) {
+                children.push(spilled_keys(partition, keys, valid));
+            }
+            spilled
+                .groups
+                .write(ArrayData::struct_array(children, partition.len()))?;
+        }
+        // Free the table before serializing the states, and serialize them
+        // one aggregate at a time, so spilling doesn't need much more memory
+        // than the table.
+        drop(self.groups);
+        let state_schema = Schema::new(ArrowType::Binary, "");
+        for (a, (mut accumulator, mut null_groups)) in self
+            .accumulators
+            .into_iter()
+            .zip(self.null_groups)
+            .enumerate()
+        {
+            if propagate {
+                null_groups.resize(n_groups, false);
+                accumulator = Box::new(PropagateNulls::new(accumulator, null_groups));
+            }
+            accumulator.resize(n_groups);
+            let states = ImportedArray::from(accumulator.state());
+            let states = states.view(&state_schema);
+            for (spilled, partition) in spilled.iter_mut().zip(&partitions) {
+                if partition.is_empty() {
+                    continue;
+                }
+                let mut builder = BinaryBuilder::with_capacity(partition.len());
+                for group in partition {
+                    let valid = states.is_valid(*group);
+                    builder.push(valid.then(|| states.binary_value(*group)));
+                }
+                let batch = ArrayData::struct_array(vec![builder.finish()], partition.len());
+                spilled.states[a].write(batch)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The spill files of a partition: one with the hash, first row and keys of
+/// the groups, and one with the states of every aggregate, with a batch per
+/// spill each.
+struct SpilledPartition {
+    groups: SpillFile,
+    states: Vec<SpillFile>,
+}
+
+impl SpilledPartition {
+    fn create(n_keys: usize, n_aggregates: usize) -> Result<SpilledPartition> {
+        let mut fields = vec![
+            Schema::new(ArrowType::UInt64, "hash"),
+            Schema::new(ArrowType::UInt64, "first_row"),
+        ];
+        fields.extend((0..n_keys).map(|c| Schema::new(ArrowType::UInt64, &format!("key_{c}"))));
+        let groups = Schema::new(ArrowType::Struct, "").with_children(fields);
+        let states = Schema::new(ArrowType::Struct, "")
+            .with_children(vec![Schema::new(ArrowType::Binary, "state")]);
+        Ok(SpilledPartition {
+            groups: SpillFile::create(&groups)?,
+            states: (0..n_aggregates)
+                .map(|_| SpillFile::create(&states))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// UInt64 array with the key of the `groups` of a key column.
+fn spilled_keys(groups: &[usize], keys: &[u64], valid: &[bool]) -> ArrayData {
+    let values: Buffer = groups.iter().map(|group| keys[*group]).collect();
+    let data = ArrayData::primitive(values, groups.len());
+    let null_count = groups.iter().filter(|group| !valid[**group]).count();
+    if null_count == 0 {
+        return data;
+    }
+    let mut validity = BitmapBuilder::with_capacity(groups.len());
+    groups.iter().for_each(|group| validity.push(valid[*group]));
+    data.with_validity(Some(validity.finish()), null_count)
+}
+
+/// The array with the keys of the groups of every key column.
+fn key_arrays(groups: &Groups, keys: &[ArrowArray]) -> Vec<ArrayData> {
+    keys.iter()
+        .enumerate()
+        .map(|(c, key)| {
+            with_key_type!(key.data_type(), T => {
+                groups.key_array::<T>(c)
+            }, _ => unreachable!("validated key type"))
+        })
+        .collect()
+}
+
+/// Merge the groups spilled to every partition, one at a time, and return
+/// the key columns and the aggregates of all the groups in the order they
+/// were first seen, like without spilling.
+fn merge_spilled(
+    spilled: Vec<SpilledPartition>,
+    keys: &[ArrowArray],
+    fields: &[Schema],
+    new_accumulators: impl Fn() -> Result<Vec<Box<dyn Accumulator>>>,
+    propagate: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<(usize, Vec<ArrayData>)> {
+    let mut columns: Vec<BatchColumn> = keys.iter().map(|_| BatchColumn::default()).collect();
+    let mut batch_groups = Vec::new();
+    let mut partitions = Vec::with_capacity(spilled.len());
+    for partition in spilled {
+        let mut accumulators = new_accumulators()?;
+        if propagate {
+            // Null states flag the groups with nulls.
+            accumulators = accumulators
+                .into_iter()
+                .map(|accumulator| {
+                    Box::new(PropagateNulls::new(accumulator, Vec::new())) as Box<dyn Accumulator>
+                })
+                .collect();
+        }
+        let mut aggregation = Aggregation::new(keys.len(), accumulators);
+        let mut groups = partition.groups.reader()?;
+        let mut states = partition
+            .states
+            .into_iter()
+            .map(SpillFile::reader)
+            .collect::<Result<Vec<_>>>()?;
+        while let Some(batch) = groups.next()? {
+            let batch = batch.view(groups.schema());
+            let n = batch.len();
+            let hashes = batch.child(0).values::<u64>();
+            let rows = batch.child(1).values::<u64>();
+            for (c, column) in columns.iter_mut().enumerate() {
+                let key = batch.child(2 + c);
+                column.keys.clear();
+                column.keys.extend_from_slice(key.values::<u64>());
+                column.valid.clear();
+                column.valid.extend((0..n).map(|i| key.is_valid(i)));
+            }
+            aggregation
+                .groups
+                .probe(&columns, hashes, 0..n, None, &mut batch_groups)?;
+            aggregation.first_seen(&batch_groups, rows.iter().copied());
+            let n_groups = aggregation.groups.len();
+            for (accumulator, states) in aggregation.accumulators.iter_mut().zip(&mut states) {
+                let Some(batch) = states.next()? else {
+                    return Err(Error::Io(
+                        "spill file with fewer states than groups".to_string(),
+                    ));
+                };
+                let batch = batch.view(states.schema());
+                if batch.len() != n {
+                    return Err(Error::Io(
+                        "spill file with fewer states than groups".to_string(),
+                    ));
+                }
+                accumulator.resize(n_groups);
+                accumulator.merge(&batch.child(0), 0..n, &batch_groups)?;
+            }
+        }
+        let first_rows = mem::take(&mut aggregation.first_rows);
+        let (_, children) = aggregation.finish(keys, false);
+        partitions.push((first_rows, children));
+        options.check_cancelled()?;
+        options.check_memory()?;
+    }
+
+    let mut order: Vec<(u64, usize, usize)> = partitions
+        .iter()
+        .enumerate()
+        .flat_map(|(p, (first_rows, _))| {
+            first_rows
+                .iter()
+                .enumerate()
+                .map(move |(group, row)| (*row, p, group))
+        })
+        .collect();
+    order.sort_unstable();
+    let order: Vec<(usize, usize)> = order.into_iter().map(|(_, p, group)| (p, group)).collect();
+    let mut parts: Vec<Vec<ImportedArray>> = fields.iter().map(|_| Vec::new()).collect();
+    for (_, children) in partitions {
+        for (part, child) in parts.iter_mut().zip(children) {
+            part.push(ImportedArray::from(child));
+        }
+    }
+    // The parts of every column are freed once the column is gathered.
+    let children = fields
+        .iter()
+        .zip(parts)
+        .map(|(field, part)| {
+            let part: Vec<ArrowArray> = part.iter().map(|array| array.view(field)).collect();
+            concat::interleave(&part, &order)
+        })
+        .collect::<Result<_>>()?;
+    Ok((order.len(), children))
+}
+
+/// Group the rows of `keys` and compute the aggregate of every value column.
+pub fn group_by(
+    keys: &[ArrowArray],
+    values: &[(ArrowArray, AggregateSpec)],
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if keys.is_empty() {
+        return Err(Error::InvalidArgument("group by without keys".to_string()));
+    }
+    let len = keys[0].len();
+    let arrays = keys.iter().chain(values.iter().map(|(array, _)| array));
+    if arrays.clone().any(|array| array.len() != len) {
+        return Err(Error::InvalidArgument(
+            "all the arrays of a group by must have the same length".to_string(),
+        ));
+    }
+    if options.null_policy()? == NullPolicy::Error && arrays.clone().any(|a| a.null_count() > 0) {
+        return Err(Error::NullValue);
+    }
+    if let Some(key) = keys.iter().find(|key| !is_key_type(key.data_type())) {
+        return Err(Error::UnsupportedType(format!(
+            "group by keys of type {:?}",
+            key.data_type()
+        )));
+    }
+    let masked = values
+        .iter()
+        .map(|(array, _)| nan::mask_non_finite(array, options))
+        .collect::<Result<Vec<_>>>()?;
+    let value_arrays: Vec<ArrowArray> = values
+        .iter()
+        .zip(&masked)
+        .map(|((array, _), masked)| {
+            masked
+                .as_ref()
+                .map_or(*array, |masked| masked.array(array.schema()))
+        })
+        .collect();
+    let new_accumulators = || -> Result<Vec<Box<dyn Accumulator>>> {
+        values
+            .iter()
+            .map(|(array, spec)| spec.accumulator(array.data_type()))
+            .collect()
+    };
+    let mut aggregation = Aggregation::new(keys.len(), new_accumulators()?);
+    let mut fields: Vec<Schema> = keys
+        .iter()
+        .map(|key| Schema::new(key.data_type(), &key.schema().name))
+        .collect();
+    for ((array, spec), accumulator) in values.iter().zip(&aggregation.accumulators) {
+        let name = spec.column_name(&array.schema().name);
+        fields.push(Schema::new(accumulator.output_type(), &name));
+    }
+    // With the propagate null policy, the groups with nulls of every value
+    // column are flagged from the values before masking NaN.
+    let propagate = options.null_policy()? == NullPolicy::Propagate;
+    let limit = options.memory_limit();
+    let mut spilled = Vec::new();
+
+    let mut columns: Vec<BatchColumn> = keys.iter().map(|_| BatchColumn::default()).collect();
+    let mut hashes = Vec::new();
+    let mut batch_groups = Vec::new();
+    exec::for_each_batch(len, options, |rows| {
+        hashes.clear();
+        hashes.resize(rows.len(), 0);
+        for (key, column) in keys.iter().zip(&mut columns) {
+            with_key_type!(key.data_type(), T => {
+                batch_keys::<T>(key, rows.clone(), &mut column.keys)
+            }, _ => unreachable!("validated key type"));
+            column.valid.clear();
+            match key.validity().filter(|_| key.null_count() > 0) {
+                Some(validity) => {
+                    column
+                        .valid
+                        .extend(rows.clone().map(|i| validity.is_set(i)));
+                    for (key, valid) in column.keys.iter_mut().zip(&column.valid) {
+                        if !valid {
+                            *key = 0;
+                        }
+                    }
+                }
+                None => column.valid.resize(rows.len(), true),
+            }
+            for ((hash, key), valid) in hashes.iter_mut().zip(&column.keys).zip(&column.valid) {
+                *hash = combine_hash(*hash, if *valid { *key } else { NULL_KEY });
+            }
+        }
+        // The rows of the batch with a group in the table, spilling the
+        // groups when the table is full.
+        let mut start = 0;
+        while start < rows.len() {
+            let groups = &mut aggregation.groups;
+            let end = groups.probe(
+                &columns,
+                &hashes,
+                start..rows.len(),
+                limit,
+                &mut batch_groups,
+            )?;
+            let probed = rows.start + start..rows.start + end;
+            if limit.is_some() {
+                aggregation.first_seen(&batch_groups, probed.start as u64..);
+            }
+            let n_groups = aggregation.groups.len();
+            for (accumulator, array) in aggregation.accumulators.iter_mut().zip(&value_arrays) {
+                accumulator.resize(n_groups);
+                accumulator.update(array, probed.clone(), &batch_groups);
+            }
+            if propagate {
+                for ((array, _), null_groups) in values.iter().zip(&mut aggregation.null_groups) {
+                    null_groups.resize(n_groups, false);
+                    aggregate::flag_null_groups(array, probed.clone(), &batch_groups, null_groups);
+                }
+            }
+            if end < rows.len() {
+                let empty = Aggregation::new(keys.len(), new_accumulators()?);
+                mem::replace(&mut aggregation, empty).spill(&mut spilled, propagate)?;
+            }
+            start = end;
+        }
+        Ok(())
+    })?;
+
+    let (n_groups, children) = if spilled.is_empty() {
+        aggregation.finish(keys, propagate)
+    } else {
+        aggregation.spill(&mut spilled, propagate)?;
+        merge_spilled(spilled, keys, &fields, new_accumulators, propagate, options)?
+    };
+    Ok((
+        Schema::new(ArrowType::Struct, "").with_children(fields),
+        ArrayData::struct_array(children, n_groups),
+    ))
+}
+
+/// Group the rows of the `n_keys` key arrays, and compute the aggregate
+/// named in `aggregates` for each of the `n_values` value arrays.
+///
+/// The result is a struct array with the keys of every group followed by
+/// the aggregates. Aggregates are `count`, `sum`, `min`, `max`, `mean`,
+/// `var([ddof])`, `std([ddof])`, `approx_quantile(q[, compression])`,
+/// `approx_count_distinct([precision])` and `bloom_filter(n[, fpp])`.
+///
+/// # Safety
+///
+/// `key_schemas` and `key_arrays` must point to `n_keys` valid Arrow C Data
+/// Interface arrays, `value_schemas` and `value_arrays` to `n_values`, and
+/// `aggregates` to `n_values` valid null-terminated strings. `options` must
+/// be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_group_by(
+    n_keys: i64,
+    key_schemas: *const *const ArrowCDataInterfaceSchema,
+    key_arrays: *const *const ArrowCDataInterfaceArray,
+    n_values: i64,
+    value_schemas: *const *const ArrowCDataInterfaceSchema,
+    value_arrays: *const *const ArrowCDataInterfaceArray,
+    aggregates: *const *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let key_schemas = Schema::from_ffi_list(n_keys, key_schemas)?;
+        let keys = (0..key_schemas.len())
+            .map(|i| ArrowArray::import(&key_schemas[i], &**key_arrays.add(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let value_schemas = Schema::from_ffi_list(n_values, value_schemas)?;
+        let values = (0..value_schemas.len())
+            .map(|i| {
+                let spec = CStr::from_ptr(*aggregates.add(i)).to_string_lossy();
+                Ok((
+                    ArrowArray::import(&value_schemas[i], &**value_arrays.add(i))?,
+                    AggregateSpec::parse(&spec)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (out, data) = group_by(&keys, &values, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn run(
+        keys: &[&Exported],
+        values: &[&Exported],
+        aggregates: &[&CStr],
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let key_schemas: Vec<_> = keys.iter().map(|key| &key.schema as *const _).collect();
+        let key_arrays: Vec<_> = keys.iter().map(|key| &key.array as *const _).collect();
+        let value_schemas: Vec<_> = values.iter().map(|v| &v.schema as *const _).collect();
+        let value_arrays: Vec<_> = values.iter().map(|v| &v.array as *const _).collect();
+        let aggregates: Vec<_> = aggregates.iter().map(|name| name.as_ptr()).collect();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_group_by(
+                keys.len() as i64,
+                key_schemas.as_ptr(),
+                key_arrays.as_ptr(),
+                values.len() as i64,
+                value_schemas.as_ptr(),
+                value_arrays.as_ptr(),
+                aggregates.as_ptr(),
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn groups_by_several_keys_through_ffi() {
+        let a = Exported::named("a", &[Some(1_i32), Some(1), None, Some(2), None, Some(1)]);
+        let b = Exported::named(
+            "b",
+            &[Some(0_u8), Some(5), Some(0), Some(0), Some(0), Some(0)],
+        );
+        let v = Exported::named(
+            "v",
+            &[Some(1_i64), Some(2), Some(3), None, Some(5), Some(6)],
+        );
+        let options = ArrowUdfExecOptions {
+            batch_size: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = run(&[&a, &b], &[&v, &v], &[c"sum", c"count"], &options).unwrap();
+        out.with_array(|array| {
+            let names: Vec<_> = array.schema().children.iter().map(|c| &c.name).collect();
+            assert_eq!(names, ["a", "b", "sum(v)", "count(v)"]);
+        });
+        assert_eq!(
+            out.child_values::<i32>(0),
+            [Some(1), Some(1), None, Some(2)]
+        );
+        assert_eq!(
+            out.child_values::<u8>(1),
+            [Some(0), Some(5), Some(0), Some(0)]
+        );
+        assert_eq!(
+            out.child_values::<i64>(2),
+            [Some(7), Some(2), Some(8), None]
+        );
+        assert_eq!(
+            out.child_values::<i64>(3),
+            [Some(2), Some(1), Some(2), Some(0)]
+        );
+    }
+
+    #[test]
+    fn many_groups_grow_the_table() {
+        let keys: Vec<_> = (0..5000_i64).map(|i| Some(i % 3000)).collect();
+        let key = Exported::named("k", &keys);
+        let out = run(
+            &[&key],
+            &[&key],
+            &[c"count"],
+            &ArrowUdfExecOptions::default(),
+        )
+        .unwrap();
+        let counts = out.child_values::<i64>(1);
+        assert_eq!(counts.len(), 3000);
+        assert_eq!(counts[1999], Some(2));
+        assert_eq!(counts[2000], Some(1));
+        assert_eq!(out.child_values::<i64>(0)[2999], Some(2999));
+    }
+
+    #[test]
+    fn spilled_groups_keep_their_results_and_order() {
+        let keys: Vec<_> = (0..20_000_i64)
+            .map(|i| (i % 7 != 0).then_some((i * 7919) % 6000))
+            .collect();
+        let values: Vec<_> = (0..20_000_i64).map(|i| (i % 5 != 0).then_some(i)).collect();
+        let key = Exported::named("k", &keys);
+        let value = Exported::named("v", &values);
+        let aggregates = [c"sum", c"count", c"min", c"max"];
+        let expected = run(
+            &[&key],
+            &[&value, &value, &value, &value],
+            &aggregates,
+            &ArrowUdfExecOptions::default(),
+        )
+        .unwrap();
+        // The table of 6000 groups doesn't fit in a quarter of 256 KiB.
+        let limited = ArrowUdfExecOptions {
+            batch_size: 1000,
+            memory_limit: 256 * 1024,
+            ..ArrowUdfExecOptions::default()
+        };
+        let spilled = run(
+            &[&key],
+            &[&value, &value, &value, &value],
+            &aggregates,
+            &limited,
+        )
+        .unwrap();
+        assert_eq!(spilled.child_values::<i64>(0).len(), 6001);
+        for c in 0..5 {
+            assert_eq!(
+                spilled.child_values::<i64>(c),
+                expected.child_values::<i64>(c)
+            );
+        }
+    }
+
+    #[test]
+    fn spilled_groups_propagate_nulls() {
+        let keys: Vec<_> = (0..8000_i32).map(|i| Some(i % 4000)).collect();
+        let values: Vec<_> = (0..8000_i64).map(|i| (i != 4001).then_some(i)).collect();
+        let key = Exported::named("k", &keys);
+        let value = Exported::named("v", &values);
+        let options = ArrowUdfExecOptions {
+            batch_size: 500,
+            memory_limit: 96 * 1024,
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = run(&[&key], &[&value], &[c"sum"], &options).unwrap();
+        let sums = out.child_values::<i64>(1);
+        assert_eq!(sums.len(), 4000);
+        assert_eq!(sums[0], Some(4000));
+        assert_eq!(sums[1], None);
+        assert_eq!(sums[3999], Some(3999 + 7999));
+    }
+
+    #[test]
+    fn limits_too_small_to_spill_fail() {
+        let keys: Vec<_> = (0..5000_i64).map(|i| Some(i % 3000)).collect();
+        let key = Exported::named("k", &keys);
+        let limited = ArrowUdfExecOptions {
+            memory_limit: 1024,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(
+            run(&[&key], &[&key], &[c"count"], &limited).err(),
+            Some(ArrowUdfStatus::OutOfBudget)
+        );
+    }
+
+    #[test]
+    fn invalid_group_bys_fail() {
+        let key = Exported::named("k", &[Some(1_i64), None]);
+        let float = Exported::named("f", &[Some(1.0_f64), Some(2.0)]);
+        let short = Exported::named("s", &[Some(1_i64)]);
+        let default = ArrowUdfExecOptions::default();
+        let error_on_nulls = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        for (keys, values, aggregate, options, status) in [
+            (
+                &[][..],
+                &[&key][..],
+                c"sum",
+                &default,
+                ArrowUdfStatus::InvalidArgument,
+            ),
+            (
+                &[&key],
+                &[&short],
+                c"sum",
+                &default,
+                ArrowUdfStatus::InvalidArgument,
+            ),
+            (
+                &[&key],
+                &[&key],
+                c"median",
+                &default,
+                ArrowUdfStatus::InvalidArgument,
+            ),
+            (
+                &[&float],
+                &[&key],
+                c"sum",
+                &default,
+                ArrowUdfStatus::UnsupportedType,
+            ),
+            (
+                &[&key],
+                &[&key],
+                c"sum",
+                &error_on_nulls,
+                ArrowUdfStatus::NullValue,
+            ),
+        ] {
+            assert_eq!(run(keys, values, &[aggregate], options).err(), Some(status));
+        }
+    }
+
+    #[test]
+    fn nulls_are_propagated_to_their_groups() {
+        let k = Exported::named("k", &[Some(1_i32), Some(2), Some(1), Some(2)]);
+        let v = Exported::named("v", &[Some(1_i64), None, Some(3), Some(4)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 3,
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = run(&[&k], &[&v], &[c"sum"], &options).unwrap();
+        assert_eq!(out.child_values::<i32>(0), [Some(1), Some(2)]);
+        assert_eq!(out.child_values::<i64>(1), [Some(4), None]);
+    }
+}
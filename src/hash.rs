@@ -0,0 +1,627 @@
+//! Hashes of the elements of an array, for hosts partitioning data or
+//! building hash tables.
+//!
+//! Every element is hashed with XXH3 from a canonical byte representation,
+//! so the hashes are stable across platforms and versions of the library:
+//! the little-endian bytes of primitive and temporal values, and the bytes
+//! of binary, large binary, fixed size binary, like UUIDs, and string
+//! values. For floats, `-0.0` is hashed as `0.0`, and all the NaN values as
+//! the same NaN, so values that compare equal have the same hash.
+//!
+//! `array_fingerprint` hashes a whole array instead, with the 128-bit XXH3,
+//! into a fingerprint of its content: its type, its length, which rows are
+//! null, and the canonical bytes of the values of the other ones. It doesn't
+//! depend on the offset of the array, on how its buffers are laid out or on
+//! the values hidden by nulls, so hosts can use it as a cache key, to detect
+//! changes, or to check that two systems computed the same result.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use xxhash_rust::xxh3::{xxh3_64_with_seed, Xxh3};
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, NativeType};
+use crate::udf;
+
+/// Primitive values, hashed from their canonical little-endian bytes.
+pub trait CanonicalBytes: NativeType {
+    type Bytes: AsRef<[u8]>;
+
+    fn canonical_bytes(self) -> Self::Bytes;
+}
+
+macro_rules! canonical_bytes {
+    (int: $($int:ty),*; float: $($float:ty),*) => {
+        $(
+            impl CanonicalBytes for $int {
+                type Bytes = [u8; std::mem::size_of::<$int>()];
+
+                fn canonical_bytes(self) -> Self::Bytes {
+                    self.to_le_bytes()
+                }
+            }
+        )*
+        $(
+            impl CanonicalBytes for $float {
+                type Bytes = [u8; std::mem::size_of::<$float>()];
+
+                fn canonical_bytes(self) -> Self::Bytes {
+                    let value = if self == 0.0 {
+                        0.0
+                    } else if self.is_nan() {
+                        <$float>::NAN
+                    } else {
+                        self
+                    };
+                    value.to_le_bytes()
+                }
+            }
+        )*
+    };
+}
+
+canonical_bytes! {
+    int: i8, i16, i32, i64, u8, u16, u32, u64;
+    float: f32, f64
+}
+
+fn hash_values<T: CanonicalBytes>(
+    array: &ArrowArray,
+    seed: u64,
+    out: &mut [u64],
+    options: &ArrowUdfExecOptions,
+) -> Result<()> {
+    let values = array.values::<T>();
+    exec::map(out, options, |rows, out| {
+        for (out, value) in out.iter_mut().zip(&values[rows]) {
+            *out = xxh3_64_with_seed(value.canonical_bytes().as_ref(), seed);
+        }
+        Ok(())
+    })
+}
+
+/// Hash of the values of a Binary, LargeBinary or Utf8 array, from its
+/// `offsets` into its data.
+fn hash_binary<O: Copy + Into<i64> + Sync>(
+    array: &ArrowArray,
+    offsets: &[O],
+    seed: u64,
+    out: &mut [u64],
+    options: &ArrowUdfExecOptions,
+) -> Result<()> {
+    let data = array.binary_data();
+    exec::map(out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            let value = &data[offsets[i].into() as usize..offsets[i + 1].into() as usize];
+            *out = xxh3_64_with_seed(value, seed);
+        }
+        Ok(())
+    })
+}
+
+fn hash_fixed_size_binary(
+    array: &ArrowArray,
+    seed: u64,
+    out: &mut [u64],
+    options: &ArrowUdfExecOptions,
+) -> Result<()> {
+    let width = array.schema().fixed_size_binary_width().unwrap_or_default();
+    let data = array.fixed_size_binary_data();
+    exec::map(out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            *out = xxh3_64_with_seed(&data[i * width..(i + 1) * width], seed);
+        }
+        Ok(())
+    })
+}
+
+/// Whether the values of `data_type` can be hashed.
+pub fn is_hashable(data_type: ArrowType) -> bool {
+    let data_type = data_type.physical_type();
+    with_native_type!(data_type, T => T::ARROW_TYPE == data_type, _ => {
+        matches!(
+            data_type,
+            ArrowType::Binary
+                | ArrowType::LargeBinary
+                | ArrowType::Utf8
+                | ArrowType::FixedSizeBinary
+        )
+    })
+}
+
+/// Call `f` with every non-null row in `rows` of `array`, and the hash of
+/// its value with `seed`.
+pub(crate) fn for_each_hash(
+    array: &ArrowArray,
+    rows: Range<usize>,
+    seed: u64,
+    mut f: impl FnMut(usize, u64),
+) -> Result<()> {
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let rows = rows.filter(|i| validity.is_none_or(|validity| validity.is_set(*i)));
+    let data_type = array.data_type().physical_type();
+    with_native_type!(data_type, T => {
+        let values = array.values::<T>();
+        rows.for_each(|i| f(i, xxh3_64_with_seed(values[i].canonical_bytes().as_ref(), seed)));
+    }, _ => match data_type {
+        ArrowType::Binary | ArrowType::Utf8 => {
+            let offsets = array.binary_offsets();
+            let data = array.binary_data();
+            rows.for_each(|i| {
+                let value = &data[offsets[i] as usize..offsets[i + 1] as usize];
+                f(i, xxh3_64_with_seed(value, seed))
+            });
+        }
+        ArrowType::LargeBinary => {
+            rows.for_each(|i| f(i, xxh3_64_with_seed(array.binary_value(i), seed)))
+        }
+        ArrowType::FixedSizeBinary => {
+            rows.for_each(|i| f(i, xxh3_64_with_seed(array.fixed_size_binary_value(i), seed)))
+        }
+        other => return Err(Error::UnsupportedType(format!("hash of {other:?} arrays"))),
+    });
+    Ok(())
+}
+
+/// XXH3 hash of every element of `array` with `seed`, as a UInt64 array.
+/// Null elements are null in the result.
+pub fn hash64(
+    array: &ArrowArray,
+    seed: u64,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let mut hashes = options.output_values(array.len() * std::mem::size_of::<u64>())?;
+    let out = hashes.typed_data_mut::<u64>();
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    // Temporal values are hashed like the integers they're stored as.
+    let data_type = array.data_type().physical_type();
+    with_native_type!(data_type, T => {
+        hash_values::<T>(array, seed, out, options)?
+    }, _ => match data_type {
+        ArrowType::Binary | ArrowType::Utf8 => {
+            hash_binary(array, array.binary_offsets(), seed, out, options)?
+        }
+        ArrowType::LargeBinary => {
+            hash_binary(array, array.large_binary_offsets(), seed, out, options)?
+        }
+        ArrowType::FixedSizeBinary => hash_fixed_size_binary(array, seed, out, options)?,
+        other => return Err(Error::UnsupportedType(format!("hash of {other:?} arrays"))),
+    });
+    let (validity, null_count) = udf::output_validity(array);
+    Ok((
+        Schema::new(ArrowType::UInt64, &array.schema().name),
+        ArrayData::primitive(hashes, array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// XXH3 hash of every element of a primitive, temporal, binary, large
+/// binary, fixed size binary or Utf8 array with `seed`, as a UInt64 array.
+/// Null elements are null in the result.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_hash64(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    seed: u64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = hash64(&array, seed, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// 128-bit fingerprint of the content of a primitive, Boolean, binary, large
+/// binary, fixed size binary or Utf8 array, the same for equal arrays
+/// whatever their offsets and buffers are.
+pub fn fingerprint(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<u128> {
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let mut hasher = Xxh3::new();
+    hasher.update(array.schema().format.as_bytes());
+    hasher.update(&(array.len() as u64).to_le_bytes());
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    match validity {
+        Some(validity) => {
+            hasher.update(&[1]);
+            validity
+                .chunks()
+                .for_each(|word| hasher.update(&word.to_le_bytes()));
+        }
+        None => hasher.update(&[0]),
+    }
+    let valid = |i: &usize| validity.is_none_or(|validity| validity.is_set(*i));
+    // The bytes of every batch, hashed at once.
+    let mut bytes = Vec::new();
+    let data_type = array.data_type().physical_type();
+    with_native_type!(data_type, T => {
+        let values = array.values::<T>();
+        exec::for_each_batch(array.len(), options, |rows| {
+            bytes.clear();
+            for i in rows.filter(valid) {
+                bytes.extend_from_slice(values[i].canonical_bytes().as_ref());
+            }
+            hasher.update(&bytes);
+            Ok(())
+        })?
+    }, _ => match data_type {
+        ArrowType::Boolean => {
+            // The values hidden by nulls are cleared.
+            let values = array.boolean_values();
+            let words: Vec<u64> = match validity {
+                Some(validity) => values
+                    .chunks()
+                    .zip(validity.chunks())
+                    .map(|(values, validity)| values & validity)
+                    .collect(),
+                None => values.chunks().collect(),
+            };
+            words
+                .iter()
+                .for_each(|word| hasher.update(&word.to_le_bytes()));
+            options.check_cancelled()?;
+        }
+        ArrowType::Binary
+        | ArrowType::LargeBinary
+        | ArrowType::Utf8
+        | ArrowType::FixedSizeBinary => {
+            let fixed = data_type == ArrowType::FixedSizeBinary;
+            exec::for_each_batch(array.len(), options, |rows| {
+                bytes.clear();
+                for i in rows.filter(valid) {
+                    let value = match fixed {
+                        true => array.fixed_size_binary_value(i),
+                        false => array.binary_value(i),
+                    };
+                    bytes.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                    bytes.extend_from_slice(value);
+                }
+                hasher.update(&bytes);
+                Ok(())
+            })?
+        }
+        other => {
+            return Err(Error::UnsupportedType(format!(
+                "fingerprint of {other:?} arrays"
+            )))
+        }
+    });
+    Ok(hasher.digest128())
+}
+
+/// Schema of the fingerprints of arrays described by `input`, as a fixed
+/// size binary of 16 bytes.
+pub(crate) fn fingerprint_schema(input: &Schema) -> Schema {
+    Schema::fixed_size_binary(&input.name, 16)
+}
+
+/// 128-bit XXH3 fingerprint of the content of a primitive, Boolean, binary,
+/// large binary, fixed size binary or Utf8 array, written into the 16 bytes
+/// of `out` in the canonical big-endian representation of XXH128. Equal
+/// arrays have the same fingerprint, whatever their offsets are and
+/// whatever their nulls hide.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out` must be valid for writes of
+/// 16 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_array_fingerprint(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out: *mut u8,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let fingerprint = fingerprint(&array, &options)?;
+        out.cast::<[u8; 16]>().write(fingerprint.to_be_bytes());
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::binary::BinaryBuilder;
+    use crate::buffer::Buffer;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::row::ArrowField;
+    use crate::testing::{self, Exported};
+
+    fn run(
+        input: &Exported,
+        seed: u64,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_hash64(
+                &input.schema,
+                &input.array,
+                seed,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn values_are_hashed_from_their_little_endian_bytes() {
+        let input = Exported::nullable(&[Some(1_i32), None, Some(-7)]);
+        let out = run(&input, 42, &ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(
+            out.nullable_values::<u64>(),
+            [
+                Some(xxh3_64_with_seed(&1_i32.to_le_bytes(), 42)),
+                None,
+                Some(xxh3_64_with_seed(&(-7_i32).to_le_bytes(), 42)),
+            ]
+        );
+        let other_seed = run(&input, 43, &ArrowUdfExecOptions::default()).unwrap();
+        assert_ne!(other_seed.values::<u64>()[0], out.values::<u64>()[0]);
+    }
+
+    #[test]
+    fn equal_floats_have_the_same_hash() {
+        let nan = f64::from_bits(f64::NAN.to_bits() | 1);
+        let input = Exported::primitive(&[0.0, -0.0, f64::NAN, -f64::NAN, nan, 1.0]);
+        let hashes = run(&input, 0, &ArrowUdfExecOptions::default())
+            .unwrap()
+            .values::<u64>();
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(hashes[2], hashes[3]);
+        assert_eq!(hashes[2], hashes[4]);
+        assert_ne!(hashes[0], hashes[5]);
+    }
+
+    #[test]
+    fn binary_values_are_hashed_from_their_bytes() {
+        let mut builder = BinaryBuilder::with_capacity(3);
+        builder.push(Some(b"arrow"));
+        builder.push(None);
+        builder.push(Some(b""));
+        let input = Exported::new(&Schema::new(ArrowType::Binary, "x"), builder.finish());
+        let out = run(&input, 7, &ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(
+            out.nullable_values::<u64>(),
+            [
+                Some(xxh3_64_with_seed(b"arrow", 7)),
+                None,
+                Some(xxh3_64_with_seed(b"", 7))
+            ]
+        );
+        let error = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(
+            run(&input, 7, &error).err(),
+            Some(ArrowUdfStatus::NullValue)
+        );
+    }
+
+    #[test]
+    fn row_hashes_match_the_kernel() {
+        let input = Exported::nullable(&[Some(3_u16), None, Some(9), Some(3)]);
+        let out = run(&input, 5, &ArrowUdfExecOptions::default()).unwrap();
+        let mut hashes = [None; 4];
+        input.with_array(|array| {
+            for_each_hash(array, 1..4, 5, |i, hash| hashes[i] = Some(hash)).unwrap()
+        });
+        assert_eq!(hashes[1..], out.nullable_values::<u64>()[1..]);
+        assert!(is_hashable(ArrowType::Binary) && is_hashable(ArrowType::Utf8));
+        assert!(is_hashable(ArrowType::Float32));
+        assert!(!is_hashable(ArrowType::Boolean) && !is_hashable(ArrowType::Struct));
+    }
+
+    #[cfg(feature = "temporal")]
+    #[test]
+    fn temporal_values_are_hashed_like_integers() {
+        let mut schema = Schema::new(ArrowType::Timestamp(crate::schema::TimeUnit::Second), "t");
+        schema.format = "tss:+01:00".to_string();
+        let input = Exported::new(
+            &schema,
+            crate::testing::nullable_data(&[Some(86_400_i64), None]),
+        );
+        let out = run(&input, 3, &ArrowUdfExecOptions::default()).unwrap();
+        let integers = run(
+            &Exported::nullable(&[Some(86_400_i64), None]),
+            3,
+            &ArrowUdfExecOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            out.nullable_values::<u64>(),
+            integers.nullable_values::<u64>()
+        );
+        for format in ["tdD", "tdm", "tts", "ttn", "tsu:"] {
+            assert!(
+                is_hashable(ArrowType::from_format(format).unwrap()),
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_types_are_unsupported() {
+        let booleans = Exported::boolean(&[true]);
+        let status = run(&booleans, 0, &ArrowUdfExecOptions::default()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+    }
+
+    #[test]
+    fn fixed_size_values_are_hashed_from_their_bytes() {
+        let uuids = [[1_u8; 16], [2; 16], [3; 16]];
+        let data = ArrayData::primitive(Buffer::from_slice(uuids.as_flattened()), 3)
+            .with_validity(Some(testing::validity(&[Some(()), None, Some(())])), 1);
+        let mut input = Exported::new(&Schema::uuid("id"), data);
+        (input.array.offset, input.array.length) = (1, 2);
+        let out = run(&input, 7, &ArrowUdfExecOptions::default()).unwrap();
+        let expected = [None, Some(xxh3_64_with_seed(&uuids[2], 7))];
+        assert_eq!(out.nullable_values::<u64>(), expected);
+        let mut hashes = [None; 2];
+        input.with_array(|array| {
+            for_each_hash(array, 0..2, 7, |i, hash| hashes[i] = Some(hash)).unwrap()
+        });
+        assert_eq!(hashes, expected);
+        assert!(is_hashable(ArrowType::FixedSizeBinary));
+    }
+
+    #[test]
+    fn large_binary_values_hash_like_binary_values() {
+        let values: [Option<&[u8]>; 3] = [Some(b"arrow"), None, Some(b"udf")];
+        let mut binary = BinaryBuilder::with_capacity(3);
+        let mut large = BinaryBuilder::large_with_capacity(3);
+        for value in values {
+            binary.push(value);
+            large.push(value);
+        }
+        let binary = Exported::new(&Schema::new(ArrowType::Binary, "x"), binary.finish());
+        let large = Exported::new(&Schema::new(ArrowType::LargeBinary, "x"), large.finish());
+        let options = ArrowUdfExecOptions::default();
+        let expected = run(&binary, 9, &options).unwrap().nullable_values::<u64>();
+        assert_eq!(
+            run(&large, 9, &options).unwrap().nullable_values::<u64>(),
+            expected
+        );
+        let mut hashes = [None; 3];
+        large.with_array(|array| {
+            for_each_hash(array, 0..3, 9, |i, hash| hashes[i] = Some(hash)).unwrap()
+        });
+        assert_eq!(hashes[..], expected);
+        assert!(is_hashable(ArrowType::LargeBinary));
+    }
+
+    fn fingerprint_of(input: &Exported) -> u128 {
+        input
+            .with_array(|array| fingerprint(array, &ArrowUdfExecOptions::default()))
+            .unwrap()
+    }
+
+    #[test]
+    fn fingerprints_of_equal_arrays_match() {
+        let values = [Some(1_i32), None, Some(3), Some(4)];
+        let expected = fingerprint_of(&Exported::nullable(&values));
+        // The same rows at an offset, with another value hidden by the null.
+        let hidden = [Some(0_i32), Some(1), Some(99), Some(3), Some(4)];
+        let data = ArrayData::primitive(Buffer::from_slice(&[0_i32, 1, 99, 3, 4]), 5)
+            .with_validity(
+                Some(testing::validity(&[
+                    Some(0),
+                    Some(1),
+                    None,
+                    Some(3),
+                    Some(4),
+                ])),
+                1,
+            );
+        let mut sliced = Exported::new(&Schema::new(ArrowType::Int32, "x"), data);
+        (
+            sliced.array.offset,
+            sliced.array.length,
+            sliced.array.null_count,
+        ) = (1, 4, -1);
+        assert_eq!(fingerprint_of(&sliced), expected);
+        assert_ne!(fingerprint_of(&Exported::nullable(&hidden[1..])), expected);
+        assert_ne!(
+            fingerprint_of(&Exported::nullable(&[
+                Some(1_i32),
+                Some(0),
+                Some(3),
+                Some(4)
+            ])),
+            expected
+        );
+        // Types with the same bytes are told apart by their format.
+        let (int32, uint32) = (Exported::primitive(&[1_i32]), Exported::primitive(&[1_u32]));
+        assert_ne!(fingerprint_of(&int32), fingerprint_of(&uint32));
+        let floats = (
+            Exported::primitive(&[0.0_f64]),
+            Exported::primitive(&[-0.0_f64]),
+        );
+        assert_eq!(fingerprint_of(&floats.0), fingerprint_of(&floats.1));
+    }
+
+    #[test]
+    fn fingerprints_of_booleans_and_strings() {
+        let data = bool::build([Some(&true), None, Some(&false)].into_iter());
+        let a = Exported::new(&Schema::new(ArrowType::Boolean, "x"), data);
+        let data = bool::build([Some(&true), None, Some(&false)].into_iter());
+        let mut b = Exported::new(&Schema::new(ArrowType::Boolean, "y"), data);
+        assert_eq!(fingerprint_of(&a), fingerprint_of(&b));
+        b.array.length = 2;
+        assert_ne!(fingerprint_of(&a), fingerprint_of(&b));
+        let split = |values: &[Option<&str>]| fingerprint_of(&Exported::utf8(values));
+        assert_ne!(
+            split(&[Some("ab"), Some("c")]),
+            split(&[Some("a"), Some("bc")])
+        );
+        assert_eq!(
+            split(&[Some("ab"), None, Some("c")]),
+            split(&[Some("ab"), None, Some("c")])
+        );
+    }
+
+    #[test]
+    fn fingerprints_through_the_entry_point() {
+        let input = Exported::primitive(&[1_u64, 2, 3]);
+        let mut out = [0_u8; 16];
+        let status = unsafe {
+            arrow_udf_array_fingerprint(&input.schema, &input.array, ptr::null(), out.as_mut_ptr())
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out, fingerprint_of(&input).to_be_bytes());
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Int32, "a")]);
+        let child = ArrayData::primitive(Buffer::from_slice(&[1_i32]), 1);
+        let structs = Exported::new(&schema, ArrayData::struct_array(vec![child], 1));
+        let status = unsafe {
+            arrow_udf_array_fingerprint(
+                &structs.schema,
+                &structs.array,
+                ptr::null(),
+                out.as_mut_ptr(),
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+        let nulls = Exported::nullable(&[Some(1_i32), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let failed = nulls.with_array(|array| fingerprint(array, &options).err());
+        assert_eq!(failed, Some(Error::NullValue));
+    }
+}
@@ -0,0 +1,252 @@
+//! Histogram of the values of a numeric array, with equal width bins.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+use crate::dispatch::{self, Kernel};
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::nan;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::Numeric;
+use crate::udf;
+
+/// Number of values of `array` in each of the `n_bins` bins between `min`
+/// and `max`.
+fn counts<T: Numeric>(
+    array: &ArrowArray,
+    n_bins: usize,
+    min: f64,
+    max: f64,
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<i64>> {
+    udf::check_input::<T>(array, options)?;
+    let masked = nan::mask_non_finite(array, options)?;
+    let array = &masked
+        .as_ref()
+        .map_or(*array, |masked| masked.array(array.schema()));
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let scale = n_bins as f64 / (max - min);
+    exec::reduce_values(
+        values,
+        options,
+        vec![0; n_bins],
+        |mut counts, rows| {
+            for i in rows {
+                let value = values[i].to_f64();
+                let valid = validity.is_none_or(|validity| validity.is_set(i));
+                // NaN is never in the range, so it's not counted.
+                if !valid || !(min..=max).contains(&value) {
+                    continue;
+                }
+                // The maximum belongs to the last bin, which is closed.
+                let bin = (((value - min) * scale) as usize).min(n_bins - 1);
+                counts[bin] += 1;
+            }
+            counts
+        },
+        |mut a, b| {
+            a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+            a
+        },
+    )
+}
+
+struct Counts;
+
+impl Kernel for Counts {
+    type Args = (usize, f64, f64);
+    type Output = Vec<i64>;
+
+    fn name(_: &(usize, f64, f64)) -> String {
+        "histogram".to_string()
+    }
+
+    fn call<T: Numeric>(
+        array: &ArrowArray,
+        (n_bins, min, max): &(usize, f64, f64),
+        options: &ArrowUdfExecOptions,
+    ) -> Result<Vec<i64>> {
+        counts::<T>(array, *n_bins, *min, *max, options)
+    }
+}
+
+/// Histogram of `array` with `n_bins` bins of the same width between `min`
+/// and `max`, as a struct array with a row per bin and the fields
+/// `bin_start`, `bin_end` and `count`.
+///
+/// Bins include their start and exclude their end, except the last one,
+/// which includes `max`. Nulls, NaN and values outside the range are not
+/// counted.
+pub fn histogram(
+    array: &ArrowArray,
+    n_bins: i64,
+    min: f64,
+    max: f64,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if n_bins <= 0 {
+        return Err(Error::InvalidArgument(format!(
+            "the number of bins must be positive, got {n_bins}"
+        )));
+    }
+    if !(min.is_finite() && max.is_finite() && min < max) {
+        return Err(Error::InvalidArgument(format!(
+            "invalid histogram range [{min}, {max}]"
+        )));
+    }
+    let n_bins = n_bins as usize;
+    let counts = dispatch::call::<Counts>(array, &(n_bins, min, max), options)?;
+    let edge = |i: usize| match i {
+        i if i == n_bins => max,
+        i => min + (max - min) * i as f64 / n_bins as f64,
+    };
+    let starts: Vec<f64> = (0..n_bins).map(edge).collect();
+    let ends: Vec<f64> = (1..=n_bins).map(edge).collect();
+    let schema = output_schema(array.schema());
+    let data = ArrayData::struct_array(
+        vec![
+            ArrayData::primitive(Buffer::from_slice(&starts), n_bins),
+            ArrayData::primitive(Buffer::from_slice(&ends), n_bins),
+            ArrayData::primitive(Buffer::from_slice(&counts), n_bins),
+        ],
+        n_bins,
+    );
+    Ok((schema, data))
+}
+
+/// Schema of the histograms of arrays described by `input`.
+pub(crate) fn output_schema(input: &Schema) -> Schema {
+    Schema::new(ArrowType::Struct, &input.name).with_children(vec![
+        Schema::new(ArrowType::Float64, "bin_start"),
+        Schema::new(ArrowType::Float64, "bin_end"),
+        Schema::new(ArrowType::Int64, "count"),
+    ])
+}
+
+/// Histogram of a numeric array with `n_bins` bins between `min` and `max`,
+/// as a struct array of `bin_start`, `bin_end` and `count`.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_histogram(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    n_bins: i64,
+    min: f64,
+    max: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = histogram(&array, n_bins, min, max, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn run(
+        input: &Exported,
+        n_bins: i64,
+        min: f64,
+        max: f64,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_histogram(
+                &input.schema,
+                &input.array,
+                n_bins,
+                min,
+                max,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn values_are_counted_in_their_bin() {
+        let input = Exported::nullable(&[
+            Some(0.0_f64),
+            Some(0.5),
+            Some(2.49),
+            Some(2.5),
+            Some(10.0),
+            Some(10.5),
+            Some(-0.1),
+            Some(f64::NAN),
+            None,
+        ]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            num_threads: 3,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = run(&input, 4, 0.0, 10.0, &options).unwrap();
+        let edges = [Some(0.0), Some(2.5), Some(5.0), Some(7.5), Some(10.0)];
+        assert_eq!(out.child_values::<f64>(0), edges[..4]);
+        assert_eq!(out.child_values::<f64>(1), edges[1..]);
+        assert_eq!(
+            out.child_values::<i64>(2),
+            [Some(3), Some(1), Some(0), Some(1)]
+        );
+    }
+
+    #[test]
+    fn integer_arrays() {
+        let input = Exported::primitive(&[1_u8, 2, 3, 200]);
+        let out = run(&input, 2, 0.0, 4.0, &ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(out.child_values::<i64>(2), [Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn invalid_histograms_fail() {
+        let input = Exported::nullable(&[Some(1_i64), None]);
+        let default = ArrowUdfExecOptions::default();
+        for (n_bins, min, max) in [(0, 0.0, 1.0), (2, 1.0, 1.0), (2, 0.0, f64::INFINITY)] {
+            assert_eq!(
+                run(&input, n_bins, min, max, &default).err(),
+                Some(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        let error_on_nulls = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(
+            run(&input, 2, 0.0, 1.0, &error_on_nulls).err(),
+            Some(ArrowUdfStatus::NullValue)
+        );
+        let booleans = Exported::boolean(&[true]);
+        assert_eq!(
+            run(&booleans, 2, 0.0, 1.0, &default).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+}
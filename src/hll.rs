@@ -0,0 +1,388 @@
+//! HyperLogLog sketches, used to approximate the number of distinct values.
+//!
+//! Every value is hashed to 64 bits. The first `p` bits of the hash, the
+//! precision, select one of the `2^p` registers of the sketch, which keeps
+//! the maximum number of leading zeros seen in the rest of the hash. The
+//! number of distinct values is estimated from the harmonic mean of the
+//! registers, using linear counting when it's small compared to the number of
+//! registers. The relative error is around `1.04 / sqrt(2^p)`: 1.6% with the
+//! default precision of 12, using 4KiB per sketch.
+//!
+//! Sketches with the same precision are merged by taking the maximum of
+//! every register, so partial results can be combined exactly.
+
+use std::ops::Range;
+
+use crate::aggregate::{self, Accumulator};
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::schema::ArrowType;
+use crate::types::{with_native_type, NativeType};
+
+/// Precision used when the spec doesn't specify it.
+pub const DEFAULT_PRECISION: u32 = 12;
+pub const MIN_PRECISION: u32 = 4;
+pub const MAX_PRECISION: u32 = 18;
+
+/// Finalizer of MurmurHash3, mixing all the bits of `x` into the result.
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+/// Values whose hash is used to count distinct values.
+pub(crate) trait HashValue: NativeType {
+    fn hash(self) -> u64;
+}
+
+macro_rules! hash_value {
+    (int: $($int:ty),*; float: $($float:ty),*) => {
+        $(
+            impl HashValue for $int {
+                fn hash(self) -> u64 {
+                    mix(self as u64)
+                }
+            }
+        )*
+        $(
+            impl HashValue for $float {
+                fn hash(self) -> u64 {
+                    // Values that compare equal must have the same hash.
+                    let value = if self == 0.0 {
+                        0.0
+                    } else if self.is_nan() {
+                        <$float>::NAN
+                    } else {
+                        self
+                    };
+                    mix(value.to_bits() as u64)
+                }
+            }
+        )*
+    };
+}
+
+hash_value! {
+    int: i8, i16, i32, i64, u8, u16, u32, u64;
+    float: f32, f64
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash = 0x9e37_79b9_7f4a_7c15 ^ bytes.len() as u64;
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        hash = mix(hash ^ u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    let mut tail = [0; 8];
+    tail[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    mix(hash ^ u64::from_le_bytes(tail))
+}
+
+/// Call `f` with the group and the hash of the non-null `rows` of `values`.
+type HashRows = fn(&ArrowArray, Range<usize>, &[u32], &mut dyn FnMut(usize, u64));
+
+fn hash_rows<T: HashValue>(
+    values: &ArrowArray,
+    rows: Range<usize>,
+    groups: &[u32],
+    f: &mut dyn FnMut(usize, u64),
+) {
+    aggregate::for_each_valid(values, rows, groups, |group, value: T| {
+        f(group, value.hash())
+    });
+}
+
+fn hash_binary_rows(
+    values: &ArrowArray,
+    rows: Range<usize>,
+    groups: &[u32],
+    f: &mut dyn FnMut(usize, u64),
+) {
+    for (row, group) in rows.zip(groups) {
+        if values.is_valid(row) {
+            f(*group as usize, hash_bytes(values.binary_value(row)));
+        }
+    }
+}
+
+/// Add `hash` to the registers of a sketch.
+fn add_hash(registers: &mut [u8], precision: u32, hash: u64) {
+    let index = (hash >> (64 - precision)) as usize;
+    // The sentinel bit bounds the rank when the rest of the hash is zero.
+    let rank = ((hash << precision) | (1 << (precision - 1))).leading_zeros() as u8 + 1;
+    registers[index] = registers[index].max(rank);
+}
+
+/// Estimate the number of distinct values added to `registers`.
+fn estimate(registers: &[u8]) -> f64 {
+    let m = registers.len() as f64;
+    let alpha = match registers.len() {
+        16 => 0.673,
+        32 => 0.697,
+        64 => 0.709,
+        _ => 0.7213 / (1.0 + 1.079 / m),
+    };
+    let sum: f64 = registers
+        .iter()
+        .map(|register| 2f64.powi(-(*register as i32)))
+        .sum();
+    let raw = alpha * m * m / sum;
+    let zeros = registers.iter().filter(|register| **register == 0).count();
+    if raw <= 2.5 * m && zeros > 0 {
+        m * (m / zeros as f64).ln()
+    } else {
+        raw
+    }
+}
+
+/// Sketch of the distinct values of a single array, for the kernels
+/// counting them along other statistics.
+#[derive(Clone)]
+pub(crate) struct Sketch {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl Sketch {
+    pub(crate) fn new(precision: u32) -> Sketch {
+        Sketch {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub(crate) fn add<T: HashValue>(&mut self, value: T) {
+        add_hash(&mut self.registers, self.precision, value.hash());
+    }
+
+    pub(crate) fn add_bytes(&mut self, bytes: &[u8]) {
+        add_hash(&mut self.registers, self.precision, hash_bytes(bytes));
+    }
+
+    /// Merge a sketch with the same precision.
+    pub(crate) fn merge(&mut self, other: &Sketch) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    pub(crate) fn estimate(&self) -> i64 {
+        estimate(&self.registers).round() as i64
+    }
+}
+
+/// Accumulator of `approx_count_distinct([precision])`.
+pub(crate) fn accumulator(data_type: ArrowType, args: &[f64]) -> Result<Box<dyn Accumulator>> {
+    let precision = match *args {
+        [] => DEFAULT_PRECISION,
+        [precision]
+            if precision.fract() == 0.0
+                && (MIN_PRECISION as f64..=MAX_PRECISION as f64).contains(&precision) =>
+        {
+            precision as u32
+        }
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "approx_count_distinct expects an optional precision between {MIN_PRECISION} \
+                 and {MAX_PRECISION}"
+            )))
+        }
+    };
+    let hash_rows: HashRows = with_native_type!(data_type, T => hash_rows::<T>, _ => {
+        match data_type {
+            ArrowType::Binary | ArrowType::Utf8 => hash_binary_rows,
+            other => return Err(aggregate::unsupported("approx_count_distinct", other)),
+        }
+    });
+    Ok(Box::new(DistinctCount {
+        precision,
+        registers: Vec::new(),
+        hash_rows,
+    }))
+}
+
+struct DistinctCount {
+    precision: u32,
+    /// The registers of all the groups, one after the other.
+    registers: Vec<u8>,
+    hash_rows: HashRows,
+}
+
+impl DistinctCount {
+    fn group_registers(&mut self, group: usize) -> &mut [u8] {
+        let m = 1 << self.precision;
+        &mut self.registers[group * m..(group + 1) * m]
+    }
+}
+
+impl Accumulator for DistinctCount {
+    fn output_type(&self) -> ArrowType {
+        ArrowType::Int64
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.registers.resize(n_groups << self.precision, 0);
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        let precision = self.precision;
+        let hash_rows = self.hash_rows;
+        hash_rows(values, rows, groups, &mut |group, hash| {
+            add_hash(self.group_registers(group), precision, hash)
+        });
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        aggregate::for_each_state(states, rows, groups, |group, mut state| {
+            let precision = state.u64()?;
+            if precision != self.precision as u64 {
+                return Err(Error::InvalidArgument(format!(
+                    "can't merge sketches with precision {precision} and {}",
+                    self.precision
+                )));
+            }
+            let other = state.bytes(1 << self.precision)?;
+            for (register, other) in self.group_registers(group).iter_mut().zip(other) {
+                *register = (*register).max(*other);
+            }
+            Ok(())
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        let m = 1 << self.precision;
+        aggregate::states_array(self.registers.len() / m, |group, state| {
+            state.extend_from_slice(&(self.precision as u64).to_le_bytes());
+            state.extend_from_slice(&self.registers[group * m..(group + 1) * m]);
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        let counts: Vec<i64> = self
+            .registers
+            .chunks_exact(1 << self.precision)
+            .map(|registers| estimate(registers).round() as i64)
+            .collect();
+        ArrayData::primitive(Buffer::from_slice(&counts), counts.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::BinaryBuilder;
+    use crate::schema::Schema;
+    use crate::testing::Exported;
+
+    /// Estimate of the distinct values of `input`, with a single group.
+    fn count(input: &Exported, args: &[f64]) -> i64 {
+        input.with_array(|array| {
+            let mut accumulator = accumulator(array.data_type(), args).unwrap();
+            accumulator.resize(1);
+            accumulator.update(array, 0..array.len(), &vec![0; array.len()]);
+            let result = Exported::new(&Schema::new(ArrowType::Int64, "x"), accumulator.finish());
+            result.values::<i64>()[0]
+        })
+    }
+
+    #[test]
+    fn estimates_are_within_the_expected_error() {
+        let values: Vec<i64> = (0..200_000).map(|i| (i % 50_000) * 7).collect();
+        let estimate = count(&Exported::primitive(&values), &[]);
+        assert!((estimate - 50_000).abs() < 2_500, "{estimate}");
+        let estimate = count(&Exported::primitive(&values), &[16.0]);
+        assert!((estimate - 50_000).abs() < 1_000, "{estimate}");
+        let small: Vec<u8> = (0..=255).chain(0..=255).collect();
+        let estimate = count(&Exported::primitive(&small), &[]);
+        assert!((estimate - 256).abs() < 8, "{estimate}");
+    }
+
+    #[test]
+    fn equal_values_have_the_same_hash() {
+        let floats = Exported::nullable(&[Some(0.0_f64), Some(-0.0), Some(f64::NAN), None]);
+        assert_eq!(count(&floats, &[]), 2);
+        let mut builder = BinaryBuilder::with_capacity(4);
+        for value in [&b"abc"[..], b"", b"abc", b"0123456789"] {
+            builder.push(Some(value));
+        }
+        let binary = Exported::new(&Schema::new(ArrowType::Binary, "x"), builder.finish());
+        assert_eq!(count(&binary, &[]), 3);
+    }
+
+    #[test]
+    fn merged_sketches_are_exact() {
+        let input = Exported::primitive(&(0..10_000_i32).collect::<Vec<_>>());
+        let (single, merged) = input.with_array(|array| {
+            let mut single = accumulator(ArrowType::Int32, &[]).unwrap();
+            single.resize(1);
+            single.update(array, 0..10_000, &[0; 10_000]);
+            let mut partial = accumulator(ArrowType::Int32, &[]).unwrap();
+            partial.resize(2);
+            let groups: Vec<u32> = (0..10_000).map(|i| i % 2).collect();
+            partial.update(array, 0..10_000, &groups);
+            let states = Exported::new(&Schema::new(ArrowType::Binary, "x"), partial.state());
+            let mut merged = accumulator(ArrowType::Int32, &[]).unwrap();
+            merged.resize(1);
+            states.with_array(|states| merged.merge(states, 0..2, &[0, 0]).unwrap());
+            (single.finish(), merged.finish())
+        });
+        let schema = Schema::new(ArrowType::Int64, "x");
+        assert_eq!(
+            Exported::new(&schema, single).values::<i64>(),
+            Exported::new(&schema, merged).values::<i64>()
+        );
+    }
+
+    #[test]
+    fn precisions_must_match() {
+        let input = Exported::primitive(&[1_i64]);
+        let states = input.with_array(|array| {
+            let mut accumulator = accumulator(ArrowType::Int64, &[10.0]).unwrap();
+            accumulator.resize(1);
+            accumulator.update(array, 0..1, &[0]);
+            Exported::new(&Schema::new(ArrowType::Binary, "x"), accumulator.state())
+        });
+        let mut other = accumulator(ArrowType::Int64, &[12.0]).unwrap();
+        other.resize(1);
+        let result = states.with_array(|states| other.merge(states, 0..1, &[0]));
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn arguments_are_validated() {
+        for args in [&[3.0][..], &[19.0], &[12.5], &[12.0, 1.0]] {
+            assert!(matches!(
+                accumulator(ArrowType::Int64, args),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        assert!(matches!(
+            accumulator(ArrowType::Boolean, &[]),
+            Err(Error::UnsupportedType(_))
+        ));
+    }
+
+    #[test]
+    fn sketches_merge_like_their_union() {
+        let (mut a, mut b) = (
+            Sketch::new(DEFAULT_PRECISION),
+            Sketch::new(DEFAULT_PRECISION),
+        );
+        for i in 0..600_i64 {
+            a.add(i);
+            b.add(i + 300);
+        }
+        b.add_bytes(b"abc");
+        let mut union = Sketch::new(DEFAULT_PRECISION);
+        (0..900_i64).for_each(|i| union.add(i));
+        union.add_bytes(b"abc");
+        a.merge(&b);
+        assert_eq!(a.estimate(), union.estimate());
+        assert!((880..=920).contains(&a.estimate()), "{}", a.estimate());
+    }
+}
@@ -0,0 +1,293 @@
+//! Conditional selection of the values of two numeric operands, by the
+//! rows of a Boolean array.
+//!
+//! `if_else(cond, then, else)` takes every row from `then` where `cond` is
+//! true and from `else` where it's false, in a single pass, instead of
+//! computing both masks and merging their results. The branches are arrays
+//! of the length of the condition, arrays of a single element, which are
+//! broadcast, or, from Rust, `f64` scalars converted to the type of the
+//! arrays, like the operands of `arith`. Rows where the condition is null
+//! are null, like in `arrow.compute.if_else`, and so are the rows taking a
+//! null value from their branch.
+
+use std::sync::Arc;
+
+use crate::arith::{Operand, Values};
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, Numeric};
+
+fn select<T: Numeric>(
+    cond: &ArrowArray,
+    then: Operand,
+    otherwise: Operand,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let len = cond.len();
+    let (then, otherwise) = (
+        Values::<T>::new(then, len, options)?,
+        Values::<T>::new(otherwise, len, options)?,
+    );
+    let values = cond.boolean_values();
+    let validity = cond.validity().filter(|_| cond.null_count() > 0);
+    let mut out: Vec<Option<T>> = vec![None; len];
+    exec::map(&mut out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            if validity.is_some_and(|validity| !validity.is_set(i)) {
+                continue;
+            }
+            *out = match values.is_set(i) {
+                true => then.get(i),
+                false => otherwise.get(i),
+            };
+        }
+        Ok(())
+    })?;
+    let mut data = options.output_values(len * std::mem::size_of::<T>())?;
+    let mut valid = BitmapBuilder::with_capacity(len);
+    for (value, out) in data.typed_data_mut::<T>().iter_mut().zip(&out) {
+        *value = out.unwrap_or_default();
+        valid.push(out.is_some());
+    }
+    let null_count = out.iter().filter(|out| out.is_none()).count();
+    let data = ArrayData::primitive(data, len);
+    Ok(match null_count {
+        0 => data,
+        _ => data.with_validity(Some(valid.finish()), null_count),
+    })
+}
+
+/// The row of `then` for every true row of the Boolean array `cond`, and the
+/// row of `otherwise` for the false ones, with the name and the type of the
+/// first array of the branches, or Float64 if both are scalars.
+pub fn if_else(
+    cond: &ArrowArray,
+    then: Operand,
+    otherwise: Operand,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if cond.data_type() != ArrowType::Boolean {
+        return Err(Error::UnsupportedType(format!(
+            "expected a Boolean condition, got {:?}",
+            cond.data_type()
+        )));
+    }
+    if options.null_policy()? == NullPolicy::Error && cond.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let branches: Vec<&ArrowArray> = [then, otherwise]
+        .into_iter()
+        .filter_map(|operand| match operand {
+            Operand::Array(array) => Some(array),
+            Operand::Scalar(_) => None,
+        })
+        .collect();
+    for branch in &branches {
+        if branch.len() != cond.len() && branch.len() != 1 {
+            return Err(Error::InvalidArgument(format!(
+                "a branch of {} rows for a condition of {}",
+                branch.len(),
+                cond.len()
+            )));
+        }
+        if branch.data_type() != branches[0].data_type() {
+            return Err(Error::UnsupportedType(format!(
+                "branches of different types: {:?} and {:?}",
+                branches[0].data_type(),
+                branch.data_type()
+            )));
+        }
+    }
+    let (data_type, name) = match branches.first() {
+        Some(branch) => (branch.data_type(), branch.schema().name.as_str()),
+        None => (ArrowType::Float64, cond.schema().name.as_str()),
+    };
+    let data = with_native_type!(data_type, T => {
+        select::<T>(cond, then, otherwise, options)?
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "if_else of {data_type:?} branches"
+    ))));
+    Ok((Schema::new(data_type, name), data))
+}
+
+/// Schema of the result of `if_else` for inputs described by the fields of
+/// a struct schema: the condition and the branches.
+pub(crate) fn output_schema(input: &Schema) -> Schema {
+    let then = &input.children[1];
+    Schema::new(then.data_type, &then.name)
+}
+
+/// The row of `then` for every true row of the Boolean array `cond`, and the
+/// row of `else` for the false ones. The branches are numeric arrays of the
+/// same type, of the length of `cond` or of a single element, which is
+/// broadcast like a scalar. Rows where `cond` is null, or the value taken
+/// is, are null.
+///
+/// # Safety
+///
+/// `cond_schema` and `cond_array`, `then_schema` and `then_array`, and
+/// `else_schema` and `else_array` must point to valid Arrow C Data
+/// Interface arrays, `options` must be null or valid, and `out_schema` and
+/// `out_array` must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_if_else(
+    cond_schema: *const ArrowCDataInterfaceSchema,
+    cond_array: *const ArrowCDataInterfaceArray,
+    then_schema: *const ArrowCDataInterfaceSchema,
+    then_array: *const ArrowCDataInterfaceArray,
+    else_schema: *const ArrowCDataInterfaceSchema,
+    else_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let cond_schema = Schema::from_ffi(&*cond_schema)?;
+        let cond = ArrowArray::import(&cond_schema, &*cond_array)?;
+        let then_schema = Schema::from_ffi(&*then_schema)?;
+        let then = ArrowArray::import(&then_schema, &*then_array)?;
+        let else_schema = Schema::from_ffi(&*else_schema)?;
+        let otherwise = ArrowArray::import(&else_schema, &*else_array)?;
+        let (out, data) = if_else(
+            &cond,
+            Operand::Array(&then),
+            Operand::Array(&otherwise),
+            &options,
+        )?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::row::ArrowField;
+    use crate::testing::Exported;
+
+    fn condition(values: &[Option<bool>]) -> Exported {
+        let data = bool::build(values.iter().map(Option::as_ref));
+        Exported::new(&Schema::new(ArrowType::Boolean, "cond"), data)
+    }
+
+    fn run(
+        cond: &Exported,
+        then: &Exported,
+        otherwise: &Exported,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_if_else(
+                &cond.schema,
+                &cond.array,
+                &then.schema,
+                &then.array,
+                &otherwise.schema,
+                &otherwise.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn rows_are_taken_from_their_branch() {
+        let cond = condition(&[Some(true), Some(false), None, Some(true)]);
+        let then = Exported::named("then", &[Some(1_i32), Some(2), Some(3), None]);
+        let otherwise = Exported::named("else", &[Some(10_i32), Some(20), Some(30), Some(40)]);
+        let out = run(&cond, &then, &otherwise).unwrap();
+        assert_eq!(
+            out.nullable_values::<i32>(),
+            [Some(1), Some(20), None, None]
+        );
+        out.with_array(|array| assert_eq!(array.schema().name, "then"));
+        let zero = Exported::primitive(&[0_i32]);
+        let broadcast = run(&cond, &then, &zero).unwrap();
+        assert_eq!(
+            broadcast.nullable_values::<i32>(),
+            [Some(1), Some(0), None, None]
+        );
+    }
+
+    #[test]
+    fn scalar_branches_are_converted_to_the_type_of_the_arrays() {
+        let cond = condition(&[Some(true), Some(false)]);
+        let options = ArrowUdfExecOptions::default();
+        let then = Exported::primitive(&[1.5_f32, 2.5]);
+        let (schema, data) = cond
+            .with_array(|cond| {
+                then.with_array(|then| {
+                    if_else(cond, Operand::Array(then), Operand::Scalar(-1.0), &options)
+                })
+            })
+            .unwrap();
+        assert_eq!(schema.data_type, ArrowType::Float32);
+        assert_eq!(Exported::new(&schema, data).values::<f32>(), [1.5, -1.0]);
+        let (schema, data) = cond
+            .with_array(|cond| if_else(cond, Operand::Scalar(1.0), Operand::Scalar(2.0), &options))
+            .unwrap();
+        assert_eq!(
+            (schema.data_type, schema.name.as_str()),
+            (ArrowType::Float64, "cond")
+        );
+        assert_eq!(Exported::new(&schema, data).values::<f64>(), [1.0, 2.0]);
+        let int8 = Exported::primitive(&[1_i8, 2]);
+        let failed = cond.with_array(|cond| {
+            int8.with_array(|then| {
+                if_else(cond, Operand::Array(then), Operand::Scalar(0.5), &options).err()
+            })
+        });
+        assert!(matches!(failed, Some(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn invalid_inputs_fail() {
+        let cond = condition(&[Some(true), None]);
+        let int32 = Exported::primitive(&[1_i32, 2]);
+        let int64 = Exported::primitive(&[1_i64, 2]);
+        let three = Exported::primitive(&[1_i32, 2, 3]);
+        assert_eq!(
+            run(&cond, &int32, &int64).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        assert_eq!(
+            run(&cond, &int32, &three).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            run(&int32, &int32, &int32).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let strings = Exported::utf8(&[Some("a"), Some("b")]);
+        assert_eq!(
+            run(&cond, &strings, &strings).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let failed = cond.with_array(|cond| {
+            int32.with_array(|then| {
+                if_else(cond, Operand::Array(then), Operand::Array(then), &options).err()
+            })
+        });
+        assert_eq!(failed, Some(Error::NullValue));
+    }
+}
@@ -0,0 +1,1117 @@
+//! Arrow IPC streams of record batches, for the files written by the
+//! library: spill files and traces.
+//!
+//! A stream is a sequence of messages, each of them a continuation marker,
+//! the length of its metadata, the metadata as a FlatBuffers `Message`
+//! table, see `flatbuffers`, and its body. The first message is the schema
+//! of the batches, and every following one a record batch, whose body has
+//! the buffers of its arrays, every one of them aligned to 8 bytes. The
+//! metadata lists the length and null count of every array, depth first,
+//! and the position of every buffer in the body. The stream ends with a
+//! marker followed by a length of 0, or at the end of the input.
+//!
+//! Record batches are struct arrays without nulls, whose fields are the
+//! fields of the schema, and the metadata of the struct is the metadata of
+//! the schema. Arrays of the types of the segments, see `segment`, plus
+//! LargeBinary, FixedSizeBinary and intervals can be written and read;
+//! dictionaries, compression, maps and unions aren't supported. Arrays don't
+//! have offsets in IPC, so the bitmaps of sliced arrays are written again
+//! from their first bit, the offsets of binary arrays are rebased to the
+//! start of their data, and run-end encoded arrays only keep their runs in
+//! the slice.
+//!
+//! Streams are read from files, so the reader checks that every buffer is
+//! in the body and as long as the arrays need, and the offsets of binary
+//! arrays in their data, before validating the batches like the arrays of
+//! the entry points, see `validate`. Buffers are in the byte order of the
+//! machine, and streams written by a machine of the other order fail with
+//! `Error::UnsupportedEndianness`.
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use crate::array::ArrowArray;
+use crate::bitmap::{self, Bitmap};
+use crate::buffer::Buffer;
+use crate::endian::Endianness;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::flatbuffers::{self, Table, TableRef, Value};
+use crate::merge;
+use crate::schema::{ArrowType, IntervalUnit, Metadata, Schema, SchemaFlags, TimeUnit};
+use crate::stream::ImportedArray;
+use crate::types::with_native_type;
+use crate::validate;
+
+/// Written before the length of the metadata of every message.
+const CONTINUATION: u32 = 0xFFFF_FFFF;
+/// `MetadataVersion.V4`, the first version with the current layout.
+const V4: i16 = 3;
+/// `MetadataVersion.V5`, the version written.
+const V5: i16 = 4;
+/// Alignment of the messages and of the buffers of their bodies.
+const ALIGNMENT: usize = 8;
+/// Bytes of the `FieldNode` and `Buffer` structs of the record batches.
+const STRUCT_LEN: usize = 16;
+
+// `MessageHeader` values.
+const SCHEMA: u8 = 1;
+const DICTIONARY_BATCH: u8 = 2;
+const RECORD_BATCH: u8 = 3;
+
+// `Type` values.
+const INT: u8 = 2;
+const FLOATING_POINT: u8 = 3;
+const BINARY: u8 = 4;
+const UTF8: u8 = 5;
+const BOOL: u8 = 6;
+const DECIMAL: u8 = 7;
+const DATE: u8 = 8;
+const TIME: u8 = 9;
+const TIMESTAMP: u8 = 10;
+const INTERVAL: u8 = 11;
+const STRUCT: u8 = 13;
+const FIXED_SIZE_BINARY: u8 = 15;
+const DURATION: u8 = 18;
+const LARGE_BINARY: u8 = 19;
+const RUN_END_ENCODED: u8 = 22;
+
+/// `TimeUnit.MILLISECOND`, the default unit of dates, times and durations.
+const MILLISECOND: i16 = 1;
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidArgument(format!("invalid Arrow IPC stream: {message}"))
+}
+
+fn io_error(error: io::Error) -> Error {
+    Error::Io(error.to_string())
+}
+
+/// How the buffers of the arrays of a type are laid out.
+#[derive(Clone, Copy)]
+enum Layout {
+    /// Validity and values of the given bits.
+    Fixed(usize),
+    /// Validity, offsets of the given bytes and data.
+    Binary(usize),
+    /// Validity, the values being in the children.
+    Struct,
+    /// No buffers, the run ends and the values being in the children.
+    RunEndEncoded,
+}
+
+impl Layout {
+    fn of(schema: &Schema) -> Result<Layout> {
+        Ok(match schema.data_type {
+            ArrowType::Boolean => Layout::Fixed(1),
+            ArrowType::Binary | ArrowType::Utf8 => Layout::Binary(4),
+            ArrowType::LargeBinary => Layout::Binary(8),
+            ArrowType::FixedSizeBinary => {
+                Layout::Fixed(8 * schema.fixed_size_binary_width().unwrap_or_default())
+            }
+            ArrowType::Decimal128 | ArrowType::Interval(IntervalUnit::MonthDayNano) => {
+                Layout::Fixed(128)
+            }
+            ArrowType::Interval(IntervalUnit::DayTime) => Layout::Fixed(64),
+            ArrowType::Struct => Layout::Struct,
+            ArrowType::RunEndEncoded => Layout::RunEndEncoded,
+            data_type => with_native_type!(data_type.physical_type(), T => {
+                Layout::Fixed(8 * mem::size_of::<T>())
+            }, _ => return Err(unsupported(data_type))),
+        })
+    }
+}
+
+fn unsupported(data_type: ArrowType) -> Error {
+    Error::UnsupportedType(format!("{data_type:?} arrays in Arrow IPC streams"))
+}
+
+fn time_unit(unit: TimeUnit) -> Value {
+    Value::I16(match unit {
+        TimeUnit::Second => 0,
+        TimeUnit::Millisecond => 1,
+        TimeUnit::Microsecond => 2,
+        TimeUnit::Nanosecond => 3,
+    })
+}
+
+/// The `Type` of the field `schema`, and its table.
+fn field_type(schema: &Schema) -> Result<(u8, Table)> {
+    let table = Table::new();
+    Ok(match schema.data_type {
+        ArrowType::Boolean => (BOOL, table),
+        data_type @ (ArrowType::Int8
+        | ArrowType::Int16
+        | ArrowType::Int32
+        | ArrowType::Int64
+        | ArrowType::UInt8
+        | ArrowType::UInt16
+        | ArrowType::UInt32
+        | ArrowType::UInt64) => {
+            let bits = with_native_type!(data_type, T => 8 * mem::size_of::<T>(), _ => 0);
+            let signed = matches!(
+                data_type,
+                ArrowType::Int8 | ArrowType::Int16 | ArrowType::Int32 | ArrowType::Int64
+            );
+            let table = table
+                .with(0, Value::I32(bits as i32))
+                .with(1, Value::Bool(signed));
+            (INT, table)
+        }
+        ArrowType::Float32 => (FLOATING_POINT, table.with(0, Value::I16(1))),
+        ArrowType::Float64 => (FLOATING_POINT, table.with(0, Value::I16(2))),
+        ArrowType::Binary => (BINARY, table),
+        ArrowType::LargeBinary => (LARGE_BINARY, table),
+        ArrowType::Utf8 => (UTF8, table),
+        ArrowType::FixedSizeBinary => {
+            let width = schema.fixed_size_binary_width().unwrap_or_default();
+            (FIXED_SIZE_BINARY, table.with(0, Value::I32(width as i32)))
+        }
+        ArrowType::Date32 => (DATE, table.with(0, Value::I16(0))),
+        ArrowType::Date64 => (DATE, table.with(0, Value::I16(MILLISECOND))),
+        ArrowType::Timestamp(unit) => {
+            let mut table = table.with(0, time_unit(unit));
+            if let Some(timezone) = schema.timezone() {
+                table = table.with(1, Value::Str(timezone.to_string()));
+            }
+            (TIMESTAMP, table)
+        }
+        ArrowType::Time(unit) => {
+            let bits = match unit {
+                TimeUnit::Second | TimeUnit::Millisecond => 32,
+                TimeUnit::Microsecond | TimeUnit::Nanosecond => 64,
+            };
+            let table = table.with(0, time_unit(unit)).with(1, Value::I32(bits));
+            (TIME, table)
+        }
+        ArrowType::Duration(unit) => (DURATION, table.with(0, time_unit(unit))),
+        ArrowType::Interval(unit) => {
+            let unit = match unit {
+                IntervalUnit::YearMonth => 0,
+                IntervalUnit::DayTime => 1,
+                IntervalUnit::MonthDayNano => 2,
+            };
+            (INTERVAL, table.with(0, Value::I16(unit)))
+        }
+        ArrowType::Decimal128 => {
+            let (precision, scale) = schema.decimal().unwrap_or((38, 0));
+            let table = table
+                .with(0, Value::I32(precision as i32))
+                .with(1, Value::I32(scale as i32))
+                .with(2, Value::I32(128));
+            (DECIMAL, table)
+        }
+        ArrowType::Struct => (STRUCT, table),
+        ArrowType::RunEndEncoded => (RUN_END_ENCODED, table),
+        data_type @ (ArrowType::Map | ArrowType::Union(_)) => return Err(unsupported(data_type)),
+    })
+}
+
+fn key_values(metadata: &Metadata) -> Value {
+    let pairs = metadata.0.iter().map(|(key, value)| {
+        Table::new()
+            .with(0, Value::Str(key.clone()))
+            .with(1, Value::Str(value.clone()))
+    });
+    Value::Tables(pairs.collect())
+}
+
+/// The `Field` table of `schema` and its children.
+fn field(schema: &Schema) -> Result<Table> {
+    let (type_id, type_table) = field_type(schema)?;
+    let children = schema.children.iter().map(field).collect::<Result<_>>()?;
+    let mut table = Table::new()
+        .with(0, Value::Str(schema.name.clone()))
+        .with(1, Value::Bool(schema.flags.nullable))
+        .with(2, Value::U8(type_id))
+        .with(3, Value::Table(type_table))
+        .with(5, Value::Tables(children));
+    if !schema.metadata.is_empty() {
+        table = table.with(6, key_values(&schema.metadata));
+    }
+    Ok(table)
+}
+
+/// Write a message with the `header` of `header_type` and the buffers of
+/// `body`.
+fn write_message(
+    out: &mut impl Write,
+    header_type: u8,
+    header: Table,
+    body: &[Cow<[u8]>],
+) -> Result<()> {
+    let body_len: usize = body
+        .iter()
+        .map(|buffer| buffer.len().next_multiple_of(ALIGNMENT))
+        .sum();
+    let metadata = flatbuffers::finish(
+        &Table::new()
+            .with(0, Value::I16(V5))
+            .with(1, Value::U8(header_type))
+            .with(2, Value::Table(header))
+            .with(3, Value::I64(body_len as i64)),
+    );
+    // The prefix and the metadata keep the body aligned.
+    let padded = (8 + metadata.len()).next_multiple_of(ALIGNMENT) - 8;
+    let padding = [0; ALIGNMENT];
+    out.write_all(&CONTINUATION.to_le_bytes())
+        .and_then(|_| out.write_all(&(padded as i32).to_le_bytes()))
+        .and_then(|_| out.write_all(&metadata))
+        .and_then(|_| out.write_all(&padding[..padded - metadata.len()]))
+        .map_err(io_error)?;
+    for buffer in body {
+        let padding = &padding[..buffer.len().next_multiple_of(ALIGNMENT) - buffer.len()];
+        out.write_all(buffer)
+            .and_then(|_| out.write_all(padding))
+            .map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// The field nodes and the buffers of a record batch being written.
+#[derive(Default)]
+struct Body<'a> {
+    nodes: Vec<u8>,
+    buffers: Vec<Cow<'a, [u8]>>,
+}
+
+impl<'a> Body<'a> {
+    fn node(&mut self, len: usize, null_count: usize) {
+        self.nodes.extend_from_slice(&(len as i64).to_le_bytes());
+        self.nodes
+            .extend_from_slice(&(null_count as i64).to_le_bytes());
+    }
+
+    /// The bits of `bitmap`, starting at the first byte.
+    fn bits(&mut self, bitmap: Bitmap) {
+        let buffer = bitmap::from_chunks(bitmap.len(), bitmap.chunks());
+        self.buffers.push(Cow::Owned(buffer.as_slice().to_vec()));
+    }
+
+    /// Write the `len` elements of `array` from `start`, relative to its
+    /// offset.
+    fn write_array(&mut self, array: &ArrowArray<'a>, start: usize, len: usize) -> Result<()> {
+        let schema = array.schema();
+        if start + len > array.len() {
+            return Err(Error::InvalidArgument(format!(
+                "field {:?} of {} rows in a struct of {}",
+                schema.name,
+                array.len(),
+                start + len
+            )));
+        }
+        let layout = Layout::of(schema)?;
+        let validity = array.validity().map(|validity| validity.slice(start, len));
+        let valid = validity.map_or(len, |validity| validity.count_set());
+        self.node(len, len - valid);
+        match validity {
+            _ if matches!(layout, Layout::RunEndEncoded) => {}
+            Some(validity) if valid < len => self.bits(validity),
+            _ => self.buffers.push(Cow::Borrowed(&[])),
+        }
+        match layout {
+            Layout::Fixed(1) => self.bits(array.boolean_values().slice(start, len)),
+            Layout::Fixed(bits) => {
+                let width = bits / 8;
+                let values = match len * width {
+                    0 => &[][..],
+                    bytes => unsafe {
+                        let values = array.ffi().buffer(1).add((array.offset() + start) * width);
+                        std::slice::from_raw_parts(values, bytes)
+                    },
+                };
+                self.buffers.push(Cow::Borrowed(values));
+            }
+            Layout::Binary(width) => {
+                let offsets: Vec<i64> = match width {
+                    4 => array.binary_offsets()[start..=start + len]
+                        .iter()
+                        .map(|offset| *offset as i64)
+                        .collect(),
+                    _ => array.large_binary_offsets()[start..=start + len].to_vec(),
+                };
+                let (first, last) = (offsets[0], offsets[len]);
+                let mut rebased = Vec::with_capacity(offsets.len() * width);
+                for offset in offsets {
+                    match width {
+                        4 => rebased.extend_from_slice(&((offset - first) as i32).to_ne_bytes()),
+                        _ => rebased.extend_from_slice(&(offset - first).to_ne_bytes()),
+                    }
+                }
+                self.buffers.push(Cow::Owned(rebased));
+                let data = &array.binary_data()[first as usize..last as usize];
+                self.buffers.push(Cow::Borrowed(data));
+            }
+            Layout::Struct => {
+                for i in 0..schema.children.len() {
+                    self.write_array(&array.child(i), array.offset() + start, len)?;
+                }
+            }
+            Layout::RunEndEncoded => self.write_runs(array, start, len)?,
+        }
+        Ok(())
+    }
+
+    /// Write the run ends and the values of the runs of the `len` elements
+    /// of the run-end encoded `array` from `start`.
+    fn write_runs(&mut self, array: &ArrowArray<'a>, start: usize, len: usize) -> Result<()> {
+        let run_ends = array.child(0);
+        let ends: Vec<i64> = match run_ends.data_type() {
+            ArrowType::Int16 => run_ends.values::<i16>().iter().map(|e| *e as i64).collect(),
+            ArrowType::Int32 => run_ends.values::<i32>().iter().map(|e| *e as i64).collect(),
+            ArrowType::Int64 => run_ends.values::<i64>().to_vec(),
+            data_type => return Err(unsupported(data_type)),
+        };
+        let (from, to) = (
+            (array.offset() + start) as i64,
+            (array.offset() + start + len) as i64,
+        );
+        let first = ends.partition_point(|end| *end <= from);
+        let last = match len {
+            0 => first,
+            _ => (ends.partition_point(|end| *end < to) + 1).min(ends.len()),
+        };
+        let mut bytes = Vec::new();
+        for end in &ends[first..last] {
+            let end = end.min(&to) - from;
+            match run_ends.data_type() {
+                ArrowType::Int16 => bytes.extend_from_slice(&(end as i16).to_ne_bytes()),
+                ArrowType::Int32 => bytes.extend_from_slice(&(end as i32).to_ne_bytes()),
+                _ => bytes.extend_from_slice(&end.to_ne_bytes()),
+            }
+        }
+        self.node(last - first, 0);
+        self.buffers.push(Cow::Borrowed(&[]));
+        self.buffers.push(Cow::Owned(bytes));
+        self.write_array(&array.child(1), first, last - first)
+    }
+}
+
+/// Writer of an Arrow IPC stream of record batches.
+pub(crate) struct StreamWriter<W: Write> {
+    out: W,
+    schema: Schema,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Write the schema of a stream of the struct arrays of `schema`, which
+    /// has the fields of the stream as its children, and the metadata of the
+    /// stream as its metadata.
+    pub(crate) fn new(mut out: W, schema: &Schema) -> Result<StreamWriter<W>> {
+        if schema.data_type != ArrowType::Struct {
+            return Err(Error::InvalidArgument(format!(
+                "Arrow IPC streams of {:?} arrays instead of record batches",
+                schema.data_type
+            )));
+        }
+        let endianness = match Endianness::NATIVE {
+            Endianness::Little => 0,
+            Endianness::Big => 1,
+        };
+        let fields = schema.children.iter().map(field).collect::<Result<_>>()?;
+        let mut header = Table::new()
+            .with(0, Value::I16(endianness))
+            .with(1, Value::Tables(fields));
+        if !schema.metadata.is_empty() {
+            header = header.with(2, key_values(&schema.metadata));
+        }
+        write_message(&mut out, SCHEMA, header, &[])?;
+        Ok(StreamWriter {
+            out,
+            schema: schema.clone(),
+        })
+    }
+
+    /// Write the struct array `batch` as a record batch.
+    pub(crate) fn write(&mut self, batch: &ArrowArray) -> Result<()> {
+        if !merge::same_layout(batch.schema(), &self.schema) {
+            return Err(Error::InvalidArgument(
+                "record batch with different fields than its stream".to_string(),
+            ));
+        }
+        if batch.null_count() > 0 {
+            return Err(Error::InvalidArgument(
+                "record batches of Arrow IPC streams can't have null rows".to_string(),
+            ));
+        }
+        let columns: Vec<ArrowArray> = (0..self.schema.children.len())
+            .map(|i| batch.child(i))
+            .collect();
+        self.write_batch(&columns, batch.offset(), batch.len())
+    }
+
+    /// Write the record batch with the arrays of the fields of the stream
+    /// in `columns`, like a struct array without nulls of those fields.
+    #[cfg(feature = "trace")]
+    pub(crate) fn write_columns(&mut self, columns: &[ArrowArray], len: usize) -> Result<()> {
+        let same_fields = columns.len() == self.schema.children.len()
+            && columns
+                .iter()
+                .zip(&self.schema.children)
+                .all(|(column, field)| merge::same_layout(column.schema(), field));
+        if !same_fields {
+            return Err(Error::InvalidArgument(
+                "record batch with different fields than its stream".to_string(),
+            ));
+        }
+        self.write_batch(columns, 0, len)
+    }
+
+    /// Write the `len` rows from `offset` of `columns` as a record batch.
+    fn write_batch(&mut self, columns: &[ArrowArray], offset: usize, len: usize) -> Result<()> {
+        let mut body = Body::default();
+        for column in columns {
+            body.write_array(column, offset, len)?;
+        }
+        let mut buffers = Vec::with_capacity(body.buffers.len() * STRUCT_LEN);
+        let mut offset = 0;
+        for buffer in &body.buffers {
+            buffers.extend_from_slice(&(offset as i64).to_le_bytes());
+            buffers.extend_from_slice(&(buffer.len() as i64).to_le_bytes());
+            offset += buffer.len().next_multiple_of(ALIGNMENT);
+        }
+        let header = Table::new()
+            .with(0, Value::I64(len as i64))
+            .with(1, Value::Structs(body.nodes, STRUCT_LEN))
+            .with(2, Value::Structs(buffers, STRUCT_LEN));
+        write_message(&mut self.out, RECORD_BATCH, header, &body.buffers)
+    }
+
+    /// End the stream, and flush it.
+    pub(crate) fn finish(mut self) -> Result<W> {
+        self.out
+            .write_all(&CONTINUATION.to_le_bytes())
+            .and_then(|_| self.out.write_all(&0_u32.to_le_bytes()))
+            .and_then(|_| self.out.flush())
+            .map_err(io_error)?;
+        Ok(self.out)
+    }
+}
+
+/// Fill `bytes` from `input`, returning false if it's at its end.
+fn read_or_end(input: &mut impl Read, bytes: &mut [u8]) -> Result<bool> {
+    let mut read = 0;
+    while read < bytes.len() {
+        match input.read(&mut bytes[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("truncated message")),
+            Ok(n) => read += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(io_error(error)),
+        }
+    }
+    Ok(true)
+}
+
+/// The next `len` bytes of `input`.
+fn read_bytes(input: &mut impl Read, len: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .map_err(io_error)?;
+    match bytes.len() == len {
+        true => Ok(bytes),
+        false => Err(invalid("truncated message")),
+    }
+}
+
+/// A message read from a stream, with its metadata and its body.
+struct Message {
+    metadata: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl Message {
+    /// The next message of `input`, or `None` at the end of the stream.
+    fn read(input: &mut impl Read) -> Result<Option<Message>> {
+        let mut prefix = [0; 4];
+        if !read_or_end(input, &mut prefix)? {
+            return Ok(None);
+        }
+        let mut len = u32::from_le_bytes(prefix);
+        // Streams written before the continuation marker start with the
+        // length.
+        if len == CONTINUATION {
+            if !read_or_end(input, &mut prefix)? {
+                return Err(invalid("truncated message"));
+            }
+            len = u32::from_le_bytes(prefix);
+        }
+        if len == 0 {
+            return Ok(None);
+        }
+        let metadata = read_bytes(input, len as usize)?;
+        let body_len = flatbuffers::root(&metadata)?.i64(3)?;
+        let body_len = usize::try_from(body_len).map_err(|_| invalid("negative body length"))?;
+        let body = read_bytes(input, body_len)?;
+        Ok(Some(Message { metadata, body }))
+    }
+
+    /// The type of the header of the message, and the header.
+    fn header(&self) -> Result<(u8, TableRef<'_>)> {
+        let message = flatbuffers::root(&self.metadata)?;
+        let version = message.i16(0, 0)?;
+        if version < V4 {
+            return Err(invalid(&format!("unsupported metadata version {version}")));
+        }
+        let header = message
+            .table(2)?
+            .ok_or_else(|| invalid("message without header"))?;
+        Ok((message.u8(1)?, header))
+    }
+}
+
+fn read_key_values(pairs: Vec<TableRef>) -> Result<Metadata> {
+    let pairs = pairs.into_iter().map(|pair| {
+        let key = pair.str(0)?.unwrap_or_default();
+        let value = pair.str(1)?.unwrap_or_default();
+        Ok((key.to_string(), value.to_string()))
+    });
+    Ok(Metadata(pairs.collect::<Result<_>>()?))
+}
+
+/// The C Data Interface format of a field of the `Type` `type_id`, whose
+/// table is `table`.
+fn format(type_id: u8, table: TableRef) -> Result<String> {
+    let unit = |default| -> Result<&str> {
+        match table.i16(0, default)? {
+            0 => Ok("s"),
+            1 => Ok("m"),
+            2 => Ok("u"),
+            3 => Ok("n"),
+            unit => Err(invalid(&format!("time unit {unit}"))),
+        }
+    };
+    let format = match type_id {
+        BOOL => "b",
+        INT => match (table.i32(0, 0)?, table.bool(1)?) {
+            (8, true) => "c",
+            (16, true) => "s",
+            (32, true) => "i",
+            (64, true) => "l",
+            (8, false) => "C",
+            (16, false) => "S",
+            (32, false) => "I",
+            (64, false) => "L",
+            (bits, _) => return Err(invalid(&format!("integers of {bits} bits"))),
+        },
+        FLOATING_POINT => match table.i16(0, 0)? {
+            1 => "f",
+            2 => "g",
+            precision => {
+                return Err(Error::UnsupportedType(format!(
+                    "floats of precision {precision} in Arrow IPC streams"
+                )))
+            }
+        },
+        BINARY => "z",
+        LARGE_BINARY => "Z",
+        UTF8 => "u",
+        FIXED_SIZE_BINARY => return Ok(format!("w:{}", table.i32(0, 0)?)),
+        DATE => match table.i16(0, MILLISECOND)? {
+            0 => "tdD",
+            1 => "tdm",
+            unit => return Err(invalid(&format!("date unit {unit}"))),
+        },
+        TIMESTAMP => {
+            let timezone = table.str(1)?.unwrap_or_default();
+            return Ok(format!("ts{}:{timezone}", unit(0)?));
+        }
+        TIME => {
+            let unit = unit(MILLISECOND)?;
+            let bits = match unit {
+                "s" | "m" => 32,
+                _ => 64,
+            };
+            if table.i32(1, 32)? != bits {
+                return Err(invalid(&format!("times in {unit} without {bits} bits")));
+            }
+            return Ok(format!("tt{unit}"));
+        }
+        DURATION => return Ok(format!("tD{}", unit(MILLISECOND)?)),
+        INTERVAL => match table.i16(0, 0)? {
+            0 => "tiM",
+            1 => "tiD",
+            2 => "tin",
+            unit => return Err(invalid(&format!("interval unit {unit}"))),
+        },
+        DECIMAL => match table.i32(2, 128)? {
+            128 => {
+                let (precision, scale) = (table.i32(0, 0)?, table.i32(1, 0)?);
+                return Ok(format!("d:{precision},{scale}"));
+            }
+            bits => {
+                return Err(Error::UnsupportedType(format!(
+                    "decimals of {bits} bits in Arrow IPC streams"
+                )))
+            }
+        },
+        STRUCT => "+s",
+        RUN_END_ENCODED => "+r",
+        type_id => {
+            return Err(Error::UnsupportedType(format!(
+                "fields of type {type_id} in Arrow IPC streams"
+            )))
+        }
+    };
+    Ok(format.to_string())
+}
+
+/// The schema of the `Field` table `field`.
+fn read_field(field: TableRef, depth: usize) -> Result<Schema> {
+    if depth > 64 {
+        return Err(invalid("fields nested too deeply"));
+    }
+    if field.table(4)?.is_some() {
+        return Err(Error::UnsupportedType(
+            "dictionary encoded fields in Arrow IPC streams".to_string(),
+        ));
+    }
+    let name = field.str(0)?.unwrap_or_default();
+    let table = field
+        .table(3)?
+        .ok_or_else(|| invalid(&format!("field {name:?} without type")))?;
+    let format = format(field.u8(2)?, table)?;
+    if format.contains('\0') || name.contains('\0') {
+        return Err(invalid("format or name with a nul character"));
+    }
+    let data_type = ArrowType::from_format(&format)
+        .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+    let children = field
+        .tables(5)?
+        .into_iter()
+        .map(|child| read_field(child, depth + 1))
+        .collect::<Result<Vec<_>>>()?;
+    let valid = match data_type {
+        ArrowType::Struct => true,
+        ArrowType::RunEndEncoded => {
+            children.len() == 2
+                && matches!(
+                    children[0].data_type,
+                    ArrowType::Int16 | ArrowType::Int32 | ArrowType::Int64
+                )
+        }
+        _ => children.is_empty(),
+    };
+    if !valid {
+        return Err(invalid(&format!(
+            "{data_type:?} field {name:?} with {} children",
+            children.len()
+        )));
+    }
+    Ok(Schema {
+        format,
+        data_type,
+        name: name.to_string(),
+        metadata: read_key_values(field.tables(6)?)?,
+        flags: SchemaFlags {
+            nullable: field.bool(1)?,
+            ..SchemaFlags::default()
+        },
+        children,
+    })
+}
+
+/// The field nodes and the buffers of a record batch being read.
+struct Batch<'a> {
+    nodes: &'a [u8],
+    buffers: &'a [u8],
+    body: &'a [u8],
+}
+
+/// The two i64 of the next struct of `structs`.
+fn next_struct(structs: &mut &[u8], what: &str) -> Result<(i64, i64)> {
+    let Some((next, rest)) = structs.split_first_chunk::<STRUCT_LEN>() else {
+        return Err(invalid(&format!("missing {what}")));
+    };
+    *structs = rest;
+    let first = i64::from_le_bytes(next[..8].try_into().unwrap());
+    let second = i64::from_le_bytes(next[8..].try_into().unwrap());
+    Ok((first, second))
+}
+
+/// The first `len` bytes of `buffer`, which must have them.
+fn prefix<'a>(buffer: &'a [u8], len: Option<usize>, what: &str) -> Result<&'a [u8]> {
+    len.and_then(|len| buffer.get(..len)).ok_or_else(|| {
+        invalid(&format!(
+            "{what} buffer of {} bytes, too short for its array",
+            buffer.len()
+        ))
+    })
+}
+
+impl<'a> Batch<'a> {
+    /// The length and the null count of the next array.
+    fn node(&mut self) -> Result<(usize, usize)> {
+        let (length, null_count) = next_struct(&mut self.nodes, "field node")?;
+        match (usize::try_from(length), usize::try_from(null_count)) {
+            (Ok(length), Ok(null_count)) if null_count <= length => Ok((length, null_count)),
+            _ => Err(invalid(&format!(
+                "null count {null_count} of an array of {length}"
+            ))),
+        }
+    }
+
+    fn buffer(&mut self) -> Result<&'a [u8]> {
+        let (offset, len) = next_struct(&mut self.buffers, "buffer")?;
+        let range = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(offset, len)| Some(offset..offset.checked_add(len)?));
+        range
+            .and_then(|range| self.body.get(range))
+            .ok_or_else(|| invalid("buffer out of the body of its message"))
+    }
+
+    /// The offsets of the `length` elements of a binary array with offsets
+    /// of `width` bytes, checking that they are in its `data`.
+    fn offsets(&mut self, length: usize, width: usize) -> Result<(Vec<i64>, &'a [u8])> {
+        let buffer = self.buffer()?;
+        let data = self.buffer()?;
+        // Writers can leave out the offsets of empty arrays.
+        if length == 0 && buffer.is_empty() {
+            return Ok((vec![0], data));
+        }
+        let bytes = length.checked_add(1).and_then(|n| n.checked_mul(width));
+        let offsets: Vec<i64> = prefix(buffer, bytes, "offsets")?
+            .chunks_exact(width)
+            .map(|offset| match width {
+                4 => i32::from_ne_bytes(offset.try_into().unwrap()) as i64,
+                _ => i64::from_ne_bytes(offset.try_into().unwrap()),
+            })
+            .collect();
+        let in_bounds = offsets[0] >= 0
+            && offsets.windows(2).all(|pair| pair[0] <= pair[1])
+            && offsets[length] as u64 <= data.len() as u64;
+        match in_bounds {
+            true => Ok((offsets, data)),
+            false => Err(invalid("binary offsets out of their data")),
+        }
+    }
+
+    /// The next array, of the field `schema`.
+    fn read_array(&mut self, schema: &Schema) -> Result<ArrayData> {
+        let (length, null_count) = self.node()?;
+        let layout = Layout::of(schema)?;
+        let validity = match layout {
+            Layout::RunEndEncoded => None,
+            _ => Some(self.buffer()?),
+        };
+        let validity = match validity {
+            Some(bits) if null_count > 0 => {
+                let bits = prefix(bits, Some(length.div_ceil(8)), "validity")?;
+                Some(Buffer::from_slice(bits))
+            }
+            _ => None,
+        };
+        let null_count = validity.as_ref().map_or(0, |_| null_count);
+        let buffers = match layout {
+            Layout::Fixed(bits) => {
+                let bytes = length.checked_mul(bits).map(|bits| bits.div_ceil(8));
+                let values = prefix(self.buffer()?, bytes, "values")?;
+                vec![validity, Some(Buffer::from_slice(values))]
+            }
+            Layout::Binary(width) => {
+                let (offsets, data) = self.offsets(length, width)?;
+                let data = Buffer::from_slice(&data[..offsets[length] as usize]);
+                let offsets = match width {
+                    4 => offsets.iter().map(|offset| *offset as i32).collect(),
+                    _ => Buffer::from_slice(&offsets),
+                };
+                vec![validity, Some(offsets), Some(data)]
+            }
+            Layout::Struct => vec![validity],
+            Layout::RunEndEncoded => Vec::new(),
+        };
+        let children = schema
+            .children
+            .iter()
+            .map(|child| self.read_array(child))
+            .collect::<Result<Vec<_>>>()?;
+        if matches!(layout, Layout::Struct) && children.iter().any(|c| c.length < length) {
+            return Err(invalid(&format!(
+                "field of struct {:?} shorter than the struct",
+                schema.name
+            )));
+        }
+        Ok(ArrayData {
+            length,
+            null_count,
+            buffers,
+            children,
+        })
+    }
+}
+
+/// Reader of an Arrow IPC stream of record batches.
+pub(crate) struct StreamReader<R: Read> {
+    input: R,
+    schema: Schema,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Read the schema of the stream of `input`.
+    pub(crate) fn new(mut input: R) -> Result<StreamReader<R>> {
+        let message = Message::read(&mut input)?.ok_or_else(|| invalid("empty stream"))?;
+        let (header_type, header) = message.header()?;
+        if header_type != SCHEMA {
+            return Err(invalid("stream without a schema"));
+        }
+        let endianness = match header.i16(0, 0)? {
+            0 => Endianness::Little,
+            1 => Endianness::Big,
+            other => return Err(invalid(&format!("endianness {other}"))),
+        };
+        if endianness != Endianness::NATIVE {
+            return Err(Error::UnsupportedEndianness(format!(
+                "the stream is {}",
+                endianness.name()
+            )));
+        }
+        let fields = header
+            .tables(1)?
+            .into_iter()
+            .map(|field| read_field(field, 0))
+            .collect::<Result<_>>()?;
+        let mut schema = Schema::new(ArrowType::Struct, "").with_children(fields);
+        schema.metadata = read_key_values(header.tables(2)?)?;
+        Ok(StreamReader { input, schema })
+    }
+
+    /// The schema of the batches: a struct with the fields of the stream,
+    /// and its metadata.
+    pub(crate) fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// The next record batch, or `None` at the end of the stream.
+    pub(crate) fn next(&mut self) -> Result<Option<ImportedArray>> {
+        let Some(message) = Message::read(&mut self.input)? else {
+            return Ok(None);
+        };
+        let (header_type, header) = message.header()?;
+        match header_type {
+            RECORD_BATCH => {}
+            DICTIONARY_BATCH => {
+                return Err(Error::UnsupportedType(
+                    "dictionary batches in Arrow IPC streams".to_string(),
+                ))
+            }
+            other => {
+                return Err(invalid(&format!(
+                    "message of type {other} after the schema"
+                )))
+            }
+        }
+        if header.table(3)?.is_some() {
+            return Err(Error::UnsupportedType(
+                "compressed Arrow IPC streams".to_string(),
+            ));
+        }
+        let length =
+            usize::try_from(header.i64(0)?).map_err(|_| invalid("negative batch length"))?;
+        let mut batch = Batch {
+            nodes: header.structs(1, STRUCT_LEN)?,
+            buffers: header.structs(2, STRUCT_LEN)?,
+            body: &message.body,
+        };
+        let children = self
+            .schema
+            .children
+            .iter()
+            .map(|field| batch.read_array(field))
+            .collect::<Result<Vec<_>>>()?;
+        if children.iter().any(|child| child.length < length) {
+            return Err(invalid("field shorter than its record batch"));
+        }
+        let batch = ImportedArray::from(ArrayData::struct_array(children, length));
+        validate::validate(&batch.view(&self.schema))?;
+        Ok(Some(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::Utf8Builder;
+    use crate::bitmap::BitmapBuilder;
+    use crate::export::constant_schema;
+    use crate::format::FormatBuilder;
+    use crate::testing::{nullable_data, Exported};
+
+    /// The stream of `batches` of `schema`.
+    fn write(schema: &Schema, batches: &[Exported]) -> Vec<u8> {
+        let mut writer = StreamWriter::new(Vec::new(), schema).unwrap();
+        for batch in batches {
+            batch.with_array(|array| writer.write(array)).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn read(bytes: &[u8]) -> Result<(Schema, Vec<ImportedArray>)> {
+        let mut reader = StreamReader::new(bytes)?;
+        let mut batches = Vec::new();
+        while let Some(batch) = reader.next()? {
+            batches.push(batch);
+        }
+        Ok((reader.schema, batches))
+    }
+
+    fn schema() -> Schema {
+        Schema::new(ArrowType::Struct, "")
+            .with_children(vec![
+                Schema::new(ArrowType::Int32, "int"),
+                Schema::new(ArrowType::Utf8, "text").with_metadata("key", "value"),
+                Schema::new(ArrowType::Boolean, "flag"),
+                constant_schema(ArrowType::Float64, "constant"),
+            ])
+            .with_metadata("stream", "metadata")
+    }
+
+    /// A batch of 5 rows of `schema`, sliced to the `len` rows from
+    /// `offset`.
+    fn batch(offset: usize, len: usize) -> Exported {
+        let mut text = Utf8Builder::with_capacity(5);
+        for value in [Some("a"), None, Some("bc"), Some(""), Some("def")] {
+            text.push(value);
+        }
+        let mut flags = BitmapBuilder::with_capacity(5);
+        [true, false, true, true, false]
+            .iter()
+            .for_each(|flag| flags.push(*flag));
+        let children = vec![
+            nullable_data(&[Some(1_i32), Some(2), None, Some(4), Some(5)]),
+            text.finish(),
+            ArrayData::primitive(flags.finish(), 5),
+            ArrayData {
+                length: 5,
+                null_count: 0,
+                buffers: Vec::new(),
+                children: vec![
+                    ArrayData::primitive(Buffer::from_slice(&[2_i64, 3, 5]), 3),
+                    ArrayData::primitive(Buffer::from_slice(&[0.5_f64, 1.5, 2.5]), 3),
+                ],
+            },
+        ];
+        let mut batch = Exported::new(&schema(), ArrayData::struct_array(children, 5));
+        (batch.array.offset, batch.array.length) = (offset as i64, len as i64);
+        batch
+    }
+
+    #[test]
+    fn batches_are_read_back_with_their_schema() {
+        let bytes = write(&schema(), &[batch(0, 5), batch(1, 3), batch(5, 0)]);
+        assert_eq!(bytes[..4], CONTINUATION.to_le_bytes());
+        assert_eq!(bytes[bytes.len() - 8..], [255, 255, 255, 255, 0, 0, 0, 0]);
+        let (read_schema, batches) = read(&bytes).unwrap();
+        assert_eq!(read_schema, schema());
+        let lens: Vec<usize> = batches.iter().map(|b| b.view(&read_schema).len()).collect();
+        assert_eq!(lens, [5, 3, 0]);
+        // The slice starts in a run, with a null, and in the strings.
+        let sliced = batches[1].view(&read_schema);
+        let int = sliced.child(0);
+        assert_eq!(int.values::<i32>()[..1], [2]);
+        assert!(!int.is_valid(1));
+        let text = sliced.child(1);
+        assert!(!text.is_valid(0));
+        assert_eq!(text.utf8_value(1), Ok("bc"));
+        assert_eq!(text.binary_offsets(), [0, 0, 2, 2]);
+        let flags: Vec<bool> = sliced.child(2).boolean_values().iter().collect();
+        assert_eq!(flags, [false, true, true]);
+        let constant = sliced.child(3);
+        assert_eq!(constant.child(0).values::<i64>(), [1, 2, 3]);
+        assert_eq!(constant.child(1).values::<f64>(), [0.5, 1.5, 2.5]);
+    }
+
+    #[test]
+    fn parameterized_types_keep_their_parameters() {
+        let mut fields = vec![
+            Schema::new(ArrowType::Timestamp(TimeUnit::Microsecond), "ts")
+                .with_format(
+                    &FormatBuilder::new(ArrowType::Timestamp(TimeUnit::Microsecond))
+                        .timezone("Europe/Paris"),
+                )
+                .unwrap(),
+            Schema::fixed_size_binary("fixed", 3),
+            Schema::new(ArrowType::Date32, "date"),
+            Schema::new(ArrowType::Duration(TimeUnit::Nanosecond), "duration"),
+            Schema::new(ArrowType::UInt16, "uint"),
+            Schema::new(ArrowType::LargeBinary, "large"),
+        ];
+        if cfg!(feature = "decimal") {
+            let decimal = Schema::new(ArrowType::Decimal128, "decimal");
+            fields.push(
+                decimal
+                    .with_format(&FormatBuilder::decimal(10, -2))
+                    .unwrap(),
+            );
+        }
+        fields[4].flags.nullable = false;
+        let schema = Schema::new(ArrowType::Struct, "").with_children(fields);
+        let bytes = StreamWriter::new(Vec::new(), &schema)
+            .unwrap()
+            .finish()
+            .unwrap();
+        let reader = StreamReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.schema(), &schema);
+    }
+
+    #[test]
+    fn unsupported_arrays_fail() {
+        let union = Schema::union("union", crate::schema::UnionMode::Sparse, Vec::new());
+        let schema = Schema::new(ArrowType::Struct, "").with_children(vec![union]);
+        assert!(matches!(
+            StreamWriter::new(Vec::new(), &schema),
+            Err(Error::UnsupportedType(_))
+        ));
+        let plain = Schema::new(ArrowType::Int64, "");
+        assert!(matches!(
+            StreamWriter::new(Vec::new(), &plain),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_streams_fail() {
+        let bytes = write(&schema(), &[batch(0, 5)]);
+        assert!(matches!(read(&[]), Err(Error::InvalidArgument(_))));
+        // Every truncation in a message fails, the end of the stream being
+        // optional.
+        let schema_end = write(&schema(), &[]).len() - 8;
+        let end = bytes.len() - 8;
+        for len in (1..end).filter(|len| *len != schema_end) {
+            assert!(read(&bytes[..len]).is_err(), "{len}");
+        }
+        assert_eq!(read(&bytes[..schema_end]).unwrap().1.len(), 0);
+        assert_eq!(read(&bytes[..end]).unwrap().1.len(), 1);
+
+        // The last offset of the strings, past the end of their data.
+        let offsets: Vec<u8> = [0_i32, 1, 1, 3, 3, 6]
+            .iter()
+            .flat_map(|offset| offset.to_ne_bytes())
+            .collect();
+        let at = (0..bytes.len() - offsets.len())
+            .find(|i| bytes[*i..*i + offsets.len()] == offsets)
+            .unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[at + 20..at + 24].copy_from_slice(&60_i32.to_ne_bytes());
+        match read(&corrupted) {
+            Err(Error::InvalidArgument(message)) => assert!(message.contains("offsets")),
+            _ => panic!("expected offsets out of their data"),
+        }
+
+        let other = match Endianness::NATIVE {
+            Endianness::Little => 1,
+            Endianness::Big => 0,
+        };
+        let mut swapped = Vec::new();
+        let header = Table::new().with(0, Value::I16(other));
+        write_message(&mut swapped, SCHEMA, header, &[]).unwrap();
+        assert!(matches!(
+            read(&swapped),
+            Err(Error::UnsupportedEndianness(_))
+        ));
+    }
+}
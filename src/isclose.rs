@@ -0,0 +1,222 @@
+//! Approximate comparison of two numeric arrays, for validation pipelines
+//! checking recomputed columns against stored ones.
+//!
+//! Like `numpy.isclose`, `a` and `b` are close when
+//! `|a - b| <= atol + rtol * |b|`, so the comparison isn't symmetric when
+//! `rtol` is not 0. Infinities are only close to the infinity of the same
+//! sign, and NaN isn't close to anything, unless the host sets the
+//! `ARROW_UDF_FLAG_EQUAL_NAN` flag, which makes NaN close to NaN.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, Numeric};
+use crate::udf;
+
+/// Host flag to consider NaN close to NaN.
+pub const ARROW_UDF_FLAG_EQUAL_NAN: u32 = 16;
+
+/// Whether `a` is close to `b`.
+pub fn is_close_value(a: f64, b: f64, rtol: f64, atol: f64, equal_nan: bool) -> bool {
+    if a.is_nan() || b.is_nan() {
+        return equal_nan && a.is_nan() && b.is_nan();
+    }
+    if a.is_infinite() || b.is_infinite() {
+        return a == b;
+    }
+    (a - b).abs() <= atol + rtol * b.abs()
+}
+
+fn compare<A: Numeric, B: Numeric>(
+    a: &ArrowArray,
+    b: &ArrowArray,
+    rtol: f64,
+    atol: f64,
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<bool>> {
+    udf::check_input::<A>(a, options)?;
+    udf::check_input::<B>(b, options)?;
+    let (a, b) = (a.values::<A>(), b.values::<B>());
+    let equal_nan = options.flags & ARROW_UDF_FLAG_EQUAL_NAN != 0;
+    let mut close = vec![false; a.len()];
+    exec::map(&mut close, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            *out = is_close_value(a[i].to_f64(), b[i].to_f64(), rtol, atol, equal_nan);
+        }
+        Ok(())
+    })?;
+    Ok(close)
+}
+
+/// Whether every element of `a` is close to the element of `b` at the same
+/// index, as a Boolean array. Positions where any of the arrays is null are
+/// null in the result.
+pub fn is_close(
+    a: &ArrowArray,
+    b: &ArrowArray,
+    rtol: f64,
+    atol: f64,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if a.len() != b.len() {
+        return Err(Error::InvalidArgument(format!(
+            "arrays of different lengths: {} and {}",
+            a.len(),
+            b.len()
+        )));
+    }
+    if !(rtol >= 0.0 && atol >= 0.0) {
+        return Err(Error::InvalidArgument(format!(
+            "tolerances must be positive, got rtol={rtol} and atol={atol}"
+        )));
+    }
+    let unsupported =
+        |data_type| Error::UnsupportedType(format!("is_close of {data_type:?} arrays"));
+    let close = with_native_type!(a.data_type(), A => {
+        with_native_type!(b.data_type(), B => {
+            compare::<A, B>(a, b, rtol, atol, options)?
+        }, _ => return Err(unsupported(b.data_type())))
+    }, _ => return Err(unsupported(a.data_type())));
+    let mut values = BitmapBuilder::with_capacity(close.len());
+    close.iter().for_each(|close| values.push(*close));
+    let (validity, null_count) = udf::combined_validity(&[a, b]);
+    Ok((
+        Schema::new(ArrowType::Boolean, &a.schema().name),
+        ArrayData::primitive(values.finish(), close.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Whether every element of the numeric array `a` is close to the element of
+/// the numeric array `b` at the same index, with the relative tolerance
+/// `rtol` and the absolute tolerance `atol`, as a Boolean array.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, and `b_schema` and `b_array`, must point to
+/// valid Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_is_close(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    rtol: f64,
+    atol: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        let (out, data) = is_close(&a, &b, rtol, atol, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    fn run(
+        a: &Exported,
+        b: &Exported,
+        rtol: f64,
+        atol: f64,
+        flags: u32,
+    ) -> std::result::Result<Vec<Option<bool>>, ArrowUdfStatus> {
+        let options = ArrowUdfExecOptions {
+            flags,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_is_close(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                rtol,
+                atol,
+                &options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out.booleans()),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn tolerances_are_relative_to_b() {
+        assert!(is_close_value(1.0, 1.1, 0.1, 0.0, false));
+        assert!(!is_close_value(1.1, 1.0, 0.05, 0.0, false));
+        assert!(is_close_value(0.0, 1e-9, 0.0, 1e-8, false));
+        let a = Exported::primitive(&[100_i32, 100, 0]);
+        let b = Exported::primitive(&[101.0, 110.0, 0.5]);
+        assert_eq!(
+            run(&a, &b, 0.01, 0.0, 0),
+            Ok(vec![Some(true), Some(false), Some(false)])
+        );
+        assert_eq!(
+            run(&a, &b, 0.01, 0.5, 0),
+            Ok(vec![Some(true), Some(false), Some(true)])
+        );
+    }
+
+    #[test]
+    fn nan_and_infinities() {
+        let a = Exported::primitive(&[f64::NAN, f64::INFINITY, f64::INFINITY, f64::NAN]);
+        let b = Exported::primitive(&[f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1.0]);
+        let expected = |nan| Ok(vec![Some(nan), Some(true), Some(false), Some(false)]);
+        assert_eq!(run(&a, &b, 1.0, 1.0, 0), expected(false));
+        assert_eq!(
+            run(&a, &b, 1.0, 1.0, ARROW_UDF_FLAG_EQUAL_NAN),
+            expected(true)
+        );
+    }
+
+    #[test]
+    fn nulls_of_either_array_are_null() {
+        let a = Exported::nullable(&[Some(1_u8), None, Some(3)]);
+        let b = Exported::nullable(&[None, Some(2_i64), Some(3)]);
+        assert_eq!(run(&a, &b, 0.0, 0.0, 0), Ok(vec![None, None, Some(true)]));
+    }
+
+    #[test]
+    fn invalid_arguments_fail() {
+        let a = Exported::primitive(&[1.0, 2.0]);
+        let short = Exported::primitive(&[1.0]);
+        assert_eq!(
+            run(&a, &short, 0.0, 0.0, 0),
+            Err(ArrowUdfStatus::InvalidArgument)
+        );
+        for (rtol, atol) in [(-1.0, 0.0), (0.0, -1e-9), (f64::NAN, 0.0)] {
+            assert_eq!(
+                run(&a, &a, rtol, atol, 0),
+                Err(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        let booleans = Exported::boolean(&[true, false]);
+        assert_eq!(
+            run(&a, &booleans, 0.0, 0.0, 0),
+            Err(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+}
@@ -0,0 +1,786 @@
+//! Import of newline-delimited JSON into struct arrays.
+//!
+//! Services receiving JSON documents instead of Arrow arrays can run the
+//! same UDFs without a dependency producing Arrow: every line of the input
+//! is an object, read as a row of a struct array with a field per member.
+//! The schema of the array is given, or inferred from the documents, with
+//! the fields in the order they first appear:
+//!
+//! - `true` and `false` are Boolean, strings are Utf8 and objects structs.
+//! - Integers are Int64, and other numbers Float64, as are the fields with
+//!   both.
+//! - Fields only ever null are Utf8.
+//!
+//! With a given schema, numbers are read as the type of their field, failing
+//! if they don't fit, and temporal fields are read from their integer values.
+//! Members missing in a line, and `null`, are null, and members without a
+//! field are ignored. Arrays aren't supported.
+
+use std::borrow::Cow;
+use std::ffi::c_char;
+use std::sync::Arc;
+
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::row::ArrowField;
+use crate::schema::{ArrowType, Schema};
+use crate::types::with_native_type;
+
+/// A parsed JSON value. Numbers keep their text, to be parsed as the type of
+/// their field.
+#[derive(Debug)]
+enum Value<'a> {
+    Null,
+    Bool(bool),
+    Number(&'a str),
+    String(Cow<'a, str>),
+    Array,
+    Object(Vec<(Cow<'a, str>, Value<'a>)>),
+}
+
+impl Value<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array => "array",
+            Value::Object(_) => "object",
+        }
+    }
+
+    /// The value of the member `name` of an object, if it isn't null.
+    fn member(&self, name: &str) -> Option<&Value<'_>> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == name)
+                .map(|(_, value)| value)
+                .filter(|value| !matches!(value, Value::Null)),
+            _ => None,
+        }
+    }
+}
+
+/// Parser of a JSON document.
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidArgument(format!("{message} at column {}", self.position + 1))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        self.skip_whitespace();
+        match self.peek() == Some(byte) {
+            true => {
+                self.position += 1;
+                Ok(())
+            }
+            false => Err(self.error(&format!("expected {:?}", byte as char))),
+        }
+    }
+
+    /// The whole input as a single value.
+    fn document(mut self) -> Result<Value<'a>> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(value),
+            Some(_) => Err(self.error("unexpected text after the value")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value<'a>> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => {
+                self.array()?;
+                Ok(Value::Array)
+            }
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => {
+                for (literal, value) in [
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                    ("null", Value::Null),
+                ] {
+                    if self.input[self.position..].starts_with(literal) {
+                        self.position += literal.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.error("expected a value"))
+            }
+            None => Err(self.error("unexpected end of the document")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value<'a>> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    /// Skip an array, checking it's valid.
+    fn array(&mut self) -> Result<()> {
+        self.expect(b'[')?;
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            self.value()?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value<'a>> {
+        let start = self.position;
+        let digits = |parser: &mut Parser| {
+            let start = parser.position;
+            while matches!(parser.peek(), Some(b'0'..=b'9')) {
+                parser.position += 1;
+            }
+            parser.position > start
+        };
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        let mut valid = digits(self);
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            valid &= digits(self);
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.position += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+            valid &= digits(self);
+        }
+        match valid {
+            true => Ok(Value::Number(&self.input[start..self.position])),
+            false => Err(self.error("invalid number")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Cow<'a, str>> {
+        self.expect(b'"')?;
+        let start = self.position;
+        let mut unescaped: Option<String> = None;
+        loop {
+            let Some(byte) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            match byte {
+                b'"' => {
+                    let end = self.position;
+                    self.position += 1;
+                    return Ok(match unescaped {
+                        Some(string) => Cow::Owned(string),
+                        None => Cow::Borrowed(&self.input[start..end]),
+                    });
+                }
+                b'\\' => {
+                    let string = unescaped
+                        .get_or_insert_with(|| self.input[start..self.position].to_string());
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex()?;
+                            if (0xd800..0xdc00).contains(&code) {
+                                if !self.input[self.position + 1..].starts_with("\\u") {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                self.position += 2;
+                                let low = self.hex()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("unpaired surrogate"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    string.push(escaped);
+                    self.position += 1;
+                }
+                byte if byte < 0x20 => return Err(self.error("control character in a string")),
+                _ => {
+                    let len = self.input[self.position..]
+                        .chars()
+                        .next()
+                        .unwrap()
+                        .len_utf8();
+                    if let Some(string) = &mut unescaped {
+                        string.push_str(&self.input[self.position..self.position + len]);
+                    }
+                    self.position += len;
+                }
+            }
+        }
+    }
+
+    /// The 4 hexadecimal digits after the `u` of an escape, leaving the
+    /// position on the last one.
+    fn hex(&mut self) -> Result<u32> {
+        let digits = self
+            .input
+            .get(self.position + 1..self.position + 5)
+            .ok_or_else(|| self.error("invalid escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid escape"))?;
+        self.position += 4;
+        Ok(code)
+    }
+}
+
+/// The documents of the non-empty lines of `input`, with their line number.
+fn parse_lines<'a>(
+    input: &'a str,
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<(usize, Value<'a>)>> {
+    let lines: Vec<(usize, &str)> = input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    let mut documents = Vec::with_capacity(lines.len());
+    exec::for_each_batch(lines.len(), options, |rows| {
+        for (number, line) in &lines[rows] {
+            let value = Parser {
+                input: line,
+                position: 0,
+            }
+            .document()
+            .map_err(|error| at_line(*number, error))?;
+            if !matches!(value, Value::Object(_)) {
+                return Err(at_line(
+                    *number,
+                    Error::InvalidArgument(format!("expected an object, got {}", value.kind())),
+                ));
+            }
+            documents.push((*number, value));
+        }
+        Ok(())
+    })?;
+    Ok(documents)
+}
+
+fn at_line(number: usize, error: Error) -> Error {
+    match error {
+        Error::InvalidArgument(message) => {
+            Error::InvalidArgument(format!("line {}: {message}", number + 1))
+        }
+        Error::UnsupportedType(message) => {
+            Error::UnsupportedType(format!("line {}: {message}", number + 1))
+        }
+        error => error,
+    }
+}
+
+/// Type of a field while its schema is inferred.
+enum Inferred {
+    Null,
+    Boolean,
+    Int64,
+    Float64,
+    Utf8,
+    Struct(Vec<(String, Inferred)>),
+}
+
+impl Inferred {
+    fn merge(&mut self, name: &str, value: &Value) -> Result<()> {
+        let conflict = |inferred: &Inferred| {
+            Error::InvalidArgument(format!(
+                "field {name:?} with {} and {} values",
+                inferred.kind(),
+                value.kind()
+            ))
+        };
+        match (&mut *self, value) {
+            (_, Value::Null) => {}
+            (_, Value::Array) => {
+                return Err(Error::UnsupportedType(format!("arrays, in field {name:?}")))
+            }
+            (Inferred::Null, value) => {
+                *self = match value {
+                    Value::Bool(_) => Inferred::Boolean,
+                    Value::Number(text) if is_integer(text) => Inferred::Int64,
+                    Value::Number(_) => Inferred::Float64,
+                    Value::String(_) => Inferred::Utf8,
+                    _ => Inferred::Struct(Vec::new()),
+                };
+                self.merge(name, value)?;
+            }
+            (Inferred::Boolean, Value::Bool(_)) | (Inferred::Utf8, Value::String(_)) => {}
+            (Inferred::Int64, Value::Number(text)) => {
+                if !is_integer(text) {
+                    *self = Inferred::Float64;
+                }
+            }
+            (Inferred::Float64, Value::Number(_)) => {}
+            (Inferred::Struct(fields), Value::Object(members)) => {
+                for (member, value) in members {
+                    let position = match fields.iter().position(|(field, _)| field == member) {
+                        Some(position) => position,
+                        None => {
+                            fields.push((member.to_string(), Inferred::Null));
+                            fields.len() - 1
+                        }
+                    };
+                    fields[position].1.merge(member, value)?;
+                }
+            }
+            (inferred, _) => return Err(conflict(inferred)),
+        }
+        Ok(())
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Inferred::Null => "null",
+            Inferred::Boolean => "boolean",
+            Inferred::Int64 | Inferred::Float64 => "number",
+            Inferred::Utf8 => "string",
+            Inferred::Struct(_) => "object",
+        }
+    }
+
+    fn schema(&self, name: &str) -> Schema {
+        match self {
+            Inferred::Null | Inferred::Utf8 => Schema::new(ArrowType::Utf8, name),
+            Inferred::Boolean => Schema::new(ArrowType::Boolean, name),
+            Inferred::Int64 => Schema::new(ArrowType::Int64, name),
+            Inferred::Float64 => Schema::new(ArrowType::Float64, name),
+            Inferred::Struct(fields) => Schema::new(ArrowType::Struct, name).with_children(
+                fields
+                    .iter()
+                    .map(|(name, inferred)| inferred.schema(name))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn is_integer(text: &str) -> bool {
+    !text.contains(['.', 'e', 'E'])
+}
+
+/// Schema of the struct array of the documents of `input`, named `name`.
+pub fn infer_schema(input: &str, name: &str) -> Result<Schema> {
+    let documents = parse_lines(input, &ArrowUdfExecOptions::default())?;
+    infer(&documents, name)
+}
+
+fn infer(documents: &[(usize, Value)], name: &str) -> Result<Schema> {
+    let mut inferred = Inferred::Struct(Vec::new());
+    for (number, document) in documents {
+        inferred
+            .merge(name, document)
+            .map_err(|error| at_line(*number, error))?;
+    }
+    Ok(inferred.schema(name))
+}
+
+/// Numbers read as the values of primitive fields.
+trait FromNumber: Sized {
+    fn from_number(text: &str) -> Option<Self>;
+}
+
+macro_rules! from_number {
+    ($($type:ty),*) => {
+        $(
+            impl FromNumber for $type {
+                fn from_number(text: &str) -> Option<$type> {
+                    text.parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+from_number!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+/// The values of a field, failing if any can't be read as `T`.
+fn convert<'v, T>(
+    values: &[(usize, Option<&'v Value<'v>>)],
+    field: &Schema,
+    f: impl Fn(&'v Value<'v>) -> Option<T>,
+) -> Result<Vec<Option<T>>> {
+    values
+        .iter()
+        .map(|(number, value)| match value {
+            None if !field.flags.nullable => Err(at_line(
+                *number,
+                Error::InvalidArgument(format!("null for the non-nullable field {:?}", field.name)),
+            )),
+            None => Ok(None),
+            Some(value) => f(value).map(Some).ok_or_else(|| {
+                at_line(
+                    *number,
+                    Error::InvalidArgument(format!(
+                        "{} value for the {:?} field {:?}",
+                        value.kind(),
+                        field.data_type,
+                        field.name
+                    )),
+                )
+            }),
+        })
+        .collect()
+}
+
+/// Array of the values of `field`, one per document, `None` when missing or
+/// null.
+fn build(values: &[(usize, Option<&Value>)], field: &Schema) -> Result<ArrayData> {
+    Ok(match field.data_type {
+        ArrowType::Boolean => {
+            let values = convert(values, field, |value| match value {
+                Value::Bool(value) => Some(*value),
+                _ => None,
+            })?;
+            bool::build(values.iter().map(Option::as_ref))
+        }
+        ArrowType::Utf8 => {
+            let values = convert(values, field, |value| match value {
+                Value::String(value) => Some(value.as_ref()),
+                _ => None,
+            })?;
+            <&str>::build(values.iter().map(Option::as_ref))
+        }
+        ArrowType::Struct => {
+            let objects = convert(values, field, |value| match value {
+                Value::Object(_) => Some(value),
+                _ => None,
+            })?;
+            struct_array(values, &objects, field)?
+        }
+        data_type => with_native_type!(data_type.physical_type(), T => {
+            let values = convert(values, field, |value| match value {
+                Value::Number(text) => T::from_number(text),
+                _ => None,
+            })?;
+            T::build(values.iter().map(Option::as_ref))
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "JSON import of {data_type:?} fields"
+        )))),
+    })
+}
+
+/// Struct array of the fields of `schema`, from `objects`, with nulls for
+/// `None`.
+fn struct_array(
+    values: &[(usize, Option<&Value>)],
+    objects: &[Option<&Value>],
+    schema: &Schema,
+) -> Result<ArrayData> {
+    let children = schema
+        .children
+        .iter()
+        .map(|field| {
+            let members: Vec<(usize, Option<&Value>)> = values
+                .iter()
+                .zip(objects)
+                .map(|((number, _), object)| {
+                    (
+                        *number,
+                        object.and_then(|object| object.member(&field.name)),
+                    )
+                })
+                .collect();
+            build(&members, field)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let data = ArrayData::struct_array(children, objects.len());
+    let null_count = objects.iter().filter(|object| object.is_none()).count();
+    if null_count == 0 {
+        return Ok(data);
+    }
+    let mut validity = BitmapBuilder::with_capacity(objects.len());
+    objects
+        .iter()
+        .for_each(|object| validity.push(object.is_some()));
+    Ok(data.with_validity(Some(validity.finish()), null_count))
+}
+
+fn to_array(documents: &[(usize, Value)], schema: &Schema) -> Result<ArrayData> {
+    if schema.data_type != ArrowType::Struct {
+        return Err(Error::UnsupportedType(format!(
+            "JSON import into {:?} arrays, expected a struct schema",
+            schema.data_type
+        )));
+    }
+    let values: Vec<(usize, Option<&Value>)> = documents
+        .iter()
+        .map(|(number, document)| (*number, Some(document)))
+        .collect();
+    let objects: Vec<Option<&Value>> = documents
+        .iter()
+        .map(|(_, document)| Some(document))
+        .collect();
+    struct_array(&values, &objects, schema)
+}
+
+/// Struct array of the documents of `input`, one per non-empty line, with
+/// the fields of `schema`, or of the schema inferred from the documents if
+/// it's `None`.
+pub fn read(
+    input: &str,
+    schema: Option<&Schema>,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let documents = parse_lines(input, options)?;
+    let schema = match schema {
+        Some(schema) => schema.clone(),
+        None => infer(&documents, "")?,
+    };
+    let data = to_array(&documents, &schema)?;
+    Ok((schema, data))
+}
+
+/// Import the `len` bytes of newline-delimited JSON at `input` as a struct
+/// array with a row per non-empty line. The fields are the ones of `schema`,
+/// a struct schema, or inferred from the documents when `schema` is null.
+///
+/// # Safety
+///
+/// `input` must be valid for reads of `len` bytes, `schema` must be null or
+/// a valid Arrow C Data Interface schema, `options` must be null or valid,
+/// and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_json_read(
+    input: *const c_char,
+    len: usize,
+    schema: *const ArrowCDataInterfaceSchema,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = match schema.is_null() {
+            true => None,
+            false => Some(Schema::from_ffi(&*schema)?),
+        };
+        let input = match len {
+            0 => "",
+            _ => std::str::from_utf8(std::slice::from_raw_parts(input as *const u8, len))
+                .map_err(|_| Error::InvalidArgument("the input is not valid UTF-8".to_string()))?,
+        };
+        let (schema, data) = read(input, schema.as_ref(), &options)?;
+        export::export_to(&schema, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    const LINES: &str = concat!(
+        "{\"id\": 1, \"name\": \"a\", \"ok\": true, \"point\": {\"x\": 1.5}}\n",
+        "\n",
+        "{\"id\": 2, \"name\": null, \"point\": {\"x\": 2}, \"other\": null}\n",
+        "{\"name\": \"c\\u00e9\\n\", \"ok\": false, \"id\": 3}\n",
+    );
+
+    fn read_ffi(
+        input: &str,
+        schema: Option<&Schema>,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut schema = schema.map(export::export_schema);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_json_read(
+                input.as_ptr() as *const c_char,
+                input.len(),
+                schema.as_ref().map_or(std::ptr::null(), |schema| schema),
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        if let Some(schema) = &mut schema {
+            unsafe { schema.release.unwrap()(schema) };
+        }
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn error(input: &str, schema: Option<&Schema>) -> Error {
+        match read(input, schema, &ArrowUdfExecOptions::default()) {
+            Ok(_) => panic!("expected {input:?} to fail"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn the_schema_is_inferred_in_the_order_of_the_fields() {
+        let expected = Schema::new(ArrowType::Struct, "rows").with_children(vec![
+            Schema::new(ArrowType::Int64, "id"),
+            Schema::new(ArrowType::Utf8, "name"),
+            Schema::new(ArrowType::Boolean, "ok"),
+            Schema::new(ArrowType::Struct, "point")
+                .with_children(vec![Schema::new(ArrowType::Float64, "x")]),
+            Schema::new(ArrowType::Utf8, "other"),
+        ]);
+        assert_eq!(infer_schema(LINES, "rows"), Ok(expected));
+    }
+
+    #[test]
+    fn documents_are_read_as_rows_with_nulls_for_missing_members() {
+        let out = read_ffi(LINES, None).unwrap();
+        assert_eq!(out.child_values::<i64>(0), [Some(1), Some(2), Some(3)]);
+        out.with_array(|array| {
+            assert_eq!(array.len(), 3);
+            let names = array.child(1);
+            assert!(names.is_valid(0) && !names.is_valid(1));
+            assert_eq!(names.utf8_value(2), Ok("c\u{e9}\n"));
+            let ok = array.child(2);
+            assert!(ok.is_valid(0) && !ok.is_valid(1) && ok.is_valid(2));
+            let x = array.child(3).child(0);
+            assert_eq!(x.values::<f64>(), [1.5, 2.0, 0.0]);
+            assert!(!array.child(3).is_valid(2));
+            assert!((0..3).all(|i| !array.child(4).is_valid(i)));
+        });
+    }
+
+    #[test]
+    fn numbers_are_read_as_the_type_of_their_field() {
+        let schema = Schema::new(ArrowType::Struct, "")
+            .with_children(vec![Schema::new(ArrowType::UInt8, "id")]);
+        let out = read_ffi(LINES, Some(&schema)).unwrap();
+        assert_eq!(out.child_values::<u8>(0), [Some(1), Some(2), Some(3)]);
+        out.with_array(|array| assert_eq!(array.schema(), &schema));
+
+        let input = "{\"id\": 256}\n{\"id\": -1}";
+        let Error::InvalidArgument(message) = error(input, Some(&schema)) else {
+            panic!("expected an invalid argument");
+        };
+        assert_eq!(message, "line 1: number value for the UInt8 field \"id\"");
+        let mut strict = schema.clone();
+        strict.children[0].flags.nullable = false;
+        let Error::InvalidArgument(message) = error("{}", Some(&strict)) else {
+            panic!("expected an invalid argument");
+        };
+        assert_eq!(message, "line 1: null for the non-nullable field \"id\"");
+    }
+
+    #[test]
+    fn invalid_documents_fail_with_their_line() {
+        for (input, expected) in [
+            ("{}\n{\"a\": 1,}", "line 2: expected '\"' at column 9"),
+            (
+                "{\"a\": 1} 2",
+                "line 1: unexpected text after the value at column 10",
+            ),
+            ("[1]", "line 1: expected an object, got array"),
+            (
+                "{\"a\": \"\\ud800\"}",
+                "line 1: unpaired surrogate at column 13",
+            ),
+            (
+                "{\"a\": 1}\n{\"a\": \"b\"}",
+                "line 2: field \"a\" with number and string values",
+            ),
+        ] {
+            let Error::InvalidArgument(message) = error(input, None) else {
+                panic!("expected an invalid argument for {input:?}");
+            };
+            assert!(message.starts_with(expected), "{message:?}");
+        }
+        assert!(matches!(
+            error("{\"a\": [1]}", None),
+            Error::UnsupportedType(_)
+        ));
+        let schema = Schema::new(ArrowType::Int64, "");
+        assert!(matches!(
+            error("{}", Some(&schema)),
+            Error::UnsupportedType(_)
+        ));
+    }
+
+    #[test]
+    fn invalid_inputs_fail_through_ffi() {
+        assert!(matches!(
+            read_ffi("{\"a\": tru}", None),
+            Err(ArrowUdfStatus::InvalidArgument)
+        ));
+        let input = [b'{', b'}', 0xff];
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_json_read(
+                input.as_ptr() as *const c_char,
+                input.len(),
+                std::ptr::null(),
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+        let empty = read_ffi("", None).unwrap();
+        empty.with_array(|array| assert_eq!(array.len(), 0));
+    }
+}
@@ -0,0 +1,579 @@
+//! Library of element-wise kernels, generated from a declarative list.
+//!
+//! Every kernel is declared with the name of its entry point, and one or
+//! more rules with the input types it supports, the output type (`Self` for
+//! the same type as the input), and the body computing a single value. The
+//! `kernels!` macro generates a specialized loop for every input type, and
+//! an entry point choosing the loop for the type of the received array:
+//!
+//! ```text
+//! arrow_udf_negate {
+//!     (x: i8, i16, i32, i64) -> Self { x.wrapping_neg() }
+//!     (x: f32, f64) -> Self { -x }
+//! }
+//! ```
+//!
+//! Kernels can also take scalar parameters, declared after the name, which
+//! the entry point receives after the array, and which are in scope in the
+//! bodies:
+//!
+//! ```text
+//! arrow_udf_scale(factor: f64) {
+//!     (x: i8, i16, i32, i64, u8, u16, u32, u64, f32, f64) -> f64 { x as f64 * factor }
+//! }
+//! ```
+//!
+//! Rules can also return several values per element, as a tuple or a
+//! struct deriving `ArrowRow`, and the entry point returns a struct array
+//! with a field per value, like `arrow_udf_divmod`:
+//!
+//! ```text
+//! arrow_udf_divmod(divisor: i64) {
+//!     (x: i8, i16, i32, i64) -> DivMod { DivMod { ... } }
+//! }
+//! ```
+//!
+//! Rules can also name a second argument after the first one, the
+//! `udf::RowCtx` of every element, with its position, whether it's null, and
+//! the number of elements and nulls of the array and of its batch, for
+//! positional logic:
+//!
+//! ```text
+//! arrow_udf_first_of_batch {
+//!     (x, ctx: i64) -> i64 { (ctx.index == ctx.batch_start) as i64 }
+//! }
+//! ```
+//!
+//! Rules carrying a state from row to row, like a moving average, declare it
+//! after the input types, and the body receives it as `&mut` of its type.
+//! It starts as the default of the type at every batch, and the batches of
+//! these kernels run in order, in a single thread:
+//!
+//! ```text
+//! arrow_udf_ema(alpha: f64) {
+//!     (x: f64; mut average: Option<f64>) -> f64 { ... }
+//! }
+//! ```
+//!
+//! Entry points receive an array and return an array of the same length,
+//! with the same calling convention and null handling as `udf::map`, or
+//! `udf::map_rows` for rows.
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error};
+use crate::export;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::row::ArrowRow;
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+use crate::udf::MapOutput;
+
+/// The output type of a rule, given its input type.
+macro_rules! output_type {
+    (Self, $input:ty) => {
+        $input
+    };
+    ($output:ty, $input:ty) => {
+        $output
+    };
+}
+
+/// The loops of a rule, applying its body to every element of an array
+/// whose values are one of its input types, with the context of every
+/// element or the state carried from row to row if the rule takes them.
+macro_rules! rule {
+    (
+        $array:expr, $options:expr, $value_type:expr, $output:tt,
+        |$x:ident| $body:block, $($input:ty),+
+    ) => {
+        $(
+            if $value_type == <$input as NativeType>::ARROW_TYPE {
+                return <output_type!($output, $input) as MapOutput>::map(
+                    $array,
+                    $options,
+                    |$x: $input| -> output_type!($output, $input) { $body },
+                );
+            }
+        )+
+    };
+    (
+        $array:expr, $options:expr, $value_type:expr, $output:tt,
+        |$x:ident, $ctx:ident| $body:block, $($input:ty),+
+    ) => {
+        $(
+            if $value_type == <$input as NativeType>::ARROW_TYPE {
+                return <output_type!($output, $input) as MapOutput>::map_with_ctx(
+                    $array,
+                    $options,
+                    |$x: $input, $ctx: crate::udf::RowCtx| -> output_type!($output, $input) {
+                        $body
+                    },
+                );
+            }
+        )+
+    };
+    (
+        $array:expr, $options:expr, $value_type:expr, $output:tt,
+        |$x:ident| mut $state:ident: $state_type:ty $body:block, $($input:ty),+
+    ) => {
+        $(
+            if $value_type == <$input as NativeType>::ARROW_TYPE {
+                return crate::udf::map_stateful(
+                    $array,
+                    $options,
+                    |$state: &mut $state_type, $x: $input| -> output_type!($output, $input) {
+                        $body
+                    },
+                );
+            }
+        )+
+    };
+}
+
+macro_rules! kernels {
+    ($(
+        $(#[$attr:meta])*
+        $name:ident $(($($param:ident: $param_type:ty),*))? {
+            $(
+                ($x:ident $(, $ctx:ident)?: $($input:ty),+ $(; mut $state:ident: $state_type:ty)?)
+                    -> $output:tt $body:block
+            )+
+        }
+    )*) => {$(
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// `schema` and `array` must point to a valid Arrow C Data Interface
+        /// array, `options` must be null or valid, and `out_schema` and
+        /// `out_array` must be valid for writes.
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(
+            schema: *const ArrowCDataInterfaceSchema,
+            array: *const ArrowCDataInterfaceArray,
+            $($($param: $param_type,)*)?
+            options: *const ArrowUdfExecOptions,
+            out_schema: *mut ArrowCDataInterfaceSchema,
+            out_array: *mut ArrowCDataInterfaceArray,
+        ) -> ArrowUdfStatus {
+            ffi_guard(|| {
+                let options = ArrowUdfExecOptions::from_ffi(options)?;
+                let schema = Schema::from_ffi(&*schema)?;
+                let array = ArrowArray::import(&schema, &*array)?;
+                let run = || {
+                    let value_type = array.value_type();
+                    $(
+                        rule!(
+                            &array,
+                            &options,
+                            value_type,
+                            $output,
+                            |$x $(, $ctx)?| $(mut $state: $state_type)? $body,
+                            $($input),+
+                        );
+                    )+
+                    Err(Error::UnsupportedType(format!(
+                        "{} of {:?} arrays",
+                        stringify!($name),
+                        value_type
+                    )))
+                };
+                #[cfg(feature = "trace")]
+                let (out, data) = crate::trace::record(
+                    stringify!($name),
+                    &[$($(crate::trace::Arg::from($param)),*)?],
+                    &array,
+                    &options,
+                    run,
+                )?;
+                #[cfg(not(feature = "trace"))]
+                let (out, data) = run().map(|(out, data)| (out, std::sync::Arc::new(data)))?;
+                export::export_to(&out, &data, out_schema, out_array)?;
+                Ok(())
+            })
+        }
+    )*
+
+    /// The entry point of every kernel, taking its scalar arguments in a
+    /// slice, to replay traces.
+    #[cfg(feature = "trace")]
+    pub(crate) static KERNEL_ENTRY_POINTS: &[(&str, crate::trace::EntryPoint)] = &[$(
+        (stringify!($name), |schema, array, args, options, out_schema, out_array| {
+            let [$($($param),*)?] = args else {
+                return Err(Error::InvalidArgument(format!(
+                    "{} takes {} arguments, got {}",
+                    stringify!($name),
+                    <[&str]>::len(&[$($(stringify!($param)),*)?]),
+                    args.len()
+                )));
+            };
+            $($(let $param = <$param_type>::try_from(*$param)?;)*)?
+            Ok(unsafe {
+                $name(schema, array, $($($param,)*)? options, out_schema, out_array)
+            })
+        }),
+    )*];
+
+    /// The entry point of every kernel, and the types of the values it
+    /// supports, with the schema of the result for each of them.
+    pub(crate) static KERNEL_SIGNATURES: &[(&str, &[(ArrowType, fn(&str) -> Schema)])] = &[$(
+        (stringify!($name), &[$($((
+            <$input as NativeType>::ARROW_TYPE,
+            <output_type!($output, $input) as MapOutput>::schema,
+        )),+),+]),
+    )*];
+    };
+}
+
+/// Quotient and remainder of a division, as returned by `arrow_udf_divmod`.
+/// Both are null for divisions by zero, and for divisions overflowing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ArrowRow)]
+pub struct DivMod {
+    pub quotient: Option<i64>,
+    pub remainder: Option<i64>,
+}
+
+impl DivMod {
+    /// Division rounding the quotient towards negative infinity, so the
+    /// remainder has the sign of the divisor, like `divmod` in Python.
+    pub fn new(x: i64, divisor: i64) -> DivMod {
+        let (Some(quotient), Some(remainder)) = (x.checked_div(divisor), x.checked_rem(divisor))
+        else {
+            return DivMod {
+                quotient: None,
+                remainder: None,
+            };
+        };
+        let (quotient, remainder) = match remainder != 0 && (remainder < 0) != (divisor < 0) {
+            true => (quotient - 1, remainder + divisor),
+            false => (quotient, remainder),
+        };
+        DivMod {
+            quotient: Some(quotient),
+            remainder: Some(remainder),
+        }
+    }
+}
+
+kernels! {
+    /// Negation of every element of an array. Integers wrap on overflow.
+    arrow_udf_negate {
+        (x: i8, i16, i32, i64) -> Self { x.wrapping_neg() }
+        (x: f32, f64) -> Self { -x }
+    }
+
+    /// Absolute value of every element of an array. Integers wrap on
+    /// overflow.
+    arrow_udf_abs {
+        (x: i8, i16, i32, i64) -> Self { x.wrapping_abs() }
+        (x: u8, u16, u32, u64) -> Self { x }
+        (x: f32, f64) -> Self { x.abs() }
+    }
+
+    /// Square of every element of an array. Integers wrap on overflow.
+    arrow_udf_square {
+        (x: i8, i16, i32, i64, u8, u16, u32, u64) -> Self { x.wrapping_mul(x) }
+        (x: f32, f64) -> Self { x * x }
+    }
+
+    /// Conversion of every element of an array to Float64.
+    arrow_udf_to_float64 {
+        (x: i8, i16, i32, i64, u8, u16, u32, u64, f32, f64) -> f64 { x as f64 }
+    }
+
+    /// Every element of an array limited to the range between `lo` and `hi`,
+    /// as Float64. Like `numpy.clip`, NaN is kept, and the result is `hi`
+    /// when `lo > hi`.
+    arrow_udf_clip(lo: f64, hi: f64) {
+        (x: i8, i16, i32, i64, u8, u16, u32, u64, f32, f64) -> f64 {
+            let x = if (x as f64) < lo { lo } else { x as f64 };
+            if x > hi { hi } else { x }
+        }
+    }
+
+    /// Every element of an array multiplied by `factor`, as Float64.
+    arrow_udf_scale(factor: f64) {
+        (x: i8, i16, i32, i64, u8, u16, u32, u64, f32, f64) -> f64 { x as f64 * factor }
+    }
+
+    /// Quotient and remainder of the division of every element of an integer
+    /// array by `divisor`, as a struct array of Int64 `quotient` and
+    /// `remainder`, rounding the quotient towards negative infinity. Both
+    /// are null when `divisor` is 0.
+    arrow_udf_divmod(divisor: i64) {
+        (x: i8, i16, i32, i64, u8, u16, u32) -> DivMod { DivMod::new(x as i64, divisor) }
+    }
+
+    /// Exponential moving average of the elements of an array, weighting
+    /// every element by `alpha` and the previous average by `1 - alpha`, as
+    /// Float64. Null elements are null, and don't change the average, which
+    /// starts again at every batch.
+    arrow_udf_ema(alpha: f64) {
+        (x: i8, i16, i32, i64, u8, u16, u32, u64, f32, f64; mut average: Option<f64>) -> f64 {
+            let next = match *average {
+                Some(average) => average + alpha * (x as f64 - average),
+                None => x as f64,
+            };
+            *average = Some(next);
+            next
+        }
+    }
+
+    /// Standard score of every element of an array, given the `mean` and the
+    /// standard deviation `std` of the population, as Float64.
+    arrow_udf_zscore(mean: f64, std: f64) {
+        (x: i8, i16, i32, i64, u8, u16, u32, u64, f32, f64) -> f64 {
+            (x as f64 - mean) / std
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::bitmap::BitmapBuilder;
+    use crate::export::ArrayData;
+    use crate::schema::ArrowType;
+    use crate::testing::Exported;
+
+    type EntryPoint = unsafe extern "C" fn(
+        *const ArrowCDataInterfaceSchema,
+        *const ArrowCDataInterfaceArray,
+        *const ArrowUdfExecOptions,
+        *mut ArrowCDataInterfaceSchema,
+        *mut ArrowCDataInterfaceArray,
+    ) -> ArrowUdfStatus;
+
+    fn call(kernel: EntryPoint, input: &Exported) -> Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            kernel(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn kernels_are_specialized_for_the_input_type() {
+        let input = Exported::nullable(&[Some(i8::MIN), None, Some(3)]);
+        let out = call(arrow_udf_negate, &input).unwrap();
+        assert_eq!(out.nullable_values::<i8>(), [Some(i8::MIN), None, Some(-3)]);
+        let out = call(arrow_udf_abs, &Exported::primitive(&[-1.5_f32, 2.0])).unwrap();
+        assert_eq!(out.values::<f32>(), [1.5, 2.0]);
+        let out = call(arrow_udf_square, &Exported::primitive(&[16_u8, 3])).unwrap();
+        assert_eq!(out.values::<u8>(), [0, 9]);
+        let out = call(arrow_udf_to_float64, &Exported::primitive(&[-2_i16, 7])).unwrap();
+        assert_eq!(out.values::<f64>(), [-2.0, 7.0]);
+    }
+
+    #[test]
+    fn scalar_parameters_are_passed_to_the_bodies() {
+        let input = Exported::nullable(&[Some(-3_i32), None, Some(1), Some(8)]);
+        let (schema, array) = (&input.schema, &input.array);
+        let mut clip = Exported::empty();
+        let status = unsafe {
+            arrow_udf_clip(
+                schema,
+                array,
+                -1.0,
+                5.0,
+                ptr::null(),
+                &mut clip.schema,
+                &mut clip.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(
+            clip.nullable_values::<f64>(),
+            [Some(-1.0), None, Some(1.0), Some(5.0)]
+        );
+        let mut inverted = Exported::empty();
+        let status = unsafe {
+            let out = (&mut inverted.schema, &mut inverted.array);
+            arrow_udf_clip(schema, array, 2.0, 0.0, ptr::null(), out.0, out.1)
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(
+            inverted.nullable_values::<f64>(),
+            [Some(0.0), None, Some(0.0), Some(0.0)]
+        );
+        let mut scale = Exported::empty();
+        let status = unsafe {
+            arrow_udf_scale(
+                schema,
+                array,
+                0.5,
+                ptr::null(),
+                &mut scale.schema,
+                &mut scale.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(
+            scale.nullable_values::<f64>(),
+            [Some(-1.5), None, Some(0.5), Some(4.0)]
+        );
+        let mut zscore = Exported::empty();
+        let status = unsafe {
+            let out = (&mut zscore.schema, &mut zscore.array);
+            arrow_udf_zscore(schema, array, 1.0, 2.0, ptr::null(), out.0, out.1)
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(
+            zscore.nullable_values::<f64>(),
+            [Some(-2.0), None, Some(0.0), Some(3.5)]
+        );
+    }
+
+    #[test]
+    fn constant_inputs_stay_constant() {
+        let schema = export::constant_schema(ArrowType::Int32, "x");
+        let input = Exported::new(&schema, ArrayData::constant(-4_i32, 10));
+        let out = call(arrow_udf_square, &input).unwrap();
+        assert_eq!(out.with_array(|array| array.len()), 10);
+        assert_eq!(
+            out.with_array(|array| array.constant_value::<i32>()),
+            Some(16)
+        );
+    }
+
+    #[test]
+    fn other_types_are_unsupported() {
+        let mut bits = BitmapBuilder::with_capacity(1);
+        bits.push(true);
+        let data = ArrayData::primitive(bits.finish(), 1);
+        let input = Exported::new(&Schema::new(ArrowType::Boolean, "x"), data);
+        assert_eq!(
+            call(arrow_udf_negate, &input).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+
+    fn divmod(input: &Exported, divisor: i64) -> Exported {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_divmod(
+                &input.schema,
+                &input.array,
+                divisor,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        out
+    }
+
+    #[test]
+    fn rows_are_returned_as_struct_arrays() {
+        let input = Exported::nullable(&[Some(7_i32), Some(-7), None]);
+        let out = divmod(&input, 2);
+        out.with_array(|array| {
+            assert_eq!(array.data_type(), ArrowType::Struct);
+            assert_eq!(array.schema().children[0].name, "quotient");
+            assert_eq!(array.null_count(), 1);
+            assert!(!array.is_valid(2));
+        });
+        assert_eq!(out.child_values::<i64>(0)[..2], [Some(3), Some(-4)]);
+        assert_eq!(out.child_values::<i64>(1)[..2], [Some(1), Some(1)]);
+        let by_zero = divmod(&input, 0);
+        assert_eq!(by_zero.child_values::<i64>(0)[..2], [None, None]);
+        let by_negative = divmod(&Exported::primitive(&[7_u8]), -2);
+        assert_eq!(by_negative.child_values::<i64>(0), [Some(-4)]);
+        assert_eq!(by_negative.child_values::<i64>(1), [Some(-1)]);
+    }
+
+    #[test]
+    fn rows_of_constant_inputs_are_repeated() {
+        let schema = export::constant_schema(ArrowType::Int64, "x");
+        let input = Exported::new(&schema, ArrayData::constant(9_i64, 3));
+        let out = divmod(&input, 4);
+        out.with_array(|array| assert_eq!(array.data_type(), ArrowType::Struct));
+        assert_eq!(out.child_values::<i64>(0), [Some(2); 3]);
+        assert_eq!(out.child_values::<i64>(1), [Some(1); 3]);
+    }
+
+    /// Whether every element is the first one of its batch, with the rule
+    /// of a kernel taking the context of the elements.
+    fn first_of_batch(input: &Exported, batch_size: i64) -> Exported {
+        let options = ArrowUdfExecOptions {
+            batch_size,
+            ..ArrowUdfExecOptions::default()
+        };
+        let run = |array: &ArrowArray| -> crate::error::Result<(Schema, ArrayData)> {
+            let value_type = array.value_type();
+            rule!(
+                array,
+                &options,
+                value_type,
+                i64,
+                |_x, ctx| { (ctx.index == ctx.batch_start) as i64 },
+                i32,
+                i64
+            );
+            Err(Error::UnsupportedType(format!("{value_type:?}")))
+        };
+        let (schema, data) = input.with_array(run).unwrap();
+        Exported::new(&schema, data)
+    }
+
+    #[test]
+    fn rules_taking_the_context_get_the_position_of_every_element() {
+        let input = Exported::nullable(&[Some(1_i32), None, Some(3), Some(4), Some(5)]);
+        let out = first_of_batch(&input, 2);
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [Some(1), None, Some(1), Some(0), Some(1)]
+        );
+        let constant = export::constant_schema(ArrowType::Int64, "x");
+        let input = Exported::new(&constant, ArrayData::constant(7_i64, 3));
+        assert_eq!(first_of_batch(&input, 2).values::<i64>(), [1, 0, 1]);
+    }
+
+    fn ema(input: &Exported, alpha: f64, options: &ArrowUdfExecOptions) -> Exported {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_ema(
+                &input.schema,
+                &input.array,
+                alpha,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        out
+    }
+
+    #[test]
+    fn stateful_rules_carry_their_state_within_batches() {
+        let input = Exported::nullable(&[Some(2_i32), None, Some(4), Some(8), Some(0)]);
+        let out = ema(&input, 0.5, &ArrowUdfExecOptions::default());
+        assert_eq!(
+            out.nullable_values::<f64>(),
+            [Some(2.0), None, Some(3.0), Some(5.5), Some(2.75)]
+        );
+        let options = ArrowUdfExecOptions {
+            batch_size: 3,
+            num_threads: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(
+            ema(&input, 0.5, &options).nullable_values::<f64>(),
+            [Some(2.0), None, Some(3.0), Some(8.0), Some(4.0)]
+        );
+    }
+}
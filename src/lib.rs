@@ -0,0 +1,269 @@
+//! Proof of concept of user defined functions over Arrow arrays.
+//!
+//! Arrays are received and returned through the Arrow C Data Interface, so
+//! the functions can be used from any language able to call a C ABI.
+//!
+//! Every entry point receives an optional `ArrowUdfExecOptions`, and returns
+//! an `ArrowUdfStatus`.
+
+// So that `#[derive(ArrowRow)]`, which refers to `::distance`, also works in
+// this crate.
+extern crate self as distance;
+
+pub mod aggregate;
+mod alias;
+pub mod arena;
+pub mod argminmax;
+pub mod arith;
+pub mod array;
+#[cfg(feature = "async")]
+pub mod async_udf;
+pub mod binary;
+pub mod bitmap;
+pub mod bloom;
+pub mod buffer;
+pub mod cast;
+pub mod check;
+pub mod chunked;
+pub mod compare;
+pub mod compose;
+pub mod concat;
+pub mod context;
+pub mod cpu;
+pub mod csv;
+pub mod dictionary;
+pub mod dispatch;
+pub mod dot;
+pub mod duplicate;
+pub mod endian;
+pub mod error;
+pub mod exec;
+pub mod export;
+pub mod expr;
+pub mod ffi;
+pub mod fill;
+mod flatbuffers;
+pub mod fma;
+pub mod format;
+pub mod geo;
+pub mod groupby;
+pub mod hash;
+pub mod histogram;
+pub mod hll;
+pub mod if_else;
+mod ipc;
+pub mod isclose;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod kernels;
+pub mod logic;
+#[cfg(all(feature = "nested", feature = "strings"))]
+pub mod map;
+pub mod memo;
+pub mod memory;
+pub mod merge;
+pub mod metrics;
+pub mod nan;
+#[cfg(all(feature = "numa", target_os = "linux"))]
+pub mod numa;
+pub mod options;
+pub mod overload;
+pub mod pairwise;
+#[cfg(feature = "strings")]
+pub mod pattern;
+pub mod pipeline;
+pub mod quantile;
+pub mod random;
+pub mod rank;
+pub mod registry;
+pub mod remap;
+pub mod rolling;
+pub mod row;
+#[cfg(feature = "serde")]
+pub mod row_serde;
+pub mod run_end;
+pub mod sample;
+pub mod schema;
+#[cfg(all(feature = "shm", target_os = "linux"))]
+mod segment;
+pub mod shift;
+#[cfg(all(feature = "shm", target_os = "linux"))]
+pub mod shm;
+pub mod slice;
+pub mod sort;
+pub mod spill;
+pub mod stats;
+pub mod strategy;
+pub mod stream;
+pub mod tdigest;
+#[cfg(feature = "temporal")]
+pub mod temporal;
+#[cfg(test)]
+mod testing;
+pub mod topk;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod types;
+pub mod udf;
+pub mod udtf;
+#[cfg(feature = "strings")]
+pub mod utf8;
+pub mod validate;
+pub mod welford;
+
+use array::ArrowArray;
+use error::{ffi_guard, ArrowUdfStatus};
+use ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use options::ArrowUdfExecOptions;
+use schema::Schema;
+
+fn distance(value: i64, point: i64) -> i64 {
+    (value - point).abs()
+}
+
+/// Sum of the distances between every element of an Int64 array and `point`,
+/// written into `out`, or exported into the scalar structs of the options
+/// when they're set, null if the array has nulls and the null policy
+/// propagates them.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out` must be valid for writes, or
+/// null if the options export the result.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_sum_distances(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    point: i64,
+    options: *const ArrowUdfExecOptions,
+    out: *mut i64,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        if options.exports_scalar() && udf::null_result(&array, &options)? {
+            return export::export_scalar::<i64>(&options, &schema.name, None);
+        }
+        let sum = udf::map_sum(&array, &options, |value| distance(value, point))?;
+        export::write_reduction(&options, &schema.name, sum, out)
+    })
+}
+
+/// Distances between every element of an Int64 array and `point`.
+///
+/// The result is exported into `out_schema` and `out_array`, and must be
+/// released by the caller.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_distances(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    point: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let key = memo::CacheKey::new("distances", &array, &[point as u64], &options);
+        let (out, data) = memo::cached(&options, key, || {
+            udf::map(&array, &options, |value: i64| distance(value, point))
+        })?;
+        export::export_to(&out, &data, out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    #[test]
+    fn distances_through_ffi() {
+        let input = Exported::nullable(&[Some(1_i64), None, Some(5), Some(-3)]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_distances(
+                &input.schema,
+                &input.array,
+                2,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [Some(1), None, Some(3), Some(5)]
+        );
+        let mut sum = 0;
+        let status = unsafe {
+            arrow_udf_sum_distances(&input.schema, &input.array, 2, ptr::null(), &mut sum)
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(sum, 9);
+    }
+
+    #[test]
+    fn errors_are_reported_with_a_status() {
+        let input = Exported::primitive(&[1_i32]);
+        let mut sum = 0;
+        let status = unsafe {
+            arrow_udf_sum_distances(&input.schema, &input.array, 2, ptr::null(), &mut sum)
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+        let message = unsafe { std::ffi::CStr::from_ptr(error::arrow_udf_last_error()) };
+        assert!(message.to_str().unwrap().contains("expected Int64 input"));
+    }
+
+    #[test]
+    fn cancelled_calls_fail() {
+        let input = Exported::primitive(&[1_i64, 2, 3]);
+        let cancel = std::sync::atomic::AtomicBool::new(true);
+        let options = ArrowUdfExecOptions {
+            cancel_flag: &cancel,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut sum = 0;
+        let status =
+            unsafe { arrow_udf_sum_distances(&input.schema, &input.array, 2, &options, &mut sum) };
+        assert_eq!(status, ArrowUdfStatus::Cancelled);
+    }
+
+    #[test]
+    fn sums_are_exported_as_scalars_when_the_options_ask_for_it() {
+        let input = Exported::nullable(&[Some(1_i64), None, Some(5)]);
+        let mut scalar = Exported::empty();
+        for (null_policy, expected) in [
+            (options::ARROW_UDF_NULL_POLICY_SKIP, Some(4)),
+            (options::ARROW_UDF_NULL_POLICY_PROPAGATE, None),
+        ] {
+            let options = ArrowUdfExecOptions {
+                null_policy,
+                scalar_schema: &mut scalar.schema,
+                scalar_array: &mut scalar.array,
+                ..ArrowUdfExecOptions::default()
+            };
+            let status = unsafe {
+                arrow_udf_sum_distances(&input.schema, &input.array, 2, &options, ptr::null_mut())
+            };
+            assert_eq!(status, ArrowUdfStatus::Ok);
+            assert_eq!(scalar.nullable_values::<i64>(), [expected]);
+            unsafe { scalar.array.release.unwrap()(&mut scalar.array) };
+            unsafe { scalar.schema.release.unwrap()(&mut scalar.schema) };
+        }
+    }
+}
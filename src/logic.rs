@@ -0,0 +1,377 @@
+//! Boolean logic of Boolean arrays, `and`, `or`, `xor` and `not`, to
+//! combine the masks of several filter UDFs.
+//!
+//! `and` and `or` follow the Kleene logic of SQL: a null is an unknown
+//! value, so `false and null` is false and `true or null` is true, and the
+//! result is only null when the known values don't decide it. `xor` and
+//! `not` are null when any of their inputs is.
+//!
+//! The kernels compute the values and the validity 64 rows at a time, with
+//! the words of the bitmaps, whatever the offsets of the arrays are.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::{self, Bitmap};
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogicOp {
+    And,
+    Or,
+    Xor,
+}
+
+/// The words of the values and of the validity of a Boolean array, with all
+/// the bits valid when it doesn't have nulls.
+fn words(array: &ArrowArray) -> Result<(Vec<u64>, Vec<u64>)> {
+    if array.data_type() != ArrowType::Boolean {
+        return Err(Error::UnsupportedType(format!(
+            "expected a Boolean input, got {:?}",
+            array.data_type()
+        )));
+    }
+    let values = array.boolean_values().chunks().collect();
+    let validity = match array.validity().filter(|_| array.null_count() > 0) {
+        Some(validity) => validity.chunks().collect(),
+        None => vec![u64::MAX; array.len().div_ceil(64)],
+    };
+    Ok((values, validity))
+}
+
+/// Boolean array of `len` rows with the words of `values` and `validity`.
+fn boolean_array(len: usize, values: Vec<u64>, validity: Option<Vec<u64>>) -> ArrayData {
+    let data = ArrayData::primitive(bitmap::from_chunks(len, values.into_iter()), len);
+    let Some(validity) = validity else {
+        return data;
+    };
+    let validity = bitmap::from_chunks(len, validity.into_iter());
+    let null_count = len - Bitmap::new(validity.as_slice(), 0, len).count_set();
+    data.with_validity(Some(validity), null_count)
+}
+
+fn check_nulls(arrays: &[&ArrowArray], options: &ArrowUdfExecOptions) -> Result<()> {
+    let nulls = arrays.iter().any(|array| array.null_count() > 0);
+    if options.null_policy()? == NullPolicy::Error && nulls {
+        return Err(Error::NullValue);
+    }
+    Ok(())
+}
+
+/// `a op b` for every row of two Boolean arrays of the same length, with
+/// the name of `a`.
+pub fn logic(
+    op: LogicOp,
+    a: &ArrowArray,
+    b: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if a.len() != b.len() {
+        return Err(Error::InvalidArgument(format!(
+            "arrays of different lengths: {} and {}",
+            a.len(),
+            b.len()
+        )));
+    }
+    check_nulls(&[a, b], options)?;
+    let ((a_values, a_valid), (b_values, b_valid)) = (words(a)?, words(b)?);
+    let (mut values, mut validity) = (Vec::with_capacity(a_values.len()), Vec::new());
+    let nulls = a.null_count() > 0 || b.null_count() > 0;
+    for i in 0..a_values.len() {
+        let (va, ma, vb, mb) = (a_values[i], a_valid[i], b_values[i], b_valid[i]);
+        let (value, valid) = match op {
+            // Known when both are, or when any of them is a known false.
+            LogicOp::And => (va & vb, (ma & mb) | (ma & !va) | (mb & !vb)),
+            // Known when both are, or when any of them is a known true.
+            LogicOp::Or => ((va & ma) | (vb & mb), (ma & mb) | (ma & va) | (mb & vb)),
+            LogicOp::Xor => (va ^ vb, ma & mb),
+        };
+        values.push(value);
+        if nulls {
+            validity.push(valid);
+        }
+    }
+    options.check_cancelled()?;
+    let data = boolean_array(a.len(), values, nulls.then_some(validity));
+    Ok((Schema::new(ArrowType::Boolean, &a.schema().name), data))
+}
+
+/// The negation of every row of a Boolean array.
+pub fn not(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)> {
+    check_nulls(&[array], options)?;
+    let (values, validity) = words(array)?;
+    let values = values.into_iter().map(|value| !value).collect();
+    let validity = (array.null_count() > 0).then_some(validity);
+    let data = boolean_array(array.len(), values, validity);
+    Ok((Schema::new(ArrowType::Boolean, &array.schema().name), data))
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn logic_ffi(
+    op: LogicOp,
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        let (out, data) = logic(op, &a, &b, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Kleene `a and b` of every row of two Boolean arrays of the same length:
+/// false if any of them is false, null if any of them is null otherwise.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, and `b_schema` and `b_array`, must point to
+/// valid Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_and(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    logic_ffi(
+        LogicOp::And,
+        a_schema,
+        a_array,
+        b_schema,
+        b_array,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+/// Kleene `a or b` of every row of two Boolean arrays of the same length:
+/// true if any of them is true, null if any of them is null otherwise.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_and`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_or(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    logic_ffi(
+        LogicOp::Or,
+        a_schema,
+        a_array,
+        b_schema,
+        b_array,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+/// `a xor b` of every row of two Boolean arrays of the same length, null if
+/// any of them is null.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_and`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_xor(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    logic_ffi(
+        LogicOp::Xor,
+        a_schema,
+        a_array,
+        b_schema,
+        b_array,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+/// The negation of every row of a Boolean array, with its nulls.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_not(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = not(&array, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::row::ArrowField;
+    use crate::testing::Exported;
+
+    fn booleans(values: &[Option<bool>]) -> Exported {
+        let data = bool::build(values.iter().map(Option::as_ref));
+        Exported::new(&Schema::new(ArrowType::Boolean, "x"), data)
+    }
+
+    fn run(op: LogicOp, a: &Exported, b: &Exported) -> Vec<Option<bool>> {
+        let (schema, data) = a
+            .with_array(|a| b.with_array(|b| logic(op, a, b, &ArrowUdfExecOptions::default())))
+            .unwrap();
+        Exported::new(&schema, data).booleans()
+    }
+
+    /// Kleene logic of a single row, with `None` as null.
+    fn kleene(op: LogicOp, a: Option<bool>, b: Option<bool>) -> Option<bool> {
+        match (op, a, b) {
+            (LogicOp::And, Some(false), _) | (LogicOp::And, _, Some(false)) => Some(false),
+            (LogicOp::Or, Some(true), _) | (LogicOp::Or, _, Some(true)) => Some(true),
+            (LogicOp::Xor, Some(a), Some(b)) => Some(a ^ b),
+            (LogicOp::And, Some(true), Some(true)) => Some(true),
+            (LogicOp::Or, Some(false), Some(false)) => Some(false),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn kleene_truth_tables() {
+        let values = [Some(true), Some(false), None];
+        let a: Vec<_> = values.iter().flat_map(|a| [*a; 3]).collect();
+        let b: Vec<_> = values.iter().cycle().take(9).copied().collect();
+        let (a_array, b_array) = (booleans(&a), booleans(&b));
+        for op in [LogicOp::And, LogicOp::Or, LogicOp::Xor] {
+            let expected: Vec<_> = a.iter().zip(&b).map(|(a, b)| kleene(op, *a, *b)).collect();
+            assert_eq!(run(op, &a_array, &b_array), expected, "{op:?}");
+        }
+        assert_eq!(
+            run(
+                LogicOp::And,
+                &Exported::boolean(&[true, false]),
+                &Exported::boolean(&[true, true])
+            ),
+            [Some(true), Some(false)]
+        );
+    }
+
+    #[test]
+    fn words_of_arrays_at_different_offsets() {
+        let a: Vec<_> = (0..150)
+            .map(|i| (i % 7 != 0).then_some(i % 3 == 0))
+            .collect();
+        let b: Vec<_> = (0..150)
+            .map(|i| (i % 11 != 0).then_some(i % 5 < 2))
+            .collect();
+        let (mut a_array, mut b_array) = (booleans(&a), booleans(&b));
+        a_array.array.offset = 3;
+        a_array.array.length = 130;
+        a_array.array.null_count = -1;
+        b_array.array.offset = 17;
+        b_array.array.length = 130;
+        b_array.array.null_count = -1;
+        for op in [LogicOp::And, LogicOp::Or, LogicOp::Xor] {
+            let expected: Vec<_> = a[3..133]
+                .iter()
+                .zip(&b[17..147])
+                .map(|(a, b)| kleene(op, *a, *b))
+                .collect();
+            assert_eq!(run(op, &a_array, &b_array), expected, "{op:?}");
+        }
+        let (schema, data) = a_array
+            .with_array(|array| not(array, &ArrowUdfExecOptions::default()))
+            .unwrap();
+        let negated = Exported::new(&schema, data).booleans();
+        let expected: Vec<_> = a[3..133].iter().map(|a| a.map(|a| !a)).collect();
+        assert_eq!(negated, expected);
+    }
+
+    #[test]
+    fn not_through_the_entry_point() {
+        let input = booleans(&[Some(true), None, Some(false)]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_not(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.booleans(), [Some(false), None, Some(true)]);
+        out.with_array(|array| assert_eq!(array.null_count(), 1));
+    }
+
+    #[test]
+    fn invalid_inputs_fail() {
+        let a = booleans(&[Some(true), None]);
+        let b = Exported::boolean(&[true, false, true]);
+        let integers = Exported::primitive(&[1_i32, 0]);
+        let call = |a: &Exported, b: &Exported| {
+            let mut out = Exported::empty();
+            unsafe {
+                arrow_udf_or(
+                    &a.schema,
+                    &a.array,
+                    &b.schema,
+                    &b.array,
+                    ptr::null(),
+                    &mut out.schema,
+                    &mut out.array,
+                )
+            }
+        };
+        assert_eq!(call(&a, &b), ArrowUdfStatus::InvalidArgument);
+        assert_eq!(call(&a, &integers), ArrowUdfStatus::UnsupportedType);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let failed = a.with_array(|array| not(array, &options).err());
+        assert_eq!(failed, Some(Error::NullValue));
+    }
+}
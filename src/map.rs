@@ -0,0 +1,246 @@
+//! Kernels over Map arrays, like the tags or properties of events.
+//!
+//! A Map array is a list of entries per element, stored as a struct array
+//! with the keys and the values of all the entries as its fields, and the
+//! offsets of the entries of every element. `ArrowArray::map_entry_range`
+//! gives the positions of the entries of an element in `map_keys` and
+//! `map_values`, which UDFs can read like any other array.
+
+use std::ffi::{c_char, CStr};
+
+use crate::array::ArrowArray;
+use crate::binary::Utf8Builder;
+use crate::error::{ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::ArrayData;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::utf8::utf8_ffi;
+
+/// The value of the first entry with `key` of every element of `array`, a
+/// Map array with Utf8 keys and values. Elements without the key, null
+/// elements and null values are null in the result.
+pub fn map_get(
+    array: &ArrowArray,
+    key: &str,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if array.data_type() != ArrowType::Map {
+        return Err(Error::UnsupportedType(format!(
+            "expected Map input, got {:?}",
+            array.data_type()
+        )));
+    }
+    let (keys, values) = (array.map_keys(), array.map_values());
+    if keys.data_type() != ArrowType::Utf8 || values.data_type() != ArrowType::Utf8 {
+        return Err(Error::UnsupportedType(format!(
+            "lookup in maps of {:?} keys and {:?} values",
+            keys.data_type(),
+            values.data_type()
+        )));
+    }
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let mut builder = Utf8Builder::with_capacity(array.len());
+    exec::for_each_batch(array.len(), options, |rows| {
+        for i in rows {
+            if !array.is_valid(i) {
+                builder.push(None);
+                continue;
+            }
+            let mut found = None;
+            for entry in array.map_entry_range(i) {
+                if keys.is_valid(entry) && keys.binary_value(entry) == key.as_bytes() {
+                    found = values
+                        .is_valid(entry)
+                        .then(|| values.utf8_value(entry))
+                        .transpose()?;
+                    break;
+                }
+            }
+            builder.push(found);
+        }
+        Ok(())
+    })?;
+    Ok((
+        Schema::new(ArrowType::Utf8, &array.schema().name),
+        builder.finish(),
+    ))
+}
+
+/// The value of the first entry with `key` of every element of a Map array
+/// with Utf8 keys and values, as a Utf8 array. Elements without the key,
+/// null elements and null values are null in the result.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `key` must be a valid null-terminated string, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_map_get(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    key: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let key = CStr::from_ptr(key)
+                .to_str()
+                .map_err(|_| Error::InvalidArgument("the key is not valid UTF-8".to_string()))?;
+            map_get(array, key, options)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::{validity, Exported};
+
+    fn utf8(values: &[Option<&str>]) -> ArrayData {
+        let mut builder = Utf8Builder::with_capacity(values.len());
+        values.iter().for_each(|value| builder.push(*value));
+        builder.finish()
+    }
+
+    /// `{"a": "1", "b": null}`, null and `{"b": "2", "a": "3"}`.
+    fn tags() -> Exported {
+        let schema = Schema::map(
+            "tags",
+            Schema::new(ArrowType::Utf8, "key"),
+            Schema::new(ArrowType::Utf8, "value"),
+        );
+        let keys = utf8(&[Some("a"), Some("b"), Some("b"), Some("a")]);
+        let values = utf8(&[Some("1"), None, Some("2"), Some("3")]);
+        let offsets = Buffer::from_slice(&[0_i32, 2, 2, 4]);
+        let data = ArrayData::map_array(offsets, keys, values, 3)
+            .with_validity(Some(validity(&[Some(()), None, Some(())])), 1);
+        Exported::new(&schema, data)
+    }
+
+    fn map_get_ffi(
+        input: &Exported,
+        key: &str,
+        options: *const ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let key = CString::new(key).unwrap();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_map_get(
+                &input.schema,
+                &input.array,
+                key.as_ptr(),
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn strings(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|value| value.map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn entries_of_every_element() {
+        let mut input = tags();
+        input.with_array(|array| {
+            assert_eq!(array.map_offsets(), [0, 2, 2, 4]);
+            assert_eq!(array.map_entry_range(0), 0..2);
+            assert_eq!(array.map_entry_range(1), 2..2);
+            assert_eq!(array.map_keys().utf8_value(3).unwrap(), "a");
+            assert!(!array.map_values().is_valid(1));
+        });
+        (input.array.offset, input.array.length) = (2, 1);
+        input.with_array(|array| assert_eq!(array.map_entry_range(0), 2..4));
+    }
+
+    #[test]
+    fn values_are_looked_up_by_key() {
+        let input = tags();
+        let out = map_get_ffi(&input, "a", ptr::null()).unwrap();
+        assert_eq!(out.strings(), strings(&[Some("1"), None, Some("3")]));
+        // Null values and missing keys are null too.
+        let out = map_get_ffi(&input, "b", ptr::null()).unwrap();
+        assert_eq!(out.strings(), strings(&[None, None, Some("2")]));
+        let out = map_get_ffi(&input, "c", ptr::null()).unwrap();
+        assert_eq!(out.strings(), strings(&[None, None, None]));
+        out.with_array(|array| assert_eq!(array.schema().name, "tags"));
+    }
+
+    #[test]
+    fn null_maps_fail_with_the_error_policy() {
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..Default::default()
+        };
+        assert_eq!(
+            map_get_ffi(&tags(), "a", &options).err(),
+            Some(ArrowUdfStatus::NullValue)
+        );
+    }
+
+    #[test]
+    fn only_maps_of_strings_are_supported() {
+        let input = Exported::utf8(&[Some("a")]);
+        assert_eq!(
+            map_get_ffi(&input, "a", ptr::null()).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let schema = Schema::map(
+            "m",
+            Schema::new(ArrowType::Utf8, "key"),
+            Schema::new(ArrowType::Int64, "value"),
+        );
+        let values = ArrayData::primitive(Buffer::from_slice(&[1_i64]), 1);
+        let offsets = Buffer::from_slice(&[0_i32, 1]);
+        let data = ArrayData::map_array(offsets, utf8(&[Some("a")]), values, 1);
+        let input = Exported::new(&schema, data);
+        assert_eq!(
+            map_get_ffi(&input, "a", ptr::null()).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+
+    #[test]
+    fn maps_need_a_struct_of_keys_and_values() {
+        let mut schema = Schema::map(
+            "m",
+            Schema::new(ArrowType::Utf8, "key"),
+            Schema::new(ArrowType::Utf8, "value"),
+        );
+        let mut exported = crate::export::export_schema(&schema);
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert_eq!(imported, Ok(schema.clone()));
+
+        schema.children[0].children.pop();
+        let mut exported = crate::export::export_schema(&schema);
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert!(matches!(imported, Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,239 @@
+//! Cache of the results of previous calls, keyed by the identity of the
+//! inputs.
+//!
+//! Engines often evaluate the same projection again over the same data, for
+//! example when retrying a pipeline. When the host sets the
+//! `ARROW_UDF_FLAG_MEMOIZE` flag and provides a context, results are cached
+//! in the context, and an identical call returns the cached array without
+//! computing it again. Calls are identical when they have the same inputs
+//! and the same options changing their result, like the null policy and the
+//! flags.
+//!
+//! Inputs are identified by the address of their buffers, not by their
+//! content. Hosts must only enable the cache when buffers are not modified
+//! or reused for different data while the context lives, or clear it with
+//! `arrow_udf_cache_clear` when they are.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::array::ArrowArray;
+use crate::context::UdfContext;
+use crate::error::Result;
+use crate::export::ArrayData;
+use crate::ffi::ArrowCDataInterfaceArray;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+
+/// Host flag enabling the cache of results in the context.
+pub const ARROW_UDF_FLAG_MEMOIZE: u32 = 2;
+
+/// Number of results kept by the cache of a context. When it's full, the
+/// oldest result is evicted.
+pub const CACHE_CAPACITY: usize = 64;
+
+/// Identity of a call: the function, its scalar arguments, the options
+/// changing its result, and the location of the input data.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    function: &'static str,
+    args: Vec<u64>,
+    /// The flags, other than `ARROW_UDF_FLAG_MEMOIZE`.
+    flags: u32,
+    null_policy: i32,
+    nan_policy: i32,
+    seed: u64,
+    partition_id: u64,
+    format: String,
+    length: i64,
+    offset: i64,
+    buffers: Vec<usize>,
+}
+
+impl CacheKey {
+    /// Key of calling `function` over `array` with `options`, with the
+    /// scalar arguments `args` represented by their bits.
+    ///
+    /// The options used by the functions to compute their result are part
+    /// of the key, so calls with a different null policy, different flags or
+    /// a different seed don't share their results. The ones only changing
+    /// how the result is computed, like the number of threads, aren't.
+    pub fn new(
+        function: &'static str,
+        array: &ArrowArray,
+        args: &[u64],
+        options: &ArrowUdfExecOptions,
+    ) -> CacheKey {
+        let mut buffers = Vec::new();
+        collect_buffers(array.ffi(), &mut buffers);
+        CacheKey {
+            function,
+            args: args.to_vec(),
+            flags: options.flags & !ARROW_UDF_FLAG_MEMOIZE,
+            null_policy: options.null_policy,
+            nan_policy: options.nan_policy,
+            seed: options.seed,
+            partition_id: options.partition_id,
+            format: array.schema().format.clone(),
+            length: array.ffi().length,
+            offset: array.ffi().offset,
+            buffers,
+        }
+    }
+}
+
+fn collect_buffers(array: &ArrowCDataInterfaceArray, buffers: &mut Vec<usize>) {
+    for i in 0..array.n_buffers as usize {
+        buffers.push(unsafe { array.buffer(i) } as usize);
+    }
+    for i in 0..array.n_children as usize {
+        let child = unsafe { array.child(i) };
+        buffers.push(child.length as usize);
+        buffers.push(child.offset as usize);
+        collect_buffers(child, buffers);
+    }
+}
+
+/// Result of a call, shared by the cache and the exported arrays.
+pub type CachedResult = (Schema, Arc<ArrayData>);
+
+/// Results stored in a context, from the oldest to the newest.
+#[derive(Default)]
+struct Cache {
+    entries: Mutex<VecDeque<(CacheKey, CachedResult)>>,
+}
+
+/// Return the cached result of the call identified by `key`, or compute it
+/// and cache it, if the options enable the cache. Calls with an output
+/// buffer aren't cached, since the host reuses its memory.
+pub fn cached<F>(options: &ArrowUdfExecOptions, key: CacheKey, compute: F) -> Result<CachedResult>
+where
+    F: FnOnce() -> Result<(Schema, ArrayData)>,
+{
+    let context = options
+        .context()
+        .filter(|_| options.flags & ARROW_UDF_FLAG_MEMOIZE != 0)
+        .filter(|_| options.output_buffer.is_null());
+    let Some(context) = context else {
+        let (schema, data) = compute()?;
+        return Ok((schema, Arc::new(data)));
+    };
+    let cache = context.get_or_insert_with(Cache::default);
+    if let Some((_, result)) = cache
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(cached_key, _)| *cached_key == key)
+    {
+        return Ok(result.clone());
+    }
+    let (schema, data) = compute()?;
+    let result = (schema, Arc::new(data));
+    let mut entries = cache.entries.lock().unwrap();
+    if entries.len() == CACHE_CAPACITY {
+        entries.pop_front();
+    }
+    entries.push_back((key, result.clone()));
+    Ok(result)
+}
+
+/// Remove all the results cached in `context`.
+///
+/// # Safety
+///
+/// `context` must be a context created with `arrow_udf_context_create`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_cache_clear(context: *const UdfContext) {
+    if let Some(context) = context.as_ref() {
+        context.remove::<Cache>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::options::{ARROW_UDF_NAN_POLICY_SKIP, ARROW_UDF_NULL_POLICY_ERROR};
+    use crate::schema::ArrowType;
+    use crate::testing::Exported;
+
+    #[test]
+    fn options_changing_the_result_are_part_of_the_key() {
+        let input = Exported::primitive(&[1_i64, 2]);
+        input.with_array(|array| {
+            let options = ArrowUdfExecOptions::default();
+            let key = |options: &ArrowUdfExecOptions| CacheKey::new("f", array, &[1], options);
+            let memoize = ArrowUdfExecOptions {
+                flags: ARROW_UDF_FLAG_MEMOIZE,
+                num_threads: 3,
+                ..options
+            };
+            assert_eq!(key(&options), key(&memoize));
+            let null_policy = ArrowUdfExecOptions {
+                null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+                ..options
+            };
+            assert_ne!(key(&options), key(&null_policy));
+            let nan_policy = ArrowUdfExecOptions {
+                nan_policy: ARROW_UDF_NAN_POLICY_SKIP,
+                ..options
+            };
+            assert_ne!(key(&options), key(&nan_policy));
+            let flags = ArrowUdfExecOptions {
+                flags: ARROW_UDF_FLAG_MEMOIZE | 1,
+                ..options
+            };
+            assert_ne!(key(&memoize), key(&flags));
+            let seed = ArrowUdfExecOptions { seed: 7, ..options };
+            assert_ne!(key(&options), key(&seed));
+            let partition = ArrowUdfExecOptions {
+                partition_id: 1,
+                ..seed
+            };
+            assert_ne!(key(&seed), key(&partition));
+            assert_ne!(key(&options), CacheKey::new("f", array, &[2], &options));
+        });
+    }
+
+    #[test]
+    fn identical_calls_are_computed_once() {
+        let context = UdfContext::new();
+        let options = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_MEMOIZE,
+            context: &context,
+            ..ArrowUdfExecOptions::default()
+        };
+        let input = Exported::primitive(&[1_i64, 2]);
+        let calls = AtomicUsize::new(0);
+        let call = |options: &ArrowUdfExecOptions| {
+            input.with_array(|array| {
+                let key = CacheKey::new("f", array, &[], options);
+                cached(options, key, || {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    let data = ArrayData::primitive(Buffer::from_slice(&[3_i64]), 1);
+                    Ok((Schema::new(ArrowType::Int64, "y"), data))
+                })
+                .unwrap()
+            })
+        };
+        let (_, first) = call(&options);
+        let (_, second) = call(&options);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        unsafe { arrow_udf_cache_clear(&context) };
+        call(&options);
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        call(&ArrowUdfExecOptions {
+            flags: 0,
+            ..options
+        });
+        call(&ArrowUdfExecOptions {
+            flags: 0,
+            ..options
+        });
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+}
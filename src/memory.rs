@@ -0,0 +1,168 @@
+//! Accounting of the memory used by every call.
+//!
+//! `ffi_guard` runs every entry point with a new `MemoryTracker`, which is
+//! also used by the threads of its kernels. Buffers, the chunks of scratch
+//! arenas and the hash tables of group by record their size in the tracker
+//! of the call allocating them with a `Reservation`, and release it when
+//! they are freed, even after the call returns. Kernels check the peak of
+//! the memory used by the call against the `memory_limit` of the execution
+//! options after every batch, like the cancellation flag.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Bytes used by a call.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    in_use: AtomicUsize,
+    peak: AtomicUsize,
+    allocated: AtomicUsize,
+}
+
+impl MemoryTracker {
+    /// Bytes currently in use, including outputs not released by the host.
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    /// Maximum of the bytes in use at the same time.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Total of the bytes allocated, whether they are freed or not.
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    fn grow(&self, bytes: usize) {
+        let in_use = self.in_use.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(in_use, Ordering::Relaxed);
+        self.allocated.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn shrink(&self, bytes: usize) {
+        self.in_use.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<MemoryTracker>>> = const { RefCell::new(None) };
+}
+
+/// The tracker of the call running in the current thread, if any.
+pub fn current() -> Option<Arc<MemoryTracker>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Run `f` with `tracker` as the tracker of the current thread, restoring
+/// the previous one after it, also if it panics.
+pub(crate) fn with_tracker<R>(tracker: Option<Arc<MemoryTracker>>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Arc<MemoryTracker>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.replace(tracker)));
+    f()
+}
+
+/// Bytes recorded in the tracker of the call that created the reservation,
+/// released when it's dropped.
+#[derive(Default)]
+pub(crate) struct Reservation {
+    tracker: Option<Arc<MemoryTracker>>,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Reserve `bytes` in the tracker of the current thread.
+    pub(crate) fn new(bytes: usize) -> Reservation {
+        let mut reservation = Reservation {
+            tracker: current(),
+            bytes: 0,
+        };
+        reservation.resize(bytes);
+        reservation
+    }
+
+    /// Change the reserved bytes to `bytes`.
+    pub(crate) fn resize(&mut self, bytes: usize) {
+        if let Some(tracker) = &self.tracker {
+            if bytes > self.bytes {
+                tracker.grow(bytes - self.bytes);
+            } else {
+                tracker.shrink(self.bytes - bytes);
+            }
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.resize(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::error::{ffi_guard, ArrowUdfStatus, Error};
+    use crate::options::ArrowUdfExecOptions;
+
+    #[test]
+    fn reservations_are_tracked_until_dropped() {
+        let tracker = Arc::new(MemoryTracker::default());
+        let (buffer, mut reservation) = with_tracker(Some(tracker.clone()), || {
+            (Buffer::with_capacity(100), Reservation::new(1000))
+        });
+        // Buffers reserve their capacity, rounded to the alignment.
+        assert_eq!(tracker.in_use(), 1128);
+        reservation.resize(10);
+        assert_eq!(tracker.in_use(), 138);
+        drop(buffer);
+        drop(reservation);
+        assert_eq!(
+            (tracker.in_use(), tracker.peak(), tracker.allocated()),
+            (0, 1128, 1128)
+        );
+        // Outside of a call, nothing is tracked.
+        assert!(current().is_none());
+        drop(Reservation::new(10));
+    }
+
+    #[test]
+    fn calls_over_the_memory_limit_fail() {
+        let options = ArrowUdfExecOptions {
+            memory_limit: 1000,
+            ..ArrowUdfExecOptions::default()
+        };
+        let status = ffi_guard(|| {
+            let _small = Buffer::with_capacity(100);
+            options.check_memory()?;
+            let _large = Buffer::with_capacity(2000);
+            options.check_memory()
+        });
+        assert_eq!(status, ArrowUdfStatus::OutOfBudget);
+        // The peak counts, even if the memory is freed before the check.
+        let status = ffi_guard(|| {
+            drop(Buffer::with_capacity(2000));
+            options.check_memory()
+        });
+        assert_eq!(status, ArrowUdfStatus::OutOfBudget);
+        let unlimited = ArrowUdfExecOptions::default();
+        let status = ffi_guard(|| {
+            drop(Buffer::with_capacity(2000));
+            unlimited.check_memory()
+        });
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(options.check_memory(), Ok(()));
+        assert_eq!(Error::OutOfBudget.status(), ArrowUdfStatus::OutOfBudget);
+    }
+}
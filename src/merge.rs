@@ -0,0 +1,529 @@
+//! K-way merge of streams sorted by the same keys.
+//!
+//! Hosts that partition their inputs, and kernels writing sorted runs, end
+//! up with several streams of record batches sorted by the same fields.
+//! `arrow_udf_merge_streams` merges them into a single stream sorted by
+//! those fields, with the keys and the order of `arrow_udf_sort_indices_by`.
+//! Rows with equal keys are emitted in the order of the streams, so merging
+//! the sorted parts of an input gives the same rows as sorting it.
+//!
+//! The merge is lazy: it keeps the current batch of every input, and reads
+//! the next one when all its rows are emitted. Every output row is the
+//! smallest of the next rows of the inputs, found by comparing all of them,
+//! which is fast for the handful of streams hosts usually merge. The output
+//! batches are built by interleaving the rows of the input batches.
+
+use std::cmp::Ordering;
+
+use crate::array::ArrowArray;
+use crate::concat;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::ArrayData;
+use crate::ffi::ArrowCDataInterfaceArrayStream;
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::sort::ArrowUdfSortKey;
+use crate::stream::{self, ArraySource, ArrayStream, ImportedArray};
+use crate::types::{with_native_type, NativeType, TotalOrd};
+
+/// Sorted input of a merge: a C stream, or the sorted runs a kernel wrote
+/// to spill files.
+pub(crate) trait SortedInput {
+    fn schema(&self) -> &Schema;
+
+    /// The next array, or `None` at the end of the input.
+    fn next_array(&mut self) -> Result<Option<ImportedArray>>;
+}
+
+impl SortedInput for ArrayStream {
+    fn schema(&self) -> &Schema {
+        ArrayStream::schema(self)
+    }
+
+    fn next_array(&mut self) -> Result<Option<ImportedArray>> {
+        ArrayStream::next_array(self)
+    }
+}
+
+struct Input {
+    stream: Box<dyn SortedInput>,
+    /// The batch being merged, as an index of the batches of the merge.
+    batch: Option<usize>,
+    /// The next row of the batch.
+    row: usize,
+    finished: bool,
+}
+
+/// The merge of several sorted streams, producing the arrays of the merged
+/// stream.
+pub struct MergeStreams {
+    inputs: Vec<Input>,
+    /// The batches with rows in the output batch being built, and the
+    /// current batch of every input, with the input they come from.
+    batches: Vec<Option<(usize, ImportedArray)>>,
+    keys: Vec<ArrowUdfSortKey>,
+    options: ArrowUdfExecOptions,
+}
+
+/// Whether arrays of the two schemas have the same layout, with the same
+/// formats and fields by position.
+pub(crate) fn same_layout(a: &Schema, b: &Schema) -> bool {
+    a.format == b.format
+        && a.children.len() == b.children.len()
+        && a.children
+            .iter()
+            .zip(&b.children)
+            .all(|(a, b)| same_layout(a, b))
+}
+
+impl MergeStreams {
+    /// Merge `streams`, all of them of struct arrays with the same fields,
+    /// sorted by `keys`. Arrays are emitted with the `batch_size` of the
+    /// options.
+    pub fn new(
+        streams: Vec<ArrayStream>,
+        keys: &[ArrowUdfSortKey],
+        options: &ArrowUdfExecOptions,
+    ) -> Result<MergeStreams> {
+        let streams = streams
+            .into_iter()
+            .map(|stream| Box::new(stream) as Box<dyn SortedInput>)
+            .collect();
+        MergeStreams::from_inputs(streams, keys, options)
+    }
+
+    /// Merge `streams` like `new`, for inputs other than C streams.
+    pub(crate) fn from_inputs(
+        streams: Vec<Box<dyn SortedInput>>,
+        keys: &[ArrowUdfSortKey],
+        options: &ArrowUdfExecOptions,
+    ) -> Result<MergeStreams> {
+        let Some(first) = streams.first() else {
+            return Err(Error::InvalidArgument("merge of no streams".to_string()));
+        };
+        let schema = first.schema();
+        if schema.data_type != ArrowType::Struct {
+            return Err(Error::UnsupportedType(format!(
+                "expected streams of struct arrays to merge, got {:?}",
+                schema.data_type
+            )));
+        }
+        if let Some(i) = streams
+            .iter()
+            .position(|stream| !same_layout(stream.schema(), schema))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "stream {i} has different fields than the first stream"
+            )));
+        }
+        if keys.is_empty() {
+            return Err(Error::InvalidArgument("merge without keys".to_string()));
+        }
+        for key in keys {
+            let Some(field) = usize::try_from(key.column)
+                .ok()
+                .and_then(|column| schema.children.get(column))
+            else {
+                return Err(Error::InvalidArgument(format!(
+                    "sort key {} out of bounds for a struct of {} fields",
+                    key.column,
+                    schema.children.len()
+                )));
+            };
+            let native = with_native_type!(
+                field.data_type,
+                T => T::ARROW_TYPE == field.data_type,
+                _ => false
+            );
+            if !native {
+                return Err(Error::UnsupportedType(format!(
+                    "merge by {:?} keys",
+                    field.data_type
+                )));
+            }
+        }
+        options.null_policy()?;
+        Ok(MergeStreams {
+            inputs: streams
+                .into_iter()
+                .map(|stream| Input {
+                    stream,
+                    batch: None,
+                    row: 0,
+                    finished: false,
+                })
+                .collect(),
+            batches: Vec::new(),
+            keys: keys.to_vec(),
+            options: *options,
+        })
+    }
+
+    fn batch(&self, batch: usize) -> ArrowArray<'_> {
+        let (input, batch) = self.batches[batch].as_ref().unwrap();
+        batch.view(self.inputs[*input].stream.schema())
+    }
+
+    /// The current batch of `input`.
+    fn view(&self, input: usize) -> ArrowArray<'_> {
+        self.batch(self.inputs[input].batch.unwrap())
+    }
+
+    /// Make sure `input` has a next row, reading its next batch if needed.
+    /// False if the stream has no more rows.
+    fn fill(&mut self, input: usize) -> Result<bool> {
+        loop {
+            let current = &self.inputs[input];
+            if current.finished {
+                return Ok(false);
+            }
+            if current.batch.is_some() && current.row < self.view(input).len() {
+                return Ok(true);
+            }
+            let Some(batch) = self.inputs[input].stream.next_array()? else {
+                self.inputs[input].finished = true;
+                return Ok(false);
+            };
+            self.check_nulls(&batch.view(self.inputs[input].stream.schema()))?;
+            self.batches.push(Some((input, batch)));
+            self.inputs[input].batch = Some(self.batches.len() - 1);
+            self.inputs[input].row = 0;
+        }
+    }
+
+    fn check_nulls(&self, batch: &ArrowArray) -> Result<()> {
+        if self.options.null_policy()? != NullPolicy::Error {
+            return Ok(());
+        }
+        let nulls = batch.null_count() > 0
+            || self.keys.iter().any(|key| {
+                let column = batch.child(key.column as usize);
+                (0..batch.len()).any(|i| !column.is_valid(batch.offset() + i))
+            });
+        match nulls {
+            true => Err(Error::NullValue),
+            false => Ok(()),
+        }
+    }
+
+    /// Compare the next rows of the inputs `a` and `b` by the keys.
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        let (batch_a, batch_b) = (self.view(a), self.view(b));
+        let (row_a, row_b) = (self.inputs[a].row, self.inputs[b].row);
+        for key in &self.keys {
+            let (column_a, column_b) = (
+                batch_a.child(key.column as usize),
+                batch_b.child(key.column as usize),
+            );
+            let (i, j) = (batch_a.offset() + row_a, batch_b.offset() + row_b);
+            let valid_a = batch_a.is_valid(row_a) && column_a.is_valid(i);
+            let valid_b = batch_b.is_valid(row_b) && column_b.is_valid(j);
+            let ordering = match (valid_a, valid_b) {
+                (true, true) => {
+                    let ordering = with_native_type!(column_a.data_type(), T => {
+                        column_a.values::<T>()[i].total_cmp(&column_b.values::<T>()[j])
+                    }, _ => unreachable!("validated key type"));
+                    match key.descending {
+                        true => ordering.reverse(),
+                        false => ordering,
+                    }
+                }
+                (false, false) => Ordering::Equal,
+                (false, true) if key.nulls_first => Ordering::Less,
+                (true, false) if key.nulls_first => Ordering::Greater,
+                (false, true) => Ordering::Greater,
+                (true, false) => Ordering::Less,
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl ArraySource for MergeStreams {
+    fn schema(&self) -> &Schema {
+        self.inputs[0].stream.schema()
+    }
+
+    fn next(&mut self) -> Result<Option<ArrayData>> {
+        let batch_len = self.options.batch_len(usize::MAX);
+        let mut indices = Vec::new();
+        while indices.len() < batch_len {
+            let mut smallest: Option<usize> = None;
+            for input in 0..self.inputs.len() {
+                if !self.fill(input)? {
+                    continue;
+                }
+                smallest = match smallest {
+                    Some(smallest) if self.compare(smallest, input).is_le() => Some(smallest),
+                    _ => Some(input),
+                };
+            }
+            let Some(smallest) = smallest else {
+                break;
+            };
+            let input = &mut self.inputs[smallest];
+            indices.push((input.batch.unwrap(), input.row));
+            input.row += 1;
+        }
+        self.options.check_cancelled()?;
+        if indices.is_empty() {
+            return Ok(None);
+        }
+
+        let batches: Vec<ArrowArray> = (0..self.batches.len()).map(|i| self.batch(i)).collect();
+        let data = concat::interleave(&batches, &indices)?;
+        drop(batches);
+        // Keep only the current batches, which can have rows left.
+        let mut current = Vec::new();
+        for input in &mut self.inputs {
+            if let Some(batch) = input.batch.as_mut() {
+                current.push(self.batches[*batch].take());
+                *batch = current.len() - 1;
+            }
+        }
+        self.batches = current;
+        self.options.check_memory()?;
+        Ok(Some(data))
+    }
+}
+
+/// Merge the `n_streams` streams of struct arrays in `streams`, all of them
+/// with the same fields and sorted by the `n_keys` fields in `keys`, into a
+/// single stream sorted by them, written into `out`. Rows with equal keys
+/// are emitted in the order of the streams. The arrays of the output have
+/// `batch_size` rows, except the last one.
+///
+/// The input streams are moved into the output stream, which reads them
+/// while it's consumed, and releases them when it's released, or when the
+/// call fails.
+///
+/// # Safety
+///
+/// `streams` must point to `n_streams` valid C Stream Interface streams and
+/// `keys` to `n_keys` sort keys. `options` must be null or valid, and stay
+/// valid while the output stream is used, and `out` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_merge_streams(
+    n_streams: i64,
+    streams: *const *mut ArrowCDataInterfaceArrayStream,
+    n_keys: i64,
+    keys: *const ArrowUdfSortKey,
+    options: *const ArrowUdfExecOptions,
+    out: *mut ArrowCDataInterfaceArrayStream,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        if n_streams < 0 || n_keys < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "the number of streams and keys must be zero or positive, got {n_streams} and \
+                 {n_keys}"
+            )));
+        }
+        // Every stream is moved, also when another one fails.
+        let streams: Vec<Result<ArrayStream>> = (0..n_streams as usize)
+            .map(|i| ArrayStream::move_from_ffi(*streams.add(i)))
+            .collect();
+        let streams = streams.into_iter().collect::<Result<Vec<_>>>()?;
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let keys = match n_keys {
+            0 => &[],
+            n => std::slice::from_raw_parts(keys, n as usize),
+        };
+        let merge = MergeStreams::new(streams, keys, &options)?;
+        stream::export_stream(Box::new(merge), out);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, nullable_data, Exported};
+
+    fn schema() -> Schema {
+        Schema::new(ArrowType::Struct, "batch").with_children(vec![
+            Schema::new(ArrowType::Int64, "k"),
+            Schema::new(ArrowType::Int32, "v"),
+        ])
+    }
+
+    /// Stream of struct batches with the keys and values of every batch.
+    fn input(batches: &[(&[Option<i64>], &[i32])]) -> ArrowCDataInterfaceArrayStream {
+        let batches = batches
+            .iter()
+            .map(|(keys, values)| {
+                let values: Vec<_> = values.iter().map(|v| Some(*v)).collect();
+                let children = vec![nullable_data(keys), nullable_data(&values)];
+                Exported::new(&schema(), ArrayData::struct_array(children, keys.len()))
+            })
+            .collect();
+        testing::stream(&schema(), batches, None)
+    }
+
+    fn merge(
+        inputs: &mut [ArrowCDataInterfaceArrayStream],
+        keys: &[ArrowUdfSortKey],
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<ArrowCDataInterfaceArrayStream, ArrowUdfStatus> {
+        let streams: Vec<_> = inputs.iter_mut().map(|input| input as *mut _).collect();
+        let mut out = std::mem::MaybeUninit::uninit();
+        let status = unsafe {
+            arrow_udf_merge_streams(
+                streams.len() as i64,
+                streams.as_ptr(),
+                keys.len() as i64,
+                keys.as_ptr(),
+                options,
+                out.as_mut_ptr(),
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(unsafe { out.assume_init() }),
+            status => Err(status),
+        }
+    }
+
+    /// Key and value of a row.
+    type Row = (Option<i64>, i32);
+
+    /// The keys and values of the merged stream, and the length of its
+    /// batches.
+    fn rows(mut out: ArrowCDataInterfaceArrayStream) -> Result<(Vec<Row>, Vec<usize>)> {
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut out) }?;
+        let (mut rows, mut lens) = (Vec::new(), Vec::new());
+        stream.for_each(|batch| {
+            lens.push(batch.len());
+            let (keys, values) = (batch.child(0), batch.child(1));
+            for i in 0..batch.len() {
+                let key = keys.is_valid(i).then(|| keys.values::<i64>()[i]);
+                rows.push((key, values.values::<i32>()[i]));
+            }
+            Ok(())
+        })?;
+        Ok((rows, lens))
+    }
+
+    fn key(descending: bool, nulls_first: bool) -> ArrowUdfSortKey {
+        ArrowUdfSortKey {
+            column: 0,
+            descending,
+            nulls_first,
+        }
+    }
+
+    #[test]
+    fn sorted_streams_are_merged_through_ffi() {
+        let mut inputs = [
+            input(&[
+                (&[Some(1), Some(4)], &[0, 1]),
+                (&[Some(4), Some(9)], &[2, 3]),
+            ]),
+            input(&[(&[], &[]), (&[Some(2), Some(4), None], &[10, 11, 12])]),
+            input(&[(&[Some(0), Some(4), Some(10)], &[20, 21, 22])]),
+        ];
+        let options = ArrowUdfExecOptions {
+            batch_size: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = merge(&mut inputs, &[key(false, false)], &options).unwrap();
+        assert!(inputs.iter().all(|input| input.release.is_none()));
+        let (rows, lens) = rows(out).unwrap();
+        // Equal keys keep the order of the streams.
+        assert_eq!(
+            rows,
+            [
+                (Some(0), 20),
+                (Some(1), 0),
+                (Some(2), 10),
+                (Some(4), 1),
+                (Some(4), 2),
+                (Some(4), 11),
+                (Some(4), 21),
+                (Some(9), 3),
+                (Some(10), 22),
+                (None, 12),
+            ]
+        );
+        assert_eq!(lens, [4, 4, 2]);
+    }
+
+    #[test]
+    fn descending_keys_with_nulls_first() {
+        let mut inputs = [
+            input(&[(&[None, Some(3), Some(1)], &[0, 1, 2])]),
+            input(&[(&[None, Some(2)], &[10, 11])]),
+        ];
+        let options = ArrowUdfExecOptions::default();
+        let out = merge(&mut inputs, &[key(true, true)], &options).unwrap();
+        let (rows, _) = rows(out).unwrap();
+        assert_eq!(
+            rows,
+            [
+                (None, 0),
+                (None, 10),
+                (Some(3), 1),
+                (Some(2), 11),
+                (Some(1), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn nulls_fail_with_the_error_policy() {
+        let mut inputs = [input(&[(&[Some(1)], &[0]), (&[None], &[1])])];
+        let options = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = merge(&mut inputs, &[key(false, false)], &options).unwrap();
+        assert_eq!(
+            rows(out).err(),
+            Some(Error::InvalidArgument(
+                "the stream failed: null value found with the error null policy".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_merges_fail() {
+        let options = ArrowUdfExecOptions::default();
+        let keys = [key(false, false)];
+        assert_eq!(
+            merge(&mut [], &keys, &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let mut inputs = [input(&[])];
+        assert_eq!(
+            merge(&mut inputs, &[], &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert!(inputs[0].release.is_none());
+        let out_of_bounds = ArrowUdfSortKey {
+            column: 2,
+            ..key(false, false)
+        };
+        assert_eq!(
+            merge(&mut [input(&[])], &[out_of_bounds], &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let ints = Schema::new(ArrowType::Int64, "k");
+        let mut inputs = [input(&[]), testing::stream(&ints, Vec::new(), None)];
+        assert_eq!(
+            merge(&mut inputs, &keys, &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert!(inputs.iter().all(|input| input.release.is_none()));
+        assert_eq!(
+            merge(
+                &mut [testing::stream(&ints, Vec::new(), None)],
+                &keys,
+                &options
+            )
+            .err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+}
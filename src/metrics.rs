@@ -0,0 +1,221 @@
+//! Metrics of every call, returned to the host.
+//!
+//! Hosts pointing the `metrics` execution option to an `ArrowUdfMetrics`
+//! get it filled when the call returns, also when it fails, to show the cost
+//! of the functions in their query plans. `ffi_guard` runs every entry point
+//! with new `CallMetrics`, shared by the threads of its kernels like the
+//! memory tracker, where the kernels record the rows they process, and
+//! whether they used SIMD instructions. The wall time and the allocated
+//! bytes are given by `ffi_guard` and the memory tracker.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Metrics of a call, written into the `metrics` of the execution options.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArrowUdfMetrics {
+    /// Wall time of the call, in microseconds.
+    pub wall_micros: i64,
+    /// Rows processed by the kernels of the call. Kernels reading their
+    /// input more than once count its rows every time.
+    pub rows: i64,
+    /// Nulls in the input arrays of the kernels.
+    pub nulls: i64,
+    /// Bytes allocated by the call, whether they are freed or not when it
+    /// returns.
+    pub bytes_allocated: i64,
+    /// Whether a kernel used SIMD instructions chosen at runtime for the
+    /// processor, like the FMA instructions of `arrow_udf_fma`.
+    pub simd_used: bool,
+}
+
+/// Counters of a call, shared by all its threads.
+#[derive(Debug, Default)]
+pub struct CallMetrics {
+    rows: AtomicUsize,
+    nulls: AtomicUsize,
+    simd_used: AtomicBool,
+    /// Where the host wants the metrics, if anywhere.
+    out: AtomicPtr<ArrowUdfMetrics>,
+}
+
+impl CallMetrics {
+    /// The metrics of the call, after running for `wall` and allocating
+    /// `bytes_allocated` bytes.
+    pub fn snapshot(&self, wall: Duration, bytes_allocated: usize) -> ArrowUdfMetrics {
+        ArrowUdfMetrics {
+            wall_micros: wall.as_micros() as i64,
+            rows: self.rows.load(Ordering::Relaxed) as i64,
+            nulls: self.nulls.load(Ordering::Relaxed) as i64,
+            bytes_allocated: bytes_allocated as i64,
+            simd_used: self.simd_used.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Write `metrics` where the host asked for them, if it did.
+    pub(crate) fn write(&self, metrics: ArrowUdfMetrics) {
+        let out = self.out.load(Ordering::Relaxed);
+        if !out.is_null() {
+            unsafe { out.write(metrics) };
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<CallMetrics>>> = const { RefCell::new(None) };
+}
+
+/// The metrics of the call running in the current thread, if any.
+pub fn current() -> Option<Arc<CallMetrics>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Run `f` with `metrics` as the metrics of the current thread, restoring
+/// the previous ones after it, also if it panics.
+pub(crate) fn with_metrics<R>(metrics: Option<Arc<CallMetrics>>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Arc<CallMetrics>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.replace(metrics)));
+    f()
+}
+
+fn record(f: impl FnOnce(&CallMetrics)) {
+    CURRENT.with(|current| {
+        if let Some(metrics) = current.borrow().as_deref() {
+            f(metrics)
+        }
+    })
+}
+
+/// Request the metrics of the current call to be written into `out`, when
+/// it's not null.
+pub(crate) fn set_output(out: *mut ArrowUdfMetrics) {
+    record(|metrics| metrics.out.store(out, Ordering::Relaxed));
+}
+
+/// Record `rows` processed by a kernel of the current call.
+pub(crate) fn record_rows(rows: usize) {
+    record(|metrics| {
+        metrics.rows.fetch_add(rows, Ordering::Relaxed);
+    });
+}
+
+/// Record `nulls` found in an input of the current call.
+pub(crate) fn record_nulls(nulls: usize) {
+    record(|metrics| {
+        metrics.nulls.fetch_add(nulls, Ordering::Relaxed);
+    });
+}
+
+/// Record that the current call used SIMD instructions.
+pub(crate) fn record_simd() {
+    record(|metrics| metrics.simd_used.store(true, Ordering::Relaxed));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::error::ArrowUdfStatus;
+    use crate::fma::arrow_udf_fma;
+    use crate::options::{ArrowUdfExecOptions, ARROW_UDF_NULL_POLICY_ERROR};
+    use crate::testing::Exported;
+
+    fn fma_metrics(options: ArrowUdfExecOptions) -> (ArrowUdfStatus, ArrowUdfMetrics) {
+        let values: Vec<_> = (0..1000)
+            .map(|i| (i % 100 != 0).then_some(i as f64))
+            .collect();
+        let (a, b) = (
+            Exported::nullable(&values),
+            Exported::primitive(&[2.0; 1000]),
+        );
+        let mut metrics = ArrowUdfMetrics {
+            rows: -1,
+            ..ArrowUdfMetrics::default()
+        };
+        let options = ArrowUdfExecOptions {
+            metrics: &mut metrics,
+            ..options
+        };
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_fma(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                &b.schema,
+                &b.array,
+                &options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        (status, metrics)
+    }
+
+    #[test]
+    fn calls_write_their_metrics() {
+        let (status, metrics) = fma_metrics(ArrowUdfExecOptions {
+            batch_size: 100,
+            num_threads: 4,
+            ..ArrowUdfExecOptions::default()
+        });
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(metrics.rows, 1000);
+        assert_eq!(metrics.nulls, 10);
+        assert!(metrics.bytes_allocated >= 8000);
+        assert!(metrics.wall_micros >= 0);
+        assert_eq!(
+            metrics.simd_used,
+            crate::cpu::level() >= crate::cpu::Level::Avx2
+        );
+    }
+
+    #[test]
+    fn failed_calls_write_their_metrics() {
+        let (status, metrics) = fma_metrics(ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        });
+        assert_eq!(status, ArrowUdfStatus::NullValue);
+        assert_eq!((metrics.rows, metrics.nulls), (0, 10));
+        assert!(!metrics.simd_used);
+    }
+
+    #[test]
+    fn nothing_is_recorded_outside_calls() {
+        record_rows(10);
+        record_simd();
+        assert!(current().is_none());
+        let metrics = Arc::new(CallMetrics::default());
+        with_metrics(Some(metrics.clone()), || {
+            record_rows(3);
+            record_nulls(1);
+            set_output(ptr::null_mut());
+        });
+        assert!(current().is_none());
+        let snapshot = metrics.snapshot(Duration::from_millis(2), 16);
+        assert_eq!(
+            snapshot,
+            ArrowUdfMetrics {
+                wall_micros: 2000,
+                rows: 3,
+                nulls: 1,
+                bytes_allocated: 16,
+                simd_used: false,
+            }
+        );
+        metrics.write(snapshot);
+    }
+}
@@ -0,0 +1,178 @@
+//! NaN and infinite values in the input of reductions.
+//!
+//! A single NaN makes the sum or the mean of a whole array NaN, which is
+//! rarely what hosts want, so reductions over Float32 and Float64 arrays
+//! honor the `nan_policy` of the execution options: values that aren't
+//! finite are kept, and propagate to the result like in IEEE arithmetic,
+//! skipped like nulls, or make the call fail.
+//!
+//! Skipping is implemented by masking the values as nulls in a copy of the
+//! array struct with a new validity bitmap, so every reduction skips them
+//! the same way it skips nulls, without copying the values.
+
+use std::ffi::c_void;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{Error, Result};
+use crate::ffi::ArrowCDataInterfaceArray;
+use crate::options::{ArrowUdfExecOptions, NanPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::types::Numeric;
+
+/// An array with its non-finite values masked as nulls. The array it was
+/// created from must outlive it.
+pub(crate) struct Masked {
+    array: ArrowCDataInterfaceArray,
+    _buffers: Vec<*const c_void>,
+    _validity: Buffer,
+}
+
+impl Masked {
+    pub(crate) fn array<'a>(&'a self, schema: &'a Schema) -> ArrowArray<'a> {
+        unsafe { ArrowArray::new(schema, &self.array) }
+    }
+}
+
+fn non_finite<T: Numeric>(array: &ArrowArray) -> Vec<bool> {
+    let values = array.values::<T>();
+    (0..array.len())
+        .map(|i| array.is_valid(i) && !values[i].to_f64().is_finite())
+        .collect()
+}
+
+/// Apply the NaN policy of `options` to the values of `array`: fail if the
+/// policy is to error and there are non-finite values, or return the array
+/// with them masked as nulls if the policy is to skip them. `None` when the
+/// array can be used as it is.
+pub(crate) fn mask_non_finite(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<Option<Masked>> {
+    let policy = options.nan_policy()?;
+    if policy == NanPolicy::Propagate {
+        return Ok(None);
+    }
+    let non_finite = match array.data_type() {
+        ArrowType::Float32 => non_finite::<f32>(array),
+        ArrowType::Float64 => non_finite::<f64>(array),
+        _ => return Ok(None),
+    };
+    let n_non_finite = non_finite.iter().filter(|non_finite| **non_finite).count();
+    if n_non_finite == 0 {
+        return Ok(None);
+    }
+    if policy == NanPolicy::Error {
+        return Err(Error::NonFiniteValue);
+    }
+    // The bitmap starts at the offset of the array, like the one it replaces.
+    let mut validity = BitmapBuilder::with_capacity(array.offset() + array.len());
+    (0..array.offset()).for_each(|_| validity.push(false));
+    for (i, non_finite) in non_finite.iter().enumerate() {
+        validity.push(array.is_valid(i) && !non_finite);
+    }
+    let validity = validity.finish();
+    let ffi = array.ffi();
+    let mut buffers: Vec<*const c_void> = (0..ffi.n_buffers as usize)
+        .map(|i| unsafe { ffi.buffer(i) } as *const c_void)
+        .collect();
+    buffers[0] = validity.as_ptr() as *const c_void;
+    let masked = ArrowCDataInterfaceArray {
+        length: ffi.length,
+        null_count: (array.null_count() + n_non_finite) as i64,
+        offset: ffi.offset,
+        n_buffers: ffi.n_buffers,
+        buffers: buffers.as_mut_ptr(),
+        ..ArrowCDataInterfaceArray::empty()
+    };
+    Ok(Some(Masked {
+        array: masked,
+        _buffers: buffers,
+        _validity: validity,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dot::arrow_udf_dot;
+    use crate::error::ArrowUdfStatus;
+    use crate::options::{ARROW_UDF_NAN_POLICY_ERROR, ARROW_UDF_NAN_POLICY_SKIP};
+    use crate::testing::Exported;
+
+    fn with_policy(nan_policy: i32) -> ArrowUdfExecOptions {
+        ArrowUdfExecOptions {
+            nan_policy,
+            ..ArrowUdfExecOptions::default()
+        }
+    }
+
+    #[test]
+    fn non_finite_values_are_masked_as_nulls() {
+        let mut input = Exported::nullable(&[
+            Some(1.0),
+            Some(f64::NAN),
+            None,
+            Some(f64::NEG_INFINITY),
+            Some(2.0),
+        ]);
+        let skip = with_policy(ARROW_UDF_NAN_POLICY_SKIP);
+        input.with_array(|array| {
+            let masked = mask_non_finite(array, &skip).unwrap().unwrap();
+            let masked = masked.array(array.schema());
+            assert_eq!(masked.null_count(), 3);
+            let valid: Vec<bool> = (0..5).map(|i| masked.is_valid(i)).collect();
+            assert_eq!(valid, [true, false, false, false, true]);
+            assert_eq!(masked.values::<f64>()[4], 2.0);
+        });
+        input.array.offset = 3;
+        input.array.length = 2;
+        input.array.null_count = -1;
+        input.with_array(|array| {
+            let masked = mask_non_finite(array, &skip).unwrap().unwrap();
+            let masked = masked.array(array.schema());
+            assert_eq!((masked.null_count(), masked.is_valid(1)), (1, true));
+            assert_eq!(masked.values::<f64>(), [f64::NEG_INFINITY, 2.0]);
+        });
+    }
+
+    #[test]
+    fn arrays_without_non_finite_values_are_used_as_they_are() {
+        let floats = Exported::primitive(&[1.0_f32, f32::NAN]);
+        floats.with_array(|array| {
+            let propagate = ArrowUdfExecOptions::default();
+            assert!(mask_non_finite(array, &propagate).unwrap().is_none());
+        });
+        let skip = with_policy(ARROW_UDF_NAN_POLICY_SKIP);
+        let finite = Exported::primitive(&[1.0_f32, -3.5]);
+        finite.with_array(|array| assert!(mask_non_finite(array, &skip).unwrap().is_none()));
+        let integers = Exported::primitive(&[i64::MAX]);
+        integers.with_array(|array| assert!(mask_non_finite(array, &skip).unwrap().is_none()));
+    }
+
+    #[test]
+    fn reductions_follow_the_policy() {
+        let a = Exported::primitive(&[1.0, f64::INFINITY, 3.0]);
+        let b = Exported::primitive(&[2_i32, 1, 1]);
+        let dot = |options: &ArrowUdfExecOptions| {
+            let mut out = 0.0;
+            let status = unsafe {
+                arrow_udf_dot(&a.schema, &a.array, &b.schema, &b.array, options, &mut out)
+            };
+            (status, out)
+        };
+        assert_eq!(
+            dot(&ArrowUdfExecOptions::default()),
+            (ArrowUdfStatus::Ok, f64::INFINITY)
+        );
+        assert_eq!(
+            dot(&with_policy(ARROW_UDF_NAN_POLICY_SKIP)),
+            (ArrowUdfStatus::Ok, 5.0)
+        );
+        let (status, _) = dot(&with_policy(ARROW_UDF_NAN_POLICY_ERROR));
+        assert_eq!(status, ArrowUdfStatus::NonFiniteValue);
+        let (status, _) = dot(&with_policy(7));
+        assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+    }
+}
@@ -0,0 +1,161 @@
+//! Placement of the threads of reductions on the NUMA nodes of their memory.
+//!
+//! On machines with more than one NUMA node, a thread reading memory of
+//! another node goes through the interconnect between the sockets, with a
+//! fraction of the bandwidth of its local memory. The memory of the arrays
+//! is allocated by the host, usually on the node of the thread that wrote it
+//! first, so the library can't choose where it lives, but it can choose where
+//! its threads run: `exec::reduce_values` runs the thread reducing every part
+//! of an array on the CPUs of the node holding the first page of the part.
+//!
+//! The nodes and their CPUs are read from `/sys/devices/system/node`, and
+//! the node of a page is asked to the kernel with the `move_pages` system
+//! call, which doesn't move anything when no nodes are given. It needs no
+//! library like libnuma or hwloc, only Linux. Machines with a single node,
+//! and kernels without NUMA support, leave the threads where they are.
+
+use std::fs;
+use std::mem;
+use std::ptr;
+use std::sync::OnceLock;
+
+/// The CPUs of every NUMA node, by node id.
+#[derive(Debug, Default)]
+pub struct Topology {
+    nodes: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    /// The topology of the machine, read once.
+    pub fn get() -> &'static Topology {
+        static TOPOLOGY: OnceLock<Topology> = OnceLock::new();
+        TOPOLOGY.get_or_init(Topology::read)
+    }
+
+    fn read() -> Topology {
+        let mut nodes = Vec::new();
+        let Ok(entries) = fs::read_dir("/sys/devices/system/node") else {
+            return Topology { nodes };
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            let cpus = fs::read_to_string(entry.path().join("cpulist"))
+                .map(|list| parse_cpu_list(&list))
+                .unwrap_or_default();
+            if nodes.len() <= id {
+                nodes.resize(id + 1, Vec::new());
+            }
+            nodes[id] = cpus;
+        }
+        Topology { nodes }
+    }
+
+    /// Number of nodes with CPUs.
+    pub fn len(&self) -> usize {
+        self.nodes.iter().filter(|cpus| !cpus.is_empty()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The CPUs of `node`, if it exists.
+    pub fn cpus(&self, node: usize) -> Option<&[usize]> {
+        self.nodes.get(node).map(Vec::as_slice)
+    }
+}
+
+/// The CPUs of a list like "0-3,8,10-11".
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) {
+            cpus.extend(start..=end);
+        }
+    }
+    cpus
+}
+
+/// The node holding the page of `address`, if the kernel knows it. Pages
+/// that were never written aren't on any node yet.
+pub fn node_of(address: *const u8) -> Option<usize> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let page = (address as usize & !(page_size - 1)) as *const libc::c_void;
+    let mut status: libc::c_int = -1;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_move_pages,
+            0,
+            1 as libc::c_ulong,
+            &page,
+            ptr::null::<libc::c_int>(),
+            &mut status,
+            0,
+        )
+    };
+    match result == 0 && status >= 0 {
+        true => Some(status as usize),
+        false => None,
+    }
+}
+
+/// Run the current thread on the CPUs of the node holding `address`, on
+/// machines with more than one node. Threads that can't be moved stay where
+/// they are, since that's only slower.
+pub fn bind_to_memory(address: *const u8) {
+    let topology = Topology::get();
+    if topology.len() < 2 {
+        return;
+    }
+    let Some(cpus) = node_of(address).and_then(|node| topology.cpus(node)) else {
+        return;
+    };
+    if cpus.is_empty() {
+        return;
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for cpu in cpus {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists_are_parsed() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5"), [5]);
+        assert!(parse_cpu_list("\n").is_empty());
+        assert_eq!(parse_cpu_list("x,2-y,4"), [4]);
+    }
+
+    #[test]
+    fn written_pages_are_on_a_node_of_the_topology() {
+        let topology = Topology::get();
+        let values = vec![1_u64; 4096];
+        // Kernels without NUMA support don't know the node of any page.
+        if let Some(node) = node_of(values.as_ptr().cast()) {
+            assert!(node < topology.nodes.len().max(1));
+        }
+        let before = std::thread::available_parallelism().map_or(1, |n| n.get());
+        std::thread::spawn(move || {
+            bind_to_memory(values.as_ptr().cast());
+            assert!(std::thread::available_parallelism().map_or(1, |n| n.get()) <= before);
+        })
+        .join()
+        .unwrap();
+    }
+}
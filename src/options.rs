@@ -0,0 +1,680 @@
+//! Options that hosts can pass to every entry point to tune the execution.
+
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::buffer::Buffer;
+use crate::context::UdfContext;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::memory;
+use crate::metrics::{self, ArrowUdfMetrics};
+
+/// How nulls in the input are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NullPolicy {
+    /// Reductions ignore null values, and maps return null for them.
+    Skip,
+    /// Fail with `ArrowUdfStatus::NullValue` if the input contains nulls.
+    Error,
+    /// Reductions return null if their input contains nulls, like SQL
+    /// arithmetic, and maps return null for them.
+    Propagate,
+}
+
+pub const ARROW_UDF_NULL_POLICY_SKIP: i32 = 0;
+pub const ARROW_UDF_NULL_POLICY_ERROR: i32 = 1;
+pub const ARROW_UDF_NULL_POLICY_PROPAGATE: i32 = 2;
+
+/// How NaN and infinite values in the input of reductions are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NanPolicy {
+    /// Values are used as they are, so NaN usually makes the result NaN.
+    Propagate,
+    /// Values that aren't finite are skipped, like nulls.
+    Skip,
+    /// Fail with `ArrowUdfStatus::NonFiniteValue` if the input contains
+    /// values that aren't finite.
+    Error,
+}
+
+pub const ARROW_UDF_NAN_POLICY_PROPAGATE: i32 = 0;
+pub const ARROW_UDF_NAN_POLICY_SKIP: i32 = 1;
+pub const ARROW_UDF_NAN_POLICY_ERROR: i32 = 2;
+
+/// How reductions choose their execution strategy, see `strategy::plan`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Chosen for every call from the length, the nulls and the type of the
+    /// input, and the threads of the options.
+    Auto,
+    /// In the calling thread, whatever the length of the input.
+    Serial,
+    /// Split among the threads of the options, whatever the length of the
+    /// input.
+    Parallel,
+    /// With the vectorized loop, whatever the length of the batches.
+    Simd,
+}
+
+pub const ARROW_UDF_STRATEGY_AUTO: i32 = 0;
+pub const ARROW_UDF_STRATEGY_SERIAL: i32 = 1;
+pub const ARROW_UDF_STRATEGY_PARALLEL: i32 = 2;
+pub const ARROW_UDF_STRATEGY_SIMD: i32 = 3;
+
+/// Execution options, as received from the host.
+///
+/// Hosts should initialize the struct with `arrow_udf_exec_options_default`
+/// and then set the fields they care about, so new fields added in future
+/// versions get sensible values. A null pointer to this struct can be
+/// passed to any entry point to use the defaults.
+///
+/// New fields are only added at the end. The struct starts with its size
+/// as the host knows it, so the library only reads the fields of the
+/// version of the header the host was built with, and uses the defaults for
+/// the others.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArrowUdfExecOptions {
+    /// Size in bytes of the struct of the host, `sizeof(ArrowUdfExecOptions)`
+    /// in its header.
+    pub size: usize,
+    /// Number of rows processed at a time. Zero processes everything in a
+    /// single batch.
+    pub batch_size: i64,
+    /// Number of threads to use. Zero uses the threads set with
+    /// `arrow_udf_set_threads`, or all the available cores if they aren't
+    /// set.
+    pub num_threads: i32,
+    /// One of the `ARROW_UDF_NULL_POLICY_*` constants.
+    pub null_policy: i32,
+    /// Ignored. Metrics are written into `metrics` instead of being printed,
+    /// and the field is kept so the fields after it don't move.
+    pub collect_metrics: bool,
+    /// Bitwise or of `ARROW_UDF_FLAG_*` constants.
+    pub flags: u32,
+    /// Optional flag that the host can set from another thread to stop the
+    /// execution. It's checked after every batch, and when set, the call
+    /// returns `ArrowUdfStatus::Cancelled`.
+    pub cancel_flag: *const AtomicBool,
+    /// Optional callback receiving the number of rows processed so far, the
+    /// total number of rows, and `progress_user_data`. The calls never
+    /// overlap, but they can happen from any of the threads of the kernel.
+    pub progress_callback: Option<ArrowUdfProgressCallback>,
+    /// Minimum number of rows processed between calls to the progress
+    /// callback. Zero calls it after every batch.
+    pub progress_interval: i64,
+    pub progress_user_data: *mut c_void,
+    /// Optional context created with `arrow_udf_context_create`, shared by
+    /// all the calls receiving it.
+    pub context: *const UdfContext,
+    /// Maximum number of rows being processed at the same time by async
+    /// UDFs. Zero processes all the rows of a batch at the same time.
+    pub max_concurrency: i64,
+    /// One of the `ARROW_UDF_NAN_POLICY_*` constants, for reductions over
+    /// float arrays.
+    pub nan_policy: i32,
+    /// Maximum number of bytes used at the same time by the call, for its
+    /// outputs and its intermediate state. Calls exceeding it fail with
+    /// `ArrowUdfStatus::OutOfBudget`. Zero means no limit.
+    pub memory_limit: i64,
+    /// Optional struct where the metrics of the call are written when it
+    /// returns, also when it fails.
+    pub metrics: *mut ArrowUdfMetrics,
+    /// One of the `ARROW_UDF_STRATEGY_*` constants, to override the
+    /// execution strategy chosen for reductions.
+    pub strategy: i32,
+    /// Optional memory of the host, aligned to 8 bytes, where the values of
+    /// the result of the call are written when it's a primitive array fitting
+    /// in `output_capacity` bytes, instead of in memory allocated by the
+    /// library. The memory isn't freed when the result is released. Other
+    /// results are allocated as usual, so hosts should check whether the
+    /// values buffer of the result is this one.
+    pub output_buffer: *mut u8,
+    /// Size in bytes of `output_buffer`.
+    pub output_capacity: i64,
+    /// Optional structs where reductions export their result, as an array of
+    /// one element of the type of the result, instead of writing it into
+    /// their `out` pointers, which can then be null. Results that reductions
+    /// can't express otherwise, like the sum of an array with nulls with the
+    /// propagate null policy, are null. Both must be set, or neither.
+    pub scalar_schema: *mut ArrowCDataInterfaceSchema,
+    pub scalar_array: *mut ArrowCDataInterfaceArray,
+    /// Seed of the random numbers of stochastic UDFs, see `random`. Zero
+    /// uses a different seed for every call.
+    pub seed: u64,
+    /// Partition of the data received by the call, mixed into the seed so
+    /// every partition gets different random numbers.
+    pub partition_id: u64,
+}
+
+pub type ArrowUdfProgressCallback =
+    unsafe extern "C" fn(rows_processed: i64, total_rows: i64, user_data: *mut c_void);
+
+// The pointers in the options are required to be usable from any thread, so
+// the kernels can share the options among the threads they use.
+unsafe impl Send for ArrowUdfExecOptions {}
+unsafe impl Sync for ArrowUdfExecOptions {}
+
+impl Default for ArrowUdfExecOptions {
+    fn default() -> ArrowUdfExecOptions {
+        ArrowUdfExecOptions {
+            size: size_of::<ArrowUdfExecOptions>(),
+            batch_size: 64 * 1024,
+            num_threads: 1,
+            null_policy: ARROW_UDF_NULL_POLICY_SKIP,
+            collect_metrics: false,
+            flags: 0,
+            cancel_flag: ptr::null(),
+            progress_callback: None,
+            progress_interval: 0,
+            progress_user_data: ptr::null_mut(),
+            context: ptr::null(),
+            max_concurrency: 64,
+            nan_policy: ARROW_UDF_NAN_POLICY_PROPAGATE,
+            memory_limit: 0,
+            metrics: ptr::null_mut(),
+            strategy: ARROW_UDF_STRATEGY_AUTO,
+            output_buffer: ptr::null_mut(),
+            output_capacity: 0,
+            scalar_schema: ptr::null_mut(),
+            scalar_array: ptr::null_mut(),
+            seed: 0,
+            partition_id: 0,
+        }
+    }
+}
+
+/// Size of the field of the options returned by `field`.
+fn field_size<T>(_field: fn(&ArrowUdfExecOptions) -> &T) -> usize {
+    size_of::<T>()
+}
+
+/// Copy the fields of `$from` to `$to` that fit in the first `$size` bytes
+/// of the struct, in the order they are declared.
+macro_rules! copy_fields {
+    ($from:expr, $to:expr, $size:expr; $($field:ident),* $(,)?) => {
+        $(
+            if std::mem::offset_of!(ArrowUdfExecOptions, $field)
+                + field_size(|options| &options.$field)
+                <= $size
+            {
+                ptr::addr_of_mut!((*$to).$field).write(ptr::addr_of!((*$from).$field).read());
+            }
+        )*
+    };
+}
+
+/// Copy the fields of `from` that fit in its first `size` bytes into `to`,
+/// where either of them can be the shorter struct of an older header.
+unsafe fn copy_prefix(from: *const ArrowUdfExecOptions, to: *mut ArrowUdfExecOptions, size: usize) {
+    copy_fields!(
+        from, to, size;
+        batch_size,
+        num_threads,
+        null_policy,
+        flags,
+        cancel_flag,
+        progress_callback,
+        progress_interval,
+        progress_user_data,
+        context,
+        max_concurrency,
+        nan_policy,
+        memory_limit,
+        metrics,
+        strategy,
+        output_buffer,
+        output_capacity,
+        scalar_schema,
+        scalar_array,
+        seed,
+        partition_id,
+    );
+}
+
+impl ArrowUdfExecOptions {
+    /// Read the options received in an entry point, using the defaults when
+    /// the pointer is null, and for the fields after the `size` declared by
+    /// the host, and register where the metrics of the call are written.
+    ///
+    /// # Safety
+    ///
+    /// `options` must be null or point to a valid `ArrowUdfExecOptions` of
+    /// at least `size` bytes.
+    pub unsafe fn from_ffi(options: *const ArrowUdfExecOptions) -> Result<ArrowUdfExecOptions> {
+        let mut read = ArrowUdfExecOptions::default();
+        if !options.is_null() {
+            let size = (*options).size;
+            if size < size_of::<usize>() {
+                return Err(Error::InvalidArgument(format!(
+                    "the size of the options must be at least {}, got {size}",
+                    size_of::<usize>()
+                )));
+            }
+            copy_prefix(options, &mut read, size);
+        }
+        let options = read;
+        if options.batch_size < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "batch_size must be zero or positive, got {}",
+                options.batch_size
+            )));
+        }
+        if options.num_threads < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "num_threads must be zero or positive, got {}",
+                options.num_threads
+            )));
+        }
+        if options.max_concurrency < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "max_concurrency must be zero or positive, got {}",
+                options.max_concurrency
+            )));
+        }
+        if options.memory_limit < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "memory_limit must be zero or positive, got {}",
+                options.memory_limit
+            )));
+        }
+        if options.progress_interval < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "progress_interval must be zero or positive, got {}",
+                options.progress_interval
+            )));
+        }
+        if options.output_capacity < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "output_capacity must be zero or positive, got {}",
+                options.output_capacity
+            )));
+        }
+        if !(options.output_buffer as usize).is_multiple_of(8) {
+            return Err(Error::InvalidArgument(format!(
+                "output_buffer at {:p} isn't aligned to 8 bytes",
+                options.output_buffer
+            )));
+        }
+        if options.scalar_schema.is_null() != options.scalar_array.is_null() {
+            return Err(Error::InvalidArgument(
+                "scalar_schema and scalar_array must be both set or both null".to_string(),
+            ));
+        }
+        options.null_policy()?;
+        options.nan_policy()?;
+        options.strategy()?;
+        metrics::set_output(options.metrics);
+        OUTPUT_USED.set(false);
+        Ok(options)
+    }
+
+    pub fn null_policy(&self) -> Result<NullPolicy> {
+        match self.null_policy {
+            ARROW_UDF_NULL_POLICY_SKIP => Ok(NullPolicy::Skip),
+            ARROW_UDF_NULL_POLICY_ERROR => Ok(NullPolicy::Error),
+            ARROW_UDF_NULL_POLICY_PROPAGATE => Ok(NullPolicy::Propagate),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown null policy {other}"
+            ))),
+        }
+    }
+
+    pub fn nan_policy(&self) -> Result<NanPolicy> {
+        match self.nan_policy {
+            ARROW_UDF_NAN_POLICY_PROPAGATE => Ok(NanPolicy::Propagate),
+            ARROW_UDF_NAN_POLICY_SKIP => Ok(NanPolicy::Skip),
+            ARROW_UDF_NAN_POLICY_ERROR => Ok(NanPolicy::Error),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown NaN policy {other}"
+            ))),
+        }
+    }
+
+    pub fn strategy(&self) -> Result<Strategy> {
+        match self.strategy {
+            ARROW_UDF_STRATEGY_AUTO => Ok(Strategy::Auto),
+            ARROW_UDF_STRATEGY_SERIAL => Ok(Strategy::Serial),
+            ARROW_UDF_STRATEGY_PARALLEL => Ok(Strategy::Parallel),
+            ARROW_UDF_STRATEGY_SIMD => Ok(Strategy::Simd),
+            other => Err(Error::InvalidArgument(format!("unknown strategy {other}"))),
+        }
+    }
+
+    /// Fail with `Error::Cancelled` if the host requested the cancellation.
+    pub fn check_cancelled(&self) -> Result<()> {
+        let cancelled =
+            unsafe { self.cancel_flag.as_ref() }.is_some_and(|flag| flag.load(Ordering::Relaxed));
+        if cancelled {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The memory limit in bytes, if any.
+    pub fn memory_limit(&self) -> Option<usize> {
+        (self.memory_limit > 0).then_some(self.memory_limit as usize)
+    }
+
+    /// Fail with `Error::OutOfBudget` if the memory used by the current call
+    /// exceeded the memory limit at any time.
+    pub fn check_memory(&self) -> Result<()> {
+        let peak = memory::current().map_or(0, |tracker| tracker.peak());
+        if self.memory_limit().is_some_and(|limit| peak > limit) {
+            Err(Error::OutOfBudget)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The context received from the host, if any.
+    pub fn context(&self) -> Option<&UdfContext> {
+        unsafe { self.context.as_ref() }
+    }
+
+    /// Whether reductions export their result as an array, see
+    /// `scalar_schema`.
+    pub fn exports_scalar(&self) -> bool {
+        !self.scalar_schema.is_null()
+    }
+
+    /// Buffer of `len` bytes set to zero for the values of the result of
+    /// the call, in the output buffer of the host if there is one, it's large
+    /// enough, and no other buffer of the call uses it.
+    pub fn output_values(&self, len: usize) -> Result<Buffer> {
+        let capacity = self.output_capacity as usize;
+        match NonNull::new(self.output_buffer) {
+            Some(output)
+                if len <= capacity
+                    && (output.as_ptr() as usize).is_multiple_of(8)
+                    && !OUTPUT_USED.replace(true) =>
+            unsafe { Ok(Buffer::zeroed_in_host(output, len, capacity)) },
+            _ => Buffer::zeroed(len),
+        }
+    }
+
+    /// Rows per batch, for an input of `len` rows.
+    pub fn batch_len(&self, len: usize) -> usize {
+        match self.batch_size {
+            0 => len.max(1),
+            size => size as usize,
+        }
+    }
+
+    /// Number of threads to actually use, never more than the threads set
+    /// with `arrow_udf_set_threads`.
+    pub fn threads(&self) -> usize {
+        match (self.num_threads, THREADS.load(Ordering::Relaxed)) {
+            (0, 0) => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            (0, max) => max,
+            (n, 0) => n as usize,
+            (n, max) => (n as usize).min(max),
+        }
+    }
+}
+
+thread_local! {
+    /// Whether a buffer of the running call is in the output buffer of its
+    /// options, so no other uses it.
+    static OUTPUT_USED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The threads set by the host with `arrow_udf_set_threads`, or zero when
+/// they aren't set.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the threads used by the calls with `num_threads` set to zero, and the
+/// maximum threads of any call, so hosts scheduling their own threads can
+/// keep the calls from using more cores than they give them. Zero goes back
+/// to using all the available cores, and the `num_threads` of every call.
+/// Threads are started by every call, so the setting applies to the calls
+/// starting after it, not to the running ones.
+#[no_mangle]
+pub extern "C" fn arrow_udf_set_threads(num_threads: i32) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let Ok(threads) = usize::try_from(num_threads) else {
+            return Err(Error::InvalidArgument(format!(
+                "num_threads must be zero or positive, got {num_threads}"
+            )));
+        };
+        THREADS.store(threads, Ordering::Relaxed);
+        Ok(())
+    })
+}
+
+/// Write the default options into `options`, a struct of `size` bytes,
+/// `sizeof(ArrowUdfExecOptions)` in the header of the host. Only the fields
+/// fitting in it are written, and its `size` is set to `size`.
+///
+/// # Safety
+///
+/// `options` must be valid for writes of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_exec_options_default(
+    options: *mut ArrowUdfExecOptions,
+    size: usize,
+) {
+    if size < size_of::<usize>() {
+        return;
+    }
+    copy_prefix(&ArrowUdfExecOptions::default(), options, size);
+    ptr::addr_of_mut!((*options).size).write(size);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    /// The options of a header with only the first fields.
+    #[repr(C)]
+    struct Prefix {
+        size: usize,
+        batch_size: i64,
+        num_threads: i32,
+    }
+
+    #[test]
+    fn reads_the_fields_declared_by_the_host() {
+        let mut prefix = Prefix {
+            size: 0,
+            batch_size: 0,
+            num_threads: 0,
+        };
+        let prefix: *mut Prefix = &mut prefix;
+        let options = prefix as *mut ArrowUdfExecOptions;
+        unsafe {
+            arrow_udf_exec_options_default(options, size_of::<Prefix>());
+            assert_eq!((*prefix).size, size_of::<Prefix>());
+            let default = ArrowUdfExecOptions::default();
+            assert_eq!((*prefix).batch_size, default.batch_size);
+            (*prefix).batch_size = 10;
+            (*prefix).num_threads = 2;
+        }
+        let read = unsafe { ArrowUdfExecOptions::from_ffi(options) }.unwrap();
+        assert_eq!(read.size, size_of::<ArrowUdfExecOptions>());
+        assert_eq!(read.batch_size, 10);
+        assert_eq!(read.num_threads, 2);
+        assert_eq!(read.null_policy, ArrowUdfExecOptions::default().null_policy);
+        assert_eq!(read.flags, ArrowUdfExecOptions::default().flags);
+    }
+
+    #[test]
+    fn rejects_a_size_without_the_size_field() {
+        let options = ArrowUdfExecOptions {
+            size: 0,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert!(matches!(
+            unsafe { ArrowUdfExecOptions::from_ffi(&options) },
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for options in [
+            ArrowUdfExecOptions {
+                batch_size: -1,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                num_threads: -2,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                null_policy: 7,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                progress_interval: -1,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                max_concurrency: -1,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                nan_policy: 3,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                memory_limit: -1,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                strategy: 4,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                output_capacity: -1,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                output_buffer: 4 as *mut u8,
+                ..ArrowUdfExecOptions::default()
+            },
+            ArrowUdfExecOptions {
+                scalar_schema: &mut ArrowCDataInterfaceSchema::empty(),
+                ..ArrowUdfExecOptions::default()
+            },
+        ] {
+            assert!(matches!(
+                unsafe { ArrowUdfExecOptions::from_ffi(&options) },
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        let defaults = unsafe { ArrowUdfExecOptions::from_ffi(ptr::null()) }.unwrap();
+        assert_eq!(defaults.batch_len(10), 64 * 1024);
+        assert_eq!(defaults.threads(), 1);
+    }
+
+    #[test]
+    fn null_policies() {
+        for (null_policy, expected) in [
+            (ARROW_UDF_NULL_POLICY_SKIP, NullPolicy::Skip),
+            (ARROW_UDF_NULL_POLICY_ERROR, NullPolicy::Error),
+            (ARROW_UDF_NULL_POLICY_PROPAGATE, NullPolicy::Propagate),
+        ] {
+            let options = ArrowUdfExecOptions {
+                null_policy,
+                ..ArrowUdfExecOptions::default()
+            };
+            let options = unsafe { ArrowUdfExecOptions::from_ffi(&options) }.unwrap();
+            assert_eq!(options.null_policy().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn threads_set_by_the_host_cap_every_call() {
+        let with_threads = |num_threads| ArrowUdfExecOptions {
+            num_threads,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(arrow_udf_set_threads(-1), ArrowUdfStatus::InvalidArgument);
+        // Higher than the threads of the other tests, which run at the same
+        // time.
+        assert_eq!(arrow_udf_set_threads(64), ArrowUdfStatus::Ok);
+        let threads = [0, 3, 100].map(|n| with_threads(n).threads());
+        assert_eq!(arrow_udf_set_threads(0), ArrowUdfStatus::Ok);
+        assert_eq!(threads, [64, 3, 64]);
+        assert_eq!(with_threads(100).threads(), 100);
+    }
+
+    #[test]
+    fn primitive_results_are_written_into_the_output_buffer() {
+        let input = Exported::primitive(&[1_i64, 5, -3]);
+        let mut memory = [7_i64; 4];
+        let options = ArrowUdfExecOptions {
+            output_buffer: memory.as_mut_ptr() as *mut u8,
+            output_capacity: 32,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut out = Exported::empty();
+        let status = unsafe {
+            crate::arrow_udf_distances(
+                &input.schema,
+                &input.array,
+                2,
+                &options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(unsafe { out.array.buffer(1) }, memory.as_ptr() as *const u8);
+        assert_eq!(out.values::<i64>(), [1, 3, 5]);
+        drop(out);
+        // Releasing the result leaves the memory of the host alone.
+        assert_eq!(memory, [1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn results_not_fitting_in_the_output_buffer_are_allocated() {
+        let mut memory = [0_i64; 2];
+        let options = ArrowUdfExecOptions {
+            output_buffer: memory.as_mut_ptr() as *mut u8,
+            output_capacity: 16,
+            ..ArrowUdfExecOptions::default()
+        };
+        let options = unsafe { ArrowUdfExecOptions::from_ffi(&options) }.unwrap();
+        let large = options.output_values(24).unwrap();
+        assert_ne!(large.as_ptr(), memory.as_ptr() as *const u8);
+        let first = options.output_values(16).unwrap();
+        assert_eq!(first.as_ptr(), memory.as_ptr() as *const u8);
+        // Only one buffer of a call can use the memory of the host.
+        let second = options.output_values(8).unwrap();
+        assert_ne!(second.as_ptr(), memory.as_ptr() as *const u8);
+        let mut first = first;
+        first.extend_from_slice(&[1_i64]);
+        assert_ne!(first.as_ptr(), memory.as_ptr() as *const u8);
+        assert_eq!(first.len(), 24);
+    }
+
+    #[test]
+    fn output_buffers_overlapping_the_input_fail() {
+        let input = Exported::primitive(&[1_i64, 5, -3]);
+        let options = ArrowUdfExecOptions {
+            output_buffer: unsafe { input.array.buffer(1) } as *mut u8,
+            output_capacity: 24,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut out = Exported::empty();
+        let status = unsafe {
+            crate::arrow_udf_distances(
+                &input.schema,
+                &input.array,
+                2,
+                &options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::AliasingError);
+        assert!(out.array.release.is_none());
+    }
+}
@@ -0,0 +1,371 @@
+//! Functions with several implementations under one name, for different
+//! signatures.
+//!
+//! Hosts think of `distance` as one function, whether it's called with two
+//! Int64 columns, two Float64 columns or two columns of points. Every
+//! implementation is registered as an overload of the name with the schemas
+//! of its parameters, and calls are resolved from the schemas of the arrays
+//! they receive, to the first overload registered whose parameters they are
+//! compatible with, as in `Schema::is_compatible_with`. Struct parameters
+//! only name the fields they need, so structs with more fields match.
+//!
+//! The `distance` overloads are registered by the library, and Rust hosts
+//! can register more with `register`.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use crate::array::ArrowArray;
+use crate::check::describe;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::geo::{self, Points};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::row::ArrowField;
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+use crate::udf::combined_validity;
+
+/// Implementation of an overload, called with arrays compatible with its
+/// parameters.
+pub type OverloadFn = fn(&[ArrowArray], &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)>;
+
+/// An implementation of a function for arrays compatible with `params`.
+#[derive(Clone)]
+pub struct Overload {
+    pub params: Vec<Schema>,
+    pub call: OverloadFn,
+}
+
+impl Overload {
+    fn matches(&self, schemas: &[&Schema]) -> bool {
+        self.params.len() == schemas.len()
+            && schemas
+                .iter()
+                .zip(&self.params)
+                .all(|(schema, param)| schema.is_compatible_with(param))
+    }
+
+    /// The signature of the overload, like `distance(Int64, Int64)`.
+    fn signature(&self, name: &str) -> String {
+        let params: Vec<String> = self.params.iter().map(describe).collect();
+        format!("{name}({})", params.join(", "))
+    }
+}
+
+static OVERLOADS: LazyLock<RwLock<HashMap<String, Vec<Overload>>>> =
+    LazyLock::new(|| RwLock::new(builtin()));
+
+fn builtin() -> HashMap<String, Vec<Overload>> {
+    let point = Schema::new(ArrowType::Struct, "point").with_children(vec![
+        Schema::new(ArrowType::Float64, "lat"),
+        Schema::new(ArrowType::Float64, "lon"),
+    ]);
+    let overload = |param: Schema, call: OverloadFn| Overload {
+        params: vec![param.clone(), param],
+        call,
+    };
+    let distance = vec![
+        overload(Schema::new(ArrowType::Int64, "a"), |arrays, options| {
+            element_wise(arrays, options, |a: i64, b: i64| crate::distance(a, b))
+        }),
+        overload(Schema::new(ArrowType::Float64, "a"), |arrays, options| {
+            element_wise(arrays, options, |a: f64, b: f64| (a - b).abs())
+        }),
+        overload(point, points_distance),
+    ];
+    HashMap::from([("distance".to_string(), distance)])
+}
+
+/// Register `call` as the implementation of `name` for arrays compatible
+/// with `params`. Fails with `InvalidArgument` if `name` already has an
+/// overload with the same parameters.
+pub fn register(name: &str, params: Vec<Schema>, call: OverloadFn) -> Result<()> {
+    let overload = Overload { params, call };
+    let mut overloads = OVERLOADS.write().unwrap();
+    let existing = overloads.entry(name.to_string()).or_default();
+    let params: Vec<&Schema> = overload.params.iter().collect();
+    if let Some(other) = existing.iter().find(|other| {
+        let other_params: Vec<&Schema> = other.params.iter().collect();
+        other.matches(&params) && overload.matches(&other_params)
+    }) {
+        return Err(Error::InvalidArgument(format!(
+            "{} is already registered",
+            other.signature(name)
+        )));
+    }
+    existing.push(overload);
+    Ok(())
+}
+
+/// The overload of `name` for arrays described by `schemas`. Fails with
+/// `InvalidArgument` if there is no function `name`, and with
+/// `UnsupportedType`, listing its signatures, if none of its overloads
+/// accepts the arrays.
+pub fn resolve(name: &str, schemas: &[&Schema]) -> Result<Overload> {
+    let overloads = OVERLOADS.read().unwrap();
+    let Some(candidates) = overloads
+        .get(name)
+        .filter(|overloads| !overloads.is_empty())
+    else {
+        return Err(Error::InvalidArgument(format!("no function {name:?}")));
+    };
+    if let Some(overload) = candidates.iter().find(|overload| overload.matches(schemas)) {
+        return Ok(overload.clone());
+    }
+    let got: Vec<String> = schemas.iter().map(|schema| describe(schema)).collect();
+    let candidates: Vec<String> = candidates
+        .iter()
+        .map(|overload| overload.signature(name))
+        .collect();
+    Err(Error::UnsupportedType(format!(
+        "{name}({}), the signatures of {name} are {}",
+        got.join(", "),
+        candidates.join(", ")
+    )))
+}
+
+/// Call the overload of `name` for `arrays`.
+pub fn call(
+    name: &str,
+    arrays: &[ArrowArray],
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let schemas: Vec<&Schema> = arrays.iter().map(|array| array.schema()).collect();
+    let overload = resolve(name, &schemas)?;
+    (overload.call)(arrays, options)
+}
+
+/// The length of the arrays of a call, failing if they differ, or with
+/// `Error::NullValue` if any has nulls and the null policy is `Error`.
+fn check_arrays(arrays: &[ArrowArray], options: &ArrowUdfExecOptions) -> Result<usize> {
+    let len = arrays.first().map_or(0, ArrowArray::len);
+    if let Some(other) = arrays.iter().find(|array| array.len() != len) {
+        return Err(Error::InvalidArgument(format!(
+            "arrays of {len} and {} elements",
+            other.len()
+        )));
+    }
+    if options.null_policy()? == NullPolicy::Error
+        && arrays.iter().any(|array| array.null_count() > 0)
+    {
+        return Err(Error::NullValue);
+    }
+    Ok(len)
+}
+
+/// `f` of the elements at every position of two primitive arrays of the
+/// same length. Positions where any of them is null are null in the result.
+fn element_wise<T, O, F>(
+    arrays: &[ArrowArray],
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    F: Fn(T, T) -> O + Sync,
+{
+    let len = check_arrays(arrays, options)?;
+    let (a, b) = (arrays[0].values::<T>(), arrays[1].values::<T>());
+    let mut values = options.output_values(len * std::mem::size_of::<O>())?;
+    exec::map(values.typed_data_mut::<O>(), options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            *out = f(a[i], b[i]);
+        }
+        Ok(())
+    })?;
+    let (validity, null_count) = combined_validity(&[&arrays[0], &arrays[1]]);
+    Ok((
+        Schema::new(O::ARROW_TYPE, &arrays[0].schema().name),
+        ArrayData::primitive(values, len).with_validity(validity, null_count),
+    ))
+}
+
+/// Distance in meters between the points at every position of two struct
+/// arrays of points, as Float64. Positions where any of the points is null
+/// are null in the result.
+fn points_distance(
+    arrays: &[ArrowArray],
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let len = check_arrays(arrays, options)?;
+    let (a, b) = (
+        Points::from_struct(&arrays[0])?,
+        Points::from_struct(&arrays[1])?,
+    );
+    let distances: Vec<Option<f64>> = (0..len)
+        .map(|i| match (a.get(i), b.get(i)) {
+            (Some((lat1, lon1)), Some((lat2, lon2))) => {
+                Some(geo::haversine_distance(lat1, lon1, lat2, lon2))
+            }
+            _ => None,
+        })
+        .collect();
+    Ok((
+        Schema::new(ArrowType::Float64, &arrays[0].schema().name),
+        f64::build(distances.iter().map(Option::as_ref)),
+    ))
+}
+
+/// The function `name` for the `n_arrays` arrays in `schemas` and `arrays`,
+/// with the implementation registered for their types. Fails with
+/// `InvalidArgument` if there is no such function, and with
+/// `UnsupportedType` if it has no overload for their types.
+///
+/// # Safety
+///
+/// `name` must be a valid null-terminated string, `schemas` and `arrays`
+/// must point to `n_arrays` valid Arrow C Data Interface arrays, `options`
+/// must be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_call(
+    name: *const c_char,
+    n_arrays: i64,
+    schemas: *const *const ArrowCDataInterfaceSchema,
+    arrays: *const *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let name = CStr::from_ptr(name)
+            .to_str()
+            .map_err(|_| Error::InvalidArgument("the name is not valid UTF-8".to_string()))?;
+        let schemas = Schema::from_ffi_list(n_arrays, schemas)?;
+        let arrays = (0..schemas.len())
+            .map(|i| ArrowArray::import(&schemas[i], &**arrays.add(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let (out, data) = call(name, &arrays, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::{nullable_data, Exported};
+
+    fn call_ffi(
+        name: &str,
+        inputs: &[&Exported],
+        options: *const ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let name = CString::new(name).unwrap();
+        let schemas: Vec<_> = inputs
+            .iter()
+            .map(|input| &input.schema as *const _)
+            .collect();
+        let arrays: Vec<_> = inputs
+            .iter()
+            .map(|input| &input.array as *const _)
+            .collect();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_call(
+                name.as_ptr(),
+                inputs.len() as i64,
+                schemas.as_ptr(),
+                arrays.as_ptr(),
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    /// Struct array of points, with an extra `id` field and `lon` first.
+    fn points(lat: &[Option<f64>], lon: &[f64]) -> Exported {
+        let schema = Schema::new(ArrowType::Struct, "p").with_children(vec![
+            Schema::new(ArrowType::Int32, "id"),
+            Schema::new(ArrowType::Float64, "lon"),
+            Schema::new(ArrowType::Float64, "lat"),
+        ]);
+        let ids: Vec<i32> = (0..lon.len() as i32).collect();
+        let children = vec![
+            ArrayData::primitive(Buffer::from_slice(&ids), lon.len()),
+            ArrayData::primitive(Buffer::from_slice(lon), lon.len()),
+            nullable_data(lat),
+        ];
+        Exported::new(&schema, ArrayData::struct_array(children, lon.len()))
+    }
+
+    #[test]
+    fn calls_are_resolved_from_the_types_of_the_arrays() {
+        let a = Exported::nullable(&[Some(1_i64), None, Some(-4)]);
+        let b = Exported::nullable(&[Some(5_i64), Some(2), Some(4)]);
+        let out = call_ffi("distance", &[&a, &b], ptr::null()).unwrap();
+        assert_eq!(out.nullable_values::<i64>(), [Some(4), None, Some(8)]);
+        let a = Exported::primitive(&[0.5_f64, -1.0]);
+        let b = Exported::primitive(&[2.0_f64, 1.0]);
+        let out = call_ffi("distance", &[&a, &b], ptr::null()).unwrap();
+        assert_eq!(out.values::<f64>(), [1.5, 2.0]);
+        let a = points(&[Some(0.0), None], &[0.0, 0.0]);
+        let b = points(&[Some(0.0), Some(1.0)], &[1.0, 0.0]);
+        let out = call_ffi("distance", &[&a, &b], ptr::null()).unwrap();
+        let expected = geo::haversine_distance(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(out.nullable_values::<f64>(), [Some(expected), None]);
+    }
+
+    #[test]
+    fn calls_without_an_overload_fail() {
+        let ints = Exported::primitive(&[1_i64]);
+        let floats = Exported::primitive(&[1.0_f64]);
+        let status = call_ffi("distance", &[&ints, &floats], ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+        let schemas = [ints.with_array(|array| array.schema().clone())];
+        let Err(Error::UnsupportedType(message)) = resolve("distance", &[&schemas[0]]) else {
+            panic!("expected an unsupported type");
+        };
+        assert_eq!(
+            message,
+            "distance(Int64), the signatures of distance are distance(Int64, Int64), \
+             distance(Float64, Float64), \
+             distance(Struct<lat: Float64, lon: Float64>, Struct<lat: Float64, lon: Float64>)"
+        );
+        let status = call_ffi("nothing", &[&ints], ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let longer = Exported::primitive(&[1_i64, 2]);
+        let status = call_ffi("distance", &[&ints, &longer], ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let nulls = Exported::nullable(&[None::<i64>]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let status = call_ffi("distance", &[&ints, &nulls], &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::NullValue));
+    }
+
+    #[test]
+    fn hosts_register_overloads() {
+        let int32 = Schema::new(ArrowType::Int32, "a");
+        let sum: OverloadFn =
+            |arrays, options| element_wise(arrays, options, |a: i32, b: i32| a as i64 + b as i64);
+        register("test_sum", vec![int32.clone(), int32.clone()], sum).unwrap();
+        let a = Exported::primitive(&[i32::MAX, 1]);
+        let out = call_ffi("test_sum", &[&a, &a], ptr::null()).unwrap();
+        assert_eq!(out.values::<i64>(), [2 * i32::MAX as i64, 2]);
+        let renamed = Schema::new(ArrowType::Int32, "b");
+        let Err(Error::InvalidArgument(message)) = register("test_sum", vec![int32, renamed], sum)
+        else {
+            panic!("expected a duplicate overload");
+        };
+        assert_eq!(message, "test_sum(Int32, Int32) is already registered");
+    }
+}
@@ -0,0 +1,277 @@
+//! Distance matrices between two sets of points.
+//!
+//! Points of `d` dimensions are received as flattened Float64 arrays, with
+//! the `d` coordinates of every point one after the other, and the `n × m`
+//! matrix of distances is returned flattened in the same way, with the
+//! distances from the first point of `a` to all the points of `b` first.
+//!
+//! The matrix is computed in blocks of points of `a` and `b` that fit in the
+//! cache, and the blocks of rows are distributed among the threads.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+
+/// Points of `a` per block of rows of the matrix.
+const ROW_BLOCK: usize = 32;
+
+/// Points of `b` per block of columns. With the points of the row block,
+/// they should fit in the L1 cache for points of a few dimensions.
+const COLUMN_BLOCK: usize = 256;
+
+/// Points of a flattened Float64 array, and whether all the coordinates of
+/// every point are valid.
+struct Points<'a> {
+    coordinates: &'a [f64],
+    valid: Vec<bool>,
+}
+
+impl<'a> Points<'a> {
+    fn new(array: &ArrowArray<'a>, dims: usize) -> Result<Points<'a>> {
+        if array.data_type() != ArrowType::Float64 {
+            return Err(Error::UnsupportedType(format!(
+                "expected Float64 coordinates, got {:?}",
+                array.data_type()
+            )));
+        }
+        if !array.len().is_multiple_of(dims) {
+            return Err(Error::InvalidArgument(format!(
+                "{} coordinates are not a multiple of {dims} dimensions",
+                array.len()
+            )));
+        }
+        let valid = (0..array.len() / dims)
+            .map(|point| (point * dims..(point + 1) * dims).all(|i| array.is_valid(i)))
+            .collect();
+        Ok(Points {
+            coordinates: array.values::<f64>(),
+            valid,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.valid.len()
+    }
+}
+
+/// Flattened matrix of the euclidean distances between every point in `a`
+/// and every point in `b`, of `dims` dimensions. Distances to points with a
+/// null coordinate are null.
+pub fn pairwise_euclidean(
+    a: &ArrowArray,
+    b: &ArrowArray,
+    dims: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if dims == 0 {
+        return Err(Error::InvalidArgument(
+            "points must have at least one dimension".to_string(),
+        ));
+    }
+    let (a, b) = (Points::new(a, dims)?, Points::new(b, dims)?);
+    let has_nulls = a.valid.contains(&false) || b.valid.contains(&false);
+    if has_nulls && options.null_policy()? == NullPolicy::Error {
+        return Err(Error::NullValue);
+    }
+    let (n, m) = (a.len(), b.len());
+    let len = n.checked_mul(m).ok_or_else(|| {
+        Error::InvalidArgument(format!("a matrix of {n} × {m} distances is too large"))
+    })?;
+    let mut distances = options.output_values(len * std::mem::size_of::<f64>())?;
+    if m > 0 {
+        let mut blocks: Vec<&mut [f64]> = distances
+            .typed_data_mut::<f64>()
+            .chunks_mut(ROW_BLOCK * m)
+            .collect();
+        exec::map(&mut blocks, options, |block_range, blocks| {
+            for (block, rows) in block_range.zip(blocks.iter_mut()) {
+                distance_block(&a, &b, dims, block * ROW_BLOCK, rows);
+            }
+            Ok(())
+        })?;
+    }
+    let mut validity = BitmapBuilder::with_capacity(len);
+    let mut null_count = 0;
+    if has_nulls {
+        for i in 0..n {
+            for j in 0..m {
+                let valid = a.valid[i] && b.valid[j];
+                validity.push(valid);
+                null_count += usize::from(!valid);
+            }
+        }
+    }
+    let validity = has_nulls.then(|| validity.finish());
+    Ok((
+        Schema::new(ArrowType::Float64, "distance"),
+        ArrayData::primitive(distances, len).with_validity(validity, null_count),
+    ))
+}
+
+/// Fill `out` with the rows of the matrix starting at the point `start` of
+/// `a`, going through `b` in blocks of columns.
+fn distance_block(a: &Points, b: &Points, dims: usize, start: usize, out: &mut [f64]) {
+    let m = b.len();
+    let rows = out.len() / m;
+    for columns in (0..m).step_by(COLUMN_BLOCK) {
+        let end = (columns + COLUMN_BLOCK).min(m);
+        for row in 0..rows {
+            let p = &a.coordinates[(start + row) * dims..(start + row + 1) * dims];
+            for (j, out) in (columns..end).zip(&mut out[row * m + columns..row * m + end]) {
+                let q = &b.coordinates[j * dims..(j + 1) * dims];
+                *out = p
+                    .iter()
+                    .zip(q)
+                    .map(|(x, y)| (x - y) * (x - y))
+                    .sum::<f64>()
+                    .sqrt();
+            }
+        }
+    }
+}
+
+/// Euclidean distances between every point in `a` and every point in `b`,
+/// of `dims` dimensions, both as flattened Float64 arrays with the
+/// coordinates of every point one after the other. The result is the
+/// flattened `n × m` Float64 matrix of distances, where `n` and `m` are the
+/// number of points of `a` and `b`, with the distances from the first point
+/// of `a` first.
+///
+/// # Safety
+///
+/// `a_schema` and `a_array`, and `b_schema` and `b_array`, must point to
+/// valid Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_pairwise_euclidean(
+    a_schema: *const ArrowCDataInterfaceSchema,
+    a_array: *const ArrowCDataInterfaceArray,
+    b_schema: *const ArrowCDataInterfaceSchema,
+    b_array: *const ArrowCDataInterfaceArray,
+    dims: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let dims = usize::try_from(dims)
+            .map_err(|_| Error::InvalidArgument(format!("negative dimensions: {dims}")))?;
+        let a_schema = Schema::from_ffi(&*a_schema)?;
+        let a = ArrowArray::import(&a_schema, &*a_array)?;
+        let b_schema = Schema::from_ffi(&*b_schema)?;
+        let b = ArrowArray::import(&b_schema, &*b_array)?;
+        let (out, data) = pairwise_euclidean(&a, &b, dims, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn run(
+        a: &Exported,
+        b: &Exported,
+        dims: i64,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_pairwise_euclidean(
+                &a.schema,
+                &a.array,
+                &b.schema,
+                &b.array,
+                dims,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn distances_from_every_point_of_a_first() {
+        let a = Exported::primitive(&[0.0, 0.0, 3.0, 4.0]);
+        let b = Exported::primitive(&[0.0, 0.0, 3.0, 0.0, 6.0, 8.0]);
+        let out = run(&a, &b, 2, &ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(out.values::<f64>(), [0.0, 3.0, 10.0, 5.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn blocks_match_the_direct_computation() {
+        let coordinates: Vec<f64> = (0..3 * 300)
+            .map(|i| ((i * 37) % 101) as f64 / 7.0)
+            .collect();
+        let a = Exported::primitive(&coordinates[..3 * 70]);
+        let b = Exported::primitive(&coordinates);
+        let options = ArrowUdfExecOptions {
+            num_threads: 4,
+            batch_size: 1,
+            ..ArrowUdfExecOptions::default()
+        };
+        let distances = run(&a, &b, 3, &options).unwrap().values::<f64>();
+        assert_eq!(distances.len(), 70 * 300);
+        for (i, j) in [(0, 0), (31, 255), (32, 256), (69, 299), (45, 17)] {
+            let p = &coordinates[i * 3..i * 3 + 3];
+            let q = &coordinates[j * 3..j * 3 + 3];
+            let expected = p
+                .iter()
+                .zip(q)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f64>()
+                .sqrt();
+            assert_eq!(distances[i * 300 + j], expected, "({i}, {j})");
+        }
+    }
+
+    #[test]
+    fn points_with_null_coordinates_are_null() {
+        let a = Exported::nullable(&[Some(0.0), Some(0.0), Some(1.0), None]);
+        let b = Exported::nullable(&[Some(0.0), Some(1.0), None, Some(0.0)]);
+        let out = run(&a, &b, 2, &ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(out.nullable_values::<f64>(), [Some(1.0), None, None, None]);
+        let error = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(
+            run(&a, &b, 2, &error).err(),
+            Some(ArrowUdfStatus::NullValue)
+        );
+        let empty = Exported::primitive::<f64>(&[]);
+        let out = run(&a, &empty, 2, &ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(out.values::<f64>(), []);
+    }
+
+    #[test]
+    fn invalid_dimensions_fail() {
+        let a = Exported::primitive(&[0.0, 1.0, 2.0]);
+        let options = ArrowUdfExecOptions::default();
+        for dims in [0, -1, 2] {
+            assert_eq!(
+                run(&a, &a, dims, &options).err(),
+                Some(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        let integers = Exported::primitive(&[0_i64, 1]);
+        let status = run(&integers, &a, 1, &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+    }
+}
@@ -0,0 +1,263 @@
+//! Regular expression kernels over Utf8 arrays.
+//!
+//! Patterns use the syntax of the `regex` crate, and are compiled once per
+//! call. When the call receives a context, the compiled patterns are kept in
+//! it, so hosts evaluating the same pattern over many batches only compile
+//! it once.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+use std::sync::Mutex;
+
+use regex::Regex;
+
+use crate::array::ArrowArray;
+use crate::binary::Utf8Builder;
+use crate::bitmap::BitmapBuilder;
+use crate::error::{ArrowUdfStatus, Error, Result};
+use crate::export::ArrayData;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::udf;
+use crate::utf8::{for_each_str, utf8_ffi};
+
+/// Maximum number of patterns kept in a context. When it's reached, the
+/// cache is emptied, so hosts generating patterns don't grow it forever.
+const MAX_CACHED_PATTERNS: usize = 256;
+
+/// Patterns compiled by the calls made with a context.
+#[derive(Default)]
+struct CompiledPatterns(Mutex<HashMap<String, Regex>>);
+
+/// The compiled `pattern`, from the context of the call if it was already
+/// compiled with it.
+pub(crate) fn compile(pattern: &str, options: &ArrowUdfExecOptions) -> Result<Regex> {
+    let compile = || {
+        Regex::new(pattern).map_err(|error| {
+            Error::InvalidArgument(format!("invalid pattern {pattern:?}: {error}"))
+        })
+    };
+    let Some(context) = options.context() else {
+        return compile();
+    };
+    let cache = context.get_or_insert_with(CompiledPatterns::default);
+    if let Some(regex) = cache.0.lock().unwrap().get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = compile()?;
+    let mut patterns = cache.0.lock().unwrap();
+    if patterns.len() >= MAX_CACHED_PATTERNS {
+        patterns.clear();
+    }
+    patterns.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// Whether every string of `array` contains a match of `regex`, as a
+/// Boolean array.
+pub fn regex_match(
+    array: &ArrowArray,
+    regex: &Regex,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let mut found = BitmapBuilder::with_capacity(array.len());
+    for_each_str(array, options, |value| {
+        found.push(value.is_some_and(|value| regex.is_match(value)))
+    })?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok((
+        Schema::new(ArrowType::Boolean, &array.schema().name),
+        ArrayData::primitive(found.finish(), array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// The text matched by the capture group `group` of the first match of
+/// `regex` in every string of `array`, as a Utf8 array. The group 0 is the
+/// whole match. Strings without a match, or where the group didn't
+/// participate in the match, are null in the result.
+pub fn regex_extract(
+    array: &ArrowArray,
+    regex: &Regex,
+    group: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if group >= regex.captures_len() {
+        return Err(Error::InvalidArgument(format!(
+            "the pattern {:?} has no group {group}",
+            regex.as_str()
+        )));
+    }
+    let mut builder = Utf8Builder::with_capacity(array.len());
+    for_each_str(array, options, |value| {
+        let matched = value
+            .and_then(|value| regex.captures(value))
+            .and_then(|captures| captures.get(group));
+        builder.push(matched.map(|matched| matched.as_str()))
+    })?;
+    Ok((
+        Schema::new(ArrowType::Utf8, &array.schema().name),
+        builder.finish(),
+    ))
+}
+
+unsafe fn pattern_arg<'a>(pattern: *const c_char) -> Result<&'a str> {
+    CStr::from_ptr(pattern)
+        .to_str()
+        .map_err(|_| Error::InvalidArgument("the pattern is not valid UTF-8".to_string()))
+}
+
+/// Whether every string of a Utf8 array contains a match of the regular
+/// expression `pattern`, as a Boolean array. Use `^` and `$` to match the
+/// whole string.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `pattern` must be a valid null-terminated string, `options` must be null
+/// or valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_regex_match(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    pattern: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let regex = compile(pattern_arg(pattern)?, options)?;
+            regex_match(array, &regex, options)
+        },
+    )
+}
+
+/// The text matched by the capture group `group` of the regular expression
+/// `pattern` in every string of a Utf8 array, as a Utf8 array, or null when
+/// there is no match. The group 0 is the whole match.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `pattern` must be a valid null-terminated string, `options` must be null
+/// or valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_regex_extract(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    pattern: *const c_char,
+    group: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let group = usize::try_from(group).map_err(|_| {
+                Error::InvalidArgument(format!("the group can't be negative, got {group}"))
+            })?;
+            let regex = compile(pattern_arg(pattern)?, options)?;
+            regex_extract(array, &regex, group, options)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+    use crate::context::UdfContext;
+    use crate::testing::Exported;
+
+    fn extract(
+        input: &Exported,
+        pattern: &str,
+        group: i64,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let pattern = CString::new(pattern).unwrap();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_regex_extract(
+                &input.schema,
+                &input.array,
+                pattern.as_ptr(),
+                group,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn matches_anywhere_in_the_strings() {
+        let input = Exported::utf8(&[Some("id-42"), Some("none"), None, Some("7")]);
+        let pattern = CString::new(r"\d+").unwrap();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            let pattern = pattern.as_ptr();
+            arrow_udf_regex_match(
+                schema,
+                array,
+                pattern,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.booleans(), [Some(true), Some(false), None, Some(true)]);
+    }
+
+    #[test]
+    fn groups_are_extracted_or_null() {
+        let input = Exported::utf8(&[Some("a=1"), Some("b="), Some("c"), None]);
+        let whole = extract(&input, r"(\w)=(\d)?", 0).unwrap();
+        assert_eq!(
+            whole.strings(),
+            [Some("a=1".to_string()), Some("b=".to_string()), None, None]
+        );
+        let digits = extract(&input, r"(\w)=(\d)?", 2).unwrap();
+        assert_eq!(digits.strings(), [Some("1".to_string()), None, None, None]);
+        for (pattern, group) in [(r"(\w)", 2), (r"(\w)", -1), ("(", 0)] {
+            let status = extract(&input, pattern, group).err();
+            assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        }
+    }
+
+    #[test]
+    fn patterns_are_cached_in_the_context() {
+        let context = UdfContext::new();
+        let options = ArrowUdfExecOptions {
+            context: &context,
+            ..ArrowUdfExecOptions::default()
+        };
+        compile("a+", &options).unwrap();
+        let cache = context.get::<CompiledPatterns>().unwrap();
+        assert!(cache.0.lock().unwrap().contains_key("a+"));
+        for i in 0..MAX_CACHED_PATTERNS {
+            compile(&format!("b{i}"), &options).unwrap();
+        }
+        let patterns = cache.0.lock().unwrap();
+        assert!(patterns.len() <= MAX_CACHED_PATTERNS);
+        assert!(!patterns.contains_key("a+"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Several registered functions applied to an array in a single pass.
+//!
+//! Instead of calling one entry point per function, and exporting and
+//! importing the intermediate arrays, the host passes a plan with the
+//! functions to apply in order. Every batch goes through all the functions
+//! before the next one is processed.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::registry::{self, ScalarFunction};
+use crate::schema::{ArrowType, Schema};
+use crate::udf;
+
+/// Step of a plan, as received from the host: the id of a registered
+/// function, and its scalar arguments.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArrowUdfPlanStep {
+    pub function_id: u32,
+    pub n_args: i64,
+    pub args: *const i64,
+}
+
+/// Validated list of functions and their arguments.
+pub struct Plan {
+    steps: Vec<(&'static ScalarFunction, Vec<i64>)>,
+}
+
+impl Plan {
+    pub fn new(steps: Vec<(&'static ScalarFunction, Vec<i64>)>) -> Result<Plan> {
+        for (function, args) in &steps {
+            if args.len() != function.n_args {
+                return Err(Error::InvalidArgument(format!(
+                    "function {} expects {} arguments, got {}",
+                    function.name,
+                    function.n_args,
+                    args.len()
+                )));
+            }
+        }
+        Ok(Plan { steps })
+    }
+
+    /// # Safety
+    ///
+    /// `steps` must point to `n_steps` valid steps.
+    pub unsafe fn from_ffi(steps: *const ArrowUdfPlanStep, n_steps: i64) -> Result<Plan> {
+        if n_steps < 0 || (steps.is_null() && n_steps > 0) {
+            return Err(Error::InvalidArgument("invalid plan".to_string()));
+        }
+        let steps = if n_steps == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(steps, n_steps as usize)
+        };
+        let steps = steps
+            .iter()
+            .map(|step| {
+                let function = registry::get(step.function_id).ok_or_else(|| {
+                    Error::InvalidArgument(format!("unknown function id {}", step.function_id))
+                })?;
+                let args = match step.n_args {
+                    0 => Vec::new(),
+                    n if n > 0 && !step.args.is_null() => {
+                        std::slice::from_raw_parts(step.args, n as usize).to_vec()
+                    }
+                    _ => return Err(Error::InvalidArgument("invalid plan arguments".to_string())),
+                };
+                Ok((function, args))
+            })
+            .collect::<Result<_>>()?;
+        Plan::new(steps)
+    }
+
+    /// Apply all the steps, in order, to `values`.
+    pub fn apply(&self, values: &mut [i64]) {
+        for (function, args) in &self.steps {
+            (function.apply)(values, args);
+        }
+    }
+}
+
+/// Apply `plan` to an Int64 array. Null elements are null in the result.
+pub fn run(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    plan: &Plan,
+) -> Result<(Schema, ArrayData)> {
+    let name = &array.schema().name;
+    if let Some(mut value) = udf::constant_input::<i64>(array, options) {
+        plan.apply(std::slice::from_mut(&mut value));
+        return Ok((
+            export::constant_schema(ArrowType::Int64, name),
+            ArrayData::constant(value, array.len()),
+        ));
+    }
+    udf::check_input::<i64>(array, options)?;
+    let input = array.values::<i64>();
+    let mut values = Buffer::from_slice(input);
+    exec::map(values.typed_data_mut::<i64>(), options, |_, out| {
+        plan.apply(out);
+        Ok(())
+    })?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok((
+        Schema::new(ArrowType::Int64, name),
+        ArrayData::primitive(values, array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Apply the `n_steps` functions in `steps` to an Int64 array, in a single
+/// pass.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `steps` must point to `n_steps` valid steps, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_pipeline(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    steps: *const ArrowUdfPlanStep,
+    n_steps: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let plan = Plan::from_ffi(steps, n_steps)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = run(&array, &options, &plan)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn step(name: &str, args: &[i64]) -> ArrowUdfPlanStep {
+        ArrowUdfPlanStep {
+            function_id: registry::lookup(name).unwrap(),
+            n_args: args.len() as i64,
+            args: if args.is_empty() {
+                ptr::null()
+            } else {
+                args.as_ptr()
+            },
+        }
+    }
+
+    #[test]
+    fn steps_are_applied_in_order() {
+        let input = Exported::nullable(&[Some(-3_i64), None, Some(4)]);
+        let (add, multiply) = ([1], [2]);
+        let steps = [
+            step("add", &add),
+            step("abs", &[]),
+            step("multiply", &multiply),
+        ];
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_pipeline(
+                &input.schema,
+                &input.array,
+                steps.as_ptr(),
+                steps.len() as i64,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.nullable_values::<i64>(), [Some(4), None, Some(10)]);
+    }
+
+    #[test]
+    fn empty_plans_copy_the_input() {
+        let input = Exported::primitive(&[1_i64, 2]);
+        let plan = unsafe { Plan::from_ffi(ptr::null(), 0) }.unwrap();
+        let (schema, data) = input
+            .with_array(|array| run(array, &ArrowUdfExecOptions::default(), &plan))
+            .unwrap();
+        assert_eq!(Exported::new(&schema, data).values::<i64>(), [1, 2]);
+    }
+
+    #[test]
+    fn invalid_plans_fail() {
+        let input = Exported::primitive(&[1_i64]);
+        let steps = [step("add", &[])];
+        let unknown = [ArrowUdfPlanStep {
+            function_id: u32::MAX,
+            n_args: 0,
+            args: ptr::null(),
+        }];
+        for (steps, n_steps) in [(steps.as_ptr(), 1), (unknown.as_ptr(), 1), (ptr::null(), 1)] {
+            let mut out = Exported::empty();
+            let status = unsafe {
+                arrow_udf_pipeline(
+                    &input.schema,
+                    &input.array,
+                    steps,
+                    n_steps,
+                    ptr::null(),
+                    &mut out.schema,
+                    &mut out.array,
+                )
+            };
+            assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Exact quantiles of numeric arrays, computed by selection.
+//!
+//! The non-null values are copied, and the one or two values around the
+//! requested position are found with `select_nth_unstable`, in linear time
+//! on average, without sorting the whole array. The interpolation between
+//! them follows the modes of `numpy.quantile`. Use the `approx_quantile`
+//! aggregate for a bounded memory approximation instead.
+
+use crate::array::ArrowArray;
+use crate::dispatch::{self, Kernel};
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::nan;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::{NativeType, Numeric};
+use crate::udf;
+
+pub const ARROW_UDF_INTERPOLATION_LINEAR: i32 = 0;
+pub const ARROW_UDF_INTERPOLATION_LOWER: i32 = 1;
+pub const ARROW_UDF_INTERPOLATION_HIGHER: i32 = 2;
+pub const ARROW_UDF_INTERPOLATION_NEAREST: i32 = 3;
+pub const ARROW_UDF_INTERPOLATION_MIDPOINT: i32 = 4;
+
+/// How the quantile is computed when it falls between two values, `i` and
+/// `j`, with `i <= j`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// `i + (j - i) * fraction`, where `fraction` is the fractional part of
+    /// the position.
+    Linear,
+    Lower,
+    Higher,
+    /// `i` or `j`, whichever is nearest, rounding halves to the even position.
+    Nearest,
+    Midpoint,
+}
+
+impl Interpolation {
+    pub fn from_ffi(interpolation: i32) -> Result<Interpolation> {
+        match interpolation {
+            ARROW_UDF_INTERPOLATION_LINEAR => Ok(Interpolation::Linear),
+            ARROW_UDF_INTERPOLATION_LOWER => Ok(Interpolation::Lower),
+            ARROW_UDF_INTERPOLATION_HIGHER => Ok(Interpolation::Higher),
+            ARROW_UDF_INTERPOLATION_NEAREST => Ok(Interpolation::Nearest),
+            ARROW_UDF_INTERPOLATION_MIDPOINT => Ok(Interpolation::Midpoint),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown interpolation {other}"
+            ))),
+        }
+    }
+}
+
+/// The non-null values of `array`.
+fn valid_values<T: NativeType>(array: &ArrowArray) -> Vec<T> {
+    let values = array.values::<T>();
+    match array.validity().filter(|_| array.null_count() > 0) {
+        Some(validity) => values
+            .iter()
+            .zip(validity.iter())
+            .filter(|(_, valid)| *valid)
+            .map(|(value, _)| *value)
+            .collect(),
+        None => values.to_vec(),
+    }
+}
+
+/// The quantile `q` of the non-null values of `array`. NaN if there are no
+/// values, or if any of them is NaN, like `numpy.quantile`, unless the NaN
+/// policy of `options` skips them. Also NaN, the result when there are no
+/// values, if there are nulls and the null policy propagates them.
+pub fn quantile<T: Numeric>(
+    array: &ArrowArray,
+    q: f64,
+    interpolation: Interpolation,
+    options: &ArrowUdfExecOptions,
+) -> Result<f64> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(Error::InvalidArgument(format!(
+            "quantile must be between 0 and 1, got {q}"
+        )));
+    }
+    udf::check_input::<T>(array, options)?;
+    if udf::null_result(array, options)? {
+        return Ok(f64::NAN);
+    }
+    let masked = nan::mask_non_finite(array, options)?;
+    let array = &masked
+        .as_ref()
+        .map_or(*array, |masked| masked.array(array.schema()));
+    let mut values = valid_values::<T>(array);
+    if values.is_empty() || values.iter().any(|value| value.is_nan()) {
+        return Ok(f64::NAN);
+    }
+    let position = q * (values.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let fraction = position - lower as f64;
+    let (_, low, higher) = values.select_nth_unstable_by(lower, |a, b| a.total_cmp(b));
+    let low = low.to_f64();
+    // The next value is the minimum of the ones after the lower one.
+    let high = match higher.iter().min_by(|a, b| a.total_cmp(b)) {
+        Some(high) if fraction > 0.0 => high.to_f64(),
+        _ => low,
+    };
+    options.check_cancelled()?;
+    Ok(match interpolation {
+        Interpolation::Linear => low + (high - low) * fraction,
+        Interpolation::Lower => low,
+        Interpolation::Higher => high,
+        Interpolation::Nearest if position.round_ties_even() as usize == lower => low,
+        Interpolation::Nearest => high,
+        Interpolation::Midpoint => (low + high) / 2.0,
+    })
+}
+
+struct Quantile;
+
+impl Kernel for Quantile {
+    type Args = (f64, Interpolation);
+    type Output = f64;
+
+    fn name(_: &(f64, Interpolation)) -> String {
+        "quantile".to_string()
+    }
+
+    fn call<T: Numeric>(
+        array: &ArrowArray,
+        (q, interpolation): &(f64, Interpolation),
+        options: &ArrowUdfExecOptions,
+    ) -> Result<f64> {
+        quantile::<T>(array, *q, *interpolation, options)
+    }
+}
+
+/// Exact quantile `q`, between 0 and 1, of the non-null values of a numeric
+/// array, written into `out`. `interpolation` is one of the
+/// `ARROW_UDF_INTERPOLATION_*` constants. The result is NaN if there are no
+/// values, if any of them is NaN, or if there are nulls and the null policy
+/// propagates them. When the options export the result as an array, it's
+/// null instead if there are no values other than nulls, or nulls
+/// propagated.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out` must be valid for writes, or
+/// null if the options export the result.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_quantile(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    q: f64,
+    interpolation: i32,
+    options: *const ArrowUdfExecOptions,
+    out: *mut f64,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let interpolation = Interpolation::from_ffi(interpolation)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let no_values = array.null_count() == array.len() || udf::null_result(&array, &options)?;
+        if options.exports_scalar() && no_values {
+            return export::export_scalar::<f64>(&options, &schema.name, None);
+        }
+        let quantile = dispatch::call::<Quantile>(&array, &(q, interpolation), &options)?;
+        export::write_reduction(&options, &schema.name, quantile, out)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn run(
+        input: &Exported,
+        q: f64,
+        interpolation: i32,
+    ) -> std::result::Result<f64, ArrowUdfStatus> {
+        let mut out = 0.0;
+        let status = unsafe {
+            arrow_udf_quantile(
+                &input.schema,
+                &input.array,
+                q,
+                interpolation,
+                ptr::null(),
+                &mut out,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn interpolations_match_numpy() {
+        let input = Exported::nullable(&[Some(4_i32), None, Some(1), Some(3), Some(2)]);
+        for (q, interpolation, expected) in [
+            (0.5, ARROW_UDF_INTERPOLATION_LINEAR, 2.5),
+            (0.5, ARROW_UDF_INTERPOLATION_LOWER, 2.0),
+            (0.5, ARROW_UDF_INTERPOLATION_HIGHER, 3.0),
+            (0.5, ARROW_UDF_INTERPOLATION_NEAREST, 3.0),
+            (0.5, ARROW_UDF_INTERPOLATION_MIDPOINT, 2.5),
+            (0.4, ARROW_UDF_INTERPOLATION_LINEAR, 2.2),
+            (0.4, ARROW_UDF_INTERPOLATION_NEAREST, 2.0),
+            (0.0, ARROW_UDF_INTERPOLATION_HIGHER, 1.0),
+            (1.0, ARROW_UDF_INTERPOLATION_LOWER, 4.0),
+        ] {
+            let result = run(&input, q, interpolation).unwrap();
+            assert!(
+                (result - expected).abs() < 1e-12,
+                "{q} {interpolation}: {result}"
+            );
+        }
+    }
+
+    #[test]
+    fn nan_without_values_or_with_nan() {
+        let empty = Exported::nullable::<f32>(&[None]);
+        assert!(run(&empty, 0.5, ARROW_UDF_INTERPOLATION_LINEAR)
+            .unwrap()
+            .is_nan());
+        let nan = Exported::primitive(&[1.0_f64, f64::NAN]);
+        assert!(run(&nan, 0.0, ARROW_UDF_INTERPOLATION_LOWER)
+            .unwrap()
+            .is_nan());
+        let skip = ArrowUdfExecOptions {
+            nan_policy: crate::options::ARROW_UDF_NAN_POLICY_SKIP,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut out = 0.0;
+        let status = unsafe {
+            let interpolation = ARROW_UDF_INTERPOLATION_LOWER;
+            arrow_udf_quantile(&nan.schema, &nan.array, 0.0, interpolation, &skip, &mut out)
+        };
+        assert_eq!((status, out), (ArrowUdfStatus::Ok, 1.0));
+    }
+
+    #[test]
+    fn invalid_quantiles_fail() {
+        let input = Exported::primitive(&[1_u64]);
+        for (q, interpolation) in [(1.5, 0), (-0.1, 0), (f64::NAN, 0), (0.5, 5)] {
+            assert_eq!(
+                run(&input, q, interpolation),
+                Err(ArrowUdfStatus::InvalidArgument)
+            );
+        }
+        assert_eq!(
+            run(&Exported::boolean(&[true]), 0.5, 0),
+            Err(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+
+    #[test]
+    fn nulls_are_propagated() {
+        let propagate = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let input = Exported::nullable(&[Some(1_i32), None, Some(3)]);
+        let result = input
+            .with_array(|array| quantile::<i32>(array, 0.5, Interpolation::Linear, &propagate));
+        assert!(result.unwrap().is_nan());
+        let valid = Exported::primitive(&[1_i32, 3]);
+        let result = valid
+            .with_array(|array| quantile::<i32>(array, 0.5, Interpolation::Linear, &propagate));
+        assert_eq!(result.unwrap(), 2.0);
+    }
+
+    #[test]
+    fn quantiles_of_no_values_are_exported_as_null() {
+        for (values, expected) in [
+            (vec![Some(1.0_f64), None, Some(3.0)], Some(2.0)),
+            (vec![None, None], None),
+        ] {
+            let input = Exported::nullable(&values);
+            let mut scalar = Exported::empty();
+            let options = ArrowUdfExecOptions {
+                scalar_schema: &mut scalar.schema,
+                scalar_array: &mut scalar.array,
+                ..ArrowUdfExecOptions::default()
+            };
+            let status = unsafe {
+                arrow_udf_quantile(
+                    &input.schema,
+                    &input.array,
+                    0.5,
+                    ARROW_UDF_INTERPOLATION_LINEAR,
+                    &options,
+                    ptr::null_mut(),
+                )
+            };
+            assert_eq!(status, ArrowUdfStatus::Ok);
+            assert_eq!(scalar.nullable_values::<f64>(), [expected]);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Random numbers for stochastic UDFs, reproducible from a seed given by
+//! the host.
+//!
+//! Sampling and jitter UDFs need random numbers, but engines retrying a
+//! query, or comparing two runs, need the same rows to get the same
+//! numbers. The `seed` option sets the seed of the call, and
+//! `partition_id` the partition of the data it receives, mixed into the
+//! seed so every partition gets different numbers with the same seed. The
+//! generator of every row is derived from the seed and the position of the
+//! row, so its numbers don't depend on the batch size or on the threads.
+//! A seed of zero picks a different seed for every call.
+//!
+//! The generator is SplitMix64: fast, and good enough for sampling, but not
+//! for cryptography.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::{with_native_type, Numeric};
+use crate::udf;
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The SplitMix64 finalizer, mixing the bits of `value`.
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// The seed of a call, from the `seed` and `partition_id` options, or a
+/// new random seed if `seed` is zero.
+pub fn call_seed(options: &ArrowUdfExecOptions) -> u64 {
+    match options.seed {
+        0 => RandomState::new().hash_one(options.partition_id),
+        seed => mix(seed ^ mix(options.partition_id.wrapping_add(GOLDEN_GAMMA))),
+    }
+}
+
+/// Generator of random numbers.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Generator of the row at position `index` of a call with `seed`, as
+    /// returned by `call_seed`.
+    pub fn for_row(seed: u64, index: usize) -> Rng {
+        Rng::new(mix(seed ^ (index as u64).wrapping_mul(GOLDEN_GAMMA)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        mix(self.state)
+    }
+
+    /// Uniform float in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform integer in `[0, n)`, for `n` greater than zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // Lemire's multiply and shift, rejecting the values that would make
+        // the smallest results more likely.
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let product = u128::from(self.next_u64()) * u128::from(n);
+            if product as u64 >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+}
+
+/// `array` as Float64 with uniform noise in `[-amplitude, amplitude)` added
+/// to every value.
+pub fn jitter(
+    array: &ArrowArray,
+    amplitude: f64,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    fn jitter_values<T: Numeric>(
+        array: &ArrowArray,
+        amplitude: f64,
+        options: &ArrowUdfExecOptions,
+    ) -> Result<(Schema, ArrayData)> {
+        udf::map_random(array, options, |value: T, rng| {
+            value.to_f64() + amplitude * (2.0 * rng.next_f64() - 1.0)
+        })
+    }
+    if !amplitude.is_finite() || amplitude < 0.0 {
+        return Err(Error::InvalidArgument(format!(
+            "the amplitude must be finite and zero or positive, got {amplitude}"
+        )));
+    }
+    with_native_type!(array.data_type(), T => {
+        jitter_values::<T>(array, amplitude, options)
+    }, _ => Err(Error::UnsupportedType(format!(
+        "jitter of {:?} arrays",
+        array.data_type()
+    ))))
+}
+
+/// Float64 copy of a numeric array with uniform noise in
+/// `[-amplitude, amplitude)` added to every value, from the `seed` and
+/// `partition_id` options. Null elements are null in the result.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_jitter(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    amplitude: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = jitter(&array, amplitude, &options)?;
+        export::export_to(&out, &data.into(), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn run(input: &Exported, amplitude: f64, options: &ArrowUdfExecOptions) -> Vec<Option<f64>> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_jitter(
+                &input.schema,
+                &input.array,
+                amplitude,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        out.nullable_values::<f64>()
+    }
+
+    #[test]
+    fn noise_is_reproducible_from_the_seed_and_partition() {
+        let values: Vec<Option<i32>> = (0..1000).map(|i| (i % 7 != 0).then_some(i)).collect();
+        let input = Exported::nullable(&values);
+        let seeded = ArrowUdfExecOptions {
+            seed: 42,
+            ..ArrowUdfExecOptions::default()
+        };
+        let jittered = run(&input, 0.5, &seeded);
+        for (value, noisy) in values.iter().zip(&jittered) {
+            match (value, noisy) {
+                (Some(value), Some(noisy)) => {
+                    assert!((noisy - *value as f64).abs() <= 0.5, "{value} {noisy}")
+                }
+                (value, noisy) => assert_eq!((value, noisy), (&None, &None)),
+            }
+        }
+        // Neither the batches nor the threads change the numbers of a row.
+        let batched = ArrowUdfExecOptions {
+            batch_size: 13,
+            num_threads: 4,
+            ..seeded
+        };
+        assert_eq!(run(&input, 0.5, &batched), jittered);
+        let partition = ArrowUdfExecOptions {
+            partition_id: 1,
+            ..seeded
+        };
+        assert_ne!(run(&input, 0.5, &partition), jittered);
+        let unseeded = ArrowUdfExecOptions::default();
+        assert_ne!(run(&input, 0.5, &unseeded), run(&input, 0.5, &unseeded));
+        assert_eq!(
+            run(&input, 0.0, &unseeded),
+            values
+                .iter()
+                .map(|value| value.map(f64::from))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn generators_are_uniform_within_their_ranges() {
+        let mut rng = Rng::new(7);
+        let mut counts = [0; 6];
+        for _ in 0..6000 {
+            let value = rng.next_f64();
+            assert!((0.0..1.0).contains(&value));
+            counts[rng.below(6) as usize] += 1;
+        }
+        assert!(
+            counts.iter().all(|count| (800..1200).contains(count)),
+            "{counts:?}"
+        );
+        assert_eq!(Rng::for_row(1, 5).next_u64(), Rng::for_row(1, 5).next_u64());
+        assert_ne!(Rng::for_row(1, 5).next_u64(), Rng::for_row(1, 6).next_u64());
+    }
+
+    #[test]
+    fn invalid_amplitudes_and_types_fail() {
+        let input = Exported::primitive(&[1.0_f64]);
+        for amplitude in [-1.0, f64::NAN, f64::INFINITY] {
+            let mut out = Exported::empty();
+            let status = unsafe {
+                arrow_udf_jitter(
+                    &input.schema,
+                    &input.array,
+                    amplitude,
+                    ptr::null(),
+                    &mut out.schema,
+                    &mut out.array,
+                )
+            };
+            assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+        }
+        let strings = Exported::utf8(&[Some("a")]);
+        let result = strings
+            .with_array(|array| jitter(array, 1.0, &ArrowUdfExecOptions::default()).map(|_| ()));
+        assert!(matches!(result, Err(Error::UnsupportedType(_))));
+    }
+}
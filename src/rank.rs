@@ -0,0 +1,310 @@
+//! Ranks of the rows of an array, for the `RANK`, `DENSE_RANK` and
+//! `ROW_NUMBER` window functions.
+//!
+//! The rank of every row is its position, starting at 1, in the array
+//! sorted in ascending or descending order, with the ties resolved by the
+//! method: the lowest or the highest position of the tied rows, the number
+//! of distinct values up to the row, or the position itself, in the order
+//! of the rows. Nulls are ranked together, before or after the other rows,
+//! or are null in the result when the null policy propagates them.
+//!
+//! Hosts whose partitions are already sorted by the column, in the same
+//! order, pass the `ARROW_UDF_FLAG_SORTED` flag to skip the sort. The ranks
+//! are then computed in a single pass, which fails if the rows aren't
+//! sorted.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::sort;
+use crate::types::{with_native_type, TotalOrd};
+use crate::udf;
+
+/// The input is already sorted in the order of the ranks.
+pub const ARROW_UDF_FLAG_SORTED: u32 = 128;
+
+pub const ARROW_UDF_RANK_MIN: i32 = 0;
+pub const ARROW_UDF_RANK_DENSE: i32 = 1;
+pub const ARROW_UDF_RANK_FIRST: i32 = 2;
+pub const ARROW_UDF_RANK_MAX: i32 = 3;
+
+/// Rank given to tied rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RankMethod {
+    /// The lowest position of the ties, like `RANK`.
+    Min,
+    /// The number of distinct values up to the ties, like `DENSE_RANK`.
+    Dense,
+    /// The position of every row, ties in the order of the rows, like
+    /// `ROW_NUMBER`.
+    First,
+    /// The highest position of the ties.
+    Max,
+}
+
+impl RankMethod {
+    pub fn from_ffi(method: i32) -> Result<RankMethod> {
+        match method {
+            ARROW_UDF_RANK_MIN => Ok(RankMethod::Min),
+            ARROW_UDF_RANK_DENSE => Ok(RankMethod::Dense),
+            ARROW_UDF_RANK_FIRST => Ok(RankMethod::First),
+            ARROW_UDF_RANK_MAX => Ok(RankMethod::Max),
+            other => Err(Error::InvalidArgument(format!(
+                "unknown rank method {other}"
+            ))),
+        }
+    }
+}
+
+/// Compare two rows of `array` in the order of the ranks, with the nulls
+/// equal to each other.
+fn row_cmp<'a, T: TotalOrd>(
+    array: &ArrowArray<'a>,
+    descending: bool,
+    nulls_first: bool,
+) -> impl Fn(usize, usize) -> Ordering + 'a {
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    move |a, b| {
+        let valid = |row: usize| validity.is_none_or(|validity| validity.is_set(row));
+        match (valid(a), valid(b)) {
+            (true, true) if descending => values[b].total_cmp(&values[a]),
+            (true, true) => values[a].total_cmp(&values[b]),
+            (false, false) => Ordering::Equal,
+            (false, true) if nulls_first => Ordering::Less,
+            (true, false) if nulls_first => Ordering::Greater,
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+}
+
+/// The rank of every row of `array`, with the ties ranked by `method`.
+/// Null rows are ranked 0 when the null policy propagates them.
+pub fn rank(
+    array: &ArrowArray,
+    method: RankMethod,
+    descending: bool,
+    nulls_first: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<i64>> {
+    let sorted = options.flags & ARROW_UDF_FLAG_SORTED != 0;
+    let mut order: Vec<usize> = match sorted {
+        true => (0..array.len()).collect(),
+        false => sort::sort_indices(array, descending, nulls_first, options)?
+            .into_iter()
+            .map(|row| row as usize)
+            .collect(),
+    };
+    if udf::null_result(array, options)? {
+        order.retain(|row| array.is_valid(*row));
+    }
+    let mut ranks = vec![0; array.len()];
+    with_native_type!(array.data_type(), T => {
+        udf::check_input::<T>(array, options)?;
+        let cmp = row_cmp::<T>(array, descending, nulls_first);
+        let mut start = 0;
+        let mut dense = 0;
+        while start < order.len() {
+            let mut end = start + 1;
+            while end < order.len() {
+                match cmp(order[end - 1], order[end]) {
+                    Ordering::Equal => end += 1,
+                    Ordering::Less => break,
+                    Ordering::Greater => {
+                        return Err(Error::InvalidArgument(format!(
+                            "the input isn't sorted at row {}, with the sorted flag",
+                            order[end]
+                        )))
+                    }
+                }
+            }
+            dense += 1;
+            for (position, row) in order.iter().enumerate().take(end).skip(start) {
+                ranks[*row] = match method {
+                    RankMethod::Min => start as i64 + 1,
+                    RankMethod::Dense => dense,
+                    RankMethod::First => position as i64 + 1,
+                    RankMethod::Max => end as i64,
+                };
+            }
+            start = end;
+        }
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "rank of {:?} arrays",
+        array.data_type()
+    ))));
+    options.check_cancelled()?;
+    options.check_memory()?;
+    Ok(ranks)
+}
+
+/// Int64 array with the rank of every row of a numeric array in ascending
+/// or descending order, with the nulls first or last. `method` is one of the
+/// `ARROW_UDF_RANK_*` constants. With the propagate null policy, null rows
+/// are null in the result, and don't take any rank.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_rank(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    method: i32,
+    descending: bool,
+    nulls_first: bool,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let method = RankMethod::from_ffi(method)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let ranks = rank(&array, method, descending, nulls_first, &options)?;
+        let mut data = ArrayData::primitive(Buffer::from_slice(&ranks), ranks.len());
+        if udf::null_result(&array, &options)? {
+            let mut validity = BitmapBuilder::with_capacity(array.len());
+            (0..array.len()).for_each(|row| validity.push(array.is_valid(row)));
+            data = data.with_validity(Some(validity.finish()), array.null_count());
+        }
+        let out = Schema::new(ArrowType::Int64, &schema.name);
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE;
+    use crate::testing::Exported;
+
+    fn run(
+        input: &Exported,
+        method: i32,
+        descending: bool,
+        nulls_first: bool,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Vec<Option<i64>>, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_rank(
+                &input.schema,
+                &input.array,
+                method,
+                descending,
+                nulls_first,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out.nullable_values::<i64>()),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn ties_are_ranked_by_the_method() {
+        let input = Exported::primitive(&[3_i32, 1, 3, 2, 1]);
+        let default = ArrowUdfExecOptions::default();
+        for (method, expected) in [
+            (ARROW_UDF_RANK_MIN, [4, 1, 4, 3, 1]),
+            (ARROW_UDF_RANK_DENSE, [3, 1, 3, 2, 1]),
+            (ARROW_UDF_RANK_FIRST, [4, 1, 5, 3, 2]),
+            (ARROW_UDF_RANK_MAX, [5, 2, 5, 3, 2]),
+        ] {
+            let expected = expected.map(Some).to_vec();
+            assert_eq!(run(&input, method, false, false, &default), Ok(expected));
+        }
+        assert_eq!(
+            run(&input, ARROW_UDF_RANK_MIN, true, false, &default),
+            Ok([1, 4, 1, 3, 4].map(Some).to_vec())
+        );
+    }
+
+    #[test]
+    fn nulls_are_ranked_together_or_propagated() {
+        let input = Exported::nullable(&[Some(2.0_f64), None, Some(f64::NAN), None, Some(-1.0)]);
+        let default = ArrowUdfExecOptions::default();
+        assert_eq!(
+            run(&input, ARROW_UDF_RANK_MIN, false, false, &default),
+            Ok([2, 4, 3, 4, 1].map(Some).to_vec())
+        );
+        assert_eq!(
+            run(&input, ARROW_UDF_RANK_DENSE, false, true, &default),
+            Ok([3, 1, 4, 1, 2].map(Some).to_vec())
+        );
+        let propagate = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(
+            run(&input, ARROW_UDF_RANK_FIRST, false, true, &propagate),
+            Ok(vec![Some(2), None, Some(3), None, Some(1)])
+        );
+    }
+
+    #[test]
+    fn sorted_inputs_are_ranked_in_a_single_pass() {
+        let sorted = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_SORTED,
+            ..ArrowUdfExecOptions::default()
+        };
+        let input = Exported::primitive(&[1_i64, 1, 4, 7, 7, 7]);
+        assert_eq!(
+            run(&input, ARROW_UDF_RANK_MIN, false, false, &sorted),
+            Ok([1, 1, 3, 4, 4, 4].map(Some).to_vec())
+        );
+        let descending = Exported::primitive(&[9_i64, 5, 5]);
+        assert_eq!(
+            run(&descending, ARROW_UDF_RANK_DENSE, true, false, &sorted),
+            Ok([1, 2, 2].map(Some).to_vec())
+        );
+        let unsorted = Exported::primitive(&[1_i64, 3, 2]);
+        assert_eq!(
+            run(&unsorted, ARROW_UDF_RANK_MIN, false, false, &sorted),
+            Err(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn invalid_methods_and_types_fail() {
+        let input = Exported::primitive(&[1_i64]);
+        assert_eq!(
+            run(&input, 4, false, false, &ArrowUdfExecOptions::default()),
+            Err(ArrowUdfStatus::InvalidArgument)
+        );
+        let strings = Exported::utf8(&[Some("a")]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_rank(
+                &strings.schema,
+                &strings.array,
+                ARROW_UDF_RANK_MIN,
+                false,
+                false,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+    }
+}
@@ -0,0 +1,295 @@
+//! Functions known by this library, that hosts can refer to by id, and the
+//! output types of all the functions.
+//!
+//! Ids are the position of the function in `FUNCTIONS`, and can be obtained
+//! from the name of the function with `arrow_udf_function_id`.
+//!
+//! Every entry point declares the type of its result with an `OutputType`,
+//! so planners can know the schema of a result before executing the
+//! function: `arrow_udf_output_schema` returns it for a function and the
+//! schema of its input, the same way `arrow_udf_check_schema` checks the
+//! input.
+
+use std::ffi::{c_char, CStr};
+
+use crate::check;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export;
+use crate::ffi::ArrowCDataInterfaceSchema;
+use crate::schema::{ArrowType, Schema};
+
+/// How the type of the result of a function is known from its input.
+pub enum OutputType {
+    /// Always the same type, whatever the input.
+    Fixed(ArrowType),
+    /// The type of the input, with its parameters, like the timezone of
+    /// timestamps.
+    SameAsInput,
+    /// Computed from the schema of the input.
+    Infer(fn(&Schema) -> Schema),
+    /// Given by the scalar arguments of the call, like a unit, so not known
+    /// from the input alone.
+    Arguments,
+}
+
+impl OutputType {
+    /// The schema of the result of a function named `function` for inputs
+    /// described by `input`, which it must support.
+    pub fn schema(&self, function: &str, input: &Schema) -> Result<Schema> {
+        match self {
+            OutputType::Fixed(data_type) => Ok(Schema::new(*data_type, &input.name)),
+            OutputType::SameAsInput => Ok(same_as(input)),
+            OutputType::Infer(infer) => Ok(infer(input)),
+            OutputType::Arguments => Err(Error::InvalidArgument(format!(
+                "the output type of {function} depends on its arguments"
+            ))),
+        }
+    }
+}
+
+/// The schema of `input` without its metadata, for results of its type.
+fn same_as(input: &Schema) -> Schema {
+    Schema {
+        metadata: Default::default(),
+        flags: Default::default(),
+        ..input.clone()
+    }
+}
+
+/// The schema of the first array of functions receiving several, described
+/// by the fields of a struct schema, for functions whose result has its type.
+pub(crate) fn first_field(input: &Schema) -> Schema {
+    same_as(&input.children[0])
+}
+
+/// Element-wise function over Int64 values, with Int64 scalar arguments.
+///
+/// It transforms a slice of values in place, so several functions can be
+/// applied one after the other over the same batch while it's in cache.
+pub struct ScalarFunction {
+    pub name: &'static str,
+    pub n_args: usize,
+    pub apply: fn(&mut [i64], &[i64]),
+}
+
+pub static FUNCTIONS: &[ScalarFunction] = &[
+    ScalarFunction {
+        name: "distance",
+        n_args: 1,
+        apply: |values, args| {
+            values
+                .iter_mut()
+                .for_each(|v| *v = v.wrapping_sub(args[0]).wrapping_abs())
+        },
+    },
+    ScalarFunction {
+        name: "add",
+        n_args: 1,
+        apply: |values, args| values.iter_mut().for_each(|v| *v = v.wrapping_add(args[0])),
+    },
+    ScalarFunction {
+        name: "multiply",
+        n_args: 1,
+        apply: |values, args| values.iter_mut().for_each(|v| *v = v.wrapping_mul(args[0])),
+    },
+    ScalarFunction {
+        name: "abs",
+        n_args: 0,
+        apply: |values, _| values.iter_mut().for_each(|v| *v = v.wrapping_abs()),
+    },
+];
+
+/// Id of the function named `name`.
+pub fn lookup(name: &str) -> Option<u32> {
+    FUNCTIONS
+        .iter()
+        .position(|function| function.name == name)
+        .map(|id| id as u32)
+}
+
+pub fn get(id: u32) -> Option<&'static ScalarFunction> {
+    FUNCTIONS.get(id as usize)
+}
+
+/// The schema of the result of `function`, named like in `check::check_schema`,
+/// for inputs described by `schema`. Fails with `Error::UnsupportedType` if
+/// the function doesn't support them, like `check_schema`, and with
+/// `Error::InvalidArgument` if the function is unknown or its output type
+/// depends on its arguments.
+pub fn output_schema(function: &str, schema: &Schema) -> Result<Schema> {
+    check::check_schema(function, schema)?;
+    let name = function.strip_prefix("arrow_udf_").unwrap_or(function);
+    check::output_schema(name, schema)
+}
+
+/// Write into `out_schema` the schema of the result of `function` for inputs
+/// described by `schema`, without executing it, so planners can know the
+/// schema of the result of a function pushed down to this library. Functions
+/// are named like in `arrow_udf_check_schema`. Functions returning a single
+/// value have the schema of an array of it.
+///
+/// Returns `ArrowUdfStatus::UnsupportedType` if the inputs aren't supported,
+/// and `ArrowUdfStatus::InvalidArgument` if the function is unknown or its
+/// output type depends on its arguments, like `arrow_udf_duration_cast`.
+///
+/// # Safety
+///
+/// `function` must be a valid null-terminated string, `schema` must point to
+/// a valid Arrow C Data Interface schema, and `out_schema` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_output_schema(
+    function: *const c_char,
+    schema: *const ArrowCDataInterfaceSchema,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let function = CStr::from_ptr(function).to_string_lossy();
+        let schema = Schema::from_ffi(&*schema)?;
+        let out = output_schema(&function, &schema)?;
+        out_schema.write(export::export_schema(&out));
+        Ok(())
+    })
+}
+
+/// Id of the function named `name`, or -1 if there is no such function.
+///
+/// # Safety
+///
+/// `name` must be a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_function_id(name: *const c_char) -> i64 {
+    CStr::from_ptr(name)
+        .to_str()
+        .ok()
+        .and_then(lookup)
+        .map_or(-1, |id| id as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions_are_found_by_name() {
+        let id = lookup("add").unwrap();
+        let mut values = [1, 2, 3];
+        (get(id).unwrap().apply)(&mut values, &[10]);
+        assert_eq!(values, [11, 12, 13]);
+        assert_eq!(lookup("unknown"), None);
+        assert!(get(FUNCTIONS.len() as u32).is_none());
+    }
+
+    #[test]
+    fn fused_steps_wrap_instead_of_overflowing() {
+        // Null slots of the plans may hold any value.
+        let mut values = [i64::MIN, 7, i64::MAX];
+        (get(lookup("distance").unwrap()).unwrap().apply)(&mut values, &[1]);
+        assert_eq!(values, [i64::MAX, 6, i64::MAX - 1]);
+    }
+
+    #[test]
+    fn function_ids_through_ffi() {
+        let id = unsafe { arrow_udf_function_id(c"abs".as_ptr()) };
+        assert_eq!(id, lookup("abs").unwrap() as i64);
+        assert_eq!(unsafe { arrow_udf_function_id(c"unknown".as_ptr()) }, -1);
+    }
+
+    fn output(function: &str, schema: &Schema) -> Result<Schema> {
+        output_schema(function, schema)
+    }
+
+    #[test]
+    fn output_types_of_every_kind_of_function() {
+        let int32 = Schema::new(ArrowType::Int32, "x");
+        let hashes = output("hash64", &int32).unwrap();
+        assert_eq!(hashes, Schema::new(ArrowType::UInt64, "x"));
+        assert_eq!(
+            output("arrow_udf_to_float64", &int32).unwrap(),
+            Schema::new(ArrowType::Float64, "x")
+        );
+        let histogram = output("histogram", &int32).unwrap();
+        assert_eq!(histogram.data_type, ArrowType::Struct);
+        assert_eq!(histogram.children[2].name, "count");
+        let sum = output("sum", &Schema::new(ArrowType::Int64, "x")).unwrap();
+        assert_eq!(sum.data_type, ArrowType::Int64);
+    }
+
+    #[cfg(feature = "temporal")]
+    #[test]
+    fn outputs_like_the_input_keep_its_parameters() {
+        let mut timestamps =
+            Schema::new(ArrowType::Timestamp(crate::schema::TimeUnit::Second), "t")
+                .with_metadata("key", "value");
+        timestamps.format = "tss:Europe/Paris".to_string();
+        let truncated = output("date_trunc", &timestamps).unwrap();
+        assert_eq!(truncated.timezone(), Some("Europe/Paris"));
+        assert!(truncated.metadata.is_empty());
+        let fields = Schema::new(ArrowType::Struct, "").with_children(vec![
+            Schema::new(ArrowType::Float32, "a"),
+            Schema::new(ArrowType::Float32, "b"),
+            Schema::new(ArrowType::Float32, "c"),
+        ]);
+        assert_eq!(
+            output("fma", &fields).unwrap(),
+            Schema::new(ArrowType::Float32, "a")
+        );
+    }
+
+    #[test]
+    fn run_end_encoded_inputs_give_run_end_encoded_outputs() {
+        let ree = Schema::new(ArrowType::RunEndEncoded, "x").with_children(vec![
+            Schema::new(ArrowType::Int32, "run_ends"),
+            Schema::new(ArrowType::Int16, "values"),
+        ]);
+        let out = output("to_float64", &ree).unwrap();
+        assert_eq!(out, export::constant_schema(ArrowType::Float64, "x"));
+        // Rows are repeated in a struct array instead.
+        let rows = output("divmod", &ree).unwrap();
+        assert_eq!(rows.data_type, ArrowType::Struct);
+        assert_eq!(rows.children[1].name, "remainder");
+    }
+
+    #[cfg(feature = "strings")]
+    #[test]
+    fn unknown_outputs_fail() {
+        let int64 = Schema::new(ArrowType::Int64, "x");
+        let utf8 = Schema::new(ArrowType::Utf8, "x");
+        assert!(matches!(
+            output("hash64", &Schema::new(ArrowType::Boolean, "x")),
+            Err(Error::UnsupportedType(_))
+        ));
+        assert!(matches!(
+            output("upper", &int64),
+            Err(Error::UnsupportedType(_))
+        ));
+        assert!(matches!(
+            output("unknown", &utf8),
+            Err(Error::InvalidArgument(_))
+        ));
+        let duration = Schema::new(ArrowType::Duration(crate::schema::TimeUnit::Second), "d");
+        assert!(matches!(
+            output("duration_cast", &duration),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[cfg(feature = "strings")]
+    #[test]
+    fn output_schemas_through_ffi() {
+        let input = export::export_schema(&Schema::new(ArrowType::Utf8, "s"));
+        let mut out = ArrowCDataInterfaceSchema::empty();
+        let status =
+            unsafe { arrow_udf_output_schema(c"arrow_udf_utf8_length".as_ptr(), &input, &mut out) };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        let schema = unsafe { Schema::from_ffi(&out) };
+        unsafe { out.release.unwrap()(&mut out) };
+        assert_eq!(schema, Ok(Schema::new(ArrowType::Int32, "s")));
+        let mut out = ArrowCDataInterfaceSchema::empty();
+        let status = unsafe { arrow_udf_output_schema(c"negate".as_ptr(), &input, &mut out) };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+        assert!(out.release.is_none());
+        let mut input = input;
+        unsafe { input.release.unwrap()(&mut input) };
+    }
+}
@@ -0,0 +1,265 @@
+//! Remapping of the values of an array through a lookup table, like codes
+//! to their labels.
+//!
+//! The mapping is given as two arrays of the same length, its keys and the
+//! value of every key. The table from the keys to their positions is built
+//! once per call, and the values of the rows are then taken from the
+//! positions of their keys with `concat::interleave`, so they can be of any
+//! type it supports. Keys can be integers, Utf8 or Binary, of the type of
+//! the array, or of the integers of its temporal type.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::array::ArrowArray;
+use crate::bitmap::{Bitmap, BitmapBuilder};
+use crate::concat;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::groupby::{with_key_type, KeyType};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+
+/// The position in the mapping of the key of every row of `array`, read
+/// with `key`. Null keys of the mapping are skipped, and duplicate keys
+/// fail the call.
+fn positions<'a, K, F>(
+    array: &ArrowArray<'a>,
+    keys: &ArrowArray<'a>,
+    options: &ArrowUdfExecOptions,
+    key: F,
+) -> Result<Vec<Option<usize>>>
+where
+    K: Hash + Eq,
+    F: Fn(&ArrowArray<'a>, usize) -> K,
+{
+    let mut table = HashMap::with_capacity(keys.len());
+    for i in (0..keys.len()).filter(|i| keys.is_valid(*i)) {
+        if table.insert(key(keys, i), i).is_some() {
+            return Err(Error::InvalidArgument(format!(
+                "duplicate key at position {i} of the mapping"
+            )));
+        }
+    }
+    let mut positions = Vec::with_capacity(array.len());
+    exec::for_each_batch(array.len(), options, |rows| {
+        for i in rows {
+            positions.push(match array.is_valid(i) {
+                true => table.get(&key(array, i)).copied(),
+                false => None,
+            });
+        }
+        Ok(())
+    })?;
+    Ok(positions)
+}
+
+/// The value in `values` of the key of every element of `array`, whose
+/// position in `values` is the position of the key in `keys`. Null elements
+/// and elements whose key isn't in `keys` are null in the result.
+pub fn remap(
+    array: &ArrowArray,
+    keys: &ArrowArray,
+    values: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if keys.data_type() != array.data_type() {
+        return Err(Error::UnsupportedType(format!(
+            "mapping with {:?} keys for a {:?} array",
+            keys.data_type(),
+            array.data_type()
+        )));
+    }
+    if keys.len() != values.len() {
+        return Err(Error::InvalidArgument(format!(
+            "mapping of {} keys and {} values",
+            keys.len(),
+            values.len()
+        )));
+    }
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let positions = match array.data_type() {
+        ArrowType::Binary | ArrowType::Utf8 => {
+            positions(array, keys, options, |array, i| array.binary_value(i))?
+        }
+        data_type => with_key_type!(data_type.physical_type(), T => {
+            positions(array, keys, options, |array, i| array.values::<T>()[i].to_key())?
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "remap of {data_type:?} arrays"
+        )))),
+    };
+    if values.is_empty() && !array.is_empty() {
+        return Err(Error::InvalidArgument("the mapping is empty".to_string()));
+    }
+    // Rows without a value take the first one, and are made null after.
+    let indices: Vec<(usize, usize)> = positions
+        .iter()
+        .map(|position| (0, position.unwrap_or(0)))
+        .collect();
+    let data = concat::interleave(std::slice::from_ref(values), &indices)?;
+    let taken = data.buffers[0]
+        .as_ref()
+        .filter(|_| data.null_count > 0)
+        .map(|validity| Bitmap::new(validity.as_slice(), 0, data.length));
+    let (mut validity, mut null_count) = (BitmapBuilder::with_capacity(data.length), 0);
+    for (i, position) in positions.iter().enumerate() {
+        let is_valid = position.is_some() && taken.as_ref().is_none_or(|taken| taken.is_set(i));
+        null_count += usize::from(!is_valid);
+        validity.push(is_valid);
+    }
+    let schema = Schema {
+        name: array.schema().name.clone(),
+        ..values.schema().clone()
+    };
+    Ok((
+        schema,
+        data.with_validity(Some(validity.finish()), null_count),
+    ))
+}
+
+/// The value of the key of every element of an array in a mapping, given as
+/// the arrays of its keys, of the type of the array, and of their values, of
+/// the same length. Integer, Utf8 and Binary keys are supported, and values
+/// of any type supported by `arrow_udf_take`. Null elements, and elements
+/// whose key isn't in the mapping, are null in the result. Duplicate keys
+/// fail with `InvalidArgument`.
+///
+/// # Safety
+///
+/// `schema` and `array`, `keys_schema` and `keys`, and `values_schema` and
+/// `values` must point to valid Arrow C Data Interface arrays, `options`
+/// must be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn arrow_udf_remap(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    keys_schema: *const ArrowCDataInterfaceSchema,
+    keys: *const ArrowCDataInterfaceArray,
+    values_schema: *const ArrowCDataInterfaceSchema,
+    values: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let keys_schema = Schema::from_ffi(&*keys_schema)?;
+        let values_schema = Schema::from_ffi(&*values_schema)?;
+        let (out, data) = remap(
+            &ArrowArray::import(&schema, &*array)?,
+            &ArrowArray::import(&keys_schema, &*keys)?,
+            &ArrowArray::import(&values_schema, &*values)?,
+            &options,
+        )?;
+        export::export_to(&out, &data.into(), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn remap_ffi(
+        input: &Exported,
+        keys: &Exported,
+        values: &Exported,
+        options: *const ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_remap(
+                &input.schema,
+                &input.array,
+                &keys.schema,
+                &keys.array,
+                &values.schema,
+                &values.array,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn strings(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|value| value.map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn keys_are_mapped_to_their_values() {
+        let input = Exported::nullable(&[Some(2_i32), None, Some(7), Some(1), Some(2)]);
+        let keys = Exported::nullable(&[Some(1_i32), Some(2), None, Some(3)]);
+        let values = Exported::utf8(&[Some("one"), Some("two"), Some("null"), None]);
+        let out = remap_ffi(&input, &keys, &values, ptr::null()).unwrap();
+        assert_eq!(
+            out.strings(),
+            strings(&[Some("two"), None, None, Some("one"), Some("two")])
+        );
+        out.with_array(|array| {
+            assert_eq!(array.data_type(), ArrowType::Utf8);
+            assert_eq!(array.schema().name, "x");
+            assert_eq!(array.null_count(), 2);
+        });
+    }
+
+    #[test]
+    fn string_keys_map_to_values_of_any_type() {
+        let input = Exported::utf8(&[Some("b"), Some("c"), Some("a")]);
+        let keys = Exported::utf8(&[Some("a"), Some("b"), Some("c")]);
+        let values = Exported::nullable(&[Some(1.5_f64), Some(2.5), None]);
+        let out = remap_ffi(&input, &keys, &values, ptr::null()).unwrap();
+        assert_eq!(out.nullable_values::<f64>(), [Some(2.5), None, Some(1.5)]);
+        let empty = Exported::utf8(&[]);
+        let none = Exported::nullable::<f64>(&[]);
+        let out = remap_ffi(&empty, &empty, &none, ptr::null()).unwrap();
+        out.with_array(|array| assert_eq!(array.len(), 0));
+    }
+
+    #[test]
+    fn invalid_mappings_fail() {
+        let input = Exported::nullable(&[Some(1_i64), None]);
+        let keys = Exported::primitive(&[1_i64, 1]);
+        let values = Exported::primitive(&[1_i8, 2]);
+        let status = remap_ffi(&input, &keys, &values, ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let short = Exported::primitive(&[1_i8]);
+        let keys = Exported::primitive(&[1_i64, 2]);
+        let status = remap_ffi(&input, &keys, &short, ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let other = Exported::primitive(&[1_i32, 2]);
+        let status = remap_ffi(&input, &other, &values, ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+        let empty = Exported::primitive::<i64>(&[]);
+        let none = Exported::primitive::<i8>(&[]);
+        let status = remap_ffi(&input, &empty, &none, ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let floats = Exported::primitive(&[1.0_f64]);
+        let status = remap_ffi(&floats, &floats, &short, ptr::null()).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let status = remap_ffi(&input, &keys, &values, &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::NullValue));
+    }
+}
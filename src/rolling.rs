@@ -0,0 +1,419 @@
+//! Rolling window aggregations over fixed-width windows of rows.
+//!
+//! The result has the same length as the input. Every row gets the
+//! aggregate of the window ending at it, made of the row and the
+//! `window - 1` rows before it. The result is null for the first rows,
+//! whose window is incomplete, and for the windows containing nulls.
+//!
+//! The mean keeps the running sum of the window. The minimum and the
+//! maximum keep a monotonic deque with the candidate rows of the window, so
+//! every row is pushed and popped at most once, and the cost doesn't depend
+//! on the width of the window.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::dispatch::{self, Kernel};
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::{NativeType, Numeric};
+use crate::udf;
+
+/// Aggregate computed over every window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollingFunction {
+    Mean,
+    Min,
+    Max,
+}
+
+/// Array of `values`, null where `valid` is false.
+fn with_nulls<T: NativeType>(values: Vec<T>, valid: BitmapBuilder, null_count: usize) -> ArrayData {
+    let len = values.len();
+    let data = ArrayData::primitive(Buffer::from_slice(&values), len);
+    match null_count {
+        0 => data,
+        _ => data.with_validity(Some(valid.finish()), null_count),
+    }
+}
+
+/// Call `f` with every row of `array`, whether its window is complete and
+/// has no nulls, and the value of the row.
+fn for_each_window<T: NativeType>(
+    array: &ArrowArray,
+    window: usize,
+    options: &ArrowUdfExecOptions,
+    mut f: impl FnMut(usize, bool, T),
+) -> Result<()> {
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    // Rows until the window stops containing the last null.
+    let mut until_valid = window - 1;
+    exec::for_each_batch(values.len(), options, |rows| {
+        for i in rows {
+            if !validity.is_none_or(|validity| validity.is_set(i)) {
+                until_valid = until_valid.max(window);
+            }
+            f(i, until_valid == 0, values[i]);
+            until_valid = until_valid.saturating_sub(1);
+        }
+        Ok(())
+    })
+}
+
+fn rolling_mean<T: Numeric>(
+    array: &ArrowArray,
+    window: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    // Infinite and NaN values are left out of the running sum, which they
+    // would spoil after leaving the window, and the windows containing them
+    // are summed from scratch.
+    let value = |i: usize| match validity.is_none_or(|validity| validity.is_set(i)) {
+        true => Some(values[i].to_f64()).filter(|value| value.is_finite()),
+        false => Some(0.0),
+    };
+    let mut sum = 0.0;
+    let mut non_finite = 0;
+    let mut means = Vec::with_capacity(values.len());
+    let mut valid = BitmapBuilder::with_capacity(values.len());
+    let mut null_count = 0;
+    for_each_window::<T>(array, window, options, |i, complete, _| {
+        match value(i) {
+            Some(value) => sum += value,
+            None => non_finite += 1,
+        }
+        if i >= window {
+            match value(i - window) {
+                Some(value) => sum -= value,
+                None => non_finite -= 1,
+            }
+        }
+        let mean = match (complete, non_finite) {
+            (false, _) => 0.0,
+            (true, 0) => sum / window as f64,
+            (true, _) => {
+                let window_sum: f64 = values[i + 1 - window..=i].iter().map(|v| v.to_f64()).sum();
+                window_sum / window as f64
+            }
+        };
+        means.push(mean);
+        valid.push(complete);
+        null_count += !complete as usize;
+    })?;
+    Ok(with_nulls(means, valid, null_count))
+}
+
+fn rolling_extreme<T: Numeric>(
+    array: &ArrowArray,
+    window: usize,
+    max: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    // Rows of the window that can still be its extreme, with their values
+    // increasing for the minimum, or decreasing for the maximum.
+    let mut candidates: VecDeque<usize> = VecDeque::with_capacity(window);
+    let mut result = Vec::with_capacity(values.len());
+    let mut valid = BitmapBuilder::with_capacity(values.len());
+    let mut null_count = 0;
+    let dominates = |a: T, b: T| match max {
+        true => a.total_cmp(&b).is_ge(),
+        false => a.total_cmp(&b).is_le(),
+    };
+    for_each_window::<T>(array, window, options, |i, complete, value| {
+        if candidates.front().is_some_and(|front| *front + window <= i) {
+            candidates.pop_front();
+        }
+        if validity.is_none_or(|validity| validity.is_set(i)) {
+            while candidates
+                .back()
+                .is_some_and(|back| dominates(value, values[*back]))
+            {
+                candidates.pop_back();
+            }
+            candidates.push_back(i);
+        }
+        match candidates.front() {
+            Some(front) if complete => result.push(values[*front]),
+            _ => result.push(T::default()),
+        }
+        valid.push(complete);
+        null_count += !complete as usize;
+    })?;
+    Ok(with_nulls(result, valid, null_count))
+}
+
+/// Rolling `function` over windows of `window` rows of `array`.
+pub fn rolling(
+    array: &ArrowArray,
+    window: i64,
+    function: RollingFunction,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if window <= 0 {
+        return Err(Error::InvalidArgument(format!(
+            "the window must be positive, got {window}"
+        )));
+    }
+    dispatch::call::<Rolling>(array, &(function, window as usize), options)
+}
+
+struct Rolling;
+
+impl Kernel for Rolling {
+    type Args = (RollingFunction, usize);
+    type Output = (Schema, ArrayData);
+
+    fn name((function, _): &(RollingFunction, usize)) -> String {
+        format!("rolling {function:?}")
+    }
+
+    fn call<T: Numeric>(
+        array: &ArrowArray,
+        (function, window): &(RollingFunction, usize),
+        options: &ArrowUdfExecOptions,
+    ) -> Result<(Schema, ArrayData)> {
+        udf::check_input::<T>(array, options)?;
+        let name = &array.schema().name;
+        match function {
+            RollingFunction::Mean => Ok((
+                Schema::new(ArrowType::Float64, name),
+                rolling_mean::<T>(array, *window, options)?,
+            )),
+            RollingFunction::Min | RollingFunction::Max => Ok((
+                Schema::new(T::ARROW_TYPE, name),
+                rolling_extreme::<T>(array, *window, *function == RollingFunction::Max, options)?,
+            )),
+        }
+    }
+}
+
+unsafe fn rolling_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    window: i64,
+    function: RollingFunction,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = rolling(&array, window, function, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Mean of every window of `window` rows of a numeric array, as a Float64
+/// array of the same length, null for incomplete windows and windows with
+/// nulls.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_rolling_mean(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    window: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let function = RollingFunction::Mean;
+    rolling_ffi(
+        schema, array, window, function, options, out_schema, out_array,
+    )
+}
+
+/// Minimum of every window of `window` rows of a numeric array, as an array
+/// of the same type and length, null for incomplete windows and windows with
+/// nulls.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_rolling_mean`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_rolling_min(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    window: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let function = RollingFunction::Min;
+    rolling_ffi(
+        schema, array, window, function, options, out_schema, out_array,
+    )
+}
+
+/// Maximum of every window of `window` rows, like `arrow_udf_rolling_min`.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_rolling_mean`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_rolling_max(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    window: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    let function = RollingFunction::Max;
+    rolling_ffi(
+        schema, array, window, function, options, out_schema, out_array,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    type EntryPoint = unsafe extern "C" fn(
+        *const ArrowCDataInterfaceSchema,
+        *const ArrowCDataInterfaceArray,
+        i64,
+        *const ArrowUdfExecOptions,
+        *mut ArrowCDataInterfaceSchema,
+        *mut ArrowCDataInterfaceArray,
+    ) -> ArrowUdfStatus;
+
+    fn run(
+        f: EntryPoint,
+        input: &Exported,
+        window: i64,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            f(
+                &input.schema,
+                &input.array,
+                window,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn windows_end_at_every_row() {
+        let input = Exported::primitive(&[3_i32, 1, 4, 1, 5, 9, 2]);
+        let options = ArrowUdfExecOptions::default();
+        let mean = run(arrow_udf_rolling_mean, &input, 3, &options).unwrap();
+        assert_eq!(
+            mean.nullable_values::<f64>(),
+            [
+                None,
+                None,
+                Some(8.0 / 3.0),
+                Some(2.0),
+                Some(10.0 / 3.0),
+                Some(5.0),
+                Some(16.0 / 3.0)
+            ]
+        );
+        let min = run(arrow_udf_rolling_min, &input, 3, &options).unwrap();
+        assert_eq!(
+            min.nullable_values::<i32>(),
+            [None, None, Some(1), Some(1), Some(1), Some(1), Some(2)]
+        );
+        let max = run(arrow_udf_rolling_max, &input, 3, &options).unwrap();
+        assert_eq!(
+            max.nullable_values::<i32>(),
+            [None, None, Some(4), Some(4), Some(5), Some(9), Some(9)]
+        );
+        let single = run(arrow_udf_rolling_max, &input, 1, &options).unwrap();
+        assert_eq!(single.values::<i32>(), [3, 1, 4, 1, 5, 9, 2]);
+    }
+
+    #[test]
+    fn windows_with_nulls_are_null() {
+        let input =
+            Exported::nullable(&[Some(1.0), None, Some(3.0), Some(4.0), Some(5.0), Some(6.0)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mean = run(arrow_udf_rolling_mean, &input, 2, &options).unwrap();
+        assert_eq!(
+            mean.nullable_values::<f64>(),
+            [None, None, None, Some(3.5), Some(4.5), Some(5.5)]
+        );
+        let min = run(arrow_udf_rolling_min, &input, 2, &options).unwrap();
+        assert_eq!(
+            min.nullable_values::<f64>(),
+            [None, None, None, Some(3.0), Some(4.0), Some(5.0)]
+        );
+        let long = run(arrow_udf_rolling_max, &input, 10, &options).unwrap();
+        assert_eq!(long.nullable_values::<f64>(), [None; 6]);
+    }
+
+    #[test]
+    fn infinite_values_leave_the_running_sum() {
+        let input = Exported::primitive(&[1.0, f64::INFINITY, 2.0, 3.0, 4.0]);
+        let mean = run(
+            arrow_udf_rolling_mean,
+            &input,
+            2,
+            &ArrowUdfExecOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            mean.nullable_values::<f64>(),
+            [
+                None,
+                Some(f64::INFINITY),
+                Some(f64::INFINITY),
+                Some(2.5),
+                Some(3.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_windows_fail() {
+        let input = Exported::primitive(&[1_u8, 2, 3]);
+        let options = ArrowUdfExecOptions::default();
+        for window in [0, -1] {
+            let status = run(arrow_udf_rolling_mean, &input, window, &options).err();
+            assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        }
+        let booleans = Exported::boolean(&[true]);
+        let status = run(arrow_udf_rolling_min, &booleans, 1, &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+        let nulls = Exported::nullable::<u8>(&[Some(1), None]);
+        let error = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let status = run(arrow_udf_rolling_max, &nulls, 1, &error).err();
+        assert_eq!(status, Some(ArrowUdfStatus::NullValue));
+    }
+}
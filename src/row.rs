@@ -0,0 +1,417 @@
+//! Typed rows of struct arrays.
+//!
+//! UDFs over struct arrays usually want a row as a Rust struct, instead of
+//! indexing the children of the array for every field. `ArrowRow` maps a
+//! struct to the fields of a struct array, and is implemented with
+//! `#[derive(ArrowRow)]`:
+//!
+//! ```ignore
+//! #[derive(ArrowRow)]
+//! struct Point {
+//!     x: f64,
+//!     y: f64,
+//!     label: Option<String>,
+//! }
+//!
+//! let points: Vec<Point> = row::rows(&array)?.collect::<Result<_>>()?;
+//! let data = row::build(&points);
+//! let schema = row::schema::<Point>("points");
+//! ```
+//!
+//! Rows can also be read as tuples, with an element per field of the array,
+//! like `array.rows::<(i64, f64, Option<&str>)>()`, for UDFs over the columns
+//! of a record batch.
+//!
+//! The fields can be of any type implementing `ArrowField`: the primitive
+//! types, `bool`, `String` and `&str` (for Utf8 arrays), and `Option` of
+//! them for nullable fields.
+
+use crate::array::ArrowArray;
+use crate::binary::Utf8Builder;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+
+pub use distance_derive::ArrowRow;
+
+/// Rust types that the values of an array can be read as, and built from.
+/// `'a` is the lifetime of the arrays values can borrow from, like `&'a str`.
+pub trait ArrowField<'a>: Sized {
+    /// Type of the arrays of the values. `Option<T>` has the type of `T`.
+    const DATA_TYPE: ArrowType;
+
+    /// The element `i` of `array`, failing with `Error::NullValue` if it's
+    /// null, unless the type is an `Option`.
+    fn read(array: &ArrowArray<'a>, i: usize) -> Result<Self>;
+
+    /// Array of `values`, with `None` as nulls.
+    fn build<'v>(values: impl ExactSizeIterator<Item = Option<&'v Self>>) -> ArrayData
+    where
+        Self: 'v;
+}
+
+/// Rust structs mapped to the fields of struct arrays, usually implemented
+/// with `#[derive(ArrowRow)]`.
+pub trait ArrowRow<'a>: Sized {
+    /// Whether the fields are matched with the children of struct arrays by
+    /// position, like the elements of tuples, instead of by name.
+    const POSITIONAL: bool = false;
+
+    /// Schemas of the fields, in the order of the columns of `read` and
+    /// `build`.
+    fn fields() -> Vec<Schema>;
+
+    /// The row `i` of `columns`, the arrays of the fields.
+    fn read(columns: &[ArrowArray<'a>], i: usize) -> Result<Self>;
+
+    /// The arrays of the fields of `rows`.
+    fn build(rows: &[Self]) -> Vec<ArrayData>;
+}
+
+/// Validity bitmap of `values` if any of them is `None`, and their number.
+fn validity<T>(values: &[Option<T>]) -> (Option<Buffer>, usize) {
+    let null_count = values.iter().filter(|value| value.is_none()).count();
+    if null_count == 0 {
+        return (None, 0);
+    }
+    let mut validity = BitmapBuilder::with_capacity(values.len());
+    values
+        .iter()
+        .for_each(|value| validity.push(value.is_some()));
+    (Some(validity.finish()), null_count)
+}
+
+fn check_valid(array: &ArrowArray, i: usize) -> Result<()> {
+    if array.is_valid(i) {
+        Ok(())
+    } else {
+        Err(Error::NullValue)
+    }
+}
+
+macro_rules! native_fields {
+    ($($type:ty),*) => {
+        $(
+            impl<'a> ArrowField<'a> for $type {
+                const DATA_TYPE: ArrowType = <$type as NativeType>::ARROW_TYPE;
+
+                fn read(array: &ArrowArray<'a>, i: usize) -> Result<Self> {
+                    check_valid(array, i)?;
+                    Ok(array.values::<$type>()[i])
+                }
+
+                fn build<'v>(values: impl ExactSizeIterator<Item = Option<&'v Self>>) -> ArrayData {
+                    let values: Vec<Option<$type>> = values.map(|value| value.copied()).collect();
+                    let (validity, null_count) = crate::row::validity(&values);
+                    let values: Vec<$type> =
+                        values.iter().map(|value| value.unwrap_or_default()).collect();
+                    ArrayData::primitive(Buffer::from_slice(&values), values.len())
+                        .with_validity(validity, null_count)
+                }
+            }
+        )*
+    };
+}
+
+native_fields!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+impl<'a> ArrowField<'a> for bool {
+    const DATA_TYPE: ArrowType = ArrowType::Boolean;
+
+    fn read(array: &ArrowArray<'a>, i: usize) -> Result<Self> {
+        check_valid(array, i)?;
+        let bit = array.offset() + i;
+        Ok(unsafe { *array.ffi().buffer(1).add(bit / 8) } & (1 << (bit % 8)) != 0)
+    }
+
+    fn build<'v>(values: impl ExactSizeIterator<Item = Option<&'v Self>>) -> ArrayData {
+        let values: Vec<Option<bool>> = values.map(|value| value.copied()).collect();
+        let (validity, null_count) = crate::row::validity(&values);
+        let mut bits = BitmapBuilder::with_capacity(values.len());
+        values
+            .iter()
+            .for_each(|value| bits.push(value.unwrap_or_default()));
+        ArrayData::primitive(bits.finish(), values.len()).with_validity(validity, null_count)
+    }
+}
+
+impl<'a> ArrowField<'a> for &'a str {
+    const DATA_TYPE: ArrowType = ArrowType::Utf8;
+
+    fn read(array: &ArrowArray<'a>, i: usize) -> Result<Self> {
+        check_valid(array, i)?;
+        array.utf8_value(i)
+    }
+
+    fn build<'v>(values: impl ExactSizeIterator<Item = Option<&'v Self>>) -> ArrayData
+    where
+        'a: 'v,
+    {
+        let mut builder = Utf8Builder::with_capacity(values.len());
+        values.for_each(|value| builder.push(value.copied()));
+        builder.finish()
+    }
+}
+
+impl<'a> ArrowField<'a> for String {
+    const DATA_TYPE: ArrowType = ArrowType::Utf8;
+
+    fn read(array: &ArrowArray<'a>, i: usize) -> Result<Self> {
+        <&str>::read(array, i).map(str::to_string)
+    }
+
+    fn build<'v>(values: impl ExactSizeIterator<Item = Option<&'v Self>>) -> ArrayData {
+        let mut builder = Utf8Builder::with_capacity(values.len());
+        values.for_each(|value| builder.push(value.map(String::as_str)));
+        builder.finish()
+    }
+}
+
+impl<'a, T: ArrowField<'a>> ArrowField<'a> for Option<T> {
+    const DATA_TYPE: ArrowType = T::DATA_TYPE;
+
+    fn read(array: &ArrowArray<'a>, i: usize) -> Result<Self> {
+        if array.is_valid(i) {
+            T::read(array, i).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn build<'v>(values: impl ExactSizeIterator<Item = Option<&'v Self>>) -> ArrayData
+    where
+        Self: 'v,
+    {
+        T::build(values.map(|value| value.and_then(Option::as_ref)))
+    }
+}
+
+macro_rules! tuple_rows {
+    ($(($($element:ident $column:tt),+)),* $(,)?) => {
+        $(
+            impl<'a, $($element: ArrowField<'a>),+> ArrowRow<'a> for ($($element,)+) {
+                const POSITIONAL: bool = true;
+
+                fn fields() -> Vec<Schema> {
+                    vec![$(Schema::new($element::DATA_TYPE, stringify!($column))),+]
+                }
+
+                fn read(columns: &[ArrowArray<'a>], i: usize) -> Result<Self> {
+                    Ok(($($element::read(&columns[$column], i)?,)+))
+                }
+
+                fn build(rows: &[Self]) -> Vec<ArrayData> {
+                    vec![$($element::build(rows.iter().map(|row| Some(&row.$column)))),+]
+                }
+            }
+        )*
+    };
+}
+
+tuple_rows! {
+    (A 0),
+    (A 0, B 1),
+    (A 0, B 1, C 2),
+    (A 0, B 1, C 2, D 3),
+    (A 0, B 1, C 2, D 3, E 4),
+    (A 0, B 1, C 2, D 3, E 4, F 5),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7),
+}
+
+/// Schema of struct arrays of `R` rows.
+pub fn schema<'a, R: ArrowRow<'a>>(name: &str) -> Schema {
+    Schema::new(ArrowType::Struct, name).with_children(R::fields())
+}
+
+fn describe_fields(schema: &Schema) -> Vec<(&str, ArrowType)> {
+    schema
+        .children
+        .iter()
+        .map(|field| (field.name.as_str(), field.data_type))
+        .collect()
+}
+
+/// The rows of a struct array. The fields of structs deriving `ArrowRow`
+/// are matched by name, ignoring extra fields of the array, and the elements
+/// of tuples by position, the array having as many fields as the tuple. Null
+/// rows fail with `Error::NullValue`.
+pub fn rows<'a, R: ArrowRow<'a>>(
+    array: &ArrowArray<'a>,
+) -> Result<impl Iterator<Item = Result<R>> + use<'a, R>> {
+    let expected = schema::<R>("");
+    let children = &array.schema().children;
+    let compatible = if R::POSITIONAL {
+        array.data_type() == ArrowType::Struct
+            && children.len() == expected.children.len()
+            && children
+                .iter()
+                .zip(&expected.children)
+                .all(|(child, field)| child.is_compatible_with(field))
+    } else {
+        array.schema().is_compatible_with(&expected)
+    };
+    if !compatible {
+        return Err(Error::UnsupportedType(format!(
+            "rows with fields {:?} from a {:?} array with fields {:?}",
+            describe_fields(&expected),
+            array.data_type(),
+            describe_fields(array.schema()),
+        )));
+    }
+    let columns = expected
+        .children
+        .iter()
+        .enumerate()
+        .map(|(position, field)| {
+            let i = match R::POSITIONAL {
+                true => position,
+                false => children
+                    .iter()
+                    .position(|child| child.name == field.name)
+                    .unwrap(),
+            };
+            let column = array.child(i);
+            if column.len() < array.offset() + array.len() {
+                return Err(Error::InvalidArgument(format!(
+                    "field {:?} with {} rows in a struct array of {}",
+                    children[i].name,
+                    column.len(),
+                    array.offset() + array.len()
+                )));
+            }
+            Ok(column)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let array = *array;
+    Ok((0..array.len()).map(move |i| {
+        check_valid(&array, i)?;
+        R::read(&columns, array.offset() + i)
+    }))
+}
+
+/// Struct array of `rows`, without nulls.
+pub fn build<'a, R: ArrowRow<'a>>(rows: &[R]) -> ArrayData {
+    ArrayData::struct_array(R::build(rows), rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Exported;
+
+    #[derive(ArrowRow, Debug, PartialEq)]
+    struct Point {
+        x: f64,
+        y: i32,
+        label: Option<String>,
+        valid: bool,
+    }
+
+    #[derive(ArrowRow, Debug, PartialEq)]
+    struct Label<'a> {
+        label: Option<&'a str>,
+    }
+
+    fn points() -> Vec<Point> {
+        vec![
+            Point {
+                x: 1.5,
+                y: -2,
+                label: Some("a".to_string()),
+                valid: true,
+            },
+            Point {
+                x: 0.0,
+                y: 7,
+                label: None,
+                valid: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn rows_round_trip_through_ffi() {
+        let schema = schema::<Point>("points");
+        let names: Vec<_> = schema.children.iter().map(|field| &field.name).collect();
+        assert_eq!(names, ["x", "y", "label", "valid"]);
+        let exported = Exported::new(&schema, build(&points()));
+        let read = exported.with_array(|array| rows::<Point>(array)?.collect::<Result<Vec<_>>>());
+        assert_eq!(read.unwrap(), points());
+        // Only the fields of `Label` are read, borrowing the strings.
+        let labels = exported.with_array(|array| {
+            let labels = rows::<Label>(array)?.map(|label| Ok(label?.label.map(str::len)));
+            labels.collect::<Result<Vec<_>>>()
+        });
+        assert_eq!(labels.unwrap(), [Some(1), None]);
+    }
+
+    #[test]
+    fn nulls_need_options() {
+        #[derive(ArrowRow)]
+        struct Required {
+            #[allow(dead_code)]
+            label: String,
+        }
+        let exported = Exported::new(&schema::<Point>("points"), build(&points()));
+        let read =
+            exported.with_array(|array| rows::<Required>(array)?.collect::<Result<Vec<_>>>());
+        assert!(matches!(read, Err(Error::NullValue)));
+        let children = Point::build(&points());
+        let data = ArrayData::struct_array(children, 2)
+            .with_validity(Some(crate::testing::validity(&[Some(()), None])), 1);
+        let exported = Exported::new(&schema::<Point>("points"), data);
+        let read = exported.with_array(|array| {
+            let mut rows = rows::<Point>(array)?;
+            Ok::<_, Error>((rows.next().unwrap().is_ok(), rows.next().unwrap()))
+        });
+        assert!(matches!(read, Ok((true, Err(Error::NullValue)))));
+    }
+
+    #[test]
+    fn fields_must_match() {
+        #[derive(ArrowRow)]
+        struct Other {
+            #[allow(dead_code)]
+            x: i64,
+        }
+        let exported = Exported::new(&schema::<Point>("points"), build(&points()));
+        let read = exported.with_array(|array| rows::<Other>(array).map(|_| ()));
+        assert!(matches!(read, Err(Error::UnsupportedType(_))));
+    }
+
+    #[test]
+    fn tuples_match_fields_by_position() {
+        let exported = Exported::new(&schema::<Point>("points"), build(&points()));
+        let read = exported.with_array(|array| {
+            let rows = array.rows::<(f64, i32, Option<&str>, bool)>()?;
+            let owned = rows.map(|row| {
+                row.map(|(x, y, label, valid)| (x, y, label.map(str::to_string), valid))
+            });
+            owned.collect::<Result<Vec<_>>>()
+        });
+        let label = Some("a".to_string());
+        assert_eq!(
+            read.unwrap(),
+            [(1.5, -2, label, true), (0.0, 7, None, false)]
+        );
+        let shorter = exported.with_array(|array| array.rows::<(f64, i32)>().map(|_| ()));
+        assert!(matches!(shorter, Err(Error::UnsupportedType(_))));
+        let swapped = exported.with_array(|array| {
+            let rows = array.rows::<(i32, f64, Option<&str>, bool)>();
+            rows.map(|_| ())
+        });
+        assert!(matches!(swapped, Err(Error::UnsupportedType(_))));
+        let pairs = [(1_u8, Some(2.5_f32)), (3, None)];
+        let schema = schema::<(u8, Option<f32>)>("pairs");
+        let exported = Exported::new(&schema, build(&pairs));
+        let read = exported.with_array(|array| {
+            let rows = array.rows::<(u8, Option<f32>)>()?;
+            rows.collect::<Result<Vec<_>>>()
+        });
+        assert_eq!(read.unwrap(), pairs);
+    }
+}
@@ -0,0 +1,798 @@
+//! Rows of struct arrays as any type implementing serde's `Deserialize` and
+//! `Serialize`, for UDFs with logic already written against plain Rust
+//! structs.
+//!
+//! Rows are deserialized as maps from the names of the fields to their
+//! values, so `#[derive(Deserialize)]` structs get their fields by name, and
+//! nested struct arrays are deserialized as nested structs. Nulls
+//! deserialize as `None`. Dates and timestamps are their integer values.
+//!
+//! Results are serialized into the fields of a given schema, converting the
+//! values to the type of each field, and fields missing in a row are null.
+
+use std::fmt::Display;
+
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, Visitor};
+use serde::ser::{self, Impossible, Serialize, SerializeMap, SerializeStruct, Serializer};
+use serde::{forward_to_deserialize_any, Deserialize};
+
+use crate::array::ArrowArray;
+use crate::binary::BinaryBuilder;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::row::ArrowField;
+use crate::schema::{ArrowType, Schema};
+use crate::types::with_native_type;
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::InvalidArgument(msg.to_string())
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::InvalidArgument(msg.to_string())
+    }
+}
+
+/// The rows of a struct array, deserialized as `T`. Null rows deserialize
+/// as `None`, so they fail unless `T` is an `Option`.
+pub fn from_rows<'a, T: Deserialize<'a>>(
+    array: &ArrowArray<'a>,
+) -> Result<impl Iterator<Item = Result<T>> + use<'a, T>> {
+    if array.data_type() != ArrowType::Struct {
+        return Err(Error::UnsupportedType(format!(
+            "rows of a {:?} array",
+            array.data_type()
+        )));
+    }
+    let array = *array;
+    Ok((0..array.len()).map(move |i| T::deserialize(ValueDeserializer { array, i })))
+}
+
+/// The element `i` of an array.
+struct ValueDeserializer<'a> {
+    array: ArrowArray<'a>,
+    i: usize,
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let (array, i) = (&self.array, self.i);
+        if !array.is_valid(i) {
+            return visitor.visit_none();
+        }
+        match array.data_type() {
+            ArrowType::Boolean => visitor.visit_bool(bool::read(array, i)?),
+            ArrowType::Int8 => visitor.visit_i8(i8::read(array, i)?),
+            ArrowType::Int16 => visitor.visit_i16(i16::read(array, i)?),
+            ArrowType::Int32 | ArrowType::Date32 => visitor.visit_i32(i32::read(array, i)?),
+            ArrowType::Int64 | ArrowType::Date64 | ArrowType::Timestamp(_) => {
+                visitor.visit_i64(i64::read(array, i)?)
+            }
+            ArrowType::UInt8 => visitor.visit_u8(u8::read(array, i)?),
+            ArrowType::UInt16 => visitor.visit_u16(u16::read(array, i)?),
+            ArrowType::UInt32 => visitor.visit_u32(u32::read(array, i)?),
+            ArrowType::UInt64 => visitor.visit_u64(u64::read(array, i)?),
+            ArrowType::Float32 => visitor.visit_f32(f32::read(array, i)?),
+            ArrowType::Float64 => visitor.visit_f64(f64::read(array, i)?),
+            ArrowType::Utf8 => visitor.visit_borrowed_str(array.utf8_value(i)?),
+            ArrowType::Binary | ArrowType::LargeBinary => {
+                visitor.visit_borrowed_bytes(array.binary_value(i))
+            }
+            ArrowType::Struct => visitor.visit_map(FieldsAccess {
+                array: self.array,
+                row: array.offset() + i,
+                field: 0,
+            }),
+            data_type => Err(Error::UnsupportedType(format!(
+                "deserialization of {data_type:?} arrays"
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.array.is_valid(self.i) {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// The fields of the row `row` of a struct array, as a map from their names
+/// to their values.
+struct FieldsAccess<'a> {
+    array: ArrowArray<'a>,
+    row: usize,
+    field: usize,
+}
+
+impl<'de> MapAccess<'de> for FieldsAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some(field) = self.array.schema().children.get(self.field) else {
+            return Ok(None);
+        };
+        seed.deserialize(BorrowedStrDeserializer::new(&field.name))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let child = self.array.child(self.field);
+        self.field += 1;
+        if child.len() <= self.row {
+            return Err(Error::InvalidArgument(format!(
+                "field {:?} with {} rows in a struct array of {}",
+                child.schema().name,
+                child.len(),
+                self.array.offset() + self.array.len()
+            )));
+        }
+        seed.deserialize(ValueDeserializer {
+            array: child,
+            i: self.row,
+        })
+    }
+}
+
+/// A serialized value, before it's converted to the type of its field.
+enum Value {
+    Null,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// Values of primitive fields, converted from the serialized values.
+trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+macro_rules! from_value {
+    (int: $($int:ty),*; float: $($float:ty),*) => {
+        $(
+            impl FromValue for $int {
+                fn from_value(value: &Value) -> Option<$int> {
+                    match value {
+                        Value::I64(value) => <$int>::try_from(*value).ok(),
+                        Value::U64(value) => <$int>::try_from(*value).ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+        $(
+            impl FromValue for $float {
+                fn from_value(value: &Value) -> Option<$float> {
+                    match value {
+                        Value::F64(value) => Some(*value as $float),
+                        Value::I64(value) => Some(*value as $float),
+                        Value::U64(value) => Some(*value as $float),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_value!(int: i8, i16, i32, i64, u8, u16, u32, u64; float: f32, f64);
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<bool> {
+        match value {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// The values of a field, failing if any can't be converted to `T`.
+fn convert<'v, T, F>(values: &'v [Value], field: &Schema, f: F) -> Result<Vec<Option<T>>>
+where
+    F: Fn(&'v Value) -> Option<T>,
+{
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Null => Ok(None),
+            value => f(value).map(Some).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "{} value for the {:?} field {:?}",
+                    value.kind(),
+                    field.data_type,
+                    field.name
+                ))
+            }),
+        })
+        .collect()
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::I64(_) | Value::U64(_) => "integer",
+            Value::F64(_) => "float",
+            Value::Str(_) => "string",
+            Value::Bytes(_) => "bytes",
+        }
+    }
+}
+
+/// Serializer of the methods of `Serializer` for the types a serializer
+/// doesn't support, failing with `$error`.
+macro_rules! reject {
+    ($error:expr; $($method:ident($($arg:ident: $type:ty),* $(,)?) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $($arg: $type),*) -> Result<$ok> {
+                $(let _ = $arg;)*
+                Err($error)
+            }
+        )*
+    };
+}
+
+/// Serializer of the values of fields.
+struct ValueSerializer;
+
+fn nested_value() -> Error {
+    Error::UnsupportedType("serialization of nested values".to_string())
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = Impossible<Value, Error>;
+    type SerializeTuple = Impossible<Value, Error>;
+    type SerializeTupleStruct = Impossible<Value, Error>;
+    type SerializeTupleVariant = Impossible<Value, Error>;
+    type SerializeMap = Impossible<Value, Error>;
+    type SerializeStruct = Impossible<Value, Error>;
+    type SerializeStructVariant = Impossible<Value, Error>;
+
+    fn serialize_bool(self, value: bool) -> Result<Value> {
+        Ok(Value::Bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value> {
+        Ok(Value::I64(value.into()))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value> {
+        Ok(Value::I64(value.into()))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value> {
+        Ok(Value::I64(value.into()))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value> {
+        Ok(Value::I64(value))
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value> {
+        Ok(Value::U64(value.into()))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value> {
+        Ok(Value::U64(value.into()))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value> {
+        Ok(Value::U64(value.into()))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value> {
+        Ok(Value::U64(value))
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value> {
+        Ok(Value::F64(value.into()))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value> {
+        Ok(Value::F64(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value> {
+        Ok(Value::Str(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value> {
+        Ok(Value::Str(value.to_string()))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value> {
+        Ok(Value::Bytes(value.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    /// Unit enum variants are serialized as their names.
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value> {
+        Ok(Value::Str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Value> {
+        Err(nested_value())
+    }
+
+    reject! { nested_value();
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(len: usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize,
+        ) -> Self::SerializeTupleVariant;
+        serialize_map(len: Option<usize>) -> Self::SerializeMap;
+        serialize_struct(name: &'static str, len: usize) -> Self::SerializeStruct;
+        serialize_struct_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize,
+        ) -> Self::SerializeStructVariant;
+    }
+}
+
+/// Serializer of a row, as a struct or a map, into the values of the
+/// columns of `schema`.
+struct RowSerializer<'s> {
+    schema: &'s Schema,
+    columns: &'s mut [Vec<Value>],
+    row: usize,
+    /// The key of the map entry being serialized.
+    key: Option<String>,
+}
+
+fn not_a_row() -> Error {
+    Error::InvalidArgument("rows must be serialized as structs or maps".to_string())
+}
+
+impl RowSerializer<'_> {
+    fn push<T: ?Sized + Serialize>(&mut self, name: &str, value: &T) -> Result<()> {
+        let i = self
+            .schema
+            .children
+            .iter()
+            .position(|field| field.name == name)
+            .ok_or_else(|| Error::InvalidArgument(format!("unknown field {name:?}")))?;
+        let value = value.serialize(ValueSerializer)?;
+        let column = &mut self.columns[i];
+        if column.len() > self.row {
+            return Err(Error::InvalidArgument(format!(
+                "field {name:?} serialized twice"
+            )));
+        }
+        column.push(value);
+        Ok(())
+    }
+}
+
+impl Serializer for RowSerializer<'_> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(not_a_row())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    reject! { not_a_row();
+        serialize_bool(value: bool) -> ();
+        serialize_i8(value: i8) -> ();
+        serialize_i16(value: i16) -> ();
+        serialize_i32(value: i32) -> ();
+        serialize_i64(value: i64) -> ();
+        serialize_u8(value: u8) -> ();
+        serialize_u16(value: u16) -> ();
+        serialize_u32(value: u32) -> ();
+        serialize_u64(value: u64) -> ();
+        serialize_f32(value: f32) -> ();
+        serialize_f64(value: f64) -> ();
+        serialize_char(value: char) -> ();
+        serialize_str(value: &str) -> ();
+        serialize_bytes(value: &[u8]) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(name: &'static str) -> ();
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str) -> ();
+        serialize_seq(len: Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(len: usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize,
+        ) -> Self::SerializeTupleVariant;
+        serialize_struct_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize,
+        ) -> Self::SerializeStructVariant;
+    }
+}
+
+impl SerializeStruct for RowSerializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl SerializeMap for RowSerializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        match key.serialize(ValueSerializer)? {
+            Value::Str(key) => self.key = Some(key),
+            key => {
+                return Err(Error::InvalidArgument(format!(
+                    "{} key in a row, fields are named by strings",
+                    key.kind()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().expect("value serialized before its key");
+        self.push(&key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn build_column(values: &[Value], field: &Schema) -> Result<ArrayData> {
+    Ok(match field.data_type {
+        ArrowType::Boolean => {
+            let values = convert(values, field, bool::from_value)?;
+            bool::build(values.iter().map(Option::as_ref))
+        }
+        ArrowType::Utf8 => {
+            let values = convert(values, field, |value| match value {
+                Value::Str(value) => Some(value.as_str()),
+                _ => None,
+            })?;
+            <&str>::build(values.iter().map(Option::as_ref))
+        }
+        ArrowType::Binary => {
+            let values = convert(values, field, |value| match value {
+                Value::Str(value) => Some(value.as_bytes()),
+                Value::Bytes(value) => Some(value.as_slice()),
+                _ => None,
+            })?;
+            let mut builder = BinaryBuilder::with_capacity(values.len());
+            values.into_iter().for_each(|value| builder.push(value));
+            builder.finish()
+        }
+        data_type => with_native_type!(data_type.physical_type(), T => {
+            let values = convert(values, field, T::from_value)?;
+            T::build(values.iter().map(Option::as_ref))
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "serialization of {data_type:?} fields"
+        )))),
+    })
+}
+
+/// Struct array with the fields of `schema`, a struct schema, built from
+/// `rows` serialized as structs or maps.
+pub fn to_array<T: Serialize>(rows: &[T], schema: &Schema) -> Result<ArrayData> {
+    if schema.data_type != ArrowType::Struct {
+        return Err(Error::UnsupportedType(format!(
+            "serialization of rows into a {:?} array",
+            schema.data_type
+        )));
+    }
+    let mut columns: Vec<Vec<Value>> = schema
+        .children
+        .iter()
+        .map(|_| Vec::with_capacity(rows.len()))
+        .collect();
+    for (row, value) in rows.iter().enumerate() {
+        value.serialize(RowSerializer {
+            schema,
+            columns: &mut columns,
+            row,
+            key: None,
+        })?;
+        // Fields that the row didn't serialize are null.
+        for column in &mut columns {
+            if column.len() == row {
+                column.push(Value::Null);
+            }
+        }
+    }
+    let children = columns
+        .iter()
+        .zip(&schema.children)
+        .map(|(values, field)| build_column(values, field))
+        .collect::<Result<_>>()?;
+    Ok(ArrayData::struct_array(children, rows.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    /// Any value, deserialized and serialized by hand, since the tests
+    /// don't have serde's derive macros.
+    #[derive(Clone, Debug, PartialEq)]
+    enum Cell {
+        Null,
+        Bool(bool),
+        Int(i64),
+        UInt(u64),
+        Float(f64),
+        Str(String),
+        Map(BTreeMap<String, Cell>),
+    }
+
+    struct CellVisitor;
+
+    impl<'de> Visitor<'de> for CellVisitor {
+        type Value = Cell;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("any value")
+        }
+
+        fn visit_bool<E>(self, value: bool) -> std::result::Result<Cell, E> {
+            Ok(Cell::Bool(value))
+        }
+
+        fn visit_i64<E>(self, value: i64) -> std::result::Result<Cell, E> {
+            Ok(Cell::Int(value))
+        }
+
+        fn visit_u64<E>(self, value: u64) -> std::result::Result<Cell, E> {
+            Ok(Cell::UInt(value))
+        }
+
+        fn visit_f64<E>(self, value: f64) -> std::result::Result<Cell, E> {
+            Ok(Cell::Float(value))
+        }
+
+        fn visit_str<E>(self, value: &str) -> std::result::Result<Cell, E> {
+            Ok(Cell::Str(value.to_string()))
+        }
+
+        fn visit_none<E>(self) -> std::result::Result<Cell, E> {
+            Ok(Cell::Null)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Cell, A::Error> {
+            let mut fields = BTreeMap::new();
+            while let Some((key, value)) = map.next_entry()? {
+                fields.insert(key, value);
+            }
+            Ok(Cell::Map(fields))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Cell {
+        fn deserialize<D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> std::result::Result<Cell, D::Error> {
+            deserializer.deserialize_any(CellVisitor)
+        }
+    }
+
+    impl Serialize for Cell {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            match self {
+                Cell::Null => serializer.serialize_none(),
+                Cell::Bool(value) => serializer.serialize_bool(*value),
+                Cell::Int(value) => serializer.serialize_i64(*value),
+                Cell::UInt(value) => serializer.serialize_u64(*value),
+                Cell::Float(value) => serializer.serialize_f64(*value),
+                Cell::Str(value) => serializer.serialize_str(value),
+                Cell::Map(fields) => {
+                    let mut map = serializer.serialize_map(Some(fields.len()))?;
+                    for (key, value) in fields {
+                        map.serialize_entry(key, value)?;
+                    }
+                    map.end()
+                }
+            }
+        }
+    }
+
+    fn row(cells: &[(&str, Cell)]) -> BTreeMap<String, Cell> {
+        cells
+            .iter()
+            .map(|(name, cell)| (name.to_string(), cell.clone()))
+            .collect()
+    }
+
+    fn schema() -> Schema {
+        Schema::new(ArrowType::Struct, "rows").with_children(vec![
+            Schema::new(ArrowType::Int32, "id"),
+            Schema::new(ArrowType::Utf8, "name"),
+            Schema::new(ArrowType::Float64, "score"),
+            Schema::new(ArrowType::Boolean, "active"),
+        ])
+    }
+
+    #[test]
+    fn rows_round_trip() {
+        let rows = [
+            row(&[
+                ("id", Cell::Int(1)),
+                ("name", Cell::Str("a".to_string())),
+                ("score", Cell::Float(0.5)),
+                ("active", Cell::Bool(true)),
+            ]),
+            row(&[
+                ("id", Cell::Int(-2)),
+                ("name", Cell::Null),
+                ("score", Cell::Float(2.0)),
+                ("active", Cell::Bool(false)),
+            ]),
+        ];
+        let schema = schema();
+        let exported = Exported::new(&schema, to_array(&rows, &schema).unwrap());
+        let read = exported.with_array(|array| {
+            from_rows::<BTreeMap<String, Cell>>(array)?.collect::<Result<Vec<_>>>()
+        });
+        assert_eq!(read.unwrap(), rows);
+    }
+
+    #[test]
+    fn values_are_converted_to_the_type_of_their_field() {
+        // Integers are written to float fields, and missing fields are null.
+        let rows = [row(&[("id", Cell::UInt(3)), ("score", Cell::Int(4))])];
+        let schema = schema();
+        let exported = Exported::new(&schema, to_array(&rows, &schema).unwrap());
+        let read = exported.with_array(|array| {
+            from_rows::<BTreeMap<String, Cell>>(array)?.collect::<Result<Vec<_>>>()
+        });
+        let expected = row(&[
+            ("id", Cell::Int(3)),
+            ("name", Cell::Null),
+            ("score", Cell::Float(4.0)),
+            ("active", Cell::Null),
+        ]);
+        assert_eq!(read.unwrap(), [expected]);
+        for rows in [
+            [row(&[("id", Cell::Int(i64::MAX))])],
+            [row(&[("id", Cell::Str("1".to_string()))])],
+            [row(&[("other", Cell::Int(1))])],
+        ] {
+            assert!(
+                matches!(to_array(&rows, &schema), Err(Error::InvalidArgument(_))),
+                "{rows:?}"
+            );
+        }
+        assert!(matches!(
+            to_array(&[Cell::Int(1)], &schema),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn null_and_nested_rows() {
+        let inner = Schema::new(ArrowType::Struct, "inner")
+            .with_children(vec![Schema::new(ArrowType::Int64, "value")]);
+        let schema = Schema::new(ArrowType::Struct, "rows").with_children(vec![inner]);
+        let value = ArrayData::primitive(crate::buffer::Buffer::from_slice(&[7_i64, 8]), 2);
+        let inner = ArrayData::struct_array(vec![value], 2);
+        let data = ArrayData::struct_array(vec![inner], 2)
+            .with_validity(Some(crate::testing::validity(&[None, Some(())])), 1);
+        let exported = Exported::new(&schema, data);
+        let read = exported
+            .with_array(|array| from_rows::<Option<Cell>>(array)?.collect::<Result<Vec<_>>>());
+        let nested = Cell::Map(row(&[("value", Cell::Int(8))]));
+        assert_eq!(
+            read.unwrap(),
+            [None, Some(Cell::Map(row(&[("inner", nested)])))]
+        );
+        let ints = Exported::primitive(&[1_i64]);
+        let read = ints.with_array(|array| from_rows::<Cell>(array).map(|_| ()));
+        assert!(matches!(read, Err(Error::UnsupportedType(_))));
+    }
+}
@@ -0,0 +1,319 @@
+//! Conversion of primitive arrays to run-end encoded arrays and back.
+//!
+//! Results that are constant or change rarely, like flags or the outputs of
+//! `forward_fill`, take a fraction of the memory run-end encoded, and are
+//! cheaper to ship back to the host. Every run of consecutive equal values,
+//! or of nulls, is stored once, with the position where it ends. Floats are
+//! compared by their bits, so NaN values make runs like any other value.
+//!
+//! The encoded arrays have Int64 run ends, like the constant results of
+//! `udf::map`. Decoding accepts Int16, Int32 and Int64 run ends, and the
+//! offset and length of the encoded array select the decoded rows.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, TotalOrd};
+
+/// Run-end encoded copy of `array`, with a run per sequence of equal values.
+pub fn encode(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)> {
+    fn encode_values<T: TotalOrd>(array: &ArrowArray) -> ArrayData {
+        let input = array.values::<T>();
+        let validity = array.validity().filter(|_| array.null_count() > 0);
+        let is_valid = |i: usize| validity.is_none_or(|validity| validity.is_set(i));
+        let mut run_ends: Vec<i64> = Vec::new();
+        let mut values: Vec<T> = Vec::new();
+        let mut valid = BitmapBuilder::with_capacity(0);
+        let mut null_count = 0;
+        for i in 0..input.len() {
+            let same_run = i > 0
+                && is_valid(i) == is_valid(i - 1)
+                && (!is_valid(i) || input[i].total_cmp(&input[i - 1]) == Ordering::Equal);
+            if same_run {
+                *run_ends.last_mut().unwrap() += 1;
+                continue;
+            }
+            run_ends.push(i as i64 + 1);
+            values.push(if is_valid(i) { input[i] } else { T::default() });
+            valid.push(is_valid(i));
+            null_count += usize::from(!is_valid(i));
+        }
+        let mut values_data = ArrayData::primitive(Buffer::from_slice(&values), values.len());
+        if null_count > 0 {
+            values_data = values_data.with_validity(Some(valid.finish()), null_count);
+        }
+        ArrayData {
+            length: input.len(),
+            null_count: 0,
+            buffers: Vec::new(),
+            children: vec![
+                ArrayData::primitive(Buffer::from_slice(&run_ends), run_ends.len()),
+                values_data,
+            ],
+        }
+    }
+    let data = with_native_type!(array.data_type().physical_type(), T => {
+        encode_values::<T>(array)
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "run-end encoding of {:?} arrays",
+        array.data_type()
+    ))));
+    options.check_cancelled()?;
+    options.check_memory()?;
+    Ok((encoded_schema(array.schema()), data))
+}
+
+/// Schema of the run-end encoded copy of arrays described by `schema`.
+pub(crate) fn encoded_schema(schema: &Schema) -> Schema {
+    let mut values = schema.clone();
+    values.name = "values".to_string();
+    Schema::new(ArrowType::RunEndEncoded, &schema.name)
+        .with_children(vec![Schema::new(ArrowType::Int64, "run_ends"), values])
+}
+
+/// Schema of the plain copy of run-end encoded arrays described by
+/// `schema`.
+pub(crate) fn decoded_schema(schema: &Schema) -> Schema {
+    let mut decoded = schema.children[1].clone();
+    decoded.name = schema.name.clone();
+    decoded
+}
+
+/// The run ends of the run-end encoded `array`.
+fn run_ends(array: &ArrowArray) -> Result<Vec<i64>> {
+    let run_ends = array.child(0);
+    Ok(match run_ends.data_type() {
+        ArrowType::Int16 => run_ends
+            .values::<i16>()
+            .iter()
+            .map(|end| *end as i64)
+            .collect(),
+        ArrowType::Int32 => run_ends
+            .values::<i32>()
+            .iter()
+            .map(|end| *end as i64)
+            .collect(),
+        ArrowType::Int64 => run_ends.values::<i64>().to_vec(),
+        other => {
+            return Err(Error::UnsupportedType(format!(
+                "run ends of type {other:?}"
+            )))
+        }
+    })
+}
+
+/// Plain copy of the run-end encoded `array`, with the values of its runs
+/// repeated.
+pub fn decode(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)> {
+    fn decode_values<T: TotalOrd>(array: &ArrowArray, run_ends: &[i64]) -> ArrayData {
+        let values_array = array.child(1);
+        let values = values_array.values::<T>();
+        let (first, len) = (array.offset(), array.len());
+        let mut out = Vec::with_capacity(len);
+        let mut valid = BitmapBuilder::with_capacity(len);
+        let mut null_count = 0;
+        let mut run = run_ends.partition_point(|end| *end as usize <= first);
+        for i in first..first + len {
+            while run_ends[run] as usize <= i {
+                run += 1;
+            }
+            let is_valid = values_array.is_valid(run);
+            out.push(values[run]);
+            valid.push(is_valid);
+            null_count += usize::from(!is_valid);
+        }
+        let data = ArrayData::primitive(Buffer::from_slice(&out), len);
+        match null_count {
+            0 => data,
+            _ => data.with_validity(Some(valid.finish()), null_count),
+        }
+    }
+    if array.data_type() != ArrowType::RunEndEncoded {
+        return Err(Error::UnsupportedType(format!(
+            "expected a run-end encoded array, got {:?}",
+            array.data_type()
+        )));
+    }
+    let run_ends = run_ends(array)?;
+    let value_schema = &array.schema().children[1];
+    let data = with_native_type!(value_schema.data_type.physical_type(), T => {
+        decode_values::<T>(array, &run_ends)
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "run-end decoding of {:?} values",
+        value_schema.data_type
+    ))));
+    options.check_cancelled()?;
+    options.check_memory()?;
+    Ok((decoded_schema(array.schema()), data))
+}
+
+unsafe fn convert_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    convert: fn(&ArrowArray, &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)>,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = convert(&array, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Run-end encoded copy of a primitive array, with Int64 run ends, and a
+/// run per sequence of equal values or of nulls.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_run_end_encode(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    convert_ffi(schema, array, encode, options, out_schema, out_array)
+}
+
+/// Primitive copy of a run-end encoded array, with the value of every run
+/// repeated for each of its rows.
+///
+/// # Safety
+///
+/// Same as `arrow_udf_run_end_encode`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_run_end_decode(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    convert_ffi(schema, array, decode, options, out_schema, out_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::{nullable_data, Exported};
+
+    type EntryPoint = unsafe extern "C" fn(
+        *const ArrowCDataInterfaceSchema,
+        *const ArrowCDataInterfaceArray,
+        *const ArrowUdfExecOptions,
+        *mut ArrowCDataInterfaceSchema,
+        *mut ArrowCDataInterfaceArray,
+    ) -> ArrowUdfStatus;
+
+    fn run(f: EntryPoint, input: &Exported) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            f(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn runs_of_values_and_nulls_round_trip() {
+        let values = [
+            Some(1.0_f64),
+            Some(1.0),
+            None,
+            None,
+            Some(f64::NAN),
+            Some(f64::NAN),
+            Some(2.0),
+        ];
+        let input = Exported::nullable(&values);
+        let encoded = run(arrow_udf_run_end_encode, &input).unwrap();
+        encoded.with_array(|array| {
+            assert_eq!(array.data_type(), ArrowType::RunEndEncoded);
+            assert_eq!(array.len(), 7);
+            assert_eq!(array.child(0).values::<i64>(), [2, 4, 6, 7]);
+            let runs = array.child(1);
+            assert_eq!(runs.null_count(), 1);
+            assert!(!runs.is_valid(1));
+        });
+        let decoded = run(arrow_udf_run_end_decode, &encoded).unwrap();
+        let decoded = decoded.nullable_values::<f64>();
+        assert_eq!(format!("{decoded:?}"), format!("{values:?}"));
+        let empty = Exported::primitive::<i32>(&[]);
+        let encoded = run(arrow_udf_run_end_encode, &empty).unwrap();
+        assert_eq!(encoded.with_array(|array| array.child(0).len()), 0);
+    }
+
+    #[test]
+    fn decoded_rows_follow_the_offset_and_the_run_ends_of_any_width() {
+        let schema = Schema::new(ArrowType::RunEndEncoded, "x").with_children(vec![
+            Schema::new(ArrowType::Int32, "run_ends"),
+            Schema::new(ArrowType::Int16, "values"),
+        ]);
+        let data = ArrayData {
+            length: 6,
+            null_count: 0,
+            buffers: Vec::new(),
+            children: vec![
+                ArrayData::primitive(Buffer::from_slice(&[2_i32, 3, 6]), 3),
+                nullable_data(&[Some(7_i16), None, Some(9)]),
+            ],
+        };
+        let input = Exported::new(&schema, data);
+        let decoded = run(arrow_udf_run_end_decode, &input).unwrap();
+        assert_eq!(
+            decoded.nullable_values::<i16>(),
+            [Some(7), Some(7), None, Some(9), Some(9), Some(9)]
+        );
+        let mut sliced = Exported::empty();
+        sliced.schema = export::export_schema(&schema);
+        sliced.array = ArrowCDataInterfaceArray {
+            offset: 1,
+            length: 3,
+            release: None,
+            ..input.array
+        };
+        let decoded = run(arrow_udf_run_end_decode, &sliced).unwrap();
+        assert_eq!(decoded.nullable_values::<i16>(), [Some(7), None, Some(9)]);
+    }
+
+    #[test]
+    fn other_types_are_unsupported() {
+        let strings = Exported::utf8(&[Some("a")]);
+        assert!(matches!(
+            run(arrow_udf_run_end_encode, &strings),
+            Err(ArrowUdfStatus::UnsupportedType)
+        ));
+        let plain = Exported::primitive(&[1_i64]);
+        assert!(matches!(
+            run(arrow_udf_run_end_decode, &plain),
+            Err(ArrowUdfStatus::UnsupportedType)
+        ));
+    }
+}
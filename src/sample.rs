@@ -0,0 +1,472 @@
+//! Random samples of the rows of arrays, from the seeds of `random`.
+//!
+//! `arrow_udf_sample_bernoulli` keeps every row with a probability, as a
+//! Boolean mask that hosts can filter with. The `reservoir_sample(k)`
+//! aggregate keeps a uniform sample of `k` non-null values of every group,
+//! among all the rows of all the batches it's updated with. Every value
+//! gets a random priority, and the sample keeps the `k` values of lowest
+//! priority, so merging the states of several partitions keeps the sample
+//! of all their rows, as if they were a single one.
+//!
+//! The priorities come from the seed of the spec, `reservoir_sample(k,
+//! seed)`, and the position of the rows among the rows the state was
+//! updated with. Partitions aggregated with the same seed get the same
+//! priorities at the same positions, so hosts should mix the partition into
+//! the seeds they pass. Without a seed, or with zero, every state uses a
+//! random one. `arrow_udf_reservoir_sample` samples a stream, with the seed
+//! of the options.
+//!
+//! There are no list arrays, so the result of the aggregate for every group
+//! is its sample as a Binary value, with the little-endian bytes of its
+//! values, and null for the groups without values.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BinaryHeap;
+use std::hash::BuildHasher;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aggregate::{self, Accumulator, Number};
+use crate::array::ArrowArray;
+use crate::binary::BinaryBuilder;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{
+    ArrowCDataInterfaceArray, ArrowCDataInterfaceArrayStream, ArrowCDataInterfaceSchema,
+};
+use crate::options::ArrowUdfExecOptions;
+use crate::random::{self, Rng};
+use crate::schema::{ArrowType, Schema};
+use crate::stream::ArrayStream;
+
+/// Boolean mask with every row of an array of `len` rows set with
+/// probability `p`, from the seed of `options`.
+pub fn bernoulli(len: usize, p: f64, options: &ArrowUdfExecOptions) -> Result<ArrayData> {
+    if !(0.0..=1.0).contains(&p) {
+        return Err(Error::InvalidArgument(format!(
+            "the probability must be between 0 and 1, got {p}"
+        )));
+    }
+    let seed = random::call_seed(options);
+    let mut mask = BitmapBuilder::with_capacity(len);
+    exec::for_each_batch(len, options, |rows| {
+        for i in rows {
+            mask.push(Rng::for_row(seed, i).next_f64() < p);
+        }
+        Ok(())
+    })?;
+    Ok(ArrayData {
+        length: len,
+        null_count: 0,
+        buffers: vec![None, Some(mask.finish())],
+        children: Vec::new(),
+    })
+}
+
+/// Sample of at most `k` values, the ones of lowest priority, with their
+/// priorities and the bits of the values.
+#[derive(Clone, Default)]
+struct Sample {
+    entries: BinaryHeap<(u64, u64)>,
+}
+
+impl Sample {
+    fn insert(&mut self, k: usize, priority: u64, bits: u64) {
+        if self.entries.len() < k {
+            self.entries.push((priority, bits));
+        } else if self
+            .entries
+            .peek()
+            .is_some_and(|(highest, _)| priority < *highest)
+        {
+            self.entries.pop();
+            self.entries.push((priority, bits));
+        }
+    }
+
+    /// The bits of the values, by priority.
+    fn values(&self) -> Vec<u64> {
+        let mut entries = self.entries.clone().into_vec();
+        entries.sort_unstable();
+        entries.into_iter().map(|(_, bits)| bits).collect()
+    }
+}
+
+/// Accumulator of `reservoir_sample`.
+struct Reservoir<T> {
+    k: usize,
+    seed: u64,
+    /// Rows the accumulator was updated with, the position of the next one.
+    rows: usize,
+    samples: Vec<Sample>,
+    _type: std::marker::PhantomData<T>,
+}
+
+impl<T: Number> Reservoir<T> {
+    fn new(k: usize, seed: u64) -> Reservoir<T> {
+        Reservoir {
+            k,
+            seed,
+            rows: 0,
+            samples: Vec::new(),
+            _type: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Number> Accumulator for Reservoir<T> {
+    fn output_type(&self) -> ArrowType {
+        ArrowType::Binary
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.samples.resize(n_groups, Sample::default());
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        let data = values.values::<T>();
+        for (row, group) in rows.clone().zip(groups) {
+            if values.is_valid(row) {
+                let priority = Rng::for_row(self.seed, self.rows + row - rows.start).next_u64();
+                self.samples[*group as usize].insert(self.k, priority, data[row].to_bits());
+            }
+        }
+        self.rows += rows.len();
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        aggregate::for_each_state(states, rows, groups, |group, mut state| {
+            for _ in 0..state.u64()? {
+                let (priority, bits) = (state.u64()?, state.u64()?);
+                self.samples[group].insert(self.k, priority, bits);
+            }
+            Ok(())
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        aggregate::states_array(self.samples.len(), |group, state| {
+            let entries = &self.samples[group].entries;
+            state.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+            for (priority, bits) in entries {
+                state.extend_from_slice(&priority.to_le_bytes());
+                state.extend_from_slice(&bits.to_le_bytes());
+            }
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        let mut builder = BinaryBuilder::with_capacity(self.samples.len());
+        for sample in &self.samples {
+            let bytes: Vec<u8> = sample
+                .values()
+                .iter()
+                .flat_map(|bits| bits.to_le_bytes())
+                .collect();
+            builder.push((!bytes.is_empty()).then_some(&bytes[..]));
+        }
+        builder.finish()
+    }
+}
+
+/// The size of the samples and the seed of `reservoir_sample(k, seed)`.
+fn reservoir_args(args: &[f64]) -> Result<(usize, u64)> {
+    let (k, seed) = match args {
+        [k] => (*k, 0.0),
+        [k, seed] => (*k, *seed),
+        _ => {
+            return Err(Error::InvalidArgument(
+                "reservoir_sample takes the size of the sample and an optional seed".to_string(),
+            ))
+        }
+    };
+    if k < 0.0 || k.fract() != 0.0 || seed < 0.0 || seed.fract() != 0.0 {
+        return Err(Error::InvalidArgument(format!(
+            "reservoir_sample({k}, {seed}) needs an integer size and seed"
+        )));
+    }
+    let seed = match seed as u64 {
+        0 => RandomState::new().hash_one(0),
+        seed => seed,
+    };
+    Ok((k as usize, seed))
+}
+
+pub(crate) fn accumulator(data_type: ArrowType, args: &[f64]) -> Result<Box<dyn Accumulator>> {
+    let (k, seed) = reservoir_args(args)?;
+    match data_type {
+        ArrowType::Int64 => Ok(Box::new(Reservoir::<i64>::new(k, seed))),
+        ArrowType::Float64 => Ok(Box::new(Reservoir::<f64>::new(k, seed))),
+        other => Err(aggregate::unsupported("reservoir_sample", other)),
+    }
+}
+
+/// A uniform sample of `k` non-null values of the arrays of `stream`, by
+/// their random priorities.
+fn reservoir_sample<T: Number>(
+    stream: &mut ArrayStream,
+    k: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrayData> {
+    let mut reservoir = Reservoir::<T>::new(k, random::call_seed(options));
+    reservoir.resize(1);
+    let mut groups = Vec::new();
+    stream.for_each(|array| {
+        groups.resize(array.len(), 0);
+        reservoir.update(array, 0..array.len(), &groups);
+        options.check_cancelled()
+    })?;
+    let values: Vec<T> = reservoir.samples[0]
+        .values()
+        .into_iter()
+        .map(T::from_bits)
+        .collect();
+    Ok(ArrayData::primitive(
+        Buffer::from_slice(&values),
+        values.len(),
+    ))
+}
+
+/// Boolean mask of the rows of an array, every one set with probability
+/// `p`, from the `seed` and `partition_id` options. The mask doesn't have
+/// nulls, and doesn't depend on the values of the array.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_sample_bernoulli(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    p: f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let mask = bernoulli(array.len(), p, &options)?;
+        let out = Schema::new(ArrowType::Boolean, &schema.name);
+        export::export_to(&out, &Arc::new(mask), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// A uniform sample of `k` non-null values of the Int64 or Float64 arrays
+/// of `stream`, from the `seed` and `partition_id` options, as an array of
+/// their type. Streams with less than `k` values return all of them, in a
+/// random order.
+///
+/// The stream is consumed and released, also when the call fails.
+///
+/// # Safety
+///
+/// `stream` must point to a valid C Stream Interface stream, `options` must
+/// be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_reservoir_sample(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    k: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let mut stream = ArrayStream::from_ffi(stream)?;
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        if k < 0 {
+            return Err(Error::InvalidArgument(format!("negative k: {k}")));
+        }
+        let schema = stream.schema().clone();
+        let data = match schema.data_type {
+            ArrowType::Int64 => reservoir_sample::<i64>(&mut stream, k as usize, &options)?,
+            ArrowType::Float64 => reservoir_sample::<f64>(&mut stream, k as usize, &options)?,
+            other => return Err(aggregate::unsupported("reservoir_sample", other)),
+        };
+        let out = Schema::new(schema.data_type, &schema.name);
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::{self, Exported};
+
+    fn mask(input: &Exported, p: f64, options: &ArrowUdfExecOptions) -> Vec<bool> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_sample_bernoulli(
+                &input.schema,
+                &input.array,
+                p,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        out.booleans().into_iter().map(Option::unwrap).collect()
+    }
+
+    #[test]
+    fn bernoulli_masks_keep_rows_with_their_probability() {
+        let input = Exported::nullable(&vec![None::<i8>; 10_000]);
+        let seeded = ArrowUdfExecOptions {
+            seed: 3,
+            ..ArrowUdfExecOptions::default()
+        };
+        let kept = mask(&input, 0.3, &seeded);
+        let count = kept.iter().filter(|kept| **kept).count();
+        assert!((2_700..3_300).contains(&count), "{count}");
+        let batched = ArrowUdfExecOptions {
+            batch_size: 100,
+            ..seeded
+        };
+        assert_eq!(mask(&input, 0.3, &batched), kept);
+        assert!(mask(&input, 0.0, &seeded).iter().all(|kept| !kept));
+        assert!(mask(&input, 1.0, &seeded).iter().all(|kept| *kept));
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_sample_bernoulli(
+                &input.schema,
+                &input.array,
+                1.5,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+    }
+
+    /// The samples of the groups of a finished accumulator.
+    fn samples(data: ArrayData) -> Vec<Option<Vec<i64>>> {
+        let result = Exported::new(&Schema::new(ArrowType::Binary, "x"), data);
+        result.with_array(|array| {
+            (0..array.len())
+                .map(|i| {
+                    array.is_valid(i).then(|| {
+                        array
+                            .binary_value(i)
+                            .chunks(8)
+                            .map(|bytes| i64::from_le_bytes(bytes.try_into().unwrap()))
+                            .collect()
+                    })
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn reservoirs_sample_the_values_of_every_group() {
+        let values: Vec<Option<i64>> = (0..1000).map(|i| (i % 3 != 0).then_some(i)).collect();
+        let input = Exported::nullable(&values);
+        let groups: Vec<u32> = (0..1000).map(|i| u32::from(i >= 500)).collect();
+        let run = |seed: f64| {
+            input.with_array(|array| {
+                let mut reservoir = accumulator(ArrowType::Int64, &[4.0, seed]).unwrap();
+                reservoir.resize(3);
+                reservoir.update(array, 0..300, &groups[..300]);
+                reservoir.update(array, 300..1000, &groups[300..]);
+                samples(reservoir.finish())
+            })
+        };
+        let sampled = run(9.0);
+        assert_eq!(sampled, run(9.0));
+        assert_ne!(sampled, run(10.0));
+        for (group, sample) in sampled[..2].iter().enumerate() {
+            let sample = sample.as_ref().unwrap();
+            assert_eq!(sample.len(), 4);
+            let rows = group as i64 * 500..(group as i64 + 1) * 500;
+            assert!(
+                sample.iter().all(|v| rows.contains(v) && v % 3 != 0),
+                "{sample:?}"
+            );
+        }
+        assert_eq!(sampled[2], None);
+    }
+
+    #[test]
+    fn merged_reservoirs_keep_the_lowest_priorities() {
+        let input = Exported::primitive(&(0..100_i64).collect::<Vec<_>>());
+        let (single, merged) = input.with_array(|array| {
+            let mut single = accumulator(ArrowType::Int64, &[5.0, 1.0]).unwrap();
+            single.resize(1);
+            single.update(array, 0..100, &[0; 100]);
+            let state = Exported::new(&Schema::new(ArrowType::Binary, "x"), single.state());
+            let mut merged = accumulator(ArrowType::Int64, &[5.0, 2.0]).unwrap();
+            merged.resize(1);
+            state.with_array(|state| merged.merge(state, 0..1, &[0]).unwrap());
+            let mut single = accumulator(ArrowType::Int64, &[5.0, 1.0]).unwrap();
+            single.resize(1);
+            single.update(array, 0..100, &[0; 100]);
+            (samples(single.finish()), samples(merged.finish()))
+        });
+        assert_eq!(single, merged);
+        for args in [&[][..], &[-1.0], &[2.5], &[1.0, 0.5], &[1.0, 2.0, 3.0]] {
+            assert!(matches!(
+                accumulator(ArrowType::Int64, args),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        assert!(matches!(
+            accumulator(ArrowType::Int32, &[1.0]),
+            Err(Error::UnsupportedType(_))
+        ));
+    }
+
+    fn sample_stream(
+        batches: Vec<Exported>,
+        k: i64,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Vec<f64>, ArrowUdfStatus> {
+        let schema = Schema::new(ArrowType::Float64, "x");
+        let mut stream = testing::stream(&schema, batches, None);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_reservoir_sample(&mut stream, k, options, &mut out.schema, &mut out.array)
+        };
+        assert!(stream.release.is_none());
+        match status {
+            ArrowUdfStatus::Ok => Ok(out.values::<f64>()),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn streams_are_sampled_across_their_batches() {
+        let batches = || {
+            vec![
+                Exported::nullable(&[Some(1.0_f64), None, Some(2.0)]),
+                Exported::primitive(&[3.0_f64, 4.0, 5.0]),
+            ]
+        };
+        let options = ArrowUdfExecOptions {
+            seed: 11,
+            ..ArrowUdfExecOptions::default()
+        };
+        let sample = sample_stream(batches(), 3, &options).unwrap();
+        assert_eq!(sample.len(), 3);
+        assert!(sample.iter().all(|v| [1.0, 2.0, 3.0, 4.0, 5.0].contains(v)));
+        assert_eq!(sample_stream(batches(), 3, &options), Ok(sample));
+        let mut all = sample_stream(batches(), 10, &options).unwrap();
+        all.sort_by(f64::total_cmp);
+        assert_eq!(all, [1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(
+            sample_stream(batches(), -1, &options),
+            Err(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,922 @@
+//! Parsing of the schemas received through the C Data Interface.
+
+use std::ffi::CStr;
+
+use crate::endian;
+use crate::error::{Error, Result};
+use crate::ffi::{
+    ArrowCDataInterfaceSchema, ARROW_FLAG_DICTIONARY_ORDERED, ARROW_FLAG_MAP_KEYS_SORTED,
+    ARROW_FLAG_NULLABLE,
+};
+use crate::format::ToArrowFormat;
+
+/// Metadata key that producers can set to `"true"` to indicate that all the
+/// values of an array are the same.
+pub const CONSTANT_METADATA_KEY: &str = "arrow_udf.constant";
+
+/// Metadata key with the name of the extension type of a field.
+pub const EXTENSION_NAME_KEY: &str = "ARROW:extension:name";
+
+/// Name of the canonical extension type of UUIDs, stored as FixedSizeBinary
+/// values of 16 bytes.
+pub const UUID_EXTENSION: &str = "arrow.uuid";
+
+/// Resolution of the values of a timestamp or time array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeUnit {
+    Second,
+    Millisecond,
+    Microsecond,
+    Nanosecond,
+}
+
+impl TimeUnit {
+    /// Number of values of this unit in a second.
+    pub fn per_second(&self) -> i64 {
+        match self {
+            TimeUnit::Second => 1,
+            TimeUnit::Millisecond => 1_000,
+            TimeUnit::Microsecond => 1_000_000,
+            TimeUnit::Nanosecond => 1_000_000_000,
+        }
+    }
+
+    /// Parse a unit name: `second`, `millisecond`, `microsecond` or
+    /// `nanosecond`.
+    pub fn from_name(name: &str) -> Result<TimeUnit> {
+        Ok(match name {
+            "second" => TimeUnit::Second,
+            "millisecond" => TimeUnit::Millisecond,
+            "microsecond" => TimeUnit::Microsecond,
+            "nanosecond" => TimeUnit::Nanosecond,
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown time unit {name:?}"
+                )))
+            }
+        })
+    }
+}
+
+/// Fields of the values of an interval array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntervalUnit {
+    /// Months, as an Int32.
+    YearMonth,
+    /// Days and milliseconds, as two Int32.
+    DayTime,
+    /// Months and days, as two Int32, and nanoseconds, as an Int64.
+    MonthDayNano,
+}
+
+/// Layout of the values of a union array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnionMode {
+    /// Every field has a row for every row of the union, the type ids telling
+    /// which of them is the value.
+    Sparse,
+    /// Every field only has the values of its type, at the positions given
+    /// by the offsets of the union.
+    Dense,
+}
+
+/// Data types that this library knows how to handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArrowType {
+    Boolean,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    Binary,
+    /// Binary with 64-bit offsets, for arrays of more than 2GiB of values.
+    LargeBinary,
+    Utf8,
+    /// Values of the same number of bytes. The number is only in the format
+    /// string, see `Schema::fixed_size_binary_width`.
+    FixedSizeBinary,
+    /// Days since the UNIX epoch, as Int32.
+    Date32,
+    /// Milliseconds since the UNIX epoch, as Int64.
+    Date64,
+    /// Time since the UNIX epoch in UTC, as Int64. The timezone, if any, is
+    /// only in the format string, see `Schema::timezone`.
+    Timestamp(TimeUnit),
+    /// Time since midnight, as Int32 for seconds and milliseconds, and as
+    /// Int64 for microseconds and nanoseconds.
+    Time(TimeUnit),
+    /// Elapsed time, as Int64.
+    Duration(TimeUnit),
+    /// Calendar interval, whose months and days don't have a fixed length.
+    Interval(IntervalUnit),
+    /// Decimal stored as an Int128. The precision and the scale are only in
+    /// the format string, see `Schema::decimal`.
+    Decimal128,
+    RunEndEncoded,
+    Struct,
+    /// List of key and value entries per row, in a struct child with the
+    /// keys and the values as fields.
+    Map,
+    /// Value of one of the fields of the union per row, chosen by its type
+    /// id. The type ids of the fields are only in the format string, see
+    /// `Schema::union_type_ids`.
+    Union(UnionMode),
+}
+
+impl ArrowType {
+    /// Parse an Arrow C Data Interface format string. Decimal formats need
+    /// the `decimal` feature, Time formats the `temporal` feature, and Map
+    /// and Union formats the `nested` feature, so arrays of those types fail
+    /// to import without them.
+    pub fn from_format(format: &str) -> Option<ArrowType> {
+        Some(match format {
+            "b" => ArrowType::Boolean,
+            "c" => ArrowType::Int8,
+            "s" => ArrowType::Int16,
+            "i" => ArrowType::Int32,
+            "l" => ArrowType::Int64,
+            "C" => ArrowType::UInt8,
+            "S" => ArrowType::UInt16,
+            "I" => ArrowType::UInt32,
+            "L" => ArrowType::UInt64,
+            "f" => ArrowType::Float32,
+            "g" => ArrowType::Float64,
+            "z" => ArrowType::Binary,
+            "Z" => ArrowType::LargeBinary,
+            "u" => ArrowType::Utf8,
+            "tdD" => ArrowType::Date32,
+            "tdm" => ArrowType::Date64,
+            #[cfg(feature = "temporal")]
+            "tts" => ArrowType::Time(TimeUnit::Second),
+            #[cfg(feature = "temporal")]
+            "ttm" => ArrowType::Time(TimeUnit::Millisecond),
+            #[cfg(feature = "temporal")]
+            "ttu" => ArrowType::Time(TimeUnit::Microsecond),
+            #[cfg(feature = "temporal")]
+            "ttn" => ArrowType::Time(TimeUnit::Nanosecond),
+            "tDs" => ArrowType::Duration(TimeUnit::Second),
+            "tDm" => ArrowType::Duration(TimeUnit::Millisecond),
+            "tDu" => ArrowType::Duration(TimeUnit::Microsecond),
+            "tDn" => ArrowType::Duration(TimeUnit::Nanosecond),
+            "tiM" => ArrowType::Interval(IntervalUnit::YearMonth),
+            "tiD" => ArrowType::Interval(IntervalUnit::DayTime),
+            "tin" => ArrowType::Interval(IntervalUnit::MonthDayNano),
+            "+r" => ArrowType::RunEndEncoded,
+            "+s" => ArrowType::Struct,
+            #[cfg(feature = "nested")]
+            "+m" => ArrowType::Map,
+            _ => match format.split_once(':')? {
+                ("tss", _) => ArrowType::Timestamp(TimeUnit::Second),
+                ("tsm", _) => ArrowType::Timestamp(TimeUnit::Millisecond),
+                ("tsu", _) => ArrowType::Timestamp(TimeUnit::Microsecond),
+                ("tsn", _) => ArrowType::Timestamp(TimeUnit::Nanosecond),
+                #[cfg(feature = "decimal")]
+                ("d", params) => {
+                    decimal_params(params)?;
+                    ArrowType::Decimal128
+                }
+                ("w", width) => {
+                    fixed_size_binary_width(width)?;
+                    ArrowType::FixedSizeBinary
+                }
+                #[cfg(feature = "nested")]
+                ("+us", ids) => {
+                    union_type_ids(ids)?;
+                    ArrowType::Union(UnionMode::Sparse)
+                }
+                #[cfg(feature = "nested")]
+                ("+ud", ids) => {
+                    union_type_ids(ids)?;
+                    ArrowType::Union(UnionMode::Dense)
+                }
+                _ => return None,
+            },
+        })
+    }
+
+    pub fn format(&self) -> &'static str {
+        match self {
+            ArrowType::Boolean => "b",
+            ArrowType::Int8 => "c",
+            ArrowType::Int16 => "s",
+            ArrowType::Int32 => "i",
+            ArrowType::Int64 => "l",
+            ArrowType::UInt8 => "C",
+            ArrowType::UInt16 => "S",
+            ArrowType::UInt32 => "I",
+            ArrowType::UInt64 => "L",
+            ArrowType::Float32 => "f",
+            ArrowType::Float64 => "g",
+            ArrowType::Binary => "z",
+            ArrowType::LargeBinary => "Z",
+            ArrowType::Utf8 => "u",
+            ArrowType::Date32 => "tdD",
+            ArrowType::Date64 => "tdm",
+            ArrowType::Timestamp(TimeUnit::Second) => "tss:",
+            ArrowType::Timestamp(TimeUnit::Millisecond) => "tsm:",
+            ArrowType::Timestamp(TimeUnit::Microsecond) => "tsu:",
+            ArrowType::Timestamp(TimeUnit::Nanosecond) => "tsn:",
+            ArrowType::Time(TimeUnit::Second) => "tts",
+            ArrowType::Time(TimeUnit::Millisecond) => "ttm",
+            ArrowType::Time(TimeUnit::Microsecond) => "ttu",
+            ArrowType::Time(TimeUnit::Nanosecond) => "ttn",
+            ArrowType::Duration(TimeUnit::Second) => "tDs",
+            ArrowType::Duration(TimeUnit::Millisecond) => "tDm",
+            ArrowType::Duration(TimeUnit::Microsecond) => "tDu",
+            ArrowType::Duration(TimeUnit::Nanosecond) => "tDn",
+            ArrowType::Interval(IntervalUnit::YearMonth) => "tiM",
+            ArrowType::Interval(IntervalUnit::DayTime) => "tiD",
+            ArrowType::Interval(IntervalUnit::MonthDayNano) => "tin",
+            // Parameterized formats are written by `format::FormatBuilder`.
+            ArrowType::Decimal128 => "d:38,0",
+            // The width is written by `Schema::fixed_size_binary`.
+            ArrowType::FixedSizeBinary => "w:",
+            ArrowType::RunEndEncoded => "+r",
+            ArrowType::Struct => "+s",
+            ArrowType::Map => "+m",
+            // The type ids of the fields are written by `Schema::union`.
+            ArrowType::Union(UnionMode::Sparse) => "+us:",
+            ArrowType::Union(UnionMode::Dense) => "+ud:",
+        }
+    }
+
+    /// The primitive type the values are stored as: the type itself, or the
+    /// integer type of temporal types. Intervals of more than one field are
+    /// their own type.
+    pub fn physical_type(&self) -> ArrowType {
+        match self {
+            ArrowType::Date32
+            | ArrowType::Time(TimeUnit::Second | TimeUnit::Millisecond)
+            | ArrowType::Interval(IntervalUnit::YearMonth) => ArrowType::Int32,
+            ArrowType::Date64
+            | ArrowType::Timestamp(_)
+            | ArrowType::Time(_)
+            | ArrowType::Duration(_) => ArrowType::Int64,
+            data_type => *data_type,
+        }
+    }
+}
+
+/// The precision and the scale of the parameters of a decimal format, like
+/// `10,2` or `10,2,128`. Only 128-bit decimals are supported.
+pub(crate) fn decimal_params(params: &str) -> Option<(u8, i8)> {
+    let mut params = params.split(',');
+    let precision: u8 = params.next()?.parse().ok()?;
+    let scale: i8 = params.next()?.parse().ok()?;
+    if !matches!(params.next(), None | Some("128")) || params.next().is_some() {
+        return None;
+    }
+    (1..=38).contains(&precision).then_some((precision, scale))
+}
+
+/// The number of bytes of the values of a FixedSizeBinary format.
+pub(crate) fn fixed_size_binary_width(width: &str) -> Option<usize> {
+    width
+        .parse::<i32>()
+        .ok()
+        .filter(|width| *width >= 0)
+        .map(|width| width as usize)
+}
+
+/// The type ids of the fields of a union format, like `0,1,4`: distinct
+/// numbers from 0 to 127.
+pub(crate) fn union_type_ids(ids: &str) -> Option<Vec<i8>> {
+    if ids.is_empty() {
+        return Some(Vec::new());
+    }
+    let ids = ids
+        .split(',')
+        .map(|id| id.parse::<i8>().ok().filter(|id| *id >= 0))
+        .collect::<Option<Vec<i8>>>()?;
+    let distinct = ids.iter().enumerate().all(|(i, id)| !ids[..i].contains(id));
+    distinct.then_some(ids)
+}
+
+/// Key-value pairs attached to a schema, in the order they were received.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata(pub Vec<(String, String)>);
+
+impl Metadata {
+    /// Decode the binary metadata representation of the C Data Interface: an
+    /// int32 with the number of pairs, followed by each key and value, every
+    /// one of them prefixed by its int32 length.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to metadata encoded as described above.
+    pub unsafe fn from_ffi(ptr: *const u8) -> Metadata {
+        if ptr.is_null() {
+            return Metadata::default();
+        }
+        unsafe fn read_i32(pos: &mut *const u8) -> i32 {
+            let value = (*pos as *const i32).read_unaligned();
+            *pos = pos.add(4);
+            value
+        }
+        unsafe fn read_str(pos: &mut *const u8) -> String {
+            let len = read_i32(pos) as usize;
+            let bytes = std::slice::from_raw_parts(*pos, len);
+            *pos = pos.add(len);
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+
+        let mut pos = ptr;
+        let n_pairs = read_i32(&mut pos);
+        let pairs = (0..n_pairs)
+            .map(|_| {
+                let key = read_str(&mut pos);
+                let value = read_str(&mut pos);
+                (key, value)
+            })
+            .collect();
+        Metadata(pairs)
+    }
+
+    /// Encode the metadata in its binary C Data Interface representation.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.0.len() as i32).to_ne_bytes());
+        for (key, value) in &self.0 {
+            bytes.extend_from_slice(&(key.len() as i32).to_ne_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&(value.len() as i32).to_ne_bytes());
+            bytes.extend_from_slice(value.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The `ARROW_FLAG_*` flags of a field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchemaFlags {
+    /// Whether the field can have nulls. Arrays of fields that can't are
+    /// read without their validity bitmap, if they have one.
+    pub nullable: bool,
+    /// Whether the order of the values of the dictionary of the field is
+    /// meaningful, so comparing their indices compares them.
+    pub dictionary_ordered: bool,
+    /// Whether the keys of every map of the field are sorted.
+    pub map_keys_sorted: bool,
+}
+
+impl Default for SchemaFlags {
+    /// Flags of the fields created by this library, which can have nulls.
+    fn default() -> SchemaFlags {
+        SchemaFlags {
+            nullable: true,
+            dictionary_ordered: false,
+            map_keys_sorted: false,
+        }
+    }
+}
+
+impl SchemaFlags {
+    /// Parse the `flags` of a field of `format`, with a dictionary or not.
+    /// Unknown flags, and flags of dictionaries and maps on fields that
+    /// aren't, are invalid.
+    pub fn from_ffi(flags: i64, format: &str, has_dictionary: bool) -> Result<SchemaFlags> {
+        let known =
+            ARROW_FLAG_NULLABLE | ARROW_FLAG_DICTIONARY_ORDERED | ARROW_FLAG_MAP_KEYS_SORTED;
+        if flags & !known != 0 {
+            return Err(Error::InvalidArgument(format!(
+                "unknown schema flags {:#x}",
+                flags & !known
+            )));
+        }
+        let flags = SchemaFlags {
+            nullable: flags & ARROW_FLAG_NULLABLE != 0,
+            dictionary_ordered: flags & ARROW_FLAG_DICTIONARY_ORDERED != 0,
+            map_keys_sorted: flags & ARROW_FLAG_MAP_KEYS_SORTED != 0,
+        };
+        if flags.dictionary_ordered && !has_dictionary {
+            return Err(Error::InvalidArgument(format!(
+                "ARROW_FLAG_DICTIONARY_ORDERED on a {format:?} field without dictionary"
+            )));
+        }
+        if flags.map_keys_sorted && format != "+m" {
+            return Err(Error::InvalidArgument(format!(
+                "ARROW_FLAG_MAP_KEYS_SORTED on a {format:?} field"
+            )));
+        }
+        Ok(flags)
+    }
+
+    /// The flags as the bits of the C Data Interface.
+    pub fn bits(&self) -> i64 {
+        let mut bits = 0;
+        if self.nullable {
+            bits |= ARROW_FLAG_NULLABLE;
+        }
+        if self.dictionary_ordered {
+            bits |= ARROW_FLAG_DICTIONARY_ORDERED;
+        }
+        if self.map_keys_sorted {
+            bits |= ARROW_FLAG_MAP_KEYS_SORTED;
+        }
+        bits
+    }
+}
+
+/// Owned representation of an `ArrowCDataInterfaceSchema`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+    pub format: String,
+    pub data_type: ArrowType,
+    pub name: String,
+    pub metadata: Metadata,
+    pub flags: SchemaFlags,
+    pub children: Vec<Schema>,
+}
+
+impl Schema {
+    pub fn new(data_type: ArrowType, name: &str) -> Schema {
+        Schema {
+            format: data_type.format().to_string(),
+            data_type,
+            name: name.to_string(),
+            metadata: Metadata::default(),
+            flags: SchemaFlags::default(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Map field with the `key` and `value` fields as the fields of its
+    /// entries.
+    pub fn map(name: &str, key: Schema, value: Schema) -> Schema {
+        let entries = Schema::new(ArrowType::Struct, "entries").with_children(vec![key, value]);
+        Schema::new(ArrowType::Map, name).with_children(vec![entries])
+    }
+
+    /// FixedSizeBinary field with values of `width` bytes.
+    pub fn fixed_size_binary(name: &str, width: usize) -> Schema {
+        let mut schema = Schema::new(ArrowType::FixedSizeBinary, name);
+        schema.format.push_str(&width.to_string());
+        schema
+    }
+
+    /// Field of the `arrow.uuid` extension type.
+    pub fn uuid(name: &str) -> Schema {
+        Schema::fixed_size_binary(name, 16).with_metadata(EXTENSION_NAME_KEY, UUID_EXTENSION)
+    }
+
+    /// Union field of `mode` with `fields`, whose type ids are their
+    /// positions.
+    pub fn union(name: &str, mode: UnionMode, fields: Vec<Schema>) -> Schema {
+        let ids: Vec<String> = (0..fields.len()).map(|i| i.to_string()).collect();
+        let mut schema = Schema::new(ArrowType::Union(mode), name).with_children(fields);
+        schema.format.push_str(&ids.join(","));
+        schema
+    }
+
+    pub fn with_children(mut self, children: Vec<Schema>) -> Schema {
+        self.children = children;
+        self
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Schema {
+        self.metadata.0.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Replace the format written by `Schema::new` with one carrying the
+    /// parameters of the type, like the timezone of a timestamp.
+    pub fn with_format(mut self, format: &impl ToArrowFormat) -> Result<Schema> {
+        let format = format.to_format()?;
+        self.data_type = ArrowType::from_format(&format)
+            .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+        self.format = format;
+        Ok(self)
+    }
+
+    /// # Safety
+    ///
+    /// `schema` must be a valid, non released, C Data Interface schema.
+    pub unsafe fn from_ffi(schema: &ArrowCDataInterfaceSchema) -> Result<Schema> {
+        let schema = Schema::from_ffi_any_endianness(schema)?;
+        endian::check(&schema)?;
+        Ok(schema)
+    }
+
+    /// Import a schema like `from_ffi`, whatever the byte order of its
+    /// fields.
+    ///
+    /// # Safety
+    ///
+    /// Like `from_ffi`.
+    pub unsafe fn from_ffi_any_endianness(schema: &ArrowCDataInterfaceSchema) -> Result<Schema> {
+        if schema.release.is_none() {
+            return Err(Error::InvalidArgument("the schema is released".to_string()));
+        }
+        let format = CStr::from_ptr(schema.format).to_string_lossy().into_owned();
+        let data_type = ArrowType::from_format(&format)
+            .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+        let name = if schema.name.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(schema.name).to_string_lossy().into_owned()
+        };
+        let flags = SchemaFlags::from_ffi(schema.flags, &format, !schema.dictionary.is_null())?;
+        let children: Vec<Schema> = (0..schema.n_children as usize)
+            .map(|i| Schema::from_ffi_any_endianness(schema.child(i)))
+            .collect::<Result<_>>()?;
+        if data_type == ArrowType::Map
+            && !matches!(&children[..], [entries] if entries.data_type == ArrowType::Struct
+                && entries.children.len() == 2)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "map field {name:?} without a struct of keys and values"
+            )));
+        }
+        let metadata = Metadata::from_ffi(schema.metadata as *const u8);
+        if metadata.get(EXTENSION_NAME_KEY) == Some(UUID_EXTENSION) && format != "w:16" {
+            return Err(Error::InvalidArgument(format!(
+                "{UUID_EXTENSION} field {name:?} of format {format:?} instead of \"w:16\""
+            )));
+        }
+        if matches!(data_type, ArrowType::Union(_))
+            && union_type_ids(&format[4..]).unwrap_or_default().len() != children.len()
+        {
+            return Err(Error::InvalidArgument(format!(
+                "union field {name:?} with {} fields and type ids {:?}",
+                children.len(),
+                &format[4..]
+            )));
+        }
+        Ok(Schema {
+            format,
+            data_type,
+            name,
+            metadata,
+            flags,
+            children,
+        })
+    }
+
+    /// Import the `n` schemas pointed by `schemas`, for entry points
+    /// receiving a list of arrays.
+    ///
+    /// # Safety
+    ///
+    /// `schemas` must point to `n` pointers to valid, non released, C Data
+    /// Interface schemas.
+    pub unsafe fn from_ffi_list(
+        n: i64,
+        schemas: *const *const ArrowCDataInterfaceSchema,
+    ) -> Result<Vec<Schema>> {
+        if n < 0 {
+            return Err(Error::InvalidArgument(
+                "negative number of arrays".to_string(),
+            ));
+        }
+        (0..n as usize)
+            .map(|i| Schema::from_ffi(&**schemas.add(i)))
+            .collect()
+    }
+
+    /// Whether arrays described by this schema can be used where arrays
+    /// described by `expected` are: they have the same format, including
+    /// parameters like the timezone, and compatible children. The fields of
+    /// structs are matched by name, and can have extra fields, while other
+    /// children are matched by position. Names are otherwise ignored, and
+    /// so are nullability and metadata.
+    pub fn is_compatible_with(&self, expected: &Schema) -> bool {
+        if self.format != expected.format {
+            return false;
+        }
+        if self.data_type == ArrowType::Struct {
+            return expected.children.iter().all(|field| {
+                self.children
+                    .iter()
+                    .any(|child| child.name == field.name && child.is_compatible_with(field))
+            });
+        }
+        self.children.len() == expected.children.len()
+            && self
+                .children
+                .iter()
+                .zip(&expected.children)
+                .all(|(child, expected)| child.is_compatible_with(expected))
+    }
+
+    /// The timezone of a timestamp array, as found after the unit in the
+    /// format string, or `None` for timestamps without a timezone.
+    pub fn timezone(&self) -> Option<&str> {
+        match self.data_type {
+            ArrowType::Timestamp(_) => Some(&self.format[4..]).filter(|tz| !tz.is_empty()),
+            _ => None,
+        }
+    }
+
+    /// The precision and the scale of a decimal array, as found in the format
+    /// string.
+    pub fn decimal(&self) -> Option<(u8, i8)> {
+        match self.data_type {
+            ArrowType::Decimal128 => decimal_params(&self.format[2..]),
+            _ => None,
+        }
+    }
+
+    /// The number of bytes of the values of a FixedSizeBinary array, as
+    /// found in the format string.
+    pub fn fixed_size_binary_width(&self) -> Option<usize> {
+        match self.data_type {
+            ArrowType::FixedSizeBinary => fixed_size_binary_width(&self.format[2..]),
+            _ => None,
+        }
+    }
+
+    /// The name of the extension type of the field, if any.
+    pub fn extension_name(&self) -> Option<&str> {
+        self.metadata.get(EXTENSION_NAME_KEY)
+    }
+
+    /// Whether the field is of the `arrow.uuid` extension type.
+    pub fn is_uuid(&self) -> bool {
+        self.extension_name() == Some(UUID_EXTENSION) && self.fixed_size_binary_width() == Some(16)
+    }
+
+    /// The type ids of the fields of a union array, in the order of the
+    /// fields, as found in the format string.
+    pub fn union_type_ids(&self) -> Option<Vec<i8>> {
+        match self.data_type {
+            ArrowType::Union(_) => union_type_ids(&self.format[4..]),
+            _ => None,
+        }
+    }
+
+    /// Whether the producer flagged the array as having a single repeated value.
+    pub fn is_constant(&self) -> bool {
+        self.metadata.get(CONSTANT_METADATA_KEY) == Some("true")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export;
+
+    #[cfg(all(feature = "decimal", feature = "nested", feature = "temporal"))]
+    #[test]
+    fn formats_round_trip() {
+        for format in [
+            "b", "c", "s", "i", "l", "C", "S", "I", "L", "f", "g", "z", "Z", "u", "tdD", "tdm",
+            "tss:", "tsn:", "tts", "ttm", "ttu", "ttn", "tDs", "tDm", "tDu", "tDn", "tiM", "tiD",
+            "tin", "+r", "+s", "+m", "+us:", "+ud:",
+        ] {
+            assert_eq!(ArrowType::from_format(format).unwrap().format(), format);
+        }
+        assert_eq!(ArrowType::from_format("x"), None);
+    }
+
+    #[cfg(feature = "temporal")]
+    #[test]
+    fn temporal_types_are_stored_as_integers() {
+        let physical = |format| ArrowType::from_format(format).unwrap().physical_type();
+        assert_eq!(physical("tdD"), ArrowType::Int32);
+        assert_eq!(physical("tts"), ArrowType::Int32);
+        assert_eq!(physical("ttm"), ArrowType::Int32);
+        assert_eq!(physical("ttu"), ArrowType::Int64);
+        assert_eq!(physical("tsn:UTC"), ArrowType::Int64);
+        assert_eq!(physical("g"), ArrowType::Float64);
+        assert_eq!(physical("tDs"), ArrowType::Int64);
+        assert_eq!(physical("tiM"), ArrowType::Int32);
+        assert_eq!(
+            physical("tin"),
+            ArrowType::Interval(IntervalUnit::MonthDayNano)
+        );
+        let mut schema = Schema::new(ArrowType::Timestamp(TimeUnit::Millisecond), "t");
+        assert_eq!(schema.timezone(), None);
+        schema.format = "tsm:Europe/Paris".to_string();
+        assert_eq!(schema.timezone(), Some("Europe/Paris"));
+        assert_eq!(ArrowType::from_format("tsx:UTC"), None);
+    }
+
+    #[test]
+    fn formats_of_disabled_features_are_unknown() {
+        for (format, enabled) in [
+            ("d:10,2", cfg!(feature = "decimal")),
+            ("ttm", cfg!(feature = "temporal")),
+            ("+m", cfg!(feature = "nested")),
+            ("+ud:0,1", cfg!(feature = "nested")),
+        ] {
+            assert_eq!(
+                ArrowType::from_format(format).is_some(),
+                enabled,
+                "{format}"
+            );
+        }
+        assert!(ArrowType::from_format("tdD").is_some());
+        assert!(ArrowType::from_format("tsu:UTC").is_some());
+    }
+
+    #[test]
+    fn schemas_round_trip_through_ffi() {
+        let mut schema = Schema::new(ArrowType::Float64, "x");
+        schema.metadata = Metadata(vec![
+            ("key".to_string(), "value".to_string()),
+            ("empty".to_string(), String::new()),
+        ]);
+        let mut exported = export::export_schema(&schema);
+        let imported = unsafe { Schema::from_ffi(&exported) }.unwrap();
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert_eq!(imported, schema);
+        assert_eq!(imported.metadata.get("key"), Some("value"));
+        assert_eq!(imported.metadata.get("other"), None);
+    }
+
+    #[test]
+    fn lists_of_schemas() {
+        let schemas = [
+            export::export_schema(&Schema::new(ArrowType::Int8, "a")),
+            export::export_schema(&Schema::new(ArrowType::Float64, "b")),
+        ];
+        let pointers: Vec<_> = schemas.iter().map(|schema| schema as *const _).collect();
+        let imported = unsafe { Schema::from_ffi_list(2, pointers.as_ptr()) }.unwrap();
+        assert_eq!(imported[1], Schema::new(ArrowType::Float64, "b"));
+        assert!(unsafe { Schema::from_ffi_list(0, std::ptr::null()) }
+            .unwrap()
+            .is_empty());
+        assert!(unsafe { Schema::from_ffi_list(-1, pointers.as_ptr()) }.is_err());
+        for mut schema in schemas {
+            unsafe { schema.release.unwrap()(&mut schema) };
+        }
+    }
+
+    #[test]
+    fn invalid_schemas_fail() {
+        let mut exported = export::export_schema(&Schema::new(ArrowType::Int8, "x"));
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert!(matches!(
+            unsafe { Schema::from_ffi(&exported) },
+            Err(Error::InvalidArgument(_))
+        ));
+        // Schemas with unknown formats can't be exported, so the format of
+        // an exported one is replaced.
+        let mut exported = export::export_schema(&Schema::new(ArrowType::Int8, "x"));
+        exported.format = c"vu".as_ptr();
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert!(matches!(imported, Err(Error::UnsupportedType(_))));
+    }
+
+    #[test]
+    fn compatible_schemas() {
+        let field = |data_type, name| Schema::new(data_type, name);
+        let points = Schema::new(ArrowType::Struct, "p").with_children(vec![
+            field(ArrowType::Float64, "lat"),
+            field(ArrowType::Float64, "lon"),
+        ]);
+        let mut reordered = Schema::new(ArrowType::Struct, "other").with_children(vec![
+            field(ArrowType::Int8, "id"),
+            field(ArrowType::Float64, "lon"),
+            field(ArrowType::Float64, "lat"),
+        ]);
+        reordered.flags.nullable = false;
+        assert!(reordered.is_compatible_with(&points));
+        assert!(!points.is_compatible_with(&reordered));
+        reordered.children[2].data_type = ArrowType::Float32;
+        reordered.children[2].format = "f".to_string();
+        assert!(!reordered.is_compatible_with(&points));
+        let mut utc = field(ArrowType::Timestamp(TimeUnit::Microsecond), "t");
+        utc.format = "tsu:UTC".to_string();
+        let mut naive = utc.clone();
+        naive.format = "tsu:".to_string();
+        assert!(utc.is_compatible_with(&utc.clone()));
+        assert!(!utc.is_compatible_with(&naive));
+        assert!(!field(ArrowType::Int32, "x").is_compatible_with(&field(ArrowType::Int64, "x")));
+    }
+
+    #[test]
+    fn flags_are_parsed_and_validated() {
+        let flags = SchemaFlags::from_ffi(ARROW_FLAG_NULLABLE, "i", false).unwrap();
+        assert_eq!(flags, SchemaFlags::default());
+        assert_eq!(flags.bits(), ARROW_FLAG_NULLABLE);
+        let flags = SchemaFlags::from_ffi(0, "i", false).unwrap();
+        assert!(!flags.nullable);
+        assert_eq!(flags.bits(), 0);
+        let ordered = SchemaFlags::from_ffi(ARROW_FLAG_DICTIONARY_ORDERED, "i", true).unwrap();
+        assert!(ordered.dictionary_ordered);
+        assert_eq!(ordered.bits(), ARROW_FLAG_DICTIONARY_ORDERED);
+        let sorted = SchemaFlags::from_ffi(ARROW_FLAG_MAP_KEYS_SORTED, "+m", false).unwrap();
+        assert_eq!(sorted.bits(), ARROW_FLAG_MAP_KEYS_SORTED);
+
+        for (flags, format) in [
+            (8, "i"),
+            (ARROW_FLAG_DICTIONARY_ORDERED, "i"),
+            (ARROW_FLAG_MAP_KEYS_SORTED, "+s"),
+        ] {
+            assert!(matches!(
+                SchemaFlags::from_ffi(flags, format, false),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn flags_round_trip_through_ffi() {
+        let mut schema = Schema::new(ArrowType::Int32, "x");
+        schema.flags.nullable = false;
+        let mut exported = export::export_schema(&schema);
+        assert_eq!(exported.flags, 0);
+        let imported = unsafe { Schema::from_ffi(&exported) }.unwrap();
+        assert_eq!(imported.flags, schema.flags);
+        exported.flags = 16;
+        let invalid = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert!(matches!(invalid, Err(Error::InvalidArgument(_))));
+    }
+
+    #[cfg(feature = "nested")]
+    #[test]
+    fn union_formats_have_the_type_ids_of_their_fields() {
+        assert_eq!(
+            ArrowType::from_format("+us:0,1,4"),
+            Some(ArrowType::Union(UnionMode::Sparse))
+        );
+        assert_eq!(
+            ArrowType::from_format("+ud:7"),
+            Some(ArrowType::Union(UnionMode::Dense))
+        );
+        for invalid in ["+us:0,0", "+us:-1", "+ud:128", "+ud:a", "+ux:0"] {
+            assert_eq!(ArrowType::from_format(invalid), None, "{invalid}");
+        }
+        let fields = vec![
+            Schema::new(ArrowType::Int32, "i"),
+            Schema::new(ArrowType::Utf8, "s"),
+        ];
+        let schema = Schema::union("u", UnionMode::Dense, fields);
+        assert_eq!(schema.format, "+ud:0,1");
+        assert_eq!(schema.union_type_ids(), Some(vec![0, 1]));
+        assert_eq!(Schema::new(ArrowType::Int8, "x").union_type_ids(), None);
+    }
+
+    #[cfg(feature = "nested")]
+    #[test]
+    fn unions_need_a_type_id_per_field() {
+        let fields = vec![Schema::new(ArrowType::Int32, "i")];
+        let mut schema = Schema::union("u", UnionMode::Sparse, fields);
+        let mut exported = export::export_schema(&schema);
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert_eq!(imported, Ok(schema.clone()));
+
+        schema.format = "+us:0,1".to_string();
+        let mut exported = export::export_schema(&schema);
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert!(matches!(imported, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn fixed_size_binary_formats_have_their_width() {
+        assert_eq!(
+            ArrowType::from_format("w:16"),
+            Some(ArrowType::FixedSizeBinary)
+        );
+        assert_eq!(ArrowType::from_format("w:-1"), None);
+        assert_eq!(ArrowType::from_format("w:"), None);
+        let schema = Schema::fixed_size_binary("x", 4);
+        assert_eq!(schema.format, "w:4");
+        assert_eq!(schema.fixed_size_binary_width(), Some(4));
+        assert!(!schema.is_uuid());
+        assert_eq!(
+            Schema::new(ArrowType::Int8, "x").fixed_size_binary_width(),
+            None
+        );
+    }
+
+    #[test]
+    fn uuids_must_have_16_bytes() {
+        let uuid = Schema::uuid("id");
+        assert!(uuid.is_uuid());
+        assert_eq!(uuid.extension_name(), Some(UUID_EXTENSION));
+        let mut exported = export::export_schema(&uuid);
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert_eq!(imported, Ok(uuid));
+
+        let narrow =
+            Schema::fixed_size_binary("id", 8).with_metadata(EXTENSION_NAME_KEY, UUID_EXTENSION);
+        let mut exported = export::export_schema(&narrow);
+        let imported = unsafe { Schema::from_ffi(&exported) };
+        unsafe { exported.release.unwrap()(&mut exported) };
+        assert!(matches!(imported, Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,594 @@
+//! Self-describing copies of arrays, as one contiguous run of bytes.
+//!
+//! Shared memory segments store arrays as a header, the description of the
+//! schema and the array, and the buffers, every one of them aligned to 64
+//! bytes:
+//!
+//! ```text
+//! header: "ARROWUDF", version u32, byte order mark u32,
+//!         description length u64
+//! node:   format, name, metadata, flags i64, length u64, null_count i64,
+//!         offset u64, n_buffers u32, n_children u32,
+//!         buffer offset u64 and length u64 for every buffer, children
+//! ```
+//!
+//! Integers are in the byte order of the machine, and the byte order mark
+//! `BYTE_ORDER_MARK` tells segments written with the other order apart.
+//! Strings have a u32 length prefix, metadata is the u32 number of pairs
+//! followed by their keys and values, and buffer offsets are relative to the
+//! first buffer, `u64::MAX` for missing buffers. Segments are validated
+//! before they are imported: buffers must be in the segment and as long as
+//! the lengths and offsets of the arrays need, and the offsets of Binary and
+//! Utf8 arrays in their data, so a misbehaving producer can't make the
+//! consumer read out of bounds.
+
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::ffi::ArrowCDataInterfaceArray;
+use crate::schema::{ArrowType, Metadata, Schema, SchemaFlags};
+use crate::types::with_native_type;
+
+const MAGIC: &[u8; 8] = b"ARROWUDF";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 24;
+/// Read as `0x04030201` by a machine with the other byte order.
+pub(crate) const BYTE_ORDER_MARK: u32 = 0x0102_0304;
+pub(crate) const ALIGNMENT: usize = 64;
+/// Offset written for missing buffers.
+const NO_BUFFER: u64 = u64::MAX;
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidArgument(format!("invalid segment: {message}"))
+}
+
+fn align(len: usize) -> usize {
+    len.next_multiple_of(ALIGNMENT)
+}
+
+/// How the buffers of the arrays of a type are laid out.
+#[derive(Clone, Copy)]
+enum Layout {
+    /// Validity and values of the given bits.
+    Fixed(usize),
+    /// Validity, i32 offsets and data.
+    Binary,
+    /// Validity, the values being in the children.
+    Struct,
+    /// No buffers, the run ends and the values being in the children.
+    RunEndEncoded,
+}
+
+impl Layout {
+    fn of(data_type: ArrowType) -> Result<Layout> {
+        Ok(match data_type {
+            ArrowType::Boolean => Layout::Fixed(1),
+            ArrowType::Binary | ArrowType::Utf8 => Layout::Binary,
+            ArrowType::Decimal128 => Layout::Fixed(128),
+            ArrowType::Struct => Layout::Struct,
+            ArrowType::RunEndEncoded => Layout::RunEndEncoded,
+            data_type => with_native_type!(data_type.physical_type(), T => {
+                Layout::Fixed(8 * mem::size_of::<T>())
+            }, _ => return Err(Error::UnsupportedType(format!(
+                "{data_type:?} arrays in segments"
+            )))),
+        })
+    }
+
+    fn n_buffers(&self) -> usize {
+        match self {
+            Layout::Fixed(_) => 2,
+            Layout::Binary => 3,
+            Layout::Struct => 1,
+            Layout::RunEndEncoded => 0,
+        }
+    }
+
+    /// Bytes of the buffer `i` of an array with `end` values from the start
+    /// of its buffers, its offset plus its length. The length of the data of
+    /// Binary arrays is their last offset instead.
+    fn buffer_len(&self, i: usize, end: usize) -> Option<usize> {
+        match (self, i) {
+            (_, 0) => Some(end.div_ceil(8)),
+            (Layout::Fixed(bits), 1) => end.checked_mul(*bits).map(|bits| bits.div_ceil(8)),
+            (Layout::Binary, 1) => end.checked_add(1)?.checked_mul(4),
+            _ => None,
+        }
+    }
+}
+
+/// Description of an array, and the buffers to copy after it.
+#[derive(Default)]
+pub(crate) struct Writer {
+    description: Vec<u8>,
+    buffers: Vec<(*const u8, usize, usize)>,
+    buffers_len: usize,
+}
+
+impl Writer {
+    /// Writer of the segment of `array`, which must outlive it.
+    pub(crate) fn new(array: &ArrowArray) -> Result<Writer> {
+        let mut writer = Writer::default();
+        writer.write_node(array)?;
+        Ok(writer)
+    }
+
+    fn start(&self) -> usize {
+        align(HEADER_LEN + self.description.len())
+    }
+
+    /// Bytes of the segment.
+    pub(crate) fn len(&self) -> usize {
+        self.start() + self.buffers_len
+    }
+
+    /// Copy the segment into `dest`, whose padding must already be zeroed.
+    ///
+    /// # Safety
+    ///
+    /// `dest` must be valid for writes of `self.len()` bytes.
+    pub(crate) unsafe fn copy_to(&self, dest: *mut u8) {
+        let mut header = [0; HEADER_LEN];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_ne_bytes());
+        header[12..16].copy_from_slice(&BYTE_ORDER_MARK.to_ne_bytes());
+        header[16..].copy_from_slice(&(self.description.len() as u64).to_ne_bytes());
+        ptr::copy_nonoverlapping(header.as_ptr(), dest, HEADER_LEN);
+        ptr::copy_nonoverlapping(
+            self.description.as_ptr(),
+            dest.add(HEADER_LEN),
+            self.description.len(),
+        );
+        let start = self.start();
+        for (data, offset, len) in &self.buffers {
+            ptr::copy_nonoverlapping(*data, dest.add(start + offset), *len);
+        }
+    }
+
+    /// The segment, as an owned copy.
+    #[cfg(test)]
+    pub(crate) fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.len()];
+        unsafe { self.copy_to(bytes.as_mut_ptr()) };
+        bytes
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.description.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.description.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_u32(value.len() as u32);
+        self.description.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_buffer(&mut self, data: *const u8, len: usize) {
+        if data.is_null() {
+            self.write_u64(NO_BUFFER);
+            self.write_u64(0);
+            return;
+        }
+        self.write_u64(self.buffers_len as u64);
+        self.write_u64(len as u64);
+        self.buffers.push((data, self.buffers_len, len));
+        self.buffers_len = align(self.buffers_len + len);
+    }
+
+    fn write_node(&mut self, array: &ArrowArray) -> Result<()> {
+        let schema = array.schema();
+        let ffi = array.ffi();
+        let layout = Layout::of(schema.data_type)?;
+        if ffi.n_buffers != layout.n_buffers() as i64
+            || ffi.n_children != schema.children.len() as i64
+        {
+            return Err(Error::InvalidArgument(format!(
+                "{:?} array with {} buffers and {} children, expected {} buffers and {} children",
+                schema.data_type,
+                ffi.n_buffers,
+                ffi.n_children,
+                layout.n_buffers(),
+                schema.children.len()
+            )));
+        }
+        self.write_str(&schema.format);
+        self.write_str(&schema.name);
+        self.write_u32(schema.metadata.0.len() as u32);
+        for (key, value) in &schema.metadata.0 {
+            self.write_str(key);
+            self.write_str(value);
+        }
+        self.write_u64(schema.flags.bits() as u64);
+        self.write_u64(ffi.length as u64);
+        self.write_u64(ffi.null_count as u64);
+        self.write_u64(ffi.offset as u64);
+        self.write_u32(ffi.n_buffers as u32);
+        self.write_u32(ffi.n_children as u32);
+        let end = array.offset() + array.len();
+        for i in 0..layout.n_buffers() {
+            let data = unsafe { ffi.buffer(i) };
+            let len = match layout.buffer_len(i, end) {
+                Some(len) => len,
+                None => array.binary_offsets()[array.len()] as usize,
+            };
+            self.write_buffer(data, len);
+        }
+        for i in 0..schema.children.len() {
+            self.write_node(&array.child(i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reader of the description of a segment, failing on truncated data.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.bytes.len() < n {
+            return Err(invalid("truncated description"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_ne_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_str(&mut self) -> Result<String> {
+        let len = self.read_u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string that isn't UTF-8"))
+    }
+}
+
+/// An array read from the description of a segment, with the offsets of
+/// its buffers in the mapping.
+struct Node {
+    schema: Schema,
+    length: usize,
+    null_count: i64,
+    offset: usize,
+    buffers: Vec<Option<usize>>,
+    children: Vec<Node>,
+}
+
+fn nested_schema(schema: Schema, children: &[Node]) -> Schema {
+    let children = children.iter().map(|child| child.schema.clone()).collect();
+    schema.with_children(children)
+}
+
+/// Read and validate a node of the description. The buffers of the segment
+/// are `buffers`, starting at `start` in the mapping.
+fn read_node(reader: &mut Reader, buffers: &[u8], start: usize, depth: usize) -> Result<Node> {
+    if depth > 64 {
+        return Err(invalid("arrays nested too deeply"));
+    }
+    let format = reader.read_str()?;
+    let name = reader.read_str()?;
+    if format.contains('\0') || name.contains('\0') {
+        return Err(invalid("format or name with a nul character"));
+    }
+    let data_type = ArrowType::from_format(&format)
+        .ok_or_else(|| Error::UnsupportedType(format!("Arrow format {format:?}")))?;
+    let n_pairs = reader.read_u32()?;
+    let pairs = (0..n_pairs)
+        .map(|_| Ok((reader.read_str()?, reader.read_str()?)))
+        .collect::<Result<_>>()?;
+    let flags = SchemaFlags::from_ffi(reader.read_u64()? as i64, &format, false)?;
+    let length = reader.read_u64()?;
+    let null_count = reader.read_u64()? as i64;
+    let offset = reader.read_u64()?;
+    let n_buffers = reader.read_u32()? as usize;
+    let n_children = reader.read_u32()? as usize;
+
+    let layout = Layout::of(data_type)?;
+    let (Ok(length), Ok(offset)) = (usize::try_from(length), usize::try_from(offset)) else {
+        return Err(invalid("array too long"));
+    };
+    let Some(end) = offset
+        .checked_add(length)
+        .filter(|end| *end <= i64::MAX as usize)
+    else {
+        return Err(invalid("array too long"));
+    };
+    if n_buffers != layout.n_buffers() {
+        return Err(invalid(&format!(
+            "{data_type:?} array with {n_buffers} buffers"
+        )));
+    }
+    if !(-1..=length as i64).contains(&null_count) {
+        return Err(invalid(&format!(
+            "null count {null_count} of an array of {length}"
+        )));
+    }
+    let mut ranges = Vec::with_capacity(n_buffers);
+    for _ in 0..n_buffers {
+        let (buffer, len) = (reader.read_u64()?, reader.read_u64()?);
+        if buffer == NO_BUFFER {
+            ranges.push(None);
+            continue;
+        }
+        let in_bounds = buffer
+            .checked_add(len)
+            .is_some_and(|buffer_end| buffer_end <= buffers.len() as u64);
+        if !in_bounds || !(buffer as usize).is_multiple_of(ALIGNMENT) {
+            return Err(invalid("buffer out of the segment"));
+        }
+        ranges.push(Some((buffer as usize, len as usize)));
+    }
+    for (i, range) in ranges.iter().enumerate() {
+        let required = layout.buffer_len(i, end);
+        match (range, required) {
+            (Some((_, len)), Some(required)) if *len < required => {
+                return Err(invalid(&format!(
+                    "buffer {i} of {len} bytes for {end} {data_type:?} values"
+                )));
+            }
+            // Only the validity can be missing in arrays with values.
+            (None, _) if i > 0 && end > 0 => {
+                return Err(invalid(&format!("missing buffer {i}")));
+            }
+            _ => {}
+        }
+    }
+    if let Layout::Binary = layout {
+        if let (Some((offsets, _)), Some((_, data_len))) = (ranges[1], ranges[2]) {
+            let offsets: &[i32] = unsafe {
+                std::slice::from_raw_parts(buffers.as_ptr().add(offsets) as *const i32, end + 1)
+            };
+            let in_bounds = offsets[offset..]
+                .windows(2)
+                .all(|pair| 0 <= pair[0] && pair[0] <= pair[1])
+                && (offsets[end] as usize) <= data_len;
+            if !in_bounds {
+                return Err(invalid("binary offsets out of the data"));
+            }
+        }
+    }
+
+    let children = (0..n_children)
+        .map(|_| read_node(reader, buffers, start, depth + 1))
+        .collect::<Result<Vec<_>>>()?;
+    match layout {
+        Layout::Struct => {
+            if let Some(child) = children.iter().find(|child| child.length < end) {
+                return Err(invalid(&format!(
+                    "struct field of {} rows in a struct of {end}",
+                    child.length
+                )));
+            }
+        }
+        Layout::RunEndEncoded => {
+            let valid = children.len() == 2
+                && matches!(
+                    children[0].schema.data_type,
+                    ArrowType::Int16 | ArrowType::Int32 | ArrowType::Int64
+                )
+                && children[1].length >= children[0].length;
+            if !valid {
+                return Err(invalid("run-end encoded array without run ends and values"));
+            }
+        }
+        _ if !children.is_empty() => {
+            return Err(invalid(&format!("{data_type:?} array with children")));
+        }
+        _ => {}
+    }
+    let mut schema = Schema::new(data_type, &name);
+    schema.format = format;
+    schema.metadata = Metadata(pairs);
+    schema.flags = flags;
+    Ok(Node {
+        schema: nested_schema(schema, &children),
+        length,
+        null_count,
+        offset,
+        buffers: ranges
+            .into_iter()
+            .map(|range| range.map(|(buffer, _)| start + buffer))
+            .collect(),
+        children,
+    })
+}
+
+struct PrivateSegmentArray {
+    // Keeps the bytes of the segment alive. It's shared by the children.
+    _owner: Arc<dyn Send + Sync>,
+    buffer_ptrs: Vec<*const c_void>,
+    children: Vec<*mut ArrowCDataInterfaceArray>,
+}
+
+fn export_node(
+    node: &Node,
+    base: *const u8,
+    owner: &Arc<dyn Send + Sync>,
+) -> ArrowCDataInterfaceArray {
+    let buffer_ptrs = node
+        .buffers
+        .iter()
+        .map(|buffer| match buffer {
+            Some(offset) => unsafe { base.add(*offset) as *const c_void },
+            None => ptr::null(),
+        })
+        .collect();
+    let children = node
+        .children
+        .iter()
+        .map(|child| Box::into_raw(Box::new(export_node(child, base, owner))))
+        .collect();
+    let mut private_data = Box::new(PrivateSegmentArray {
+        _owner: owner.clone(),
+        buffer_ptrs,
+        children,
+    });
+    ArrowCDataInterfaceArray {
+        length: node.length as i64,
+        null_count: node.null_count,
+        offset: node.offset as i64,
+        n_buffers: private_data.buffer_ptrs.len() as i64,
+        n_children: private_data.children.len() as i64,
+        buffers: private_data.buffer_ptrs.as_mut_ptr(),
+        children: private_data.children.as_mut_ptr(),
+        dictionary: ptr::null_mut(),
+        release: Some(release_segment_array),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    }
+}
+
+unsafe extern "C" fn release_segment_array(array: *mut ArrowCDataInterfaceArray) {
+    if array.is_null() || (*array).release.is_none() {
+        return;
+    }
+    let private_data = Box::from_raw((*array).private_data as *mut PrivateSegmentArray);
+    for child in private_data.children.iter().copied() {
+        if let Some(release) = (*child).release {
+            release(child);
+        }
+        drop(Box::from_raw(child));
+    }
+    drop(private_data);
+    (*array).release = None;
+}
+
+/// The schema of the array of the segment `bytes`, and a C Data Interface
+/// array reading it in place. `owner` keeps `bytes` alive until the array
+/// and all its children are released, and `bytes` must start at an address
+/// aligned to `ALIGNMENT`.
+pub(crate) fn import(
+    bytes: &[u8],
+    owner: Arc<dyn Send + Sync>,
+) -> Result<(Schema, ArrowCDataInterfaceArray)> {
+    if bytes.len() < HEADER_LEN {
+        return Err(invalid("truncated header"));
+    }
+    if &bytes[..8] != MAGIC {
+        return Err(invalid("not an array segment"));
+    }
+    match u32::from_ne_bytes(bytes[12..16].try_into().unwrap()) {
+        BYTE_ORDER_MARK => {}
+        mark if mark == BYTE_ORDER_MARK.swap_bytes() => {
+            return Err(Error::UnsupportedEndianness(
+                "the segment was written by a machine with the other byte order".to_string(),
+            ));
+        }
+        _ => return Err(invalid("invalid byte order mark")),
+    }
+    let version = u32::from_ne_bytes(bytes[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported version {version}")));
+    }
+    let description_len = u64::from_ne_bytes(bytes[16..24].try_into().unwrap());
+    let start = usize::try_from(description_len)
+        .ok()
+        .and_then(|len| HEADER_LEN.checked_add(len))
+        .map(align)
+        .filter(|start| *start <= bytes.len())
+        .ok_or_else(|| invalid("truncated description"))?;
+    let mut reader = Reader {
+        bytes: &bytes[HEADER_LEN..HEADER_LEN + description_len as usize],
+    };
+    let node = read_node(&mut reader, &bytes[start..], start, 0)?;
+    if !reader.bytes.is_empty() {
+        return Err(invalid("trailing bytes after the description"));
+    }
+    let array = export_node(&node, bytes.as_ptr(), &owner);
+    Ok((node.schema, array))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::export::ArrayData;
+    use crate::testing::{nullable_data, Exported};
+
+    /// The segment of `input`.
+    fn write(input: &Exported) -> Vec<u8> {
+        input.with_array(|array| Writer::new(array).unwrap().to_vec())
+    }
+
+    /// The array of `bytes`, copied into an aligned buffer.
+    fn read(bytes: &[u8]) -> Result<Exported> {
+        let buffer = Arc::new(Buffer::from_slice(bytes));
+        let (schema, array) = import(buffer.as_slice(), buffer.clone())?;
+        Ok(Exported {
+            schema: crate::export::export_schema(&schema),
+            array,
+        })
+    }
+
+    #[test]
+    fn segments_are_aligned_copies_of_arrays() {
+        let mut input = Exported::nullable(&[Some(1_i32), None, Some(3)]);
+        (input.array.offset, input.array.length) = (1, 2);
+        let bytes = write(&input);
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(bytes.len() % ALIGNMENT, 0);
+        drop(input);
+        let out = read(&bytes).unwrap();
+        assert_eq!(out.nullable_values::<i32>(), [None, Some(3)]);
+
+        let schema = Schema::new(ArrowType::Struct, "s").with_children(vec![
+            Schema::new(ArrowType::Utf8, "a"),
+            Schema::new(ArrowType::Int64, "b"),
+        ]);
+        let mut strings = crate::binary::Utf8Builder::with_capacity(2);
+        strings.push(Some("segment"));
+        strings.push(None);
+        let children = vec![strings.finish(), nullable_data(&[Some(1_i64), Some(2)])];
+        let input = Exported::new(&schema, ArrayData::struct_array(children, 2));
+        let out = read(&write(&input)).unwrap();
+        out.with_array(|array| {
+            assert_eq!(array.schema(), &schema);
+            assert_eq!(array.child(0).utf8_value(0), Ok("segment"));
+            assert!(!array.child(0).is_valid(1));
+        });
+        assert_eq!(out.child_values::<i64>(1), [Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn invalid_segments_fail() {
+        let bytes = write(&Exported::utf8(&[Some("a"), Some("bc")]));
+        let invalid = |bytes: &[u8], message: &str| match read(bytes) {
+            Err(Error::InvalidArgument(error)) => assert!(error.contains(message), "{error}"),
+            _ => panic!("expected {message:?}"),
+        };
+        invalid(&bytes[..HEADER_LEN - 1], "truncated header");
+        invalid(&bytes[..HEADER_LEN], "truncated description");
+        let mut version = bytes.clone();
+        version[8..12].copy_from_slice(&2_u32.to_ne_bytes());
+        invalid(&version, "unsupported version 2");
+        let mut swapped = bytes.clone();
+        swapped[12..16].copy_from_slice(&BYTE_ORDER_MARK.swap_bytes().to_ne_bytes());
+        assert!(matches!(
+            read(&swapped),
+            Err(Error::UnsupportedEndianness(_))
+        ));
+        // The last offset of the strings, past the end of their data.
+        let offsets: Vec<u8> = [0_i32, 1, 3].iter().flat_map(|o| o.to_ne_bytes()).collect();
+        let last = (0..bytes.len() - 12)
+            .step_by(ALIGNMENT)
+            .find(|i| bytes[*i..*i + 12] == offsets)
+            .unwrap()
+            + 8;
+        let mut offsets = bytes.clone();
+        offsets[last..last + 4].copy_from_slice(&100_i32.to_ne_bytes());
+        invalid(&offsets, "");
+    }
+}
@@ -0,0 +1,210 @@
+//! Shift of the rows of an array, for the `LAG` and `LEAD` window functions.
+//!
+//! Row `i` of the result is row `i - n` of the input: positive shifts move
+//! the values down, like `LAG(n)`, and negative shifts move them up, like
+//! `LEAD(-n)`. The `n` rows at the boundary, with no row of the input to
+//! take their value from, are null, or take a fill value, converted to the
+//! type of the array, which must be able to represent it. Nulls of the
+//! input stay null in their new position.
+
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::bitmap::BitmapBuilder;
+use crate::dispatch::{self, Kernel};
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::types::Numeric;
+use crate::udf;
+
+/// Copy of `array` with its rows shifted by `n`, and the rows at the
+/// boundary set to `fill`, or null without it. Fails with
+/// `Error::InvalidArgument` if the type of `array` can't represent `fill`,
+/// like 2.5 or 1e300 for Int64 arrays.
+pub fn shift(
+    array: &ArrowArray,
+    n: i64,
+    fill: Option<f64>,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let data = dispatch::call::<Shift>(array, &(n, fill), options)?;
+    Ok((Schema::new(array.data_type(), &array.schema().name), data))
+}
+
+/// The row of the input moved to row `i` of the result, if any.
+fn source(i: usize, n: i64, len: usize) -> Option<usize> {
+    let source = i as i128 - n as i128;
+    (0..len as i128)
+        .contains(&source)
+        .then_some(source as usize)
+}
+
+struct Shift;
+
+impl Kernel for Shift {
+    type Args = (i64, Option<f64>);
+    type Output = ArrayData;
+
+    fn name(_: &(i64, Option<f64>)) -> String {
+        "shift".to_string()
+    }
+
+    fn call<T: Numeric>(
+        array: &ArrowArray,
+        &(n, fill): &(i64, Option<f64>),
+        options: &ArrowUdfExecOptions,
+    ) -> Result<ArrayData> {
+        udf::check_input::<T>(array, options)?;
+        let input = array.values::<T>();
+        let len = input.len();
+        let value = match fill {
+            Some(fill) => T::checked_from_f64(fill).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "the fill value {fill} can't be represented as {:?}",
+                    array.data_type()
+                ))
+            })?,
+            None => T::default(),
+        };
+        let mut values = options.output_values(std::mem::size_of_val(input))?;
+        exec::map(values.typed_data_mut::<T>(), options, |rows, out| {
+            for (out, i) in out.iter_mut().zip(rows) {
+                *out = source(i, n, len).map_or(value, |source| input[source]);
+            }
+            Ok(())
+        })?;
+        let data = ArrayData::primitive(values, len);
+        let validity = array.validity().filter(|_| array.null_count() > 0);
+        if validity.is_none() && fill.is_some() {
+            return Ok(data);
+        }
+        let mut valid = BitmapBuilder::with_capacity(len);
+        let mut null_count = 0;
+        for i in 0..len {
+            let is_valid = match source(i, n, len) {
+                Some(source) => validity.is_none_or(|validity| validity.is_set(source)),
+                None => fill.is_some(),
+            };
+            null_count += usize::from(!is_valid);
+            valid.push(is_valid);
+        }
+        Ok(data.with_validity(Some(valid.finish()), null_count))
+    }
+}
+
+/// Copy of a numeric array with row `i` of the result taken from row
+/// `i - n` of the input. The `n` rows without a row to take the value from
+/// are set to `*fill`, converted to the type of the array, or are null if
+/// `fill` is null. Fill values the type can't represent, like 2.5 for an
+/// Int64 array, fail with the `InvalidArgument` status.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `fill` must be null or valid, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_shift(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    n: i64,
+    fill: *const f64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = shift(&array, n, fill.as_ref().copied(), &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::testing::Exported;
+
+    fn run(
+        input: &Exported,
+        n: i64,
+        fill: Option<f64>,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let fill = fill.as_ref().map_or(ptr::null(), |fill| fill as *const f64);
+        let status = unsafe {
+            arrow_udf_shift(
+                &input.schema,
+                &input.array,
+                n,
+                fill,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn lags_and_leads_move_the_values_and_their_nulls() {
+        let input = Exported::nullable(&[Some(1_i64), None, Some(3), Some(4)]);
+        let lag = run(&input, 1, None).unwrap();
+        assert_eq!(lag.nullable_values::<i64>(), [None, Some(1), None, Some(3)]);
+        let lead = run(&input, -2, Some(-1.0)).unwrap();
+        assert_eq!(
+            lead.nullable_values::<i64>(),
+            [Some(3), Some(4), Some(-1), Some(-1)]
+        );
+        let all = run(&input, i64::MIN, None).unwrap();
+        assert_eq!(all.nullable_values::<i64>(), [None; 4]);
+        let same = run(&input, 0, Some(9.0)).unwrap();
+        assert_eq!(
+            same.nullable_values::<i64>(),
+            [Some(1), None, Some(3), Some(4)]
+        );
+        let floats = Exported::primitive(&[0.5_f32, 1.5]);
+        let filled = run(&floats, 1, Some(0.25)).unwrap();
+        assert_eq!(filled.values::<f32>(), [0.25, 0.5]);
+        assert_eq!(filled.with_array(|array| array.null_count()), 0);
+    }
+
+    #[test]
+    fn fill_values_the_type_cannot_represent_fail() {
+        let int64 = Exported::primitive(&[1_i64, 2]);
+        for fill in [2.5, 1e300, f64::NAN, 2f64.powi(63)] {
+            assert!(matches!(
+                run(&int64, 1, Some(fill)),
+                Err(ArrowUdfStatus::InvalidArgument)
+            ));
+        }
+        let uint8 = Exported::primitive(&[1_u8]);
+        assert!(matches!(
+            run(&uint8, 1, Some(-1.0)),
+            Err(ArrowUdfStatus::InvalidArgument)
+        ));
+        assert!(run(&uint8, 1, Some(255.0)).is_ok());
+        let float32 = Exported::primitive(&[1.0_f32]);
+        assert!(matches!(
+            run(&float32, 1, Some(1e300)),
+            Err(ArrowUdfStatus::InvalidArgument)
+        ));
+        let strings = Exported::utf8(&[Some("a")]);
+        assert!(matches!(
+            run(&strings, 1, None),
+            Err(ArrowUdfStatus::UnsupportedType)
+        ));
+    }
+}
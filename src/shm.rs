@@ -0,0 +1,342 @@
+//! Transport of arrays between processes through shared memory.
+//!
+//! Hosts running UDFs in another process, to isolate their crashes or
+//! untrusted code, can't pass the pointers of the C Data Interface to it.
+//! `arrow_udf_shm_export` copies an array once into a memfd segment, and
+//! returns its file descriptor, which hosts send to the other process over
+//! a Unix socket with `SCM_RIGHTS`, or let it inherit. `arrow_udf_shm_import`
+//! maps the segment and returns a C Data Interface array with its buffers
+//! pointing into the mapping, so the consumer reads the values in place.
+//!
+//! Segments are sealed against writes and resizes when exported, and only
+//! sealed segments are imported, so the producer can't change or truncate
+//! the buffers while the consumer reads them. The layout of the segments is
+//! described in [`crate::segment`]. Its integers are in the byte order of
+//! the machine, since segments don't leave it, and imported segments are
+//! validated before they are used.
+
+use std::ffi::{c_int, c_void};
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::Schema;
+use crate::segment::{self, Writer};
+
+fn os_error() -> Error {
+    Error::Io(io::Error::last_os_error().to_string())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidArgument(format!("invalid shared memory segment: {message}"))
+}
+
+/// Mapping of a segment, unmapped when dropped.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is only read after it's created.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, prot: c_int) -> Result<Mapping> {
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, prot, libc::MAP_SHARED, fd, 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut c_void, self.len) };
+    }
+}
+
+/// Copy `array` into a new sealed memfd segment.
+pub fn export_segment(array: &ArrowArray) -> Result<OwnedFd> {
+    let writer = Writer::new(array)?;
+    let size = writer.len();
+
+    let name = c"arrow_udf_shm";
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::ftruncate(fd.as_raw_fd(), size as libc::off_t) } < 0 {
+        return Err(os_error());
+    }
+    {
+        let mapping = Mapping::new(fd.as_raw_fd(), size, libc::PROT_READ | libc::PROT_WRITE)?;
+        unsafe { writer.copy_to(mapping.ptr) };
+    }
+    // Writes can only be sealed once the writable mapping is unmapped.
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(os_error());
+    }
+    Ok(fd)
+}
+
+/// Map the sealed segment `fd`, returning the schema of its array and a C
+/// Data Interface array reading it in place. The segment stays mapped until
+/// the array and all its children are released.
+pub fn import_segment(fd: RawFd) -> Result<(Schema, ArrowCDataInterfaceArray)> {
+    let seals = unsafe { libc::fcntl(fd, libc::F_GET_SEALS) };
+    if seals < 0 {
+        return match io::Error::last_os_error().raw_os_error() {
+            Some(libc::EINVAL) => Err(invalid("not a memfd")),
+            _ => Err(os_error()),
+        };
+    }
+    if seals & (libc::F_SEAL_SHRINK | libc::F_SEAL_WRITE)
+        != libc::F_SEAL_SHRINK | libc::F_SEAL_WRITE
+    {
+        return Err(invalid("not sealed against writes and shrinking"));
+    }
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(os_error());
+    }
+    let size = stat.st_size as usize;
+    if size == 0 {
+        return Err(invalid("truncated header"));
+    }
+    let mapping = Arc::new(Mapping::new(fd, size, libc::PROT_READ)?);
+    segment::import(mapping.bytes(), mapping.clone())
+}
+
+/// Copy the array in `schema` and `array` into a new shared memory segment,
+/// and write its file descriptor into `out_fd`. The caller owns the
+/// descriptor, and closes it once it's sent to the consumer. The segment is
+/// sealed, so neither process can modify it.
+///
+/// # Safety
+///
+/// `schema` and `array` must be a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_fd` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_shm_export(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_fd: *mut c_int,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let fd = export_segment(&array)?;
+        out_fd.write(fd.into_raw_fd());
+        Ok(())
+    })
+}
+
+/// Import the array of the shared memory segment `fd`, written by
+/// `arrow_udf_shm_export` in this process or another one, into `out_schema`
+/// and `out_array`. The buffers of the array point into the segment, which
+/// is mapped until the array is released. The descriptor isn't taken, and
+/// can be closed after the call.
+///
+/// Fails with `InvalidArgument` if the segment isn't sealed, or if its
+/// buffers don't match the description of the array.
+///
+/// # Safety
+///
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_shm_import(
+    fd: c_int,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        ArrowUdfExecOptions::from_ffi(options)?;
+        let (schema, array) = import_segment(fd)?;
+        out_schema.write(export::export_schema(&schema));
+        out_array.write(array);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::export::ArrayData;
+    use crate::schema::{ArrowType, Metadata};
+    use crate::segment::BYTE_ORDER_MARK;
+    use crate::testing::{nullable_data, Exported};
+
+    fn export(input: &Exported) -> std::result::Result<OwnedFd, ArrowUdfStatus> {
+        let mut fd = -1;
+        let status =
+            unsafe { arrow_udf_shm_export(&input.schema, &input.array, ptr::null(), &mut fd) };
+        match status {
+            ArrowUdfStatus::Ok => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+            status => Err(status),
+        }
+    }
+
+    fn import(fd: RawFd) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status =
+            unsafe { arrow_udf_shm_import(fd, ptr::null(), &mut out.schema, &mut out.array) };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    /// Bytes of the segment `fd`.
+    fn contents(fd: &OwnedFd) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        File::from(fd.try_clone().unwrap())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    /// A memfd with `bytes`, sealed if `seal`.
+    fn segment(bytes: &[u8], seal: bool) -> OwnedFd {
+        let fd = unsafe { libc::memfd_create(c"test".as_ptr(), libc::MFD_ALLOW_SEALING) };
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        File::from(fd.try_clone().unwrap())
+            .write_all(bytes)
+            .unwrap();
+        if seal {
+            let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+            assert_eq!(
+                unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) },
+                0
+            );
+        }
+        fd
+    }
+
+    #[test]
+    fn arrays_round_trip_through_a_segment() {
+        let mut input = Exported::nullable(&[Some(1_i64), None, Some(3), Some(4)]);
+        (input.array.offset, input.array.length) = (1, 3);
+        let fd = export(&input).unwrap();
+        drop(input);
+        let out = import(fd.as_raw_fd()).unwrap();
+        drop(fd);
+        assert_eq!(out.nullable_values::<i64>(), [None, Some(3), Some(4)]);
+        out.with_array(|array| {
+            assert_eq!(array.schema().name, "x");
+            assert_eq!(array.null_count(), 1);
+        });
+
+        let input = Exported::utf8(&[Some("a"), None, Some("shared")]);
+        let out = import(export(&input).unwrap().as_raw_fd()).unwrap();
+        assert_eq!(out.strings(), input.strings());
+
+        let input = Exported::boolean(&[true, false, true]);
+        let out = import(export(&input).unwrap().as_raw_fd()).unwrap();
+        assert_eq!(out.booleans(), [Some(true), Some(false), Some(true)]);
+    }
+
+    #[test]
+    fn structs_keep_their_fields_and_metadata() {
+        let mut schema = Schema::new(ArrowType::Struct, "s").with_children(vec![
+            Schema::new(ArrowType::Int32, "a"),
+            Schema::new(ArrowType::Float64, "b"),
+        ]);
+        schema.metadata = Metadata(vec![("key".to_string(), "value".to_string())]);
+        let children = vec![
+            nullable_data(&[Some(1_i32), None]),
+            ArrayData::primitive(Buffer::from_slice(&[0.5_f64, 1.5]), 2),
+        ];
+        let input = Exported::new(&schema, ArrayData::struct_array(children, 2));
+        let out = import(export(&input).unwrap().as_raw_fd()).unwrap();
+        out.with_array(|array| assert_eq!(array.schema(), &schema));
+        assert_eq!(out.child_values::<i32>(0), [Some(1), None]);
+        assert_eq!(out.child_values::<f64>(1), [Some(0.5), Some(1.5)]);
+    }
+
+    #[test]
+    fn exported_segments_are_sealed() {
+        let fd = export(&Exported::primitive(&[1_i64])).unwrap();
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        assert_ne!(seals & libc::F_SEAL_WRITE, 0);
+        assert!(File::from(fd).write_all(b"changed").is_err());
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        let bytes = contents(&export(&Exported::primitive(&[1_i64, 2])).unwrap());
+        assert!(import(segment(&bytes, true).as_raw_fd()).is_ok());
+        // The same segment, but not sealed.
+        assert_eq!(
+            import(segment(&bytes, false).as_raw_fd()).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(
+            import(segment(&wrong_magic, true).as_raw_fd()).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        // The length of the array, after the header, the format "l", the
+        // name "x", no metadata and the flags, so its buffers are too short.
+        let mut too_long = bytes.clone();
+        too_long[46..54].copy_from_slice(&1000_u64.to_ne_bytes());
+        assert_eq!(
+            import(segment(&too_long, true).as_raw_fd()).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            import(segment(&bytes[..40], true).as_raw_fd()).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let file = File::open("/dev/null").unwrap();
+        assert_eq!(
+            import(file.as_raw_fd()).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn segments_in_the_other_byte_order_are_rejected() {
+        let bytes = contents(&export(&Exported::primitive(&[1_i64])).unwrap());
+        assert_eq!(bytes[12..16], BYTE_ORDER_MARK.to_ne_bytes());
+        let mut swapped = bytes.clone();
+        swapped[12..16].copy_from_slice(&BYTE_ORDER_MARK.swap_bytes().to_ne_bytes());
+        assert_eq!(
+            import(segment(&swapped, true).as_raw_fd()).err(),
+            Some(ArrowUdfStatus::UnsupportedEndianness)
+        );
+        let mut unknown = bytes;
+        unknown[12..16].copy_from_slice(&0_u32.to_ne_bytes());
+        assert_eq!(
+            import(segment(&unknown, true).as_raw_fd()).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+}
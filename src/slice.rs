@@ -0,0 +1,381 @@
+//! Slices and takes of arrays, sharing the buffers of their input.
+//!
+//! A slice of an array, or a take of a contiguous run of its rows, doesn't
+//! need new buffers: the output points to the buffers of the input, with
+//! another offset and length. `arrow_udf_slice` and `arrow_udf_take` move
+//! their input into a reference-counted holder, kept by the output and every
+//! one of its children, so the input is released by its producer only when
+//! the last of them is released. Takes of rows out of order can't be
+//! represented over the same buffers, so they are copied, and the input is
+//! released when the call returns.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::concat;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export;
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Schema};
+use crate::stream::ImportedArray;
+
+/// The rows `offset..offset + length` of `array`, sharing its buffers.
+pub fn slice(
+    schema: &Schema,
+    array: &Arc<ImportedArray>,
+    offset: usize,
+    length: usize,
+) -> Result<ArrowCDataInterfaceArray> {
+    let view = array.view(schema);
+    if offset
+        .checked_add(length)
+        .is_none_or(|end| end > view.len())
+    {
+        return Err(Error::InvalidArgument(format!(
+            "slice of {length} rows from {offset} out of bounds for an array of {}",
+            view.len()
+        )));
+    }
+    let owner: Arc<dyn Any + Send + Sync> = array.clone();
+    let mut out = unsafe { export::export_foreign(view.ffi(), &owner) };
+    out.offset += offset as i64;
+    out.length = length as i64;
+    // The nulls of the slice aren't known without counting them.
+    out.null_count = match view.null_count() {
+        0 => 0,
+        _ => -1,
+    };
+    Ok(out)
+}
+
+/// The rows of `array` at `indices`. A contiguous run of rows in order is a
+/// slice sharing the buffers of `array`, other indices are copied.
+pub fn take(
+    schema: &Schema,
+    array: &Arc<ImportedArray>,
+    indices: &[i64],
+) -> Result<ArrowCDataInterfaceArray> {
+    let view = array.view(schema);
+    if let Some(index) = indices
+        .iter()
+        .find(|index| !matches!(usize::try_from(**index), Ok(index) if index < view.len()))
+    {
+        return Err(Error::InvalidArgument(format!(
+            "index {index} out of bounds for an array of {}",
+            view.len()
+        )));
+    }
+    let first = indices.first().map_or(0, |first| *first as usize);
+    let contiguous = indices
+        .iter()
+        .enumerate()
+        .all(|(i, index)| *index as usize == first + i);
+    if contiguous {
+        return slice(schema, array, first, indices.len());
+    }
+    let indices: Vec<(usize, usize)> = indices.iter().map(|index| (0, *index as usize)).collect();
+    let data = concat::interleave(&[view], &indices)?;
+    Ok(export::export_array(data))
+}
+
+/// The `length` rows of `array` from `offset`, sharing its buffers.
+///
+/// The array is moved into the output, also when the call fails, and it's
+/// released when the output and all its children are released.
+///
+/// # Safety
+///
+/// `schema` and `array` must be a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_slice(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *mut ArrowCDataInterfaceArray,
+    offset: i64,
+    length: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let array = Arc::new(ImportedArray::move_from_ffi(array)?);
+        ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        array.import(&schema)?;
+        let (Ok(offset), Ok(length)) = (usize::try_from(offset), usize::try_from(length)) else {
+            return Err(Error::InvalidArgument(format!(
+                "the offset and the length of a slice must be zero or positive, got {offset} \
+                 and {length}"
+            )));
+        };
+        let out = slice(&schema, &array, offset, length)?;
+        out_schema.write(export::export_schema(&schema));
+        out_array.write(out);
+        Ok(())
+    })
+}
+
+/// The rows of `array` at the positions in `indices`, an Int64 array
+/// without nulls, like the output of `arrow_udf_sort_indices`. When the
+/// indices are a run of consecutive positions the output shares the buffers
+/// of the array, otherwise the rows are copied. Primitive, Boolean, Binary,
+/// Utf8, temporal and struct arrays are supported.
+///
+/// The array is moved into the call, also when it fails, and it's released
+/// when the output and all its children are released.
+///
+/// # Safety
+///
+/// `schema` and `array`, and `indices_schema` and `indices`, must be valid
+/// Arrow C Data Interface arrays, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_take(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *mut ArrowCDataInterfaceArray,
+    indices_schema: *const ArrowCDataInterfaceSchema,
+    indices: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let array = Arc::new(ImportedArray::move_from_ffi(array)?);
+        ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        array.import(&schema)?;
+        let indices_schema = Schema::from_ffi(&*indices_schema)?;
+        let indices = ArrowArray::import(&indices_schema, &*indices)?;
+        if indices.data_type() != ArrowType::Int64 {
+            return Err(Error::UnsupportedType(format!(
+                "expected Int64 indices, got {:?}",
+                indices.data_type()
+            )));
+        }
+        if indices.null_count() > 0 {
+            return Err(Error::InvalidArgument("null indices".to_string()));
+        }
+        let out = take(&schema, &array, indices.values::<i64>())?;
+        out_schema.write(export::export_schema(&schema));
+        out_array.write(out);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::export::ArrayData;
+    use crate::testing::{nullable_data, Exported};
+
+    /// An array exported by the host, flagging when it's released.
+    struct Input {
+        exported: Box<Exported>,
+        array: ArrowCDataInterfaceArray,
+        released: Arc<AtomicBool>,
+    }
+
+    impl Input {
+        fn new(exported: Exported) -> Input {
+            let mut exported = Box::new(exported);
+            let released = Arc::new(AtomicBool::new(false));
+            let array = ArrowCDataInterfaceArray {
+                release: Some(release_input),
+                private_data: Box::into_raw(Box::new((
+                    exported.array.release,
+                    ptr::addr_of_mut!(exported.array),
+                    released.clone(),
+                ))) as *mut _,
+                ..unsafe { ptr::read(&exported.array) }
+            };
+            Input {
+                exported,
+                array,
+                released,
+            }
+        }
+
+        fn released(&self) -> bool {
+            self.released.load(Ordering::SeqCst)
+        }
+    }
+
+    type Release = Option<unsafe extern "C" fn(*mut ArrowCDataInterfaceArray)>;
+
+    unsafe extern "C" fn release_input(array: *mut ArrowCDataInterfaceArray) {
+        let (release, original, released) = *Box::from_raw(
+            (*array).private_data as *mut (Release, *mut ArrowCDataInterfaceArray, Arc<AtomicBool>),
+        );
+        release.unwrap()(original);
+        released.store(true, Ordering::SeqCst);
+        (*array).release = None;
+    }
+
+    fn run_slice(
+        input: &mut Input,
+        offset: i64,
+        length: i64,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_slice(
+                &input.exported.schema,
+                &mut input.array,
+                offset,
+                length,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn run_take(
+        input: &mut Input,
+        indices: &Exported,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_take(
+                &input.exported.schema,
+                &mut input.array,
+                &indices.schema,
+                &indices.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn slices_share_the_buffers_of_the_input() {
+        let mut input = Input::new(Exported::nullable(&[Some(1_i32), None, Some(3), Some(4)]));
+        let values = unsafe { input.array.buffer(1) };
+        let out = run_slice(&mut input, 1, 2).unwrap();
+        assert!(input.array.release.is_none());
+        assert_eq!(unsafe { out.array.buffer(1) }, values);
+        assert_eq!(out.array.null_count, -1);
+        assert_eq!(out.nullable_values::<i32>(), [None, Some(3)]);
+        assert!(!input.released());
+        drop(out);
+        assert!(input.released());
+
+        let mut input = Input::new(Exported::primitive(&[1_i64, 2, 3]));
+        let out = run_slice(&mut input, 3, 0).unwrap();
+        assert_eq!((out.array.length, out.array.null_count), (0, 0));
+    }
+
+    #[test]
+    fn children_keep_the_input_until_they_are_released() {
+        let children = vec![
+            nullable_data(&[Some(1_i32), Some(2), Some(3)]),
+            nullable_data(&[Some(4_i32), None, Some(6)]),
+        ];
+        let schema = Schema::new(ArrowType::Struct, "s").with_children(vec![
+            Schema::new(ArrowType::Int32, "a"),
+            Schema::new(ArrowType::Int32, "b"),
+        ]);
+        let mut input = Input::new(Exported::new(&schema, ArrayData::struct_array(children, 3)));
+        let mut out = run_slice(&mut input, 1, 2).unwrap();
+        // The host moves the second field out, and releases the struct.
+        let mut field = unsafe {
+            let child = *out.array.children.add(1);
+            let field = ptr::read(child);
+            (*child).release = None;
+            field
+        };
+        unsafe { out.array.release.unwrap()(&mut out.array) };
+        assert!(!input.released());
+        let field_schema = Schema::new(ArrowType::Int32, "b");
+        let values = unsafe {
+            let array = ArrowArray::new(&field_schema, &field);
+            (1..3)
+                .map(|i| array.is_valid(i).then(|| array.values::<i32>()[i]))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values, [None, Some(6)]);
+        unsafe { field.release.unwrap()(&mut field) };
+        assert!(input.released());
+    }
+
+    #[test]
+    fn takes_share_contiguous_rows_and_copy_the_others() {
+        let mut input = Input::new(Exported::utf8(&[Some("a"), None, Some("c"), Some("d")]));
+        let data = unsafe { input.array.buffer(2) };
+        let out = run_take(&mut input, &Exported::primitive(&[1_i64, 2, 3])).unwrap();
+        assert_eq!(unsafe { out.array.buffer(2) }, data);
+        assert_eq!(
+            out.strings(),
+            [None, Some("c".to_string()), Some("d".to_string())]
+        );
+        assert!(!input.released());
+        drop(out);
+        assert!(input.released());
+
+        let mut input = Input::new(Exported::nullable(&[Some(1_i64), None, Some(3)]));
+        let out = run_take(&mut input, &Exported::primitive(&[2_i64, 0, 1, 2])).unwrap();
+        // The input is released by the call, since its rows were copied.
+        assert!(input.released());
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [Some(3), Some(1), None, Some(3)]
+        );
+    }
+
+    #[test]
+    fn invalid_slices_and_takes_fail_and_release_the_input() {
+        let values = || Exported::primitive(&[1_i64, 2, 3]);
+        for (offset, length) in [(2, 2), (-1, 1), (0, -1), (i64::MAX, 1)] {
+            let mut input = Input::new(values());
+            assert_eq!(
+                run_slice(&mut input, offset, length).err(),
+                Some(ArrowUdfStatus::InvalidArgument)
+            );
+            assert!(input.array.release.is_none());
+            assert!(input.released());
+        }
+        for (indices, status) in [
+            (
+                Exported::primitive(&[3_i64]),
+                ArrowUdfStatus::InvalidArgument,
+            ),
+            (
+                Exported::primitive(&[-1_i64]),
+                ArrowUdfStatus::InvalidArgument,
+            ),
+            (
+                Exported::nullable(&[Some(0_i64), None]),
+                ArrowUdfStatus::InvalidArgument,
+            ),
+            (
+                Exported::primitive(&[0_i32]),
+                ArrowUdfStatus::UnsupportedType,
+            ),
+        ] {
+            let mut input = Input::new(values());
+            assert_eq!(run_take(&mut input, &indices).err(), Some(status));
+            assert!(input.released());
+        }
+        let mut input = Input::new(values());
+        unsafe { release_input(&mut input.array) };
+        assert_eq!(
+            run_slice(&mut input, 0, 1).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,698 @@
+//! Sort indices: the permutation of the rows of an array that sorts it.
+//!
+//! The sort is stable, so rows with equal values keep their order. Null
+//! rows are placed before or after all the others, also in their original
+//! order.
+//!
+//! Struct arrays, like the ones exported for record batches, can be sorted
+//! by several of their fields, each with its own order and placement of
+//! nulls. Rows are compared by the first key, and ties are broken by the
+//! following keys.
+//!
+//! Large inputs are sorted in parallel with a sample sort when the options
+//! allow more than a thread. A sample of the rows is sorted to pick a
+//! splitter per thread, the rows are partitioned in buckets between
+//! consecutive splitters, and the buckets are sorted independently, so the
+//! concatenation of the sorted buckets is the sorted permutation. Equal rows
+//! always land in the same bucket, in their original order, which keeps the
+//! sort stable.
+//!
+//! With a memory limit, inputs whose permutation would take more than half
+//! of the limit are sorted in runs of contiguous rows that fit in it. Every
+//! run is written to a spill file, with the keys and the position of its
+//! rows, and the runs are merged like sorted streams, see `merge`, which
+//! reads a batch of every run at a time. Runs are merged in the order of
+//! their rows, so the sort is still stable.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+use crate::array::ArrowArray;
+use crate::bitmap::{Bitmap, BitmapBuilder};
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::memory::{self, Reservation};
+use crate::merge::{MergeStreams, SortedInput};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::spill::SpillFile;
+use crate::stream::{ArraySource, ImportedArray};
+use crate::types::{with_native_type, TotalOrd};
+use crate::udf;
+
+/// Inputs with fewer rows are always sorted by a single thread.
+pub const PARALLEL_SORT_MIN_ROWS: usize = 1 << 16;
+
+/// Rows sampled per thread to pick the splitters of the sample sort.
+const OVERSAMPLING: usize = 32;
+
+/// Bytes used per row to sort in memory: the permutation, and the scratch
+/// of the stable sort.
+const ROW_BYTES: usize = 2 * size_of::<usize>();
+
+/// Runs shorter than this are not worth spilling, and fail instead.
+const MIN_RUN_LEN: usize = 1024;
+
+/// Sort `rows` in place with the comparator `cmp`, keeping the order of the
+/// rows that compare equal.
+pub(crate) fn sort_rows<F>(rows: &mut Vec<usize>, cmp: &F, options: &ArrowUdfExecOptions)
+where
+    F: Fn(usize, usize) -> Ordering + Sync,
+{
+    let threads = options.threads();
+    if threads <= 1 || rows.len() < PARALLEL_SORT_MIN_ROWS {
+        rows.sort_by(|a, b| cmp(*a, *b));
+        return;
+    }
+    let step = (rows.len() / (threads * OVERSAMPLING)).max(1);
+    let mut sample: Vec<usize> = rows.iter().step_by(step).copied().collect();
+    sample.sort_by(|a, b| cmp(*a, *b));
+    let splitters: Vec<usize> = (1..threads)
+        .map(|i| sample[i * sample.len() / threads])
+        .collect();
+    let bucket = |row: usize| splitters.partition_point(|splitter| cmp(*splitter, row).is_le());
+
+    // Every thread partitions a contiguous chunk of the rows, and the
+    // buckets are then concatenated in the order of the chunks.
+    let chunk_len = rows.len().div_ceil(threads);
+    let partitions: Vec<Vec<Vec<usize>>> = thread::scope(|scope| {
+        let handles: Vec<_> = rows
+            .chunks(chunk_len)
+            .map(|chunk| {
+                let bucket = &bucket;
+                scope.spawn(move || {
+                    let mut buckets = vec![Vec::new(); threads];
+                    chunk
+                        .iter()
+                        .for_each(|row| buckets[bucket(*row)].push(*row));
+                    buckets
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    let mut buckets: Vec<Vec<usize>> = (0..threads)
+        .map(|b| partitions.iter().flat_map(|p| &p[b]).copied().collect())
+        .collect();
+    drop(partitions);
+    thread::scope(|scope| {
+        for bucket in &mut buckets {
+            scope.spawn(move || bucket.sort_by(|a, b| cmp(*a, *b)));
+        }
+    });
+    rows.clear();
+    buckets
+        .iter()
+        .for_each(|bucket| rows.extend_from_slice(bucket));
+}
+
+/// Compare the values of two rows of a column, in ascending order.
+fn column_cmp<'a, T: TotalOrd>(
+    array: &ArrowArray<'a>,
+) -> impl Fn(usize, usize) -> Ordering + Sync + 'a {
+    let values = array.values::<T>();
+    move |a, b| values[a].total_cmp(&values[b])
+}
+
+/// Permutation of the rows of `array` that sorts it, in ascending or
+/// descending order, with the nulls first or last.
+pub fn sort_indices(
+    array: &ArrowArray,
+    descending: bool,
+    nulls_first: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<i64>> {
+    if let Some(run_len) = spilled_run_len(array.len(), options) {
+        let key = ArrowUdfSortKey {
+            column: 0,
+            descending,
+            nulls_first,
+        };
+        let cmp = key_cmp(*array, 0, None, &key, options)?;
+        return sort_spilled(
+            &cmp,
+            array.len(),
+            &[(*array, key)],
+            0,
+            None,
+            run_len,
+            options,
+        );
+    }
+    let _state = Reservation::new(array.len() * ROW_BYTES);
+    let (mut valid, nulls): (Vec<usize>, Vec<usize>) = match array.validity() {
+        Some(validity) if array.null_count() > 0 => {
+            (0..array.len()).partition(|i| validity.is_set(*i))
+        }
+        _ => ((0..array.len()).collect(), Vec::new()),
+    };
+    with_native_type!(array.data_type(), T => {
+        udf::check_input::<T>(array, options)?;
+        let cmp = column_cmp::<T>(array);
+        if descending {
+            sort_rows(&mut valid, &|a, b| cmp(b, a), options);
+        } else {
+            sort_rows(&mut valid, &cmp, options);
+        }
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "sort of {:?} arrays",
+        array.data_type()
+    ))));
+    options.check_cancelled()?;
+    options.check_memory()?;
+    let indices = if nulls_first {
+        nulls.iter().chain(&valid)
+    } else {
+        valid.iter().chain(&nulls)
+    };
+    Ok(indices.map(|i| *i as i64).collect())
+}
+
+/// How a field of a struct array is sorted by `arrow_udf_sort_indices_by`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArrowUdfSortKey {
+    /// Index of the field of the struct.
+    pub column: i64,
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+type RowCmp<'a> = Box<dyn Fn(usize, usize) -> Ordering + Sync + 'a>;
+
+/// Compare two rows of a struct by the values of the field `column`, which
+/// are at `offset` plus the row. Rows that are null in the struct are null
+/// for all the fields.
+fn key_cmp<'a>(
+    column: ArrowArray<'a>,
+    offset: usize,
+    parent_validity: Option<Bitmap<'a>>,
+    key: &ArrowUdfSortKey,
+    options: &ArrowUdfExecOptions,
+) -> Result<RowCmp<'a>> {
+    let cmp: RowCmp<'a> = with_native_type!(column.data_type(), T => {
+        udf::check_input::<T>(&column, options)?;
+        let cmp = column_cmp::<T>(&column);
+        Box::new(move |a, b| cmp(a + offset, b + offset))
+    }, _ => return Err(Error::UnsupportedType(format!(
+        "sort of {:?} arrays",
+        column.data_type()
+    ))));
+    let cmp: RowCmp<'a> = match key.descending {
+        true => Box::new(move |a, b| cmp(b, a)),
+        false => cmp,
+    };
+    let validity = column.validity().filter(|_| column.null_count() > 0);
+    if validity.is_none() && parent_validity.is_none() {
+        return Ok(cmp);
+    }
+    let is_valid = move |row: usize| {
+        validity.is_none_or(|validity| validity.is_set(row + offset))
+            && parent_validity.is_none_or(|validity| validity.is_set(row))
+    };
+    let nulls_first = key.nulls_first;
+    Ok(Box::new(move |a, b| match (is_valid(a), is_valid(b)) {
+        (true, true) => cmp(a, b),
+        (false, false) => Ordering::Equal,
+        (false, true) if nulls_first => Ordering::Less,
+        (true, false) if nulls_first => Ordering::Greater,
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+    }))
+}
+
+/// Permutation of the rows of the struct array `batch` that sorts it by the
+/// fields in `keys`.
+pub fn sort_indices_by(
+    batch: &ArrowArray,
+    keys: &[ArrowUdfSortKey],
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<i64>> {
+    if batch.data_type() != ArrowType::Struct {
+        return Err(Error::UnsupportedType(format!(
+            "expected a struct array to sort by keys, got {:?}",
+            batch.data_type()
+        )));
+    }
+    if keys.is_empty() {
+        return Err(Error::InvalidArgument("sort without keys".to_string()));
+    }
+    let parent_validity = batch.validity().filter(|_| batch.null_count() > 0);
+    if parent_validity.is_some() && options.null_policy()? == NullPolicy::Error {
+        return Err(Error::NullValue);
+    }
+    let n_columns = batch.ffi().n_children;
+    let cmps = keys
+        .iter()
+        .map(|key| {
+            if !(0..n_columns).contains(&key.column) {
+                return Err(Error::InvalidArgument(format!(
+                    "sort key {} out of bounds for a struct of {n_columns} fields",
+                    key.column
+                )));
+            }
+            let column = batch.child(key.column as usize);
+            if column.len() < batch.offset() + batch.len() {
+                return Err(Error::InvalidArgument(format!(
+                    "field {} is shorter than the struct",
+                    key.column
+                )));
+            }
+            key_cmp(column, batch.offset(), parent_validity, key, options)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let cmp = |a: usize, b: usize| {
+        cmps.iter()
+            .map(|cmp| cmp(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    };
+    if let Some(run_len) = spilled_run_len(batch.len(), options) {
+        let columns: Vec<_> = keys
+            .iter()
+            .map(|key| (batch.child(key.column as usize), *key))
+            .collect();
+        let offset = batch.offset();
+        return sort_spilled(
+            &cmp,
+            batch.len(),
+            &columns,
+            offset,
+            parent_validity,
+            run_len,
+            options,
+        );
+    }
+    let _state = Reservation::new(batch.len() * ROW_BYTES);
+    let mut rows: Vec<usize> = (0..batch.len()).collect();
+    sort_rows(&mut rows, &cmp, options);
+    options.check_cancelled()?;
+    options.check_memory()?;
+    Ok(rows.into_iter().map(|i| i as i64).collect())
+}
+
+/// With a memory limit, the length of the runs to sort `len` rows in, if
+/// sorting them at once would use more than half of the limit, leaving the
+/// rest for the output and the batches of the merge.
+fn spilled_run_len(len: usize, options: &ArrowUdfExecOptions) -> Option<usize> {
+    let limit = options.memory_limit()?;
+    let in_use = memory::current().map_or(0, |tracker| tracker.in_use());
+    let available = (limit / 2).saturating_sub(in_use);
+    (len.saturating_mul(ROW_BYTES) > available).then_some(available / ROW_BYTES)
+}
+
+/// Sort the `len` rows by `cmp` in runs of `run_len` rows, spill them with
+/// the values of the key `columns`, which are at `offset` plus the row, and
+/// merge them by their keys.
+fn sort_spilled<F>(
+    cmp: &F,
+    len: usize,
+    columns: &[(ArrowArray, ArrowUdfSortKey)],
+    offset: usize,
+    parent_validity: Option<Bitmap>,
+    run_len: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<Vec<i64>>
+where
+    F: Fn(usize, usize) -> Ordering + Sync,
+{
+    if run_len < MIN_RUN_LEN {
+        return Err(Error::OutOfBudget);
+    }
+    let mut fields: Vec<Schema> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (column, _))| Schema::new(column.data_type(), &format!("key_{i}")))
+        .collect();
+    fields.push(Schema::new(ArrowType::Int64, "row"));
+    let schema = Schema::new(ArrowType::Struct, "").with_children(fields);
+    // A batch of every run is in memory while merging them.
+    let n_runs = len.div_ceil(run_len);
+    let batch_len = options.batch_len(run_len).min(run_len / n_runs).max(1);
+
+    let mut runs: Vec<Box<dyn SortedInput>> = Vec::with_capacity(n_runs);
+    let _state = Reservation::new(run_len * ROW_BYTES);
+    let mut rows = Vec::with_capacity(run_len);
+    for start in (0..len).step_by(run_len) {
+        rows.clear();
+        rows.extend(start..len.min(start + run_len));
+        sort_rows(&mut rows, cmp, options);
+        let mut file = SpillFile::create(&schema)?;
+        for chunk in rows.chunks(batch_len) {
+            let mut children: Vec<ArrayData> = columns
+                .iter()
+                .map(|(column, _)| run_keys(column, chunk, offset, parent_validity))
+                .collect();
+            let positions: Buffer = chunk.iter().map(|row| *row as i64).collect();
+            children.push(ArrayData::primitive(positions, chunk.len()));
+            file.write(ArrayData::struct_array(children, chunk.len()))?;
+        }
+        runs.push(Box::new(file.reader()?));
+        options.check_cancelled()?;
+        options.check_memory()?;
+    }
+    drop(rows);
+
+    let keys: Vec<ArrowUdfSortKey> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (_, key))| ArrowUdfSortKey {
+            column: i as i64,
+            ..*key
+        })
+        .collect();
+    let merge_options = ArrowUdfExecOptions {
+        batch_size: batch_len as i64,
+        ..*options
+    };
+    let mut merge = MergeStreams::from_inputs(runs, &keys, &merge_options)?;
+    let mut indices = Vec::with_capacity(len);
+    while let Some(batch) = merge.next()? {
+        let batch = ImportedArray::from(batch);
+        let batch = batch.view(merge.schema());
+        indices.extend_from_slice(batch.child(columns.len()).values::<i64>());
+    }
+    Ok(indices)
+}
+
+/// The values of the key `column` for the `rows` of a run, null where the
+/// key or the struct is null.
+fn run_keys(
+    column: &ArrowArray,
+    rows: &[usize],
+    offset: usize,
+    parent_validity: Option<Bitmap>,
+) -> ArrayData {
+    let data = with_native_type!(column.data_type(), T => {
+        let values = column.values::<T>();
+        let values: Buffer = rows.iter().map(|row| values[row + offset]).collect();
+        ArrayData::primitive(values, rows.len())
+    }, _ => unreachable!("validated key type"));
+    let validity = column.validity().filter(|_| column.null_count() > 0);
+    if validity.is_none() && parent_validity.is_none() {
+        return data;
+    }
+    let mut bits = BitmapBuilder::with_capacity(rows.len());
+    let mut null_count = 0;
+    for row in rows {
+        let valid = validity.is_none_or(|validity| validity.is_set(row + offset))
+            && parent_validity.is_none_or(|validity| validity.is_set(*row));
+        null_count += !valid as usize;
+        bits.push(valid);
+    }
+    data.with_validity(Some(bits.finish()), null_count)
+}
+
+/// Int64 array with the positions of the rows of `array` in sorted order.
+/// Nulls are placed first if `nulls_first`, or last otherwise.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_sort_indices(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    descending: bool,
+    nulls_first: bool,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let indices = sort_indices(&array, descending, nulls_first, &options)?;
+        let data = ArrayData::primitive(Buffer::from_slice(&indices), indices.len());
+        let out = Schema::new(ArrowType::Int64, &schema.name);
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Int64 array with the positions of the rows of a struct array in the
+/// order given by the `n_keys` sort keys, each naming a field of the struct.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `keys` to `n_keys` valid sort keys, `options` must be null or valid, and
+/// `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_sort_indices_by(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    n_keys: i64,
+    keys: *const ArrowUdfSortKey,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        if n_keys < 0 {
+            return Err(Error::InvalidArgument(format!(
+                "the number of sort keys must be zero or positive, got {n_keys}"
+            )));
+        }
+        let keys = match n_keys {
+            0 => &[],
+            n => std::slice::from_raw_parts(keys, n as usize),
+        };
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let indices = sort_indices_by(&array, keys, &options)?;
+        let data = ArrayData::primitive(Buffer::from_slice(&indices), indices.len());
+        let out = Schema::new(ArrowType::Int64, &schema.name);
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Exported};
+
+    fn run(input: &Exported, descending: bool, nulls_first: bool) -> Vec<i64> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_sort_indices(
+                &input.schema,
+                &input.array,
+                descending,
+                nulls_first,
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        out.values()
+    }
+
+    #[test]
+    fn sorts_are_stable_with_the_nulls_apart() {
+        let input = Exported::nullable(&[Some(3_i32), None, Some(1), Some(3), None, Some(2)]);
+        assert_eq!(run(&input, false, false), [2, 5, 0, 3, 1, 4]);
+        assert_eq!(run(&input, false, true), [1, 4, 2, 5, 0, 3]);
+        assert_eq!(run(&input, true, false), [0, 3, 5, 2, 1, 4]);
+    }
+
+    #[test]
+    fn nan_sorts_after_every_float() {
+        let input = Exported::primitive(&[f64::NAN, 1.0, f64::NEG_INFINITY, -0.0, 0.0]);
+        assert_eq!(run(&input, false, false), [2, 3, 4, 1, 0]);
+    }
+
+    #[test]
+    fn parallel_sorts_match_the_sequential_one() {
+        let values: Vec<u16> = (0..PARALLEL_SORT_MIN_ROWS * 2)
+            .map(|i| (i * 7919 % 1000) as u16)
+            .collect();
+        let input = Exported::primitive(&values);
+        let options = |num_threads| ArrowUdfExecOptions {
+            num_threads,
+            ..ArrowUdfExecOptions::default()
+        };
+        let sequential = input.with_array(|array| sort_indices(array, true, false, &options(1)));
+        let parallel = input.with_array(|array| sort_indices(array, true, false, &options(4)));
+        assert_eq!(parallel.unwrap(), sequential.unwrap());
+    }
+
+    /// Options with a memory limit that spills the runs of sorts of 5000
+    /// rows.
+    fn spilling() -> ArrowUdfExecOptions {
+        ArrowUdfExecOptions {
+            memory_limit: 100_000,
+            batch_size: 700,
+            ..ArrowUdfExecOptions::default()
+        }
+    }
+
+    fn nullable_values(len: usize) -> Vec<Option<i32>> {
+        (0..len)
+            .map(|i| (i % 7 != 0).then_some((i * 7919 % 100) as i32))
+            .collect()
+    }
+
+    #[test]
+    fn spilled_sorts_match_the_in_memory_one() {
+        let input = Exported::nullable(&nullable_values(5000));
+        assert!(spilled_run_len(5000, &spilling()).is_some());
+        for (descending, nulls_first) in [(false, false), (true, true)] {
+            let sort = |options| {
+                input.with_array(|array| sort_indices(array, descending, nulls_first, &options))
+            };
+            let in_memory = sort(ArrowUdfExecOptions::default()).unwrap();
+            assert_eq!(sort(spilling()).unwrap(), in_memory);
+        }
+    }
+
+    #[test]
+    fn budgets_too_small_to_spill_fail() {
+        let input = Exported::nullable(&nullable_values(5000));
+        let options = ArrowUdfExecOptions {
+            memory_limit: 1000,
+            ..ArrowUdfExecOptions::default()
+        };
+        let result = input.with_array(|array| sort_indices(array, false, false, &options));
+        assert_eq!(result, Err(Error::OutOfBudget));
+    }
+
+    #[test]
+    fn other_types_are_unsupported() {
+        let input = Exported::boolean(&[true, false]);
+        let result = input
+            .with_array(|array| sort_indices(array, false, false, &ArrowUdfExecOptions::default()));
+        assert!(matches!(result, Err(Error::UnsupportedType(_))));
+    }
+
+    /// Struct with an Int32 field `a` and a Float64 field `b`, and the
+    /// validity of the struct in `valid`.
+    fn batch(a: &[Option<i32>], b: &[Option<f64>], valid: &[Option<()>]) -> Exported {
+        let schema = Schema::new(ArrowType::Struct, "batch").with_children(vec![
+            Schema::new(ArrowType::Int32, "a"),
+            Schema::new(ArrowType::Float64, "b"),
+        ]);
+        let children = vec![testing::nullable_data(a), testing::nullable_data(b)];
+        let null_count = valid.iter().filter(|valid| valid.is_none()).count();
+        let data = ArrayData::struct_array(children, valid.len())
+            .with_validity(Some(testing::validity(valid)), null_count);
+        Exported::new(&schema, data)
+    }
+
+    fn run_by(
+        batch: &Exported,
+        keys: &[(i64, bool, bool)],
+    ) -> std::result::Result<Vec<i64>, ArrowUdfStatus> {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|&(column, descending, nulls_first)| ArrowUdfSortKey {
+                column,
+                descending,
+                nulls_first,
+            })
+            .collect();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_sort_indices_by(
+                &batch.schema,
+                &batch.array,
+                keys.len() as i64,
+                keys.as_ptr(),
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out.values()),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn structs_are_sorted_by_several_keys() {
+        let valid = [Some(()); 6];
+        let a = [Some(1), Some(2), Some(1), None, Some(2), Some(1)];
+        let b = [Some(0.5), Some(0.1), None, Some(9.0), Some(0.1), Some(0.7)];
+        let batch = batch(&a, &b, &valid);
+        assert_eq!(
+            run_by(&batch, &[(0, false, false), (1, true, true)]).unwrap(),
+            [2, 5, 0, 1, 4, 3]
+        );
+        assert_eq!(
+            run_by(&batch, &[(0, true, true), (1, false, false)]).unwrap(),
+            [3, 1, 4, 0, 5, 2]
+        );
+    }
+
+    #[test]
+    fn null_structs_are_null_in_every_key() {
+        let a = [Some(3), Some(1), Some(2), Some(0)];
+        let b = [Some(1.0); 4];
+        let mut batch = batch(&a, &b, &[Some(()), Some(()), None, None]);
+        assert_eq!(run_by(&batch, &[(0, false, true)]).unwrap(), [2, 3, 1, 0]);
+        // The fields are not sliced with the struct.
+        batch.array.offset = 1;
+        batch.array.length = 3;
+        assert_eq!(run_by(&batch, &[(0, false, false)]).unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn spilled_struct_sorts_match_the_in_memory_one() {
+        let a: Vec<Option<i32>> = nullable_values(5001)
+            .iter()
+            .map(|a| a.map(|a| a % 3))
+            .collect();
+        let b: Vec<Option<f64>> = nullable_values(5001)
+            .iter()
+            .rev()
+            .map(|b| b.map(f64::from))
+            .collect();
+        let valid: Vec<Option<()>> = (0..5001).map(|i| (i % 11 != 0).then_some(())).collect();
+        let mut batch = batch(&a, &b, &valid);
+        batch.array.offset = 1;
+        batch.array.length = 5000;
+        let keys = [
+            ArrowUdfSortKey {
+                column: 0,
+                descending: true,
+                nulls_first: false,
+            },
+            ArrowUdfSortKey {
+                column: 1,
+                descending: false,
+                nulls_first: true,
+            },
+        ];
+        let sort = |options| batch.with_array(|array| sort_indices_by(array, &keys, &options));
+        let in_memory = sort(ArrowUdfExecOptions::default()).unwrap();
+        assert_eq!(sort(spilling()).unwrap(), in_memory);
+    }
+
+    #[test]
+    fn invalid_sort_keys_fail() {
+        let batch = batch(&[Some(1)], &[Some(1.0)], &[Some(())]);
+        assert_eq!(run_by(&batch, &[]), Err(ArrowUdfStatus::InvalidArgument));
+        assert_eq!(
+            run_by(&batch, &[(2, false, false)]),
+            Err(ArrowUdfStatus::InvalidArgument)
+        );
+        let plain = Exported::primitive(&[1_i64]);
+        assert_eq!(
+            run_by(&plain, &[(0, false, false)]),
+            Err(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+}
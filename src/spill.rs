@@ -0,0 +1,174 @@
+//! Temporary files for the state of kernels that doesn't fit in the memory
+//! limit of a call.
+//!
+//! Group by writes its groups, with the serialized states of their
+//! aggregates, to spill files when its hash table would grow past the
+//! `memory_limit` of the execution options, and sort writes the sorted runs
+//! of its rows when sorting them all at once would, see `sort`. Both read
+//! the files back to merge them. Spill files are Arrow IPC streams of record
+//! batches, see `ipc`, written and read one batch at a time.
+//!
+//! Files are created in the temporary directory of the system, given by
+//! `TMPDIR` on Unix, and removed when dropped.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::ipc::{StreamReader, StreamWriter};
+use crate::merge::SortedInput;
+use crate::schema::Schema;
+use crate::stream::ImportedArray;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn io_error(error: io::Error) -> Error {
+    Error::Io(error.to_string())
+}
+
+/// Path of a temporary file, removed when dropped.
+struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Temporary file written a record batch at a time, and then read from the
+/// start.
+pub(crate) struct SpillFile {
+    writer: StreamWriter<BufWriter<File>>,
+    schema: Schema,
+    path: TempPath,
+}
+
+impl SpillFile {
+    /// Spill file of the struct arrays of `schema`.
+    pub(crate) fn create(schema: &Schema) -> Result<SpillFile> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("arrow_udf_spill_{}_{id}", process::id()));
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(io_error)?;
+        // The file is removed if writing the schema fails.
+        let path = TempPath(path);
+        Ok(SpillFile {
+            writer: StreamWriter::new(BufWriter::new(file), schema)?,
+            schema: schema.clone(),
+            path,
+        })
+    }
+
+    /// Write `batch`, a struct array without nulls with the fields of the
+    /// schema of the file, as the next batch.
+    pub(crate) fn write(&mut self, batch: ArrayData) -> Result<()> {
+        let batch = ImportedArray::from(batch);
+        self.writer.write(&batch.view(&self.schema))
+    }
+
+    /// Reader of the batches written, in the order they were written.
+    pub(crate) fn reader(self) -> Result<SpillReader> {
+        self.writer.finish()?;
+        let file = File::open(&self.path.0).map_err(io_error)?;
+        Ok(SpillReader {
+            reader: StreamReader::new(BufReader::new(file))?,
+            _path: self.path,
+        })
+    }
+}
+
+/// Reader of the batches of a spill file, which is removed when the reader
+/// is dropped.
+pub(crate) struct SpillReader {
+    reader: StreamReader<BufReader<File>>,
+    _path: TempPath,
+}
+
+impl SpillReader {
+    /// The schema the file was created with, without its name.
+    pub(crate) fn schema(&self) -> &Schema {
+        self.reader.schema()
+    }
+
+    /// The next batch, or `None` after the last one.
+    pub(crate) fn next(&mut self) -> Result<Option<ImportedArray>> {
+        self.reader.next()
+    }
+}
+
+impl SortedInput for SpillReader {
+    fn schema(&self) -> &Schema {
+        self.reader.schema()
+    }
+
+    fn next_array(&mut self) -> Result<Option<ImportedArray>> {
+        self.reader.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::BinaryBuilder;
+    use crate::schema::ArrowType;
+    use crate::testing::nullable_data;
+
+    fn schema() -> Schema {
+        Schema::new(ArrowType::Struct, "").with_children(vec![
+            Schema::new(ArrowType::UInt64, "hash"),
+            Schema::new(ArrowType::Binary, "state"),
+        ])
+    }
+
+    fn batch(hashes: &[Option<u64>], states: &[Option<&[u8]>]) -> ArrayData {
+        let mut builder = BinaryBuilder::with_capacity(states.len());
+        states.iter().for_each(|state| builder.push(*state));
+        ArrayData::struct_array(vec![nullable_data(hashes), builder.finish()], hashes.len())
+    }
+
+    #[test]
+    fn batches_are_read_in_the_order_they_were_written() {
+        let mut file = SpillFile::create(&schema()).unwrap();
+        file.write(batch(&[Some(u64::MAX - 1), None], &[Some(b"state"), None]))
+            .unwrap();
+        file.write(batch(&[], &[])).unwrap();
+        file.write(batch(&[Some(7)], &[Some(b"")])).unwrap();
+        let mut reader = file.reader().unwrap();
+        assert_eq!(reader.schema(), &schema());
+        let first = reader.next().unwrap().unwrap();
+        let first = first.view(reader.schema());
+        let hashes = first.child(0);
+        assert_eq!(hashes.values::<u64>()[0], u64::MAX - 1);
+        assert!(!hashes.is_valid(1));
+        let states = first.child(1);
+        assert_eq!(states.binary_value(0), b"state");
+        assert!(!states.is_valid(1));
+        assert!(reader.next().unwrap().unwrap().view(&schema()).is_empty());
+        let last = reader.next().unwrap().unwrap();
+        assert_eq!(last.view(&schema()).child(0).values::<u64>(), [7]);
+        assert!(reader.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn files_are_removed_when_dropped() {
+        let file = SpillFile::create(&schema()).unwrap();
+        let path = file.path.0.clone();
+        assert!(path.exists());
+        let reader = file.reader().unwrap();
+        assert!(path.exists());
+        drop(reader);
+        assert!(!path.exists());
+        let file = SpillFile::create(&schema()).unwrap();
+        let path = file.path.0.clone();
+        drop(file);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,366 @@
+//! Statistics of a column, to collect the statistics hosts use for pushdown,
+//! like the ones of Parquet row groups, over data they don't own.
+//!
+//! `column_stats` computes the minimum, the maximum, the number of nulls
+//! and an approximation of the number of distinct values of an array in a
+//! single pass, split in parts computed by different threads. The distinct
+//! values are counted with a HyperLogLog sketch of the default precision,
+//! so the count has a relative error around 1.6%.
+//!
+//! NaN is skipped by the minimum and the maximum, like in the statistics of
+//! Parquet, but it's counted as a distinct value. Strings and binaries are
+//! compared by their bytes, which for UTF-8 is the order of their code
+//! points.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aggregate;
+use crate::array::ArrowArray;
+use crate::binary::{BinaryBuilder, Utf8Builder};
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::hll::{self, HashValue, Sketch};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::types::{with_native_type, Numeric};
+
+/// Minimum, maximum and sketch of the rows of a part of an array.
+#[derive(Clone)]
+struct Stats<T> {
+    min: Option<T>,
+    max: Option<T>,
+    sketch: Sketch,
+}
+
+impl<T: Copy> Stats<T> {
+    fn new() -> Stats<T> {
+        Stats {
+            min: None,
+            max: None,
+            sketch: Sketch::new(hll::DEFAULT_PRECISION),
+        }
+    }
+
+    /// Combine the statistics of two parts, `less` comparing their values.
+    fn merge(mut self, other: Stats<T>, less: impl Fn(&T, &T) -> bool) -> Stats<T> {
+        if let Some(min) = other.min {
+            if self.min.is_none_or(|current| less(&min, &current)) {
+                self.min = Some(min);
+            }
+        }
+        if let Some(max) = other.max {
+            if self.max.is_none_or(|current| less(&current, &max)) {
+                self.max = Some(max);
+            }
+        }
+        self.sketch.merge(&other.sketch);
+        self
+    }
+}
+
+fn less<T: Numeric>(a: &T, b: &T) -> bool {
+    a.total_cmp(b).is_lt()
+}
+
+fn primitive_stats<T: Numeric + HashValue>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(ArrayData, ArrayData, i64)> {
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let stats = exec::reduce_values(
+        values,
+        options,
+        Stats::<T>::new(),
+        |mut stats, rows| {
+            for i in rows {
+                if validity.is_some_and(|validity| !validity.is_set(i)) {
+                    continue;
+                }
+                let value = values[i];
+                stats.sketch.add(value);
+                if value.is_nan() {
+                    continue;
+                }
+                if stats.min.is_none_or(|min| less(&value, &min)) {
+                    stats.min = Some(value);
+                }
+                if stats.max.is_none_or(|max| less(&max, &value)) {
+                    stats.max = Some(value);
+                }
+            }
+            stats
+        },
+        |a, b| a.merge(b, less),
+    )?;
+    let scalar = |value: Option<T>| {
+        aggregate::nullable_result(&[value.unwrap_or_default()], &[value.is_some() as i64])
+    };
+    Ok((
+        scalar(stats.min),
+        scalar(stats.max),
+        stats.sketch.estimate(),
+    ))
+}
+
+/// Statistics of a Binary, LargeBinary or Utf8 array, from its `offsets`
+/// into its data.
+fn binary_stats<O: Copy + Into<i64> + Sync>(
+    array: &ArrowArray,
+    offsets: &[O],
+    options: &ArrowUdfExecOptions,
+) -> Result<(ArrayData, ArrayData, i64)> {
+    let data = array.binary_data();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let value = |i: usize| &data[offsets[i].into() as usize..offsets[i + 1].into() as usize];
+    let less = |a: &usize, b: &usize| value(*a) < value(*b);
+    let fold = |mut stats: Stats<usize>, rows: Range<usize>| {
+        for i in rows {
+            if validity.is_some_and(|validity| !validity.is_set(i)) {
+                continue;
+            }
+            stats.sketch.add_bytes(value(i));
+            if stats.min.is_none_or(|min| less(&i, &min)) {
+                stats.min = Some(i);
+            }
+            if stats.max.is_none_or(|max| less(&max, &i)) {
+                stats.max = Some(i);
+            }
+        }
+        stats
+    };
+    let stats = exec::reduce(array.len(), options, Stats::new(), fold, |a, b| {
+        a.merge(b, less)
+    })?;
+    let scalar = |row: Option<usize>| -> Result<ArrayData> {
+        Ok(match array.data_type() {
+            ArrowType::Utf8 => {
+                let mut builder = Utf8Builder::with_capacity(1);
+                builder.push(row.map(|row| array.utf8_value(row)).transpose()?);
+                builder.finish()
+            }
+            ArrowType::LargeBinary => {
+                let mut builder = BinaryBuilder::large_with_capacity(1);
+                builder.push(row.map(|row| array.binary_value(row)));
+                builder.finish()
+            }
+            _ => {
+                let mut builder = BinaryBuilder::with_capacity(1);
+                builder.push(row.map(|row| array.binary_value(row)));
+                builder.finish()
+            }
+        })
+    };
+    Ok((
+        scalar(stats.min)?,
+        scalar(stats.max)?,
+        stats.sketch.estimate(),
+    ))
+}
+
+/// Minimum, maximum, number of nulls and approximate number of distinct
+/// values of a numeric, temporal, Utf8 or binary array, as a struct array
+/// with a single row and the fields `min`, `max`, `null_count` and
+/// `distinct_count`.
+///
+/// `min` and `max` have the type of the input, and are null when the array
+/// doesn't have any non-null value other than NaN. Nulls are counted
+/// whatever the null policy is, unless it's `Error`.
+pub fn column_stats(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let data_type = array.data_type();
+    let (min, max, distinct_count) = match data_type {
+        ArrowType::Utf8 | ArrowType::Binary => {
+            binary_stats(array, array.binary_offsets(), options)?
+        }
+        ArrowType::LargeBinary => binary_stats(array, array.large_binary_offsets(), options)?,
+        _ => with_native_type!(data_type.physical_type(), T => {
+            primitive_stats::<T>(array, options)?
+        }, _ => return Err(Error::UnsupportedType(format!(
+            "column_stats of {data_type:?} values"
+        )))),
+    };
+    let data = ArrayData::struct_array(
+        vec![
+            min,
+            max,
+            ArrayData::primitive(Buffer::from_slice(&[array.null_count() as i64]), 1),
+            ArrayData::primitive(Buffer::from_slice(&[distinct_count]), 1),
+        ],
+        1,
+    );
+    Ok((output_schema(array.schema()), data))
+}
+
+/// Schema of the statistics of arrays described by `input`.
+pub(crate) fn output_schema(input: &Schema) -> Schema {
+    let bound = |name: &str| Schema {
+        name: name.to_string(),
+        ..input.clone()
+    };
+    Schema::new(ArrowType::Struct, &input.name).with_children(vec![
+        bound("min"),
+        bound("max"),
+        Schema::new(ArrowType::Int64, "null_count"),
+        Schema::new(ArrowType::Int64, "distinct_count"),
+    ])
+}
+
+/// Minimum, maximum, number of nulls and approximate number of distinct
+/// values of an array, as a struct array with a single row of `min`, `max`,
+/// `null_count` and `distinct_count`.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_column_stats(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = column_stats(&array, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    fn stats(input: &Exported, batch_size: i64) -> Exported {
+        let options = ArrowUdfExecOptions {
+            batch_size,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| column_stats(array, &options))
+            .unwrap();
+        Exported::new(&schema, data)
+    }
+
+    fn counts(out: &Exported) -> (Option<i64>, Option<i64>) {
+        (out.child_values::<i64>(2)[0], out.child_values::<i64>(3)[0])
+    }
+
+    #[test]
+    fn numbers_across_parts() {
+        let values: Vec<Option<i32>> = (0..1000)
+            .map(|i| (i % 10 != 0).then_some((i * 37) % 500 - 250))
+            .collect();
+        let input = Exported::named("x", &values);
+        for batch_size in [0, 7, 64] {
+            let out = stats(&input, batch_size);
+            assert_eq!(out.child_values::<i32>(0), [Some(-249)]);
+            assert_eq!(out.child_values::<i32>(1), [Some(249)]);
+            let (nulls, distinct) = counts(&out);
+            assert_eq!(nulls, Some(100));
+            let distinct = distinct.unwrap();
+            // 450 distinct values, within the error of the sketch.
+            assert!((420..=480).contains(&distinct), "{distinct}");
+        }
+        let out = stats(&input, 0);
+        out.with_array(|array| {
+            let names: Vec<_> = (0..4)
+                .map(|i| array.child(i).schema().name.clone())
+                .collect();
+            assert_eq!(names, ["min", "max", "null_count", "distinct_count"]);
+            assert_eq!(array.child(0).data_type(), ArrowType::Int32);
+        });
+    }
+
+    #[test]
+    fn nan_is_counted_but_not_a_bound() {
+        let input = Exported::nullable(&[Some(f64::NAN), Some(2.0), None, Some(-1.0)]);
+        let out = stats(&input, 0);
+        assert_eq!(out.child_values::<f64>(0), [Some(-1.0)]);
+        assert_eq!(out.child_values::<f64>(1), [Some(2.0)]);
+        assert_eq!(counts(&out), (Some(1), Some(3)));
+        let only_nan = Exported::nullable(&[Some(f32::NAN), None]);
+        let out = stats(&only_nan, 0);
+        assert_eq!(out.child_values::<f32>(0), [None]);
+        assert_eq!(out.child_values::<f32>(1), [None]);
+        assert_eq!(counts(&out), (Some(1), Some(1)));
+    }
+
+    #[test]
+    fn strings_and_binaries_by_their_bytes() {
+        let input = Exported::utf8(&[Some("pear"), None, Some("apple"), Some("é"), Some("pear")]);
+        let out = stats(&input, 2);
+        out.with_array(|array| {
+            assert_eq!(array.child(0).utf8_value(0), Ok("apple"));
+            assert_eq!(array.child(1).utf8_value(0), Ok("é"));
+        });
+        assert_eq!(counts(&out), (Some(1), Some(3)));
+        let mut builder = BinaryBuilder::large_with_capacity(3);
+        for value in [&b"\x01\x02"[..], b"\x01", b"\xff"] {
+            builder.push(Some(value));
+        }
+        let binaries = Exported::new(&Schema::new(ArrowType::LargeBinary, "x"), builder.finish());
+        let out = stats(&binaries, 0);
+        out.with_array(|array| {
+            assert_eq!(array.child(0).data_type(), ArrowType::LargeBinary);
+            assert_eq!(array.child(0).binary_value(0), b"\x01");
+            assert_eq!(array.child(1).binary_value(0), b"\xff");
+        });
+    }
+
+    #[test]
+    fn through_the_entry_point() {
+        let input = Exported::primitive(&[3_u64, 1, 2]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_column_stats(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.child_values::<u64>(0), [Some(1)]);
+        assert_eq!(out.child_values::<u64>(1), [Some(3)]);
+        assert_eq!(counts(&out), (Some(0), Some(3)));
+        let booleans = Exported::boolean(&[true]);
+        let status = unsafe {
+            arrow_udf_column_stats(
+                &booleans.schema,
+                &booleans.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+        let nulls = Exported::nullable(&[Some(1_i32), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let failed = nulls.with_array(|array| column_stats(array, &options).err());
+        assert_eq!(failed, Some(Error::NullValue));
+    }
+}
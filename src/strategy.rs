@@ -0,0 +1,517 @@
+//! Strategies to run reductions, and the planner choosing between them.
+//!
+//! The rows of a batch of a `map_sum` reduction can be summed in different
+//! ways:
+//!
+//! - `Iterator` goes over the indices of the rows, checking the validity of
+//!   every one of them.
+//! - `Slice` goes over the slice of values, reading the validity in words of
+//!   64 bits, so runs of valid or null rows don't check every bit.
+//! - `Simd` sums `LANES` values at a time in independent lanes, which the
+//!   compiler vectorizes, reading the validity in words like `Slice`.
+//! - `Unrolled` sums `UNROLL` groups of lanes like `Simd` per iteration,
+//!   with a scalar loop for the tail, and asks the processor to prefetch the
+//!   values `PREFETCH_DISTANCE` bytes ahead, for arrays larger than the
+//!   caches. With a validity, only the words of 64 valid rows are summed
+//!   this way, prefetching past them.
+//!
+//! and the batches can run in the calling thread, or be split among the
+//! threads of the options. `benches/strategies.rs` compares them for several
+//! lengths and fractions of nulls, and the constants below come from it.
+//! The compiler vectorizes the `Slice` loop too, so the two are close, and
+//! the `Iterator` loop is never the fastest, it's only the reference the
+//! others are compared with. The results depend on the processor, so
+//! `cargo bench --bench strategies` can be run to check them for a machine.
+//! Like `map_sum`, all the loops check for overflows.
+//!
+//! `plan` chooses the strategy of every call: inputs too short to pay for
+//! starting threads run in the calling thread, batches too short for the
+//! lanes use the `Slice` loop, and inputs without nulls larger than the
+//! caches use the `Unrolled` loop. Hosts can override the choice with the
+//! `strategy` execution option.
+//!
+//! The loops are compiled for AVX2 and AVX-512 too, used on the processors
+//! supporting them, see `cpu`.
+
+use std::ops::Range;
+
+use crate::bitmap::Bitmap;
+use crate::cpu::{self, Level};
+use crate::error::Result;
+use crate::metrics;
+use crate::options::{ArrowUdfExecOptions, Strategy};
+use crate::types::NativeType;
+
+/// Values summed at the same time by the `Simd` loop.
+pub const LANES: usize = 8;
+
+/// Groups of `LANES` values summed by every iteration of the `Unrolled`
+/// loop.
+pub const UNROLL: usize = 4;
+
+/// Bytes ahead of the values being summed that the `Unrolled` loop
+/// prefetches, far enough for the memory latency at its throughput.
+pub const PREFETCH_DISTANCE: usize = 1024;
+
+/// Inputs without nulls of more bytes than this, the size of a common last
+/// level cache, are summed with the `Unrolled` loop, since their values are
+/// read from the memory. It's up to 10% faster than the `Simd` loop for them.
+/// With nulls, most words of the validity mix valid and null rows, which are
+/// summed one by one, and the two loops are as fast.
+pub const UNROLLED_MIN_BYTES: usize = 32 << 20;
+
+/// Batches with fewer rows are summed with the `Slice` loop, since the
+/// `Simd` loop isn't faster for them.
+pub const SIMD_MIN_ROWS: usize = 1 << 10;
+
+/// Rows with nulls take this many times longer to sum than rows without
+/// them, branching on every bit of the words with valid and null rows.
+pub const NULLS_COST_FACTOR: usize = 4;
+
+/// Inputs with fewer rows, times `NULLS_COST_FACTOR` with nulls, are reduced
+/// in the calling thread. Starting the threads takes about as long as summing
+/// 30,000 rows, so splitting fewer rows among them doesn't pay off.
+pub const PARALLEL_MIN_ROWS: usize = 1 << 17;
+
+/// How the rows of a batch are looped over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopStrategy {
+    Iterator,
+    Slice,
+    Simd,
+    Unrolled,
+}
+
+/// How a reduction runs: the loop over the rows of its batches, and the
+/// threads its batches are split among.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Plan {
+    pub loop_strategy: LoopStrategy,
+    pub threads: usize,
+}
+
+impl Plan {
+    /// `options` with the threads of the plan, for `exec`.
+    pub fn options(&self, options: &ArrowUdfExecOptions) -> ArrowUdfExecOptions {
+        ArrowUdfExecOptions {
+            num_threads: self.threads as i32,
+            ..*options
+        }
+    }
+}
+
+/// The plan of a reduction over `len` values of `width` bytes, `null_count`
+/// of them null, with the `strategy` and the threads of `options`.
+pub fn plan(
+    len: usize,
+    null_count: usize,
+    width: usize,
+    options: &ArrowUdfExecOptions,
+) -> Result<Plan> {
+    let strategy = options.strategy()?;
+    let vectorized = strategy == Strategy::Simd || options.batch_len(len).min(len) >= SIMD_MIN_ROWS;
+    let loop_strategy = match vectorized {
+        false => LoopStrategy::Slice,
+        true if null_count == 0 && len.saturating_mul(width) >= UNROLLED_MIN_BYTES => {
+            LoopStrategy::Unrolled
+        }
+        true => LoopStrategy::Simd,
+    };
+    // Values are summed in lanes of 8 bytes, so narrower values cost the
+    // same as them.
+    let mut work = len.saturating_mul(width.max(8) / 8);
+    if null_count > 0 {
+        work = work.saturating_mul(NULLS_COST_FACTOR);
+    }
+    let threads = match strategy {
+        Strategy::Serial => 1,
+        Strategy::Parallel => options.threads(),
+        _ if work < PARALLEL_MIN_ROWS => 1,
+        _ => options.threads(),
+    };
+    Ok(Plan {
+        loop_strategy,
+        threads,
+    })
+}
+
+/// Sum of `f` over the valid values of `values` in `rows`, with the loop of
+/// `strategy`, or `None` if it overflows. `validity` is the bitmap of all
+/// the values, if any of them is null. The loop is compiled for the widest
+/// instruction set of the processor.
+pub fn sum_rows<T, F>(
+    strategy: LoopStrategy,
+    values: &[T],
+    validity: Option<Bitmap>,
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64>
+where
+    T: NativeType,
+    F: Fn(T) -> i64,
+{
+    // Safety: the processor supports the features the functions are
+    // compiled for.
+    match cpu::level() {
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx512 => {
+            metrics::record_simd();
+            unsafe { sum_rows_avx512(strategy, values, validity, rows, f) }
+        }
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx2 => {
+            metrics::record_simd();
+            unsafe { sum_rows_avx2(strategy, values, validity, rows, f) }
+        }
+        _ => sum_rows_generic(strategy, values, validity, rows, f),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn sum_rows_avx2<T: NativeType, F: Fn(T) -> i64>(
+    strategy: LoopStrategy,
+    values: &[T],
+    validity: Option<Bitmap>,
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64> {
+    sum_rows_generic(strategy, values, validity, rows, f)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx2,fma")]
+unsafe fn sum_rows_avx512<T: NativeType, F: Fn(T) -> i64>(
+    strategy: LoopStrategy,
+    values: &[T],
+    validity: Option<Bitmap>,
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64> {
+    sum_rows_generic(strategy, values, validity, rows, f)
+}
+
+#[inline(always)]
+fn sum_rows_generic<T, F>(
+    strategy: LoopStrategy,
+    values: &[T],
+    validity: Option<Bitmap>,
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64>
+where
+    T: NativeType,
+    F: Fn(T) -> i64,
+{
+    let validity = validity.map(|validity| validity.slice(rows.start, rows.len()));
+    match (strategy, validity) {
+        (LoopStrategy::Iterator, Some(validity)) => rows
+            .clone()
+            .filter(|i| validity.is_set(i - rows.start))
+            .try_fold(0i64, |sum, i| sum.checked_add(f(values[i]))),
+        (LoopStrategy::Iterator, None) => rows
+            .clone()
+            .try_fold(0i64, |sum, i| sum.checked_add(f(values[i]))),
+        (LoopStrategy::Slice, Some(validity)) => by_words(values, validity, rows, f, sum_slice),
+        (LoopStrategy::Slice, None) => sum_slice(values, rows, f),
+        (LoopStrategy::Simd, Some(validity)) => by_words(values, validity, rows, f, sum_lanes),
+        (LoopStrategy::Simd, None) => sum_lanes(values, rows, f),
+        (LoopStrategy::Unrolled, Some(validity)) => {
+            by_words(values, validity, rows, f, sum_unrolled)
+        }
+        (LoopStrategy::Unrolled, None) => sum_unrolled(values, rows, f),
+    }
+}
+
+// The loops receive all the values and the rows to sum, instead of the
+// slice of them, so `sum_unrolled` can prefetch the values past the rows of
+// a word of the validity.
+
+#[inline(always)]
+fn sum_slice<T: NativeType, F: Fn(T) -> i64>(
+    values: &[T],
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64> {
+    values[rows]
+        .iter()
+        .try_fold(0i64, |sum, value| sum.checked_add(f(*value)))
+}
+
+#[inline(always)]
+fn sum_lanes<T: NativeType, F: Fn(T) -> i64>(
+    values: &[T],
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64> {
+    let values = &values[rows];
+    let mut lanes = [0i64; LANES];
+    // Overflows are flagged instead of branching on every addition, so the
+    // lanes are still vectorized.
+    let mut overflow = false;
+    let chunks = values.chunks_exact(LANES);
+    let rest = sum_slice(chunks.remainder(), 0..chunks.remainder().len(), f)?;
+    for chunk in chunks {
+        for (lane, value) in lanes.iter_mut().zip(chunk) {
+            let (sum, overflowed) = lane.overflowing_add(f(*value));
+            *lane = sum;
+            overflow |= overflowed;
+        }
+    }
+    if overflow {
+        return None;
+    }
+    lanes
+        .iter()
+        .try_fold(rest, |sum, lane| sum.checked_add(*lane))
+}
+
+#[inline(always)]
+fn sum_unrolled<T: NativeType, F: Fn(T) -> i64>(
+    values: &[T],
+    rows: Range<usize>,
+    f: &F,
+) -> Option<i64> {
+    let mut lanes = [[0i64; LANES]; UNROLL];
+    let mut overflow = false;
+    let chunks = values[rows.clone()].chunks_exact(LANES * UNROLL);
+    let rest = sum_slice(chunks.remainder(), 0..chunks.remainder().len(), f)?;
+    let ahead = rows.start + PREFETCH_DISTANCE / std::mem::size_of::<T>().max(1);
+    for (i, chunk) in chunks.enumerate() {
+        for (j, (lanes, chunk)) in lanes.iter_mut().zip(chunk.chunks_exact(LANES)).enumerate() {
+            // Every group of 8 bytes lanes is a cache line.
+            if let Some(value) = values.get((i * UNROLL + j) * LANES + ahead) {
+                prefetch(value);
+            }
+            for (lane, value) in lanes.iter_mut().zip(chunk) {
+                let (sum, overflowed) = lane.overflowing_add(f(*value));
+                *lane = sum;
+                overflow |= overflowed;
+            }
+        }
+    }
+    if overflow {
+        return None;
+    }
+    lanes
+        .iter()
+        .flatten()
+        .try_fold(rest, |sum, lane| sum.checked_add(*lane))
+}
+
+/// Ask the processor to load the cache line of `value`, without waiting for
+/// it.
+#[inline(always)]
+fn prefetch<T>(value: &T) {
+    #[cfg(target_arch = "x86_64")]
+    // Safety: prefetching has no effect on the program, whatever the
+    // address.
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>((value as *const T).cast());
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = value;
+}
+
+/// Sum the values in `rows` of every word of 64 rows of `validity` with
+/// `sum` when all of them are valid, skipping the words without valid rows.
+#[inline(always)]
+fn by_words<T, F>(
+    values: &[T],
+    validity: Bitmap,
+    rows: Range<usize>,
+    f: &F,
+    sum: fn(&[T], Range<usize>, &F) -> Option<i64>,
+) -> Option<i64>
+where
+    T: NativeType,
+    F: Fn(T) -> i64,
+{
+    rows.clone()
+        .step_by(64)
+        .zip(validity.chunks())
+        .try_fold(0i64, |total, (start, word)| {
+            let chunk = start..(start + 64).min(rows.end);
+            let word_sum = match word {
+                0 => 0,
+                word if word.count_ones() as usize == chunk.len() => sum(values, chunk, f)?,
+                word => values[chunk]
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| word & (1 << i) != 0)
+                    .try_fold(0i64, |sum, (_, value)| sum.checked_add(f(*value)))?,
+            };
+            total.checked_add(word_sum)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::BitmapBuilder;
+
+    const STRATEGIES: [LoopStrategy; 4] = [
+        LoopStrategy::Iterator,
+        LoopStrategy::Slice,
+        LoopStrategy::Simd,
+        LoopStrategy::Unrolled,
+    ];
+
+    #[test]
+    fn every_strategy_gives_the_same_sum() {
+        let values: Vec<i64> = (0..1000).map(|i| i * 7 % 101 - 50).collect();
+        let mut builder = BitmapBuilder::with_capacity(values.len());
+        (0..values.len()).for_each(|i| builder.push(i % 3 != 0 && !(200..300).contains(&i)));
+        let bytes = builder.finish();
+        let validity = Bitmap::new(bytes.as_slice(), 0, values.len());
+        let f = |value: i64| value.abs();
+        for rows in [0..1000, 0..0, 5..13, 130..777] {
+            let all: i64 = values[rows.clone()].iter().map(|v| f(*v)).sum();
+            let valid: i64 = rows
+                .clone()
+                .filter(|i| validity.is_set(*i))
+                .map(|i| f(values[i]))
+                .sum();
+            for strategy in STRATEGIES {
+                assert_eq!(
+                    sum_rows(strategy, &values, None, rows.clone(), &f),
+                    Some(all)
+                );
+                assert_eq!(
+                    sum_rows(strategy, &values, Some(validity), rows.clone(), &f),
+                    Some(valid),
+                    "{strategy:?} of {rows:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn overflows_are_none_with_every_strategy() {
+        let values = vec![i64::MAX / 4; 100];
+        let f = |value: i64| value;
+        let mut builder = BitmapBuilder::with_capacity(values.len());
+        (0..values.len()).for_each(|i| builder.push(i % 2 == 0));
+        let bytes = builder.finish();
+        let validity = Bitmap::new(bytes.as_slice(), 0, values.len());
+        for strategy in STRATEGIES {
+            assert_eq!(sum_rows(strategy, &values, None, 0..100, &f), None);
+            assert_eq!(
+                sum_rows(strategy, &values, Some(validity), 0..100, &f),
+                None
+            );
+            assert_eq!(
+                sum_rows(strategy, &values, None, 0..4, &f),
+                Some(i64::MAX / 4 * 4)
+            );
+            // The nulls are not summed.
+            assert_eq!(
+                sum_rows(strategy, &values, Some(validity), 0..8, &f),
+                Some(i64::MAX / 4 * 4)
+            );
+        }
+    }
+
+    #[test]
+    fn plans_depend_on_the_length_the_nulls_and_the_options() {
+        let options = ArrowUdfExecOptions {
+            num_threads: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        let plan_of = |len, nulls, width, options: &ArrowUdfExecOptions| {
+            let plan = plan(len, nulls, width, options).unwrap();
+            (plan.loop_strategy, plan.threads)
+        };
+        assert_eq!(plan_of(100, 0, 8, &options), (LoopStrategy::Slice, 1));
+        assert_eq!(plan_of(1 << 16, 0, 8, &options), (LoopStrategy::Simd, 1));
+        assert_eq!(plan_of(1 << 20, 0, 8, &options), (LoopStrategy::Simd, 4));
+        // Nulls make the rows more expensive, and narrow values don't make
+        // them cheaper.
+        assert_eq!(plan_of(1 << 16, 1, 8, &options), (LoopStrategy::Simd, 4));
+        assert_eq!(plan_of(1 << 16, 0, 4, &options), (LoopStrategy::Simd, 1));
+        assert_eq!(plan_of(1 << 16, 0, 16, &options), (LoopStrategy::Simd, 4));
+        let small_batches = ArrowUdfExecOptions {
+            batch_size: 64,
+            ..options
+        };
+        assert_eq!(
+            plan_of(1 << 20, 0, 8, &small_batches),
+            (LoopStrategy::Slice, 4)
+        );
+    }
+
+    #[test]
+    fn the_strategy_option_overrides_the_plan() {
+        let with = |strategy| ArrowUdfExecOptions {
+            num_threads: 4,
+            strategy,
+            ..ArrowUdfExecOptions::default()
+        };
+        let serial = plan(
+            1 << 20,
+            0,
+            8,
+            &with(crate::options::ARROW_UDF_STRATEGY_SERIAL),
+        );
+        assert_eq!(serial.unwrap().threads, 1);
+        let parallel = plan(10, 0, 8, &with(crate::options::ARROW_UDF_STRATEGY_PARALLEL));
+        assert_eq!(
+            parallel.unwrap(),
+            Plan {
+                loop_strategy: LoopStrategy::Slice,
+                threads: 4
+            }
+        );
+        let simd = plan(10, 0, 8, &with(crate::options::ARROW_UDF_STRATEGY_SIMD)).unwrap();
+        assert_eq!(simd.loop_strategy, LoopStrategy::Simd);
+        assert_eq!(simd.options(&with(0)).num_threads, 1);
+        assert!(matches!(
+            plan(10, 0, 8, &with(9)),
+            Err(crate::error::Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn compiled_versions_match_the_generic_loops() {
+        let values: Vec<i32> = (0..777).map(|i| i * 31 % 97 - 48).collect();
+        let mut builder = BitmapBuilder::with_capacity(values.len());
+        (0..values.len()).for_each(|i| builder.push(i % 5 != 1));
+        let bytes = builder.finish();
+        let validity = Some(Bitmap::new(bytes.as_slice(), 0, values.len()));
+        let f = |value: i32| value as i64 * 3;
+        let level = crate::cpu::level();
+        for strategy in STRATEGIES {
+            for validity in [None, validity] {
+                let generic = sum_rows_generic(strategy, &values, validity, 3..770, &f);
+                if level >= crate::cpu::Level::Avx2 {
+                    let avx2 = unsafe { sum_rows_avx2(strategy, &values, validity, 3..770, &f) };
+                    assert_eq!(avx2, generic);
+                }
+                if level >= crate::cpu::Level::Avx512 {
+                    let avx512 =
+                        unsafe { sum_rows_avx512(strategy, &values, validity, 3..770, &f) };
+                    assert_eq!(avx512, generic);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inputs_larger_than_the_caches_are_unrolled() {
+        let options = ArrowUdfExecOptions::default();
+        let loop_of = |len, width| plan(len, 0, width, &options).unwrap().loop_strategy;
+        assert_eq!(loop_of(UNROLLED_MIN_BYTES / 8, 8), LoopStrategy::Unrolled);
+        assert_eq!(loop_of(UNROLLED_MIN_BYTES / 8 - 1, 8), LoopStrategy::Simd);
+        assert_eq!(loop_of(UNROLLED_MIN_BYTES / 8, 4), LoopStrategy::Simd);
+        let with_nulls = plan(UNROLLED_MIN_BYTES / 8, 1, 8, &options).unwrap();
+        assert_eq!(with_nulls.loop_strategy, LoopStrategy::Simd);
+        let small_batches = ArrowUdfExecOptions {
+            batch_size: 64,
+            ..options
+        };
+        let plan = plan(UNROLLED_MIN_BYTES, 0, 8, &small_batches).unwrap();
+        assert_eq!(plan.loop_strategy, LoopStrategy::Slice);
+    }
+}
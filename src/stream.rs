@@ -0,0 +1,502 @@
+//! Consumption of the streams of arrays received through the C Stream
+//! Interface, and export of the streams produced by the library.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+
+use crate::alias;
+use crate::array::ArrowArray;
+use crate::error::{arrow_udf_last_error, ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{
+    ArrowCDataInterfaceArray, ArrowCDataInterfaceArrayStream, ArrowCDataInterfaceSchema,
+};
+use crate::schema::Schema;
+
+/// Stream imported from the C Stream Interface. It's released when dropped.
+pub struct ArrayStream {
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    schema: Schema,
+    /// Whether `stream` was moved by `move_from_ffi` into a box of its own.
+    moved: bool,
+}
+
+impl ArrayStream {
+    /// Take ownership of `stream`, and import its schema.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must point to a valid, non released, C Stream Interface
+    /// stream, not used by anyone else.
+    pub unsafe fn from_ffi(stream: *mut ArrowCDataInterfaceArrayStream) -> Result<ArrayStream> {
+        if stream.is_null() || (*stream).release.is_none() {
+            return Err(Error::InvalidArgument("the stream is released".to_string()));
+        }
+        match import_schema(stream) {
+            Ok(schema) => Ok(ArrayStream {
+                stream,
+                schema,
+                moved: false,
+            }),
+            Err(err) => {
+                (*stream).release.unwrap()(stream);
+                Err(err)
+            }
+        }
+    }
+
+    /// Take ownership of `stream` like `from_ffi`, moving it out of the
+    /// struct of the host, which is marked as released, so the stream can be
+    /// used after the struct is freed.
+    ///
+    /// # Safety
+    ///
+    /// `stream` must point to a valid C Stream Interface stream, not used by
+    /// anyone else.
+    pub unsafe fn move_from_ffi(
+        stream: *mut ArrowCDataInterfaceArrayStream,
+    ) -> Result<ArrayStream> {
+        if stream.is_null() || (*stream).release.is_none() {
+            return Err(Error::InvalidArgument("the stream is released".to_string()));
+        }
+        let moved = Box::into_raw(Box::new(ptr::read(stream)));
+        (*stream).release = None;
+        match ArrayStream::from_ffi(moved) {
+            Ok(mut stream) => {
+                stream.moved = true;
+                Ok(stream)
+            }
+            Err(err) => {
+                drop(Box::from_raw(moved));
+                Err(err)
+            }
+        }
+    }
+
+    /// Schema of all the arrays of the stream.
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Call `f` with every remaining array of the stream, in order. The
+    /// arrays are released after `f` returns, and the first error stops the
+    /// consumption of the stream.
+    pub fn for_each<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&ArrowArray) -> Result<()>,
+    {
+        while let Some(array) = self.next_array()? {
+            f(&array.view(&self.schema))?;
+        }
+        Ok(())
+    }
+
+    /// The next array of the stream, or `None` at its end.
+    pub fn next_array(&mut self) -> Result<Option<ImportedArray>> {
+        unsafe {
+            let get_next = (*self.stream).get_next.unwrap();
+            let mut array = ArrowCDataInterfaceArray::empty();
+            let code = get_next(self.stream, &mut array);
+            if code != 0 {
+                return Err(stream_error(self.stream, code));
+            }
+            // A released array marks the end of the stream.
+            if array.release.is_none() {
+                return Ok(None);
+            }
+            let array = ImportedArray { array };
+            array.import(&self.schema)?;
+            Ok(Some(array))
+        }
+    }
+
+    /// Read all the remaining arrays of the stream, keeping them alive, to
+    /// be used as the chunks of a `ChunkedArray` with the schema of the
+    /// stream.
+    pub fn read_all(&mut self) -> Result<Vec<ImportedArray>> {
+        let mut arrays = Vec::new();
+        while let Some(array) = self.next_array()? {
+            arrays.push(array);
+        }
+        Ok(arrays)
+    }
+}
+
+/// Array received from a stream, or moved from the host, released when
+/// dropped.
+pub struct ImportedArray {
+    array: ArrowCDataInterfaceArray,
+}
+
+// The C Data Interface allows releasing arrays from any thread.
+unsafe impl Send for ImportedArray {}
+unsafe impl Sync for ImportedArray {}
+
+impl ImportedArray {
+    /// Take ownership of `array`, marking it as released, so the host
+    /// doesn't release it again.
+    ///
+    /// # Safety
+    ///
+    /// `array` must point to a valid C Data Interface array, not used by
+    /// anyone else.
+    pub unsafe fn move_from_ffi(array: *mut ArrowCDataInterfaceArray) -> Result<ImportedArray> {
+        if array.is_null() {
+            return Err(Error::InvalidArgument("the array is released".to_string()));
+        }
+        alias::claim(array, true)?;
+        if (*array).release.is_none() {
+            return Err(Error::InvalidArgument("the array is released".to_string()));
+        }
+        let moved = ptr::read(array);
+        (*array).release = None;
+        Ok(ImportedArray { array: moved })
+    }
+
+    /// View of the array, with the schema of the stream it comes from.
+    pub fn view<'a>(&'a self, schema: &'a Schema) -> ArrowArray<'a> {
+        unsafe { ArrowArray::new(schema, &self.array) }
+    }
+
+    /// View of the array like `view`, validated like the arrays of
+    /// `ArrowArray::import`.
+    pub fn import<'a>(&'a self, schema: &'a Schema) -> Result<ArrowArray<'a>> {
+        self.view(schema).validated()
+    }
+}
+
+impl From<ArrayData> for ImportedArray {
+    /// Export `data`, to read it like an array received from a stream.
+    fn from(data: ArrayData) -> ImportedArray {
+        ImportedArray {
+            array: export::export_array(data),
+        }
+    }
+}
+
+impl Drop for ImportedArray {
+    fn drop(&mut self) {
+        if let Some(release) = self.array.release {
+            unsafe { release(&mut self.array) };
+        }
+    }
+}
+
+unsafe fn import_schema(stream: *mut ArrowCDataInterfaceArrayStream) -> Result<Schema> {
+    let (Some(get_schema), Some(_)) = ((*stream).get_schema, (*stream).get_next) else {
+        return Err(Error::InvalidArgument("invalid stream".to_string()));
+    };
+    let mut ffi_schema = ArrowCDataInterfaceSchema::empty();
+    let code = get_schema(stream, &mut ffi_schema);
+    if code != 0 {
+        return Err(stream_error(stream, code));
+    }
+    let schema = Schema::from_ffi(&ffi_schema);
+    if let Some(release) = ffi_schema.release {
+        release(&mut ffi_schema);
+    }
+    schema
+}
+
+unsafe fn stream_error(stream: *mut ArrowCDataInterfaceArrayStream, code: i32) -> Error {
+    let message = (*stream)
+        .get_last_error
+        .map(|get_last_error| get_last_error(stream))
+        .filter(|message| !message.is_null())
+        .map(|message| CStr::from_ptr(message).to_string_lossy().into_owned());
+    Error::InvalidArgument(match message {
+        Some(message) => format!("the stream failed: {message}"),
+        None => format!("the stream failed with error code {code}"),
+    })
+}
+
+impl Drop for ArrayStream {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(release) = (*self.stream).release {
+                release(self.stream);
+            }
+            if self.moved {
+                drop(Box::from_raw(self.stream));
+            }
+        }
+    }
+}
+
+/// Producer of the arrays of a stream exported by the library.
+pub trait ArraySource {
+    /// Schema of all the arrays.
+    fn schema(&self) -> &Schema;
+
+    /// The next array, or `None` at the end of the stream.
+    fn next(&mut self) -> Result<Option<ArrayData>>;
+}
+
+/// Error codes returned by `get_next`, from `errno.h`.
+const EIO: i32 = 5;
+const ENOMEM: i32 = 12;
+const EINVAL: i32 = 22;
+
+struct ExportedStream {
+    source: Box<dyn ArraySource>,
+    last_error: Option<CString>,
+}
+
+/// Export the arrays of `source` as a C Stream Interface stream. Every call
+/// to `get_next` runs like an entry point, with its own memory tracker and
+/// catching panics, and the description of its error is returned by
+/// `get_last_error`.
+///
+/// # Safety
+///
+/// `out` must be valid for writes.
+pub unsafe fn export_stream(
+    source: Box<dyn ArraySource>,
+    out: *mut ArrowCDataInterfaceArrayStream,
+) {
+    let private_data = Box::new(ExportedStream {
+        source,
+        last_error: None,
+    });
+    out.write(ArrowCDataInterfaceArrayStream {
+        get_schema: Some(get_schema),
+        get_next: Some(get_next),
+        get_last_error: Some(get_last_error),
+        release: Some(release_stream),
+        private_data: Box::into_raw(private_data) as *mut c_void,
+    });
+}
+
+unsafe extern "C" fn get_schema(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    out: *mut ArrowCDataInterfaceSchema,
+) -> i32 {
+    let exported = &*((*stream).private_data as *const ExportedStream);
+    out.write(export::export_schema(exported.source.schema()));
+    0
+}
+
+unsafe extern "C" fn get_next(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    out: *mut ArrowCDataInterfaceArray,
+) -> i32 {
+    let exported = &mut *((*stream).private_data as *mut ExportedStream);
+    let mut next = None;
+    let status = ffi_guard(|| {
+        next = exported.source.next()?;
+        Ok(())
+    });
+    let code = match status {
+        ArrowUdfStatus::Ok => {
+            out.write(next.map_or(ArrowCDataInterfaceArray::empty(), export::export_array));
+            return 0;
+        }
+        ArrowUdfStatus::OutOfBudget => ENOMEM,
+        ArrowUdfStatus::Panic | ArrowUdfStatus::Cancelled | ArrowUdfStatus::Io => EIO,
+        _ => EINVAL,
+    };
+    exported.last_error = Some(CStr::from_ptr(arrow_udf_last_error()).to_owned());
+    code
+}
+
+unsafe extern "C" fn get_last_error(stream: *mut ArrowCDataInterfaceArrayStream) -> *const c_char {
+    let exported = &*((*stream).private_data as *const ExportedStream);
+    exported
+        .last_error
+        .as_ref()
+        .map_or(ptr::null(), |message| message.as_ptr())
+}
+
+unsafe extern "C" fn release_stream(stream: *mut ArrowCDataInterfaceArrayStream) {
+    if stream.is_null() || (*stream).release.is_none() {
+        return;
+    }
+    drop(Box::from_raw((*stream).private_data as *mut ExportedStream));
+    (*stream).release = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked::ChunkedArray;
+    use crate::schema::ArrowType;
+    use crate::testing::{self, Exported};
+
+    fn batches() -> Vec<Exported> {
+        vec![
+            Exported::primitive(&[1_i64, 2]),
+            Exported::primitive::<i64>(&[]),
+            Exported::primitive(&[3_i64]),
+        ]
+    }
+
+    #[test]
+    fn arrays_are_consumed_in_order() {
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let mut ffi = testing::stream(&schema, batches(), None);
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut ffi) }.unwrap();
+        assert_eq!(stream.schema(), &schema);
+        let mut values = Vec::new();
+        stream
+            .for_each(|array| {
+                values.extend_from_slice(array.values::<i64>());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(values, [1, 2, 3]);
+        drop(stream);
+        assert!(ffi.release.is_none());
+    }
+
+    #[test]
+    fn errors_stop_the_stream() {
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let mut ffi = testing::stream(&schema, batches(), Some(1));
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut ffi) }.unwrap();
+        let mut calls = 0;
+        let result = stream.for_each(|_| {
+            calls += 1;
+            Ok(())
+        });
+        assert_eq!(calls, 1);
+        assert_eq!(
+            result,
+            Err(Error::InvalidArgument(
+                "the stream failed: batch not available".to_string()
+            ))
+        );
+        let mut ffi = testing::stream(&schema, batches(), None);
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut ffi) }.unwrap();
+        assert_eq!(
+            stream.for_each(|_| Err(Error::NullValue)),
+            Err(Error::NullValue)
+        );
+    }
+
+    #[test]
+    fn read_all_keeps_the_arrays() {
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let mut ffi = testing::stream(&schema, batches(), None);
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut ffi) }.unwrap();
+        let arrays = stream.read_all().unwrap();
+        let views = arrays.iter().map(|array| array.view(stream.schema()));
+        let chunked = ChunkedArray::new(stream.schema(), views.collect()).unwrap();
+        let values: Vec<_> = chunked.iter::<i64>().collect();
+        assert_eq!(values, [Some(1), Some(2), Some(3)]);
+        let mut ffi = testing::stream(&schema, batches(), Some(2));
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut ffi) }.unwrap();
+        assert!(matches!(stream.read_all(), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn released_streams_are_rejected() {
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let mut ffi = testing::stream(&schema, Vec::new(), None);
+        unsafe { ffi.release.unwrap()(&mut ffi) };
+        assert!(unsafe { ArrayStream::from_ffi(&mut ffi) }.is_err());
+        assert!(unsafe { ArrayStream::from_ffi(std::ptr::null_mut()) }.is_err());
+    }
+
+    #[test]
+    fn exported_data_reads_like_a_received_array() {
+        let values: crate::buffer::Buffer = [4_i64, 5, 6].into_iter().collect();
+        let array = ImportedArray::from(ArrayData::primitive(values, 3));
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let view = array.view(&schema);
+        assert_eq!(view.values::<i64>(), [4, 5, 6]);
+        assert!(view.is_valid(2));
+    }
+
+    /// Source of the integers in `batches`, failing after them with `error`.
+    struct Batches {
+        schema: Schema,
+        batches: std::vec::IntoIter<Vec<i64>>,
+        error: Option<Error>,
+    }
+
+    impl ArraySource for Batches {
+        fn schema(&self) -> &Schema {
+            &self.schema
+        }
+
+        fn next(&mut self) -> Result<Option<ArrayData>> {
+            match self.batches.next() {
+                Some(values) => {
+                    let len = values.len();
+                    Ok(Some(ArrayData::primitive(
+                        values.into_iter().collect(),
+                        len,
+                    )))
+                }
+                None => self.error.take().map_or(Ok(None), Err),
+            }
+        }
+    }
+
+    fn exported(batches: Vec<Vec<i64>>, error: Option<Error>) -> ArrowCDataInterfaceArrayStream {
+        let source = Batches {
+            schema: Schema::new(ArrowType::Int64, "x"),
+            batches: batches.into_iter(),
+            error,
+        };
+        let mut out = std::mem::MaybeUninit::uninit();
+        unsafe {
+            export_stream(Box::new(source), out.as_mut_ptr());
+            out.assume_init()
+        }
+    }
+
+    #[test]
+    fn exported_streams_round_trip() {
+        let mut ffi = exported(vec![vec![1, 2], vec![], vec![3]], None);
+        let mut stream = unsafe { ArrayStream::from_ffi(&mut ffi) }.unwrap();
+        assert_eq!(stream.schema(), &Schema::new(ArrowType::Int64, "x"));
+        let mut values = Vec::new();
+        stream
+            .for_each(|array| {
+                values.extend_from_slice(array.values::<i64>());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(values, [1, 2, 3]);
+        assert!(stream.next_array().unwrap().is_none());
+        drop(stream);
+        assert!(ffi.release.is_none());
+    }
+
+    #[test]
+    fn errors_of_exported_streams_are_described() {
+        for (error, code) in [
+            (Error::OutOfBudget, ENOMEM),
+            (Error::Io("disk full".to_string()), EIO),
+            (Error::InvalidArgument("bad batch".to_string()), EINVAL),
+        ] {
+            let message = error.to_string();
+            let mut ffi = exported(vec![vec![1]], Some(error));
+            unsafe {
+                let get_next = ffi.get_next.unwrap();
+                let mut array = ArrowCDataInterfaceArray::empty();
+                assert_eq!(get_next(&mut ffi, &mut array), 0);
+                array.release.unwrap()(&mut array);
+                assert_eq!(get_next(&mut ffi, &mut array), code);
+                let last_error = CStr::from_ptr(ffi.get_last_error.unwrap()(&mut ffi));
+                assert_eq!(last_error.to_str().unwrap(), message);
+                ffi.release.unwrap()(&mut ffi);
+            }
+        }
+    }
+
+    #[test]
+    fn moved_streams_outlive_the_struct_of_the_host() {
+        let schema = Schema::new(ArrowType::Int64, "x");
+        let mut ffi = Box::new(testing::stream(&schema, batches(), None));
+        let mut stream = unsafe { ArrayStream::move_from_ffi(&mut *ffi) }.unwrap();
+        assert!(ffi.release.is_none());
+        drop(ffi);
+        let arrays = stream.read_all().unwrap();
+        assert_eq!(arrays.len(), 3);
+        let mut released = testing::stream(&schema, Vec::new(), None);
+        unsafe { released.release.unwrap()(&mut released) };
+        assert!(unsafe { ArrayStream::move_from_ffi(&mut released) }.is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! T-digest sketches, used to approximate quantiles with bounded memory.
+//!
+//! This is the merging t-digest, as described in "Computing Extremely
+//! Accurate Quantiles Using t-Digests" by Ted Dunning and Otmar Ertl. Values
+//! are buffered, and when the buffer is full, they are merged with the
+//! existing centroids, limiting the weight of each centroid with the scale
+//! function `k(q) = δ / 2π · asin(2q - 1)`, where `δ` is the compression.
+//! Centroids near the extremes are kept small, so the tails are accurate.
+
+use std::ops::Range;
+
+use crate::aggregate::{self, Accumulator, Number};
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::schema::ArrowType;
+
+/// Compression used when the spec doesn't specify it.
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// Sketch of the distribution of a set of values.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    total_weight: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> TDigest {
+        TDigest {
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            total_weight: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_weight == 0.0
+    }
+
+    /// Add a value to the sketch. NaN values are ignored.
+    pub fn add(&mut self, value: f64) {
+        if !value.is_nan() {
+            self.add_centroid(Centroid {
+                mean: value,
+                weight: 1.0,
+            });
+        }
+    }
+
+    fn add_centroid(&mut self, centroid: Centroid) {
+        self.min = self.min.min(centroid.mean);
+        self.max = self.max.max(centroid.mean);
+        self.total_weight += centroid.weight;
+        self.buffer.push(centroid);
+        if self.buffer.len() as f64 >= 5.0 * self.compression {
+            self.compress();
+        }
+    }
+
+    /// Add all the values summarized by `other` to the sketch.
+    pub fn merge(&mut self, other: &TDigest) {
+        for centroid in other.centroids.iter().chain(&other.buffer) {
+            self.add_centroid(*centroid);
+        }
+    }
+
+    fn k(&self, q: f64) -> f64 {
+        self.compression / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin()
+    }
+
+    /// Merge the buffered values with the centroids.
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut centroids = std::mem::take(&mut self.buffer);
+        centroids.append(&mut self.centroids);
+        centroids.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let mut merged: Vec<Centroid> = Vec::with_capacity(centroids.len());
+        let mut weight_so_far = 0.0;
+        let mut limit = self.k(0.0) + 1.0;
+        for centroid in centroids {
+            let last = merged.last_mut();
+            match last {
+                Some(last)
+                    if self
+                        .k((weight_so_far + last.weight + centroid.weight) / self.total_weight)
+                        <= limit =>
+                {
+                    last.weight += centroid.weight;
+                    last.mean += (centroid.mean - last.mean) * centroid.weight / last.weight;
+                }
+                _ => {
+                    if let Some(last) = merged.last() {
+                        weight_so_far += last.weight;
+                        limit = self.k(weight_so_far / self.total_weight) + 1.0;
+                    }
+                    merged.push(centroid);
+                }
+            }
+        }
+        self.centroids = merged;
+    }
+
+    /// Estimate the value at quantile `q`, between 0 and 1, interpolating
+    /// between the centers of the centroids. `None` if the sketch is empty.
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.compress();
+        if self.is_empty() {
+            return None;
+        }
+        let target = q.clamp(0.0, 1.0) * self.total_weight;
+        // Position of the center of each centroid, with the minimum and the
+        // maximum at both ends.
+        let mut previous = (0.0, self.min);
+        let mut cumulative = 0.0;
+        for centroid in &self.centroids {
+            let center = (cumulative + centroid.weight / 2.0, centroid.mean);
+            if target < center.0 {
+                return Some(interpolate(previous, center, target));
+            }
+            cumulative += centroid.weight;
+            previous = center;
+        }
+        Some(interpolate(previous, (self.total_weight, self.max), target))
+    }
+
+    /// Serialize the sketch, to be restored with `TDigest::deserialize`.
+    pub fn serialize(&mut self, out: &mut Vec<u8>) {
+        self.compress();
+        out.extend_from_slice(&self.compression.to_le_bytes());
+        out.extend_from_slice(&self.total_weight.to_le_bytes());
+        out.extend_from_slice(&self.min.to_le_bytes());
+        out.extend_from_slice(&self.max.to_le_bytes());
+        out.extend_from_slice(&(self.centroids.len() as u64).to_le_bytes());
+        for centroid in &self.centroids {
+            out.extend_from_slice(&centroid.mean.to_le_bytes());
+            out.extend_from_slice(&centroid.weight.to_le_bytes());
+        }
+    }
+
+    pub fn deserialize(state: &mut aggregate::StateReader) -> Result<TDigest> {
+        let mut digest = TDigest::new(state.f64()?);
+        digest.total_weight = state.f64()?;
+        digest.min = state.f64()?;
+        digest.max = state.f64()?;
+        let n = state.u64()?;
+        for _ in 0..n {
+            digest.centroids.push(Centroid {
+                mean: state.f64()?,
+                weight: state.f64()?,
+            });
+        }
+        Ok(digest)
+    }
+}
+
+fn interpolate((x0, y0): (f64, f64), (x1, y1): (f64, f64), x: f64) -> f64 {
+    if x1 <= x0 {
+        return y1;
+    }
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
+/// Accumulator of `approx_quantile(q[, compression])`.
+pub(crate) fn accumulator(data_type: ArrowType, args: &[f64]) -> Result<Box<dyn Accumulator>> {
+    let (quantile, compression) = match *args {
+        [quantile] => (quantile, DEFAULT_COMPRESSION),
+        [quantile, compression] => (quantile, compression),
+        _ => {
+            return Err(Error::InvalidArgument(
+                "approx_quantile expects the quantile and optionally the compression".to_string(),
+            ))
+        }
+    };
+    if !(0.0..=1.0).contains(&quantile) {
+        return Err(Error::InvalidArgument(format!(
+            "quantile must be between 0 and 1, got {quantile}"
+        )));
+    }
+    if !(10.0..=10_000.0).contains(&compression) {
+        return Err(Error::InvalidArgument(format!(
+            "compression must be between 10 and 10000, got {compression}"
+        )));
+    }
+    match data_type {
+        ArrowType::Int64 => Ok(Box::new(Quantile::<i64>::new(quantile, compression))),
+        ArrowType::Float64 => Ok(Box::new(Quantile::<f64>::new(quantile, compression))),
+        other => Err(aggregate::unsupported("approx_quantile", other)),
+    }
+}
+
+struct Quantile<T> {
+    quantile: f64,
+    compression: f64,
+    digests: Vec<TDigest>,
+    _type: std::marker::PhantomData<T>,
+}
+
+impl<T> Quantile<T> {
+    fn new(quantile: f64, compression: f64) -> Quantile<T> {
+        Quantile {
+            quantile,
+            compression,
+            digests: Vec::new(),
+            _type: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Number> Accumulator for Quantile<T> {
+    fn output_type(&self) -> ArrowType {
+        ArrowType::Float64
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.digests
+            .resize(n_groups, TDigest::new(self.compression));
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        aggregate::for_each_valid(values, rows, groups, |group, value: T| {
+            self.digests[group].add(value.to_f64())
+        });
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        aggregate::for_each_state(states, rows, groups, |group, mut state| {
+            let digest = TDigest::deserialize(&mut state)?;
+            self.digests[group].merge(&digest);
+            Ok(())
+        })
+    }
+
+    fn state(mut self: Box<Self>) -> ArrayData {
+        aggregate::states_array(self.digests.len(), |group, state| {
+            self.digests[group].serialize(state)
+        })
+    }
+
+    fn finish(mut self: Box<Self>) -> ArrayData {
+        let quantile = self.quantile;
+        let (values, counts): (Vec<f64>, Vec<i64>) = self
+            .digests
+            .iter_mut()
+            .map(|digest| match digest.quantile(quantile) {
+                Some(value) => (value, 1),
+                None => (0.0, 0),
+            })
+            .unzip();
+        aggregate::nullable_result(&values, &counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(values: impl IntoIterator<Item = f64>) -> TDigest {
+        let mut digest = TDigest::new(DEFAULT_COMPRESSION);
+        values.into_iter().for_each(|value| digest.add(value));
+        digest
+    }
+
+    #[test]
+    fn quantiles_are_close_to_the_exact_ones() {
+        let mut uniform = digest((0..10_000).map(|i| ((i * 7919) % 10_000) as f64));
+        assert_eq!(uniform.quantile(0.0), Some(0.0));
+        assert_eq!(uniform.quantile(1.0), Some(9999.0));
+        for q in [0.01, 0.25, 0.5, 0.9, 0.999] {
+            let estimate = uniform.quantile(q).unwrap();
+            assert!((estimate - q * 9999.0).abs() < 20.0, "{q}: {estimate}");
+        }
+        assert_eq!(TDigest::new(50.0).quantile(0.5), None);
+        assert_eq!(digest([f64::NAN, 2.0]).quantile(0.5), Some(2.0));
+    }
+
+    #[test]
+    fn merged_digests_match_a_single_one() {
+        let mut merged = digest((0..5000).map(f64::from));
+        merged.merge(&digest((5000..10_000).map(f64::from)));
+        merged.merge(&TDigest::new(DEFAULT_COMPRESSION));
+        let median = merged.quantile(0.5).unwrap();
+        assert!((median - 5000.0).abs() < 20.0, "{median}");
+    }
+
+    #[test]
+    fn serialized_digests_round_trip() {
+        let mut original = digest((0..1000).map(|i| f64::from(i).sqrt()));
+        let mut bytes = Vec::new();
+        original.serialize(&mut bytes);
+        let mut restored = TDigest::deserialize(&mut aggregate::StateReader::new(&bytes)).unwrap();
+        for q in [0.0, 0.3, 0.75, 1.0] {
+            assert_eq!(restored.quantile(q), original.quantile(q));
+        }
+        let truncated = &bytes[..bytes.len() - 4];
+        assert!(TDigest::deserialize(&mut aggregate::StateReader::new(truncated)).is_err());
+    }
+
+    #[test]
+    fn arguments_are_validated() {
+        assert!(accumulator(ArrowType::Float64, &[0.5]).is_ok());
+        assert!(accumulator(ArrowType::Int64, &[0.5, 20.0]).is_ok());
+        for args in [&[][..], &[1.5], &[0.5, 5.0], &[0.5, 100.0, 1.0]] {
+            assert!(matches!(
+                accumulator(ArrowType::Float64, args),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        assert!(matches!(
+            accumulator(ArrowType::Int32, &[0.5]),
+            Err(Error::UnsupportedType(_))
+        ));
+    }
+}
@@ -0,0 +1,1290 @@
+//! Date and time kernels over Date32, Date64 and Timestamp arrays, and the
+//! Interval and Duration arrays added to them or converted.
+//!
+//! Timestamps are stored in UTC, and converted to the timezone recorded in
+//! their format string before truncating them, extracting their fields or
+//! adding calendar intervals, so that days start at midnight in that
+//! timezone, and adding a day across a daylight saving time change keeps
+//! the time of the day. Dates, and timestamps without a timezone, are
+//! computed in UTC.
+//!
+//! Timezones can be `UTC` or fixed offsets like `+01:00`. Names from the
+//! IANA database, like `Europe/Paris`, require the `tz` feature.
+//!
+//! Intervals have the semantics of the Arrow month-day-nano interval: months
+//! and days are calendar units, whose length depends on the date they are
+//! added to, and nanoseconds are elapsed time. Year-month intervals only have
+//! months, and day-time intervals days and milliseconds.
+//!
+//! Elements whose result is out of range fail the call, or are null when
+//! the host collects the errors of the rows, see `udf::RowErrors`.
+
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, LocalResult, Months, NaiveDate, NaiveDateTime,
+    NaiveTime, Offset, TimeDelta, TimeZone, Timelike,
+};
+
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, IntervalUnit, Schema, TimeUnit};
+use crate::udf::{self, RowErrors};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// Interval added by `arrow_udf_timestamp_add`, with the same fields as the
+/// Arrow month-day-nano interval type. Months and days are calendar units,
+/// added in the timezone of the timestamps, and nanoseconds are elapsed time.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArrowUdfInterval {
+    pub months: i32,
+    pub days: i32,
+    pub nanoseconds: i64,
+}
+
+impl ArrowUdfInterval {
+    /// The interval with its fields negated, to subtract it.
+    fn checked_neg(self) -> Option<ArrowUdfInterval> {
+        Some(ArrowUdfInterval {
+            months: self.months.checked_neg()?,
+            days: self.days.checked_neg()?,
+            nanoseconds: self.nanoseconds.checked_neg()?,
+        })
+    }
+}
+
+/// Timezone of the values of an array.
+#[derive(Clone, Copy)]
+enum Zone {
+    Fixed(FixedOffset),
+    #[cfg(feature = "tz")]
+    Named(chrono_tz::Tz),
+}
+
+impl Zone {
+    fn parse(timezone: Option<&str>) -> Result<Zone> {
+        let utc = Zone::Fixed(FixedOffset::east_opt(0).unwrap());
+        let timezone = match timezone {
+            None | Some("UTC") | Some("Z") => return Ok(utc),
+            Some(timezone) => timezone,
+        };
+        if let Ok(offset) = timezone.parse::<FixedOffset>() {
+            return Ok(Zone::Fixed(offset));
+        }
+        #[cfg(feature = "tz")]
+        if let Ok(tz) = timezone.parse::<chrono_tz::Tz>() {
+            return Ok(Zone::Named(tz));
+        }
+        Err(Error::UnsupportedType(if cfg!(feature = "tz") {
+            format!("timezone {timezone:?}")
+        } else {
+            format!("timezone {timezone:?}, named timezones require the tz feature")
+        }))
+    }
+
+    fn to_local(self, utc: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Zone::Fixed(tz) => tz.from_utc_datetime(&utc).naive_local(),
+            #[cfg(feature = "tz")]
+            Zone::Named(tz) => tz.from_utc_datetime(&utc).naive_local(),
+        }
+    }
+
+    fn to_utc(self, local: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Zone::Fixed(tz) => local_to_utc(&tz, local),
+            #[cfg(feature = "tz")]
+            Zone::Named(tz) => local_to_utc(&tz, local),
+        }
+    }
+}
+
+/// The UTC time of a local time. Local times that happen twice, when clocks
+/// go back, are the first one, and local times that don't exist, when clocks
+/// go forward, use the offset before the change, like most databases.
+fn local_to_utc<Z: TimeZone>(tz: &Z, local: NaiveDateTime) -> NaiveDateTime {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(datetime) | LocalResult::Ambiguous(datetime, _) => datetime.naive_utc(),
+        LocalResult::None => {
+            let before = local.checked_sub_days(Days::new(1)).unwrap_or(local);
+            let offset = tz.offset_from_utc_datetime(&before).fix();
+            local - TimeDelta::seconds(offset.local_minus_utc() as i64)
+        }
+    }
+}
+
+/// How the values of a temporal array represent a point in time.
+#[derive(Clone, Copy)]
+enum Temporal {
+    Date32,
+    Date64,
+    Timestamp(TimeUnit),
+}
+
+impl Temporal {
+    fn of(data_type: ArrowType) -> Option<Temporal> {
+        match data_type {
+            ArrowType::Date32 => Some(Temporal::Date32),
+            ArrowType::Date64 => Some(Temporal::Date64),
+            ArrowType::Timestamp(unit) => Some(Temporal::Timestamp(unit)),
+            _ => None,
+        }
+    }
+
+    fn per_second(self) -> i64 {
+        match self {
+            Temporal::Date32 => 1,
+            Temporal::Date64 => 1_000,
+            Temporal::Timestamp(unit) => unit.per_second(),
+        }
+    }
+
+    fn decode(self, value: i64) -> Option<NaiveDateTime> {
+        let (seconds, nanos) = match self {
+            Temporal::Date32 => (value.checked_mul(SECONDS_PER_DAY)?, 0),
+            _ => {
+                let per_second = self.per_second();
+                let nanos = value.rem_euclid(per_second) * (NANOS_PER_SECOND / per_second);
+                (value.div_euclid(per_second), nanos as u32)
+            }
+        };
+        DateTime::from_timestamp(seconds, nanos).map(|datetime| datetime.naive_utc())
+    }
+
+    /// The value of a UTC time. Dates discard the time of the day, and all
+    /// the types discard the precision they can't represent.
+    fn encode(self, utc: NaiveDateTime) -> Option<i64> {
+        let utc = utc.and_utc();
+        match self {
+            Temporal::Date32 => Some(utc.timestamp().div_euclid(SECONDS_PER_DAY)),
+            Temporal::Date64 => utc
+                .date_naive()
+                .and_time(NaiveTime::MIN)
+                .and_utc()
+                .timestamp()
+                .checked_mul(1_000),
+            Temporal::Timestamp(unit) => {
+                let per_second = unit.per_second();
+                let nanos = utc.timestamp_subsec_nanos() as i64;
+                utc.timestamp()
+                    .checked_mul(per_second)?
+                    .checked_add(nanos / (NANOS_PER_SECOND / per_second))
+            }
+        }
+    }
+}
+
+fn out_of_range() -> Error {
+    Error::InvalidArgument("date or time out of range".to_string())
+}
+
+/// Units `arrow_udf_date_trunc` can truncate to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TruncUnit {
+    Year,
+    Quarter,
+    Month,
+    /// Weeks start on Monday.
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl TruncUnit {
+    pub fn from_name(name: &str) -> Result<TruncUnit> {
+        Ok(match name {
+            "year" => TruncUnit::Year,
+            "quarter" => TruncUnit::Quarter,
+            "month" => TruncUnit::Month,
+            "week" => TruncUnit::Week,
+            "day" => TruncUnit::Day,
+            "hour" => TruncUnit::Hour,
+            "minute" => TruncUnit::Minute,
+            "second" => TruncUnit::Second,
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown unit {name:?}, expected year, quarter, month, week, day, hour, \
+                     minute or second"
+                )))
+            }
+        })
+    }
+
+    fn truncate(self, local: NaiveDateTime) -> Option<NaiveDateTime> {
+        let date = local.date();
+        let first_of_month = |month| NaiveDate::from_ymd_opt(date.year(), month, 1);
+        let start = match self {
+            TruncUnit::Year => first_of_month(1)?,
+            TruncUnit::Quarter => first_of_month((date.month0() / 3) * 3 + 1)?,
+            TruncUnit::Month => first_of_month(date.month())?,
+            TruncUnit::Week => {
+                date.checked_sub_days(Days::new(date.weekday().num_days_from_monday() as u64))?
+            }
+            TruncUnit::Day => date,
+            TruncUnit::Hour => return date.and_hms_opt(local.hour(), 0, 0),
+            TruncUnit::Minute => return date.and_hms_opt(local.hour(), local.minute(), 0),
+            TruncUnit::Second => return local.with_nanosecond(0),
+        };
+        Some(start.and_time(NaiveTime::MIN))
+    }
+}
+
+/// Fields `arrow_udf_extract` can extract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Year,
+    /// From 1 to 4.
+    Quarter,
+    /// From 1 to 12.
+    Month,
+    /// ISO 8601 week number, from 1 to 53.
+    Week,
+    /// Day of the month, from 1 to 31.
+    Day,
+    /// From 0 for Monday to 6 for Sunday.
+    DayOfWeek,
+    /// From 1 to 366.
+    DayOfYear,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Field {
+    pub fn from_name(name: &str) -> Result<Field> {
+        Ok(match name {
+            "year" => Field::Year,
+            "quarter" => Field::Quarter,
+            "month" => Field::Month,
+            "week" => Field::Week,
+            "day" => Field::Day,
+            "day_of_week" => Field::DayOfWeek,
+            "day_of_year" => Field::DayOfYear,
+            "hour" => Field::Hour,
+            "minute" => Field::Minute,
+            "second" => Field::Second,
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown field {name:?}, expected year, quarter, month, week, day, \
+                     day_of_week, day_of_year, hour, minute or second"
+                )))
+            }
+        })
+    }
+
+    fn extract(self, local: NaiveDateTime) -> i64 {
+        (match self {
+            Field::Year => return local.year() as i64,
+            Field::Quarter => local.month0() / 3 + 1,
+            Field::Month => local.month(),
+            Field::Week => local.iso_week().week(),
+            Field::Day => local.day(),
+            Field::DayOfWeek => local.weekday().num_days_from_monday(),
+            Field::DayOfYear => local.ordinal(),
+            Field::Hour => local.hour(),
+            Field::Minute => local.minute(),
+            Field::Second => local.second(),
+        }) as i64
+    }
+}
+
+/// How the values of a temporal array are read, and the timezone they are
+/// computed in, after checking the type of the array and the null policy.
+fn temporal_input(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(Temporal, Zone)> {
+    let temporal = Temporal::of(array.data_type()).ok_or_else(|| {
+        Error::UnsupportedType(format!(
+            "expected a date or timestamp input, got {:?}",
+            array.data_type()
+        ))
+    })?;
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    Ok((temporal, Zone::parse(array.schema().timezone())?))
+}
+
+/// Apply `f` to the position and the local date and time of every element
+/// of a temporal array. The values of null elements, and of the elements
+/// whose errors are collected in `errors`, are left as 0.
+fn map_temporal<F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    errors: &RowErrors,
+    f: F,
+) -> Result<Vec<i64>>
+where
+    F: Fn(usize, NaiveDateTime) -> Result<i64> + Sync,
+{
+    let (temporal, zone) = temporal_input(array, options)?;
+    let values = match temporal {
+        Temporal::Date32 => array.values::<i32>().iter().map(|v| *v as i64).collect(),
+        _ => array.values::<i64>().to_vec(),
+    };
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let mut out = vec![0; array.len()];
+    exec::map(&mut out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            if validity.is_none_or(|validity| validity.is_set(i)) {
+                let utc = temporal.decode(values[i]).ok_or_else(out_of_range);
+                if let Some(value) =
+                    errors.check(i, utc.and_then(|utc| f(i, zone.to_local(utc))))?
+                {
+                    *out = value;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(out)
+}
+
+/// Apply `f` to the position and the local date and time of every element of
+/// a temporal array, and the timezone of the array, producing the UTC time
+/// of the elements of an array of the same type and timezone.
+fn map_temporal_to_temporal<F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    errors: &RowErrors,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    F: Fn(usize, NaiveDateTime, Zone) -> Option<NaiveDateTime> + Sync,
+{
+    let (temporal, zone) = temporal_input(array, options)?;
+    let values = map_temporal(array, options, errors, |i, local| {
+        let utc = f(i, local, zone).ok_or_else(out_of_range)?;
+        temporal.encode(utc).ok_or_else(out_of_range)
+    })?;
+    let values = match temporal {
+        Temporal::Date32 => {
+            let days = values
+                .iter()
+                .map(|v| i32::try_from(*v).map_err(|_| out_of_range()));
+            Buffer::from_slice(&days.collect::<Result<Vec<_>>>()?)
+        }
+        _ => Buffer::from_slice(&values),
+    };
+    let (validity, null_count) = udf::output_validity(array);
+    let mut schema = Schema::new(array.data_type(), &array.schema().name);
+    schema.format = array.schema().format.clone();
+    Ok((
+        schema,
+        ArrayData::primitive(values, array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Every element of a temporal array truncated to the start of its `unit`.
+pub fn date_trunc(
+    array: &ArrowArray,
+    unit: TruncUnit,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let errors = RowErrors::new(options);
+    let (schema, data) = map_temporal_to_temporal(array, options, &errors, |_, local, zone| {
+        Some(zone.to_utc(unit.truncate(local)?))
+    })?;
+    Ok(errors.finish(schema, data))
+}
+
+/// The `field` of every element of a temporal array, as Int64.
+pub fn extract(
+    array: &ArrowArray,
+    field: Field,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let errors = RowErrors::new(options);
+    let values = map_temporal(array, options, &errors, |_, local| Ok(field.extract(local)))?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok(errors.finish(
+        Schema::new(ArrowType::Int64, &array.schema().name),
+        ArrayData::primitive(Buffer::from_slice(&values), array.len())
+            .with_validity(validity, null_count),
+    ))
+}
+
+/// The UTC time of `local`, in `zone`, plus `interval`: the months, then the
+/// days, in `zone`, then the nanoseconds.
+fn add_interval(
+    local: NaiveDateTime,
+    zone: Zone,
+    interval: ArrowUdfInterval,
+) -> Option<NaiveDateTime> {
+    let months = Months::new(interval.months.unsigned_abs());
+    let days = Days::new(interval.days.unsigned_abs() as u64);
+    let local = match interval.months < 0 {
+        true => local.checked_sub_months(months)?,
+        false => local.checked_add_months(months)?,
+    };
+    let local = match interval.days < 0 {
+        true => local.checked_sub_days(days)?,
+        false => local.checked_add_days(days)?,
+    };
+    zone.to_utc(local)
+        .checked_add_signed(TimeDelta::nanoseconds(interval.nanoseconds))
+}
+
+/// Every element of a temporal array plus `interval`. Adding months keeps
+/// the day of the month, or uses the last day of the month when it's too
+/// short.
+pub fn timestamp_add(
+    array: &ArrowArray,
+    interval: ArrowUdfInterval,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let errors = RowErrors::new(options);
+    let (schema, data) = map_temporal_to_temporal(array, options, &errors, |_, local, zone| {
+        add_interval(local, zone, interval)
+    })?;
+    Ok(errors.finish(schema, data))
+}
+
+/// The values of an Interval array, as month-day-nano intervals, with the
+/// offset of the array already applied.
+fn interval_values(array: &ArrowArray) -> Result<Vec<ArrowUdfInterval>> {
+    let ArrowType::Interval(unit) = array.data_type() else {
+        return Err(Error::UnsupportedType(format!(
+            "expected an interval input, got {:?}",
+            array.data_type()
+        )));
+    };
+    let data = unsafe { array.ffi().buffer(1) };
+    if array.is_empty() {
+        return Ok(Vec::new());
+    }
+    let start = array.offset();
+    let read = |i: usize| unsafe {
+        match unit {
+            IntervalUnit::YearMonth => ArrowUdfInterval {
+                months: (data as *const i32).add(start + i).read_unaligned(),
+                ..ArrowUdfInterval::default()
+            },
+            IntervalUnit::DayTime => {
+                let fields = (data as *const i32).add(2 * (start + i));
+                ArrowUdfInterval {
+                    months: 0,
+                    days: fields.read_unaligned(),
+                    nanoseconds: fields.add(1).read_unaligned() as i64 * 1_000_000,
+                }
+            }
+            // Stored with the layout of `ArrowUdfInterval`.
+            IntervalUnit::MonthDayNano => (data as *const ArrowUdfInterval)
+                .add(start + i)
+                .read_unaligned(),
+        }
+    };
+    Ok((0..array.len()).map(read).collect())
+}
+
+/// Every element of a temporal array plus, or minus if `subtract`, the
+/// element of the Interval array `intervals` at the same position. Positions
+/// where any of the arrays is null are null in the result.
+pub fn timestamp_add_intervals(
+    array: &ArrowArray,
+    intervals: &ArrowArray,
+    subtract: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if array.len() != intervals.len() {
+        return Err(Error::InvalidArgument(format!(
+            "arrays of different lengths: {} and {}",
+            array.len(),
+            intervals.len()
+        )));
+    }
+    let values = interval_values(intervals)?;
+    if options.null_policy()? == NullPolicy::Error && intervals.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let validity = intervals.validity().filter(|_| intervals.null_count() > 0);
+    let errors = RowErrors::new(options);
+    let (schema, data) = map_temporal_to_temporal(array, options, &errors, |i, local, zone| {
+        if validity.is_some_and(|validity| !validity.is_set(i)) {
+            return Some(zone.to_utc(local));
+        }
+        let interval = match subtract {
+            true => values[i].checked_neg()?,
+            false => values[i],
+        };
+        add_interval(local, zone, interval)
+    })?;
+    let (validity, null_count) = udf::combined_validity(&[array, intervals]);
+    Ok(errors.finish(schema, data.with_validity(validity, null_count)))
+}
+
+/// Every element of a Duration array converted to `unit`. Conversions to a
+/// coarser unit truncate the values towards zero, like the casts of Arrow,
+/// and conversions to a finer one fail for the values that overflow.
+pub fn duration_cast(
+    array: &ArrowArray,
+    unit: TimeUnit,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let ArrowType::Duration(from) = array.data_type() else {
+        return Err(Error::UnsupportedType(format!(
+            "expected a duration input, got {:?}",
+            array.data_type()
+        )));
+    };
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let (from, to) = (from.per_second(), unit.per_second());
+    let values = array.values::<i64>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let errors = RowErrors::new(options);
+    let mut out = vec![0i64; array.len()];
+    exec::map(&mut out, options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            if validity.is_some_and(|validity| !validity.is_set(i)) {
+                continue;
+            }
+            let value = match to >= from {
+                true => values[i].checked_mul(to / from).ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "duration {} out of range in {unit:?}",
+                        values[i]
+                    ))
+                }),
+                false => Ok(values[i] / (from / to)),
+            };
+            if let Some(value) = errors.check(i, value)? {
+                *out = value;
+            }
+        }
+        Ok(())
+    })?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok(errors.finish(
+        Schema::new(ArrowType::Duration(unit), &array.schema().name),
+        ArrayData::primitive(Buffer::from_slice(&out), array.len())
+            .with_validity(validity, null_count),
+    ))
+}
+
+/// Formatter of the values of a Date32, Date64 or Timestamp field described
+/// by `schema` as ISO 8601 text: dates like `2024-01-31`, and timestamps in
+/// the timezone of the field, like `2024-01-31T08:30:00.250+01:00`, or
+/// without an offset if the field has no timezone. Values out of range are
+/// `None`.
+pub(crate) fn text_formatter(schema: &Schema) -> Result<impl Fn(i64) -> Option<String>> {
+    let temporal = Temporal::of(schema.data_type)
+        .ok_or_else(|| Error::UnsupportedType(format!("text of {:?} values", schema.data_type)))?;
+    let zone = schema
+        .timezone()
+        .map(|tz| Zone::parse(Some(tz)))
+        .transpose()?;
+    Ok(move |value: i64| {
+        let utc = temporal.decode(value)?;
+        Some(match (temporal, zone) {
+            (Temporal::Date32 | Temporal::Date64, _) => utc.format("%Y-%m-%d").to_string(),
+            (_, None) => utc.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+            (_, Some(zone)) => {
+                let offset = (zone.to_local(utc) - utc).num_seconds() as i32;
+                FixedOffset::east_opt(offset)?
+                    .from_utc_datetime(&utc)
+                    .format("%Y-%m-%dT%H:%M:%S%.f%:z")
+                    .to_string()
+            }
+        })
+    })
+}
+
+/// Parser of ISO 8601 text into the values of a Date32, Date64 or
+/// Timestamp field described by `schema`: dates like `2024-01-31`, at
+/// midnight for timestamps, local times like `2024-01-31 08:30:00.25` or
+/// with a `T`, in the timezone of the field, and RFC 3339 times with an
+/// offset. Text that isn't a date or a time, or out of range, is `None`.
+pub(crate) fn text_parser(schema: &Schema) -> Result<impl Fn(&str) -> Option<i64>> {
+    let temporal = Temporal::of(schema.data_type).ok_or_else(|| {
+        Error::UnsupportedType(format!("parsing of {:?} values", schema.data_type))
+    })?;
+    let zone = Zone::parse(schema.timezone())?;
+    Ok(move |text: &str| {
+        let text = text.trim();
+        let utc = match DateTime::parse_from_rfc3339(text) {
+            Ok(datetime) => datetime.naive_utc(),
+            Err(_) => {
+                let local = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+                    .iter()
+                    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+                    .or_else(|| {
+                        let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+                        Some(date.and_time(NaiveTime::MIN))
+                    })?;
+                zone.to_utc(local)
+            }
+        };
+        temporal.encode(utc)
+    })
+}
+
+/// Import the array of an entry point, compute the result with `f`, and
+/// export it.
+unsafe fn temporal_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+    f: impl FnOnce(&ArrowArray, &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)>,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = f(&array, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Every element of a Date32, Date64 or Timestamp array truncated to the
+/// start of its `unit`: `year`, `quarter`, `month`, `week` (starting on
+/// Monday), `day`, `hour`, `minute` or `second`, in the timezone of the
+/// array. The result has the type of the input.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `unit` must be a valid null-terminated string, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_date_trunc(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    unit: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    temporal_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let unit = TruncUnit::from_name(&CStr::from_ptr(unit).to_string_lossy())?;
+            date_trunc(array, unit, options)
+        },
+    )
+}
+
+/// The `field` of every element of a Date32, Date64 or Timestamp array, in
+/// the timezone of the array, as an Int64 array: `year`, `quarter`, `month`,
+/// `week` (ISO 8601), `day`, `day_of_week` (0 for Monday), `day_of_year`,
+/// `hour`, `minute` or `second`.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `field` must be a valid null-terminated string, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_extract(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    field: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    temporal_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let field = Field::from_name(&CStr::from_ptr(field).to_string_lossy())?;
+            extract(array, field, options)
+        },
+    )
+}
+
+/// Every element of a Date32, Date64 or Timestamp array plus `interval`.
+/// Months and days are added in the timezone of the array, and nanoseconds
+/// as elapsed time. The result has the type of the input.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_timestamp_add(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    interval: ArrowUdfInterval,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    temporal_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| timestamp_add(array, interval, options),
+    )
+}
+
+/// Every element of a Date32, Date64 or Timestamp array plus the element of
+/// an Interval array at the same position, of any of its units. Months and
+/// days are added in the timezone of the array, and nanoseconds as elapsed
+/// time. Positions where any of the arrays is null are null in the result,
+/// which has the type of the input.
+///
+/// # Safety
+///
+/// `schema` and `array`, and `interval_schema` and `interval_array`, must
+/// point to valid Arrow C Data Interface arrays, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_timestamp_add_interval(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    interval_schema: *const ArrowCDataInterfaceSchema,
+    interval_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    intervals_ffi(
+        schema,
+        array,
+        interval_schema,
+        interval_array,
+        false,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+/// Every element of a Date32, Date64 or Timestamp array minus the element of
+/// an Interval array at the same position, like
+/// `arrow_udf_timestamp_add_interval` with the interval negated.
+///
+/// # Safety
+///
+/// Like `arrow_udf_timestamp_add_interval`.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_timestamp_sub_interval(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    interval_schema: *const ArrowCDataInterfaceSchema,
+    interval_array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    intervals_ffi(
+        schema,
+        array,
+        interval_schema,
+        interval_array,
+        true,
+        options,
+        out_schema,
+        out_array,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn intervals_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    interval_schema: *const ArrowCDataInterfaceSchema,
+    interval_array: *const ArrowCDataInterfaceArray,
+    subtract: bool,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let interval_schema = Schema::from_ffi(&*interval_schema)?;
+        let intervals = ArrowArray::import(&interval_schema, &*interval_array)?;
+        let (out, data) = timestamp_add_intervals(&array, &intervals, subtract, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Every element of a Duration array converted to `unit`: `second`,
+/// `millisecond`, `microsecond` or `nanosecond`, as a Duration array of that
+/// unit. Conversions to a coarser unit truncate towards zero, and
+/// conversions to a finer one fail with `ArrowUdfStatus::InvalidArgument` if
+/// a value overflows.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `unit` must be a valid null-terminated string, `options` must be null or
+/// valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_duration_cast(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    unit: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    temporal_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let unit = TimeUnit::from_name(&CStr::from_ptr(unit).to_string_lossy())?;
+            duration_cast(array, unit, options)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::{self, Exported};
+
+    /// 2024-02-29, a Thursday.
+    const LEAP_DAY: i32 = 19_782;
+    /// 2024-02-29T23:30:00Z.
+    const LATE_LEAP_DAY: i64 = LEAP_DAY as i64 * SECONDS_PER_DAY + 84_600;
+
+    /// Array of `values` with the temporal type of `format`.
+    fn temporal<T: crate::types::NativeType>(format: &str, values: &[Option<T>]) -> Exported {
+        let mut schema = Schema::new(ArrowType::from_format(format).unwrap(), "x");
+        schema.format = format.to_string();
+        Exported::new(&schema, testing::nullable_data(values))
+    }
+
+    fn run(
+        input: &Exported,
+        f: impl FnOnce(
+            &ArrowCDataInterfaceSchema,
+            &ArrowCDataInterfaceArray,
+            &mut ArrowCDataInterfaceSchema,
+            &mut ArrowCDataInterfaceArray,
+        ) -> ArrowUdfStatus,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        match f(&input.schema, &input.array, &mut out.schema, &mut out.array) {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn trunc(input: &Exported, unit: &str) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let unit = CString::new(unit).unwrap();
+        run(input, |schema, array, out_schema, out_array| unsafe {
+            arrow_udf_date_trunc(
+                schema,
+                array,
+                unit.as_ptr(),
+                ptr::null(),
+                out_schema,
+                out_array,
+            )
+        })
+    }
+
+    fn field(input: &Exported, field: &str) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let field = CString::new(field).unwrap();
+        run(input, |schema, array, out_schema, out_array| unsafe {
+            arrow_udf_extract(
+                schema,
+                array,
+                field.as_ptr(),
+                ptr::null(),
+                out_schema,
+                out_array,
+            )
+        })
+    }
+
+    fn add(input: &Exported, interval: ArrowUdfInterval) -> Exported {
+        run(input, |schema, array, out_schema, out_array| unsafe {
+            arrow_udf_timestamp_add(schema, array, interval, ptr::null(), out_schema, out_array)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn fields_of_dates() {
+        let input = temporal("tdD", &[Some(LEAP_DAY), None, Some(0)]);
+        let expected = [
+            ("year", 2024, 1970),
+            ("quarter", 1, 1),
+            ("month", 2, 1),
+            ("week", 9, 1),
+            ("day", 29, 1),
+            ("day_of_week", 3, 3),
+            ("day_of_year", 60, 1),
+            ("hour", 0, 0),
+        ];
+        for (name, leap_day, epoch) in expected {
+            let out = field(&input, name).unwrap();
+            assert_eq!(
+                out.nullable_values::<i64>(),
+                [Some(leap_day), None, Some(epoch)],
+                "{name}"
+            );
+        }
+        let millis = temporal("tdm", &[Some(LEAP_DAY as i64 * SECONDS_PER_DAY * 1_000)]);
+        assert_eq!(field(&millis, "day").unwrap().values::<i64>(), [29]);
+    }
+
+    #[test]
+    fn timestamps_are_computed_in_their_timezone() {
+        let utc = temporal("tss:", &[Some(LATE_LEAP_DAY)]);
+        let paris = temporal("tss:+01:00", &[Some(LATE_LEAP_DAY)]);
+        assert_eq!(field(&utc, "month").unwrap().values::<i64>(), [2]);
+        assert_eq!(field(&paris, "month").unwrap().values::<i64>(), [3]);
+        let midnight = LEAP_DAY as i64 * SECONDS_PER_DAY;
+        assert_eq!(trunc(&utc, "day").unwrap().values::<i64>(), [midnight]);
+        let truncated = trunc(&paris, "day").unwrap();
+        assert_eq!(truncated.values::<i64>(), [midnight + 23 * 3_600]);
+        assert_eq!(
+            truncated.with_array(|array| array.schema().timezone().map(str::to_string)),
+            Some("+01:00".to_string())
+        );
+        let millis = temporal("tsm:", &[Some(LATE_LEAP_DAY * 1_000 + 999), None]);
+        let truncated = trunc(&millis, "month").unwrap();
+        let february = (LEAP_DAY as i64 - 28) * SECONDS_PER_DAY * 1_000;
+        assert_eq!(truncated.nullable_values::<i64>(), [Some(february), None]);
+    }
+
+    #[test]
+    fn months_keep_the_day_or_use_the_last_one() {
+        // 2024-01-31.
+        let input = temporal("tdD", &[Some(LEAP_DAY - 29), None]);
+        let month = ArrowUdfInterval {
+            months: 1,
+            ..ArrowUdfInterval::default()
+        };
+        assert_eq!(
+            add(&input, month).nullable_values::<i32>(),
+            [Some(LEAP_DAY), None]
+        );
+        let back = ArrowUdfInterval {
+            months: -1,
+            days: -1,
+            nanoseconds: 0,
+        };
+        let input = temporal("tsu:", &[Some(LATE_LEAP_DAY * 1_000_000)]);
+        let expected = (LATE_LEAP_DAY - 32 * SECONDS_PER_DAY) * 1_000_000 + 5;
+        let interval = ArrowUdfInterval {
+            nanoseconds: 5_000,
+            ..back
+        };
+        assert_eq!(add(&input, interval).values::<i64>(), [expected]);
+    }
+
+    #[cfg(feature = "tz")]
+    #[test]
+    fn days_across_daylight_saving_time_keep_the_time() {
+        // 2024-03-30T12:00:00+01:00, the day before the change to +02:00.
+        let noon = (LEAP_DAY as i64 + 30) * SECONDS_PER_DAY + 11 * 3_600;
+        let input = temporal("tss:Europe/Paris", &[Some(noon)]);
+        let day = ArrowUdfInterval {
+            days: 1,
+            ..ArrowUdfInterval::default()
+        };
+        let expected = noon + SECONDS_PER_DAY - 3_600;
+        assert_eq!(add(&input, day).values::<i64>(), [expected]);
+    }
+
+    #[test]
+    fn invalid_inputs_fail() {
+        let input = temporal("tdD", &[Some(LEAP_DAY)]);
+        assert_eq!(
+            trunc(&input, "decade").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            field(&input, "era").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let integers = Exported::primitive(&[LEAP_DAY]);
+        assert_eq!(
+            field(&integers, "day").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let times = temporal("ttm", &[Some(1_000)]);
+        assert_eq!(
+            field(&times, "hour").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let mars = temporal("tss:Mars/Olympus", &[Some(0_i64)]);
+        assert_eq!(
+            field(&mars, "day").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let overflow = temporal("tdD", &[Some(i32::MAX)]);
+        assert_eq!(
+            trunc(&overflow, "year").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let nulls = temporal::<i32>("tdD", &[None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let status = run(&nulls, |schema, array, out_schema, out_array| unsafe {
+            let unit = c"day".as_ptr();
+            arrow_udf_date_trunc(schema, array, unit, &options, out_schema, out_array)
+        })
+        .err();
+        assert_eq!(status, Some(ArrowUdfStatus::NullValue));
+    }
+
+    /// Interval array of `unit` with `values`, where `None` is null.
+    fn intervals(unit: &str, values: &[Option<ArrowUdfInterval>]) -> Exported {
+        let mut bytes = Vec::new();
+        for value in values {
+            let value = value.unwrap_or_default();
+            match unit {
+                "tiM" => bytes.extend_from_slice(&value.months.to_ne_bytes()),
+                "tiD" => {
+                    let millis = (value.nanoseconds / 1_000_000) as i32;
+                    bytes.extend_from_slice(&value.days.to_ne_bytes());
+                    bytes.extend_from_slice(&millis.to_ne_bytes());
+                }
+                _ => {
+                    bytes.extend_from_slice(&value.months.to_ne_bytes());
+                    bytes.extend_from_slice(&value.days.to_ne_bytes());
+                    bytes.extend_from_slice(&value.nanoseconds.to_ne_bytes());
+                }
+            }
+        }
+        let validity = values
+            .iter()
+            .map(|value| value.map(|_| ()))
+            .collect::<Vec<_>>();
+        let nulls = validity.iter().filter(|value| value.is_none()).count();
+        let data = ArrayData::primitive(Buffer::from_slice(&bytes), values.len())
+            .with_validity(Some(testing::validity(&validity)), nulls);
+        let schema = Schema::new(ArrowType::from_format(unit).unwrap(), "i");
+        Exported::new(&schema, data)
+    }
+
+    fn add_intervals(
+        input: &Exported,
+        intervals: &Exported,
+        subtract: bool,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let f = match subtract {
+            true => arrow_udf_timestamp_sub_interval,
+            false => arrow_udf_timestamp_add_interval,
+        };
+        run(input, |schema, array, out_schema, out_array| unsafe {
+            f(
+                schema,
+                array,
+                &intervals.schema,
+                &intervals.array,
+                options,
+                out_schema,
+                out_array,
+            )
+        })
+    }
+
+    fn cast(input: &Exported, unit: &str) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let unit = CString::new(unit).unwrap();
+        run(input, |schema, array, out_schema, out_array| unsafe {
+            arrow_udf_duration_cast(
+                schema,
+                array,
+                unit.as_ptr(),
+                ptr::null(),
+                out_schema,
+                out_array,
+            )
+        })
+    }
+
+    fn months(months: i32) -> Option<ArrowUdfInterval> {
+        Some(ArrowUdfInterval {
+            months,
+            ..ArrowUdfInterval::default()
+        })
+    }
+
+    #[test]
+    fn intervals_of_every_unit_are_added_row_by_row() {
+        let options = ArrowUdfExecOptions::default();
+        // 2024-01-31, 2024-02-29 and null.
+        let dates = temporal("tdD", &[Some(LEAP_DAY - 29), Some(LEAP_DAY), None]);
+        let year_month = intervals("tiM", &[months(1), months(12), months(1)]);
+        let out = add_intervals(&dates, &year_month, false, &options).unwrap();
+        assert_eq!(
+            out.nullable_values::<i32>(),
+            [Some(LEAP_DAY), Some(LEAP_DAY + 365), None]
+        );
+
+        let day_time = ArrowUdfInterval {
+            days: 1,
+            nanoseconds: 2_000_000_000,
+            ..ArrowUdfInterval::default()
+        };
+        let seconds = temporal("tss:", &[Some(LATE_LEAP_DAY), Some(0)]);
+        let out = add_intervals(
+            &seconds,
+            &intervals("tiD", &[Some(day_time), None]),
+            false,
+            &options,
+        )
+        .unwrap();
+        let expected = [Some(LATE_LEAP_DAY + SECONDS_PER_DAY + 2), None];
+        assert_eq!(out.nullable_values::<i64>(), expected);
+
+        let month_day_nano = ArrowUdfInterval {
+            months: 1,
+            days: 1,
+            nanoseconds: 5_000,
+        };
+        let micros = temporal("tsu:", &[Some(0_i64)]);
+        let out = add_intervals(
+            &micros,
+            &intervals("tin", &[Some(month_day_nano)]),
+            false,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(out.values::<i64>(), [32 * SECONDS_PER_DAY * 1_000_000 + 5]);
+    }
+
+    #[test]
+    fn intervals_are_subtracted_negated() {
+        let options = ArrowUdfExecOptions::default();
+        let dates = temporal("tdD", &[Some(LEAP_DAY)]);
+        let out = add_intervals(&dates, &intervals("tiM", &[months(1)]), true, &options).unwrap();
+        // 2024-01-29.
+        assert_eq!(out.values::<i32>(), [LEAP_DAY - 31]);
+        let out = add_intervals(
+            &dates,
+            &intervals("tiM", &[months(i32::MIN)]),
+            true,
+            &options,
+        );
+        assert_eq!(out.err(), Some(ArrowUdfStatus::InvalidArgument));
+    }
+
+    #[test]
+    fn invalid_intervals_fail() {
+        let options = ArrowUdfExecOptions::default();
+        let dates = temporal("tdD", &[Some(LEAP_DAY), Some(LEAP_DAY)]);
+        let one = intervals("tiM", &[months(1)]);
+        let status = add_intervals(&dates, &one, false, &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidArgument));
+        let integers = Exported::primitive(&[1_i32, 2]);
+        let status = add_intervals(&dates, &integers, false, &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::UnsupportedType));
+        let nulls = intervals("tiM", &[months(1), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let status = add_intervals(&dates, &nulls, false, &options).err();
+        assert_eq!(status, Some(ArrowUdfStatus::NullValue));
+    }
+
+    #[test]
+    fn durations_are_cast_between_units() {
+        let seconds = temporal("tDs", &[Some(2_i64), None, Some(-3)]);
+        let millis = cast(&seconds, "millisecond").unwrap();
+        assert_eq!(
+            millis.nullable_values::<i64>(),
+            [Some(2_000), None, Some(-3_000)]
+        );
+        millis.with_array(|array| {
+            assert_eq!(
+                array.data_type(),
+                ArrowType::Duration(TimeUnit::Millisecond)
+            )
+        });
+        // Coarser units truncate towards zero.
+        let nanos = temporal("tDn", &[Some(1_999_999_999_i64), Some(-1_999_999_999)]);
+        assert_eq!(cast(&nanos, "second").unwrap().values::<i64>(), [1, -1]);
+
+        let large = temporal("tDs", &[Some(i64::MAX / 10)]);
+        assert_eq!(
+            cast(&large, "nanosecond").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            cast(&seconds, "fortnight").err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let timestamps = temporal("tss:", &[Some(1_i64)]);
+        assert_eq!(
+            cast(&timestamps, "millisecond").err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+
+    #[test]
+    fn rows_out_of_range_are_collected_with_their_errors() {
+        let dates = temporal("tdD", &[Some(LEAP_DAY), None, Some(i32::MAX)]);
+        let month = months(1).unwrap();
+        let collect = ArrowUdfExecOptions {
+            flags: udf::ARROW_UDF_FLAG_COLLECT_ERRORS,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = dates
+            .with_array(|array| timestamp_add(array, month, &collect))
+            .unwrap();
+        assert_eq!(schema.data_type, ArrowType::Struct);
+        assert_eq!(schema.children[0].name, udf::VALUE_FIELD);
+        assert_eq!(schema.children[0].data_type, ArrowType::Date32);
+        let out = Exported::new(&schema, data);
+        assert_eq!(
+            out.child_values::<i32>(0),
+            [Some(LEAP_DAY + 29), None, None]
+        );
+        let errors = out.with_array(|array| {
+            let errors = array.child(1);
+            (0..3).map(|i| errors.is_valid(i)).collect::<Vec<_>>()
+        });
+        assert_eq!(errors, [false, false, true]);
+        let failed = dates
+            .with_array(|array| timestamp_add(array, month, &ArrowUdfExecOptions::default()).err());
+        assert!(matches!(failed, Some(Error::InvalidArgument(_))));
+
+        let seconds = temporal("tDs", &[Some(i64::MAX / 10), Some(2_i64)]);
+        let (schema, data) = seconds
+            .with_array(|array| duration_cast(array, TimeUnit::Nanosecond, &collect))
+            .unwrap();
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.child_values::<i64>(0), [None, Some(2_000_000_000)]);
+        out.with_array(|array| {
+            let errors = array.child(1);
+            assert!(errors.utf8_value(0).unwrap().contains("out of range"));
+            assert!(!errors.is_valid(1));
+        });
+    }
+}
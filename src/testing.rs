@@ -0,0 +1,231 @@
+//! Arrays exported from Rust values, to call the entry points in tests the
+//! way a host does.
+
+use std::ffi::c_char;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::binary::Utf8Builder;
+use crate::bitmap::{Bitmap, BitmapBuilder};
+use crate::buffer::Buffer;
+use crate::export::{self, ArrayData};
+use crate::ffi::{
+    ArrowCDataInterfaceArray, ArrowCDataInterfaceArrayStream, ArrowCDataInterfaceSchema,
+};
+use crate::schema::{ArrowType, Schema};
+use crate::types::NativeType;
+
+/// Schema and array exported through the C Data Interface, released when
+/// they are dropped.
+pub struct Exported {
+    pub schema: ArrowCDataInterfaceSchema,
+    pub array: ArrowCDataInterfaceArray,
+}
+
+impl Exported {
+    /// Released structs, for the outputs of an entry point.
+    pub fn empty() -> Exported {
+        Exported {
+            schema: ArrowCDataInterfaceSchema::empty(),
+            array: ArrowCDataInterfaceArray::empty(),
+        }
+    }
+
+    pub fn new(schema: &Schema, data: ArrayData) -> Exported {
+        let mut exported = Exported::empty();
+        let data = Arc::new(data);
+        unsafe { export::export_to(schema, &data, &mut exported.schema, &mut exported.array) }
+            .unwrap();
+        exported
+    }
+
+    /// Primitive array named `x` with `values`.
+    pub fn primitive<T: NativeType>(values: &[T]) -> Exported {
+        Exported::new(
+            &Schema::new(T::ARROW_TYPE, "x"),
+            ArrayData::primitive(Buffer::from_slice(values), values.len()),
+        )
+    }
+
+    /// Boolean array named `x` with `values`.
+    pub fn boolean(values: &[bool]) -> Exported {
+        let mut bits = BitmapBuilder::with_capacity(values.len());
+        values.iter().for_each(|value| bits.push(*value));
+        let data = ArrayData::primitive(bits.finish(), values.len());
+        Exported::new(&Schema::new(ArrowType::Boolean, "x"), data)
+    }
+
+    /// Primitive array named `x` with `values`, where `None` is null.
+    pub fn nullable<T: NativeType>(values: &[Option<T>]) -> Exported {
+        Exported::named("x", values)
+    }
+
+    /// Primitive array named `name` with `values`, where `None` is null.
+    pub fn named<T: NativeType>(name: &str, values: &[Option<T>]) -> Exported {
+        Exported::new(&Schema::new(T::ARROW_TYPE, name), nullable_data(values))
+    }
+
+    /// Utf8 array named `x` with `values`, where `None` is null.
+    pub fn utf8(values: &[Option<&str>]) -> Exported {
+        let mut builder = Utf8Builder::with_capacity(values.len());
+        values.iter().for_each(|value| builder.push(*value));
+        Exported::new(&Schema::new(ArrowType::Utf8, "x"), builder.finish())
+    }
+
+    /// Call `f` with the exported array imported back.
+    pub fn with_array<R>(&self, f: impl FnOnce(&ArrowArray) -> R) -> R {
+        let schema = unsafe { Schema::from_ffi(&self.schema) }.unwrap();
+        let array = unsafe { ArrowArray::new(&schema, &self.array) };
+        f(&array)
+    }
+
+    pub fn values<T: NativeType>(&self) -> Vec<T> {
+        self.with_array(|array| array.values::<T>().to_vec())
+    }
+
+    /// The values of the child `i` of a struct array, with `None` for the
+    /// nulls.
+    pub fn child_values<T: NativeType>(&self, i: usize) -> Vec<Option<T>> {
+        self.with_array(|array| {
+            let child = array.child(i);
+            let values = child.values::<T>();
+            (0..child.len())
+                .map(|i| child.is_valid(i).then_some(values[i]))
+                .collect()
+        })
+    }
+
+    /// The values of a Boolean array, with `None` for the nulls.
+    pub fn booleans(&self) -> Vec<Option<bool>> {
+        self.with_array(|array| {
+            let bytes = (array.offset() + array.len()).div_ceil(8);
+            let data = unsafe { std::slice::from_raw_parts(array.ffi().buffer(1), bytes) };
+            let values = Bitmap::new(data, array.offset(), array.len());
+            (0..array.len())
+                .map(|i| array.is_valid(i).then(|| values.is_set(i)))
+                .collect()
+        })
+    }
+
+    /// The strings of a Utf8 array, with `None` for the nulls.
+    pub fn strings(&self) -> Vec<Option<String>> {
+        self.with_array(|array| {
+            (0..array.len())
+                .map(|i| {
+                    array
+                        .is_valid(i)
+                        .then(|| array.utf8_value(i).unwrap().to_string())
+                })
+                .collect()
+        })
+    }
+
+    /// The values, with `None` for the nulls.
+    pub fn nullable_values<T: NativeType>(&self) -> Vec<Option<T>> {
+        self.with_array(|array| {
+            let values = array.values::<T>();
+            (0..array.len())
+                .map(|i| array.is_valid(i).then_some(values[i]))
+                .collect()
+        })
+    }
+}
+
+/// Data of a primitive array with `values`, where `None` is null.
+pub fn nullable_data<T: NativeType>(values: &[Option<T>]) -> ArrayData {
+    let null_count = values.iter().filter(|value| value.is_none()).count();
+    let buffer: Buffer = values
+        .iter()
+        .map(|value| value.unwrap_or_default())
+        .collect();
+    ArrayData::primitive(buffer, values.len()).with_validity(Some(validity(values)), null_count)
+}
+
+/// Validity bitmap of `values`, with the `None` values unset.
+pub fn validity<T>(values: &[Option<T>]) -> Buffer {
+    let mut validity = BitmapBuilder::with_capacity(values.len());
+    for value in values {
+        validity.push(value.is_some());
+    }
+    validity.finish()
+}
+
+impl Drop for Exported {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(release) = self.array.release {
+                release(&mut self.array);
+            }
+            if let Some(release) = self.schema.release {
+                release(&mut self.schema);
+            }
+        }
+    }
+}
+
+/// Producer of a C Stream Interface stream over exported arrays.
+struct StreamState {
+    schema: Schema,
+    batches: std::vec::IntoIter<Exported>,
+    fail_at: Option<usize>,
+    served: usize,
+}
+
+/// Stream with the arrays of `batches`, which must have the type of
+/// `schema`. With `fail_at`, the call to `get_next` for that batch fails.
+pub fn stream(
+    schema: &Schema,
+    batches: Vec<Exported>,
+    fail_at: Option<usize>,
+) -> ArrowCDataInterfaceArrayStream {
+    let state = StreamState {
+        schema: schema.clone(),
+        batches: batches.into_iter(),
+        fail_at,
+        served: 0,
+    };
+    ArrowCDataInterfaceArrayStream {
+        get_schema: Some(get_schema),
+        get_next: Some(get_next),
+        get_last_error: Some(get_last_error),
+        release: Some(release_stream),
+        private_data: Box::into_raw(Box::new(state)) as *mut _,
+    }
+}
+
+unsafe fn state<'a>(stream: *mut ArrowCDataInterfaceArrayStream) -> &'a mut StreamState {
+    &mut *((*stream).private_data as *mut StreamState)
+}
+
+unsafe extern "C" fn get_schema(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    out: *mut ArrowCDataInterfaceSchema,
+) -> i32 {
+    *out = export::export_schema(&state(stream).schema);
+    0
+}
+
+unsafe extern "C" fn get_next(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    out: *mut ArrowCDataInterfaceArray,
+) -> i32 {
+    let state = state(stream);
+    if state.fail_at == Some(state.served) {
+        return 5;
+    }
+    state.served += 1;
+    *out = match state.batches.next() {
+        Some(mut batch) => std::mem::replace(&mut batch.array, ArrowCDataInterfaceArray::empty()),
+        None => ArrowCDataInterfaceArray::empty(),
+    };
+    0
+}
+
+unsafe extern "C" fn get_last_error(_: *mut ArrowCDataInterfaceArrayStream) -> *const c_char {
+    c"batch not available".as_ptr()
+}
+
+unsafe extern "C" fn release_stream(stream: *mut ArrowCDataInterfaceArrayStream) {
+    drop(Box::from_raw((*stream).private_data as *mut StreamState));
+    (*stream).release = None;
+}
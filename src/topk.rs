@@ -0,0 +1,289 @@
+//! Top-k values of a stream of arrays, to push `ORDER BY ... LIMIT k` down
+//! to this library.
+//!
+//! The best `k` values seen so far are kept in a bounded heap, so the memory
+//! used doesn't depend on the length of the stream. Null values are skipped,
+//! and ties are resolved in favor of the first row with the value.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{
+    ArrowCDataInterfaceArray, ArrowCDataInterfaceArrayStream, ArrowCDataInterfaceSchema,
+};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::stream::ArrayStream;
+use crate::types::{with_native_type, TotalOrd};
+
+/// Value in the heap, together with its position in the stream.
+struct Entry<T> {
+    value: T,
+    index: i64,
+    descending: bool,
+}
+
+impl<T: TotalOrd> Entry<T> {
+    /// Order in which entries are returned: the first one is the best.
+    fn rank(&self, other: &Entry<T>) -> Ordering {
+        let order = self.value.total_cmp(&other.value);
+        let order = if self.descending {
+            order.reverse()
+        } else {
+            order
+        };
+        order.then(self.index.cmp(&other.index))
+    }
+}
+
+// The heap keeps the worst entry on top, so it's the one evicted when a
+// better one is found.
+impl<T: TotalOrd> Ord for Entry<T> {
+    fn cmp(&self, other: &Entry<T>) -> Ordering {
+        self.rank(other)
+    }
+}
+
+impl<T: TotalOrd> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Entry<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: TotalOrd> PartialEq for Entry<T> {
+    fn eq(&self, other: &Entry<T>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: TotalOrd> Eq for Entry<T> {}
+
+/// Best `k` values of a sequence of arrays.
+pub struct TopK<T> {
+    k: usize,
+    descending: bool,
+    heap: BinaryHeap<Entry<T>>,
+    rows: i64,
+}
+
+impl<T: TotalOrd> TopK<T> {
+    /// Keep the `k` largest values if `descending`, or the `k` smallest.
+    pub fn new(k: usize, descending: bool) -> TopK<T> {
+        TopK {
+            k,
+            descending,
+            heap: BinaryHeap::with_capacity(k + 1),
+            rows: 0,
+        }
+    }
+
+    /// Add the non-null values of `array`. Their indices follow the ones of
+    /// the previous arrays.
+    pub fn update(&mut self, array: &ArrowArray) {
+        let values = array.values::<T>();
+        let validity = array.validity().filter(|_| array.null_count() > 0);
+        for (i, value) in values.iter().enumerate() {
+            if validity.is_some_and(|validity| !validity.is_set(i)) {
+                continue;
+            }
+            let entry = Entry {
+                value: *value,
+                index: self.rows + i as i64,
+                descending: self.descending,
+            };
+            if self.heap.len() < self.k {
+                self.heap.push(entry);
+            } else if self.heap.peek().is_some_and(|worst| entry < *worst) {
+                self.heap.pop();
+                self.heap.push(entry);
+            }
+        }
+        self.rows += values.len() as i64;
+    }
+
+    /// The values kept, from the best to the worst, and their indices.
+    pub fn finish(self) -> (Vec<T>, Vec<i64>) {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| (entry.value, entry.index))
+            .unzip()
+    }
+}
+
+/// Values and indices of the top-k of `stream`, as an exported array.
+fn topk<T: TotalOrd>(
+    stream: &mut ArrayStream,
+    k: usize,
+    descending: bool,
+    with_indices: bool,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let mut top = TopK::<T>::new(k, descending);
+    let null_policy = options.null_policy()?;
+    stream.for_each(|array| {
+        if null_policy == NullPolicy::Error && array.null_count() > 0 {
+            return Err(Error::NullValue);
+        }
+        top.update(array);
+        options.check_cancelled()
+    })?;
+    let (values, indices) = top.finish();
+    let len = values.len();
+    let values = ArrayData::primitive(Buffer::from_slice(&values), len);
+    let name = &stream.schema().name;
+    if !with_indices {
+        return Ok((Schema::new(T::ARROW_TYPE, name), values));
+    }
+    let indices = ArrayData::primitive(Buffer::from_slice(&indices), len);
+    Ok((
+        Schema::new(ArrowType::Struct, name).with_children(vec![
+            Schema::new(T::ARROW_TYPE, "values"),
+            Schema::new(ArrowType::Int64, "index"),
+        ]),
+        ArrayData::struct_array(vec![values, indices], len),
+    ))
+}
+
+/// The `k` largest values of the arrays of `stream` if `descending`, or the
+/// `k` smallest, from the best to the worst. With `with_indices`, the result
+/// is a struct array with the `values`, and their `index` in the stream.
+/// Null values are skipped, so the result has less than `k` values when the
+/// stream doesn't have enough non-null values.
+///
+/// The stream is consumed and released, also when the call fails.
+///
+/// # Safety
+///
+/// `stream` must point to a valid C Stream Interface stream, `options` must
+/// be null or valid, and `out_schema` and `out_array` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_topk(
+    stream: *mut ArrowCDataInterfaceArrayStream,
+    k: i64,
+    descending: bool,
+    with_indices: bool,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let mut stream = ArrayStream::from_ffi(stream)?;
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        if k < 0 {
+            return Err(Error::InvalidArgument(format!("negative k: {k}")));
+        }
+        let k = k as usize;
+        let data_type = stream.schema().data_type;
+        let (out, data) = with_native_type!(data_type, T => {
+            topk::<T>(&mut stream, k, descending, with_indices, &options)?
+        }, _ => {
+            return Err(Error::UnsupportedType(format!("top-k of {data_type:?} values")))
+        });
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::{self, Exported};
+
+    fn run(
+        batches: Vec<Exported>,
+        k: i64,
+        descending: bool,
+        with_indices: bool,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let schema = batches[0].with_array(|array| array.schema().clone());
+        let mut stream = testing::stream(&schema, batches, None);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_topk(
+                &mut stream,
+                k,
+                descending,
+                with_indices,
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert!(stream.release.is_none(), "the stream must be released");
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    #[test]
+    fn best_values_first_and_ties_by_position() {
+        let mut top = TopK::new(3, false);
+        let values = Exported::primitive(&[5_i32, 1, 3, 1, 0, 3]);
+        values.with_array(|array| top.update(array));
+        assert_eq!(top.finish(), (vec![0, 1, 1], vec![4, 1, 3]));
+        let mut top = TopK::<f64>::new(2, true);
+        let values = Exported::primitive(&[1.0_f64, f64::NAN, 7.0]);
+        values.with_array(|array| top.update(array));
+        let (values, indices) = top.finish();
+        assert!(values[0].is_nan());
+        assert_eq!((values[1], indices), (7.0, vec![1, 2]));
+    }
+
+    #[test]
+    fn topk_of_a_stream_through_ffi() {
+        let batches = vec![
+            Exported::nullable(&[Some(4_i64), None, Some(9)]),
+            Exported::nullable(&[Some(1_i64), Some(9), None, Some(6)]),
+        ];
+        let options = ArrowUdfExecOptions::default();
+        let out = run(batches, 3, true, true, &options).unwrap();
+        assert_eq!(out.child_values::<i64>(0), [Some(9), Some(9), Some(6)]);
+        assert_eq!(out.child_values::<i64>(1), [Some(2), Some(4), Some(6)]);
+        let batches = vec![Exported::nullable(&[Some(2_u8), None])];
+        let out = run(batches, 5, false, false, &options).unwrap();
+        assert_eq!(out.values::<u8>(), [2]);
+    }
+
+    #[test]
+    fn invalid_calls_fail() {
+        let default = ArrowUdfExecOptions::default();
+        let error_on_nulls = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let batches = || vec![Exported::nullable(&[Some(1_i64), None])];
+        assert_eq!(
+            run(batches(), -1, false, false, &default).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        assert_eq!(
+            run(batches(), 1, false, false, &error_on_nulls).err(),
+            Some(ArrowUdfStatus::NullValue)
+        );
+        let schema = Schema::new(ArrowType::Boolean, "x");
+        let mut stream = testing::stream(&schema, Vec::new(), None);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_topk(
+                &mut stream,
+                1,
+                false,
+                false,
+                &default,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+    }
+}
@@ -0,0 +1,623 @@
+//! Recording of calls into trace files, to replay them without the host.
+//!
+//! Wrong results reported by users often depend on data and options that
+//! only exist in their environment. With the `trace` feature, setting the
+//! `ARROW_UDF_TRACE` environment variable to a directory makes every call
+//! to the built-in kernels write a trace file there, with the entry point,
+//! its scalar arguments, the options, a copy of the input array, and the
+//! result or the error of the call. Users attach the file to the report,
+//! and `Trace::read` and `Trace::replay` call the entry point again with the
+//! recorded input, telling if the result is the recorded one.
+//!
+//! Trace files are two Arrow IPC streams, see [`crate::ipc`], one after
+//! the other, so they are read back with the validations of the IPC reader
+//! and can be opened by other Arrow libraries:
+//!
+//! ```text
+//! call:   a field with the input array, and a batch with it. The schema
+//!         metadata has the function, the arguments as `args`, like
+//!         "i64:3,f64:0.5", and every recorded option by its name
+//! result: the output field and a batch with it, or no fields for calls
+//!         failing. The schema metadata has the status and the message
+//! ```
+//!
+//! The result is only written when the call returns, so the trace of a call
+//! panicking or crashing the process still has its input. The callbacks,
+//! the context and the other pointers of the options aren't recorded.
+
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::array::ArrowArray;
+use crate::error::{arrow_udf_last_error, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::ipc::{StreamReader, StreamWriter};
+use crate::kernels::KERNEL_ENTRY_POINTS;
+use crate::options::ArrowUdfExecOptions;
+use crate::schema::{ArrowType, Metadata, Schema};
+use crate::stream::ImportedArray;
+
+/// Environment variable with the directory where traces are written.
+pub const TRACE_ENV: &str = "ARROW_UDF_TRACE";
+
+/// A scalar argument of an entry point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arg {
+    Int(i64),
+    Float(f64),
+}
+
+impl From<i64> for Arg {
+    fn from(value: i64) -> Arg {
+        Arg::Int(value)
+    }
+}
+
+impl From<f64> for Arg {
+    fn from(value: f64) -> Arg {
+        Arg::Float(value)
+    }
+}
+
+impl TryFrom<Arg> for i64 {
+    type Error = Error;
+
+    fn try_from(arg: Arg) -> Result<i64> {
+        match arg {
+            Arg::Int(value) => Ok(value),
+            arg => Err(Error::InvalidArgument(format!(
+                "expected an Int64 argument, got {arg:?}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<Arg> for f64 {
+    type Error = Error;
+
+    fn try_from(arg: Arg) -> Result<f64> {
+        match arg {
+            Arg::Float(value) => Ok(value),
+            arg => Err(Error::InvalidArgument(format!(
+                "expected a Float64 argument, got {arg:?}"
+            ))),
+        }
+    }
+}
+
+/// An entry point called with its scalar arguments in a slice, failing if
+/// they aren't the ones it takes.
+pub(crate) type EntryPoint = unsafe fn(
+    *const ArrowCDataInterfaceSchema,
+    *const ArrowCDataInterfaceArray,
+    &[Arg],
+    *const ArrowUdfExecOptions,
+    *mut ArrowCDataInterfaceSchema,
+    *mut ArrowCDataInterfaceArray,
+) -> Result<ArrowUdfStatus>;
+
+fn io_error(error: io::Error) -> Error {
+    Error::Io(error.to_string())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidArgument(format!("invalid trace: {message}"))
+}
+
+/// The recorded options, by their name in the metadata of the call.
+fn option_values(options: &ArrowUdfExecOptions) -> [(&'static str, String); 8] {
+    [
+        ("batch_size", options.batch_size.to_string()),
+        ("num_threads", options.num_threads.to_string()),
+        ("null_policy", options.null_policy.to_string()),
+        ("flags", options.flags.to_string()),
+        ("max_concurrency", options.max_concurrency.to_string()),
+        ("nan_policy", options.nan_policy.to_string()),
+        ("memory_limit", options.memory_limit.to_string()),
+        ("strategy", options.strategy.to_string()),
+    ]
+}
+
+/// The value of `key` in `metadata`.
+fn value<'a>(metadata: &'a Metadata, key: &str) -> Result<&'a str> {
+    metadata
+        .get(key)
+        .ok_or_else(|| invalid(&format!("no {key} in the metadata")))
+}
+
+/// The number in the value of `key` in `metadata`.
+fn number<T: FromStr>(metadata: &Metadata, key: &str) -> Result<T> {
+    let value = value(metadata, key)?;
+    value
+        .parse()
+        .map_err(|_| invalid(&format!("{key} {value:?}")))
+}
+
+fn parse_arg(arg: &str) -> Result<Arg> {
+    let parsed = match arg.split_once(':') {
+        Some(("i64", value)) => value.parse().ok().map(Arg::Int),
+        Some(("f64", value)) => value.parse().ok().map(Arg::Float),
+        _ => None,
+    };
+    parsed.ok_or_else(|| invalid(&format!("argument {arg:?}")))
+}
+
+/// The result stream of a call returning `status`, with the error
+/// `message` or the `output` array.
+fn result_stream(
+    status: ArrowUdfStatus,
+    message: &str,
+    output: Option<&ArrowArray>,
+) -> Result<Vec<u8>> {
+    let fields = output.map(|output| output.schema().clone());
+    let schema = Schema::new(ArrowType::Struct, "")
+        .with_children(fields.into_iter().collect())
+        .with_metadata("status", &(status as i32).to_string())
+        .with_metadata("message", message);
+    let mut writer = StreamWriter::new(Vec::new(), &schema)?;
+    if let Some(output) = output {
+        writer.write_columns(&[*output], output.len())?;
+    }
+    writer.finish()
+}
+
+/// Run `f`, the body of the entry point `function` over `array`, writing a
+/// trace of the call when `ARROW_UDF_TRACE` is set.
+pub(crate) fn record(
+    function: &str,
+    args: &[Arg],
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: impl FnOnce() -> Result<(Schema, ArrayData)>,
+) -> Result<(Schema, Arc<ArrayData>)> {
+    let Some(dir) = std::env::var_os(TRACE_ENV) else {
+        return f().map(|(schema, data)| (schema, Arc::new(data)));
+    };
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    let path = Path::new(&dir).join(format!(
+        "{function}-{}-{}.trace",
+        std::process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed)
+    ));
+    record_to(&path, function, args, array, options, f)
+}
+
+/// Run `f` like `record`, writing the trace of the call to `path`.
+fn record_to(
+    path: &Path,
+    function: &str,
+    args: &[Arg],
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: impl FnOnce() -> Result<(Schema, ArrayData)>,
+) -> Result<(Schema, Arc<ArrayData>)> {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| match arg {
+            Arg::Int(value) => format!("i64:{value}"),
+            Arg::Float(value) => format!("f64:{value:?}"),
+        })
+        .collect();
+    let mut schema = Schema::new(ArrowType::Struct, "")
+        .with_children(vec![array.schema().clone()])
+        .with_metadata("function", function)
+        .with_metadata("args", &args.join(","));
+    for (name, value) in option_values(options) {
+        schema = schema.with_metadata(name, &value);
+    }
+    let file = File::create(path).map_err(io_error)?;
+    let mut call = StreamWriter::new(BufWriter::new(file), &schema)?;
+    call.write_columns(&[*array], array.len())?;
+    let mut file = call.finish()?;
+
+    let result = f().map(|(schema, data)| (schema, Arc::new(data)));
+    let outcome = match &result {
+        Ok((schema, data)) => {
+            let mut ffi = export::export_shared(data);
+            let output = unsafe { ArrowArray::new(schema, &ffi) };
+            let outcome = result_stream(ArrowUdfStatus::Ok, "", Some(&output));
+            if let Some(release) = ffi.release {
+                unsafe { release(&mut ffi) };
+            }
+            outcome?
+        }
+        Err(error) => result_stream(error.status(), &error.to_string(), None)?,
+    };
+    io::Write::write_all(&mut file, &outcome)
+        .and_then(|_| io::Write::flush(&mut file))
+        .map_err(io_error)?;
+    result
+}
+
+/// How a recorded call returned.
+pub struct Outcome {
+    /// The `ArrowUdfStatus` returned, as an integer.
+    pub status: i32,
+    /// The description of the error, empty for successful calls.
+    pub message: String,
+    /// The schema of the result stream, with the output field.
+    schema: Schema,
+    output: Option<ImportedArray>,
+    /// The result stream, to compare the result of replays with.
+    stream: Vec<u8>,
+}
+
+/// A call read from a trace file.
+pub struct Trace {
+    pub function: String,
+    pub args: Vec<Arg>,
+    /// The recorded options, without callbacks, context or metrics.
+    pub options: ArrowUdfExecOptions,
+    /// The schema of the call stream, with the input field.
+    schema: Schema,
+    input: ImportedArray,
+    /// How the call returned, or `None` if the process ended during the
+    /// call.
+    pub outcome: Option<Outcome>,
+}
+
+/// The result of replaying a trace.
+pub struct Replay {
+    /// The `ArrowUdfStatus` returned, as an integer.
+    pub status: i32,
+    /// The description of the error, empty for successful calls.
+    pub message: String,
+    /// Whether the status, the error and the result are the recorded ones.
+    /// Always false for traces without a recorded outcome.
+    pub matches: bool,
+}
+
+impl Trace {
+    /// Read the trace file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Trace> {
+        let bytes = fs::read(path).map_err(io_error)?;
+        let mut rest = bytes.as_slice();
+        let mut call = StreamReader::new(&mut rest)?;
+        let schema = call.schema().clone();
+        if schema.children.len() != 1 {
+            return Err(invalid("call without a single input"));
+        }
+        let input = call
+            .next()?
+            .ok_or_else(|| invalid("call without its input"))?;
+        if call.next()?.is_some() {
+            return Err(invalid("call with several inputs"));
+        }
+        let metadata = &schema.metadata;
+        let args = match value(metadata, "args")? {
+            "" => Vec::new(),
+            args => args.split(',').map(parse_arg).collect::<Result<_>>()?,
+        };
+        let options = ArrowUdfExecOptions {
+            batch_size: number(metadata, "batch_size")?,
+            num_threads: number(metadata, "num_threads")?,
+            null_policy: number(metadata, "null_policy")?,
+            flags: number(metadata, "flags")?,
+            max_concurrency: number(metadata, "max_concurrency")?,
+            nan_policy: number(metadata, "nan_policy")?,
+            memory_limit: number(metadata, "memory_limit")?,
+            strategy: number(metadata, "strategy")?,
+            ..ArrowUdfExecOptions::default()
+        };
+        let function = value(metadata, "function")?.to_string();
+        let outcome = match rest.is_empty() {
+            true => None,
+            false => Some(Outcome::read(rest)?),
+        };
+        Ok(Trace {
+            function,
+            args,
+            options,
+            schema,
+            input,
+            outcome,
+        })
+    }
+
+    /// The recorded input array.
+    pub fn input(&self) -> ArrowArray<'_> {
+        self.input.view(&self.schema).child(0)
+    }
+
+    /// The recorded result, if the call succeeded.
+    pub fn output(&self) -> Option<ArrowArray<'_>> {
+        let outcome = self.outcome.as_ref()?;
+        let output = outcome.output.as_ref()?;
+        Some(output.view(&outcome.schema).child(0))
+    }
+
+    /// Call the recorded entry point again, with the recorded input,
+    /// arguments and options, and compare the result with the recorded one.
+    pub fn replay(&self) -> Result<Replay> {
+        let Some((_, entry_point)) = KERNEL_ENTRY_POINTS
+            .iter()
+            .find(|(name, _)| *name == self.function)
+        else {
+            return Err(Error::InvalidArgument(format!(
+                "no entry point {:?} to replay",
+                self.function
+            )));
+        };
+        let input = self.input();
+        let mut ffi_schema = export::export_schema(input.schema());
+        let mut out_schema = ArrowCDataInterfaceSchema::empty();
+        let mut out_array = ArrowCDataInterfaceArray::empty();
+        let status = unsafe {
+            entry_point(
+                &ffi_schema,
+                input.ffi(),
+                &self.args,
+                &self.options,
+                &mut out_schema,
+                &mut out_array,
+            )
+        };
+        if let Some(release) = ffi_schema.release {
+            unsafe { release(&mut ffi_schema) };
+        }
+        let status = status?;
+        let (message, stream) = match status {
+            ArrowUdfStatus::Ok => {
+                let schema = unsafe { Schema::from_ffi(&out_schema) };
+                let stream = schema.and_then(|schema| {
+                    let output = unsafe { ArrowArray::new(&schema, &out_array) };
+                    result_stream(status, "", Some(&output))
+                });
+                release(&mut out_array, out_schema);
+                (String::new(), stream?)
+            }
+            _ => {
+                let message = unsafe { CStr::from_ptr(arrow_udf_last_error()) };
+                let message = message.to_string_lossy().into_owned();
+                let stream = result_stream(status, &message, None)?;
+                (message, stream)
+            }
+        };
+        let matches = self
+            .outcome
+            .as_ref()
+            .is_some_and(|outcome| outcome.stream == stream);
+        Ok(Replay {
+            status: status as i32,
+            message,
+            matches,
+        })
+    }
+}
+
+impl Outcome {
+    /// The result stream in `bytes`, which must end with it.
+    fn read(bytes: &[u8]) -> Result<Outcome> {
+        let mut rest = bytes;
+        let mut result = StreamReader::new(&mut rest)?;
+        let schema = result.schema().clone();
+        let status = number(&schema.metadata, "status")?;
+        let message = value(&schema.metadata, "message")?.to_string();
+        let output = match status == ArrowUdfStatus::Ok as i32 {
+            true if schema.children.len() == 1 => Some(
+                result
+                    .next()?
+                    .ok_or_else(|| invalid("result without its output"))?,
+            ),
+            true => return Err(invalid("result without a single output")),
+            false => None,
+        };
+        if result.next()?.is_some() {
+            return Err(invalid("result with several outputs"));
+        }
+        if !rest.is_empty() {
+            return Err(invalid("trailing bytes after the result"));
+        }
+        Ok(Outcome {
+            status,
+            message,
+            schema,
+            output,
+            stream: bytes.to_vec(),
+        })
+    }
+}
+
+/// Release an array and its schema, if they weren't released yet.
+fn release(array: &mut ArrowCDataInterfaceArray, mut schema: ArrowCDataInterfaceSchema) {
+    unsafe {
+        if let Some(release) = array.release {
+            release(array);
+        }
+        if let Some(release) = schema.release {
+            release(&mut schema);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::*;
+    use crate::testing::Exported;
+    use crate::udf::MapOutput;
+
+    /// A path for the trace file of a test, removed when dropped.
+    struct TracePath(std::path::PathBuf);
+
+    impl TracePath {
+        fn new(name: &str) -> TracePath {
+            let file = format!("arrow_udf_trace_{}_{name}.trace", std::process::id());
+            TracePath(std::env::temp_dir().join(file))
+        }
+    }
+
+    impl Drop for TracePath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Record a call to `arrow_udf_clip` of `input` between 0 and `hi`,
+    /// computing its result with `f`.
+    fn record_clip(
+        path: &TracePath,
+        input: &Exported,
+        hi: f64,
+        options: &ArrowUdfExecOptions,
+        f: impl Fn(f64) -> f64 + Sync,
+    ) -> Result<(Schema, Arc<ArrayData>)> {
+        let args = [Arg::Float(0.0), Arg::Float(hi)];
+        input.with_array(|array| {
+            record_to(&path.0, "arrow_udf_clip", &args, array, options, || {
+                <f64 as MapOutput>::map(array, options, &f)
+            })
+        })
+    }
+
+    fn values(array: &ArrowArray) -> Vec<Option<f64>> {
+        let values = array.values::<f64>();
+        (0..array.len())
+            .map(|i| array.is_valid(i).then_some(values[i]))
+            .collect()
+    }
+
+    #[test]
+    fn calls_are_recorded_and_replayed() {
+        let path = TracePath::new("replayed");
+        let input = Exported::nullable(&[Some(-1.0_f64), None, Some(3.0)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            flags: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (_, data) = record_clip(&path, &input, 2.0, &options, |x| x.clamp(0.0, 2.0)).unwrap();
+        assert_eq!(data.length, 3);
+        let trace = Trace::read(&path.0).unwrap();
+        assert_eq!(trace.function, "arrow_udf_clip");
+        assert_eq!(trace.args, [Arg::Float(0.0), Arg::Float(2.0)]);
+        assert_eq!((trace.options.batch_size, trace.options.flags), (2, 4));
+        let recorded = trace.input();
+        assert_eq!(recorded.schema(), &Schema::new(ArrowType::Float64, "x"));
+        assert_eq!(values(&recorded), [Some(-1.0), None, Some(3.0)]);
+        let output = trace.output().unwrap();
+        assert_eq!(values(&output), [Some(0.0), None, Some(2.0)]);
+        let replay = trace.replay().unwrap();
+        assert_eq!(replay.status, ArrowUdfStatus::Ok as i32);
+        assert!(replay.matches);
+    }
+
+    #[test]
+    fn replays_tell_results_and_errors_apart() {
+        let path = TracePath::new("wrong");
+        let input = Exported::primitive(&[1.0_f64, 5.0]);
+        let options = ArrowUdfExecOptions::default();
+        record_clip(&path, &input, 2.0, &options, |x| x).unwrap();
+        let trace = Trace::read(&path.0).unwrap();
+        assert!(!trace.replay().unwrap().matches);
+
+        let path = TracePath::new("failed");
+        let input = Exported::utf8(&[Some("a")]);
+        let failed = input.with_array(|array| {
+            record_to(&path.0, "arrow_udf_negate", &[], array, &options, || {
+                Err(Error::UnsupportedType(
+                    "arrow_udf_negate of Utf8 arrays".to_string(),
+                ))
+            })
+        });
+        assert!(failed.is_err());
+        let trace = Trace::read(&path.0).unwrap();
+        let outcome = trace.outcome.as_ref().unwrap();
+        assert_eq!(outcome.status, ArrowUdfStatus::UnsupportedType as i32);
+        assert!(trace.output().is_none());
+        let replay = trace.replay().unwrap();
+        assert_eq!(replay.message, outcome.message);
+        assert!(replay.matches);
+    }
+
+    #[test]
+    fn calls_that_never_returned_keep_their_input() {
+        let path = TracePath::new("panicked");
+        let input = Exported::primitive(&[1.0_f64]);
+        let options = ArrowUdfExecOptions::default();
+        let call = panic::catch_unwind(AssertUnwindSafe(|| {
+            record_clip(&path, &input, 2.0, &options, |_| panic!("crashed"))
+        }));
+        assert!(call.is_err());
+        let trace = Trace::read(&path.0).unwrap();
+        assert!(trace.outcome.is_none());
+        assert_eq!(values(&trace.input()), [Some(1.0)]);
+        assert!(!trace.replay().unwrap().matches);
+    }
+
+    #[test]
+    fn sliced_inputs_are_recorded_from_their_offset() {
+        let path = TracePath::new("sliced");
+        let mut input = Exported::nullable(&[Some(1.0_f64), None, Some(5.0), Some(-2.0)]);
+        (input.array.offset, input.array.length) = (1, 3);
+        let options = ArrowUdfExecOptions::default();
+        record_clip(&path, &input, 2.0, &options, |x| x.clamp(0.0, 2.0)).unwrap();
+        let trace = Trace::read(&path.0).unwrap();
+        assert_eq!(values(&trace.input()), [None, Some(5.0), Some(-2.0)]);
+        assert_eq!(
+            values(&trace.output().unwrap()),
+            [None, Some(2.0), Some(0.0)]
+        );
+        assert!(trace.replay().unwrap().matches);
+    }
+
+    #[test]
+    fn invalid_traces_fail() {
+        let path = TracePath::new("invalid");
+        record_clip(
+            &path,
+            &Exported::primitive(&[1.0_f64]),
+            2.0,
+            &ArrowUdfExecOptions::default(),
+            |x| x,
+        )
+        .unwrap();
+        let bytes = fs::read(&path.0).unwrap();
+        let invalid = |bytes: &[u8]| {
+            fs::write(&path.0, bytes).unwrap();
+            Trace::read(&path.0).err()
+        };
+        assert!(matches!(
+            invalid(b"ARROWUDF"),
+            Some(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            invalid(&bytes[..30]),
+            Some(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            invalid(&bytes[..bytes.len() - 1]),
+            Some(Error::InvalidArgument(_))
+        ));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            invalid(&trailing),
+            Some(Error::InvalidArgument(_))
+        ));
+        let at = bytes
+            .windows(14)
+            .position(|window| window == b"arrow_udf_clip")
+            .unwrap();
+        let mut unknown = bytes;
+        unknown[at..at + 14].copy_from_slice(b"arrow_udf_none");
+        fs::write(&path.0, &unknown).unwrap();
+        let trace = Trace::read(&path.0).unwrap();
+        assert!(matches!(trace.replay(), Err(Error::InvalidArgument(_))));
+        assert!(i64::try_from(Arg::Float(1.0)).is_err());
+        assert_eq!(f64::try_from(Arg::from(1.5)), Ok(1.5));
+        assert_eq!(
+            parse_arg("f64:NaN").map(|arg| f64::try_from(arg).unwrap().is_nan()),
+            Ok(true)
+        );
+        assert_eq!(parse_arg("i64:-3"), Ok(Arg::Int(-3)));
+        assert!(parse_arg("u8:1").is_err());
+    }
+}
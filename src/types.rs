@@ -0,0 +1,238 @@
+//! Mapping between Rust primitive types and Arrow data types.
+
+use std::cmp::Ordering;
+
+use crate::schema::ArrowType;
+
+/// Rust types whose in-memory representation is the one of an Arrow
+/// fixed-width values buffer.
+pub trait NativeType: Copy + Default + PartialEq + Send + Sync + 'static {
+    const ARROW_TYPE: ArrowType;
+}
+
+/// Total order of the values of a type. Floats are ordered with
+/// `total_cmp`, so NaN is greater than any other value.
+pub trait TotalOrd: NativeType {
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+macro_rules! native_type {
+    ($($type:ty => $arrow_type:ident),* $(,)?) => {
+        $(
+            impl NativeType for $type {
+                const ARROW_TYPE: ArrowType = ArrowType::$arrow_type;
+            }
+        )*
+    };
+}
+
+macro_rules! total_ord {
+    (ord: $($type:ty),*; float: $($float:ty),*) => {
+        $(
+            impl TotalOrd for $type {
+                fn total_cmp(&self, other: &$type) -> Ordering {
+                    self.cmp(other)
+                }
+            }
+        )*
+        $(
+            impl TotalOrd for $float {
+                fn total_cmp(&self, other: &$float) -> Ordering {
+                    <$float>::total_cmp(self, other)
+                }
+            }
+        )*
+    };
+}
+
+/// Conversions between the numeric types and `f64`, for kernels computing
+/// in floating point. Conversions to integers saturate, and NaN becomes 0.
+pub trait Numeric: TotalOrd {
+    fn to_f64(self) -> f64;
+    fn from_f64(value: f64) -> Self;
+    /// `value` in this type, or `None` if it can't represent it: integers
+    /// must be whole and in the range of the type, and floats must not
+    /// overflow to infinity.
+    fn checked_from_f64(value: f64) -> Option<Self>;
+    fn is_nan(self) -> bool;
+}
+
+macro_rules! numeric {
+    (int: $($int:ty),*; float: $($float:ty),*) => {
+        $(
+            impl Numeric for $int {
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn from_f64(value: f64) -> $int {
+                    value as $int
+                }
+
+                fn checked_from_f64(value: f64) -> Option<$int> {
+                    // The maximum of the 64-bit types rounds up to the power
+                    // of two above it, which is out of range.
+                    let in_range = value >= <$int>::MIN as f64 && value < <$int>::MAX as f64 + 1.0;
+                    (in_range && value.fract() == 0.0).then_some(value as $int)
+                }
+
+                fn is_nan(self) -> bool {
+                    false
+                }
+            }
+        )*
+        $(
+            impl Numeric for $float {
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn from_f64(value: f64) -> $float {
+                    value as $float
+                }
+
+                fn checked_from_f64(value: f64) -> Option<$float> {
+                    let converted = value as $float;
+                    (converted.is_finite() == value.is_finite()).then_some(converted)
+                }
+
+                fn is_nan(self) -> bool {
+                    <$float>::is_nan(self)
+                }
+            }
+        )*
+    };
+}
+
+/// Evaluate `$body` with `$type` being the Rust type of the values of
+/// `$data_type`, or `$fallback` if it's not a primitive type.
+macro_rules! with_native_type {
+    ($data_type:expr, $type:ident => $body:expr, _ => $fallback:expr) => {
+        match $data_type {
+            $crate::schema::ArrowType::Int8 => {
+                type $type = i8;
+                $body
+            }
+            $crate::schema::ArrowType::Int16 => {
+                type $type = i16;
+                $body
+            }
+            $crate::schema::ArrowType::Int32 => {
+                type $type = i32;
+                $body
+            }
+            $crate::schema::ArrowType::Int64 => {
+                type $type = i64;
+                $body
+            }
+            $crate::schema::ArrowType::UInt8 => {
+                type $type = u8;
+                $body
+            }
+            $crate::schema::ArrowType::UInt16 => {
+                type $type = u16;
+                $body
+            }
+            $crate::schema::ArrowType::UInt32 => {
+                type $type = u32;
+                $body
+            }
+            $crate::schema::ArrowType::UInt64 => {
+                type $type = u64;
+                $body
+            }
+            $crate::schema::ArrowType::Float32 => {
+                type $type = f32;
+                $body
+            }
+            $crate::schema::ArrowType::Float64 => {
+                type $type = f64;
+                $body
+            }
+            _ => $fallback,
+        }
+    };
+}
+
+pub(crate) use with_native_type;
+
+native_type! {
+    i8 => Int8,
+    i16 => Int16,
+    i32 => Int32,
+    i64 => Int64,
+    u8 => UInt8,
+    u16 => UInt16,
+    u32 => UInt32,
+    u64 => UInt64,
+    f32 => Float32,
+    f64 => Float64,
+}
+
+total_ord! {
+    ord: i8, i16, i32, i64, u8, u16, u32, u64;
+    float: f32, f64
+}
+
+numeric! {
+    int: i8, i16, i32, i64, u8, u16, u32, u64;
+    float: f32, f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floats_have_a_total_order() {
+        let mut values = [2.0_f64, f64::NAN, -0.0, f64::NEG_INFINITY, 0.0];
+        values.sort_by(TotalOrd::total_cmp);
+        assert_eq!(format!("{values:?}"), "[-inf, -0.0, 0.0, 2.0, NaN]");
+        assert_eq!(TotalOrd::total_cmp(&-3_i8, &2), Ordering::Less);
+    }
+
+    #[test]
+    fn conversions_to_integers_saturate() {
+        assert_eq!(<u8 as Numeric>::from_f64(300.0), u8::MAX);
+        assert_eq!(<i16 as Numeric>::from_f64(-1e9), i16::MIN);
+        assert_eq!(<i32 as Numeric>::from_f64(f64::NAN), 0);
+        assert_eq!(<i64 as Numeric>::from_f64(-2.7), -2);
+        assert!(Numeric::is_nan(f32::NAN) && !Numeric::is_nan(1_u64));
+    }
+
+    #[test]
+    fn checked_conversions_reject_values_out_of_the_type() {
+        assert_eq!(<u8 as Numeric>::checked_from_f64(255.0), Some(u8::MAX));
+        assert_eq!(<u8 as Numeric>::checked_from_f64(256.0), None);
+        assert_eq!(<i32 as Numeric>::checked_from_f64(2.5), None);
+        assert_eq!(<i32 as Numeric>::checked_from_f64(f64::NAN), None);
+        assert_eq!(
+            <i64 as Numeric>::checked_from_f64(-(2f64.powi(63))),
+            Some(i64::MIN)
+        );
+        assert_eq!(<i64 as Numeric>::checked_from_f64(2f64.powi(63)), None);
+        assert_eq!(<u64 as Numeric>::checked_from_f64(2f64.powi(64)), None);
+        assert_eq!(<i64 as Numeric>::checked_from_f64(1e300), None);
+        assert_eq!(<f32 as Numeric>::checked_from_f64(1e300), None);
+        assert_eq!(<f32 as Numeric>::checked_from_f64(0.5), Some(0.5));
+        assert!(<f32 as Numeric>::checked_from_f64(f64::NAN)
+            .unwrap()
+            .is_nan());
+        assert_eq!(
+            <f64 as Numeric>::checked_from_f64(f64::INFINITY),
+            Some(f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn native_types_of_arrow_types() {
+        let size = |data_type: ArrowType| {
+            with_native_type!(data_type, T => {
+                Some(std::mem::size_of::<T>())
+            }, _ => None)
+        };
+        assert_eq!(size(ArrowType::UInt16), Some(2));
+        assert_eq!(size(ArrowType::Float64), Some(8));
+        assert_eq!(size(ArrowType::Boolean), None);
+    }
+}
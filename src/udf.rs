@@ -0,0 +1,1210 @@
+//! Application of user defined functions over imported arrays.
+
+use std::ops::Range;
+use std::sync::Mutex;
+
+use crate::array::ArrowArray;
+use crate::binary::Utf8Builder;
+use crate::bitmap::{Bitmap, BitmapBuilder};
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::exec;
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::metrics;
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::random::{self, Rng};
+use crate::row::{self, ArrowRow};
+use crate::schema::{ArrowType, Schema};
+use crate::strategy;
+use crate::types::NativeType;
+
+pub use distance_derive::arrow_udf;
+
+/// Host flag declaring that all the values of the input array are the same.
+pub const ARROW_UDF_FLAG_CONSTANT: u32 = 1;
+
+/// Host flag asking maps to make the rows they fail on null, instead of
+/// failing, and to return the errors with the result.
+pub const ARROW_UDF_FLAG_COLLECT_ERRORS: u32 = 64;
+
+/// Names of the fields of the results of maps collecting their errors: the
+/// result of every row, and the message of its error.
+pub const VALUE_FIELD: &str = "value";
+pub const ERROR_FIELD: &str = "error";
+
+/// The value of a constant input array, if the host flags, the schema
+/// metadata or the encoding of the array tell us it's constant.
+pub(crate) fn constant_input<T: NativeType>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Option<T> {
+    if options.flags & ARROW_UDF_FLAG_CONSTANT != 0
+        && !array.is_empty()
+        && array.null_count() == 0
+        && array.data_type() == T::ARROW_TYPE
+    {
+        return Some(array.values::<T>()[0]);
+    }
+    array.constant_value()
+}
+
+/// Make sure `array` contains values of type `T`, and that its nulls are
+/// acceptable for the null policy.
+pub(crate) fn check_input<T: NativeType>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<()> {
+    if array.data_type() == ArrowType::RunEndEncoded {
+        return Err(Error::UnsupportedType(
+            "run-end encoded arrays are only supported when they consist of a single run"
+                .to_string(),
+        ));
+    }
+    if array.data_type() != T::ARROW_TYPE {
+        return Err(Error::UnsupportedType(format!(
+            "expected {:?} input, got {:?}",
+            T::ARROW_TYPE,
+            array.data_type()
+        )));
+    }
+    let nulls = array.null_count();
+    metrics::record_nulls(nulls);
+    if options.null_policy()? == NullPolicy::Error && nulls > 0 {
+        return Err(Error::NullValue);
+    }
+    Ok(())
+}
+
+/// Whether the result of a reduction over `array` is null: the null policy
+/// is to propagate nulls, and the array has some.
+pub(crate) fn null_result(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<bool> {
+    Ok(options.null_policy()? == NullPolicy::Propagate && array.null_count() > 0)
+}
+
+/// Validity bitmap of the result of a map, and its number of nulls: the
+/// same as the input.
+pub(crate) fn output_validity(array: &ArrowArray) -> (Option<Buffer>, usize) {
+    let null_count = array.null_count();
+    let validity = (null_count > 0)
+        .then(|| array.validity().map(|validity| validity.to_buffer()))
+        .flatten();
+    (validity, null_count)
+}
+
+/// Validity bitmap of the result of a function of several arrays of the
+/// same length, and its number of nulls: the rows where all the arrays are
+/// valid. The arrays can have different offsets.
+pub(crate) fn combined_validity(arrays: &[&ArrowArray]) -> (Option<Buffer>, usize) {
+    let validities: Vec<_> = arrays
+        .iter()
+        .filter(|array| array.null_count() > 0)
+        .filter_map(|array| array.validity())
+        .collect();
+    let Some((first, rest)) = validities.split_first() else {
+        return (None, 0);
+    };
+    let mut validity = first.to_buffer();
+    for other in rest {
+        validity = Bitmap::new(validity.as_slice(), 0, first.len()).and(other);
+    }
+    let null_count = first.len() - Bitmap::new(validity.as_slice(), 0, first.len()).count_set();
+    (Some(validity), null_count)
+}
+
+/// Apply `f` to every element of `array`. Null elements are null in the
+/// result.
+///
+/// When the input is constant, `f` is evaluated once, and the result is a
+/// run-end encoded array with a single run.
+pub fn map<T, O, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    F: Fn(T) -> O + Sync,
+{
+    let name = &array.schema().name;
+    if let Some(value) = constant_input::<T>(array, options) {
+        return Ok((
+            export::constant_schema(O::ARROW_TYPE, name),
+            ArrayData::constant(f(value), array.len()),
+        ));
+    }
+    check_input::<T>(array, options)?;
+    let input = array.values::<T>();
+    let mut values = options.output_values(input.len() * std::mem::size_of::<O>())?;
+    exec::map(values.typed_data_mut::<O>(), options, |rows, out| {
+        for (out, value) in out.iter_mut().zip(&input[rows]) {
+            *out = f(*value);
+        }
+        Ok(())
+    })?;
+    let (validity, null_count) = output_validity(array);
+    Ok((
+        Schema::new(O::ARROW_TYPE, name),
+        ArrayData::primitive(values, array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Apply `f` to every element of `array`, returning a row of several values
+/// for each of them, like a tuple or a struct deriving `ArrowRow`. The result
+/// is a struct array with a field per value, whose rows are null for the null
+/// elements.
+///
+/// When the input is constant, `f` is evaluated once, and its row repeated.
+pub fn map_rows<T, R, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    R: ArrowRow<'static> + Clone + Send,
+    F: Fn(T) -> R + Sync,
+{
+    let schema = row::schema::<R>(&array.schema().name);
+    if let Some(value) = constant_input::<T>(array, options) {
+        let rows = vec![f(value); array.len()];
+        return Ok((schema, row::build(&rows)));
+    }
+    check_input::<T>(array, options)?;
+    let input = array.values::<T>();
+    let mut rows: Vec<Option<R>> = (0..input.len()).map(|_| None).collect();
+    exec::map(&mut rows, options, |range, out| {
+        for (out, value) in out.iter_mut().zip(&input[range]) {
+            *out = Some(f(*value));
+        }
+        Ok(())
+    })?;
+    let rows: Vec<R> = rows.into_iter().flatten().collect();
+    let (validity, null_count) = output_validity(array);
+    Ok((
+        schema,
+        row::build(&rows).with_validity(validity, null_count),
+    ))
+}
+
+/// Where the element a function is applied to is, for functions with
+/// positional logic, like flagging the first row of every batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RowCtx {
+    /// Position of the element in the array.
+    pub index: usize,
+    /// Whether the element is null. Its result is null anyway, but the
+    /// function is called for every element.
+    pub is_null: bool,
+    /// Number of elements of the array, and how many of them are null.
+    pub len: usize,
+    pub null_count: usize,
+    /// Position of the first element of the batch with the element, and
+    /// the number of elements of the batch, and of them null.
+    pub batch_start: usize,
+    pub batch_len: usize,
+    pub batch_null_count: usize,
+}
+
+/// The values of the elements of an array for `map_with_ctx` and
+/// `map_rows_with_ctx`: the constant value of the array if it's constant,
+/// or its values, with its validity and number of nulls.
+#[derive(Clone, Copy)]
+struct CtxInput<'a, T> {
+    constant: Option<T>,
+    values: &'a [T],
+    validity: Option<Bitmap<'a>>,
+    null_count: usize,
+}
+
+impl<'a, T: NativeType> CtxInput<'a, T> {
+    fn new(array: &ArrowArray<'a>, options: &ArrowUdfExecOptions) -> Result<CtxInput<'a, T>> {
+        if let Some(value) = constant_input::<T>(array, options) {
+            return Ok(CtxInput {
+                constant: Some(value),
+                values: &[],
+                validity: None,
+                null_count: 0,
+            });
+        }
+        check_input::<T>(array, options)?;
+        let null_count = array.null_count();
+        Ok(CtxInput {
+            constant: None,
+            values: array.values::<T>(),
+            validity: array.validity().filter(|_| null_count > 0),
+            null_count,
+        })
+    }
+
+    /// Call `f` with the value and the context of every element of `rows`,
+    /// a batch of an array of `len` elements.
+    fn for_each(&self, len: usize, rows: Range<usize>, mut f: impl FnMut(T, RowCtx)) {
+        let batch_null_count = self.validity.map_or(0, |validity| {
+            rows.len() - validity.slice(rows.start, rows.len()).count_set()
+        });
+        for index in rows.clone() {
+            let ctx = RowCtx {
+                index,
+                is_null: self
+                    .validity
+                    .is_some_and(|validity| !validity.is_set(index)),
+                len,
+                null_count: self.null_count,
+                batch_start: rows.start,
+                batch_len: rows.len(),
+                batch_null_count,
+            };
+            f(self.constant.unwrap_or_else(|| self.values[index]), ctx);
+        }
+    }
+}
+
+/// Apply `f` to every element of `array` and its context, its position and
+/// whether it's null. Null elements are null in the result.
+///
+/// Unlike `map`, `f` is called for every element also when the input is
+/// constant, since its result can depend on the position.
+pub fn map_with_ctx<T, O, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    F: Fn(T, RowCtx) -> O + Sync,
+{
+    let input = CtxInput::<T>::new(array, options)?;
+    let constant = input.constant.is_some();
+    let len = array.len();
+    let mut values = options.output_values(len * std::mem::size_of::<O>())?;
+    exec::map(values.typed_data_mut::<O>(), options, |rows, out| {
+        let mut out = out.iter_mut();
+        input.for_each(len, rows, |value, ctx| {
+            *out.next().unwrap() = f(value, ctx);
+        });
+        Ok(())
+    })?;
+    let (validity, null_count) = match constant {
+        true => (None, 0),
+        false => output_validity(array),
+    };
+    Ok((
+        Schema::new(O::ARROW_TYPE, &array.schema().name),
+        ArrayData::primitive(values, len).with_validity(validity, null_count),
+    ))
+}
+
+/// Apply `f` to every element of `array` and a generator of random numbers,
+/// derived from the seed of the options and the position of the element, see
+/// `random`. Null elements are null in the result.
+pub fn map_random<T, O, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    F: Fn(T, &mut Rng) -> O + Sync,
+{
+    let seed = random::call_seed(options);
+    map_with_ctx(array, options, |value, ctx| {
+        f(value, &mut Rng::for_row(seed, ctx.index))
+    })
+}
+
+/// Apply `f` to every element of `array` and its context, returning a row
+/// for each of them like `map_rows`.
+pub fn map_rows_with_ctx<T, R, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    R: ArrowRow<'static> + Send,
+    F: Fn(T, RowCtx) -> R + Sync,
+{
+    let input = CtxInput::<T>::new(array, options)?;
+    let constant = input.constant.is_some();
+    let len = array.len();
+    let mut rows: Vec<Option<R>> = (0..len).map(|_| None).collect();
+    exec::map(&mut rows, options, |range, out| {
+        let mut out = out.iter_mut();
+        input.for_each(len, range, |value, ctx| {
+            *out.next().unwrap() = Some(f(value, ctx));
+        });
+        Ok(())
+    })?;
+    let rows: Vec<R> = rows.into_iter().flatten().collect();
+    let (validity, null_count) = match constant {
+        true => (None, 0),
+        false => output_validity(array),
+    };
+    Ok((
+        row::schema::<R>(&array.schema().name),
+        row::build(&rows).with_validity(validity, null_count),
+    ))
+}
+
+/// Apply `f` to every non-null element of `array` in order, with a state
+/// carried from row to row, like the last average of an exponential moving
+/// average. Null elements are null in the result, and don't reach `f`.
+///
+/// The state starts as `S::default()` at every batch, so the results depend
+/// on the batch size, and batches run one after the other in a single
+/// thread, whatever the number of threads of the options.
+pub fn map_stateful<S, T, O, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    S: Default,
+    T: NativeType,
+    O: NativeType,
+    F: Fn(&mut S, T) -> O + Sync,
+{
+    let input = CtxInput::<T>::new(array, options)?;
+    let constant = input.constant.is_some();
+    let len = array.len();
+    let serial = ArrowUdfExecOptions {
+        num_threads: 1,
+        ..*options
+    };
+    let mut values = options.output_values(len * std::mem::size_of::<O>())?;
+    exec::map(values.typed_data_mut::<O>(), &serial, |rows, out| {
+        let (mut state, mut out) = (S::default(), out.iter_mut());
+        input.for_each(len, rows, |value, ctx| {
+            let out = out.next().unwrap();
+            if !ctx.is_null {
+                *out = f(&mut state, value);
+            }
+        });
+        Ok(())
+    })?;
+    let (validity, null_count) = match constant {
+        true => (None, 0),
+        false => output_validity(array),
+    };
+    Ok((
+        Schema::new(O::ARROW_TYPE, &array.schema().name),
+        ArrayData::primitive(values, len).with_validity(validity, null_count),
+    ))
+}
+
+/// The state of a batch of `map_with_setup`, given to the teardown when
+/// it's dropped, also while unwinding from a panic.
+struct BatchState<'a, S, D: Fn(S)> {
+    state: Option<S>,
+    teardown: &'a D,
+}
+
+impl<S, D: Fn(S)> Drop for BatchState<'_, S, D> {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            (self.teardown)(state);
+        }
+    }
+}
+
+/// Apply `f` to every non-null element of `array`, with the state returned
+/// by `setup` at the start of every batch, like a lookup table loaded from
+/// the context of the options, so expensive initialization runs once per
+/// batch instead of once per row. Null elements are null in the result, and
+/// don't reach `f`.
+///
+/// `teardown` receives the state at the end of every batch, also when the
+/// call fails or panics during the batch. Errors of `setup` fail the call.
+pub fn map_with_setup<S, T, O, Setup, F, Teardown>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    setup: Setup,
+    f: F,
+    teardown: Teardown,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    Setup: Fn(&ArrowUdfExecOptions) -> Result<S> + Sync,
+    F: Fn(&S, T) -> O + Sync,
+    Teardown: Fn(S) + Sync,
+{
+    let input = CtxInput::<T>::new(array, options)?;
+    let constant = input.constant.is_some();
+    let len = array.len();
+    let mut values = options.output_values(len * std::mem::size_of::<O>())?;
+    exec::map(values.typed_data_mut::<O>(), options, |rows, out| {
+        let state = BatchState {
+            state: Some(setup(options)?),
+            teardown: &teardown,
+        };
+        let mut out = out.iter_mut();
+        input.for_each(len, rows, |value, ctx| {
+            let out = out.next().unwrap();
+            if let (false, Some(state)) = (ctx.is_null, &state.state) {
+                *out = f(state, value);
+            }
+        });
+        Ok(())
+    })?;
+    let (validity, null_count) = match constant {
+        true => (None, 0),
+        false => output_validity(array),
+    };
+    Ok((
+        Schema::new(O::ARROW_TYPE, &array.schema().name),
+        ArrayData::primitive(values, len).with_validity(validity, null_count),
+    ))
+}
+
+/// Import the array of an entry point, apply `f` to its elements with
+/// `map_with_setup`, and export the result. This is the body of the entry
+/// points made with `#[arrow_udf(setup = ..., teardown = ...)]`.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn map_with_setup_ffi<S, T, O, Setup, F, Teardown>(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+    setup: Setup,
+    f: F,
+    teardown: Teardown,
+) -> ArrowUdfStatus
+where
+    T: NativeType,
+    O: NativeType,
+    Setup: Fn(&ArrowUdfExecOptions) -> Result<S> + Sync,
+    F: Fn(&S, T) -> O + Sync,
+    Teardown: Fn(S) + Sync,
+{
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = ArrowArray::import(&schema, &*array)?;
+        let (out, data) = map_with_setup(&array, &options, setup, f, teardown)?;
+        export::export_to(&out, &data.into(), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Results of the functions applied to every element of an array by the
+/// kernels: primitive values, for an array of their type, or rows of several
+/// values, like tuples or structs deriving `ArrowRow`, for a struct array.
+pub trait MapOutput: Sized + Send {
+    /// Schema of the results, named `name`.
+    fn schema(name: &str) -> Schema;
+
+    /// Apply `f` to every element of `array`, with `map` or `map_rows`.
+    fn map<T, F>(
+        array: &ArrowArray,
+        options: &ArrowUdfExecOptions,
+        f: F,
+    ) -> Result<(Schema, ArrayData)>
+    where
+        T: NativeType,
+        F: Fn(T) -> Self + Sync;
+
+    /// Apply `f` to every element of `array` and its context, with
+    /// `map_with_ctx` or `map_rows_with_ctx`.
+    fn map_with_ctx<T, F>(
+        array: &ArrowArray,
+        options: &ArrowUdfExecOptions,
+        f: F,
+    ) -> Result<(Schema, ArrayData)>
+    where
+        T: NativeType,
+        F: Fn(T, RowCtx) -> Self + Sync;
+}
+
+macro_rules! native_outputs {
+    ($($type:ty),*) => {
+        $(
+            impl MapOutput for $type {
+                fn schema(name: &str) -> Schema {
+                    Schema::new(<$type as NativeType>::ARROW_TYPE, name)
+                }
+
+                fn map<T, F>(
+                    array: &ArrowArray,
+                    options: &ArrowUdfExecOptions,
+                    f: F,
+                ) -> Result<(Schema, ArrayData)>
+                where
+                    T: NativeType,
+                    F: Fn(T) -> Self + Sync,
+                {
+                    map(array, options, f)
+                }
+
+                fn map_with_ctx<T, F>(
+                    array: &ArrowArray,
+                    options: &ArrowUdfExecOptions,
+                    f: F,
+                ) -> Result<(Schema, ArrayData)>
+                where
+                    T: NativeType,
+                    F: Fn(T, RowCtx) -> Self + Sync,
+                {
+                    map_with_ctx(array, options, f)
+                }
+            }
+        )*
+    };
+}
+
+native_outputs!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+impl<R: ArrowRow<'static> + Clone + Send> MapOutput for R {
+    fn schema(name: &str) -> Schema {
+        row::schema::<R>(name)
+    }
+
+    fn map<T, F>(
+        array: &ArrowArray,
+        options: &ArrowUdfExecOptions,
+        f: F,
+    ) -> Result<(Schema, ArrayData)>
+    where
+        T: NativeType,
+        F: Fn(T) -> Self + Sync,
+    {
+        map_rows(array, options, f)
+    }
+
+    fn map_with_ctx<T, F>(
+        array: &ArrowArray,
+        options: &ArrowUdfExecOptions,
+        f: F,
+    ) -> Result<(Schema, ArrayData)>
+    where
+        T: NativeType,
+        F: Fn(T, RowCtx) -> Self + Sync,
+    {
+        map_rows_with_ctx(array, options, f)
+    }
+}
+
+/// Errors of the rows of a map. They fail the call, unless the host sets
+/// `ARROW_UDF_FLAG_COLLECT_ERRORS`: then the rows failing are null, and the
+/// result is a struct array of the `value` of every row and the Utf8 `error`
+/// message of the rows failing, so pipelines can quarantine them.
+pub struct RowErrors {
+    collect: bool,
+    errors: Mutex<Vec<(usize, Error)>>,
+}
+
+impl RowErrors {
+    pub fn new(options: &ArrowUdfExecOptions) -> RowErrors {
+        RowErrors {
+            collect: options.flags & ARROW_UDF_FLAG_COLLECT_ERRORS != 0,
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The value of the row `row`, or `None` if it failed and its error is
+    /// collected.
+    pub fn check<T>(&self, row: usize, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self.collect => {
+                self.errors.lock().unwrap().push((row, error));
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The result of the map, `schema` and `data`, as it is, or with the rows
+    /// failing null and with their errors when they are collected.
+    pub fn finish(self, schema: Schema, data: ArrayData) -> (Schema, ArrayData) {
+        if !self.collect {
+            return (schema, data);
+        }
+        let mut errors = self.errors.into_inner().unwrap();
+        errors.sort_by_key(|(row, _)| *row);
+        let len = data.length;
+        let valid = data.buffers[0]
+            .as_ref()
+            .filter(|_| data.null_count > 0)
+            .map(|validity| Bitmap::new(validity.as_slice(), 0, len));
+        let (mut validity, mut messages) = (
+            BitmapBuilder::with_capacity(len),
+            Utf8Builder::with_capacity(len),
+        );
+        let mut errors = errors.iter().peekable();
+        let mut null_count = 0;
+        for i in 0..len {
+            let error = errors.next_if(|(row, _)| *row == i);
+            let is_valid = error.is_none() && valid.as_ref().is_none_or(|valid| valid.is_set(i));
+            null_count += usize::from(!is_valid);
+            validity.push(is_valid);
+            messages.push(error.map(|(_, error)| error.to_string()).as_deref());
+        }
+        let data = data.with_validity(Some(validity.finish()), null_count);
+        let name = schema.name.clone();
+        let fields = vec![
+            Schema {
+                name: VALUE_FIELD.to_string(),
+                ..schema
+            },
+            Schema::new(ArrowType::Utf8, ERROR_FIELD),
+        ];
+        (
+            Schema::new(ArrowType::Struct, &name).with_children(fields),
+            ArrayData::struct_array(vec![data, messages.finish()], len),
+        )
+    }
+}
+
+/// Apply `f`, which can fail, to every non-null element of `array`. Null
+/// elements are null in the result, and the errors of `f` fail the call or
+/// are collected, see `RowErrors`.
+pub fn try_map<T, O, F>(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    T: NativeType,
+    O: NativeType,
+    F: Fn(T) -> Result<O> + Sync,
+{
+    check_input::<T>(array, options)?;
+    let input = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let errors = RowErrors::new(options);
+    let mut values = options.output_values(input.len() * std::mem::size_of::<O>())?;
+    exec::map(values.typed_data_mut::<O>(), options, |rows, out| {
+        for (out, i) in out.iter_mut().zip(rows) {
+            if validity.is_none_or(|validity| validity.is_set(i)) {
+                if let Some(value) = errors.check(i, f(input[i]))? {
+                    *out = value;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    let (validity, null_count) = output_validity(array);
+    Ok(errors.finish(
+        Schema::new(O::ARROW_TYPE, &array.schema().name),
+        ArrayData::primitive(values, array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Sum the results of applying `f` to every non-null element of `array`,
+/// failing if the sum overflows. The sum can't be null, so with the
+/// propagate null policy, nulls make it fail like with the error policy.
+///
+/// When the input is constant, `f` is evaluated once, and multiplied by the
+/// length of the array. Otherwise, the loop and the threads summing it are
+/// chosen by `strategy::plan`.
+pub fn map_sum<T, F>(array: &ArrowArray, options: &ArrowUdfExecOptions, f: F) -> Result<i64>
+where
+    T: NativeType,
+    F: Fn(T) -> i64 + Sync,
+{
+    if let Some(value) = constant_input::<T>(array, options) {
+        return f(value)
+            .checked_mul(array.len() as i64)
+            .ok_or_else(sum_overflow);
+    }
+    check_input::<T>(array, options)?;
+    if null_result(array, options)? {
+        return Err(Error::NullValue);
+    }
+    let values = array.values::<T>();
+    let validity = array.validity().filter(|_| array.null_count() > 0);
+    let plan = strategy::plan(
+        values.len(),
+        array.null_count(),
+        std::mem::size_of::<T>(),
+        options,
+    )?;
+    exec::reduce_values(
+        values,
+        &plan.options(options),
+        Some(0),
+        |sum: Option<i64>, rows| {
+            let rows = strategy::sum_rows(plan.loop_strategy, values, validity, rows, &f);
+            sum?.checked_add(rows?)
+        },
+        |a, b| a?.checked_add(b?),
+    )?
+    .ok_or_else(sum_overflow)
+}
+
+fn sum_overflow() -> Error {
+    Error::InvalidArgument("the sum overflows an i64".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::testing::Exported;
+
+    #[test]
+    fn map_applies_the_function_to_every_element() {
+        let input = Exported::primitive(&[1_i64, -2, 3]);
+        let options = ArrowUdfExecOptions::default();
+        let (schema, data) = input
+            .with_array(|array| map(array, &options, |x: i64| x as f64 * 0.5))
+            .unwrap();
+        assert_eq!(schema.data_type, ArrowType::Float64);
+        assert_eq!(schema.name, "x");
+        assert_eq!(
+            Exported::new(&schema, data).values::<f64>(),
+            [0.5, -1.0, 1.5]
+        );
+    }
+
+    #[test]
+    fn map_keeps_the_nulls() {
+        let input = Exported::nullable(&[Some(1_i32), None, Some(3)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 1,
+            num_threads: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| map(array, &options, |x: i32| x * 10))
+            .unwrap();
+        assert_eq!(
+            Exported::new(&schema, data).nullable_values::<i32>(),
+            [Some(10), None, Some(30)]
+        );
+        let error = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_ERROR,
+            ..options
+        };
+        let result = input.with_array(|array| map(array, &error, |x: i32| x).map(|_| ()));
+        assert_eq!(result, Err(Error::NullValue));
+    }
+
+    #[test]
+    fn map_rejects_other_types() {
+        let input = Exported::primitive(&[1_i32]);
+        let options = ArrowUdfExecOptions::default();
+        let result = input.with_array(|array| map(array, &options, |x: i64| x).map(|_| ()));
+        assert!(matches!(result, Err(Error::UnsupportedType(_))));
+    }
+
+    #[test]
+    fn map_sum_skips_nulls_and_fails_on_overflow() {
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            num_threads: 3,
+            ..ArrowUdfExecOptions::default()
+        };
+        let input = Exported::nullable(&[Some(1_i64), None, Some(3), Some(4), None]);
+        let sum = input.with_array(|array| map_sum(array, &options, |x: i64| x * 2));
+        assert_eq!(sum, Ok(16));
+        let input = Exported::primitive(&[1, 2, i64::MAX, 1]);
+        let sum = input.with_array(|array| map_sum(array, &options, |x: i64| x));
+        assert!(matches!(sum, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn constant_inputs_are_evaluated_once() {
+        let input = Exported::primitive(&[4_i64, 4, 4]);
+        let options = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_CONSTANT,
+            ..ArrowUdfExecOptions::default()
+        };
+        let calls = AtomicUsize::new(0);
+        let f = |x: i64| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            x + 1
+        };
+        let (schema, data) = input.with_array(|array| map(array, &options, f)).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(schema.data_type, ArrowType::RunEndEncoded);
+        let out = Exported::new(&schema, data);
+        assert_eq!(
+            out.with_array(|array| array.constant_value::<i64>()),
+            Some(5)
+        );
+        assert_eq!(out.with_array(|array| array.len()), 3);
+        let sum = input.with_array(|array| map_sum(array, &options, f));
+        assert_eq!(sum, Ok(15));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn constant_sums_fail_on_overflow() {
+        let input = Exported::primitive(&[i64::MAX / 2; 3]);
+        let options = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_CONSTANT,
+            ..ArrowUdfExecOptions::default()
+        };
+        let sum = input.with_array(|array| map_sum(array, &options, |x: i64| x));
+        assert!(matches!(sum, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn map_sum_fails_on_nulls_with_the_propagate_policy() {
+        let input = Exported::nullable(&[Some(1_i32), None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_PROPAGATE,
+            ..ArrowUdfExecOptions::default()
+        };
+        let sum = input.with_array(|array| map_sum(array, &options, |x: i32| x as i64));
+        assert!(matches!(sum, Err(Error::NullValue)));
+        let valid = Exported::primitive(&[1_i32, 2]);
+        let sum = valid.with_array(|array| map_sum(array, &options, |x: i32| x as i64));
+        assert_eq!(sum.unwrap(), 3);
+    }
+
+    #[test]
+    fn combined_validity_of_arrays_at_different_offsets() {
+        let values = |seed: usize| -> Vec<Option<i32>> {
+            (0..200)
+                .map(|i| ((i * 7 + seed) % 5 < 3).then_some(i as i32))
+                .collect()
+        };
+        let mut a = Exported::nullable(&values(1));
+        let mut b = Exported::nullable(&values(4));
+        let c = Exported::primitive(&[0_i32; 150]);
+        (a.array.offset, a.array.length) = (13, 150);
+        (b.array.offset, b.array.length) = (42, 150);
+        let expected: Vec<_> = (0..150)
+            .map(|i| a.with_array(|a| a.is_valid(i)) && b.with_array(|b| b.is_valid(i)))
+            .collect();
+        let (validity, null_count) =
+            a.with_array(|a| b.with_array(|b| c.with_array(|c| combined_validity(&[a, c, b]))));
+        let validity = validity.unwrap();
+        let actual: Vec<_> = Bitmap::new(validity.as_slice(), 0, 150).iter().collect();
+        assert_eq!(actual, expected);
+        assert_eq!(null_count, expected.iter().filter(|valid| !**valid).count());
+        assert!(c.with_array(|c| combined_validity(&[c, c])).0.is_none());
+    }
+
+    #[test]
+    fn map_sum_is_checked_with_every_strategy() {
+        let values: Vec<_> = (0..5000_i64).map(|i| (i % 7 != 0).then_some(i)).collect();
+        let expected: i64 = values.iter().flatten().sum();
+        let input = Exported::nullable(&values);
+        let big = Exported::primitive(&[i64::MAX / 3000; 5000]);
+        for strategy in [
+            crate::options::ARROW_UDF_STRATEGY_AUTO,
+            crate::options::ARROW_UDF_STRATEGY_SERIAL,
+            crate::options::ARROW_UDF_STRATEGY_PARALLEL,
+            crate::options::ARROW_UDF_STRATEGY_SIMD,
+        ] {
+            let options = ArrowUdfExecOptions {
+                batch_size: 1000,
+                num_threads: 3,
+                strategy,
+                ..ArrowUdfExecOptions::default()
+            };
+            let sum = input.with_array(|array| map_sum(array, &options, |x: i64| x));
+            assert_eq!(sum, Ok(expected));
+            let sum = big.with_array(|array| map_sum(array, &options, |x: i64| x));
+            assert!(matches!(sum, Err(Error::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn map_rows_returns_a_field_per_value() {
+        let input = Exported::nullable(&[Some(3_i32), None, Some(-4)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 1,
+            num_threads: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| map_rows(array, &options, |x: i32| (x as i64 * 2, x as f64 / 2.0)))
+            .unwrap();
+        assert_eq!(schema.data_type, ArrowType::Struct);
+        assert_eq!(schema.children.len(), 2);
+        let out = Exported::new(&schema, data);
+        out.with_array(|array| assert_eq!(array.null_count(), 1));
+        assert_eq!(out.child_values::<i64>(0)[0], Some(6));
+        assert_eq!(out.child_values::<f64>(1)[2], Some(-2.0));
+        let error = ArrowUdfExecOptions {
+            null_policy: crate::options::ARROW_UDF_NULL_POLICY_ERROR,
+            ..options
+        };
+        let result = input.with_array(|array| map_rows(array, &error, |x: i32| (x, x)).map(|_| ()));
+        assert_eq!(result, Err(Error::NullValue));
+    }
+
+    #[test]
+    fn rows_of_constant_inputs_are_computed_once() {
+        let input = Exported::primitive(&[4_i64, 4, 4]);
+        let options = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_CONSTANT,
+            ..ArrowUdfExecOptions::default()
+        };
+        let calls = AtomicUsize::new(0);
+        let f = |x: i64| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            (x, x + 1)
+        };
+        let (schema, data) = input
+            .with_array(|array| map_rows(array, &options, f))
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.child_values::<i64>(1), [Some(5); 3]);
+    }
+
+    #[test]
+    fn try_map_fails_or_collects_the_errors_of_rows() {
+        let input = Exported::nullable(&[Some(1_i64), Some(-1), None, Some(3)]);
+        let half = |x: i64| match x % 2 {
+            0 => Ok(x / 2),
+            _ if x < 0 => Err(Error::InvalidArgument(format!("negative {x}"))),
+            _ => Ok(x),
+        };
+        let options = ArrowUdfExecOptions::default();
+        let failed = input.with_array(|array| try_map(array, &options, half).err());
+        assert_eq!(
+            failed,
+            Some(Error::InvalidArgument("negative -1".to_string()))
+        );
+        let collect = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_COLLECT_ERRORS,
+            batch_size: 1,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (schema, data) = input
+            .with_array(|array| try_map(array, &collect, half))
+            .unwrap();
+        let names: Vec<&str> = schema
+            .children
+            .iter()
+            .map(|child| child.name.as_str())
+            .collect();
+        assert_eq!(names, [VALUE_FIELD, ERROR_FIELD]);
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.child_values::<i64>(0), [Some(1), None, None, Some(3)]);
+        out.with_array(|array| {
+            let errors = array.child(1);
+            assert_eq!(errors.utf8_value(1), Ok("invalid argument: negative -1"));
+            assert!([0, 2, 3].iter().all(|i| !errors.is_valid(*i)));
+        });
+    }
+
+    #[test]
+    fn functions_with_context_see_their_batch_and_nulls() {
+        let input = Exported::nullable(&[Some(1_i64), None, Some(3), None, Some(5)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 3,
+            ..ArrowUdfExecOptions::default()
+        };
+        let contexts = Mutex::new(Vec::new());
+        let (schema, data) = input
+            .with_array(|array| {
+                map_with_ctx(array, &options, |x: i64, ctx| {
+                    contexts.lock().unwrap().push(ctx);
+                    x + ctx.index as i64
+                })
+            })
+            .unwrap();
+        let out = Exported::new(&schema, data);
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [Some(1), None, Some(5), None, Some(9)]
+        );
+        let mut contexts = contexts.into_inner().unwrap();
+        contexts.sort_by_key(|ctx| ctx.index);
+        assert_eq!(contexts.len(), 5);
+        assert_eq!(
+            contexts[3],
+            RowCtx {
+                index: 3,
+                is_null: true,
+                len: 5,
+                null_count: 2,
+                batch_start: 3,
+                batch_len: 2,
+                batch_null_count: 1,
+            }
+        );
+        assert_eq!(
+            (contexts[0].batch_len, contexts[0].batch_null_count),
+            (3, 1)
+        );
+    }
+
+    #[test]
+    fn rows_with_context_are_computed_for_every_element() {
+        let schema = export::constant_schema(ArrowType::Int64, "x");
+        let input = Exported::new(&schema, ArrayData::constant(7_i64, 3));
+        let (schema, data) = input
+            .with_array(|array| {
+                map_rows_with_ctx(array, &ArrowUdfExecOptions::default(), |x: i64, ctx| {
+                    (x, ctx.index as i64)
+                })
+            })
+            .unwrap();
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.child_values::<i64>(0), [Some(7); 3]);
+        assert_eq!(out.child_values::<i64>(1), [Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn stateful_maps_run_their_batches_in_order() {
+        let input = Exported::primitive(&(1..=8_i64).collect::<Vec<_>>());
+        let options = ArrowUdfExecOptions {
+            batch_size: 4,
+            num_threads: 4,
+            ..ArrowUdfExecOptions::default()
+        };
+        let order = Mutex::new(Vec::new());
+        let (schema, data) = input
+            .with_array(|array| {
+                map_stateful(array, &options, |sum: &mut i64, x: i64| {
+                    order.lock().unwrap().push(x);
+                    *sum += x;
+                    *sum
+                })
+            })
+            .unwrap();
+        assert_eq!(order.into_inner().unwrap(), (1..=8).collect::<Vec<_>>());
+        let out = Exported::new(&schema, data);
+        assert_eq!(out.values::<i64>(), [1, 3, 6, 10, 5, 11, 18, 26]);
+
+        let schema = export::constant_schema(ArrowType::Int64, "x");
+        let constant = Exported::new(&schema, ArrayData::constant(2_i64, 3));
+        let (schema, data) = constant
+            .with_array(|array| {
+                let options = ArrowUdfExecOptions::default();
+                map_stateful(array, &options, |n: &mut i64, x: i64| {
+                    *n += 1;
+                    x * *n
+                })
+            })
+            .unwrap();
+        assert_eq!(Exported::new(&schema, data).values::<i64>(), [2, 4, 6]);
+    }
+
+    #[test]
+    fn setup_and_teardown_run_around_every_batch() {
+        let input = Exported::nullable(&[Some(1_i64), None, Some(3), Some(4), Some(5)]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let (setups, teardowns) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let (schema, data) = input
+            .with_array(|array| {
+                map_with_setup(
+                    array,
+                    &options,
+                    |options| {
+                        setups.fetch_add(1, Ordering::SeqCst);
+                        Ok(options.batch_size * 10)
+                    },
+                    |offset: &i64, x: i64| x + offset,
+                    |_| {
+                        teardowns.fetch_add(1, Ordering::SeqCst);
+                    },
+                )
+            })
+            .unwrap();
+        let out = Exported::new(&schema, data);
+        assert_eq!(
+            out.nullable_values::<i64>(),
+            [Some(21), None, Some(23), Some(24), Some(25)]
+        );
+        assert_eq!(setups.load(Ordering::SeqCst), 3);
+        assert_eq!(teardowns.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn teardown_runs_when_the_batch_fails() {
+        let input = Exported::primitive(&[1_i64, 2, 3, 4]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 2,
+            ..ArrowUdfExecOptions::default()
+        };
+        let teardowns = AtomicUsize::new(0);
+        let teardown = |_: i64| {
+            teardowns.fetch_add(1, Ordering::SeqCst);
+        };
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            input.with_array(|array| {
+                let f = |_: &i64, x: i64| if x == 3 { panic!("row 3") } else { x };
+                map_with_setup(array, &options, |_| Ok(0_i64), f, teardown)
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(teardowns.load(Ordering::SeqCst), 2);
+        let failed = input.with_array(|array| {
+            let setup = |_: &ArrowUdfExecOptions| -> Result<i64> { Err(Error::NullValue) };
+            map_with_setup(array, &options, setup, |_, x: i64| x, teardown).err()
+        });
+        assert_eq!(failed, Some(Error::NullValue));
+        assert_eq!(teardowns.load(Ordering::SeqCst), 2);
+    }
+
+    static OFFSETS_RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    fn load_offset(options: &ArrowUdfExecOptions) -> Result<i64> {
+        match options.batch_size {
+            1 => Err(Error::InvalidArgument(
+                "no offset for single rows".to_string(),
+            )),
+            _ => Ok(100),
+        }
+    }
+
+    fn release_offset(_: i64) {
+        OFFSETS_RELEASED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Every element plus the offset loaded at the start of its batch.
+    #[arrow_udf(setup = load_offset, teardown = release_offset)]
+    fn arrow_udf_test_add_offset(offset: &i64, x: i32) -> i64 {
+        x as i64 + offset
+    }
+
+    #[test]
+    fn arrow_udf_functions_are_entry_points() {
+        let input = Exported::nullable(&[Some(1_i32), None, Some(-1)]);
+        let call = |options: &ArrowUdfExecOptions| {
+            let mut out = Exported::empty();
+            let status = unsafe {
+                arrow_udf_test_add_offset(
+                    &input.schema,
+                    &input.array,
+                    options,
+                    &mut out.schema,
+                    &mut out.array,
+                )
+            };
+            (status, out)
+        };
+        let (status, out) = call(&ArrowUdfExecOptions::default());
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.nullable_values::<i64>(), [Some(101), None, Some(99)]);
+        assert_eq!(OFFSETS_RELEASED.load(Ordering::SeqCst), 1);
+        let single = ArrowUdfExecOptions {
+            batch_size: 1,
+            ..ArrowUdfExecOptions::default()
+        };
+        assert_eq!(call(&single).0, ArrowUdfStatus::InvalidArgument);
+        let wrong_type = Exported::primitive(&[1_i64]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&wrong_type.schema, &wrong_type.array);
+            arrow_udf_test_add_offset(schema, array, ptr::null(), &mut out.schema, &mut out.array)
+        };
+        assert_eq!(status, ArrowUdfStatus::UnsupportedType);
+    }
+}
@@ -0,0 +1,250 @@
+//! Table functions, returning any number of rows for every input row.
+//!
+//! Functions like splitting strings into words return zero, one or many
+//! rows per input row, so their results can't be aligned with the input by
+//! position like the results of maps. They return a struct array with an
+//! Int64 `row` field, the position in the input of the row every output row
+//! comes from, followed by the fields of the rows, in the order of the
+//! input. Hosts join the result with the input on `row`, or use it to repeat
+//! the other columns of the input.
+//!
+//! Null input rows produce no rows, unless the null policy is `Error`.
+
+#[cfg(feature = "strings")]
+use std::ffi::{c_char, CStr};
+
+use crate::array::ArrowArray;
+use crate::buffer::Buffer;
+#[cfg(feature = "strings")]
+use crate::error::ArrowUdfStatus;
+use crate::error::{Error, Result};
+use crate::exec;
+use crate::export::ArrayData;
+#[cfg(feature = "strings")]
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::row::{ArrowField, ArrowRow};
+use crate::schema::{ArrowType, Schema};
+#[cfg(feature = "strings")]
+use crate::utf8::utf8_ffi;
+
+/// Name of the field with the position of the input row of every output
+/// row.
+pub const ROW_FIELD: &str = "row";
+
+/// Schema of the results of table functions returning `R` rows.
+pub fn schema<'a, R: ArrowRow<'a>>(name: &str) -> Schema {
+    let mut fields = vec![Schema::new(ArrowType::Int64, ROW_FIELD)];
+    fields.extend(R::fields());
+    Schema::new(ArrowType::Struct, name).with_children(fields)
+}
+
+/// The rows returned by `f` for every element of `array`, read as `A`, with
+/// the position of the element they come from.
+pub fn flat_map<'a, A, R, I, F>(
+    array: &ArrowArray<'a>,
+    options: &ArrowUdfExecOptions,
+    mut f: F,
+) -> Result<(Schema, ArrayData)>
+where
+    A: ArrowField<'a>,
+    R: ArrowRow<'a>,
+    I: IntoIterator<Item = R>,
+    F: FnMut(A) -> I,
+{
+    if array.data_type() != A::DATA_TYPE {
+        return Err(Error::UnsupportedType(format!(
+            "expected {:?} input, got {:?}",
+            A::DATA_TYPE,
+            array.data_type()
+        )));
+    }
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    let (mut positions, mut rows) = (Vec::new(), Vec::new());
+    exec::for_each_batch(array.len(), options, |batch| {
+        for i in batch {
+            if !array.is_valid(i) {
+                continue;
+            }
+            for row in f(A::read(array, i)?) {
+                positions.push(i as i64);
+                rows.push(row);
+            }
+        }
+        Ok(())
+    })?;
+    let mut children = vec![ArrayData::primitive(
+        Buffer::from_slice(&positions),
+        positions.len(),
+    )];
+    children.extend(R::build(&rows));
+    Ok((
+        schema::<R>(&array.schema().name),
+        ArrayData::struct_array(children, rows.len()),
+    ))
+}
+
+/// A part of a string split by `split`.
+#[cfg(feature = "strings")]
+#[derive(ArrowRow)]
+pub struct Part<'a> {
+    pub value: &'a str,
+}
+
+/// The parts of every string of `array`, a Utf8 array, separated by
+/// `separator`, as rows of `value`. Empty strings have a single empty part.
+#[cfg(feature = "strings")]
+pub fn split(
+    array: &ArrowArray,
+    separator: &str,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    if separator.is_empty() {
+        return Err(Error::InvalidArgument("the separator is empty".to_string()));
+    }
+    flat_map(array, options, |value: &str| {
+        value.split(separator).map(|value| Part { value })
+    })
+}
+
+/// Schema of the results of `split` of arrays described by `input`.
+#[cfg(feature = "strings")]
+pub(crate) fn split_schema(input: &Schema) -> Schema {
+    schema::<Part>(&input.name)
+}
+
+/// The parts of every string of a Utf8 array separated by `separator`, as a
+/// struct array with a row per part, of the Int64 `row` of the string and
+/// the Utf8 `value` of the part. Null strings have no parts.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `separator` must be a valid null-terminated string, `options` must be
+/// null or valid, and `out_schema` and `out_array` must be valid for writes.
+#[cfg(feature = "strings")]
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_split(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    separator: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let separator = CStr::from_ptr(separator).to_str().map_err(|_| {
+                Error::InvalidArgument("the separator is not valid UTF-8".to_string())
+            })?;
+            split(array, separator, options)
+        },
+    )
+}
+
+#[cfg(all(test, feature = "strings"))]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::registry;
+    use crate::testing::Exported;
+
+    fn split_ffi(
+        input: &Exported,
+        separator: &str,
+        options: &ArrowUdfExecOptions,
+    ) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let separator = CString::new(separator).unwrap();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            arrow_udf_split(
+                &input.schema,
+                &input.array,
+                separator.as_ptr(),
+                options,
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    /// The `row` and `value` fields of the result of `split`.
+    fn parts(out: &Exported) -> Vec<(i64, String)> {
+        out.with_array(|array| {
+            let (rows, values) = (array.child(0), array.child(1));
+            (0..array.len())
+                .map(|i| {
+                    (
+                        rows.values::<i64>()[i],
+                        values.utf8_value(i).unwrap().to_string(),
+                    )
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn strings_are_split_into_rows() {
+        let input = Exported::utf8(&[Some("a b"), None, Some(""), Some("c")]);
+        let options = ArrowUdfExecOptions {
+            batch_size: 1,
+            ..ArrowUdfExecOptions::default()
+        };
+        let out = split_ffi(&input, " ", &options).unwrap();
+        let expected = [(0, "a"), (0, "b"), (2, ""), (3, "c")];
+        let expected: Vec<_> = expected.map(|(row, value)| (row, value.to_string())).into();
+        assert_eq!(parts(&out), expected);
+        out.with_array(|array| {
+            assert_eq!(array.schema(), &schema::<Part>("x"));
+            assert_eq!(array.schema().children[0].name, ROW_FIELD);
+        });
+        let declared = registry::output_schema("split", &Schema::new(ArrowType::Utf8, "x"));
+        assert_eq!(declared, Ok(schema::<Part>("x")));
+    }
+
+    #[test]
+    fn rows_keep_their_position_in_sliced_inputs() {
+        let mut input = Exported::utf8(&[Some("a,b"), Some("c,d,e")]);
+        (input.array.offset, input.array.length) = (1, 1);
+        let out = split_ffi(&input, ",", &ArrowUdfExecOptions::default()).unwrap();
+        let expected = [(0, "c"), (0, "d"), (0, "e")];
+        let expected: Vec<_> = expected.map(|(row, value)| (row, value.to_string())).into();
+        assert_eq!(parts(&out), expected);
+    }
+
+    #[test]
+    fn invalid_inputs_fail() {
+        let input = Exported::utf8(&[Some("a"), None]);
+        let options = ArrowUdfExecOptions::default();
+        assert_eq!(
+            split_ffi(&input, "", &options).err(),
+            Some(ArrowUdfStatus::InvalidArgument)
+        );
+        let error = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..options
+        };
+        assert_eq!(
+            split_ffi(&input, " ", &error).err(),
+            Some(ArrowUdfStatus::NullValue)
+        );
+        let integers = Exported::primitive(&[1_i64]);
+        assert_eq!(
+            split_ffi(&integers, " ", &options).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+    }
+}
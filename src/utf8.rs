@@ -0,0 +1,583 @@
+//! Kernels over Utf8 arrays.
+//!
+//! Strings are processed as Unicode characters, not bytes: lengths and
+//! positions count characters, and results never split a multi-byte
+//! character. Results of kernels returning strings can have a different
+//! byte length than their input, for example when converting the case, so
+//! they are built one string at a time with a `Utf8Builder`. The strings
+//! converting the case are written in the scratch arena of the thread
+//! instead of allocating a `String` for every one, see `arena`.
+//!
+//! The strings of the inputs are checked to be valid UTF-8 when they are
+//! imported, all at once, since making a `&str` of invalid bytes is
+//! undefined behavior. Hosts whose strings are already known to be valid
+//! skip the check with `ARROW_UDF_FLAG_TRUSTED_UTF8`.
+
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+use crate::arena::{self, Arena};
+use crate::array::ArrowArray;
+use crate::binary::Utf8Builder;
+use crate::bitmap::BitmapBuilder;
+use crate::buffer::Buffer;
+use crate::error::{ffi_guard, ArrowUdfStatus, Error, Result};
+use crate::export::{self, ArrayData};
+use crate::ffi::{ArrowCDataInterfaceArray, ArrowCDataInterfaceSchema};
+use crate::options::{ArrowUdfExecOptions, NullPolicy};
+use crate::schema::{ArrowType, Schema};
+use crate::udf;
+
+/// Host flag declaring that the strings of the Utf8 inputs are valid UTF-8,
+/// so they are used without checking them. Strings that aren't are
+/// undefined behavior.
+pub const ARROW_UDF_FLAG_TRUSTED_UTF8: u32 = 32;
+
+/// `array` with its strings checked at once, failing with
+/// `Error::InvalidUtf8` with the first invalid one, or trusted when the host
+/// sets `ARROW_UDF_FLAG_TRUSTED_UTF8`.
+pub(crate) fn import_utf8<'a>(
+    array: ArrowArray<'a>,
+    options: &ArrowUdfExecOptions,
+) -> Result<ArrowArray<'a>> {
+    match options.flags & ARROW_UDF_FLAG_TRUSTED_UTF8 != 0 {
+        true => Ok(unsafe { array.assume_utf8() }),
+        false => array.validate_utf8(),
+    }
+}
+
+/// Call `f` with the string of every element of `array`, or `None` for
+/// nulls, after checking the type of the array and the null policy.
+pub(crate) fn for_each_str(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    mut f: impl FnMut(Option<&str>),
+) -> Result<()> {
+    check_strings(array, options)?;
+    let batch_len = options.batch_len(array.len());
+    for i in 0..array.len() {
+        if i % batch_len == 0 {
+            options.check_cancelled()?;
+        }
+        if array.is_valid(i) {
+            f(Some(array.utf8_value(i)?));
+        } else {
+            f(None);
+        }
+    }
+    Ok(())
+}
+
+/// Check that `array` is Utf8, and that it has no nulls if the null policy
+/// fails with them.
+fn check_strings(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<()> {
+    if array.data_type() != ArrowType::Utf8 {
+        return Err(Error::UnsupportedType(format!(
+            "expected Utf8 input, got {:?}",
+            array.data_type()
+        )));
+    }
+    if options.null_policy()? == NullPolicy::Error && array.null_count() > 0 {
+        return Err(Error::NullValue);
+    }
+    Ok(())
+}
+
+/// Apply `f` to every string of `array`. Null elements are null in the
+/// result.
+fn map_strings(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: impl Fn(&str) -> String,
+) -> Result<(Schema, ArrayData)> {
+    let mut builder = Utf8Builder::with_capacity(array.len());
+    for_each_str(array, options, |value| {
+        builder.push(value.map(&f).as_deref())
+    })?;
+    Ok((
+        Schema::new(ArrowType::Utf8, &array.schema().name),
+        builder.finish(),
+    ))
+}
+
+/// Apply `f` to every string of `array`, with `f` writing its result in the
+/// scratch arena, which is reset after every batch. Null elements are null
+/// in the result.
+fn map_strings_in_scratch(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+    f: impl for<'s> Fn(&str, &'s Arena) -> &'s str,
+) -> Result<(Schema, ArrayData)> {
+    check_strings(array, options)?;
+    let mut builder = Utf8Builder::with_capacity(array.len());
+    let batch_len = options.batch_len(array.len());
+    for start in (0..array.len()).step_by(batch_len) {
+        options.check_cancelled()?;
+        arena::with_scratch(|arena| {
+            for i in start..(start + batch_len).min(array.len()) {
+                match array.is_valid(i) {
+                    true => builder.push(Some(f(array.utf8_value(i)?, arena))),
+                    false => builder.push(None),
+                }
+            }
+            Ok::<_, Error>(())
+        })?;
+        arena::reset_scratch();
+    }
+    Ok((
+        Schema::new(ArrowType::Utf8, &array.schema().name),
+        builder.finish(),
+    ))
+}
+
+/// `value` with every character replaced by the ones of `f`, or by
+/// `ascii` for ASCII characters, in `arena`.
+fn convert_case<'s, I: Iterator<Item = char>>(
+    value: &str,
+    arena: &'s Arena,
+    ascii: fn(&u8) -> u8,
+    f: fn(char) -> I,
+) -> &'s str {
+    // The case mappings of Unicode make strings at most three times longer
+    // in UTF-8.
+    let bytes = arena.alloc_slice_fill(value.len() * 3, 0u8);
+    let mut len = 0;
+    for c in value.chars() {
+        if c.is_ascii() {
+            bytes[len] = ascii(&(c as u8));
+            len += 1;
+            continue;
+        }
+        for c in f(c) {
+            len += c.encode_utf8(&mut bytes[len..]).len();
+        }
+    }
+    unsafe { std::str::from_utf8_unchecked(&bytes[..len]) }
+}
+
+/// `value` converted to uppercase, in `arena`.
+pub fn to_uppercase_in<'s>(value: &str, arena: &'s Arena) -> &'s str {
+    if value.is_ascii() {
+        let value = arena.alloc_str(value);
+        value.make_ascii_uppercase();
+        return value;
+    }
+    convert_case(value, arena, u8::to_ascii_uppercase, char::to_uppercase)
+}
+
+/// `value` converted to lowercase, in `arena`. A capital sigma at the end of
+/// a word becomes a final sigma, so the strings with one are converted with
+/// `str::to_lowercase`.
+pub fn to_lowercase_in<'s>(value: &str, arena: &'s Arena) -> &'s str {
+    if value.is_ascii() {
+        let value = arena.alloc_str(value);
+        value.make_ascii_lowercase();
+        return value;
+    }
+    if value.contains('Σ') {
+        return arena.alloc_str(&value.to_lowercase());
+    }
+    convert_case(value, arena, u8::to_ascii_lowercase, char::to_lowercase)
+}
+
+/// Every string of `array` converted to uppercase.
+pub fn upper(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)> {
+    map_strings_in_scratch(array, options, to_uppercase_in)
+}
+
+/// Every string of `array` converted to lowercase.
+pub fn lower(array: &ArrowArray, options: &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)> {
+    map_strings_in_scratch(array, options, to_lowercase_in)
+}
+
+/// Number of characters of every string of `array`, as Int32.
+pub fn utf8_length(
+    array: &ArrowArray,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let mut lengths = Vec::with_capacity(array.len());
+    for_each_str(array, options, |value| {
+        lengths.push(value.map_or(0, |value| value.chars().count() as i32))
+    })?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok((
+        Schema::new(ArrowType::Int32, &array.schema().name),
+        ArrayData::primitive(Buffer::from_slice(&lengths), array.len())
+            .with_validity(validity, null_count),
+    ))
+}
+
+/// The part of `value` starting at the character `start`, and of at most
+/// `length` characters. A negative `start` counts from the end.
+fn substring(value: &str, start: i64, length: usize) -> &str {
+    let start = match usize::try_from(start) {
+        Ok(start) => start,
+        Err(_) => {
+            let n_chars = value.chars().count();
+            n_chars.saturating_sub(start.unsigned_abs() as usize)
+        }
+    };
+    let byte_index = |chars: usize| {
+        value
+            .char_indices()
+            .nth(chars)
+            .map_or(value.len(), |(i, _)| i)
+    };
+    &value[byte_index(start)..byte_index(start.saturating_add(length))]
+}
+
+/// Whether every string of `array` contains `pattern`, as a Boolean array.
+pub fn contains(
+    array: &ArrowArray,
+    pattern: &str,
+    options: &ArrowUdfExecOptions,
+) -> Result<(Schema, ArrayData)> {
+    let mut found = BitmapBuilder::with_capacity(array.len());
+    for_each_str(array, options, |value| {
+        found.push(value.is_some_and(|value| value.contains(pattern)))
+    })?;
+    let (validity, null_count) = udf::output_validity(array);
+    Ok((
+        Schema::new(ArrowType::Boolean, &array.schema().name),
+        ArrayData::primitive(found.finish(), array.len()).with_validity(validity, null_count),
+    ))
+}
+
+/// Import the array of an entry point, with its strings checked or trusted
+/// by `import_utf8`, compute the result with `f`, and export it.
+pub(crate) unsafe fn utf8_ffi(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+    f: impl FnOnce(&ArrowArray, &ArrowUdfExecOptions) -> Result<(Schema, ArrayData)>,
+) -> ArrowUdfStatus {
+    ffi_guard(|| {
+        let options = ArrowUdfExecOptions::from_ffi(options)?;
+        let schema = Schema::from_ffi(&*schema)?;
+        let array = import_utf8(ArrowArray::import(&schema, &*array)?, &options)?;
+        let (out, data) = f(&array, &options)?;
+        export::export_to(&out, &Arc::new(data), out_schema, out_array)?;
+        Ok(())
+    })
+}
+
+/// Number of characters, not bytes, of every string of a Utf8 array, as an
+/// Int32 array.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_utf8_length(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(schema, array, options, out_schema, out_array, utf8_length)
+}
+
+/// Every string of a Utf8 array converted to uppercase, following the
+/// Unicode rules, which can change the length of the strings.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_upper(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(schema, array, options, out_schema, out_array, upper)
+}
+
+/// Every string of a Utf8 array converted to lowercase, following the
+/// Unicode rules, which can change the length of the strings.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_lower(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(schema, array, options, out_schema, out_array, lower)
+}
+
+/// The part of every string of a Utf8 array starting at the character
+/// `start`, counting from 0, and of at most `length` characters. A negative
+/// `start` counts from the end of the string, so `-1` is the last character.
+/// Strings shorter than `start` result in an empty string.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `options` must be null or valid, and `out_schema` and `out_array` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_substring(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    start: i64,
+    length: i64,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let length = usize::try_from(length).map_err(|_| {
+                Error::InvalidArgument(format!("the length can't be negative, got {length}"))
+            })?;
+            map_strings(array, options, |value| {
+                substring(value, start, length).to_string()
+            })
+        },
+    )
+}
+
+/// Whether every string of a Utf8 array contains the string `pattern`, as a
+/// Boolean array. The match is exact and case sensitive.
+///
+/// # Safety
+///
+/// `schema` and `array` must point to a valid Arrow C Data Interface array,
+/// `pattern` must be a valid null-terminated string, `options` must be null
+/// or valid, and `out_schema` and `out_array` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn arrow_udf_contains(
+    schema: *const ArrowCDataInterfaceSchema,
+    array: *const ArrowCDataInterfaceArray,
+    pattern: *const c_char,
+    options: *const ArrowUdfExecOptions,
+    out_schema: *mut ArrowCDataInterfaceSchema,
+    out_array: *mut ArrowCDataInterfaceArray,
+) -> ArrowUdfStatus {
+    utf8_ffi(
+        schema,
+        array,
+        options,
+        out_schema,
+        out_array,
+        |array, options| {
+            let pattern = CStr::from_ptr(pattern).to_str().map_err(|_| {
+                Error::InvalidArgument("the pattern is not valid UTF-8".to_string())
+            })?;
+            contains(array, pattern, options)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+    use crate::options::ARROW_UDF_NULL_POLICY_ERROR;
+    use crate::testing::Exported;
+
+    type EntryPoint = unsafe extern "C" fn(
+        *const ArrowCDataInterfaceSchema,
+        *const ArrowCDataInterfaceArray,
+        *const ArrowUdfExecOptions,
+        *mut ArrowCDataInterfaceSchema,
+        *mut ArrowCDataInterfaceArray,
+    ) -> ArrowUdfStatus;
+
+    fn run(f: EntryPoint, input: &Exported) -> std::result::Result<Exported, ArrowUdfStatus> {
+        let mut out = Exported::empty();
+        let status = unsafe {
+            f(
+                &input.schema,
+                &input.array,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        match status {
+            ArrowUdfStatus::Ok => Ok(out),
+            status => Err(status),
+        }
+    }
+
+    fn strings(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|value| value.map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn lengths_and_cases_count_characters() {
+        let input = Exported::utf8(&[Some("straße"), None, Some(""), Some("Ωmega")]);
+        let lengths = run(arrow_udf_utf8_length, &input).unwrap();
+        assert_eq!(
+            lengths.nullable_values::<i32>(),
+            [Some(6), None, Some(0), Some(5)]
+        );
+        let upper = run(arrow_udf_upper, &input).unwrap();
+        assert_eq!(
+            upper.strings(),
+            strings(&[Some("STRASSE"), None, Some(""), Some("ΩMEGA")])
+        );
+        let lower = run(arrow_udf_lower, &input).unwrap();
+        assert_eq!(
+            lower.strings(),
+            strings(&[Some("straße"), None, Some(""), Some("ωmega")])
+        );
+    }
+
+    #[test]
+    fn cases_converted_in_the_arena_match_the_standard_library() {
+        let mut arena = Arena::new();
+        // Every character, so the longest mappings fit in the three times
+        // the bytes allocated for them.
+        let every_char: String = (char::MIN..=char::MAX).collect();
+        for value in [every_char.as_str(), "ΌΣΟΣ ΣΑΣ", "ΐ ŉ ǰ İ ß", "plain ASCII"] {
+            assert_eq!(to_uppercase_in(value, &arena), value.to_uppercase());
+            assert_eq!(to_lowercase_in(value, &arena), value.to_lowercase());
+        }
+        for c in char::MIN..=char::MAX {
+            let value = c.to_string();
+            assert_eq!(to_uppercase_in(&value, &arena), value.to_uppercase());
+            assert_eq!(to_lowercase_in(&value, &arena), value.to_lowercase());
+            arena.reset();
+        }
+    }
+
+    #[test]
+    fn substrings_never_split_characters() {
+        assert_eq!(substring("añb€", 1, 2), "ñb");
+        assert_eq!(substring("añb€", -1, 5), "€");
+        assert_eq!(substring("añb€", -10, 1), "a");
+        assert_eq!(substring("añb€", 10, 1), "");
+        let input = Exported::utf8(&[Some("añb€"), None]);
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            arrow_udf_substring(
+                schema,
+                array,
+                2,
+                9,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.strings(), strings(&[Some("b€"), None]));
+        let mut failed = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            let out = (&mut failed.schema, &mut failed.array);
+            arrow_udf_substring(schema, array, 0, -1, ptr::null(), out.0, out.1)
+        };
+        assert_eq!(status, ArrowUdfStatus::InvalidArgument);
+    }
+
+    #[test]
+    fn contains_is_case_sensitive() {
+        let input = Exported::utf8(&[Some("Arrow"), Some("sparrow"), None]);
+        let pattern = CString::new("rrow").unwrap();
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            let pattern = pattern.as_ptr();
+            arrow_udf_contains(
+                schema,
+                array,
+                pattern,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.booleans(), [Some(true), Some(true), None]);
+        let mut out = Exported::empty();
+        let upper = CString::new("ARR").unwrap();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            let pattern = upper.as_ptr();
+            arrow_udf_contains(
+                schema,
+                array,
+                pattern,
+                ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.booleans(), [Some(false), Some(false), None]);
+    }
+
+    #[test]
+    fn inputs_are_validated() {
+        let binary = Exported::primitive(&[1_u8]);
+        assert_eq!(
+            run(arrow_udf_upper, &binary).err(),
+            Some(ArrowUdfStatus::UnsupportedType)
+        );
+        let mut invalid = crate::binary::BinaryBuilder::with_capacity(1);
+        invalid.push(Some(&[0xff, 0xfe]));
+        let invalid = Exported::new(&Schema::new(ArrowType::Utf8, "x"), invalid.finish());
+        let status = run(arrow_udf_utf8_length, &invalid).err();
+        assert_eq!(status, Some(ArrowUdfStatus::InvalidUtf8));
+        let nulls = Exported::utf8(&[None]);
+        let options = ArrowUdfExecOptions {
+            null_policy: ARROW_UDF_NULL_POLICY_ERROR,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&nulls.schema, &nulls.array);
+            arrow_udf_lower(schema, array, &options, &mut out.schema, &mut out.array)
+        };
+        assert_eq!(status, ArrowUdfStatus::NullValue);
+    }
+
+    #[test]
+    fn trusted_strings_are_not_checked_again() {
+        let input = Exported::utf8(&[Some("Añb"), None]);
+        let options = ArrowUdfExecOptions {
+            flags: ARROW_UDF_FLAG_TRUSTED_UTF8,
+            ..ArrowUdfExecOptions::default()
+        };
+        let mut out = Exported::empty();
+        let status = unsafe {
+            let (schema, array) = (&input.schema, &input.array);
+            arrow_udf_upper(schema, array, &options, &mut out.schema, &mut out.array)
+        };
+        assert_eq!(status, ArrowUdfStatus::Ok);
+        assert_eq!(out.strings(), strings(&[Some("AÑB"), None]));
+        let schema = Schema::new(ArrowType::Utf8, "x");
+        let array = unsafe { ArrowArray::new(&schema, &input.array) };
+        let trusted = import_utf8(array, &options).unwrap();
+        assert_eq!(trusted.utf8_value(0), Ok("Añb"));
+    }
+}
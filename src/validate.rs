@@ -0,0 +1,281 @@
+//! Validation of the structure of the arrays received by the entry points.
+//!
+//! The accessors of `ArrowArray` trust the producer, like the C Data
+//! Interface does: a missing child, or offsets going backwards, make them
+//! panic, which `ffi_guard` reports as `ArrowUdfStatus::Panic`. Hosts built
+//! with `panic = "abort"` can't catch panics, and neither can this library
+//! when it's built that way, so the same array aborts the host. With the
+//! `abort_safe` feature, and in any build with `panic = "abort"`,
+//! `ArrowArray::import` validates every array an entry point receives
+//! before the call runs, so malformed arrays fail it with `InvalidArgument`
+//! instead, and the accessors don't find anything to panic about.
+//!
+//! The sizes of the buffers aren't part of the C Data Interface, so they
+//! can't be checked: buffers must still be as long as the length, the
+//! offset and the offsets of the array say.
+
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::schema::{ArrowType, UnionMode};
+
+/// Number of buffers of the arrays of `data_type` in the C Data Interface.
+fn n_buffers(data_type: ArrowType) -> i64 {
+    match data_type {
+        ArrowType::RunEndEncoded => 0,
+        ArrowType::Struct | ArrowType::Union(UnionMode::Sparse) => 1,
+        ArrowType::Binary | ArrowType::LargeBinary | ArrowType::Utf8 => 3,
+        _ => 2,
+    }
+}
+
+/// Whether `offsets` are not negative and never go backwards.
+fn valid_offsets<T: Copy + Default + PartialOrd>(offsets: &[T]) -> bool {
+    offsets[0] >= T::default() && offsets.windows(2).all(|pair| pair[0] <= pair[1])
+}
+
+/// Check that the buffers and children of `array`, and of its children,
+/// are consistent with its schema and with each other, so the accessors of
+/// `ArrowArray` can't panic reading it. Fails with `Error::InvalidArgument`,
+/// naming the field of the first inconsistency.
+pub fn validate(array: &ArrowArray) -> Result<()> {
+    let (schema, ffi) = (array.schema(), array.ffi());
+    let invalid = |message: String| {
+        Err(Error::InvalidArgument(format!(
+            "malformed {:?} array {:?}: {message}",
+            schema.data_type, schema.name
+        )))
+    };
+    if ffi.length < 0 || ffi.offset < 0 || ffi.null_count < -1 {
+        return invalid(format!(
+            "length {}, offset {} and null count {}",
+            ffi.length, ffi.offset, ffi.null_count
+        ));
+    }
+    let Some(end) = ffi.offset.checked_add(ffi.length) else {
+        return invalid("offset and length overflow".to_string());
+    };
+    if ffi.n_buffers != n_buffers(schema.data_type)
+        || ffi.n_children != schema.children.len() as i64
+    {
+        return invalid(format!(
+            "{} buffers and {} children, expected {} buffers and {} children",
+            ffi.n_buffers,
+            ffi.n_children,
+            n_buffers(schema.data_type),
+            schema.children.len()
+        ));
+    }
+    if (ffi.n_buffers > 0 && ffi.buffers.is_null())
+        || (ffi.n_children > 0 && ffi.children.is_null())
+        || (0..ffi.n_children as usize).any(|i| unsafe { (*ffi.children.add(i)).is_null() })
+    {
+        return invalid("null buffers or children".to_string());
+    }
+    let buffer = |i: usize| unsafe { ffi.buffer(i) };
+    if ffi.null_count > 0 && ffi.n_buffers > 0 && buffer(0).is_null() {
+        return invalid(format!("{} nulls without validity", ffi.null_count));
+    }
+    let children: Vec<ArrowArray> = (0..ffi.n_children as usize)
+        .map(|i| array.child(i))
+        .collect();
+    children.iter().try_for_each(validate)?;
+    let filled = ffi.length > 0;
+    match schema.data_type {
+        ArrowType::Binary | ArrowType::Utf8 | ArrowType::LargeBinary => {
+            if filled && buffer(1).is_null() {
+                return invalid("no offsets".to_string());
+            }
+            let valid = match schema.data_type {
+                ArrowType::LargeBinary => valid_offsets(array.large_binary_offsets()),
+                _ => valid_offsets(array.binary_offsets()),
+            };
+            if !valid {
+                return invalid("offsets negative or going backwards".to_string());
+            }
+            if !array.binary_data().is_empty() && buffer(2).is_null() {
+                return invalid("no data".to_string());
+            }
+        }
+        ArrowType::Map => {
+            if filled && buffer(1).is_null() {
+                return invalid("no offsets".to_string());
+            }
+            let offsets = array.map_offsets();
+            if !valid_offsets(offsets) {
+                return invalid("offsets negative or going backwards".to_string());
+            }
+            let Some(entries) = children
+                .first()
+                .filter(|entries| entries.data_type() == ArrowType::Struct)
+                .filter(|entries| entries.schema().children.len() == 2)
+            else {
+                return invalid("entries not a struct of keys and values".to_string());
+            };
+            let entries = entries.len() as i64;
+            if i64::from(*offsets.last().unwrap()) > entries {
+                return invalid(format!("offsets beyond the {entries} entries"));
+            }
+        }
+        ArrowType::Struct | ArrowType::Union(UnionMode::Sparse) => {
+            if let Some(child) = children.iter().find(|child| (child.len() as i64) < end) {
+                return invalid(format!(
+                    "field {:?} of {} rows, shorter than the array",
+                    child.schema().name,
+                    child.len()
+                ));
+            }
+            if schema.data_type != ArrowType::Struct && filled && buffer(0).is_null() {
+                return invalid("no type ids".to_string());
+            }
+        }
+        ArrowType::Union(UnionMode::Dense) => {
+            if filled && (buffer(0).is_null() || buffer(1).is_null()) {
+                return invalid("no type ids or offsets".to_string());
+            }
+        }
+        ArrowType::RunEndEncoded => {
+            let [run_ends, values] = &children[..] else {
+                return invalid("no run ends and values".to_string());
+            };
+            if run_ends.len() != values.len() {
+                return invalid(format!(
+                    "{} run ends and {} values",
+                    run_ends.len(),
+                    values.len()
+                ));
+            }
+            let run_ends: Vec<i64> = match run_ends.data_type() {
+                ArrowType::Int16 => run_ends.values::<i16>().iter().map(|v| *v as i64).collect(),
+                ArrowType::Int32 => run_ends.values::<i32>().iter().map(|v| *v as i64).collect(),
+                ArrowType::Int64 => run_ends.values::<i64>().to_vec(),
+                other => return invalid(format!("{other:?} run ends")),
+            };
+            if run_ends.first().is_some_and(|first| *first <= 0)
+                || run_ends.windows(2).any(|pair| pair[1] <= pair[0])
+                || run_ends.last().copied().unwrap_or(0) < end
+            {
+                return invalid("run ends not positive and increasing to the end".to_string());
+            }
+        }
+        _ => {
+            if filled && buffer(1).is_null() {
+                return invalid("no values".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::export::ArrayData;
+    use crate::ffi::ArrowCDataInterfaceArray;
+    use crate::schema::Schema;
+    use crate::testing::Exported;
+
+    /// Validate a copy of the array of `exported`, changed by `tamper`.
+    fn validate_with(
+        exported: &Exported,
+        tamper: impl FnOnce(&mut ArrowCDataInterfaceArray),
+    ) -> Result<()> {
+        let schema = unsafe { Schema::from_ffi(&exported.schema) }.unwrap();
+        let mut ffi = ArrowCDataInterfaceArray { ..exported.array };
+        tamper(&mut ffi);
+        validate(unsafe { &ArrowArray::new(&schema, &ffi) })
+    }
+
+    fn is_malformed(result: Result<()>, message: &str) -> bool {
+        matches!(result, Err(Error::InvalidArgument(m)) if m.contains(message))
+    }
+
+    #[test]
+    fn well_formed_arrays_are_valid() {
+        let int64 = Exported::nullable(&[Some(1_i64), None, Some(3)]);
+        assert_eq!(validate_with(&int64, |_| {}), Ok(()));
+        let strings = Exported::utf8(&[Some("a"), None, Some("bc")]);
+        assert_eq!(validate_with(&strings, |_| {}), Ok(()));
+        let empty = Exported::primitive::<f64>(&[]);
+        assert_eq!(validate_with(&empty, |_| {}), Ok(()));
+        let schema = Schema::new(ArrowType::Struct, "s").with_children(vec![
+            Schema::new(ArrowType::Int32, "a"),
+            Schema::new(ArrowType::Float64, "b"),
+        ]);
+        let data = ArrayData::struct_array(
+            vec![
+                ArrayData::primitive(Buffer::from_slice(&[1_i32, 2]), 2),
+                ArrayData::primitive(Buffer::from_slice(&[0.5_f64, 1.5]), 2),
+            ],
+            2,
+        );
+        assert_eq!(validate_with(&Exported::new(&schema, data), |_| {}), Ok(()));
+    }
+
+    #[test]
+    fn inconsistent_lengths_and_buffers_fail() {
+        let int64 = Exported::primitive(&[1_i64, 2, 3]);
+        let result = validate_with(&int64, |ffi| ffi.length = -1);
+        assert!(is_malformed(result, "length -1"));
+        let result = validate_with(&int64, |ffi| ffi.offset = i64::MAX);
+        assert!(is_malformed(result, "overflow"));
+        let result = validate_with(&int64, |ffi| ffi.n_buffers = 3);
+        assert!(is_malformed(result, "3 buffers and 0 children"));
+        let result = validate_with(&int64, |ffi| ffi.null_count = 1);
+        assert!(is_malformed(result, "1 nulls without validity"));
+        let mut buffers = [std::ptr::null(); 2];
+        let result = validate_with(&int64, |ffi| ffi.buffers = buffers.as_mut_ptr());
+        assert!(is_malformed(result, "no values"));
+    }
+
+    #[test]
+    fn offsets_going_backwards_fail() {
+        let strings = Exported::utf8(&[Some("ab"), Some("c")]);
+        let offsets = [0_i32, 2, 1];
+        let data = unsafe { strings.array.buffer(2) } as *const c_void;
+        let mut buffers = [std::ptr::null(), offsets.as_ptr() as *const c_void, data];
+        let result = validate_with(&strings, |ffi| ffi.buffers = buffers.as_mut_ptr());
+        assert!(is_malformed(result, "going backwards"));
+    }
+
+    #[test]
+    fn struct_fields_shorter_than_the_array_fail() {
+        let schema = Schema::new(ArrowType::Struct, "s")
+            .with_children(vec![Schema::new(ArrowType::Int32, "a")]);
+        let data = ArrayData::struct_array(
+            vec![ArrayData::primitive(Buffer::from_slice(&[1_i32, 2]), 2)],
+            2,
+        );
+        let exported = Exported::new(&schema, data);
+        let result = validate_with(&exported, |ffi| ffi.length = 3);
+        assert!(is_malformed(result, "field \"a\" of 2 rows"));
+        let result = validate_with(&exported, |ffi| ffi.n_children = 0);
+        assert!(is_malformed(
+            result,
+            "0 children, expected 1 buffers and 1 children"
+        ));
+    }
+
+    #[cfg(feature = "abort_safe")]
+    #[test]
+    fn entry_points_reject_malformed_arrays() {
+        let input = Exported::primitive(&[1_i64, 2, 3]);
+        let mut array = ArrowCDataInterfaceArray { ..input.array };
+        array.null_count = 2;
+        let mut out = Exported::empty();
+        let status = unsafe {
+            crate::arrow_udf_distances(
+                &input.schema,
+                &array,
+                0,
+                std::ptr::null(),
+                &mut out.schema,
+                &mut out.array,
+            )
+        };
+        assert_eq!(status, crate::error::ArrowUdfStatus::InvalidArgument);
+        assert!(out.array.release.is_none());
+    }
+}
@@ -0,0 +1,229 @@
+//! Mean, variance and standard deviation aggregates, computed with
+//! Welford's online algorithm.
+//!
+//! Every group keeps its count, its mean and the sum of the squared
+//! differences from the mean (`M2`), updated with every value. Unlike
+//! computing the sum of the values and of their squares, this doesn't lose
+//! precision when the variance is small compared to the mean. States are
+//! merged with the formula of Chan et al. for parallel variance.
+
+use std::ops::Range;
+
+use crate::aggregate::{self, Accumulator, Number};
+use crate::array::ArrowArray;
+use crate::error::{Error, Result};
+use crate::export::ArrayData;
+use crate::schema::ArrowType;
+
+/// Statistic computed by the accumulator. The variance and the standard
+/// deviation are divided by the count minus the delta degrees of freedom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Statistic {
+    Mean,
+    Variance(u32),
+    Std(u32),
+}
+
+/// Delta degrees of freedom of `var([ddof])` and `std([ddof])`: 1 by
+/// default, for the sample variance, or 0 for the population variance.
+pub(crate) fn ddof(name: &str, args: &[f64]) -> Result<u32> {
+    match *args {
+        [] => Ok(1),
+        [ddof] if ddof.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&ddof) => Ok(ddof as u32),
+        _ => Err(Error::InvalidArgument(format!(
+            "{name} expects an optional non-negative integer ddof"
+        ))),
+    }
+}
+
+pub(crate) fn accumulator(
+    name: &str,
+    data_type: ArrowType,
+    statistic: Statistic,
+) -> Result<Box<dyn Accumulator>> {
+    match data_type {
+        ArrowType::Int64 => Ok(Box::new(Welford::<i64>::new(statistic))),
+        ArrowType::Float64 => Ok(Box::new(Welford::<f64>::new(statistic))),
+        other => Err(aggregate::unsupported(name, other)),
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Moments {
+    count: i64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn merge(&mut self, other: Moments) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 +=
+            other.m2 + delta * delta * (self.count as f64 * other.count as f64) / count as f64;
+        self.count = count;
+    }
+}
+
+struct Welford<T> {
+    statistic: Statistic,
+    moments: Vec<Moments>,
+    _type: std::marker::PhantomData<T>,
+}
+
+impl<T> Welford<T> {
+    fn new(statistic: Statistic) -> Welford<T> {
+        Welford {
+            statistic,
+            moments: Vec::new(),
+            _type: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Number> Accumulator for Welford<T> {
+    fn output_type(&self) -> ArrowType {
+        ArrowType::Float64
+    }
+
+    fn resize(&mut self, n_groups: usize) {
+        self.moments.resize(n_groups, Moments::default());
+    }
+
+    fn update(&mut self, values: &ArrowArray, rows: Range<usize>, groups: &[u32]) {
+        aggregate::for_each_valid(values, rows, groups, |group, value: T| {
+            self.moments[group].add(value.to_f64())
+        });
+    }
+
+    fn merge(&mut self, states: &ArrowArray, rows: Range<usize>, groups: &[u32]) -> Result<()> {
+        aggregate::for_each_state(states, rows, groups, |group, mut state| {
+            let other = Moments {
+                count: state.u64()? as i64,
+                mean: state.f64()?,
+                m2: state.f64()?,
+            };
+            self.moments[group].merge(other);
+            Ok(())
+        })
+    }
+
+    fn state(self: Box<Self>) -> ArrayData {
+        aggregate::states_array(self.moments.len(), |group, state| {
+            let moments = self.moments[group];
+            state.extend_from_slice(&moments.count.to_le_bytes());
+            state.extend_from_slice(&moments.mean.to_le_bytes());
+            state.extend_from_slice(&moments.m2.to_le_bytes());
+        })
+    }
+
+    fn finish(self: Box<Self>) -> ArrayData {
+        // Groups without more values than the degrees of freedom are null.
+        let ddof = match self.statistic {
+            Statistic::Mean => 0,
+            Statistic::Variance(ddof) | Statistic::Std(ddof) => ddof as i64,
+        };
+        let (values, valid): (Vec<f64>, Vec<i64>) = self
+            .moments
+            .iter()
+            .map(|moments| {
+                let variance = || moments.m2 / (moments.count - ddof) as f64;
+                let value = match self.statistic {
+                    _ if moments.count <= ddof => 0.0,
+                    Statistic::Mean => moments.mean,
+                    Statistic::Variance(_) => variance(),
+                    Statistic::Std(_) => variance().sqrt(),
+                };
+                (value, (moments.count > ddof) as i64)
+            })
+            .unzip();
+        aggregate::nullable_result(&values, &valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::AggregateSpec;
+    use crate::schema::Schema;
+    use crate::testing::Exported;
+
+    /// Result of `spec` for the values of `groups`, every group in its own
+    /// batch.
+    fn aggregate(spec: &str, groups: &[&[Option<f64>]]) -> Vec<Option<f64>> {
+        let spec = AggregateSpec::parse(spec).unwrap();
+        let mut accumulator = spec.accumulator(ArrowType::Float64).unwrap();
+        accumulator.resize(groups.len());
+        for (group, values) in groups.iter().enumerate() {
+            let input = Exported::nullable(values);
+            let ids = vec![group as u32; values.len()];
+            input.with_array(|array| accumulator.update(array, 0..values.len(), &ids));
+        }
+        let output = Exported::new(&Schema::new(ArrowType::Float64, "x"), accumulator.finish());
+        output.nullable_values()
+    }
+
+    #[test]
+    fn sample_and_population_statistics() {
+        let values = [Some(2.0), Some(4.0), None, Some(4.0), Some(4.0), Some(5.0)];
+        let values = [&values[..], &[Some(5.0), Some(7.0), Some(9.0)]].concat();
+        assert_eq!(aggregate("mean", &[&values]), [Some(5.0)]);
+        assert_eq!(aggregate("var(0)", &[&values]), [Some(4.0)]);
+        assert_eq!(aggregate("std(0)", &[&values]), [Some(2.0)]);
+        assert_eq!(aggregate("var", &[&values]), [Some(32.0 / 7.0)]);
+    }
+
+    #[test]
+    fn groups_need_more_values_than_the_degrees_of_freedom() {
+        let groups: [&[Option<f64>]; 3] = [&[Some(1.0)], &[None], &[Some(1.0), Some(3.0)]];
+        assert_eq!(aggregate("var", &groups), [None, None, Some(2.0)]);
+        assert_eq!(aggregate("var(0)", &groups), [Some(0.0), None, Some(1.0)]);
+        assert_eq!(aggregate("mean", &groups), [Some(1.0), None, Some(2.0)]);
+    }
+
+    #[test]
+    fn small_variances_of_large_values_are_precise() {
+        let values: Vec<_> = [4.0, 7.0, 13.0, 16.0].map(|v| Some(1e9 + v)).to_vec();
+        let variance = aggregate("var", &[&values])[0].unwrap();
+        assert!((variance - 30.0).abs() < 1e-6, "{variance}");
+    }
+
+    #[test]
+    fn merged_moments_match_a_single_pass() {
+        let values: Vec<f64> = (0..100).map(|i| (i * i % 17) as f64).collect();
+        let mut single = Moments::default();
+        values.iter().for_each(|value| single.add(*value));
+        let (mut left, mut right) = (Moments::default(), Moments::default());
+        values[..30].iter().for_each(|value| left.add(*value));
+        values[30..].iter().for_each(|value| right.add(*value));
+        left.merge(right);
+        left.merge(Moments::default());
+        assert_eq!(left.count, single.count);
+        assert!((left.mean - single.mean).abs() < 1e-12);
+        assert!((left.m2 - single.m2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn arguments_are_validated() {
+        assert_eq!(ddof("var", &[]).unwrap(), 1);
+        assert_eq!(ddof("var", &[2.0]).unwrap(), 2);
+        for args in [&[-1.0][..], &[0.5], &[1.0, 2.0]] {
+            assert!(matches!(ddof("std", args), Err(Error::InvalidArgument(_))));
+        }
+        assert!(matches!(
+            accumulator("std", ArrowType::Int8, Statistic::Std(1)),
+            Err(Error::UnsupportedType(_))
+        ));
+    }
+}